@@ -0,0 +1,89 @@
+#[macro_use]
+extern crate criterion;
+
+use aries_askar::{
+    fixtures::{generate_store, Fixture, FixtureSpec, TagSpec},
+    future::block_on,
+    PassKey, ProvisionKeys, StoreKeyMethod, TagFilter,
+};
+
+use criterion::{black_box, Criterion};
+
+const ROW_COUNT: usize = 10_000;
+const PROVISION_COUNT: usize = 100;
+
+async fn init_store() -> Fixture {
+    generate_store(FixtureSpec {
+        seed: 1,
+        categories: 1,
+        entries_per_category: ROW_COUNT,
+        tags: vec![
+            TagSpec::encrypted("enc", 2, 0.0),
+            TagSpec::plaintext("plain", 100, 1.0),
+        ],
+        ..Default::default()
+    })
+    .await
+    .expect("Error generating store fixture")
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let fixture = block_on(init_store());
+    let store = fixture.store.clone();
+    let category = FixtureSpec::category_name(0);
+
+    c.bench_function("sqlite count", |b| {
+        b.iter(|| {
+            block_on(async {
+                let mut conn = store.session(None).await.unwrap();
+                black_box(conn.count(black_box(&category), None).await.unwrap())
+            })
+        })
+    });
+
+    c.bench_function("sqlite count with tag filter", |b| {
+        b.iter(|| {
+            block_on(async {
+                let filter = TagFilter::all_of(vec![
+                    TagFilter::is_eq("enc", FixtureSpec::tag_value("enc", 1)),
+                    TagFilter::is_gt("~plain", FixtureSpec::tag_value("plain", 10)),
+                ]);
+                let mut conn = store.session(None).await.unwrap();
+                black_box(
+                    conn.count(black_box(&category), Some(filter))
+                        .await
+                        .unwrap(),
+                )
+            })
+        })
+    });
+
+    drop(store);
+    block_on(fixture.remove()).expect("Error removing store fixture");
+
+    c.bench_function("create provision keys", |b| {
+        b.iter(|| {
+            for _ in 0..PROVISION_COUNT {
+                black_box(
+                    ProvisionKeys::generate(StoreKeyMethod::RawKey, PassKey::empty()).unwrap(),
+                );
+            }
+        })
+    });
+
+    c.bench_function("create provision keys batch", |b| {
+        b.iter(|| {
+            black_box(
+                block_on(ProvisionKeys::create_batch(
+                    PROVISION_COUNT,
+                    StoreKeyMethod::RawKey,
+                    PassKey::empty(),
+                ))
+                .unwrap(),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,10 +1,18 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
 use super::{Backend, ManageBackend, QueryBackend};
 use crate::{
+    crypto::buffer::SecretBytes,
     error::Error,
     future::BoxFuture,
-    protect::{PassKey, StoreKeyMethod},
+    protect::{EncryptionPolicy, PassKey, StoreKey, StoreKeyMethod},
     storage::{
-        Entry, EntryKind, EntryOperation, EntryTag, IntoOptions, Scan, Session, Store, TagFilter,
+        AccessLogEntry, ConditionalFetch, DecryptionAudit, DecryptionAuditOptions, Entry,
+        EntryAccess, EntryKind, EntryOperation, EntryTag, EntryVersion, ExpiryFilter, ExpiryUpdate,
+        FilteredFetch, HealthStatus, IntegrityReport, IntoOptions, NameNormalization, ProfileQuota,
+        ProfileUsage, QueryHint, ResumableScan, Scan, ScanCursor, Session, Store, StoreDiagnostics,
+        StoreInfo, TagFilter, TagRetrieval, TagStats, TaggedEntry,
     },
 };
 
@@ -53,31 +61,245 @@ macro_rules! with_backend {
 impl Backend for AnyBackend {
     type Session = AnyQueryBackend;
 
-    fn create_profile(&self, name: Option<String>) -> BoxFuture<'_, Result<String, Error>> {
-        with_backend!(self, store, store.create_profile(name))
+    fn create_profile(
+        &self,
+        name: Option<String>,
+        policy: EncryptionPolicy,
+    ) -> BoxFuture<'_, Result<String, Error>> {
+        with_backend!(self, store, store.create_profile(name, policy))
     }
 
     fn get_profile_name(&self) -> &str {
         with_backend!(self, store, store.get_profile_name())
     }
 
+    fn load_profile(&self, name: String) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.load_profile(name))
+    }
+
     fn remove_profile(&self, name: String) -> BoxFuture<'_, Result<bool, Error>> {
         with_backend!(self, store, store.remove_profile(name))
     }
 
+    fn list_profiles(&self) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        with_backend!(self, store, store.list_profiles())
+    }
+
+    fn truncate_profile(
+        &self,
+        profile: Option<String>,
+        categories: Option<Vec<String>>,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, i64>, Error>> {
+        with_backend!(self, store, store.truncate_profile(profile, categories))
+    }
+
+    fn list_categories(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        with_backend!(self, store, store.list_categories(profile, kind))
+    }
+
+    fn list_categories_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category_prefix: String,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        with_backend!(
+            self,
+            store,
+            store.list_categories_prefix(profile, kind, category_prefix)
+        )
+    }
+
     fn scan(
         &self,
         profile: Option<String>,
         kind: EntryKind,
         category: String,
         tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
         with_backend!(
             self,
             store,
-            store.scan(profile, kind, category, tag_filter, offset, limit)
+            store.scan(profile, kind, category, tag_filter, expiry, hint, offset, limit)
+        )
+    }
+
+    fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        with_backend!(
+            self,
+            store,
+            store.scan_prefix(
+                profile,
+                kind,
+                category,
+                name_prefix,
+                tag_filter,
+                offset,
+                limit
+            )
+        )
+    }
+
+    fn scan_resumable(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        cursor: Option<ScanCursor>,
+    ) -> BoxFuture<'_, Result<ResumableScan, Error>> {
+        with_backend!(
+            self,
+            store,
+            store.scan_resumable(profile, kind, category, tag_filter, cursor)
+        )
+    }
+
+    fn enable_name_index(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_name_index())
+    }
+
+    fn enable_category_index(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_category_index())
+    }
+
+    fn enable_access_tracking(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_access_tracking())
+    }
+
+    fn enable_value_history(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_value_history())
+    }
+
+    fn enable_value_etags(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_value_etags())
+    }
+
+    fn enable_value_envelopes(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_value_envelopes())
+    }
+
+    fn enable_access_log(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.enable_access_log())
+    }
+
+    fn read_access_log(
+        &self,
+        limit: Option<i64>,
+        since: Option<i64>,
+    ) -> BoxFuture<'_, Result<Vec<AccessLogEntry>, Error>> {
+        with_backend!(self, store, store.read_access_log(limit, since))
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        with_backend!(self, store, store.name_normalization())
+    }
+
+    fn default_expiry_filter(&self) -> ExpiryFilter {
+        with_backend!(self, store, store.default_expiry_filter())
+    }
+
+    fn default_query_hint(&self) -> Option<QueryHint> {
+        with_backend!(self, store, store.default_query_hint())
+    }
+
+    fn scan_coldest(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<Vec<EntryAccess>, Error>> {
+        with_backend!(
+            self,
+            store,
+            store.scan_coldest(profile, kind, category, limit)
+        )
+    }
+
+    fn tag_stats(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+    ) -> BoxFuture<'_, Result<Vec<TagStats>, Error>> {
+        with_backend!(self, store, store.tag_stats(profile, kind, category))
+    }
+
+    fn purge_expired(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        with_backend!(self, store, store.purge_expired())
+    }
+
+    fn checkpoint(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.checkpoint())
+    }
+
+    fn vacuum(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.vacuum())
+    }
+
+    fn verify_integrity(&self) -> BoxFuture<'_, Result<IntegrityReport, Error>> {
+        with_backend!(self, store, store.verify_integrity())
+    }
+
+    fn audit_decryption(
+        &self,
+        profile: Option<String>,
+        options: DecryptionAuditOptions,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<DecryptionAudit, Error>> {
+        with_backend!(self, store, store.audit_decryption(profile, options, limit))
+    }
+
+    fn cleanup_orphans(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        with_backend!(self, store, store.cleanup_orphans())
+    }
+
+    fn diagnostics(&self) -> BoxFuture<'_, Result<StoreDiagnostics, Error>> {
+        with_backend!(self, store, store.diagnostics())
+    }
+
+    fn info(&self, refresh: bool) -> BoxFuture<'_, Result<StoreInfo, Error>> {
+        with_backend!(self, store, store.info(refresh))
+    }
+
+    fn ping(&self, deadline: Duration) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.ping(deadline))
+    }
+
+    fn health(&self, deadline: Duration) -> BoxFuture<'_, HealthStatus> {
+        with_backend!(self, store, store.health(deadline))
+    }
+
+    fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        categories: Vec<String>,
+        tag_filter: Option<TagFilter>,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, Vec<Entry>>, Error>> {
+        with_backend!(
+            self,
+            store,
+            store.scan_grouped(profile, kind, categories, tag_filter, limit)
         )
     }
 
@@ -99,6 +321,90 @@ impl Backend for AnyBackend {
         }
     }
 
+    fn session_with_external_key(
+        &self,
+        profile: Option<String>,
+        store_key: StoreKey,
+        transaction: bool,
+    ) -> Result<Self::Session, Error> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::Postgres(store) => {
+                let session = store.session_with_external_key(profile, store_key, transaction)?;
+                Ok(AnyQueryBackend::PostgresSession(session))
+            }
+
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(store) => {
+                let session = store.session_with_external_key(profile, store_key, transaction)?;
+                Ok(AnyQueryBackend::SqliteSession(session))
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn export_profile_key(
+        &self,
+        profile: Option<String>,
+    ) -> BoxFuture<'_, Result<SecretBytes, Error>> {
+        with_backend!(self, store, store.export_profile_key(profile))
+    }
+
+    fn import_profile_key(
+        &self,
+        profile: String,
+        key: SecretBytes,
+        force: bool,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.import_profile_key(profile, key, force))
+    }
+
+    fn protect_profile(
+        &self,
+        profile: String,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(
+            self,
+            store,
+            store.protect_profile(profile, method, pass_key)
+        )
+    }
+
+    fn unlock_profile(
+        &self,
+        profile: String,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.unlock_profile(profile, pass_key))
+    }
+
+    fn lock_profile(&self, profile: String) -> BoxFuture<'_, Result<bool, Error>> {
+        with_backend!(self, store, store.lock_profile(profile))
+    }
+
+    fn set_profile_quota(
+        &self,
+        profile: Option<String>,
+        quota: ProfileQuota,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.set_profile_quota(profile, quota))
+    }
+
+    fn profile_usage(&self, profile: Option<String>) -> BoxFuture<'_, Result<ProfileUsage, Error>> {
+        with_backend!(self, store, store.profile_usage(profile))
+    }
+
+    fn rebuild_usage(&self) -> BoxFuture<'_, Result<(), Error>> {
+        with_backend!(self, store, store.rebuild_usage())
+    }
+
+    fn clear_cache(&self) {
+        with_backend!(self, store, store.clear_cache())
+    }
+
     fn rekey_backend(
         &mut self,
         method: StoreKeyMethod,
@@ -149,8 +455,8 @@ impl QueryBackend for AnyQueryBackend {
     fn fetch<'q>(
         &'q mut self,
         kind: EntryKind,
-        category: &'q str,
-        name: &'q str,
+        category: &'q [u8],
+        name: &'q [u8],
         for_update: bool,
     ) -> BoxFuture<'q, Result<Option<Entry>, Error>> {
         match self {
@@ -164,6 +470,93 @@ impl QueryBackend for AnyQueryBackend {
         }
     }
 
+    fn fetch_conditional<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        etag: Option<&'q [u8]>,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<ConditionalFetch>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => {
+                session.fetch_conditional(kind, category, name, etag, for_update)
+            }
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => {
+                session.fetch_conditional(kind, category, name, etag, for_update)
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn fetch_filtered<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_filter: TagFilter,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<FilteredFetch>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => {
+                session.fetch_filtered(kind, category, name, tag_filter, for_update)
+            }
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => {
+                session.fetch_filtered(kind, category, name, tag_filter, for_update)
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn fetch_with_tags<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_mode: TagRetrieval,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<TaggedEntry>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => {
+                session.fetch_with_tags(kind, category, name, tag_mode, for_update)
+            }
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => {
+                session.fetch_with_tags(kind, category, name, tag_mode, for_update)
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn fetch_history<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: &'q str,
+        limit: Option<i64>,
+    ) -> BoxFuture<'q, Result<Vec<EntryVersion>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.fetch_history(kind, category, name, limit),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.fetch_history(kind, category, name, limit),
+
+            _ => unreachable!(),
+        }
+    }
+
     fn fetch_all<'q>(
         &'q mut self,
         kind: EntryKind,
@@ -204,31 +597,176 @@ impl QueryBackend for AnyQueryBackend {
         }
     }
 
+    fn take<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: Option<&'q str>,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<Option<Entry>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.take(kind, category, name, tag_filter),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.take(kind, category, name, tag_filter),
+
+            _ => unreachable!(),
+        }
+    }
+
     fn update<'q>(
         &'q mut self,
         kind: EntryKind,
         operation: EntryOperation,
-        category: &'q str,
-        name: &'q str,
+        category: &'q [u8],
+        name: &'q [u8],
         value: Option<&'q [u8]>,
+        value_type: Option<&'q str>,
         tags: Option<&'q [EntryTag]>,
-        expiry_ms: Option<i64>,
+        expiry: ExpiryUpdate,
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.update(
+                kind, operation, category, name, value, value_type, tags, expiry,
+            ),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.update(
+                kind, operation, category, name, value, value_type, tags, expiry,
+            ),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn rename<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        new_category: &'q [u8],
+        new_name: &'q [u8],
     ) -> BoxFuture<'q, Result<(), Error>> {
         match self {
             #[cfg(feature = "postgres")]
             Self::PostgresSession(session) => {
-                session.update(kind, operation, category, name, value, tags, expiry_ms)
+                session.rename(kind, category, name, new_category, new_name)
             }
 
             #[cfg(feature = "sqlite")]
             Self::SqliteSession(session) => {
-                session.update(kind, operation, category, name, value, tags, expiry_ms)
+                session.rename(kind, category, name, new_category, new_name)
             }
 
             _ => unreachable!(),
         }
     }
 
+    fn applied_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+    ) -> BoxFuture<'q, Result<Option<i64>, Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.applied_batch(idempotency_key),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.applied_batch(idempotency_key),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn record_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+        updates: i64,
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.record_batch(idempotency_key, updates),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.record_batch(idempotency_key, updates),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.set_query_timeout(timeout),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.set_query_timeout(timeout),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_expiry_filter(&mut self, expiry: ExpiryFilter) {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.set_expiry_filter(expiry),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.set_expiry_filter(expiry),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_query_hint(&mut self, hint: Option<QueryHint>) {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.set_query_hint(hint),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.set_query_hint(hint),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_profile(&mut self, profile: String) {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.set_profile(profile),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.set_profile(profile),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_commit_operation(&mut self, operation: &'static str) {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.set_commit_operation(operation),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.set_commit_operation(operation),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgresSession(session) => session.name_normalization(),
+
+            #[cfg(feature = "sqlite")]
+            Self::SqliteSession(session) => session.name_normalization(),
+
+            _ => unreachable!(),
+        }
+    }
+
     fn close(self, commit: bool) -> BoxFuture<'static, Result<(), Error>> {
         match self {
             #[cfg(feature = "postgres")]
@@ -0,0 +1,168 @@
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::db_utils::EncScanEntry;
+use crate::error::Error;
+
+/// Prefix identifying a sidecar file reference stored in place of an item value
+pub(crate) const BLOB_REF_PREFIX: &[u8] = b"askar-blob:";
+
+/// The default size (in bytes) above which encrypted values are moved to a sidecar file
+pub(crate) const DEFAULT_BLOB_THRESHOLD: usize = 64 * 1024;
+
+/// Storage for large encrypted item values in a directory of sidecar files
+#[derive(Debug)]
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+    threshold: usize,
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf, threshold: usize) -> Self {
+        Self { dir, threshold }
+    }
+
+    /// Move an encrypted value to a sidecar file when it exceeds the threshold.
+    /// Returns the value to be stored in the database, along with the identifier
+    /// of the new sidecar file, if any.
+    pub fn store(&self, enc_value: Vec<u8>) -> Result<(Vec<u8>, Option<String>), Error> {
+        if enc_value.len() <= self.threshold {
+            return Ok((enc_value, None));
+        }
+        fs::create_dir_all(&self.dir)
+            .map_err(err_map!(Backend, "Error creating blob directory"))?;
+        let blob_id = uuid::Uuid::new_v4().to_string();
+        fs::write(self.dir.join(&blob_id), enc_value)
+            .map_err(err_map!(Backend, "Error writing blob file"))?;
+        let mut blob_ref = Vec::with_capacity(BLOB_REF_PREFIX.len() + blob_id.len());
+        blob_ref.extend_from_slice(BLOB_REF_PREFIX);
+        blob_ref.extend_from_slice(blob_id.as_bytes());
+        Ok((blob_ref, Some(blob_id)))
+    }
+
+    /// Load the encrypted value for a stored value, reading the sidecar file if necessary
+    pub fn resolve(&self, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if let Some(blob_id) = blob_id(&value) {
+            fs::read(self.dir.join(blob_id)).map_err(err_map!(Backend, "Error reading blob file"))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Load the encrypted values for a batch of scan results
+    pub fn resolve_rows(&self, mut rows: Vec<EncScanEntry>) -> Result<Vec<EncScanEntry>, Error> {
+        for row in rows.iter_mut() {
+            if blob_id(&row.value).is_some() {
+                row.value = self.resolve(std::mem::take(&mut row.value))?;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Remove a sidecar file, ignoring files which are already missing
+    pub fn remove(&self, blob_id: &str) -> Result<(), Error> {
+        match fs::remove_file(self.dir.join(blob_id)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err_msg!(Backend, "Error removing blob file").with_cause(err)),
+        }
+    }
+
+    /// Remove a set of sidecar files, logging any failure rather than returning it
+    pub fn remove_all(&self, blob_ids: Vec<String>) {
+        for blob_id in blob_ids {
+            if let Err(err) = self.remove(&blob_id) {
+                warn!("Error removing blob file {}: {}", blob_id, err);
+            }
+        }
+    }
+}
+
+/// Extract the sidecar file identifier from a stored value, if it is a reference
+pub(crate) fn blob_id(value: &[u8]) -> Option<&str> {
+    if value.starts_with(BLOB_REF_PREFIX) {
+        // only accept well-formed identifiers so that a stored value can never
+        // address a path outside of the blob directory
+        std::str::from_utf8(&value[BLOB_REF_PREFIX.len()..])
+            .ok()
+            .filter(|id| uuid::Uuid::parse_str(id).is_ok())
+    } else {
+        None
+    }
+}
+
+/// A newly written sidecar file, which is removed unless the update referencing
+/// it completes successfully
+pub(crate) struct PendingBlob {
+    store: Arc<BlobStore>,
+    blob_id: String,
+    keep: bool,
+}
+
+impl PendingBlob {
+    pub fn new(store: Arc<BlobStore>, blob_id: String) -> Self {
+        Self {
+            store,
+            blob_id,
+            keep: false,
+        }
+    }
+
+    pub fn keep(mut self) -> String {
+        self.keep = true;
+        std::mem::take(&mut self.blob_id)
+    }
+}
+
+impl Drop for PendingBlob {
+    fn drop(&mut self) {
+        if !self.keep {
+            self.store
+                .remove_all(vec![std::mem::take(&mut self.blob_id)]);
+        }
+    }
+}
+
+/// Sidecar file changes made within a session.
+///
+/// Within a transaction, removal of replaced sidecar files is deferred until the
+/// transaction is committed, and new sidecar files are removed on roll-back.
+#[derive(Debug)]
+pub(crate) struct SessionBlobs {
+    pub(crate) store: Arc<BlobStore>,
+    created: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl SessionBlobs {
+    pub fn new(store: Arc<BlobStore>) -> Self {
+        Self {
+            store,
+            created: vec![],
+            removed: vec![],
+        }
+    }
+
+    /// Record the sidecar files added and removed by a successful update
+    pub fn track(&mut self, transaction: bool, created: Option<String>, removed: Vec<String>) {
+        if transaction {
+            self.created.extend(created);
+            self.removed.extend(removed);
+        } else {
+            self.store.remove_all(removed);
+        }
+    }
+
+    /// Apply or discard the sidecar file changes when a transaction is closed
+    pub fn finish(&mut self, commit: bool) {
+        let removed = std::mem::take(&mut self.removed);
+        let created = std::mem::take(&mut self.created);
+        if commit {
+            self.store.remove_all(removed);
+        } else {
+            self.store.remove_all(created);
+        }
+    }
+}
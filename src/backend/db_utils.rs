@@ -1,33 +1,821 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+use std::time::{Duration, Instant};
 
+use async_lock::{Mutex as AsyncMutex, MutexGuardArc};
+use sha2::{Digest, Sha256};
 use sqlx::{
     database::HasArguments, pool::PoolConnection, Arguments, Database, Encode, Error as SqlxError,
-    IntoArguments, Pool, TransactionManager, Type,
+    Executor, IntoArguments, Pool, TransactionManager, Type,
 };
 
+#[cfg(feature = "blob_store")]
+use super::blob::{BlobStore, SessionBlobs};
 use crate::{
+    crypto::buffer::SecretBytes,
     error::Error,
-    future::BoxFuture,
-    protect::{EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, StoreKey, StoreKeyMethod},
+    future::{timeout, BoxFuture},
+    protect::{
+        EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, ProvisionKeys, StoreKey,
+        StoreKeyMethod, StoreKeyReference, ENVELOPE_VERSION,
+    },
     storage::{
         wql::{
             sql::TagSqlEncoder,
             tags::{tag_query, TagQueryEncoder},
         },
-        {EncEntryTag, Entry, EntryTag, TagFilter},
+        {
+            CommitRecord, EncEntryTag, Entry, EntryKind, EntryTag, ExpiryFilter, HealthStatus,
+            IdSource, Metrics, NameNormalization, OperationRecord, QueryHint, ScanTracker,
+            StoreInfo, TagFilter, TagValueType, WaitKind,
+        },
     },
 };
 
 pub const PAGE_SIZE: usize = 32;
 
+/// The name of the `config` entry recording that the name index is enabled
+pub const NAME_INDEX: &'static str = "name_index";
+
+/// The name of the `config` entry recording that the category index is enabled
+pub const CATEGORY_INDEX: &'static str = "category_index";
+
+/// The maximum number of items removed in a single statement when truncating a profile
+pub const DELETE_BATCH_SIZE: i64 = 1000;
+
+/// The maximum length in bytes of an entry category or name.
+///
+/// Encrypted categories and names are part of a unique index, and Postgres
+/// cannot index values larger than about 2.7 KB.
+pub const MAX_ENTRY_KEY_LEN: usize = 1024;
+
+/// The separator between the levels of a hierarchical entry category, as
+/// used by the category index
+pub const CATEGORY_SEPARATOR: &str = "::";
+
 pub type Expiry = chrono::DateTime<chrono::Utc>;
 
+/// The minimum interval between updates of the access counters of a single record
+pub const ACCESS_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+// the number of tracked records above which idle records are discarded
+const ACCESS_TRACKER_PRUNE_LEN: usize = 10_000;
+
+/// The default period for which the idempotency keys of applied batches are retained
+pub const DEFAULT_BATCH_RETENTION: Duration = Duration::from_secs(86400);
+
+/// The default limit on the total size of the record values in a page of scan
+/// results, after which the page is returned early
+pub const DEFAULT_SCAN_PAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// The default period for which a record is held in the record cache
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The default limit on the number of distinct record fetches which may be
+/// coalesced at once
+pub const DEFAULT_FETCH_FLIGHTS: usize = 1024;
+
+/// The default number of prior values retained for each record, when value
+/// history is enabled
+pub const DEFAULT_HISTORY_VERSIONS: usize = 10;
+
+/// The default period for which prior record values are retained, when value
+/// history is enabled
+pub const DEFAULT_HISTORY_RETENTION: Duration = Duration::from_secs(30 * 86400);
+
+/// The default period for which access log entries are retained, when the
+/// access log is enabled
+pub const DEFAULT_ACCESS_LOG_RETENTION: Duration = Duration::from_secs(90 * 86400);
+
+/// Holds the store information last read from the database
+#[derive(Debug, Default)]
+pub struct StoreInfoCache(Mutex<Option<StoreInfo>>);
+
+impl StoreInfoCache {
+    /// Get the cached store information, if any
+    pub fn get(&self) -> Option<StoreInfo> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Replace the cached store information
+    pub fn set(&self, info: StoreInfo) {
+        self.0.lock().unwrap().replace(info);
+    }
+
+    /// Clear the cached store information after the store configuration or
+    /// profiles have been changed
+    pub fn invalidate(&self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+/// Debounces the updates of record access counters, when access tracking is enabled
+#[derive(Debug, Default)]
+pub struct AccessTracker {
+    enabled: AtomicBool,
+    // item id -> (time of the last counter update, reads not yet written)
+    pending: Mutex<HashMap<i64, (Instant, i64)>>,
+}
+
+impl AccessTracker {
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release)
+    }
+
+    /// Register a read of a record, returning the number of reads to be added
+    /// to its counters when an update is due.
+    ///
+    /// The counters of a record are updated at most once per `ACCESS_UPDATE_INTERVAL`,
+    /// and reads in between are added by the next update. Reads which are pending
+    /// for a record that is not read again may be discarded, so the counts are
+    /// approximate.
+    pub fn record_read(&self, item_id: i64) -> Option<i64> {
+        let now = Instant::now();
+        // the map is left consistent if another thread panicked while holding the lock
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if pending.len() >= ACCESS_TRACKER_PRUNE_LEN {
+            pending.retain(|_, (updated, _)| now.duration_since(*updated) < ACCESS_UPDATE_INTERVAL);
+        }
+        match pending.get_mut(&item_id) {
+            Some((updated, count)) => {
+                *count += 1;
+                if now.duration_since(*updated) >= ACCESS_UPDATE_INTERVAL {
+                    *updated = now;
+                    Some(std::mem::take(count))
+                } else {
+                    None
+                }
+            }
+            None => {
+                pending.insert(item_id, (now, 0));
+                Some(1)
+            }
+        }
+    }
+}
+
+// the profile, kind, encrypted category and name, and expiry filter of a fetch
+type FetchKey = (ProfileId, EntryKind, Vec<u8>, Vec<u8>, ExpiryFilter);
+
+// the result of a fetch, once published by the caller which performed it
+type FetchSlot = AsyncMutex<Option<Option<Entry>>>;
+
+/// Coalesces concurrent fetches of the same record, so that the record is
+/// queried and decrypted once and each caller receives a copy of the result.
+///
+/// Results are only shared while the fetch is in flight, and are never cached.
+#[derive(Debug)]
+pub struct FetchCoalescer {
+    limit: usize,
+    flights: Mutex<HashMap<FetchKey, Arc<FetchSlot>>>,
+}
+
+impl FetchCoalescer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Join the in-flight fetch of a record, or start a new one
+    pub fn join(self: &Arc<Self>, key: FetchKey) -> FetchFlight {
+        let mut flights = self
+            .flights
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(slot) = flights.get(&key) {
+            return FetchFlight::Follower(slot.clone());
+        }
+        if flights.len() >= self.limit {
+            return FetchFlight::Bypass;
+        }
+        let slot = Arc::new(AsyncMutex::new(None));
+        let guard = slot.try_lock_arc().expect("Error locking new mutex");
+        flights.insert(key.clone(), slot);
+        FetchFlight::Leader(FetchLeader {
+            coalescer: self.clone(),
+            key,
+            guard,
+        })
+    }
+}
+
+/// The role of a caller in a coalesced fetch
+#[derive(Debug)]
+pub enum FetchFlight {
+    /// The caller performs the fetch and publishes the result
+    Leader(FetchLeader),
+    /// The caller waits for the result of a fetch performed by another
+    Follower(Arc<FetchSlot>),
+    /// The fetch is not coalesced
+    Bypass,
+}
+
+impl FetchFlight {
+    /// Wait for the result of the fetch being followed, if any. `None` is
+    /// returned when the caller must perform the fetch itself, including when
+    /// the fetch being followed has failed or was cancelled
+    pub async fn shared_result(&self) -> Option<Option<Entry>> {
+        match self {
+            Self::Follower(slot) => slot.lock().await.clone(),
+            _ => None,
+        }
+    }
+
+    /// Publish the result of the fetch to any waiting callers
+    pub fn complete(self, result: &Option<Entry>) {
+        if let Self::Leader(mut leader) = self {
+            leader.guard.replace(result.clone());
+        }
+    }
+}
+
+/// Holds an in-flight fetch until its result is published or it is abandoned
+#[derive(Debug)]
+pub struct FetchLeader {
+    coalescer: Arc<FetchCoalescer>,
+    key: FetchKey,
+    guard: MutexGuardArc<Option<Option<Entry>>>,
+}
+
+impl Drop for FetchLeader {
+    fn drop(&mut self) {
+        // later callers start a new fetch, while the waiting callers are
+        // released when the guard is dropped
+        self.coalescer
+            .flights
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.key);
+    }
+}
+
+/// The profile, kind, and encrypted category and name of a cached record
+pub type EntryCacheKey = (ProfileId, EntryKind, Vec<u8>, Vec<u8>);
+
+/// A bounded cache of decrypted records, consulted by fetches outside of a
+/// transaction.
+///
+/// The least recently used record is evicted when the cache is full, and
+/// records are discarded once they have been cached for longer than the
+/// time-to-live. Writes made through the same store invalidate the affected
+/// records, but writes made by other processes sharing the database do not.
+#[derive(Debug)]
+pub struct EntryCache {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<EntryCacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct EntryCacheInner {
+    entries: HashMap<EntryCacheKey, CachedEntry>,
+    // the keys of the cached records in order of last use
+    used: BTreeMap<u64, EntryCacheKey>,
+    tick: u64,
+    // incremented whenever records are invalidated
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct CachedEntry {
+    entry: Entry,
+    added: Instant,
+    used: u64,
+}
+
+impl EntryCacheInner {
+    fn remove(&mut self, key: &EntryCacheKey) {
+        if let Some(cached) = self.entries.remove(key) {
+            self.used.remove(&cached.used);
+        }
+    }
+}
+
+impl EntryCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            inner: Mutex::new(EntryCacheInner::default()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, EntryCacheInner> {
+        // the cache is left consistent if another thread panicked while holding the lock
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The current generation of the cache, to be passed to `insert` after
+    /// the record has been loaded
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Get a copy of a cached record, if it is present and has not expired
+    pub fn get(&self, key: &EntryCacheKey) -> Option<Entry> {
+        let mut inner = self.lock();
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let (stale, prev_used) = match inner.entries.get(key) {
+            Some(cached) => (
+                cached.added.elapsed() >= self.ttl
+                    || cached
+                        .entry
+                        .expiry
+                        .map(|exp| exp <= now_ms)
+                        .unwrap_or(false),
+                cached.used,
+            ),
+            None => return None,
+        };
+        if stale {
+            inner.remove(key);
+            return None;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.used.remove(&prev_used);
+        inner.used.insert(tick, key.clone());
+        let cached = inner.entries.get_mut(key).unwrap();
+        cached.used = tick;
+        Some(cached.entry.clone())
+    }
+
+    /// Add a loaded record to the cache, unless any records have been
+    /// invalidated since `generation` was obtained
+    pub fn insert(&self, key: EntryCacheKey, entry: Entry, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(&key);
+        while inner.entries.len() >= self.capacity {
+            // evicted values are zeroized when dropped
+            let oldest = match inner.used.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(evict) = inner.used.remove(&oldest) {
+                inner.entries.remove(&evict);
+            }
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.used.insert(tick, key.clone());
+        inner.entries.insert(
+            key,
+            CachedEntry {
+                entry,
+                added: Instant::now(),
+                used: tick,
+            },
+        );
+    }
+
+    /// Discard a cached record after it has been written
+    pub fn invalidate(&self, key: &EntryCacheKey) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.remove(key);
+    }
+
+    /// Discard all cached records
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.used.clear();
+    }
+}
+
+/// Store-level settings shared by all sessions of a database store
+#[derive(Clone, Debug, Default)]
+pub struct DbSettings {
+    /// Drop entry tags which cannot be decrypted instead of failing the request,
+    /// reporting them in `Entry::dropped_tags`
+    pub lenient_tags: bool,
+    /// The default maximum duration of a single query
+    pub query_timeout: Option<Duration>,
+    /// The expiry filter applied by sessions and scans unless another is selected
+    pub expiry_filter: ExpiryFilter,
+    /// The index hint applied by sessions and scans unless another is selected
+    pub query_hint: Option<QueryHint>,
+    /// Maintain the index of entry name prefixes used by `scan_prefix`
+    pub name_index: Arc<AtomicBool>,
+    /// Maintain the index of entry category prefixes used by `list_categories_prefix`
+    pub category_index: Arc<AtomicBool>,
+    /// The receiver for measurements of store operations, if any
+    pub metrics: Option<Arc<dyn Metrics>>,
+    /// Maintain the record access counters used by `scan_coldest`
+    pub access_tracker: Arc<AccessTracker>,
+    /// The normalization applied to record categories and names, as recorded
+    /// in the store configuration when it was provisioned
+    pub name_normalization: NameNormalization,
+    /// The active record scans, as reported by `diagnostics`, along with the
+    /// limit on their number
+    pub scans: Arc<ScanTracker>,
+    /// The store information reported by `info`, when it has been loaded
+    pub info: Arc<StoreInfoCache>,
+    /// The period for which the idempotency keys of applied batches are retained,
+    /// if not the default
+    pub batch_retention: Option<Duration>,
+    /// Retain the prior values of replaced and removed records
+    pub value_history: Arc<AtomicBool>,
+    /// Store the entity tags of record values when they are written
+    pub value_etags: Arc<AtomicBool>,
+    /// Write record values within an envelope, which prior releases cannot read
+    pub value_envelope: Arc<AtomicBool>,
+    /// The number of prior values retained for each record, if not the default
+    pub history_versions: Option<usize>,
+    /// The period for which prior record values are retained, if not the default
+    pub history_retention: Option<Duration>,
+    /// Record the opening and closing of the store and other key operations in
+    /// the access log
+    pub access_log: Arc<AtomicBool>,
+    /// The period for which access log entries are retained, if not the default
+    pub access_log_retention: Option<Duration>,
+    /// The label identifying the client in the access log, if any
+    pub client_label: Option<String>,
+    /// The ID which the store must have when it is opened, if any
+    pub expected_store_id: Option<String>,
+    /// Share the decrypted store and profile keys with the other handles of the
+    /// process opening the same store, when supported by the backend
+    pub share_keys: bool,
+    /// The limit on the size of the record values in a page of scan results,
+    /// if not the default
+    pub scan_page_bytes: Option<usize>,
+    /// The maximum number of tags fetched for a single entry record, if any
+    pub max_entry_tags: Option<usize>,
+    /// Coalesce concurrent fetches of the same record outside of transactions,
+    /// when supported by the backend
+    pub fetch_coalescer: Option<Arc<FetchCoalescer>>,
+    /// The cache of decrypted records consulted by fetches, when enabled and
+    /// supported by the backend
+    pub entry_cache: Option<Arc<EntryCache>>,
+    /// The source of the IDs of new profiles, if not assigned by the database
+    pub id_source: Option<Arc<dyn IdSource>>,
+}
+
+impl DbSettings {
+    /// Parse the settings from the query parameters of a store URI
+    pub fn from_query(query: &mut HashMap<String, String>) -> Result<Self, Error> {
+        let lenient_tags = if let Some(lenient) = query.remove("lenient_tags") {
+            lenient.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'lenient_tags' parameter: '{}'",
+                lenient
+            ))?
+        } else {
+            false
+        };
+        let query_timeout = if let Some(timeout) = query.remove("query_timeout") {
+            let secs: u64 = timeout.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'query_timeout' parameter: '{}'",
+                timeout
+            ))?;
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        };
+        let expiry_filter = if let Some(filter) = query.remove("expiry_filter") {
+            ExpiryFilter::parse(&filter).map_err(err_map!(
+                Input,
+                "Error parsing 'expiry_filter' parameter: '{}'",
+                filter
+            ))?
+        } else {
+            ExpiryFilter::default()
+        };
+        let query_hint = if let Some(hint) = query.remove("query_hint") {
+            Some(QueryHint::parse(&hint).map_err(err_map!(
+                Input,
+                "Error parsing 'query_hint' parameter: '{}'",
+                hint
+            ))?)
+        } else {
+            None
+        };
+        let name_normalization = if let Some(norm) = query.remove("name_normalization") {
+            NameNormalization::parse(&norm).map_err(err_map!(
+                Input,
+                "Error parsing 'name_normalization' parameter: '{}'",
+                norm
+            ))?
+        } else {
+            NameNormalization::None
+        };
+        let batch_retention = if let Some(retention) = query.remove("batch_retention") {
+            let secs: u64 = retention.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'batch_retention' parameter: '{}'",
+                retention
+            ))?;
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        };
+        let history_versions = if let Some(versions) = query.remove("history_versions") {
+            Some(versions.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'history_versions' parameter: '{}'",
+                versions
+            ))?)
+        } else {
+            None
+        };
+        let history_retention = if let Some(retention) = query.remove("history_retention") {
+            let secs: u64 = retention.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'history_retention' parameter: '{}'",
+                retention
+            ))?;
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        };
+        let access_log_retention = if let Some(retention) = query.remove("access_log_retention") {
+            let secs: u64 = retention.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'access_log_retention' parameter: '{}'",
+                retention
+            ))?;
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        };
+        let client_label = query.remove("client_label");
+        let expected_store_id = query.remove("store_id");
+        let share_keys = if let Some(share) = query.remove("share_keys") {
+            share.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'share_keys' parameter: '{}'",
+                share
+            ))?
+        } else {
+            false
+        };
+        let scan_page_bytes = if let Some(bytes) = query.remove("scan_page_bytes") {
+            Some(bytes.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'scan_page_bytes' parameter: '{}'",
+                bytes
+            ))?)
+        } else {
+            None
+        };
+        let max_entry_tags = if let Some(max) = query.remove("max_entry_tags") {
+            Some(max.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'max_entry_tags' parameter: '{}'",
+                max
+            ))?)
+        } else {
+            None
+        };
+        let scans = if let Some(max) = query.remove("max_scans") {
+            let max = max.parse().map_err(err_map!(
+                Input,
+                "Error parsing 'max_scans' parameter: '{}'",
+                max
+            ))?;
+            ScanTracker::new(max)
+        } else {
+            ScanTracker::default()
+        };
+        Ok(Self {
+            lenient_tags,
+            query_timeout,
+            expiry_filter,
+            query_hint,
+            name_index: Default::default(),
+            category_index: Default::default(),
+            metrics: None,
+            access_tracker: Default::default(),
+            name_normalization,
+            scans: Arc::new(scans),
+            info: Default::default(),
+            batch_retention,
+            value_history: Default::default(),
+            value_etags: Default::default(),
+            value_envelope: Default::default(),
+            history_versions,
+            history_retention,
+            access_log: Default::default(),
+            access_log_retention,
+            client_label,
+            expected_store_id,
+            share_keys,
+            scan_page_bytes,
+            max_entry_tags,
+            fetch_coalescer: None,
+            entry_cache: None,
+            id_source: None,
+        })
+    }
+
+    /// Select the ID of a new profile, or `None` to let the database assign it
+    pub fn next_profile_id(&self) -> Option<ProfileId> {
+        self.id_source
+            .as_ref()
+            .and_then(|source| source.next_profile_id())
+    }
+
+    /// The period for which the idempotency keys of applied batches are retained
+    #[inline]
+    pub fn batch_retention(&self) -> Duration {
+        self.batch_retention.unwrap_or(DEFAULT_BATCH_RETENTION)
+    }
+
+    /// The number of prior values retained for each record
+    #[inline]
+    pub fn history_versions(&self) -> usize {
+        self.history_versions.unwrap_or(DEFAULT_HISTORY_VERSIONS)
+    }
+
+    /// The period for which prior record values are retained
+    #[inline]
+    pub fn history_retention(&self) -> Duration {
+        self.history_retention.unwrap_or(DEFAULT_HISTORY_RETENTION)
+    }
+
+    /// The period for which access log entries are retained
+    #[inline]
+    pub fn access_log_retention(&self) -> Duration {
+        self.access_log_retention
+            .unwrap_or(DEFAULT_ACCESS_LOG_RETENTION)
+    }
+
+    #[inline]
+    pub fn access_log(&self) -> bool {
+        self.access_log.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_access_log(&self, enabled: bool) {
+        self.access_log.store(enabled, Ordering::Release)
+    }
+
+    /// Check the ID of a store being opened against the expected ID, if any
+    pub fn check_store_id(&self, store_id: &str) -> Result<(), Error> {
+        match self.expected_store_id.as_deref() {
+            Some(expected) if expected != store_id => Err(err_msg!(
+                Input,
+                "Store ID mismatch: expected '{}', found '{}'",
+                expected,
+                store_id
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The limit on the total size of the record values in a page of scan results
+    #[inline]
+    pub fn scan_page_bytes(&self) -> usize {
+        self.scan_page_bytes.unwrap_or(DEFAULT_SCAN_PAGE_BYTES)
+    }
+
+    #[inline]
+    pub fn value_history(&self) -> bool {
+        self.value_history.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_value_history(&self, enabled: bool) {
+        self.value_history.store(enabled, Ordering::Release)
+    }
+
+    #[inline]
+    pub fn value_etags(&self) -> bool {
+        self.value_etags.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_value_etags(&self, enabled: bool) {
+        self.value_etags.store(enabled, Ordering::Release)
+    }
+
+    #[inline]
+    pub fn value_envelope(&self) -> bool {
+        self.value_envelope.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_value_envelope(&self, enabled: bool) {
+        self.value_envelope.store(enabled, Ordering::Release)
+    }
+
+    #[inline]
+    pub fn name_index(&self) -> bool {
+        self.name_index.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_name_index(&self, enabled: bool) {
+        self.name_index.store(enabled, Ordering::Release)
+    }
+
+    #[inline]
+    pub fn category_index(&self) -> bool {
+        self.category_index.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_category_index(&self, enabled: bool) {
+        self.category_index.store(enabled, Ordering::Release)
+    }
+
+    #[inline]
+    pub fn name_normalization(&self) -> NameNormalization {
+        self.name_normalization
+    }
+
+    /// Start timing a wait, when metrics are being collected
+    #[inline]
+    pub fn wait_start(&self) -> Option<Instant> {
+        self.metrics.as_ref().map(|_| Instant::now())
+    }
+
+    /// Report the time spent waiting since `wait_start`
+    #[inline]
+    pub fn record_wait(&self, kind: WaitKind, start: Option<Instant>) {
+        if let (Some(metrics), Some(start)) = (self.metrics.as_ref(), start) {
+            metrics.record_wait(kind, start.elapsed());
+        }
+    }
+
+    /// Report a committed transaction, timed from `wait_start`
+    #[inline]
+    pub fn record_commit(
+        &self,
+        operation: &'static str,
+        start: Option<Instant>,
+        writes: TxnWrites,
+    ) {
+        if let (Some(metrics), Some(start)) = (self.metrics.as_ref(), start) {
+            metrics.record_commit(&CommitRecord {
+                operation,
+                duration: start.elapsed(),
+                statements: writes.statements,
+                value_bytes: writes.value_bytes,
+            });
+        }
+    }
+
+    /// Discard all records held in the record cache, if enabled
+    #[inline]
+    pub fn clear_entry_cache(&self) {
+        if let Some(cache) = self.entry_cache.as_ref() {
+            cache.clear();
+        }
+    }
+
+    /// Report a lookup in the record cache
+    #[inline]
+    pub fn record_cache(&self, hit: bool) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.record_cache(hit);
+        }
+    }
+}
+
+/// The writes performed within a transaction, reported when it is committed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TxnWrites {
+    pub statements: u64,
+    pub value_bytes: u64,
+}
+
 #[derive(Debug)]
 pub(crate) enum DbSessionState<DB: ExtDatabase> {
-    Active { conn: PoolConnection<DB> },
-    Pending { pool: Pool<DB> },
+    Active {
+        conn: PoolConnection<DB>,
+        pool: Pool<DB>,
+    },
+    Pending {
+        pool: Pool<DB>,
+    },
 }
 
 unsafe impl<DB: ExtDatabase> Sync for DbSessionState<DB> where DB::Connection: Send {}
@@ -36,7 +824,19 @@ unsafe impl<DB: ExtDatabase> Sync for DbSessionState<DB> where DB::Connection: S
 pub struct DbSession<DB: ExtDatabase> {
     profile_key: DbSessionKey,
     state: DbSessionState<DB>,
+    settings: Arc<DbSettings>,
+    query_timeout: Option<Duration>,
+    expiry_filter: ExpiryFilter,
+    query_hint: Option<QueryHint>,
     transaction: bool,
+    aborted: bool,
+    // set when records are written within a transaction while caching is enabled
+    cache_dirty: bool,
+    // the operation reported for the commit of a session transaction
+    commit_operation: &'static str,
+    writes: TxnWrites,
+    #[cfg(feature = "blob_store")]
+    blobs: Option<SessionBlobs>,
 }
 
 impl<DB: ExtDatabase> DbSession<DB> {
@@ -44,21 +844,56 @@ impl<DB: ExtDatabase> DbSession<DB> {
         pool: Pool<DB>,
         cache: Arc<KeyCache>,
         profile: String,
+        settings: Arc<DbSettings>,
         transaction: bool,
     ) -> Self
     where
         DB: Database,
     {
+        let query_timeout = settings.query_timeout;
+        let expiry_filter = settings.expiry_filter;
+        let query_hint = settings.query_hint;
         Self {
             profile_key: DbSessionKey::Pending { cache, profile },
             state: DbSessionState::Pending { pool },
+            settings,
+            query_timeout,
+            expiry_filter,
+            query_hint,
             transaction,
+            aborted: false,
+            cache_dirty: false,
+            commit_operation: "transaction",
+            writes: TxnWrites::default(),
+            #[cfg(feature = "blob_store")]
+            blobs: None,
+        }
+    }
+
+    #[cfg(feature = "blob_store")]
+    pub(crate) fn with_blob_store(mut self, store: Option<Arc<BlobStore>>) -> Self {
+        self.blobs = store.map(SessionBlobs::new);
+        self
+    }
+
+    #[cfg(feature = "blob_store")]
+    #[inline]
+    pub(crate) fn blob_store(&self) -> Option<Arc<BlobStore>> {
+        self.blobs.as_ref().map(|blobs| blobs.store.clone())
+    }
+
+    /// Record the sidecar files created and replaced by a successful update
+    #[cfg(feature = "blob_store")]
+    pub(crate) fn track_blobs(&mut self, created: Option<String>, removed: Vec<String>) {
+        let transaction = self.transaction;
+        if let Some(blobs) = self.blobs.as_mut() {
+            blobs.track(transaction, created, removed);
         }
     }
 
     #[inline]
     fn connection_mut(&mut self) -> Option<&mut PoolConnection<DB>> {
-        if let DbSessionState::Active { conn } = &mut self.state {
+        if let DbSessionState::Active { conn, .. } = &mut self.state {
             Some(conn)
         } else {
             None
@@ -70,6 +905,102 @@ impl<DB: ExtDatabase> DbSession<DB> {
         self.transaction
     }
 
+    #[inline]
+    pub(crate) fn settings(&self) -> Arc<DbSettings> {
+        self.settings.clone()
+    }
+
+    #[inline]
+    pub(crate) fn metrics(&self) -> Option<Arc<dyn Metrics>> {
+        self.settings.metrics.clone()
+    }
+
+    #[inline]
+    pub(crate) fn pool(&self) -> &Pool<DB> {
+        match &self.state {
+            DbSessionState::Active { pool, .. } | DbSessionState::Pending { pool } => pool,
+        }
+    }
+
+    /// Override the store default for the maximum duration of each query
+    #[inline]
+    pub(crate) fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    #[inline]
+    pub(crate) fn expiry_filter(&self) -> ExpiryFilter {
+        self.expiry_filter
+    }
+
+    /// Select records for queries according to their expiry time
+    #[inline]
+    pub(crate) fn set_expiry_filter(&mut self, expiry: ExpiryFilter) {
+        self.expiry_filter = expiry;
+    }
+
+    #[inline]
+    pub(crate) fn query_hint(&self) -> Option<QueryHint> {
+        self.query_hint
+    }
+
+    /// Select the index hint applied to queries with a tag filter
+    #[inline]
+    pub(crate) fn set_query_hint(&mut self, hint: Option<QueryHint>) {
+        self.query_hint = hint;
+    }
+
+    #[inline]
+    pub(crate) fn set_commit_operation(&mut self, operation: &'static str) {
+        self.commit_operation = operation;
+    }
+
+    /// Give up on the active connection after a query has timed out.
+    ///
+    /// The connection may still be executing the query, so the query is
+    /// interrupted where the database supports it and the connection is
+    /// detached from the pool rather than being returned to it. A transaction in progress is
+    /// rolled back along with the connection, and cannot be used further.
+    fn abandon_connection(&mut self) -> Error {
+        warn!("Query timed out: closing connection");
+        if let Some(conn) = self.connection_mut() {
+            DB::interrupt(conn);
+        }
+        self.detach_connection();
+        err_msg!(Timeout, "Query timed out")
+    }
+
+    /// Give up on the active connection after a query failed with an error
+    /// which leaves the connection unusable, such as an I/O error.
+    ///
+    /// The connection is closed rather than being returned to the pool, where
+    /// it would cause subsequent operations to fail.
+    fn discard_connection(&mut self, err: SqlxError) -> Error {
+        if DB::is_fatal_error(&err) {
+            warn!("Connection error: closing connection");
+            self.detach_connection();
+        }
+        err.into()
+    }
+
+    fn detach_connection(&mut self) {
+        if let DbSessionState::Active { pool, .. } = &self.state {
+            let pool = pool.clone();
+            if let DbSessionState::Active { conn, .. } =
+                std::mem::replace(&mut self.state, DbSessionState::Pending { pool })
+            {
+                drop(conn.detach());
+            }
+        }
+        if self.transaction {
+            self.aborted = true;
+            #[cfg(feature = "blob_store")]
+            if let Some(blobs) = self.blobs.as_mut() {
+                blobs.finish(false);
+            }
+        }
+    }
+
     #[inline]
     fn pool(&self) -> Option<&Pool<DB>> {
         if let DbSessionState::Pending { pool, .. } = &self.state {
@@ -83,6 +1014,7 @@ impl<DB: ExtDatabase> DbSession<DB> {
         if let DbSessionKey::Active {
             profile_id,
             ref key,
+            ..
         } = self.profile_key
         {
             Some((profile_id, key.clone()))
@@ -91,6 +1023,18 @@ impl<DB: ExtDatabase> DbSession<DB> {
         }
     }
 
+    /// Select the profile used by subsequent operations of the session. The
+    /// profile key is resolved by the next operation, within any transaction
+    /// already in progress
+    pub(crate) fn set_profile(&mut self, profile: String) {
+        let cache = match &self.profile_key {
+            DbSessionKey::Active { cache, .. } | DbSessionKey::Pending { cache, .. } => {
+                cache.clone()
+            }
+        };
+        self.profile_key = DbSessionKey::Pending { cache, profile };
+    }
+
     pub(crate) async fn make_active<I>(
         &mut self,
         init_key: I,
@@ -98,14 +1042,31 @@ impl<DB: ExtDatabase> DbSession<DB> {
     where
         I: for<'a> GetProfileKey<'a, DB>,
     {
+        if self.aborted {
+            return Err(err_msg!(
+                Timeout,
+                "Transaction was aborted after a query timeout or connection failure"
+            ));
+        }
         if matches!(self.state, DbSessionState::Pending { .. }) {
             info!("Acquire pool connection");
-            let mut conn = self.pool().unwrap().acquire().await?;
+            let pool = self.pool().unwrap().clone();
+            let start = self.settings.wait_start();
+            let mut conn = pool.acquire().await?;
+            self.settings.record_wait(WaitKind::Connection, start);
             if self.transaction {
                 info!("Start transaction");
-                DB::start_transaction(&mut conn, false).await?;
+                let start = self.settings.wait_start();
+                if let Err(err) = DB::start_transaction(&mut conn, false).await {
+                    if DB::is_fatal_error(&err) {
+                        warn!("Connection error: closing connection");
+                        drop(conn.detach());
+                    }
+                    return Err(err.into());
+                }
+                self.settings.record_wait(WaitKind::Lock, start);
             }
-            self.state = DbSessionState::Active { conn };
+            self.state = DbSessionState::Active { conn, pool };
         }
         let profile_id = match &mut self.profile_key {
             DbSessionKey::Pending { cache, profile } => {
@@ -113,9 +1074,13 @@ impl<DB: ExtDatabase> DbSession<DB> {
                 let mut get_profile = String::new();
                 std::mem::swap(profile, &mut get_profile);
                 let (profile_id, key) = init_key
-                    .call_once(self.connection_mut().unwrap(), cache, get_profile)
+                    .call_once(self.connection_mut().unwrap(), cache.clone(), get_profile)
                     .await?;
-                self.profile_key = DbSessionKey::Active { profile_id, key };
+                self.profile_key = DbSessionKey::Active {
+                    profile_id,
+                    key,
+                    cache,
+                };
                 profile_id
             }
             DbSessionKey::Active { profile_id, .. } => *profile_id,
@@ -140,16 +1105,47 @@ impl<DB: ExtDatabase> DbSession<DB> {
     }
 
     pub(crate) async fn close(mut self, commit: bool) -> Result<(), Error> {
+        if self.aborted {
+            self.transaction = false;
+            if commit {
+                return Err(err_msg!(
+                    Timeout,
+                    "Transaction was aborted after a query timeout or connection failure"
+                ));
+            }
+        }
         if self.transaction {
+            let writes = std::mem::take(&mut self.writes);
+            let start = self.settings.wait_start();
             if let Some(conn) = self.connection_mut() {
-                if commit {
+                if let Err(err) = if commit {
                     info!("Commit transaction on close");
                     DB::TransactionManager::commit(conn).await
                 } else {
                     info!("Roll-back transaction on close");
                     DB::TransactionManager::rollback(conn).await
+                } {
+                    if DB::is_fatal_error(&err) {
+                        warn!("Connection error: closing connection");
+                        self.detach_connection();
+                    }
+                    return Err(err_msg!(Backend, "Error closing transaction").with_cause(err));
+                }
+                if commit {
+                    self.settings
+                        .record_commit(self.commit_operation, start, writes);
+                }
+            }
+            #[cfg(feature = "blob_store")]
+            if let Some(blobs) = self.blobs.as_mut() {
+                blobs.finish(commit);
+            }
+            if commit && self.cache_dirty {
+                // other sessions may have cached the prior values of the
+                // records written before the commit
+                if let Some(cache) = self.settings.entry_cache.as_ref() {
+                    cache.clear();
                 }
-                .map_err(err_map!(Backend, "Error closing transaction"))?;
             }
             self.transaction = false;
         }
@@ -164,6 +1160,10 @@ impl<'q, DB: ExtDatabase> Drop for DbSession<DB> {
                 info!("Dropped transaction: roll-back");
                 DB::TransactionManager::start_rollback(conn);
             }
+            #[cfg(feature = "blob_store")]
+            if let Some(blobs) = self.blobs.as_mut() {
+                blobs.finish(false);
+            }
         } else {
             info!("Dropped pool connection")
         }
@@ -201,6 +1201,7 @@ pub(crate) enum DbSessionKey {
     Active {
         profile_id: ProfileId,
         key: Arc<ProfileKey>,
+        cache: Arc<KeyCache>,
     },
     Pending {
         cache: Arc<KeyCache>,
@@ -208,13 +1209,69 @@ pub(crate) enum DbSessionKey {
     },
 }
 
+/// The outcome of executing a statement, independent of the result type
+/// reported by the database driver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExecResult {
+    rows_affected: u64,
+    inserted_id: Option<i64>,
+}
+
+impl ExecResult {
+    pub fn new(rows_affected: u64, inserted_id: Option<i64>) -> Self {
+        Self {
+            rows_affected,
+            inserted_id,
+        }
+    }
+
+    /// Create the result of an insert statement which returns the id of the
+    /// new row, or no row when the insert was ignored
+    pub fn returning(inserted_id: Option<i64>) -> Self {
+        Self {
+            rows_affected: inserted_id.is_some() as u64,
+            inserted_id,
+        }
+    }
+
+    /// The number of rows changed by the statement
+    pub fn rows_affected(&self) -> u64 {
+        self.rows_affected
+    }
+
+    /// The id of the row added by an insert statement
+    pub fn inserted_id(&self) -> Result<i64, Error> {
+        self.inserted_id
+            .ok_or_else(|| err_msg!(Unexpected, "Inserted row id was not reported"))
+    }
+}
+
 pub trait ExtDatabase: Database {
+    /// Convert the result of executing a statement
+    fn exec_result(result: <Self as Database>::QueryResult) -> ExecResult;
+
     fn start_transaction(
         conn: &mut PoolConnection<Self>,
         _nested: bool,
     ) -> BoxFuture<'_, Result<(), SqlxError>> {
         <Self as Database>::TransactionManager::begin(conn)
     }
+
+    /// Stop a query which may still be running on a connection abandoned after
+    /// a timeout, so that it does not continue to hold database locks
+    fn interrupt(_conn: &mut PoolConnection<Self>) {}
+
+    /// Determine whether an error leaves the connection unusable, in which
+    /// case it is not returned to the pool
+    fn is_fatal_error(err: &SqlxError) -> bool {
+        matches!(
+            err,
+            SqlxError::Io(_)
+                | SqlxError::Tls(_)
+                | SqlxError::Protocol(_)
+                | SqlxError::WorkerCrashed
+        )
+    }
 }
 
 pub enum DbSessionRef<'q, DB: ExtDatabase> {
@@ -255,12 +1312,79 @@ impl<'q, DB: ExtDatabase> DbSessionActive<'q, DB> {
         self.inner.connection_mut().unwrap()
     }
 
-    pub async fn commit(mut self) -> Result<(), Error> {
+    #[inline]
+    pub fn query_timeout(&self) -> Option<Duration> {
+        self.inner.query_timeout
+    }
+
+    /// Unwrap the result of a query run by `run_query`, abandoning the
+    /// connection if the query timed out or the connection failed
+    pub fn check_timeout<T>(&mut self, result: Option<Result<T, SqlxError>>) -> Result<T, Error> {
+        match result {
+            Some(Ok(result)) => Ok(result),
+            Some(Err(err)) => Err(self.check_error(err)),
+            None => Err(self.timed_out()),
+        }
+    }
+
+    /// Convert a query error, closing the connection if it is no longer usable
+    #[inline]
+    pub fn check_error(&mut self, err: SqlxError) -> Error {
+        self.inner.discard_connection(err)
+    }
+
+    /// Abandon the connection after a query timed out
+    #[inline]
+    pub fn timed_out(&mut self) -> Error {
+        self.inner.abandon_connection()
+    }
+
+    pub async fn commit(self) -> Result<(), Error> {
+        self.finish_commit(None).await
+    }
+
+    /// Commit a transaction started by `as_transaction`, reporting its size and
+    /// the commit latency to the store metrics under the given operation name.
+    /// Within a session transaction, the writes are reported when the session
+    /// is committed
+    pub async fn commit_operation(self, operation: &'static str) -> Result<(), Error> {
+        self.finish_commit(Some(operation)).await
+    }
+
+    async fn finish_commit(mut self, operation: Option<&'static str>) -> Result<(), Error> {
         if self.txn_depth > 0 && !self.false_txn {
+            let writes = std::mem::take(&mut self.inner.writes);
+            let start = operation.and_then(|_| self.inner.settings.wait_start());
             let conn = self.connection_mut();
             info!("Commit transaction");
             DB::TransactionManager::commit(conn).await?;
             self.txn_depth = 0;
+            if let Some(operation) = operation {
+                self.inner.settings.record_commit(operation, start, writes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the write statements executed and the bytes of record values
+    /// written within a transaction, to be reported when it is committed
+    #[inline]
+    pub fn record_writes(&mut self, statements: u64, value_bytes: usize) {
+        if self.txn_depth > 0 {
+            self.inner.writes.statements += statements;
+            self.inner.writes.value_bytes += value_bytes as u64;
+        }
+    }
+
+    /// Roll back a transaction started by `as_transaction`. Within a session
+    /// transaction, the changes are left for the session to commit or discard
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        if self.txn_depth > 0 && !self.false_txn {
+            self.inner.writes = TxnWrites::default();
+            let conn = self.connection_mut();
+            info!("Roll-back transaction");
+            DB::TransactionManager::rollback(conn).await?;
+            self.txn_depth = 0;
         }
         Ok(())
     }
@@ -271,6 +1395,21 @@ impl<'q, DB: ExtDatabase> DbSessionActive<'q, DB> {
         self.txn_depth > 0
     }
 
+    /// Discard any cached copy of a written record, or of all records when no
+    /// key is given. Within a session transaction the cache is also cleared
+    /// when the transaction is committed
+    pub fn invalidate_cached(&mut self, key: Option<EntryCacheKey>) {
+        if let Some(cache) = self.inner.settings.entry_cache.as_ref() {
+            match key {
+                Some(key) => cache.invalidate(&key),
+                None => cache.clear(),
+            }
+            if self.inner.transaction {
+                self.inner.cache_dirty = true;
+            }
+        }
+    }
+
     #[allow(unused)]
     pub async fn transaction<'t>(&'t mut self) -> Result<DbSessionActive<'t, DB>, Error>
     where
@@ -292,7 +1431,10 @@ impl<'q, DB: ExtDatabase> DbSessionActive<'q, DB> {
     {
         if self.txn_depth == 0 {
             info!("Start transaction");
+            self.inner.writes = TxnWrites::default();
+            let start = self.inner.settings.wait_start();
             DB::start_transaction(self.connection_mut(), false).await?;
+            self.inner.settings.record_wait(WaitKind::Lock, start);
             Ok(DbSessionActive {
                 inner: &mut *self.inner,
                 profile_id: self.profile_id,
@@ -313,8 +1455,10 @@ impl<'q, DB: ExtDatabase> DbSessionActive<'q, DB> {
 impl<'a, DB: ExtDatabase> Drop for DbSessionActive<'a, DB> {
     fn drop(&mut self) {
         if self.txn_depth > 1 && !self.false_txn {
-            info!("Roll-back dropped nested transaction");
-            DB::TransactionManager::start_rollback(self.connection_mut());
+            if let Some(conn) = self.inner.connection_mut() {
+                info!("Roll-back dropped nested transaction");
+                DB::TransactionManager::start_rollback(conn);
+            }
         }
     }
 }
@@ -335,10 +1479,132 @@ where
     }
 }
 
+/// Remove the items of a profile category in batches of `DELETE_BATCH_SIZE`
+/// rows, returning the number of items removed.
+///
+/// The delete query takes the profile ID, entry kind, encrypted category and
+/// batch size as its arguments. Each batch is committed in its own transaction,
+/// so that truncating a large category does not hold a long write lock.
+pub(crate) async fn delete_category_batches<DB>(
+    active: &mut DbSessionActive<'_, DB>,
+    query: &'static str,
+    profile_id: ProfileId,
+    enc_category: &[u8],
+) -> Result<i64, Error>
+where
+    DB: ExtDatabase,
+    for<'c> &'c mut PoolConnection<DB>: Executor<'c, Database = DB>,
+    for<'a> <DB as HasArguments<'a>>::Arguments: IntoArguments<'a, DB>,
+    i16: for<'e> Encode<'e, DB> + Type<DB>,
+    i64: for<'e> Encode<'e, DB> + Type<DB>,
+    Vec<u8>: for<'e> Encode<'e, DB> + Type<DB>,
+{
+    let mut removed = 0;
+    loop {
+        let mut txn = active.as_transaction().await?;
+        let done = sqlx::query(query)
+            .bind(profile_id)
+            .bind(EntryKind::Item as i16)
+            .bind(enc_category.to_vec())
+            .bind(DELETE_BATCH_SIZE)
+            .execute(txn.connection_mut())
+            .await
+            .map(DB::exec_result)?
+            .rows_affected() as i64;
+        txn.commit().await?;
+        removed += done;
+        if done < DELETE_BATCH_SIZE {
+            return Ok(removed);
+        }
+    }
+}
+
+/// Run a query, giving up if it does not complete within the timeout (if any).
+///
+/// The result should be passed to `DbSessionActive::check_timeout`.
+pub(crate) async fn run_query<F: Future>(
+    timeout_dur: Option<Duration>,
+    query: F,
+) -> Option<F::Output> {
+    if let Some(dur) = timeout_dur {
+        timeout(dur, query).await
+    } else {
+        Some(query.await)
+    }
+}
+
+/// Run a health probe of the database, failing with a `Timeout` error if it
+/// does not complete within the deadline
+pub(crate) async fn run_probe<T>(
+    deadline: Duration,
+    probe: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    timeout(deadline, probe).await.unwrap_or_else(|| {
+        Err(err_msg!(
+            Timeout,
+            "Health check did not complete within {}ms",
+            deadline.as_millis()
+        ))
+    })
+}
+
+/// Classify the outcome of a health probe. A probe which succeeds but takes
+/// more than half of its deadline reports the store as degraded, as does a
+/// panic while the active scans were being updated
+pub(crate) fn health_status(
+    result: Result<(), Error>,
+    elapsed: Duration,
+    deadline: Duration,
+    scans: &ScanTracker,
+) -> HealthStatus {
+    match result {
+        Err(err) => HealthStatus::Unhealthy(err.to_string()),
+        Ok(()) if scans.is_poisoned() => HealthStatus::Degraded(
+            "A task panicked while updating the active scans, the store should be reopened"
+                .to_string(),
+        ),
+        Ok(()) if elapsed > deadline / 2 => HealthStatus::Degraded(format!(
+            "Health check took {}ms of the {}ms deadline",
+            elapsed.as_millis(),
+            deadline.as_millis()
+        )),
+        Ok(()) => HealthStatus::Healthy,
+    }
+}
+
+/// Report the outcome of a store operation to the configured metrics, if any.
+///
+/// The operation is returned unchanged when no metrics are configured.
+pub(crate) fn instrument<'q, T: Send + 'q>(
+    metrics: Option<Arc<dyn Metrics>>,
+    operation: &'static str,
+    rows: fn(&T) -> Option<i64>,
+    fut: BoxFuture<'q, Result<T, Error>>,
+) -> BoxFuture<'q, Result<T, Error>> {
+    if let Some(metrics) = metrics {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = fut.await;
+            metrics.record_operation(&OperationRecord {
+                operation,
+                duration: start.elapsed(),
+                error: result.as_ref().err().map(Error::kind),
+                rows: result.as_ref().ok().and_then(rows),
+            });
+            result
+        })
+    } else {
+        fut
+    }
+}
+
 pub struct EncScanEntry {
+    pub id: i64,
     pub name: Vec<u8>,
     pub value: Vec<u8>,
+    pub value_type: Option<Vec<u8>>,
     pub tags: Vec<u8>,
+    pub expiry: Option<i64>,
 }
 
 pub struct QueryParams<'q, DB: Database> {
@@ -354,6 +1620,16 @@ impl<'q, DB: Database> QueryParams<'q, DB> {
         }
     }
 
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut params = Self::new();
+        params.reserve(capacity);
+        params
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.args.reserve(additional, 0);
+    }
+
     pub fn extend<I, T>(&mut self, vals: I)
     where
         I: IntoIterator<Item = T>,
@@ -391,10 +1667,29 @@ where
 pub trait QueryPrepare {
     type DB: Database;
 
+    /// The maximum number of arguments bound to a single query. The default is
+    /// the lowest value of SQLITE_MAX_VARIABLE_NUMBER used by SQLite builds
+    const MAX_PARAMS: usize = 999;
+
+    /// The maximum number of SELECT statements combined in a compound query,
+    /// the default being SQLITE_MAX_COMPOUND_SELECT
+    const MAX_COMPOUND_SELECT: usize = 500;
+
     fn placeholder(index: i64) -> String {
         format!("?{}", index)
     }
 
+    /// The SQL expression for the current time, compared to the expiry of a record
+    fn current_timestamp() -> &'static str {
+        "DATETIME('now')"
+    }
+
+    /// Apply an index hint to a query on records with a tag filter. Hints which
+    /// are not supported by the database are ignored
+    fn hint_query(query: String, _hint: QueryHint) -> String {
+        query
+    }
+
     fn limit_query<'q>(
         mut query: String,
         args: &mut QueryParams<'q, Self::DB>,
@@ -415,6 +1710,63 @@ pub trait QueryPrepare {
     }
 }
 
+/// Build a query selecting the records of a single profile, entry kind and category.
+///
+/// The `columns` are selected from the `items` table aliased as `i`, and any
+/// `conditions` are added to the filter. The profile ID, entry kind and encrypted
+/// category are bound to the first three arguments, followed by the arguments of
+/// the conditions, which use `$$` placeholders. Expired records are excluded,
+/// which may be adjusted using `expiry_query`.
+///
+/// Queries on records are built here so that the entry kind is always part of
+/// the filter, and stored keys are never visible to the item methods or the
+/// reverse.
+pub fn item_query<Q: QueryPrepare>(columns: &str, conditions: &str) -> String {
+    let mut query = format!(
+        "SELECT {} FROM items i WHERE {}",
+        columns,
+        item_scope(conditions)
+    );
+    query.push_str(" AND (i.expiry IS NULL OR i.expiry > ");
+    query.push_str(Q::current_timestamp());
+    query.push(')');
+    replace_arg_placeholders::<Q>(&query, 1)
+}
+
+/// Build a statement removing the records of a single profile, entry kind and
+/// category, including expired records. The arguments are bound as for `item_query`.
+pub fn item_delete_query<Q: QueryPrepare>(conditions: &str) -> String {
+    let query = format!("DELETE FROM items AS i WHERE {}", item_scope(conditions));
+    replace_arg_placeholders::<Q>(&query, 1)
+}
+
+fn item_scope(conditions: &str) -> String {
+    let mut scope = "i.profile_id = $$ AND i.kind = $$ AND i.category = $$".to_string();
+    if !conditions.is_empty() {
+        scope.push_str(" AND ");
+        scope.push_str(conditions);
+    }
+    scope
+}
+
+/// Adjust the condition on the expiry of the records selected by a query.
+///
+/// Queries on items exclude expired records by default, using the condition
+/// `AND (i.expiry IS NULL OR i.expiry > <now>)`
+pub fn expiry_query<Q: QueryPrepare>(
+    query: Cow<'static, str>,
+    expiry: ExpiryFilter,
+) -> Cow<'static, str> {
+    let now = Q::current_timestamp();
+    let replace = match expiry {
+        ExpiryFilter::Exclude => return query,
+        ExpiryFilter::Include => String::new(),
+        ExpiryFilter::Only => format!("AND (i.expiry IS NOT NULL AND i.expiry <= {})", now),
+    };
+    let active = format!("AND (i.expiry IS NULL OR i.expiry > {})", now);
+    Cow::Owned(query.replace(&active, &replace))
+}
+
 pub fn replace_arg_placeholders<Q: QueryPrepare + ?Sized>(
     filter: &str,
     start_index: i64,
@@ -455,9 +1807,12 @@ pub fn replace_arg_placeholders<Q: QueryPrepare + ?Sized>(
     buffer
 }
 
+// each tag is encoded as `<flag>:<hex name>:<hex value>`, where the flag is
+// 0 for encrypted tags and one plus the value type for plaintext tags
 pub(crate) fn decode_tags(tags: Vec<u8>) -> Result<Vec<EncEntryTag>, ()> {
     let mut idx = 0;
     let mut plaintext;
+    let mut value_type;
     let mut name_start;
     let mut name_end;
     let mut enc_tags = vec![];
@@ -466,7 +1821,12 @@ pub(crate) fn decode_tags(tags: Vec<u8>) -> Result<Vec<EncEntryTag>, ()> {
         if idx >= end {
             break;
         }
-        plaintext = tags[idx] == b'1';
+        plaintext = tags[idx] != b'0';
+        value_type = if plaintext {
+            TagValueType::from_i16(tags[idx].wrapping_sub(b'1') as i16).ok_or(())?
+        } else {
+            TagValueType::Text
+        };
         // assert ':' at idx + 1
         idx += 2;
         name_start = idx;
@@ -482,6 +1842,7 @@ pub(crate) fn decode_tags(tags: Vec<u8>) -> Result<Vec<EncEntryTag>, ()> {
                     name,
                     value,
                     plaintext,
+                    value_type,
                 });
                 break;
             }
@@ -502,25 +1863,191 @@ pub fn decrypt_scan_batch(
     category: String,
     enc_rows: Vec<EncScanEntry>,
     key: &ProfileKey,
+    settings: &DbSettings,
 ) -> Result<Vec<Entry>, Error> {
     let mut batch = Vec::with_capacity(enc_rows.len());
     for enc_entry in enc_rows {
-        batch.push(decrypt_scan_entry(category.clone(), enc_entry, key)?);
+        batch.push(decrypt_scan_entry(
+            category.clone(),
+            enc_entry,
+            key,
+            settings,
+        )?);
+    }
+    Ok(batch)
+}
+
+/// Decrypt a batch of scanned records, pairing each with its record ID
+pub fn decrypt_scan_batch_with_ids(
+    category: String,
+    enc_rows: Vec<EncScanEntry>,
+    key: &ProfileKey,
+    settings: &DbSettings,
+) -> Result<Vec<(i64, Entry)>, Error> {
+    let mut batch = Vec::with_capacity(enc_rows.len());
+    for enc_entry in enc_rows {
+        let id = enc_entry.id;
+        batch.push((
+            id,
+            decrypt_scan_entry(category.clone(), enc_entry, key, settings)?,
+        ));
     }
     Ok(batch)
 }
 
+/// Derive the check value of a scan cursor from the encrypted category and the
+/// tag filter of the scan. The encrypted category depends on the profile key,
+/// so a cursor cannot be used with another store or profile
+pub fn scan_cursor_check(enc_category: &[u8], tag_filter: Option<&str>) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&(enc_category.len() as u64).to_be_bytes());
+    hasher.update(enc_category);
+    if let Some(tag_filter) = tag_filter {
+        hasher.update(tag_filter.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
 pub fn decrypt_scan_entry(
     category: String,
     enc_entry: EncScanEntry,
     key: &ProfileKey,
+    settings: &DbSettings,
 ) -> Result<Entry, Error> {
     let name = key.decrypt_entry_name(enc_entry.name)?;
     let value = key.decrypt_entry_value(category.as_bytes(), name.as_bytes(), enc_entry.value)?;
-    let tags = key.decrypt_entry_tags(
+    let value_type = decrypt_value_type(
+        key,
+        category.as_bytes(),
+        name.as_bytes(),
+        enc_entry.value_type,
+    )?;
+    let (tags, dropped_tags) = decrypt_tags(
+        key,
+        decode_tags(enc_entry.tags).map_err(|_| err_msg!(Unexpected, "Error decoding tags"))?,
+        settings,
+    )?;
+    Ok(Entry::new(category.to_string(), name, value, tags)
+        .with_value_type(value_type)
+        .with_expiry(enc_entry.expiry)
+        .with_dropped_tags(dropped_tags))
+}
+
+pub struct EncAuditEntry {
+    pub id: i64,
+    pub category: Vec<u8>,
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+    pub value_type: Option<Vec<u8>>,
+    pub tags: Vec<u8>,
+    pub kind: i16,
+}
+
+/// Decrypt each part of a record for a decryption audit. Unlike a fetch, tags
+/// which cannot be decrypted are always reported, regardless of the lenient
+/// tags setting
+pub fn audit_entry(key: &Arc<ProfileKey>, enc_entry: EncAuditEntry) -> Result<(), Error> {
+    let key = if enc_entry.kind == EntryKind::Kms as i16 {
+        key.clone().for_kind(EntryKind::Kms)
+    } else {
+        key.clone()
+    };
+    let key = key.as_ref();
+    let category = key.decrypt_entry_category(enc_entry.category)?;
+    let name = key.decrypt_entry_name(enc_entry.name)?;
+    key.decrypt_entry_value(category.as_bytes(), name.as_bytes(), enc_entry.value)?;
+    decrypt_value_type(
+        key,
+        category.as_bytes(),
+        name.as_bytes(),
+        enc_entry.value_type,
+    )?;
+    key.decrypt_entry_tags(
         decode_tags(enc_entry.tags).map_err(|_| err_msg!(Unexpected, "Error decoding tags"))?,
     )?;
-    Ok(Entry::new(category.to_string(), name, value, tags))
+    Ok(())
+}
+
+/// Encrypt the value type hint of an entry using the key for the entry value,
+/// within an envelope when `envelope` is set
+pub fn encrypt_value_type(
+    key: &ProfileKey,
+    category: &[u8],
+    name: &[u8],
+    value_type: Option<SecretBytes>,
+    envelope: bool,
+) -> Result<Option<Vec<u8>>, Error> {
+    value_type
+        .map(|value_type| key.encrypt_entry_value(category, name, value_type, envelope))
+        .transpose()
+}
+
+/// Decrypt the value type hint of an entry, if present
+pub fn decrypt_value_type(
+    key: &ProfileKey,
+    category: &[u8],
+    name: &[u8],
+    enc_value_type: Option<Vec<u8>>,
+) -> Result<Option<String>, Error> {
+    if let Some(enc_value_type) = enc_value_type {
+        let value_type = key.decrypt_entry_value(category, name, enc_value_type)?;
+        Ok(Some(
+            String::from_utf8(value_type.into_vec())
+                .map_err(err_map!(Encryption, "Invalid value type"))?,
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The tags decrypted for an entry, along with the names of any tags dropped
+/// in lenient mode, as reported by `Entry::dropped_tags`
+pub type DecryptedTags = (Vec<EntryTag>, Vec<Option<String>>);
+
+/// Decrypt the tags of an entry. In lenient mode, tags which fail to decrypt
+/// are dropped and reported to the caller, so that the remainder of the entry
+/// is still returned.
+pub fn decrypt_tags(
+    key: &ProfileKey,
+    enc_tags: Vec<EncEntryTag>,
+    settings: &DbSettings,
+) -> Result<DecryptedTags, Error> {
+    if let Some(max) = settings.max_entry_tags {
+        if enc_tags.len() > max {
+            return Err(err_msg!(
+                Unexpected,
+                "Entry record has {} tags, exceeding the configured limit of {}",
+                enc_tags.len(),
+                max
+            ));
+        }
+    }
+    let mut dropped = Vec::new();
+    let mut tags = if !settings.lenient_tags {
+        key.decrypt_entry_tags(enc_tags)?
+    } else {
+        let mut tags = Vec::with_capacity(enc_tags.len());
+        for enc_tag in enc_tags {
+            let enc_name = enc_tag.name.clone();
+            match key.decrypt_entry_tags(vec![enc_tag]) {
+                Ok(tag) => tags.extend(tag),
+                Err(err) => {
+                    warn!("Dropped entry tag which could not be decrypted: {}", err);
+                    dropped.push(
+                        key.decrypt_tag_name(enc_name)
+                            .ok()
+                            .and_then(|name| String::from_utf8(name.into_vec()).ok()),
+                    );
+                }
+            }
+        }
+        tags
+    };
+    // the aggregated tags are returned by the database in no particular order,
+    // and encrypted tag names do not sort in the same order as their plaintext
+    tags.sort();
+    dropped.sort();
+    Ok((tags, dropped))
 }
 
 pub fn expiry_timestamp(expire_ms: i64) -> Result<Expiry, Error> {
@@ -552,19 +2079,24 @@ pub fn encode_tag_filter<Q: QueryPrepare>(
 }
 
 // convert a slice of tags into a Vec, when ensuring there is
-// adequate space in the allocations to reuse them during encryption
+// adequate space in the allocations to reuse them during encryption.
+// repeated tags and reserved tag names are rejected here, before any
+// transaction is started
 pub fn prepare_tags(tags: &[EntryTag]) -> Result<Vec<EntryTag>, Error> {
     let mut result = Vec::with_capacity(tags.len());
-    for tag in tags {
+    let mut seen = HashSet::with_capacity(tags.len());
+    for (idx, tag) in tags.iter().enumerate() {
+        check_tag_name(idx, tag.name())?;
+        // tag names are case-sensitive, and a name may be given multiple values
+        if !seen.insert(tag) {
+            return Err(err_msg!(
+                Input,
+                "Duplicate tag at index {}: '{}'",
+                idx,
+                tag.name()
+            ));
+        }
         result.push(match tag {
-            EntryTag::Plaintext(name, value) => EntryTag::Plaintext(
-                unsafe {
-                    String::from_utf8_unchecked(
-                        ProfileKey::prepare_input(name.as_bytes()).into_vec(),
-                    )
-                },
-                value.clone(),
-            ),
             EntryTag::Encrypted(name, value) => EntryTag::Encrypted(
                 unsafe {
                     String::from_utf8_unchecked(
@@ -577,46 +2109,295 @@ pub fn prepare_tags(tags: &[EntryTag]) -> Result<Vec<EntryTag>, Error> {
                     )
                 },
             ),
+            tag => tag.map_name(|name| unsafe {
+                String::from_utf8_unchecked(ProfileKey::prepare_input(name.as_bytes()).into_vec())
+            }),
         });
     }
     Ok(result)
 }
 
+/// Extend a constant query with a tag filter clause and result limits.
+///
+/// The query is returned unchanged, without allocating, when there is no
+/// filter and no limit. An index hint is only applied along with a tag filter.
 pub fn extend_query<'q, Q: QueryPrepare>(
-    query: &str,
+    query: &'static str,
     args: &mut QueryParams<'q, Q::DB>,
     tag_filter: Option<(String, Vec<Vec<u8>>)>,
+    hint: Option<QueryHint>,
     offset: Option<i64>,
     limit: Option<i64>,
-) -> Result<String, Error>
+) -> Result<Cow<'static, str>, Error>
 where
     i64: for<'e> Encode<'e, Q::DB> + Type<Q::DB>,
     Vec<u8>: for<'e> Encode<'e, Q::DB> + Type<Q::DB>,
 {
-    let mut query = query.to_string();
+    let has_limit = offset.is_some() || limit.is_some();
+    if tag_filter.is_none() && !has_limit {
+        return Ok(Cow::Borrowed(query));
+    }
+    let mut extended;
     if let Some((filter_clause, filter_args)) = tag_filter {
+        args.reserve(filter_args.len() + if has_limit { 2 } else { 0 });
         args.extend(filter_args);
-        query.push_str(" AND "); // assumes WHERE already occurs
-        query.push_str(&filter_clause);
+        extended = String::with_capacity(query.len() + filter_clause.len() + 32);
+        extended.push_str(query);
+        extended.push_str(" AND "); // assumes WHERE already occurs
+        extended.push_str(&filter_clause);
+        if let Some(hint) = hint {
+            extended = Q::hint_query(extended, hint);
+        }
+    } else {
+        extended = String::with_capacity(query.len() + 32);
+        extended.push_str(query);
+    }
+    if has_limit {
+        extended = Q::limit_query(extended, args, offset, limit);
+    }
+    Ok(Cow::Owned(extended))
+}
+
+/// Combine a limited scan query for each category into a set of compound queries.
+///
+/// The scan query must select from `items i`, using the placeholders `$1`, `$2`
+/// and `$3` for the profile ID, entry kind and encrypted category. The results
+/// are prefixed by a `grp` column holding the index of the category. Categories
+/// are split between queries as needed to stay within the `MAX_PARAMS` and
+/// `MAX_COMPOUND_SELECT` limits of the database.
+pub fn grouped_scan_queries<'q, Q: QueryPrepare>(
+    query: &str,
+    profile_id: ProfileId,
+    kind: EntryKind,
+    enc_categories: Vec<Vec<u8>>,
+    tag_filter: Option<TagFilter>,
+    key: &ProfileKey,
+    limit: i64,
+) -> Result<Vec<(String, QueryParams<'q, Q::DB>)>, Error>
+where
+    i16: for<'e> Encode<'e, Q::DB> + Type<Q::DB>,
+    i64: for<'e> Encode<'e, Q::DB> + Type<Q::DB>,
+    Vec<u8>: for<'e> Encode<'e, Q::DB> + Type<Q::DB>,
+{
+    let filter_len = encode_tag_filter::<Q>(tag_filter.clone(), key, 0)?
+        .map(|(_, filter_args)| filter_args.len())
+        .unwrap_or(0);
+    let group_len = filter_len + 4;
+    if group_len > Q::MAX_PARAMS {
+        return Err(err_msg!(
+            Input,
+            "Tag filter exceeds the maximum of {} query parameters",
+            Q::MAX_PARAMS
+        ));
+    }
+    let chunk_size = (Q::MAX_PARAMS / group_len).min(Q::MAX_COMPOUND_SELECT);
+
+    let mut queries = Vec::new();
+    let mut grouped = String::new();
+    let mut args = QueryParams::new();
+    for (idx, enc_category) in enc_categories.into_iter().enumerate() {
+        if idx > 0 && idx % chunk_size == 0 {
+            grouped.push_str(" ORDER BY grp, id DESC");
+            queries.push((
+                std::mem::take(&mut grouped),
+                std::mem::replace(&mut args, QueryParams::new()),
+            ));
+        }
+        let mut sub_query = replace_arg_placeholders::<Q>(query, (args.len() as i64) + 1);
+        args.push(profile_id);
+        args.push(kind as i16);
+        args.push(enc_category);
+        if let Some((filter_clause, filter_args)) =
+            encode_tag_filter::<Q>(tag_filter.clone(), key, args.len())?
+        {
+            args.extend(filter_args);
+            sub_query.push_str(" AND "); // assumes WHERE already occurs
+            sub_query.push_str(&filter_clause);
+        }
+        sub_query.push_str(" ORDER BY i.id DESC LIMIT ");
+        sub_query.push_str(&Q::placeholder((args.len() as i64) + 1));
+        args.push(limit);
+        if !grouped.is_empty() {
+            grouped.push_str(" UNION ALL ");
+        }
+        grouped.push_str(&format!(
+            "SELECT {} AS grp, g.* FROM ({}) AS g",
+            idx, sub_query
+        ));
+    }
+    if !grouped.is_empty() {
+        grouped.push_str(" ORDER BY grp, id DESC");
+        queries.push((grouped, args));
+    }
+    Ok(queries)
+}
+
+/// Derive the name index values for each `/`-separated prefix of an entry name,
+/// not including the full name
+pub fn encode_name_prefixes(
+    key: &ProfileKey,
+    category: &[u8],
+    name: &[u8],
+) -> Result<Vec<Vec<u8>>, Error> {
+    name.iter()
+        .enumerate()
+        .filter(|(idx, c)| **c == b'/' && idx + 1 < name.len())
+        .map(|(idx, _)| key.entry_name_prefix_hash(category, &name[..=idx]))
+        .collect()
+}
+
+/// Derive the category index values for each `::`-separated prefix of an entry
+/// category, not including the full category
+pub fn encode_category_prefixes(key: &ProfileKey, category: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let sep = CATEGORY_SEPARATOR.as_bytes();
+    (0..category.len().saturating_sub(sep.len()))
+        .filter(|idx| &category[*idx..(idx + sep.len())] == sep)
+        .map(|idx| key.entry_category_prefix_hash(&category[..(idx + sep.len())]))
+        .collect()
+}
+
+/// Derive the name and category index values of an entry, for each of the
+/// indexes which are enabled
+pub fn encode_index_prefixes(
+    key: &ProfileKey,
+    category: &[u8],
+    name: &[u8],
+    name_index: bool,
+    category_index: bool,
+) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>), Error> {
+    let enc_prefixes = if name_index {
+        encode_name_prefixes(key, category, name)?
+    } else {
+        vec![]
     };
-    if offset.is_some() || limit.is_some() {
-        query = Q::limit_query(query, args, offset, limit);
+    let enc_category_prefixes = if category_index {
+        encode_category_prefixes(key, category)?
+    } else {
+        vec![]
     };
-    Ok(query)
+    Ok((enc_prefixes, enc_category_prefixes))
+}
+
+/// Load the key of a profile whose existing records are added to a newly
+/// enabled index. A profile protected by a passphrase can only be indexed while
+/// it is unlocked: `None` is returned for a locked profile, whose records are
+/// added to the index when it is next unlocked.
+pub async fn index_profile_key(
+    key_cache: &KeyCache,
+    profile_id: ProfileId,
+    name: &str,
+    enc_key: Vec<u8>,
+    protected: bool,
+) -> Result<Option<Arc<ProfileKey>>, Error> {
+    if protected {
+        Ok(key_cache.get_profile(name).await.map(|(_, key)| key))
+    } else {
+        Ok(Some(Arc::new(
+            key_cache.load_key(profile_id, enc_key).await?,
+        )))
+    }
+}
+
+/// Encode the name and category index prefixes of existing records, given
+/// their IDs and encrypted categories and names
+pub fn encode_backfill_prefixes(
+    key: &ProfileKey,
+    items: Vec<(i64, Vec<u8>, Vec<u8>)>,
+    name_index: bool,
+    category_index: bool,
+) -> Result<(Vec<(i64, Vec<u8>)>, Vec<(i64, Vec<u8>)>), Error> {
+    let mut name_prefixes = vec![];
+    let mut category_prefixes = vec![];
+    for (item_id, enc_category, enc_name) in items {
+        let category = key.decrypt_entry_category(enc_category)?;
+        if name_index {
+            let name = key.decrypt_entry_name(enc_name)?;
+            for prefix in encode_name_prefixes(key, category.as_bytes(), name.as_bytes())? {
+                name_prefixes.push((item_id, prefix));
+            }
+        }
+        if category_index {
+            for prefix in encode_category_prefixes(key, category.as_bytes())? {
+                category_prefixes.push((item_id, prefix));
+            }
+        }
+    }
+    Ok((name_prefixes, category_prefixes))
+}
+
+/// The name of the `config` entry recording that the records of a locked
+/// profile are yet to be added to an index
+pub fn index_pending_config(index: &str, profile_id: ProfileId) -> String {
+    format!("{}_pending:{}", index, profile_id)
+}
+
+/// Check that an entry category and name do not exceed the maximum length
+pub fn check_entry_key(category: &[u8], name: &[u8]) -> Result<(), Error> {
+    if category.len() > MAX_ENTRY_KEY_LEN {
+        Err(err_msg!(
+            Input,
+            "Entry category exceeds the maximum length of {} bytes",
+            MAX_ENTRY_KEY_LEN
+        ))
+    } else if name.len() > MAX_ENTRY_KEY_LEN {
+        Err(err_msg!(
+            Input,
+            "Entry name exceeds the maximum length of {} bytes",
+            MAX_ENTRY_KEY_LEN
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that a tag name can be referenced in a WQL tag query. Names beginning
+/// with `$` would be parsed as query operators, and names beginning with `~`
+/// would be mistaken for the plaintext tag marker.
+pub fn check_tag_name(idx: usize, name: &str) -> Result<(), Error> {
+    match name.chars().next() {
+        Some(reserved @ '$') | Some(reserved @ '~') => Err(err_msg!(
+            Input,
+            "Invalid tag at index {}: the name '{}' begins with the reserved character '{}'",
+            idx,
+            name,
+            reserved
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Check that a category prefix for `list_categories_prefix` ends with a separator
+pub fn check_category_prefix(category_prefix: &str) -> Result<(), Error> {
+    if category_prefix.ends_with(CATEGORY_SEPARATOR) {
+        Ok(())
+    } else {
+        Err(err_msg!(
+            Input,
+            "Category prefix must end with '{}'",
+            CATEGORY_SEPARATOR
+        ))
+    }
+}
+
+/// Check that a name prefix for `scan_prefix` ends with a separator
+pub fn check_name_prefix(name_prefix: &str) -> Result<(), Error> {
+    if name_prefix.ends_with('/') {
+        Ok(())
+    } else {
+        Err(err_msg!(Input, "Name prefix must end with '/'"))
+    }
 }
 
 pub fn init_keys<'a>(
     method: StoreKeyMethod,
     pass_key: PassKey<'a>,
 ) -> Result<(ProfileKey, Vec<u8>, StoreKey, String), Error> {
-    let (store_key, store_key_ref) = method.resolve(pass_key)?;
-    let profile_key = ProfileKey::new()?;
-    let enc_profile_key = encode_profile_key(&profile_key, &store_key)?;
+    let keys = ProvisionKeys::generate(method, pass_key)?;
     Ok((
-        profile_key,
-        enc_profile_key,
-        store_key,
-        store_key_ref.into_uri(),
+        keys.profile_key,
+        keys.enc_profile_key,
+        keys.store_key,
+        keys.store_key_ref,
     ))
 }
 
@@ -627,7 +2408,49 @@ pub fn encode_profile_key(
     store_key.wrap_data(profile_key.to_bytes()?)
 }
 
+/// Wrap a profile key with a key derived from the profile passphrase, returning
+/// the encrypted key and the reference to be recorded for the profile
+pub fn protect_profile_key(
+    profile_key: &ProfileKey,
+    method: StoreKeyMethod,
+    pass_key: PassKey<'_>,
+) -> Result<(Vec<u8>, String), Error> {
+    if method == StoreKeyMethod::Unprotected || pass_key.is_empty() {
+        return Err(err_msg!(
+            Input,
+            "A passphrase is required to protect a profile"
+        ));
+    }
+    let (wrap_key, wrap_ref) = method.resolve(pass_key)?;
+    Ok((
+        encode_profile_key(profile_key, &wrap_key)?,
+        wrap_ref.into_uri(),
+    ))
+}
+
+/// Derive the wrapping key of a profile protected by a passphrase
+pub fn resolve_profile_wrap_key(reference: &str, pass_key: PassKey<'_>) -> Result<StoreKey, Error> {
+    StoreKeyReference::parse_uri(reference)?.resolve(pass_key)
+}
+
 #[inline]
 pub fn random_profile_name() -> String {
     uuid::Uuid::new_v4().to_string()
 }
+
+/// Check whether the store configuration records that record values are
+/// written within an envelope. Stores which have not opted in have no version
+/// recorded, and values are written in the headerless layout
+pub(crate) fn envelope_version_enabled(recorded: Option<&str>) -> Result<bool, Error> {
+    match recorded.map(str::parse::<u8>) {
+        None => Ok(false),
+        Some(Ok(version)) if version <= ENVELOPE_VERSION => Ok(true),
+        Some(_) => Err(err_msg!(Unsupported, "Unsupported value envelope version")),
+    }
+}
+
+/// Generate the unique identifier recorded for a store when it is provisioned
+#[inline]
+pub fn random_store_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
@@ -5,6 +5,9 @@
 /// Generic backend (from URI) support
 pub mod any;
 
+#[cfg(feature = "blob_store")]
+pub(crate) mod blob;
+
 #[cfg(any(feature = "postgres", feature = "sqlite"))]
 pub(crate) mod db_utils;
 
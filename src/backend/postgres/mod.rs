@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_stream::try_stream;
 
@@ -9,64 +10,274 @@ use futures_lite::{
     stream::{Stream, StreamExt},
 };
 
+use once_cell::sync::Lazy;
 use sqlx::{
     pool::PoolConnection,
-    postgres::{PgPool, Postgres},
-    Row,
+    postgres::{PgConnection, PgPool, PgQueryResult, PgRow, Postgres},
+    Executor, Row,
 };
 
 use crate::{
     backend::{
         db_utils::{
-            decode_tags, decrypt_scan_batch, encode_profile_key, encode_tag_filter,
-            expiry_timestamp, extend_query, prepare_tags, random_profile_name,
-            replace_arg_placeholders, DbSession, DbSessionActive, DbSessionRef, EncScanEntry,
-            ExtDatabase, QueryParams, QueryPrepare, PAGE_SIZE,
+            audit_entry, check_category_prefix, check_entry_key, check_name_prefix, decode_tags,
+            decrypt_scan_batch, decrypt_scan_batch_with_ids, decrypt_tags, decrypt_value_type,
+            delete_category_batches, encode_backfill_prefixes, encode_index_prefixes,
+            encode_profile_key, encode_tag_filter, encrypt_value_type, expiry_query,
+            expiry_timestamp, extend_query, grouped_scan_queries, health_status,
+            index_pending_config, index_profile_key, item_delete_query, item_query, prepare_tags,
+            protect_profile_key, random_profile_name, replace_arg_placeholders,
+            resolve_profile_wrap_key, run_probe, run_query, scan_cursor_check, DbSession,
+            DbSessionActive, DbSessionRef, DbSettings, EncAuditEntry, EncScanEntry, ExecResult,
+            Expiry, ExtDatabase, QueryParams, QueryPrepare, CATEGORY_INDEX, NAME_INDEX, PAGE_SIZE,
         },
         types::{Backend, QueryBackend},
     },
+    crypto::buffer::SecretBytes,
     error::Error,
     future::{unblock, BoxFuture},
-    protect::{EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, StoreKeyMethod},
-    storage::{EncEntryTag, Entry, EntryKind, EntryOperation, EntryTag, Scan, TagFilter},
+    protect::{
+        EncryptionPolicy, EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, StoreKey,
+        StoreKeyMethod, StoreKeyReference, ENVELOPE_VERSION, PROFILE_KEY_ALG,
+    },
+    storage::{
+        AccessEvent, AccessLogEntry, ConditionalFetch, DecryptionAudit, DecryptionAuditOptions,
+        DecryptionFailure, EncEntryTag, Entry, EntryAccess, EntryKind, EntryOperation, EntryTag,
+        EntryVersion, ExpiryFilter, ExpiryUpdate, FilteredFetch, HealthStatus, IntegrityReport,
+        NameNormalization, ProfileQuota, ProfileUsage, QueryHint, ResumableScan, Scan, ScanCursor,
+        StoreDiagnostics, StoreInfo, TagFilter, TagRetrieval, TagStats, TaggedEntry,
+        TAG_STATS_DISTINCT_LIMIT,
+    },
 };
 
-const COUNT_QUERY: &'static str = "SELECT COUNT(*) FROM items i
-    WHERE profile_id = $1 AND kind = $2 AND category = $3
-    AND (expiry IS NULL OR expiry > CURRENT_TIMESTAMP)";
-const DELETE_QUERY: &'static str = "DELETE FROM items
+// the encoded tags of a record, as decoded by `decode_tags`
+const TAGS_COLUMN: &'static str =
+    "(SELECT ARRAY_TO_STRING(ARRAY_AGG((it.plaintext + it.value_type) || ':'
+        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')
+        ORDER BY it.plaintext, it.name), ',')
+        FROM items_tags it WHERE it.item_id = i.id) tags";
+const PLAINTEXT_TAGS_COLUMN: &'static str =
+    "(SELECT ARRAY_TO_STRING(ARRAY_AGG((it.plaintext + it.value_type) || ':'
+        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')
+        ORDER BY it.name), ',')
+        FROM items_tags it WHERE it.item_id = i.id AND it.plaintext = 1) tags";
+const TAG_COUNT_COLUMN: &'static str =
+    "(SELECT COUNT(*) FROM items_tags it WHERE it.item_id = i.id) tag_count";
+const EXPIRY_COLUMN: &'static str = "CAST(EXTRACT(EPOCH FROM i.expiry) * 1000 AS BIGINT) AS expiry";
+
+static COUNT_QUERY: Lazy<String> = Lazy::new(|| item_query::<PostgresStore>("COUNT(*)", ""));
+static DELETE_QUERY: Lazy<String> = Lazy::new(|| item_delete_query::<PostgresStore>("i.name = $$"));
+static DELETE_ALL_QUERY: Lazy<String> = Lazy::new(|| item_delete_query::<PostgresStore>(""));
+static FETCH_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.value, {}, i.value_type, {}",
+            TAGS_COLUMN, EXPIRY_COLUMN
+        ),
+        "i.name = $$",
+    )
+});
+static FETCH_EXISTS_QUERY: Lazy<String> =
+    Lazy::new(|| item_query::<PostgresStore>("COUNT(*)", "i.name = $$"));
+// the tag columns selected for each `TagRetrieval` mode other than `All`
+static FETCH_PLAINTEXT_TAGS_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.value, {}, i.value_type, {}, {}",
+            PLAINTEXT_TAGS_COLUMN, EXPIRY_COLUMN, TAG_COUNT_COLUMN
+        ),
+        "i.name = $$",
+    )
+});
+static FETCH_TAG_COUNT_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.value, NULL tags, i.value_type, {}, {}",
+            EXPIRY_COLUMN, TAG_COUNT_COLUMN
+        ),
+        "i.name = $$",
+    )
+});
+static FETCH_NO_TAGS_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!("i.id, i.value, NULL tags, i.value_type, {}", EXPIRY_COLUMN),
+        "i.name = $$",
+    )
+});
+static FETCH_QUERY_UPDATE: Lazy<String> = Lazy::new(|| format!("{} FOR UPDATE", *FETCH_QUERY));
+static SCAN_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, {}",
+            TAGS_COLUMN, EXPIRY_COLUMN
+        ),
+        "",
+    )
+});
+static SCAN_PREFIX_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, {}",
+            TAGS_COLUMN, EXPIRY_COLUMN
+        ),
+        "i.id IN (SELECT item_id FROM items_name_index WHERE prefix = $$)",
+    )
+});
+static SCAN_AFTER_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<PostgresStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, {}",
+            TAGS_COLUMN, EXPIRY_COLUMN
+        ),
+        "i.id > $$",
+    )
+});
+
+// all kinds of record are audited, including expired records
+static AUDIT_QUERY: Lazy<String> = Lazy::new(|| {
+    format!(
+        "SELECT i.id, i.category, i.name, i.value, i.value_type, {}, i.kind
+        FROM items i WHERE i.profile_id = $1 AND i.id > $2 ORDER BY i.id LIMIT $3",
+        TAGS_COLUMN
+    )
+});
+
+const EXPIRY_FETCH_QUERY: &'static str =
+    "SELECT CAST(EXTRACT(EPOCH FROM expiry) * 1000 AS BIGINT) FROM items
     WHERE profile_id = $1 AND kind = $2 AND category = $3 AND name = $4";
-const FETCH_QUERY: &'static str = "SELECT id, value,
-    (SELECT ARRAY_TO_STRING(ARRAY_AGG(it.plaintext || ':'
-        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')), ',')
-        FROM items_tags it WHERE it.item_id = i.id) tags
-    FROM items i
-    WHERE profile_id = $1 AND kind = $2 AND category = $3 AND name = $4
-    AND (expiry IS NULL OR expiry > CURRENT_TIMESTAMP)";
-const FETCH_QUERY_UPDATE: &'static str = "SELECT id, value,
-    (SELECT ARRAY_TO_STRING(ARRAY_AGG(it.plaintext || ':'
-        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')), ',')
-        FROM items_tags it WHERE it.item_id = i.id) tags
-    FROM items i
-    WHERE profile_id = $1 AND kind = $2 AND category = $3 AND name = $4
-    AND (expiry IS NULL OR expiry > CURRENT_TIMESTAMP) FOR UPDATE";
+const HEALTH_PROFILE_QUERY: &'static str =
+    "SELECT id, profile_key, reference FROM profiles WHERE name = $1";
+const HEALTH_VERSION_QUERY: &'static str = "SELECT value FROM config WHERE name = 'version'";
+const INFO_CONFIG_QUERY: &'static str = "SELECT name, value FROM config
+    WHERE name IN ('default_profile', 'key', 'store_id', 'version')";
 const INSERT_QUERY: &'static str =
-    "INSERT INTO items (profile_id, kind, category, name, value, expiry)
-    VALUES ($1, $2, $3, $4, $5, $6)
+    "INSERT INTO items (profile_id, kind, category, name, value, value_type, expiry)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
     ON CONFLICT DO NOTHING RETURNING id";
-const SCAN_QUERY: &'static str = "SELECT id, name, value,
-    (SELECT ARRAY_TO_STRING(ARRAY_AGG(it.plaintext || ':'
-        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')), ',')
-        FROM items_tags it WHERE it.item_id = i.id) tags
-    FROM items i WHERE profile_id = $1 AND kind = $2 AND category = $3
-    AND (expiry IS NULL OR expiry > CURRENT_TIMESTAMP)";
-const DELETE_ALL_QUERY: &'static str = "DELETE FROM items i
-    WHERE i.profile_id = $1 AND i.kind = $2 AND i.category = $3";
+const MISSING_KEYS_QUERY: &'static str = "SELECT p.id, COUNT(i.id) FROM profiles p
+    LEFT JOIN items i ON i.profile_id = p.id
+    WHERE p.profile_key IS NULL GROUP BY p.id ORDER BY p.id";
+const ORPHANED_ITEMS_QUERY: &'static str = "SELECT i.profile_id, COUNT(i.id) FROM items i
+    LEFT JOIN profiles p ON p.id = i.profile_id
+    WHERE p.id IS NULL GROUP BY i.profile_id ORDER BY i.profile_id";
+const ORPHANED_TAGS_QUERY: &'static str = "SELECT COUNT(*) FROM items_tags it
+    WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = it.item_id)";
+const ORPHANED_TAGS_DELETE_QUERY: &'static str = "DELETE FROM items_tags
+    WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = items_tags.item_id)";
+const PROFILE_INSERT_QUERY: &'static str = "INSERT INTO profiles (id, name, profile_key)
+    VALUES (COALESCE($3, nextval(pg_get_serial_sequence('profiles', 'id'))), $1, $2)
+    ON CONFLICT DO NOTHING RETURNING id";
+// advance the profile ID sequence past an ID selected by an `IdSource`, so that
+// profiles created without one do not collide with it
+const PROFILE_SEQUENCE_QUERY: &'static str =
+    "SELECT setval(pg_get_serial_sequence('profiles', 'id'),
+    GREATEST((SELECT MAX(id) FROM profiles), 1))";
+const PURGE_EXPIRED_QUERY: &'static str = "DELETE FROM items
+    WHERE expiry IS NOT NULL AND expiry <= CURRENT_TIMESTAMP";
+const SCAN_GROUP_QUERY: &'static str = "SELECT i.id, i.name, i.value,
+    (SELECT ARRAY_TO_STRING(ARRAY_AGG((it.plaintext + it.value_type) || ':'
+        || ENCODE(it.name, 'hex') || ':' || ENCODE(it.value, 'hex')
+        ORDER BY it.plaintext, it.name), ',')
+        FROM items_tags it WHERE it.item_id = i.id) tags, i.value_type,
+    CAST(EXTRACT(EPOCH FROM i.expiry) * 1000 AS BIGINT) AS expiry
+    FROM items i WHERE i.profile_id = $1 AND i.kind = $2 AND i.category = $3
+    AND (i.expiry IS NULL OR i.expiry > CURRENT_TIMESTAMP)";
+const DELETE_ID_QUERY: &'static str = "DELETE FROM items WHERE id = $1";
+const RENAME_FETCH_QUERY: &'static str = "SELECT id, value, value_type FROM items
+    WHERE profile_id = $1 AND kind = $2 AND category = $3 AND name = $4
+    AND (expiry IS NULL OR expiry > CURRENT_TIMESTAMP) FOR UPDATE";
+const RENAME_EXISTS_QUERY: &'static str = "SELECT COUNT(*) FROM items
+    WHERE profile_id = $1 AND kind = $2 AND category = $3 AND name = $4";
+const RENAME_UPDATE_QUERY: &'static str = "UPDATE items
+    SET category = $1, name = $2, value = $3, value_type = $4 WHERE id = $5";
+const ACCESS_LOG_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS access_log (
+        id BIGSERIAL,
+        logged_at TIMESTAMPTZ NOT NULL,
+        event TEXT NOT NULL,
+        client TEXT NULL,
+        profile TEXT NULL,
+        success BOOLEAN NOT NULL,
+        PRIMARY KEY (id)
+    );
+    CREATE INDEX IF NOT EXISTS ix_access_log_logged_at ON access_log (logged_at);
+    INSERT INTO config (name, value) VALUES ('access_log', '1')
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value;
+";
+const ACCESS_LOG_INSERT_QUERY: &'static str = "INSERT INTO access_log
+    (logged_at, event, client, profile, success) VALUES ($1, $2, $3, $4, $5)";
+const ACCESS_LOG_FETCH_QUERY: &'static str =
+    "SELECT id, logged_at, event, client, profile, success FROM access_log
+    WHERE $1::TIMESTAMPTZ IS NULL OR logged_at >= $1 ORDER BY id DESC LIMIT $2";
+const ACCESS_LOG_PURGE_QUERY: &'static str = "DELETE FROM access_log WHERE logged_at < $1";
+const ACCESS_LOG_TABLE_QUERY: &'static str = "SELECT COUNT(*) FROM information_schema.tables
+    WHERE table_schema = CURRENT_SCHEMA() AND table_name = 'access_log'";
+const NAME_INDEX_DELETE_QUERY: &'static str = "DELETE FROM items_name_index WHERE item_id = $1";
+const CATEGORY_INDEX_DELETE_QUERY: &'static str =
+    "DELETE FROM items_category_index WHERE item_id = $1";
+const NAME_INDEX_INSERT_QUERY: &'static str = "INSERT INTO items_name_index
+    (item_id, prefix) VALUES ($1, $2) ON CONFLICT DO NOTHING";
+const NAME_INDEX_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_name_index (
+        item_id BIGINT NOT NULL,
+        prefix BYTEA NOT NULL,
+        PRIMARY KEY (item_id, prefix),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS ix_items_name_index_prefix ON items_name_index (prefix);
+    INSERT INTO config (name, value) VALUES ('name_index', '1')
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value;
+";
+const TAG_DISTINCT_QUERY: &'static str = "SELECT COUNT(*) FROM
+    (SELECT DISTINCT it.value FROM items_tags it JOIN items i ON i.id = it.item_id
+        WHERE i.profile_id = $1 AND i.kind = $2 AND i.category = $3
+        AND it.name = $4 AND it.plaintext = 1 LIMIT $5) v";
 const TAG_INSERT_QUERY: &'static str = "INSERT INTO items_tags
-    (item_id, name, value, plaintext) VALUES ($1, $2, $3, $4)";
+    (item_id, name, value, plaintext, value_type) VALUES ($1, $2, $3, $4, $5)";
+const TAG_STATS_QUERY: &'static str = "SELECT it.name, COUNT(*), SUM(it.plaintext)::BIGINT,
+    AVG(CASE WHEN it.plaintext = 1 THEN OCTET_LENGTH(it.value) END)::FLOAT8
+    FROM items_tags it JOIN items i ON i.id = it.item_id
+    WHERE i.profile_id = $1 AND i.kind = $2 AND i.category = $3
+    GROUP BY it.name";
+const TRUNCATE_BATCH_QUERY: &'static str = "DELETE FROM items WHERE id IN
+    (SELECT id FROM items WHERE profile_id = $1 AND kind = $2 AND category = $3 LIMIT $4)";
+const CATEGORIES_QUERY: &'static str = "SELECT DISTINCT category FROM items
+    WHERE profile_id = $1 AND kind = $2";
+const CATEGORIES_PREFIX_QUERY: &'static str = "SELECT DISTINCT category FROM items
+    WHERE profile_id = $1 AND kind = $2
+    AND id IN (SELECT item_id FROM items_category_index WHERE prefix = $3)";
+const CATEGORY_INDEX_INSERT_QUERY: &'static str = "INSERT INTO items_category_index
+    (item_id, prefix) VALUES ($1, $2) ON CONFLICT DO NOTHING";
+const INDEX_CONFIG_QUERY: &'static str = "SELECT name FROM config
+    WHERE name IN ('category_index', 'name_index') AND value = '1'";
+const INDEX_PENDING_DELETE_QUERY: &'static str = "DELETE FROM config WHERE name IN ($1, $2)";
+const INDEX_PENDING_INSERT_QUERY: &'static str =
+    "INSERT INTO config (name, value) VALUES ($1, '1') ON CONFLICT DO NOTHING";
+const INDEX_PENDING_QUERY: &'static str = "SELECT name FROM config WHERE name IN ($1, $2)";
+// taken by a writer before checking whether the indexes are enabled, conflicting
+// with the lock taken while an index is enabled
+const INDEX_WRITE_LOCK: &'static str = "LOCK TABLE items IN ROW EXCLUSIVE MODE";
+// taken while an index is enabled, so that records written concurrently are
+// either added to the index by the writer or found by the backfill
+const INDEX_ENABLE_LOCK: &'static str = "LOCK TABLE items IN SHARE MODE";
+const CATEGORY_INDEX_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_category_index (
+        item_id BIGINT NOT NULL,
+        prefix BYTEA NOT NULL,
+        PRIMARY KEY (item_id, prefix),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS ix_items_category_index_prefix
+        ON items_category_index (prefix);
+    INSERT INTO config (name, value) VALUES ('category_index', '1')
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value;
+";
 
 mod provision;
 pub use provision::PostgresStoreOptions;
+use provision::SCHEMA_VERSION;
 
 #[cfg(any(test, feature = "pg_test"))]
 pub mod test_db;
@@ -78,6 +289,7 @@ pub struct PostgresStore {
     key_cache: Arc<KeyCache>,
     host: String,
     name: String,
+    settings: Arc<DbSettings>,
 }
 
 impl PostgresStore {
@@ -87,6 +299,7 @@ impl PostgresStore {
         key_cache: KeyCache,
         host: String,
         name: String,
+        settings: DbSettings,
     ) -> Self {
         Self {
             conn_pool,
@@ -94,31 +307,236 @@ impl PostgresStore {
             key_cache: Arc::new(key_cache),
             host,
             name,
+            settings: Arc::new(settings),
+        }
+    }
+
+    /// Replace the store key, wrapping the profile keys again with the new key
+    async fn rekey_inner(
+        &mut self,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'static>,
+    ) -> Result<(), Error> {
+        let (store_key, store_key_ref) = unblock(move || method.resolve(pass_key)).await?;
+        let store_key = Arc::new(store_key);
+        let mut txn = self.conn_pool.begin().await?;
+        // profiles protected by a passphrase are not wrapped by the store key
+        let mut rows = sqlx::query("SELECT id, profile_key FROM profiles WHERE reference IS NULL")
+            .fetch(&mut txn);
+        let mut upd_keys = BTreeMap::<ProfileId, Vec<u8>>::new();
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            let pid = row.try_get(0)?;
+            let enc_key = row.try_get(1)?;
+            let profile_key = self.key_cache.load_key(pid, enc_key).await?;
+            let upd_key = unblock({
+                let store_key = store_key.clone();
+                move || encode_profile_key(&profile_key, &store_key)
+            })
+            .await?;
+            upd_keys.insert(pid, upd_key);
+        }
+        drop(rows);
+        for (pid, key) in upd_keys {
+            if sqlx::query("UPDATE profiles SET profile_key=$1 WHERE id=$2")
+                .bind(key)
+                .bind(pid)
+                .execute(&mut txn)
+                .await?
+                .rows_affected()
+                != 1
+            {
+                return Err(err_msg!(Backend, "Error updating profile key"));
+            }
+        }
+        if sqlx::query("UPDATE config SET value=$1 WHERE name='key'")
+            .bind(store_key_ref.into_uri())
+            .execute(&mut txn)
+            .await?
+            .rows_affected()
+            != 1
+        {
+            return Err(err_msg!(Backend, "Error updating store key"));
+        }
+        txn.commit().await?;
+        self.key_cache = Arc::new(KeyCache::new(store_key));
+        self.settings.info.invalidate();
+        Ok(())
+    }
+
+    /// Record an event in the access log, if enabled. The event may follow
+    /// changes which are already committed, so a failure to record it is
+    /// logged rather than returned in place of the outcome of the operation
+    async fn log_access(&self, event: AccessEvent, profile: Option<&str>, success: bool) {
+        if !self.settings.access_log() {
+            return;
+        }
+        let logged = async {
+            let mut conn = self.conn_pool.acquire().await?;
+            log_access(
+                &mut conn,
+                self.settings.client_label.as_deref(),
+                event,
+                profile,
+                success,
+            )
+            .await
+        }
+        .await;
+        if let Err(err) = logged {
+            warn!(
+                "Error recording {} in the access log: {}",
+                event.as_str(),
+                err
+            );
+        }
+    }
+
+    /// Add the existing records of each profile to a newly enabled index. The
+    /// records of a locked profile are added when it is next unlocked.
+    async fn backfill_index(&self, conn: &mut PgConnection, index: &str) -> Result<(), Error> {
+        let profiles = sqlx::query("SELECT id, name, profile_key, reference FROM profiles")
+            .fetch_all(&mut *conn)
+            .await?;
+        for row in profiles {
+            let profile_id: ProfileId = row.try_get(0)?;
+            let name: String = row.try_get(1)?;
+            let protected = row.try_get::<Option<String>, _>(3)?.is_some();
+            match index_profile_key(
+                &self.key_cache,
+                profile_id,
+                &name,
+                row.try_get(2)?,
+                protected,
+            )
+            .await?
+            {
+                Some(key) => {
+                    add_index_records(
+                        conn,
+                        profile_id,
+                        key,
+                        index == NAME_INDEX,
+                        index == CATEGORY_INDEX,
+                    )
+                    .await?
+                }
+                None => {
+                    debug!("Deferred indexing the records of locked profile '{}'", name);
+                    sqlx::query(INDEX_PENDING_INSERT_QUERY)
+                        .bind(index_pending_config(index, profile_id))
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the records of an unlocked profile to the indexes enabled while it
+    /// was locked
+    async fn backfill_unlocked(
+        &self,
+        profile_id: ProfileId,
+        key: Arc<ProfileKey>,
+    ) -> Result<(), Error> {
+        let name_pending = index_pending_config(NAME_INDEX, profile_id);
+        let category_pending = index_pending_config(CATEGORY_INDEX, profile_id);
+        let mut txn = self.conn_pool.begin().await?;
+        let pending = sqlx::query_scalar::<_, String>(INDEX_PENDING_QUERY)
+            .bind(&name_pending)
+            .bind(&category_pending)
+            .fetch_all(&mut txn)
+            .await?;
+        if pending.is_empty() {
+            return Ok(());
         }
+        add_index_records(
+            &mut txn,
+            profile_id,
+            key,
+            pending.contains(&name_pending),
+            pending.contains(&category_pending),
+        )
+        .await?;
+        sqlx::query(INDEX_PENDING_DELETE_QUERY)
+            .bind(&name_pending)
+            .bind(&category_pending)
+            .execute(&mut txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    fn scan_entries(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: Option<String>,
+        tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            session.set_expiry_filter(expiry);
+            session.set_query_hint(hint);
+            let mut active = session.owned_ref();
+            let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
+            let scan = perform_scan(
+                active,
+                profile_id,
+                key.clone(),
+                kind,
+                category.clone(),
+                name_prefix,
+                tag_filter,
+                None,
+                offset,
+                limit,
+                false,
+            );
+            let settings = self.settings.clone();
+            let stream = scan.then(move |enc_rows| {
+                let category = category.clone();
+                let key = key.clone();
+                let settings = settings.clone();
+                unblock(move || decrypt_scan_batch(category, enc_rows?, &key, &settings))
+            });
+            Scan::new(stream, PAGE_SIZE).tracked(&self.settings.scans)
+        })
     }
 }
 
 impl Backend for PostgresStore {
     type Session = DbSession<Postgres>;
 
-    fn create_profile(&self, name: Option<String>) -> BoxFuture<'_, Result<String, Error>> {
+    fn create_profile(
+        &self,
+        name: Option<String>,
+        policy: EncryptionPolicy,
+    ) -> BoxFuture<'_, Result<String, Error>> {
         let name = name.unwrap_or_else(random_profile_name);
         Box::pin(async move {
-            let key = ProfileKey::new()?;
+            let key = ProfileKey::new_with_policy(policy)?;
             let enc_key = key.to_bytes()?;
             let mut conn = self.conn_pool.acquire().await?;
-            if let Some(pid) = sqlx::query_scalar(
-                "INSERT INTO profiles (name, profile_key) VALUES ($1, $2) 
-                ON CONFLICT DO NOTHING RETURNING id",
+            if let Some(pid) = insert_profile(
+                &mut conn,
+                &name,
+                enc_key.as_ref(),
+                self.settings.next_profile_id(),
             )
-            .bind(&name)
-            .bind(enc_key.as_ref())
-            .fetch_optional(&mut conn)
             .await?
             {
                 self.key_cache
                     .add_profile(name.clone(), pid, Arc::new(key))
                     .await;
+                self.settings.info.invalidate();
                 Ok(name)
             } else {
                 Err(err_msg!(Duplicate, "Duplicate profile name"))
@@ -130,181 +548,1334 @@ impl Backend for PostgresStore {
         self.default_profile.as_str()
     }
 
+    fn load_profile(&self, name: String) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            resolve_profile_key(&mut conn, self.key_cache.clone(), name).await?;
+            Ok(())
+        })
+    }
+
     fn remove_profile(&self, name: String) -> BoxFuture<'_, Result<bool, Error>> {
         Box::pin(async move {
             let mut conn = self.conn_pool.acquire().await?;
-            Ok(sqlx::query("DELETE FROM profiles WHERE name=$1")
+            let removed = sqlx::query("DELETE FROM profiles WHERE name=$1")
                 .bind(&name)
                 .execute(&mut conn)
                 .await?
                 .rows_affected()
-                != 0)
+                != 0;
+            self.settings.info.invalidate();
+            Ok(removed)
         })
     }
 
-    fn rekey_backend(
-        &mut self,
-        method: StoreKeyMethod,
-        pass_key: PassKey<'_>,
+    fn list_profiles(&self) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let names = sqlx::query_scalar("SELECT name FROM profiles ORDER BY name")
+                .fetch_all(&mut conn)
+                .await?;
+            Ok(names)
+        })
+    }
+
+    fn export_profile_key(
+        &self,
+        profile: Option<String>,
+    ) -> BoxFuture<'_, Result<SecretBytes, Error>> {
+        let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let (_, key) =
+                resolve_profile_key(&mut conn, self.key_cache.clone(), profile.clone()).await?;
+            info!("Exporting the key of profile '{}'", profile);
+            key.to_bytes()
+        })
+    }
+
+    fn import_profile_key(
+        &self,
+        profile: String,
+        key: SecretBytes,
+        force: bool,
     ) -> BoxFuture<'_, Result<(), Error>> {
-        let pass_key = pass_key.into_owned();
         Box::pin(async move {
-            let (store_key, store_key_ref) = unblock(move || method.resolve(pass_key)).await?;
-            let store_key = Arc::new(store_key);
+            let store_key = self.key_cache.store_key.clone();
+            let (profile_key, enc_key) = unblock(move || {
+                let profile_key = ProfileKey::from_slice(key.as_ref())
+                    .map_err(err_map!(Input, "Invalid profile key"))?;
+                let enc_key = encode_profile_key(&profile_key, &store_key)?;
+                Result::<_, Error>::Ok((profile_key, enc_key))
+            })
+            .await?;
             let mut txn = self.conn_pool.begin().await?;
-            let mut rows = sqlx::query("SELECT id, profile_key FROM profiles").fetch(&mut txn);
-            let mut upd_keys = BTreeMap::<ProfileId, Vec<u8>>::new();
-            while let Some(row) = rows.next().await {
-                let row = row?;
-                let pid = row.try_get(0)?;
-                let enc_key = row.try_get(1)?;
-                let profile_key = self.key_cache.load_key(enc_key).await?;
-                let upd_key = unblock({
-                    let store_key = store_key.clone();
-                    move || encode_profile_key(&profile_key, &store_key)
-                })
-                .await?;
-                upd_keys.insert(pid, upd_key);
-            }
-            drop(rows);
-            for (pid, key) in upd_keys {
-                if sqlx::query("UPDATE profiles SET profile_key=$1 WHERE id=$2")
-                    .bind(key)
-                    .bind(pid)
-                    .execute(&mut txn)
-                    .await?
-                    .rows_affected()
-                    != 1
+            let found =
+                sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=$1")
+                    .bind(&profile)
+                    .fetch_optional(&mut txn)
+                    .await?;
+            let pid = if let Some(row) = found {
+                let pid: ProfileId = row.try_get(0)?;
+                let protected = row.try_get::<Option<String>, _>(2)?.is_some();
+                if !force
+                    && (protected
+                        || self.key_cache.get_profile(&profile).await.is_some()
+                        || self.key_cache.load_key(pid, row.try_get(1)?).await.is_ok())
                 {
-                    return Err(err_msg!(Backend, "Error updating profile key"));
+                    return Err(err_msg!(
+                        Duplicate,
+                        "Profile '{}' has a working key, which is only replaced when forced",
+                        profile
+                    ));
                 }
+                sqlx::query("UPDATE profiles SET profile_key=$1, reference=NULL WHERE id=$2")
+                    .bind(&enc_key)
+                    .bind(pid)
+                    .execute(&mut txn)
+                    .await?;
+                pid
+            } else {
+                insert_profile(
+                    &mut txn,
+                    &profile,
+                    &enc_key,
+                    self.settings.next_profile_id(),
+                )
+                .await?
+                .ok_or_else(|| err_msg!(Duplicate, "Duplicate profile name"))?
+            };
+            txn.commit().await?;
+            info!("Imported the key of profile '{}'", profile);
+            self.key_cache
+                .add_profile(profile, pid, Arc::new(profile_key))
+                .await;
+            self.settings.info.invalidate();
+            Ok(())
+        })
+    }
+
+    fn protect_profile(
+        &self,
+        profile: String,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
+        Box::pin(async move {
+            if profile == self.default_profile {
+                return Err(err_msg!(
+                    Unsupported,
+                    "The default profile cannot be protected by a passphrase"
+                ));
             }
-            if sqlx::query("UPDATE config SET value=$1 WHERE name='key'")
-                .bind(store_key_ref.into_uri())
-                .execute(&mut txn)
+            let mut conn = self.conn_pool.acquire().await?;
+            let (pid, key) =
+                resolve_profile_key(&mut conn, self.key_cache.clone(), profile.clone()).await?;
+            let (enc_key, reference) =
+                unblock(move || protect_profile_key(&key, method, pass_key)).await?;
+            if sqlx::query("UPDATE profiles SET profile_key=$1, reference=$2 WHERE id=$3")
+                .bind(enc_key)
+                .bind(reference)
+                .bind(pid)
+                .execute(&mut conn)
                 .await?
                 .rows_affected()
                 != 1
             {
-                return Err(err_msg!(Backend, "Error updating store key"));
+                return Err(err_msg!(Backend, "Error updating profile key"));
             }
-            txn.commit().await?;
-            self.key_cache = Arc::new(KeyCache::new(store_key));
+            info!("Protected profile '{}' with a passphrase", profile);
             Ok(())
         })
     }
 
+    fn unlock_profile(
+        &self,
+        profile: String,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
+        Box::pin(async move {
+            let unlocked = async {
+                let mut conn = self.conn_pool.acquire().await?;
+                let row =
+                    sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=$1")
+                        .bind(&profile)
+                        .fetch_optional(&mut conn)
+                        .await?
+                        .ok_or_else(|| err_msg!(NotFound, "Profile not found: '{}'", profile))?;
+                let pid = row.try_get(0)?;
+                let reference: String = row.try_get::<Option<String>, _>(2)?.ok_or_else(|| {
+                    err_msg!(
+                        Input,
+                        "Profile '{}' is not protected by a passphrase",
+                        profile
+                    )
+                })?;
+                let wrap_key =
+                    unblock(move || resolve_profile_wrap_key(&reference, pass_key)).await?;
+                let key = Arc::new(
+                    self.key_cache
+                        .unlock_key(pid, row.try_get(1)?, wrap_key)
+                        .await?,
+                );
+                drop(conn);
+                self.backfill_unlocked(pid, key.clone()).await?;
+                self.key_cache.add_profile(profile.clone(), pid, key).await;
+                Result::<_, Error>::Ok(())
+            }
+            .await;
+            self.log_access(AccessEvent::UnlockProfile, Some(&profile), unlocked.is_ok())
+                .await;
+            unlocked
+        })
+    }
+
+    fn lock_profile(&self, profile: String) -> BoxFuture<'_, Result<bool, Error>> {
+        Box::pin(async move {
+            let locked = self.key_cache.lock_profile(&profile).await;
+            if locked {
+                // drop any records decrypted with the profile key
+                self.settings.clear_entry_cache();
+            }
+            Ok(locked)
+        })
+    }
+
+    fn set_profile_quota(
+        &self,
+        _profile: Option<String>,
+        _quota: ProfileQuota,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Profile quotas are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn profile_usage(
+        &self,
+        _profile: Option<String>,
+    ) -> BoxFuture<'_, Result<ProfileUsage, Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Profile quotas are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn rebuild_usage(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Profile quotas are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn clear_cache(&self) {
+        self.settings.clear_entry_cache();
+    }
+
+    fn rekey_backend(
+        &mut self,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
+        Box::pin(async move {
+            let rekeyed = self.rekey_inner(method, pass_key).await;
+            self.log_access(AccessEvent::Rekey, None, rekeyed.is_ok())
+                .await;
+            rekeyed
+        })
+    }
+
+    fn truncate_profile(
+        &self,
+        profile: Option<String>,
+        categories: Option<Vec<String>>,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, i64>, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories = if let Some(categories) = categories {
+                unblock(move || {
+                    categories
+                        .into_iter()
+                        .map(|category| {
+                            let enc_category = key.encrypt_entry_category(
+                                ProfileKey::prepare_input(category.as_bytes()),
+                            )?;
+                            Ok((category, enc_category))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()
+                })
+                .await?
+            } else {
+                let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_QUERY)
+                    .bind(profile_id)
+                    .bind(EntryKind::Item as i16)
+                    .fetch_all(active.connection_mut())
+                    .await?;
+                unblock(move || {
+                    enc_categories
+                        .into_iter()
+                        .map(|enc_category| {
+                            Ok((
+                                key.decrypt_entry_category(enc_category.clone())?,
+                                enc_category,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()
+                })
+                .await?
+            };
+            let mut counts = BTreeMap::new();
+            for (category, enc_category) in enc_categories {
+                let removed = delete_category_batches(
+                    &mut active,
+                    TRUNCATE_BATCH_QUERY,
+                    profile_id,
+                    &enc_category,
+                )
+                .await?;
+                counts.insert(category, removed);
+            }
+            Ok(counts)
+        })
+    }
+
+    fn list_categories(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .fetch_all(active.connection_mut())
+                .await?;
+            drop(active);
+            unblock(move || {
+                let mut categories = enc_categories
+                    .into_iter()
+                    .map(|enc_category| key.decrypt_entry_category(enc_category))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                categories.sort();
+                Ok(categories)
+            })
+            .await
+        })
+    }
+
+    fn list_categories_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category_prefix: String,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        Box::pin(async move {
+            check_category_prefix(&category_prefix)?;
+            if !self.settings.category_index() {
+                return Err(err_msg!(
+                    Unsupported,
+                    "Category index is not enabled for the store"
+                ));
+            }
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let enc_prefix = unblock({
+                let key = key.clone();
+                move || key.entry_category_prefix_hash(category_prefix.as_bytes())
+            })
+            .await?;
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_PREFIX_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .bind(enc_prefix)
+                .fetch_all(active.connection_mut())
+                .await?;
+            drop(active);
+            unblock(move || {
+                let mut categories = enc_categories
+                    .into_iter()
+                    .map(|enc_category| key.decrypt_entry_category(enc_category))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                categories.sort();
+                Ok(categories)
+            })
+            .await
+        })
+    }
+
     fn scan(
         &self,
         profile: Option<String>,
         kind: EntryKind,
         category: String,
         tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        self.scan_entries(
+            profile, kind, category, None, tag_filter, expiry, hint, offset, limit,
+        )
+    }
+
+    fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        if let Err(err) = check_name_prefix(&name_prefix) {
+            return Box::pin(async move { Err(err) });
+        }
+        if !self.settings.name_index() {
+            return Box::pin(async move {
+                Err(err_msg!(
+                    Unsupported,
+                    "Name index is not enabled for the store"
+                ))
+            });
+        }
+        self.scan_entries(
+            profile,
+            kind,
+            category,
+            Some(name_prefix),
+            tag_filter,
+            ExpiryFilter::Exclude,
+            None,
+            offset,
+            limit,
+        )
+    }
+
+    fn scan_resumable(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        cursor: Option<ScanCursor>,
+    ) -> BoxFuture<'_, Result<ResumableScan, Error>> {
         Box::pin(async move {
-            let session = self.session(profile, false)?;
+            let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+            let session = self.session(Some(profile.clone()), false)?;
             let mut active = session.owned_ref();
             let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
+            let filter_str = tag_filter.as_ref().map(TagFilter::to_string).transpose()?;
+            let check = unblock({
+                let key = key.clone();
+                let category = category.clone();
+                move || {
+                    let enc_category =
+                        key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?;
+                    Result::<_, Error>::Ok(scan_cursor_check(&enc_category, filter_str.as_deref()))
+                }
+            })
+            .await?;
+            let last_id = match cursor {
+                Some(cursor) if cursor.check() != check.as_slice() => {
+                    return Err(err_msg!(
+                        Input,
+                        "Scan cursor does not match the store, profile, category or tag filter"
+                    ));
+                }
+                Some(cursor) => cursor.last_id(),
+                None => 0,
+            };
             let scan = perform_scan(
                 active,
                 profile_id,
                 key.clone(),
                 kind,
                 category.clone(),
+                None,
                 tag_filter,
-                offset,
-                limit,
+                Some(last_id),
+                None,
+                None,
                 false,
             );
+            let settings = self.settings.clone();
             let stream = scan.then(move |enc_rows| {
                 let category = category.clone();
                 let key = key.clone();
-                unblock(move || decrypt_scan_batch(category, enc_rows?, &key))
+                let settings = settings.clone();
+                unblock(move || decrypt_scan_batch_with_ids(category, enc_rows?, &key, &settings))
             });
-            Ok(Scan::new(stream, PAGE_SIZE))
+            let scan = Scan::new(stream, PAGE_SIZE).tracked(&self.settings.scans)?;
+            Ok(ResumableScan::new(scan, profile, check, last_id))
+        })
+    }
+
+    fn enable_name_index(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let mut txn = self.conn_pool.begin().await?;
+            txn.execute(INDEX_ENABLE_LOCK).await?;
+            txn.execute(NAME_INDEX_SCHEMA).await?;
+            self.backfill_index(&mut txn, NAME_INDEX).await?;
+            txn.commit().await?;
+            self.settings.set_name_index(true);
+            Ok(())
+        })
+    }
+
+    fn enable_category_index(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let mut txn = self.conn_pool.begin().await?;
+            txn.execute(INDEX_ENABLE_LOCK).await?;
+            txn.execute(CATEGORY_INDEX_SCHEMA).await?;
+            self.backfill_index(&mut txn, CATEGORY_INDEX).await?;
+            txn.commit().await?;
+            self.settings.set_category_index(true);
+            Ok(())
+        })
+    }
+
+    fn enable_access_tracking(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Access tracking is not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn enable_value_history(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Value history is not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn enable_value_etags(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Stored entity tags are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn enable_value_envelopes(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            // existing records are not rewritten, and remain readable
+            sqlx::query(
+                "INSERT INTO config (name, value) VALUES ('envelope_version', $1)
+                ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+            )
+            .bind(ENVELOPE_VERSION.to_string())
+            .execute(&self.conn_pool)
+            .await?;
+            self.settings.set_value_envelope(true);
+            Ok(())
+        })
+    }
+
+    fn enable_access_log(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.conn_pool.execute(ACCESS_LOG_SCHEMA).await?;
+            self.settings.set_access_log(true);
+            Ok(())
+        })
+    }
+
+    fn read_access_log(
+        &self,
+        limit: Option<i64>,
+        since: Option<i64>,
+    ) -> BoxFuture<'_, Result<Vec<AccessLogEntry>, Error>> {
+        Box::pin(async move {
+            let since = since
+                .map(|since| {
+                    chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, since)
+                        .single()
+                        .ok_or_else(|| err_msg!(Input, "Invalid access log timestamp"))
+                })
+                .transpose()?;
+            let mut conn = self.conn_pool.acquire().await?;
+            if sqlx::query_scalar::<_, i64>(ACCESS_LOG_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 0
+            {
+                return Ok(vec![]);
+            }
+            let rows = sqlx::query(ACCESS_LOG_FETCH_QUERY)
+                .bind(since)
+                .bind(limit)
+                .fetch_all(&mut conn)
+                .await?;
+            let mut entries = Vec::with_capacity(rows.len());
+            for row in rows {
+                entries.push(AccessLogEntry {
+                    id: row.try_get(0)?,
+                    logged_at: row.try_get::<Expiry, _>(1)?.timestamp_millis(),
+                    event: AccessEvent::parse(row.try_get(2)?)?,
+                    client: row.try_get(3)?,
+                    profile: row.try_get(4)?,
+                    success: row.try_get(5)?,
+                });
+            }
+            Ok(entries)
+        })
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        self.settings.name_normalization()
+    }
+
+    fn default_expiry_filter(&self) -> ExpiryFilter {
+        self.settings.expiry_filter
+    }
+
+    fn default_query_hint(&self) -> Option<QueryHint> {
+        self.settings.query_hint
+    }
+
+    fn scan_coldest(
+        &self,
+        _profile: Option<String>,
+        _kind: EntryKind,
+        _category: String,
+        _limit: i64,
+    ) -> BoxFuture<'_, Result<Vec<EntryAccess>, Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Access tracking is not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn tag_stats(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+    ) -> BoxFuture<'_, Result<Vec<TagStats>, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let enc_category = unblock({
+                let key = key.clone();
+                move || key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))
+            })
+            .await?;
+            let mut active = acquire_session(&mut session).await?;
+            let rows = sqlx::query(TAG_STATS_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .bind(enc_category.as_slice())
+                .fetch_all(active.connection_mut())
+                .await?;
+            let mut enc_stats = Vec::with_capacity(rows.len());
+            for row in rows {
+                let enc_name: Vec<u8> = row.try_get(0)?;
+                let plaintext_count: i64 = row.try_get(2)?;
+                let distinct_values = if plaintext_count > 0 {
+                    Some(
+                        sqlx::query_scalar(TAG_DISTINCT_QUERY)
+                            .bind(profile_id)
+                            .bind(kind as i16)
+                            .bind(enc_category.as_slice())
+                            .bind(enc_name.as_slice())
+                            .bind(TAG_STATS_DISTINCT_LIMIT)
+                            .fetch_one(active.connection_mut())
+                            .await?,
+                    )
+                } else {
+                    None
+                };
+                enc_stats.push((
+                    enc_name,
+                    TagStats {
+                        name: String::new(),
+                        count: row.try_get(1)?,
+                        plaintext_count,
+                        distinct_values,
+                        avg_value_len: row.try_get(3)?,
+                    },
+                ));
+            }
+            drop(active);
+            unblock(move || {
+                enc_stats
+                    .into_iter()
+                    .map(|(enc_name, mut stats)| {
+                        stats.name = String::from_utf8(key.decrypt_tag_name(enc_name)?.into_vec())
+                            .map_err(err_map!(Unexpected, "Error decoding tag name"))?;
+                        Ok(stats)
+                    })
+                    .collect()
+            })
+            .await
+        })
+    }
+
+    fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        categories: Vec<String>,
+        tag_filter: Option<TagFilter>,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, Vec<Entry>>, Error>> {
+        Box::pin(async move {
+            let categories = categories.into_iter().collect::<BTreeSet<_>>();
+            if categories.is_empty() {
+                return Ok(BTreeMap::new());
+            }
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let (categories, queries) = unblock({
+                let key = key.clone();
+                move || {
+                    let enc_categories = categories
+                        .iter()
+                        .map(|category| {
+                            key.encrypt_entry_category(ProfileKey::prepare_input(
+                                category.as_bytes(),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    let queries = grouped_scan_queries::<PostgresStore>(
+                        SCAN_GROUP_QUERY,
+                        profile_id,
+                        kind,
+                        enc_categories,
+                        tag_filter,
+                        &key,
+                        limit,
+                    )?;
+                    Result::<_, Error>::Ok((categories, queries))
+                }
+            })
+            .await?;
+            let mut groups = categories
+                .iter()
+                .map(|_| Vec::new())
+                .collect::<Vec<Vec<EncScanEntry>>>();
+            let mut active = acquire_session(&mut session).await?;
+            let query_timeout = active.query_timeout();
+            for (query, params) in queries {
+                let rows = run_query(
+                    query_timeout,
+                    sqlx::query_with(&*query, params).fetch_all(active.connection_mut()),
+                )
+                .await;
+                for row in active.check_timeout(rows)? {
+                    let grp: i32 = row.try_get(0)?;
+                    let group = groups
+                        .get_mut(grp as usize)
+                        .ok_or_else(|| err_msg!(Unexpected, "Invalid scan group"))?;
+                    group.push(EncScanEntry {
+                        id: row.try_get(1)?,
+                        name: row.try_get(2)?,
+                        value: row.try_get(3)?,
+                        value_type: row.try_get(5)?,
+                        tags: row
+                            .try_get::<Option<String>, _>(4)?
+                            .map(String::into_bytes)
+                            .unwrap_or_default(),
+                        expiry: row.try_get(6)?,
+                    });
+                }
+            }
+            drop(active);
+            let settings = self.settings.clone();
+            unblock(move || {
+                categories
+                    .into_iter()
+                    .zip(groups)
+                    .map(|(category, enc_rows)| {
+                        let entries =
+                            decrypt_scan_batch(category.clone(), enc_rows, &key, &settings)?;
+                        Result::<_, Error>::Ok((category, entries))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, Error>>()
+            })
+            .await
+        })
+    }
+
+    fn session(&self, profile: Option<String>, transaction: bool) -> Result<Self::Session, Error> {
+        Ok(DbSession::new(
+            self.conn_pool.clone(),
+            self.key_cache.clone(),
+            profile.unwrap_or_else(|| self.default_profile.clone()),
+            self.settings.clone(),
+            transaction,
+        ))
+    }
+
+    fn session_with_external_key(
+        &self,
+        profile: Option<String>,
+        store_key: StoreKey,
+        transaction: bool,
+    ) -> Result<Self::Session, Error> {
+        // the key cache is private to the session, so that the external key is
+        // not used by other sessions and the profile keys it unwraps are not shared
+        Ok(DbSession::new(
+            self.conn_pool.clone(),
+            Arc::new(KeyCache::new(store_key)),
+            profile.unwrap_or_else(|| self.default_profile.clone()),
+            self.settings.clone(),
+            transaction,
+        ))
+    }
+
+    fn purge_expired(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let removed = sqlx::query(PURGE_EXPIRED_QUERY)
+                .execute(&mut conn)
+                .await?
+                .rows_affected();
+            if sqlx::query_scalar::<_, i64>(ACCESS_LOG_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 1
+            {
+                let retention = chrono::Duration::from_std(self.settings.access_log_retention())
+                    .map_err(err_map!(Input, "Invalid access log retention"))?;
+                sqlx::query(ACCESS_LOG_PURGE_QUERY)
+                    .bind(chrono::Utc::now() - retention)
+                    .execute(&mut conn)
+                    .await?;
+            }
+            Ok(removed as i64)
+        })
+    }
+
+    fn verify_integrity(&self) -> BoxFuture<'_, Result<IntegrityReport, Error>> {
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let missing_keys = sqlx::query_as(MISSING_KEYS_QUERY)
+                .fetch_all(&mut conn)
+                .await?;
+            let orphaned_items = sqlx::query_as(ORPHANED_ITEMS_QUERY)
+                .fetch_all(&mut conn)
+                .await?;
+            let orphaned_tags = sqlx::query_scalar(ORPHANED_TAGS_QUERY)
+                .fetch_one(&mut conn)
+                .await?;
+            Ok(IntegrityReport {
+                missing_keys,
+                orphaned_items,
+                orphaned_tags,
+            })
+        })
+    }
+
+    fn audit_decryption(
+        &self,
+        profile: Option<String>,
+        options: DecryptionAuditOptions,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<DecryptionAudit, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let enc_categories = if let Some(categories) = options.categories.clone() {
+                let key = key.clone();
+                Some(
+                    unblock(move || {
+                        categories
+                            .into_iter()
+                            .map(|category| {
+                                key.encrypt_entry_category(ProfileKey::prepare_input(
+                                    category.as_bytes(),
+                                ))
+                            })
+                            .collect::<Result<BTreeSet<_>, Error>>()
+                    })
+                    .await?,
+                )
+            } else {
+                None
+            };
+            let mut active = acquire_session(&mut session).await?;
+            let rows = sqlx::query(AUDIT_QUERY.as_str())
+                .bind(profile_id)
+                .bind(options.resume_after.unwrap_or(0))
+                .bind(limit)
+                .fetch_all(active.connection_mut())
+                .await?;
+            drop(active);
+            let scanned = rows.len() as i64;
+            let mut last_id = options.resume_after;
+            let mut enc_rows = vec![];
+            for row in rows {
+                let id: i64 = row.try_get(0)?;
+                last_id = Some(id);
+                let category: Vec<u8> = row.try_get(1)?;
+                if !options.sample(id)
+                    || !enc_categories
+                        .as_ref()
+                        .map_or(true, |cats| cats.contains(&category))
+                {
+                    continue;
+                }
+                enc_rows.push(EncAuditEntry {
+                    id,
+                    category,
+                    name: row.try_get(2)?,
+                    value: row.try_get(3)?,
+                    value_type: row.try_get(4)?,
+                    tags: row
+                        .try_get::<Option<String>, _>(5)?
+                        .map(String::into_bytes)
+                        .unwrap_or_default(),
+                    kind: row.try_get(6)?,
+                });
+            }
+            let checked = enc_rows.len() as i64;
+            let failures = unblock(move || {
+                enc_rows
+                    .into_iter()
+                    .filter_map(|enc_entry| {
+                        let id = enc_entry.id;
+                        audit_entry(&key, enc_entry)
+                            .err()
+                            .map(|err| DecryptionFailure::new(id, err))
+                    })
+                    .collect()
+            })
+            .await;
+            Ok(DecryptionAudit {
+                scanned,
+                checked,
+                failures,
+                last_id,
+                complete: scanned < limit,
+            })
+        })
+    }
+
+    fn cleanup_orphans(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let removed = sqlx::query(ORPHANED_TAGS_DELETE_QUERY)
+                .execute(&mut conn)
+                .await?
+                .rows_affected();
+            Ok(removed as i64)
+        })
+    }
+
+    fn diagnostics(&self) -> BoxFuture<'_, Result<StoreDiagnostics, Error>> {
+        Box::pin(async move {
+            Ok(StoreDiagnostics {
+                active_scans: self.settings.scans.active(),
+                max_scans: self.settings.scans.max_active(),
+                pool_size: self.conn_pool.size(),
+                pool_idle: self.conn_pool.num_idle(),
+                read_pool_size: None,
+                cached_profiles: self.key_cache.profile_count().await,
+            })
+        })
+    }
+
+    fn info(&self, refresh: bool) -> BoxFuture<'_, Result<StoreInfo, Error>> {
+        Box::pin(async move {
+            if !refresh {
+                if let Some(info) = self.settings.info.get() {
+                    return Ok(info);
+                }
+            }
+            let mut conn = self.conn_pool.acquire().await?;
+            let mut schema_version: Option<String> = None;
+            let mut default_profile: Option<String> = None;
+            let mut store_key_ref: Option<String> = None;
+            let mut store_id: Option<String> = None;
+            let config = sqlx::query(INFO_CONFIG_QUERY).fetch_all(&mut conn).await?;
+            for row in config {
+                match row.try_get(0)? {
+                    "default_profile" => {
+                        default_profile.replace(row.try_get(1)?);
+                    }
+                    "key" => {
+                        store_key_ref.replace(row.try_get(1)?);
+                    }
+                    "store_id" => {
+                        store_id.replace(row.try_get(1)?);
+                    }
+                    "version" => {
+                        schema_version.replace(row.try_get(1)?);
+                    }
+                    _ => (),
+                }
+            }
+            let store_key_ref =
+                store_key_ref.ok_or_else(|| err_msg!(Unsupported, "Store key not found"))?;
+            let profile_count = sqlx::query_scalar("SELECT COUNT(*) FROM profiles")
+                .fetch_one(&mut conn)
+                .await?;
+            let info = StoreInfo {
+                backend: "postgres".to_string(),
+                schema_version: schema_version
+                    .ok_or_else(|| err_msg!(Unsupported, "Store version not found"))?,
+                // only the method is retained, not the derivation parameters
+                store_key_method: StoreKeyReference::parse_uri(&store_key_ref)?.method(),
+                encryption: PROFILE_KEY_ALG,
+                default_profile: default_profile
+                    .ok_or_else(|| err_msg!(Unsupported, "Default store profile not found"))?,
+                profile_count,
+                store_id: store_id.ok_or_else(|| err_msg!(Unsupported, "Store ID not found"))?,
+                journal_mode: None,
+            };
+            self.settings.info.set(info.clone());
+            Ok(info)
+        })
+    }
+
+    fn ping(&self, deadline: Duration) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(run_probe(deadline, async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            sqlx::query_scalar::<_, i32>("SELECT 1")
+                .fetch_one(&mut conn)
+                .await?;
+            Ok(())
+        }))
+    }
+
+    fn health(&self, deadline: Duration) -> BoxFuture<'_, HealthStatus> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = run_probe(deadline, async {
+                let mut conn = self.conn_pool.acquire().await?;
+                let version: Option<String> = sqlx::query_scalar(HEALTH_VERSION_QUERY)
+                    .fetch_optional(&mut conn)
+                    .await?;
+                if version.as_deref().and_then(|v| v.parse().ok()) != Some(SCHEMA_VERSION) {
+                    return Err(err_msg!(
+                        Unsupported,
+                        "Unexpected store version: {}",
+                        version.as_deref().unwrap_or("none")
+                    ));
+                }
+                let row = sqlx::query(HEALTH_PROFILE_QUERY)
+                    .bind(&self.default_profile)
+                    .fetch_optional(&mut conn)
+                    .await?
+                    .ok_or_else(|| err_msg!(NotFound, "Default store profile not found"))?;
+                drop(conn);
+                // a profile protected by a passphrase is not unwrapped by the store key
+                if row.try_get::<Option<String>, _>(2)?.is_none() {
+                    self.key_cache
+                        .load_key(row.try_get(0)?, row.try_get(1)?)
+                        .await?;
+                }
+                Ok(())
+            })
+            .await;
+            health_status(result, start.elapsed(), deadline, &self.settings.scans)
+        })
+    }
+
+    fn checkpoint(&self) -> BoxFuture<'_, Result<(), Error>> {
+        // the server is responsible for checkpointing its write-ahead log
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn vacuum(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            // the statement cannot be prepared or run within a transaction
+            self.conn_pool.execute("VACUUM ANALYZE").await?;
+            Ok(())
+        })
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.log_access(AccessEvent::Close, None, true).await;
+            self.conn_pool.close().await;
+            Ok(())
+        })
+    }
+}
+
+impl Debug for PostgresStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresStore")
+            .field("default_profile", &self.default_profile)
+            .field("host", &self.host)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl QueryBackend for DbSession<Postgres> {
+    fn count<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<i64, Error>> {
+        let category = ProfileKey::prepare_input(category.as_bytes());
+
+        Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let mut params = QueryParams::with_capacity(3);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, tag_filter) = unblock({
+                let params_len = params.len() + 1; // plus category
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        encode_tag_filter::<PostgresStore>(tag_filter, &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            params.push(enc_category);
+            let query = expiry_query::<PostgresStore>(
+                extend_query::<PostgresStore>(
+                    COUNT_QUERY.as_str(),
+                    &mut params,
+                    tag_filter,
+                    self.query_hint(),
+                    None,
+                    None,
+                )?,
+                self.expiry_filter(),
+            );
+            let mut active = acquire_session(&mut *self).await?;
+            let query_timeout = active.query_timeout();
+            let count = run_query(
+                query_timeout,
+                sqlx::query_scalar_with(&*query, params).fetch_one(active.connection_mut()),
+            )
+            .await;
+            active.check_timeout(count)
+        })
+    }
+
+    fn fetch(
+        &mut self,
+        kind: EntryKind,
+        category: &[u8],
+        name: &[u8],
+        for_update: bool,
+    ) -> BoxFuture<'_, Result<Option<Entry>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
+
+        Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            let (enc_category, enc_name) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                    ))
+                }
+            })
+            .await?;
+            let expiry = self.expiry_filter();
+            let mut active = acquire_session(&mut *self).await?;
+            let query = expiry_query::<PostgresStore>(
+                if for_update && active.is_transaction() {
+                    FETCH_QUERY_UPDATE.as_str()
+                } else {
+                    FETCH_QUERY.as_str()
+                }
+                .into(),
+                expiry,
+            );
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query(&*query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_optional(active.connection_mut()),
+            )
+            .await;
+            if let Some(row) = active.check_timeout(row)? {
+                Ok(Some(
+                    decrypt_fetch_row(row, key, settings, category, name).await?,
+                ))
+            } else {
+                Ok(None)
+            }
         })
     }
 
-    fn session(&self, profile: Option<String>, transaction: bool) -> Result<Self::Session, Error> {
-        Ok(DbSession::new(
-            self.conn_pool.clone(),
-            self.key_cache.clone(),
-            profile.unwrap_or_else(|| self.default_profile.clone()),
-            transaction,
-        ))
-    }
-
-    fn close(&self) -> BoxFuture<'_, Result<(), Error>> {
+    fn fetch_conditional<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        etag: Option<&'q [u8]>,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<ConditionalFetch>, Error>> {
         Box::pin(async move {
-            self.conn_pool.close().await;
-            Ok(())
+            // entity tags are not stored, and are derived from the decrypted value
+            let entry = match self.fetch(kind, category, name, for_update).await? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            let (_, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let category = category.to_vec();
+            let name = name.to_vec();
+            let (entry, current) = unblock(move || {
+                let current = key.entry_value_hash(&category, &name, entry.value.as_ref())?;
+                Result::<_, Error>::Ok((entry, current))
+            })
+            .await?;
+            if etag == Some(current.as_slice()) {
+                Ok(Some(ConditionalFetch::NotModified))
+            } else {
+                Ok(Some(ConditionalFetch::Modified {
+                    entry,
+                    etag: current,
+                }))
+            }
         })
     }
-}
-
-impl Debug for PostgresStore {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PostgresStore")
-            .field("default_profile", &self.default_profile)
-            .field("host", &self.host)
-            .field("name", &self.name)
-            .finish()
-    }
-}
 
-impl QueryBackend for DbSession<Postgres> {
-    fn count<'q>(
+    fn fetch_filtered<'q>(
         &'q mut self,
         kind: EntryKind,
-        category: &'q str,
-        tag_filter: Option<TagFilter>,
-    ) -> BoxFuture<'q, Result<i64, Error>> {
-        let category = ProfileKey::prepare_input(category.as_bytes());
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_filter: TagFilter,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<FilteredFetch>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
 
         Box::pin(async move {
             let (profile_id, key) = acquire_key(&mut *self).await?;
-            let mut params = QueryParams::new();
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            let mut params = QueryParams::with_capacity(4);
             params.push(profile_id);
             params.push(kind as i16);
-            let (enc_category, tag_filter) = unblock({
-                let params_len = params.len() + 1; // plus category
+            let (enc_category, enc_name, tag_filter) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
+                let params_len = params.len() + 2; // plus category and name
                 move || {
                     Result::<_, Error>::Ok((
                         key.encrypt_entry_category(category)?,
-                        encode_tag_filter::<PostgresStore>(tag_filter, &key, params_len)?,
+                        key.encrypt_entry_name(name)?,
+                        encode_tag_filter::<PostgresStore>(Some(tag_filter), &key, params_len)?,
                     ))
                 }
             })
             .await?;
-            params.push(enc_category);
-            let query =
-                extend_query::<PostgresStore>(COUNT_QUERY, &mut params, tag_filter, None, None)?;
+            params.push(enc_category.clone());
+            params.push(enc_name.clone());
+            let expiry = self.expiry_filter();
+            let mut query = expiry_query::<PostgresStore>(
+                extend_query::<PostgresStore>(
+                    FETCH_QUERY.as_str(),
+                    &mut params,
+                    tag_filter,
+                    self.query_hint(),
+                    None,
+                    None,
+                )?,
+                expiry,
+            );
             let mut active = acquire_session(&mut *self).await?;
-            let count = sqlx::query_scalar_with(query.as_str(), params)
-                .fetch_one(active.connection_mut())
-                .await?;
-            Ok(count)
+            if for_update && active.is_transaction() {
+                query.to_mut().push_str(" FOR UPDATE");
+            }
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query_with(&*query, params).fetch_optional(active.connection_mut()),
+            )
+            .await;
+            if let Some(row) = active.check_timeout(row)? {
+                drop(active);
+                let entry = decrypt_fetch_row(row, key, settings, category, name).await?;
+                return Ok(Some(FilteredFetch::Matched(entry)));
+            }
+            // distinguish a record which does not match from a missing record
+            let exists_query =
+                expiry_query::<PostgresStore>(FETCH_EXISTS_QUERY.as_str().into(), expiry);
+            let count = run_query(
+                query_timeout,
+                sqlx::query_scalar::<_, i64>(&*exists_query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_one(active.connection_mut()),
+            )
+            .await;
+            let count = active.check_timeout(count)?;
+            Ok(if count > 0 {
+                Some(FilteredFetch::Filtered)
+            } else {
+                None
+            })
         })
     }
 
-    fn fetch(
-        &mut self,
+    fn fetch_with_tags<'q>(
+        &'q mut self,
         kind: EntryKind,
-        category: &str,
-        name: &str,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_mode: TagRetrieval,
         for_update: bool,
-    ) -> BoxFuture<'_, Result<Option<Entry>, Error>> {
-        let category = category.to_string();
-        let name = name.to_string();
+    ) -> BoxFuture<'q, Result<Option<TaggedEntry>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
 
         Box::pin(async move {
             let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
             let (enc_category, enc_name) = unblock({
                 let key = key.clone();
-                let category = ProfileKey::prepare_input(category.as_bytes());
-                let name = ProfileKey::prepare_input(name.as_bytes());
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
                 move || {
                     Result::<_, Error>::Ok((
                         key.encrypt_entry_category(category)?,
@@ -313,41 +1884,51 @@ impl QueryBackend for DbSession<Postgres> {
                 }
             })
             .await?;
+            let mut query = expiry_query::<PostgresStore>(
+                fetch_tags_query(tag_mode).into(),
+                self.expiry_filter(),
+            );
             let mut active = acquire_session(&mut *self).await?;
-            if let Some(row) = sqlx::query(if for_update && active.is_transaction() {
-                FETCH_QUERY_UPDATE
-            } else {
-                FETCH_QUERY
-            })
-            .bind(profile_id)
-            .bind(kind as i16)
-            .bind(enc_category)
-            .bind(enc_name)
-            .fetch_optional(active.connection_mut())
-            .await?
-            {
-                let value = row.try_get(1)?;
-                let tags = row.try_get::<Option<String>, _>(2)?.map(String::into_bytes);
-                let (category, name, value, tags) = unblock(move || {
-                    let value = key.decrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
-                    let tags = if let Some(enc_tags) = tags {
-                        key.decrypt_entry_tags(
-                            decode_tags(enc_tags)
-                                .map_err(|_| err_msg!(Unexpected, "Error decoding tags"))?,
-                        )?
-                    } else {
-                        Vec::new()
-                    };
-                    Result::<_, Error>::Ok((category, name, value, tags))
-                })
-                .await?;
-                Ok(Some(Entry::new(category, name, value, tags)))
+            if for_update && active.is_transaction() {
+                query.to_mut().push_str(" FOR UPDATE");
+            }
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query(&*query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_optional(active.connection_mut()),
+            )
+            .await;
+            if let Some(row) = active.check_timeout(row)? {
+                drop(active);
+                Ok(Some(
+                    decrypt_tagged_row(row, key, settings, category, name, tag_mode).await?,
+                ))
             } else {
                 Ok(None)
             }
         })
     }
 
+    fn fetch_history<'q>(
+        &'q mut self,
+        _kind: EntryKind,
+        _category: &'q str,
+        _name: &'q str,
+        _limit: Option<i64>,
+    ) -> BoxFuture<'q, Result<Vec<EntryVersion>, Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Value history is not supported by the postgres backend"
+            ))
+        })
+    }
+
     fn fetch_all<'q>(
         &'q mut self,
         kind: EntryKind,
@@ -359,16 +1940,20 @@ impl QueryBackend for DbSession<Postgres> {
         let category = category.to_string();
         Box::pin(async move {
             let for_update = for_update && self.is_transaction();
+            let settings = self.settings();
             let mut active = self.borrow_mut();
             let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
             let scan = perform_scan(
                 active,
                 profile_id,
                 key.clone(),
                 kind,
                 category.clone(),
+                None,
                 tag_filter,
                 None,
+                None,
                 limit,
                 for_update,
             );
@@ -381,7 +1966,7 @@ impl QueryBackend for DbSession<Postgres> {
                     break;
                 }
             }
-            unblock(move || decrypt_scan_batch(category, enc_rows, &key)).await
+            unblock(move || decrypt_scan_batch(category, enc_rows, &key, &settings)).await
         })
     }
 
@@ -395,7 +1980,8 @@ impl QueryBackend for DbSession<Postgres> {
 
         Box::pin(async move {
             let (profile_id, key) = acquire_key(&mut *self).await?;
-            let mut params = QueryParams::new();
+            let key = key.for_kind(kind);
+            let mut params = QueryParams::with_capacity(3);
             params.push(profile_id);
             params.push(kind as i16);
             let (enc_category, tag_filter) = unblock({
@@ -410,91 +1996,271 @@ impl QueryBackend for DbSession<Postgres> {
             .await?;
             params.push(enc_category);
             let query = extend_query::<PostgresStore>(
-                DELETE_ALL_QUERY,
+                DELETE_ALL_QUERY.as_str(),
                 &mut params,
                 tag_filter,
+                self.query_hint(),
                 None,
                 None,
             )?;
 
             let mut active = acquire_session(&mut *self).await?;
-            let removed = sqlx::query_with(query.as_str(), params)
-                .execute(active.connection_mut())
-                .await?
-                .rows_affected();
+            let query_timeout = active.query_timeout();
+            let removed = run_query(
+                query_timeout,
+                sqlx::query_with(&*query, params).execute(active.connection_mut()),
+            )
+            .await;
+            let removed = active.check_timeout(removed)?.rows_affected();
             Ok(removed as i64)
         })
     }
 
+    fn take<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: Option<&'q str>,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<Option<Entry>, Error>> {
+        let category = category.to_string();
+        let name = name.map(|name| ProfileKey::prepare_input(name.as_bytes()));
+
+        Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            let mut params = QueryParams::with_capacity(4);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, enc_name, tag_filter) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(category.as_bytes());
+                let params_len = params.len() + 1; // plus category
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        name.map(|name| key.encrypt_entry_name(name)).transpose()?,
+                        encode_tag_filter::<PostgresStore>(tag_filter, &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            params.push(enc_category);
+            let mut query = extend_query::<PostgresStore>(
+                SCAN_QUERY.as_str(),
+                &mut params,
+                tag_filter,
+                self.query_hint(),
+                None,
+                None,
+            )?;
+            let skip_locked = enc_name.is_none();
+            if let Some(enc_name) = enc_name {
+                query
+                    .to_mut()
+                    .push_str(&replace_arg_placeholders::<PostgresStore>(
+                        " AND name = $$",
+                        (params.len() as i64) + 1,
+                    ));
+                params.push(enc_name);
+            }
+            if skip_locked {
+                // concurrent callers take the next available record
+                query
+                    .to_mut()
+                    .push_str(" ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED");
+            } else {
+                query.to_mut().push_str(" ORDER BY id LIMIT 1 FOR UPDATE");
+            }
+
+            let mut active = acquire_session(&mut *self).await?;
+            let mut txn = active.as_transaction().await?;
+            let row = if let Some(row) = sqlx::query_with(&*query, params)
+                .fetch_optional(txn.connection_mut())
+                .await?
+            {
+                row
+            } else {
+                txn.commit().await?;
+                return Ok(None);
+            };
+            let item_id: i64 = row.try_get(0)?;
+            let enc_row = EncScanEntry {
+                id: item_id,
+                name: row.try_get(1)?,
+                value: row.try_get(2)?,
+                value_type: row.try_get(4)?,
+                tags: row
+                    .try_get::<Option<String>, _>(3)?
+                    .map(String::into_bytes)
+                    .unwrap_or_default(),
+                expiry: row.try_get(5)?,
+            };
+            let entry =
+                unblock(move || decrypt_scan_batch(category, vec![enc_row], &key, &settings))
+                    .await?
+                    .pop();
+            sqlx::query(DELETE_ID_QUERY)
+                .bind(item_id)
+                .execute(txn.connection_mut())
+                .await?;
+            txn.commit().await?;
+            Ok(entry)
+        })
+    }
+
     fn update<'q>(
         &'q mut self,
         kind: EntryKind,
         operation: EntryOperation,
-        category: &'q str,
-        name: &'q str,
+        category: &'q [u8],
+        name: &'q [u8],
         value: Option<&'q [u8]>,
+        value_type: Option<&'q str>,
         tags: Option<&'q [EntryTag]>,
-        expiry_ms: Option<i64>,
+        expiry: ExpiryUpdate,
     ) -> BoxFuture<'q, Result<(), Error>> {
-        let category = ProfileKey::prepare_input(category.as_bytes());
-        let name = ProfileKey::prepare_input(name.as_bytes());
+        let category = ProfileKey::prepare_input(category);
+        let name = ProfileKey::prepare_input(name);
 
         match operation {
             EntryOperation::Insert => {
                 let value = ProfileKey::prepare_input(value.unwrap());
+                let value_type = value_type.map(|vt| ProfileKey::prepare_input(vt.as_bytes()));
                 let tags = tags.map(prepare_tags);
                 Box::pin(async move {
+                    check_entry_key(category.as_ref(), name.as_ref())?;
                     let (_, key) = acquire_key(&mut *self).await?;
-                    let (enc_category, enc_name, enc_value, enc_tags) = unblock(move || {
-                        let enc_value =
-                            key.encrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
-                        Result::<_, Error>::Ok((
-                            key.encrypt_entry_category(category)?,
-                            key.encrypt_entry_name(name)?,
-                            enc_value,
-                            tags.transpose()?
-                                .map(|t| key.encrypt_entry_tags(t))
-                                .transpose()?,
-                        ))
-                    })
-                    .await?;
+                    let settings = self.settings();
+                    let key = key.for_kind(kind);
+                    let envelope = settings.value_envelope();
+                    let index_input = (key.clone(), category.clone(), name.clone());
+                    let (enc_category, enc_name, enc_value, enc_value_type, enc_tags) =
+                        unblock(move || {
+                            let enc_value = key.encrypt_entry_value(
+                                category.as_ref(),
+                                name.as_ref(),
+                                value,
+                                envelope,
+                            )?;
+                            let enc_value_type = encrypt_value_type(
+                                &key,
+                                category.as_ref(),
+                                name.as_ref(),
+                                value_type,
+                                envelope,
+                            )?;
+                            Result::<_, Error>::Ok((
+                                key.encrypt_entry_category(category)?,
+                                key.encrypt_entry_name(name)?,
+                                enc_value,
+                                enc_value_type,
+                                tags.transpose()?
+                                    .map(|t| key.encrypt_entry_tags(t))
+                                    .transpose()?,
+                            ))
+                        })
+                        .await?;
+                    let expiry = match expiry {
+                        ExpiryUpdate::Set(expiry_ms) => Some(expiry_timestamp(expiry_ms)?),
+                        ExpiryUpdate::Clear | ExpiryUpdate::Keep => None,
+                    };
                     let mut active = acquire_session(&mut *self).await?;
                     let mut txn = active.as_transaction().await?;
+                    let (name_index, category_index) =
+                        load_index_settings(&mut txn, &settings).await?;
+                    let (key, category, name) = index_input;
+                    let (enc_prefixes, enc_category_prefixes) = encode_index_prefixes(
+                        &key,
+                        category.as_ref(),
+                        name.as_ref(),
+                        name_index,
+                        category_index,
+                    )?;
                     perform_insert(
                         &mut txn,
                         kind,
                         &enc_category,
                         &enc_name,
                         &enc_value,
+                        enc_value_type.as_deref(),
                         enc_tags,
-                        expiry_ms,
+                        enc_prefixes,
+                        enc_category_prefixes,
+                        expiry,
                     )
                     .await?;
-                    txn.commit().await?;
+                    txn.commit_operation("insert").await?;
                     Ok(())
                 })
             }
             EntryOperation::Replace => {
                 let value = ProfileKey::prepare_input(value.unwrap());
+                let value_type = value_type.map(|vt| ProfileKey::prepare_input(vt.as_bytes()));
                 let tags = tags.map(prepare_tags);
                 Box::pin(async move {
+                    check_entry_key(category.as_ref(), name.as_ref())?;
                     let (_, key) = acquire_key(&mut *self).await?;
-                    let (enc_category, enc_name, enc_value, enc_tags) = unblock(move || {
-                        let enc_value =
-                            key.encrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
-                        Result::<_, Error>::Ok((
-                            key.encrypt_entry_category(category)?,
-                            key.encrypt_entry_name(name)?,
-                            enc_value,
-                            tags.transpose()?
-                                .map(|t| key.encrypt_entry_tags(t))
-                                .transpose()?,
-                        ))
-                    })
-                    .await?;
+                    let settings = self.settings();
+                    let key = key.for_kind(kind);
+                    let envelope = settings.value_envelope();
+                    let index_input = (key.clone(), category.clone(), name.clone());
+                    let (enc_category, enc_name, enc_value, enc_value_type, enc_tags) =
+                        unblock(move || {
+                            let enc_value = key.encrypt_entry_value(
+                                category.as_ref(),
+                                name.as_ref(),
+                                value,
+                                envelope,
+                            )?;
+                            let enc_value_type = encrypt_value_type(
+                                &key,
+                                category.as_ref(),
+                                name.as_ref(),
+                                value_type,
+                                envelope,
+                            )?;
+                            Result::<_, Error>::Ok((
+                                key.encrypt_entry_category(category)?,
+                                key.encrypt_entry_name(name)?,
+                                enc_value,
+                                enc_value_type,
+                                tags.transpose()?
+                                    .map(|t| key.encrypt_entry_tags(t))
+                                    .transpose()?,
+                            ))
+                        })
+                        .await?;
 
                     let mut active = acquire_session(&mut *self).await?;
                     let mut txn = active.as_transaction().await?;
+                    let (name_index, category_index) =
+                        load_index_settings(&mut txn, &settings).await?;
+                    let (key, category, name) = index_input;
+                    let (enc_prefixes, enc_category_prefixes) = encode_index_prefixes(
+                        &key,
+                        category.as_ref(),
+                        name.as_ref(),
+                        name_index,
+                        category_index,
+                    )?;
+                    let expiry = match expiry {
+                        ExpiryUpdate::Set(expiry_ms) => Some(expiry_timestamp(expiry_ms)?),
+                        ExpiryUpdate::Clear => None,
+                        ExpiryUpdate::Keep => {
+                            sqlx::query_scalar::<_, Option<i64>>(EXPIRY_FETCH_QUERY)
+                                .bind(txn.profile_id)
+                                .bind(kind as i16)
+                                .bind(enc_category.as_slice())
+                                .bind(enc_name.as_slice())
+                                .fetch_optional(txn.connection_mut())
+                                .await?
+                                .flatten()
+                                .map(expiry_timestamp)
+                                .transpose()?
+                        }
+                    };
                     perform_remove(&mut txn, kind, &enc_category, &enc_name, false).await?;
                     perform_insert(
                         &mut txn,
@@ -502,17 +2268,21 @@ impl QueryBackend for DbSession<Postgres> {
                         &enc_category,
                         &enc_name,
                         &enc_value,
+                        enc_value_type.as_deref(),
                         enc_tags,
-                        expiry_ms,
+                        enc_prefixes,
+                        enc_category_prefixes,
+                        expiry,
                     )
                     .await?;
-                    txn.commit().await?;
+                    txn.commit_operation("replace").await?;
                     Ok(())
                 })
             }
 
             EntryOperation::Remove => Box::pin(async move {
                 let (_, key) = acquire_key(&mut *self).await?;
+                let key = key.for_kind(kind);
                 let (enc_category, enc_name) = unblock(move || {
                     Result::<_, Error>::Ok((
                         key.encrypt_entry_category(category)?,
@@ -526,20 +2296,213 @@ impl QueryBackend for DbSession<Postgres> {
         }
     }
 
+    fn rename<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        new_category: &'q [u8],
+        new_name: &'q [u8],
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        let category = ProfileKey::prepare_input(category);
+        let name = ProfileKey::prepare_input(name);
+        let new_category = ProfileKey::prepare_input(new_category);
+        let new_name = ProfileKey::prepare_input(new_name);
+
+        Box::pin(async move {
+            check_entry_key(new_category.as_ref(), new_name.as_ref())?;
+            let (_, key) = acquire_key(&mut *self).await?;
+            let settings = self.settings();
+            let key = key.for_kind(kind);
+            let envelope = settings.value_envelope();
+            let (enc_category, enc_name, enc_new_category, enc_new_name) = unblock({
+                let key = key.clone();
+                let (category, name) = (category.clone(), name.clone());
+                let (new_category, new_name) = (new_category.clone(), new_name.clone());
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                        key.encrypt_entry_category(new_category)?,
+                        key.encrypt_entry_name(new_name)?,
+                    ))
+                }
+            })
+            .await?;
+            let mut active = acquire_session(&mut *self).await?;
+            let mut txn = active.as_transaction().await?;
+            let (name_index, category_index) = load_index_settings(&mut txn, &settings).await?;
+            let row = sqlx::query(RENAME_FETCH_QUERY)
+                .bind(txn.profile_id)
+                .bind(kind as i16)
+                .bind(enc_category.as_slice())
+                .bind(enc_name.as_slice())
+                .fetch_optional(txn.connection_mut())
+                .await?
+                .ok_or_else(|| err_msg!(NotFound, "Entry not found"))?;
+            let item_id: i64 = row.try_get(0)?;
+            let enc_value: Vec<u8> = row.try_get(1)?;
+            let enc_value_type: Option<Vec<u8>> = row.try_get(2)?;
+            // expired records are counted, as they still occupy the unique key
+            let existing: i64 = sqlx::query_scalar(RENAME_EXISTS_QUERY)
+                .bind(txn.profile_id)
+                .bind(kind as i16)
+                .bind(enc_new_category.as_slice())
+                .bind(enc_new_name.as_slice())
+                .fetch_one(txn.connection_mut())
+                .await?;
+            if existing != 0 {
+                return Err(err_msg!(Duplicate, "Duplicate row"));
+            }
+            // the value and value type are bound to the category and name
+            let (enc_value, enc_value_type, enc_prefixes, enc_category_prefixes) =
+                unblock(move || {
+                    let value =
+                        key.decrypt_entry_value(category.as_ref(), name.as_ref(), enc_value)?;
+                    let value_type =
+                        decrypt_value_type(&key, category.as_ref(), name.as_ref(), enc_value_type)?
+                            .map(|vt| ProfileKey::prepare_input(vt.as_bytes()));
+                    let (enc_prefixes, enc_category_prefixes) = encode_index_prefixes(
+                        &key,
+                        new_category.as_ref(),
+                        new_name.as_ref(),
+                        name_index,
+                        category_index,
+                    )?;
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_value(
+                            new_category.as_ref(),
+                            new_name.as_ref(),
+                            value,
+                            envelope,
+                        )?,
+                        encrypt_value_type(
+                            &key,
+                            new_category.as_ref(),
+                            new_name.as_ref(),
+                            value_type,
+                            envelope,
+                        )?,
+                        enc_prefixes,
+                        enc_category_prefixes,
+                    ))
+                })
+                .await?;
+            sqlx::query(RENAME_UPDATE_QUERY)
+                .bind(enc_new_category.as_slice())
+                .bind(enc_new_name.as_slice())
+                .bind(enc_value)
+                .bind(enc_value_type)
+                .bind(item_id)
+                .execute(txn.connection_mut())
+                .await?;
+            if name_index {
+                sqlx::query(NAME_INDEX_DELETE_QUERY)
+                    .bind(item_id)
+                    .execute(txn.connection_mut())
+                    .await?;
+                for prefix in enc_prefixes {
+                    sqlx::query(NAME_INDEX_INSERT_QUERY)
+                        .bind(item_id)
+                        .bind(prefix)
+                        .execute(txn.connection_mut())
+                        .await?;
+                }
+            }
+            if category_index {
+                sqlx::query(CATEGORY_INDEX_DELETE_QUERY)
+                    .bind(item_id)
+                    .execute(txn.connection_mut())
+                    .await?;
+                for prefix in enc_category_prefixes {
+                    sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+                        .bind(item_id)
+                        .bind(prefix)
+                        .execute(txn.connection_mut())
+                        .await?;
+                }
+            }
+            txn.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn applied_batch<'q>(
+        &'q mut self,
+        _idempotency_key: &'q str,
+    ) -> BoxFuture<'q, Result<Option<i64>, Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Idempotency keys are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn record_batch<'q>(
+        &'q mut self,
+        _idempotency_key: &'q str,
+        _updates: i64,
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        Box::pin(async move {
+            Err(err_msg!(
+                Unsupported,
+                "Idempotency keys are not supported by the postgres backend"
+            ))
+        })
+    }
+
+    fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        DbSession::set_query_timeout(self, timeout)
+    }
+
+    fn set_expiry_filter(&mut self, expiry: ExpiryFilter) {
+        DbSession::set_expiry_filter(self, expiry)
+    }
+
+    fn set_query_hint(&mut self, hint: Option<QueryHint>) {
+        DbSession::set_query_hint(self, hint)
+    }
+
+    fn set_profile(&mut self, profile: String) {
+        DbSession::set_profile(self, profile)
+    }
+
+    fn set_commit_operation(&mut self, operation: &'static str) {
+        DbSession::set_commit_operation(self, operation)
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        self.settings().name_normalization()
+    }
+
     fn close(self, commit: bool) -> BoxFuture<'static, Result<(), Error>> {
         Box::pin(DbSession::close(self, commit))
     }
 }
 
-impl ExtDatabase for Postgres {}
+impl ExtDatabase for Postgres {
+    // inserted row ids are only available from a RETURNING clause
+    fn exec_result(result: PgQueryResult) -> ExecResult {
+        ExecResult::new(result.rows_affected(), None)
+    }
+}
 
 impl QueryPrepare for PostgresStore {
     type DB = Postgres;
 
+    const MAX_PARAMS: usize = 65535;
+
+    const MAX_COMPOUND_SELECT: usize = usize::MAX;
+
     fn placeholder(index: i64) -> String {
         format!("${}", index)
     }
 
+    fn current_timestamp() -> &'static str {
+        "CURRENT_TIMESTAMP"
+    }
+
     fn limit_query<'q>(
         mut query: String,
         args: &mut QueryParams<'q, Self::DB>,
@@ -560,6 +2523,26 @@ impl QueryPrepare for PostgresStore {
     }
 }
 
+/// Append an entry to the access log, which must have been created by
+/// `enable_access_log`
+pub(crate) async fn log_access(
+    conn: &mut PgConnection,
+    client: Option<&str>,
+    event: AccessEvent,
+    profile: Option<&str>,
+    success: bool,
+) -> Result<(), Error> {
+    sqlx::query(ACCESS_LOG_INSERT_QUERY)
+        .bind(chrono::Utc::now())
+        .bind(event.as_str())
+        .bind(client)
+        .bind(profile)
+        .bind(success)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
 async fn acquire_key(
     session: &mut DbSession<Postgres>,
 ) -> Result<(ProfileId, Arc<ProfileKey>), Error> {
@@ -571,6 +2554,88 @@ async fn acquire_key(
     }
 }
 
+/// Add the existing records of a profile to the name and/or category indexes
+async fn add_index_records(
+    conn: &mut PgConnection,
+    profile_id: ProfileId,
+    key: Arc<ProfileKey>,
+    name_index: bool,
+    category_index: bool,
+) -> Result<(), Error> {
+    let items = sqlx::query("SELECT id, category, name FROM items WHERE profile_id = $1")
+        .bind(profile_id)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)))
+        .collect::<Result<Vec<(i64, Vec<u8>, Vec<u8>)>, Error>>()?;
+    let (name_prefixes, category_prefixes) =
+        unblock(move || encode_backfill_prefixes(&key, items, name_index, category_index)).await?;
+    for (item_id, prefix) in name_prefixes {
+        sqlx::query(NAME_INDEX_INSERT_QUERY)
+            .bind(item_id)
+            .bind(prefix)
+            .execute(&mut *conn)
+            .await?;
+    }
+    for (item_id, prefix) in category_prefixes {
+        sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+            .bind(item_id)
+            .bind(prefix)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Insert a new profile record, using the given profile ID if any, otherwise
+/// the next value of the profile ID sequence. Returns `None` if the profile
+/// name is already in use.
+pub(super) async fn insert_profile(
+    conn: &mut PgConnection,
+    name: &str,
+    enc_key: &[u8],
+    profile_id: Option<ProfileId>,
+) -> Result<Option<ProfileId>, Error> {
+    let pid = sqlx::query_scalar(PROFILE_INSERT_QUERY)
+        .bind(name)
+        .bind(enc_key)
+        .bind(profile_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    if pid.is_some() && profile_id.is_some() {
+        sqlx::query(PROFILE_SEQUENCE_QUERY)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(pid)
+}
+
+/// Determine whether the name and category indexes are enabled, from within a
+/// write transaction. An index may have been enabled by another instance of the
+/// store since it was opened, in which case the settings are updated.
+async fn load_index_settings(
+    txn: &mut DbSessionActive<'_, Postgres>,
+    settings: &DbSettings,
+) -> Result<(bool, bool), Error> {
+    if !settings.name_index() || !settings.category_index() {
+        sqlx::query(INDEX_WRITE_LOCK)
+            .execute(txn.connection_mut())
+            .await?;
+        let enabled = sqlx::query_scalar::<_, String>(INDEX_CONFIG_QUERY)
+            .fetch_all(txn.connection_mut())
+            .await?;
+        for name in enabled {
+            match name.as_str() {
+                "name_index" => settings.set_name_index(true),
+                "category_index" => settings.set_category_index(true),
+                _ => (),
+            }
+        }
+    }
+    Ok((settings.name_index(), settings.category_index()))
+}
+
 async fn acquire_session<'q>(
     session: &'q mut DbSession<Postgres>,
 ) -> Result<DbSessionActive<'q, Postgres>, Error> {
@@ -585,19 +2650,101 @@ async fn resolve_profile_key(
     if let Some((pid, key)) = cache.get_profile(profile.as_str()).await {
         Ok((pid, key))
     } else {
-        if let Some(row) = sqlx::query("SELECT id, profile_key FROM profiles WHERE name=?1")
-            .bind(profile.as_str())
-            .fetch_optional(conn)
-            .await?
+        if let Some(row) =
+            sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=$1")
+                .bind(profile.as_str())
+                .fetch_optional(conn)
+                .await?
         {
+            if row.try_get::<Option<String>, _>(2)?.is_some() {
+                return Err(locked_profile_error(&profile));
+            }
             let pid = row.try_get(0)?;
-            let key = Arc::new(cache.load_key(row.try_get(1)?).await?);
+            let key = Arc::new(cache.load_key(pid, row.try_get(1)?).await?);
             cache.add_profile(profile, pid, key.clone()).await;
             Ok((pid, key))
         } else {
-            Err(err_msg!(NotFound, "Profile not found"))
+            Err(err_msg!(NotFound, "Profile not found: '{}'", profile))
+        }
+    }
+}
+
+fn locked_profile_error(profile: &str) -> Error {
+    err_msg!(
+        Locked,
+        "Profile '{}' is protected by a passphrase and must be unlocked",
+        profile
+    )
+}
+
+fn fetch_tags_query(tag_mode: TagRetrieval) -> &'static str {
+    match tag_mode {
+        TagRetrieval::None => FETCH_NO_TAGS_QUERY.as_str(),
+        TagRetrieval::PlaintextOnly => FETCH_PLAINTEXT_TAGS_QUERY.as_str(),
+        TagRetrieval::CountOnly => FETCH_TAG_COUNT_QUERY.as_str(),
+        TagRetrieval::All => FETCH_QUERY.as_str(),
+    }
+}
+
+// decrypt a record selected by `FETCH_QUERY`
+async fn decrypt_fetch_row(
+    row: PgRow,
+    key: Arc<ProfileKey>,
+    settings: Arc<DbSettings>,
+    category: Vec<u8>,
+    name: Vec<u8>,
+) -> Result<Entry, Error> {
+    Ok(
+        decrypt_tagged_row(row, key, settings, category, name, TagRetrieval::All)
+            .await?
+            .entry,
+    )
+}
+
+// decrypt a record selected by the query for the tag retrieval mode. Only
+// the tags present in the row are decoded and decrypted
+async fn decrypt_tagged_row(
+    row: PgRow,
+    key: Arc<ProfileKey>,
+    settings: Arc<DbSettings>,
+    category: Vec<u8>,
+    name: Vec<u8>,
+    tag_mode: TagRetrieval,
+) -> Result<TaggedEntry, Error> {
+    let value = row.try_get(1)?;
+    let tags = row.try_get::<Option<String>, _>(2)?.map(String::into_bytes);
+    let value_type = row.try_get(3)?;
+    let expiry = row.try_get(4)?;
+    let mut tag_count = match tag_mode {
+        TagRetrieval::PlaintextOnly | TagRetrieval::CountOnly => {
+            Some(row.try_get::<i64, _>(5)? as usize)
         }
+        TagRetrieval::None | TagRetrieval::All => None,
+    };
+    let (category, name, value, value_type, (tags, dropped_tags), enc_count) = unblock(move || {
+        let value = key.decrypt_entry_value(&category, &name, value)?;
+        let value_type = decrypt_value_type(&key, &category, &name, value_type)?;
+        let (tags, enc_count) = if let Some(enc_tags) = tags {
+            let enc_tags =
+                decode_tags(enc_tags).map_err(|_| err_msg!(Unexpected, "Error decoding tags"))?;
+            let enc_count = enc_tags.len();
+            (decrypt_tags(&key, enc_tags, &settings)?, enc_count)
+        } else {
+            ((Vec::new(), Vec::new()), 0)
+        };
+        Result::<_, Error>::Ok((category, name, value, value_type, tags, enc_count))
+    })
+    .await?;
+    if tag_mode == TagRetrieval::All {
+        tag_count = Some(enc_count);
     }
+    Ok(TaggedEntry {
+        entry: Entry::from_bytes(category, name, value, tags)
+            .with_value_type(value_type)
+            .with_expiry(expiry)
+            .with_dropped_tags(dropped_tags),
+        tag_count,
+    })
 }
 
 async fn perform_insert<'q>(
@@ -606,20 +2753,29 @@ async fn perform_insert<'q>(
     enc_category: &[u8],
     enc_name: &[u8],
     enc_value: &[u8],
+    enc_value_type: Option<&[u8]>,
     enc_tags: Option<Vec<EncEntryTag>>,
-    expiry_ms: Option<i64>,
+    enc_prefixes: Vec<Vec<u8>>,
+    enc_category_prefixes: Vec<Vec<u8>>,
+    expiry: Option<Expiry>,
 ) -> Result<(), Error> {
     trace!("Insert entry");
-    let row_id: i64 = sqlx::query_scalar(INSERT_QUERY)
+    let inserted = sqlx::query_scalar(INSERT_QUERY)
         .bind(active.profile_id)
         .bind(kind as i16)
         .bind(enc_category)
         .bind(enc_name)
         .bind(enc_value)
-        .bind(expiry_ms.map(expiry_timestamp).transpose()?)
+        .bind(enc_value_type)
+        .bind(expiry)
         .fetch_optional(active.connection_mut())
-        .await?
-        .ok_or_else(|| err_msg!(Duplicate, "Duplicate row"))?;
+        .await
+        .map(ExecResult::returning)?;
+    if inserted.rows_affected() == 0 {
+        return Err(err_msg!(Duplicate, "Duplicate row"));
+    }
+    let row_id = inserted.inserted_id()?;
+    let mut statements = 1;
     if let Some(tags) = enc_tags {
         for tag in tags {
             sqlx::query(TAG_INSERT_QUERY)
@@ -627,10 +2783,29 @@ async fn perform_insert<'q>(
                 .bind(&tag.name)
                 .bind(&tag.value)
                 .bind(tag.plaintext as i16)
+                .bind(tag.value_type as i16)
                 .execute(active.connection_mut())
                 .await?;
+            statements += 1;
         }
     }
+    for prefix in enc_prefixes {
+        sqlx::query(NAME_INDEX_INSERT_QUERY)
+            .bind(row_id)
+            .bind(prefix)
+            .execute(active.connection_mut())
+            .await?;
+        statements += 1;
+    }
+    for prefix in enc_category_prefixes {
+        sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+            .bind(row_id)
+            .bind(prefix)
+            .execute(active.connection_mut())
+            .await?;
+        statements += 1;
+    }
+    active.record_writes(statements, enc_value.len());
     Ok(())
 }
 
@@ -642,13 +2817,14 @@ async fn perform_remove<'q>(
     ignore_error: bool,
 ) -> Result<(), Error> {
     trace!("Remove entry");
-    let done = sqlx::query(DELETE_QUERY)
+    let done = sqlx::query(DELETE_QUERY.as_str())
         .bind(active.profile_id)
         .bind(kind as i16)
         .bind(enc_category)
         .bind(enc_name)
         .execute(active.connection_mut())
         .await?;
+    active.record_writes(1, 0);
     if done.rows_affected() == 0 && !ignore_error {
         Err(err_msg!(NotFound, "Entry not found"))
     } else {
@@ -662,45 +2838,99 @@ fn perform_scan<'q>(
     key: Arc<ProfileKey>,
     kind: EntryKind,
     category: String,
+    name_prefix: Option<String>,
     tag_filter: Option<TagFilter>,
+    after_id: Option<i64>,
     offset: Option<i64>,
     limit: Option<i64>,
     for_update: bool,
 ) -> impl Stream<Item = Result<Vec<EncScanEntry>, Error>> + 'q {
     try_stream! {
-        let mut params = QueryParams::new();
+        let expiry = active.expiry_filter();
+        let hint = active.query_hint();
+        let page_bytes = active.settings().scan_page_bytes();
+        let mut params = QueryParams::with_capacity(3);
         params.push(profile_id);
         params.push(kind as i16);
-        let (enc_category, tag_filter) = unblock({
+        let (enc_category, enc_prefix, tag_filter) = unblock({
             let key = key.clone();
-            let category = ProfileKey::prepare_input(category.as_bytes());
-            let params_len = params.len() + 1; // plus category
+            // plus category, and name prefix or last record ID
+            let params_len =
+                params.len() + 1 + (name_prefix.is_some() || after_id.is_some()) as usize;
             move || {
+                let enc_prefix = name_prefix
+                    .map(|prefix| key.entry_name_prefix_hash(category.as_bytes(), prefix.as_bytes()))
+                    .transpose()?;
                 Result::<_, Error>::Ok((
-                    key.encrypt_entry_category(category)?,
+                    key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?,
+                    enc_prefix,
                     encode_tag_filter::<PostgresStore>(tag_filter, &key, params_len)?
                 ))
             }
         }).await?;
         params.push(enc_category);
-        let mut query = extend_query::<PostgresStore>(SCAN_QUERY, &mut params, tag_filter, offset, limit)?;
+        let base_query = if let Some(enc_prefix) = enc_prefix {
+            params.push(enc_prefix);
+            SCAN_PREFIX_QUERY.as_str()
+        } else if let Some(after_id) = after_id {
+            params.push(after_id);
+            SCAN_AFTER_QUERY.as_str()
+        } else {
+            SCAN_QUERY.as_str()
+        };
+        let mut query = expiry_query::<PostgresStore>(
+            extend_query::<PostgresStore>(base_query, &mut params, tag_filter, hint, offset, limit)?,
+            expiry,
+        );
+        if after_id.is_some() {
+            // resumable scans are not limited, so the ordering follows the conditions
+            query.to_mut().push_str(" ORDER BY i.id");
+        }
         if for_update {
-            query.push_str(" FOR UPDATE");
+            query.to_mut().push_str(" FOR UPDATE");
         }
         let mut batch = Vec::with_capacity(PAGE_SIZE);
+        let mut batch_bytes = 0;
 
         let mut acquired = acquire_session(&mut *active).await?;
-        let mut rows = sqlx::query_with(query.as_str(), params).fetch(acquired.connection_mut());
-        while let Some(row) = rows.try_next().await? {
+        let query_timeout = acquired.query_timeout();
+        let mut rows = sqlx::query_with(&*query, params).fetch(acquired.connection_mut());
+        let mut timed_out = false;
+        let mut failed = None;
+        loop {
+            let row = match run_query(query_timeout, rows.try_next()).await {
+                Some(Ok(Some(row))) => row,
+                Some(Ok(None)) => break,
+                Some(Err(err)) => {
+                    failed = Some(err);
+                    break;
+                }
+                None => {
+                    timed_out = true;
+                    break;
+                }
+            };
             let tags = row.try_get::<Option<String>, _>(3)?.map(String::into_bytes).unwrap_or_default();
+            let value: Vec<u8> = row.try_get(2)?;
+            batch_bytes += value.len();
             batch.push(EncScanEntry {
-                name: row.try_get(1)?, value: row.try_get(2)?, tags
+                id: row.try_get(0)?,
+                name: row.try_get(1)?, value, value_type: row.try_get(4)?, tags,
+                expiry: row.try_get(5)?,
             });
-            if batch.len() == PAGE_SIZE {
+            // large values end the page early to limit the memory held by each scan
+            if batch.len() == PAGE_SIZE || batch_bytes >= page_bytes {
+                batch_bytes = 0;
                 yield batch.split_off(0);
             }
         }
         drop(rows);
+        if timed_out {
+            Err::<(), _>(acquired.timed_out())?;
+        }
+        if let Some(err) = failed {
+            Err::<(), _>(acquired.check_error(err))?;
+        }
         drop(acquired);
         drop(active);
 
@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use sqlx::{
@@ -9,16 +10,22 @@ use sqlx::{
 
 use crate::{
     backend::{
-        db_utils::{init_keys, random_profile_name},
+        db_utils::{envelope_version_enabled, random_profile_name, random_store_id, DbSettings},
         types::ManageBackend,
     },
     error::Error,
     future::{unblock, BoxFuture},
-    protect::{KeyCache, PassKey, ProfileId, StoreKeyMethod, StoreKeyReference},
-    storage::{IntoOptions, Store},
+    protect::{
+        KeyCache, PassKey, ProfileId, ProvisionKeys, ProvisionResult, StoreKeyMethod,
+        StoreKeyReference,
+    },
+    storage::{AccessEvent, IdSource, IntoOptions, NameNormalization, Store},
 };
 
-use super::PostgresStore;
+use super::{insert_profile, log_access, PostgresStore};
+
+// the schema version of a newly provisioned store
+pub(super) const SCHEMA_VERSION: u32 = 3;
 
 const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
 const DEFAULT_IDLE_TIMEOUT: u64 = 300;
@@ -36,6 +43,7 @@ pub struct PostgresStoreOptions {
     pub(crate) admin_uri: String,
     pub(crate) host: String,
     pub(crate) name: String,
+    pub(crate) settings: DbSettings,
 }
 
 impl PostgresStoreOptions {
@@ -73,6 +81,7 @@ impl PostgresStoreOptions {
         } else {
             DEFAULT_MIN_CONNECTIONS
         };
+        let settings = DbSettings::from_query(&mut opts.query)?;
         let admin_acct = opts.query.remove("admin_account");
         let admin_pass = opts.query.remove("admin_password");
         let uri = opts.clone().into_uri();
@@ -107,6 +116,7 @@ impl PostgresStoreOptions {
             admin_uri: opts.into_uri(),
             host,
             name,
+            settings,
         })
     }
 
@@ -180,48 +190,92 @@ impl PostgresStoreOptions {
         if recreate {
             // remove expected tables
             reset_db(&mut *txn).await?;
-        } else {
-            if sqlx::query_scalar::<_, i64>(
-                "SELECT COUNT(*) FROM information_schema.tables
-                WHERE table_schema='public' AND table_name='config'",
+        } else if has_store(&mut *txn).await? {
+            // proceed to open, will fail if the version doesn't match
+            return open_db(
+                conn_pool,
+                Some(method),
+                pass_key,
+                profile,
+                self.host,
+                self.name,
+                self.settings,
             )
-            .fetch_one(&mut txn)
-            .await?
-                == 1
-            {
-                // proceed to open, will fail if the version doesn't match
-                return open_db(
-                    conn_pool,
-                    Some(method),
-                    pass_key,
-                    profile,
-                    self.host,
-                    self.name,
-                )
-                .await;
-            }
-            // no 'config' table, assume empty database
+            .await;
         }
 
-        let (profile_key, enc_profile_key, store_key, store_key_ref) = unblock({
-            let pass_key = pass_key.into_owned();
-            move || init_keys(method, pass_key)
-        })
-        .await?;
+        let keys = ProvisionKeys::create(method, pass_key).await?;
+        self.init_store(conn_pool, txn, keys, profile)
+            .await
+            .map(|(store, _)| store)
+    }
+
+    /// Provision a new Postgres store, returning the details of the store needed
+    /// to open it again, including any generated raw key or derivation salt.
+    ///
+    /// Unlike `provision`, an existing store is not opened: the database must
+    /// not already contain a store.
+    pub async fn provision_with_result(
+        self,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+        profile: Option<&str>,
+        recreate: bool,
+    ) -> Result<(Store<PostgresStore>, ProvisionResult), Error> {
+        let conn_pool = self.create_db_pool().await?;
+        let mut txn = conn_pool.begin().await?;
+
+        if recreate {
+            // remove expected tables
+            reset_db(&mut *txn).await?;
+        } else if has_store(&mut *txn).await? {
+            return Err(err_msg!(Duplicate, "The database already contains a store"));
+        }
+
+        let keys = ProvisionKeys::create(method, pass_key).await?;
+        self.init_store(conn_pool, txn, keys, profile).await
+    }
+
+    async fn init_store(
+        self,
+        conn_pool: PgPool,
+        txn: Transaction<'static, Postgres>,
+        keys: ProvisionKeys,
+        profile: Option<&str>,
+    ) -> Result<(Store<PostgresStore>, ProvisionResult), Error> {
         let default_profile = profile
             .map(str::to_string)
             .unwrap_or_else(random_profile_name);
-        let profile_id = init_db(txn, &default_profile, store_key_ref, enc_profile_key).await?;
+        let mut result = ProvisionResult::new(&keys, default_profile.clone(), 0, SCHEMA_VERSION)?;
+        let ProvisionKeys {
+            profile_key,
+            enc_profile_key,
+            store_key,
+            store_key_ref,
+            ..
+        } = keys;
+        let profile_id = init_db(
+            txn,
+            &default_profile,
+            self.settings.next_profile_id(),
+            store_key_ref,
+            enc_profile_key,
+            self.settings.name_normalization(),
+        )
+        .await?;
+        result.profile_id = profile_id;
         let mut key_cache = KeyCache::new(store_key);
         key_cache.add_profile_mut(default_profile.clone(), profile_id, profile_key);
 
-        Ok(Store::new(PostgresStore::new(
+        let store = Store::new(PostgresStore::new(
             conn_pool,
             default_profile,
             key_cache,
             self.host,
             self.name,
-        )))
+            self.settings,
+        ));
+        Ok((store, result))
     }
 
     /// Open an existing Postgres store from this set of configuration options
@@ -240,7 +294,16 @@ impl PostgresStoreOptions {
             }
             Err(e) => Err(e.into()),
         }?;
-        open_db(pool, method, pass_key, profile, self.host, self.name).await
+        open_db(
+            pool,
+            method,
+            pass_key,
+            profile,
+            self.host,
+            self.name,
+            self.settings,
+        )
+        .await
     }
 
     /// Remove an existing Postgres store defined by these configuration options
@@ -262,6 +325,15 @@ impl PostgresStoreOptions {
             Err(err) => Err(err_msg!(Backend, "Error removing database").with_cause(err)),
         }
     }
+
+    /// Assign the IDs of new profiles from an `IdSource`, such as a
+    /// `SequentialIds` sequence, instead of letting the database assign them.
+    /// This applies to the default profile of a provisioned store and to the
+    /// profiles created or imported through the store
+    pub fn id_source(mut self, source: Arc<dyn IdSource>) -> Self {
+        self.settings.id_source = Some(source);
+        self
+    }
 }
 
 impl<'a> ManageBackend<'a> for PostgresStoreOptions {
@@ -293,11 +365,24 @@ impl<'a> ManageBackend<'a> for PostgresStoreOptions {
     }
 }
 
+// check for the configuration table of an existing store
+async fn has_store(conn: &mut PgConnection) -> Result<bool, Error> {
+    Ok(sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM information_schema.tables
+        WHERE table_schema='public' AND table_name='config'",
+    )
+    .fetch_one(conn)
+    .await?
+        == 1)
+}
+
 pub(crate) async fn init_db<'t>(
     mut txn: Transaction<'t, Postgres>,
     profile_name: &str,
+    profile_id: Option<ProfileId>,
     store_key_ref: String,
     enc_profile_key: Vec<u8>,
+    name_normalization: NameNormalization,
 ) -> Result<ProfileId, Error> {
     txn.execute(
         "
@@ -323,6 +408,7 @@ pub(crate) async fn init_db<'t>(
             category BYTEA NOT NULL,
             name BYTEA NOT NULL,
             value BYTEA NOT NULL,
+            value_type BYTEA NULL,
             expiry TIMESTAMP NULL,
             PRIMARY KEY(id),
             FOREIGN KEY(profile_id) REFERENCES profiles(id)
@@ -336,6 +422,7 @@ pub(crate) async fn init_db<'t>(
             name BYTEA NOT NULL,
             value BYTEA NOT NULL,
             plaintext SMALLINT NOT NULL,
+            value_type SMALLINT NOT NULL DEFAULT 0,
             PRIMARY KEY(id),
             FOREIGN KEY(item_id) REFERENCES items(id)
                 ON DELETE CASCADE ON UPDATE CASCADE
@@ -351,20 +438,21 @@ pub(crate) async fn init_db<'t>(
         "INSERT INTO config (name, value) VALUES
             ('default_profile', $1),
             ('key', $2),
-            ('version', '1')",
+            ('name_normalization', $3),
+            ('store_id', $4),
+            ('version', '3')",
     )
     .persistent(false)
     .bind(profile_name)
     .bind(store_key_ref)
+    .bind(name_normalization.as_str())
+    .bind(random_store_id())
     .execute(&mut txn)
     .await?;
 
-    let profile_id =
-        sqlx::query_scalar("INSERT INTO profiles (name, profile_key) VALUES ($1, $2) RETURNING id")
-            .bind(profile_name)
-            .bind(enc_profile_key)
-            .fetch_one(&mut txn)
-            .await?;
+    let profile_id = insert_profile(&mut txn, profile_name, &enc_profile_key, profile_id)
+        .await?
+        .ok_or_else(|| err_msg!(Unexpected, "Default profile not created"))?;
 
     txn.commit().await?;
 
@@ -377,13 +465,66 @@ pub(crate) async fn reset_db(conn: &mut PgConnection) -> Result<(), Error> {
         DROP TABLE IF EXISTS
           config, profiles,
           profile_keys, keys,
-          items, items_tags;
+          items, items_tags, access_log;
         ",
     )
     .await?;
     Ok(())
 }
 
+/// Add the `value_type` column introduced in version 2 of the schema
+async fn upgrade_db_v1(conn: &mut PgConnection) -> Result<(), Error> {
+    let mut txn = conn.begin().await?;
+    // the store may have been upgraded by another connection in the meantime
+    let version: String =
+        sqlx::query_scalar("SELECT value FROM config WHERE name = 'version' FOR UPDATE")
+            .fetch_one(&mut txn)
+            .await?;
+    if version == "1" {
+        txn.execute("ALTER TABLE items ADD COLUMN value_type BYTEA NULL")
+            .await?;
+        txn.execute("UPDATE config SET value = '2' WHERE name = 'version'")
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Add the tag `value_type` column introduced in version 3 of the schema
+async fn upgrade_db_v2(conn: &mut PgConnection) -> Result<(), Error> {
+    let mut txn = conn.begin().await?;
+    let version: String =
+        sqlx::query_scalar("SELECT value FROM config WHERE name = 'version' FOR UPDATE")
+            .fetch_one(&mut txn)
+            .await?;
+    if version == "2" {
+        txn.execute("ALTER TABLE items_tags ADD COLUMN value_type SMALLINT NOT NULL DEFAULT 0")
+            .await?;
+        txn.execute("UPDATE config SET value = '3' WHERE name = 'version'")
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Record an ID for a store provisioned before store IDs were introduced,
+/// returning the ID in effect
+async fn assign_store_id(conn: &mut PgConnection) -> Result<String, Error> {
+    // another connection may assign the ID in the meantime
+    sqlx::query(
+        "INSERT INTO config (name, value) VALUES ('store_id', $1)
+        ON CONFLICT (name) DO NOTHING",
+    )
+    .bind(random_store_id())
+    .execute(&mut *conn)
+    .await?;
+    Ok(
+        sqlx::query_scalar("SELECT value FROM config WHERE name = 'store_id'")
+            .fetch_one(&mut *conn)
+            .await?,
+    )
+}
+
 pub(crate) async fn open_db(
     conn_pool: PgPool,
     method: Option<StoreKeyMethod>,
@@ -391,69 +532,121 @@ pub(crate) async fn open_db(
     profile: Option<&str>,
     host: String,
     name: String,
+    mut settings: DbSettings,
 ) -> Result<Store<PostgresStore>, Error> {
     let mut conn = conn_pool.acquire().await?;
-    let mut ver_ok = false;
+    let mut version: Option<String> = None;
     let mut default_profile: Option<String> = None;
     let mut store_key_ref: Option<String> = None;
+    let mut store_id: Option<String> = None;
+    let mut envelope_version: Option<String> = None;
+    let mut name_normalization = NameNormalization::None;
 
     let config = sqlx::query(
         r#"SELECT name, value FROM config
-        WHERE name IN ('default_profile', 'key', 'version')"#,
+        WHERE name IN ('access_log', 'category_index', 'default_profile', 'envelope_version',
+            'key', 'name_index', 'name_normalization', 'store_id', 'version')"#,
     )
     .fetch_all(&mut conn)
     .await?;
     for row in config {
         match row.try_get(0)? {
+            "access_log" => {
+                settings.set_access_log(row.try_get::<&str, _>(1)? == "1");
+            }
+            "category_index" => {
+                settings.set_category_index(row.try_get::<&str, _>(1)? == "1");
+            }
             "default_profile" => {
                 default_profile.replace(row.try_get(1)?);
             }
+            "envelope_version" => {
+                envelope_version.replace(row.try_get(1)?);
+            }
             "key" => {
                 store_key_ref.replace(row.try_get(1)?);
             }
+            "name_index" => {
+                settings.set_name_index(row.try_get::<&str, _>(1)? == "1");
+            }
+            "name_normalization" => {
+                name_normalization = NameNormalization::parse(row.try_get(1)?)?;
+            }
+            "store_id" => {
+                store_id.replace(row.try_get(1)?);
+            }
             "version" => {
-                if row.try_get::<&str, _>(1)? != "1" {
-                    return Err(err_msg!(Unsupported, "Unsupported store version"));
-                }
-                ver_ok = true;
+                version.replace(row.try_get(1)?);
             }
             _ => (),
         }
     }
-    if !ver_ok {
-        return Err(err_msg!(Unsupported, "Store version not found"));
+    let store_id = match store_id {
+        Some(store_id) => store_id,
+        None => assign_store_id(&mut conn).await?,
+    };
+    // a store opened in place of another is rejected before it is upgraded
+    settings.check_store_id(&store_id)?;
+    settings.set_value_envelope(envelope_version_enabled(envelope_version.as_deref())?);
+    match version.as_deref() {
+        Some("1") => {
+            upgrade_db_v1(&mut conn).await?;
+            upgrade_db_v2(&mut conn).await?;
+        }
+        Some("2") => upgrade_db_v2(&mut conn).await?,
+        Some("3") => (),
+        Some(_) => return Err(err_msg!(Unsupported, "Unsupported store version")),
+        None => return Err(err_msg!(Unsupported, "Store version not found")),
     }
     let profile = profile
         .map(str::to_string)
         .or(default_profile)
         .ok_or_else(|| err_msg!(Unsupported, "Default store profile not found"))?;
-    let store_key = if let Some(store_key_ref) = store_key_ref {
-        let wrap_ref = StoreKeyReference::parse_uri(&store_key_ref)?;
-        if let Some(method) = method {
-            if !wrap_ref.compare_method(&method) {
-                return Err(err_msg!(Input, "Store key method mismatch"));
+    let opened = async {
+        let store_key = if let Some(store_key_ref) = store_key_ref {
+            let wrap_ref = StoreKeyReference::parse_uri(&store_key_ref)?;
+            if let Some(method) = method {
+                if !wrap_ref.compare_method(&method) {
+                    return Err(err_msg!(Input, "Store key method mismatch"));
+                }
             }
-        }
-        unblock({
-            let pass_key = pass_key.into_owned();
-            move || wrap_ref.resolve(pass_key)
-        })
-        .await?
-    } else {
-        return Err(err_msg!(Unsupported, "Store key not found"));
-    };
-    let mut key_cache = KeyCache::new(store_key);
+            unblock({
+                let pass_key = pass_key.into_owned();
+                move || wrap_ref.resolve(pass_key)
+            })
+            .await?
+        } else {
+            return Err(err_msg!(Unsupported, "Store key not found"));
+        };
+        let mut key_cache = KeyCache::new(store_key);
 
-    let row = sqlx::query("SELECT id, profile_key FROM profiles WHERE name = $1")
-        .bind(&profile)
-        .fetch_one(&mut conn)
+        let row = sqlx::query("SELECT id, profile_key FROM profiles WHERE name = $1")
+            .bind(&profile)
+            .fetch_optional(&mut conn)
+            .await?
+            .ok_or_else(|| err_msg!(NotFound, "Store profile not found: '{}'", profile))?;
+        let profile_id = row.try_get(0)?;
+        let profile_key = key_cache.load_key(profile_id, row.try_get(1)?).await?;
+        key_cache.add_profile_mut(profile.clone(), profile_id, profile_key);
+        Result::<_, Error>::Ok(key_cache)
+    }
+    .await;
+    if settings.access_log() {
+        // a store key which cannot be verified is recorded as a failed open
+        log_access(
+            &mut conn,
+            settings.client_label.as_deref(),
+            AccessEvent::Open,
+            Some(&profile),
+            opened.is_ok(),
+        )
         .await?;
-    let profile_id = row.try_get(0)?;
-    let profile_key = key_cache.load_key(row.try_get(1)?).await?;
-    key_cache.add_profile_mut(profile.clone(), profile_id, profile_key);
+    }
+    let key_cache = opened?;
+    settings.name_normalization = name_normalization;
 
     Ok(Store::new(PostgresStore::new(
-        conn_pool, profile, key_cache, host, name,
+        conn_pool, profile, key_cache, host, name, settings,
     )))
 }
 
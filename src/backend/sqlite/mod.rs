@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_stream::try_stream;
 use futures_lite::{
@@ -8,61 +9,429 @@ use futures_lite::{
     stream::{Stream, StreamExt},
 };
 
+use libsqlite3_sys::sqlite3_interrupt;
+use once_cell::sync::Lazy;
 use sqlx::{
     pool::PoolConnection,
-    sqlite::{Sqlite, SqlitePool},
+    sqlite::{Sqlite, SqliteConnection, SqlitePool, SqliteQueryResult, SqliteRow},
     Database, Error as SqlxError, Row, TransactionManager,
 };
 
+#[cfg(feature = "blob_store")]
+use crate::backend::blob::{blob_id, BlobStore, PendingBlob};
 use crate::{
     backend::{
         db_utils::{
-            decode_tags, decrypt_scan_batch, encode_profile_key, encode_tag_filter,
-            expiry_timestamp, extend_query, prepare_tags, random_profile_name, DbSession,
-            DbSessionActive, DbSessionRef, EncScanEntry, ExtDatabase, QueryParams, QueryPrepare,
-            PAGE_SIZE,
+            audit_entry, check_category_prefix, check_entry_key, check_name_prefix, decode_tags,
+            decrypt_scan_batch, decrypt_scan_batch_with_ids, decrypt_tags, decrypt_value_type,
+            delete_category_batches, encode_backfill_prefixes, encode_index_prefixes,
+            encode_profile_key, encode_tag_filter, encrypt_value_type, expiry_query,
+            expiry_timestamp, extend_query, grouped_scan_queries, health_status,
+            index_pending_config, index_profile_key, instrument, item_delete_query, item_query,
+            prepare_tags, protect_profile_key, random_profile_name, replace_arg_placeholders,
+            resolve_profile_wrap_key, run_probe, run_query, scan_cursor_check, DbSession,
+            DbSessionActive, DbSessionRef, DbSettings, EncAuditEntry, EncScanEntry, ExecResult,
+            Expiry, ExtDatabase, FetchFlight, QueryParams, QueryPrepare, CATEGORY_INDEX,
+            NAME_INDEX, PAGE_SIZE,
         },
         types::{Backend, QueryBackend},
     },
+    crypto::buffer::SecretBytes,
     error::Error,
-    future::{unblock, BoxFuture},
-    protect::{EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, StoreKeyMethod},
-    storage::{EncEntryTag, Entry, EntryKind, EntryOperation, EntryTag, Scan, TagFilter},
+    future::{spawn_ok, unblock, BoxFuture},
+    protect::{
+        EncryptionPolicy, EntryEncryptor, KeyCache, PassKey, ProfileId, ProfileKey, StoreKey,
+        StoreKeyMethod, StoreKeyReference, ENVELOPE_VERSION, PROFILE_KEY_ALG,
+    },
+    storage::{
+        AccessEvent, AccessLogEntry, ConditionalFetch, DecryptionAudit, DecryptionAuditOptions,
+        DecryptionFailure, EncEntryTag, Entry, EntryAccess, EntryKind, EntryOperation, EntryTag,
+        EntryVersion, ExpiryFilter, ExpiryUpdate, FilteredFetch, HealthStatus, IntegrityReport,
+        NameNormalization, ProfileQuota, ProfileUsage, QueryHint, ResumableScan, Scan, ScanCursor,
+        StoreDiagnostics, StoreInfo, TagFilter, TagRetrieval, TagStats, TaggedEntry,
+        TAG_STATS_DISTINCT_LIMIT,
+    },
 };
 
 mod provision;
-pub use provision::SqliteStoreOptions;
+use provision::SCHEMA_VERSION;
+pub use provision::{MigrationStep, SqliteStoreOptions};
 
-const COUNT_QUERY: &'static str = "SELECT COUNT(*) FROM items i
-    WHERE profile_id = ?1 AND kind = ?2 AND category = ?3
-    AND (expiry IS NULL OR expiry > DATETIME('now'))";
-const DELETE_QUERY: &'static str = "DELETE FROM items
+#[cfg(feature = "unsafe_queries")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unsafe_queries")))]
+mod raw;
+#[cfg(feature = "unsafe_queries")]
+pub use raw::{RawItem, RawValue};
+
+mod snapshot;
+
+// result codes for errors which leave a connection unusable
+const SQLITE_IOERR: i32 = 10;
+const SQLITE_CORRUPT: i32 = 11;
+const SQLITE_FULL: i32 = 13;
+const SQLITE_CANTOPEN: i32 = 14;
+const SQLITE_NOTADB: i32 = 26;
+
+// the encoded tags of a record, as decoded by `decode_tags`
+const TAGS_COLUMN: &'static str = "(SELECT GROUP_CONCAT((it.plaintext + it.value_type) || ':' || HEX(it.name) || ':' || HEX(it.value))
+        FROM items_tags it WHERE it.item_id = i.id) AS tags";
+const PLAINTEXT_TAGS_COLUMN: &'static str = "(SELECT GROUP_CONCAT((it.plaintext + it.value_type) || ':' || HEX(it.name) || ':' || HEX(it.value))
+        FROM items_tags it WHERE it.item_id = i.id AND it.plaintext = 1) AS tags";
+const TAG_COUNT_COLUMN: &'static str =
+    "(SELECT COUNT(*) FROM items_tags it WHERE it.item_id = i.id) AS tag_count";
+
+static COUNT_QUERY: Lazy<String> = Lazy::new(|| item_query::<SqliteStore>("COUNT(*)", ""));
+static DELETE_QUERY: Lazy<String> = Lazy::new(|| item_delete_query::<SqliteStore>("i.name = $$"));
+static DELETE_ALL_QUERY: Lazy<String> = Lazy::new(|| item_delete_query::<SqliteStore>(""));
+static FETCH_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!("i.id, i.value, {}, i.value_type, i.expiry", TAGS_COLUMN),
+        "i.name = $$",
+    )
+});
+static FETCH_EXISTS_QUERY: Lazy<String> =
+    Lazy::new(|| item_query::<SqliteStore>("COUNT(*)", "i.name = $$"));
+// the tag columns selected for each `TagRetrieval` mode other than `All`
+static FETCH_PLAINTEXT_TAGS_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!(
+            "i.id, i.value, {}, i.value_type, i.expiry, {}",
+            PLAINTEXT_TAGS_COLUMN, TAG_COUNT_COLUMN
+        ),
+        "i.name = $$",
+    )
+});
+static FETCH_TAG_COUNT_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!(
+            "i.id, i.value, NULL AS tags, i.value_type, i.expiry, {}",
+            TAG_COUNT_COLUMN
+        ),
+        "i.name = $$",
+    )
+});
+static FETCH_NO_TAGS_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        "i.id, i.value, NULL AS tags, i.value_type, i.expiry",
+        "i.name = $$",
+    )
+});
+static ETAG_FETCH_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        "(SELECT e.etag FROM items_etag e WHERE e.item_id = i.id)",
+        "i.name = $$",
+    )
+});
+static SCAN_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, i.expiry",
+            TAGS_COLUMN
+        ),
+        "",
+    )
+});
+static SCAN_PREFIX_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, i.expiry",
+            TAGS_COLUMN
+        ),
+        "i.id IN (SELECT item_id FROM items_name_index WHERE prefix = $$)",
+    )
+});
+static SCAN_AFTER_QUERY: Lazy<String> = Lazy::new(|| {
+    item_query::<SqliteStore>(
+        &format!(
+            "i.id, i.name, i.value, {}, i.value_type, i.expiry",
+            TAGS_COLUMN
+        ),
+        "i.id > $$",
+    )
+});
+
+// all kinds of record are audited, including expired records
+static AUDIT_QUERY: Lazy<String> = Lazy::new(|| {
+    format!(
+        "SELECT i.id, i.category, i.name, i.value, i.value_type, {}, i.kind
+        FROM items i WHERE i.profile_id = ?1 AND i.id > ?2 ORDER BY i.id LIMIT ?3",
+        TAGS_COLUMN
+    )
+});
+
+const ACCESS_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_access (
+        item_id INTEGER NOT NULL,
+        read_count INTEGER NOT NULL,
+        last_read_at DATETIME NOT NULL,
+        PRIMARY KEY (item_id),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    INSERT OR REPLACE INTO config (name, value) VALUES ('access_tracking', '1');
+";
+const ACCESS_UPDATE_QUERY: &'static str = "INSERT INTO items_access
+    (item_id, read_count, last_read_at) VALUES (?1, ?2, ?3)
+    ON CONFLICT (item_id) DO UPDATE SET read_count = read_count + excluded.read_count,
+    last_read_at = excluded.last_read_at";
+const BATCH_SCHEMA: &'static str = "CREATE TABLE IF NOT EXISTS applied_batches (
+        profile_id INTEGER NOT NULL,
+        idempotency_key BLOB NOT NULL,
+        updates INTEGER NOT NULL,
+        applied_at DATETIME NOT NULL,
+        PRIMARY KEY (profile_id, idempotency_key),
+        FOREIGN KEY (profile_id) REFERENCES profiles (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    )";
+const BATCH_FETCH_QUERY: &'static str = "SELECT updates FROM applied_batches
+    WHERE profile_id = ?1 AND idempotency_key = ?2 AND applied_at > DATETIME('now', ?3)";
+const BATCH_INSERT_QUERY: &'static str = "INSERT OR REPLACE INTO applied_batches
+    (profile_id, idempotency_key, updates, applied_at) VALUES (?1, ?2, ?3, DATETIME('now'))";
+const BATCH_PURGE_QUERY: &'static str =
+    "DELETE FROM applied_batches WHERE applied_at <= DATETIME('now', ?1)";
+const BATCH_TABLE_QUERY: &'static str =
+    "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='applied_batches'";
+const HISTORY_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_history (
+        id INTEGER NOT NULL,
+        profile_id INTEGER NOT NULL,
+        kind INTEGER NOT NULL,
+        category BLOB NOT NULL,
+        name BLOB NOT NULL,
+        value BLOB NOT NULL,
+        value_type BLOB NULL,
+        replaced_at DATETIME NOT NULL,
+        PRIMARY KEY (id),
+        FOREIGN KEY (profile_id) REFERENCES profiles (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS ix_items_history_item
+        ON items_history (profile_id, kind, category, name, id);
+    INSERT OR REPLACE INTO config (name, value) VALUES ('value_history', '1');
+";
+const HISTORY_FETCH_QUERY: &'static str = "SELECT value, value_type, replaced_at
+    FROM items_history WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4
+    ORDER BY id DESC LIMIT ?5";
+const HISTORY_INSERT_QUERY: &'static str = "INSERT INTO items_history
+    (profile_id, kind, category, name, value, value_type, replaced_at)
+    SELECT profile_id, kind, category, name, value, value_type, ?5 FROM items
     WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4";
-const FETCH_QUERY: &'static str = "SELECT i.id, i.value,
-    (SELECT GROUP_CONCAT(it.plaintext || ':' || HEX(it.name) || ':' || HEX(it.value))
-        FROM items_tags it WHERE it.item_id = i.id) AS tags
-    FROM items i WHERE i.profile_id = ?1 AND i.kind = ?2
-    AND i.category = ?3 AND i.name = ?4
-    AND (i.expiry IS NULL OR i.expiry > DATETIME('now'))";
+const HISTORY_PURGE_QUERY: &'static str = "DELETE FROM items_history
+    WHERE replaced_at <= DATETIME('now', ?1)
+    OR (SELECT COUNT(*) FROM items_history h WHERE h.profile_id = items_history.profile_id
+        AND h.kind = items_history.kind AND h.category = items_history.category
+        AND h.name = items_history.name AND h.id > items_history.id) >= ?2";
+const HISTORY_TABLE_QUERY: &'static str =
+    "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='items_history'";
+const ACCESS_LOG_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS access_log (
+        id INTEGER NOT NULL,
+        logged_at DATETIME NOT NULL,
+        event TEXT NOT NULL,
+        client TEXT NULL,
+        profile TEXT NULL,
+        success INTEGER NOT NULL,
+        PRIMARY KEY (id)
+    );
+    CREATE INDEX IF NOT EXISTS ix_access_log_logged_at ON access_log (logged_at);
+    INSERT OR REPLACE INTO config (name, value) VALUES ('access_log', '1');
+";
+const ACCESS_LOG_INSERT_QUERY: &'static str = "INSERT INTO access_log
+    (logged_at, event, client, profile, success) VALUES (?1, ?2, ?3, ?4, ?5)";
+const ACCESS_LOG_FETCH_QUERY: &'static str =
+    "SELECT id, logged_at, event, client, profile, success FROM access_log
+    WHERE ?1 IS NULL OR logged_at >= ?1 ORDER BY id DESC LIMIT ?2";
+const ACCESS_LOG_PURGE_QUERY: &'static str = "DELETE FROM access_log WHERE logged_at < ?1";
+const ACCESS_LOG_TABLE_QUERY: &'static str =
+    "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='access_log'";
+const EXPIRY_FETCH_QUERY: &'static str = "SELECT expiry FROM items
+    WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4";
+const HEALTH_PROFILE_QUERY: &'static str =
+    "SELECT id, profile_key, reference FROM profiles WHERE name = ?1";
+const HEALTH_VERSION_QUERY: &'static str = "SELECT value FROM config WHERE name = 'version'";
+const INFO_CONFIG_QUERY: &'static str = "SELECT name, value FROM config
+    WHERE name IN ('default_profile', 'key', 'store_id', 'version')";
 const INSERT_QUERY: &'static str =
-    "INSERT OR IGNORE INTO items (profile_id, kind, category, name, value, expiry)
-    VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
-const SCAN_QUERY: &'static str = "SELECT i.id, i.name, i.value,
-    (SELECT GROUP_CONCAT(it.plaintext || ':' || HEX(it.name) || ':' || HEX(it.value))
-        FROM items_tags it WHERE it.item_id = i.id) AS tags
-    FROM items i WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3
+    "INSERT OR IGNORE INTO items (profile_id, kind, category, name, value, value_type, expiry)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+const MISSING_KEYS_QUERY: &'static str = "SELECT p.id, COUNT(i.id) FROM profiles p
+    LEFT JOIN items i ON i.profile_id = p.id
+    WHERE p.profile_key IS NULL GROUP BY p.id ORDER BY p.id";
+const ORPHANED_ITEMS_QUERY: &'static str = "SELECT i.profile_id, COUNT(i.id) FROM items i
+    LEFT JOIN profiles p ON p.id = i.profile_id
+    WHERE p.id IS NULL GROUP BY i.profile_id ORDER BY i.profile_id";
+const ORPHANED_TAGS_QUERY: &'static str = "SELECT COUNT(*) FROM items_tags it
+    WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = it.item_id)";
+const ORPHANED_TAGS_DELETE_QUERY: &'static str = "DELETE FROM items_tags
+    WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = items_tags.item_id)";
+const PURGE_EXPIRED_QUERY: &'static str = "DELETE FROM items
+    WHERE expiry IS NOT NULL AND expiry <= DATETIME('now')";
+const QUOTA_CHECK_QUERY: &'static str = "SELECT u.item_count, u.value_bytes,
+    p.quota_items, p.quota_bytes FROM profiles p JOIN profile_usage u ON u.profile_id = p.id
+    WHERE p.id = ?1 AND (p.quota_items IS NOT NULL OR p.quota_bytes IS NOT NULL)";
+const SCAN_GROUP_QUERY: &'static str = "SELECT i.id, i.name, i.value,
+    (SELECT GROUP_CONCAT((it.plaintext + it.value_type) || ':' || HEX(it.name) || ':' || HEX(it.value))
+        FROM items_tags it WHERE it.item_id = i.id) AS tags, i.value_type, i.expiry
+    FROM items i WHERE i.profile_id = $1 AND i.kind = $2 AND i.category = $3
     AND (i.expiry IS NULL OR i.expiry > DATETIME('now'))";
-const DELETE_ALL_QUERY: &'static str = "DELETE FROM items AS i
-    WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3";
+const SCAN_COLDEST_QUERY: &'static str = "SELECT i.id, i.name, i.value,
+    (SELECT GROUP_CONCAT((it.plaintext + it.value_type) || ':' || HEX(it.name) || ':' || HEX(it.value))
+        FROM items_tags it WHERE it.item_id = i.id) AS tags, i.value_type, i.expiry,
+    COALESCE(a.read_count, 0) AS read_count, a.last_read_at
+    FROM items i LEFT JOIN items_access a ON a.item_id = i.id
+    WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3
+    AND (i.expiry IS NULL OR i.expiry > DATETIME('now'))
+    ORDER BY read_count, a.last_read_at, i.id LIMIT ?4";
+const DELETE_ID_QUERY: &'static str = "DELETE FROM items WHERE id = ?1";
+const RENAME_FETCH_QUERY: &'static str = "SELECT id, value, value_type FROM items
+    WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4
+    AND (expiry IS NULL OR expiry > DATETIME('now'))";
+const RENAME_EXISTS_QUERY: &'static str = "SELECT COUNT(*) FROM items
+    WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4";
+const RENAME_UPDATE_QUERY: &'static str = "UPDATE items
+    SET category = ?1, name = ?2, value = ?3, value_type = ?4 WHERE id = ?5";
+const NAME_INDEX_DELETE_QUERY: &'static str = "DELETE FROM items_name_index WHERE item_id = ?1";
+const CATEGORY_INDEX_DELETE_QUERY: &'static str =
+    "DELETE FROM items_category_index WHERE item_id = ?1";
+const NAME_INDEX_INSERT_QUERY: &'static str = "INSERT OR IGNORE INTO items_name_index
+    (item_id, prefix) VALUES (?1, ?2)";
+const NAME_INDEX_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_name_index (
+        item_id INTEGER NOT NULL,
+        prefix BLOB NOT NULL,
+        PRIMARY KEY (item_id, prefix),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS ix_items_name_index_prefix ON items_name_index (prefix);
+    INSERT OR REPLACE INTO config (name, value) VALUES ('name_index', '1');
+";
+const USAGE_FETCH_QUERY: &'static str = "SELECT COALESCE(u.item_count, 0),
+    COALESCE(u.value_bytes, 0), p.quota_items, p.quota_bytes
+    FROM profiles p LEFT JOIN profile_usage u ON u.profile_id = p.id WHERE p.name = ?1";
+pub(super) const USAGE_REBUILD_QUERY: &'static str = "DELETE FROM profile_usage;
+    INSERT INTO profile_usage (profile_id, item_count, value_bytes)
+    SELECT p.id, COUNT(i.id), COALESCE(SUM(LENGTH(i.value)), 0)
+    FROM profiles p LEFT JOIN items i ON i.profile_id = p.id GROUP BY p.id";
+// the usage counters are maintained by triggers, so that every removal path
+// (including cascades from removed profiles) is accounted for
+pub(super) const USAGE_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS profile_usage (
+        profile_id INTEGER NOT NULL,
+        item_count INTEGER NOT NULL DEFAULT 0,
+        value_bytes INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (profile_id),
+        FOREIGN KEY (profile_id) REFERENCES profiles (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE TRIGGER IF NOT EXISTS tr_items_usage_insert AFTER INSERT ON items
+    BEGIN
+        INSERT OR IGNORE INTO profile_usage (profile_id) VALUES (NEW.profile_id);
+        UPDATE profile_usage SET item_count = item_count + 1,
+            value_bytes = value_bytes + LENGTH(NEW.value)
+            WHERE profile_id = NEW.profile_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS tr_items_usage_update AFTER UPDATE OF value ON items
+    BEGIN
+        UPDATE profile_usage SET value_bytes = value_bytes - LENGTH(OLD.value) + LENGTH(NEW.value)
+            WHERE profile_id = NEW.profile_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS tr_items_usage_delete AFTER DELETE ON items
+    BEGIN
+        UPDATE profile_usage SET item_count = item_count - 1,
+            value_bytes = value_bytes - LENGTH(OLD.value)
+            WHERE profile_id = OLD.profile_id;
+    END;
+";
+const TAG_DISTINCT_QUERY: &'static str = "SELECT COUNT(*) FROM
+    (SELECT DISTINCT it.value FROM items_tags it JOIN items i ON i.id = it.item_id
+        WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3
+        AND it.name = ?4 AND it.plaintext = 1 LIMIT ?5) v";
 const TAG_INSERT_QUERY: &'static str = "INSERT INTO items_tags
-    (item_id, name, value, plaintext) VALUES (?1, ?2, ?3, ?4)";
+    (item_id, name, value, plaintext, value_type) VALUES (?1, ?2, ?3, ?4, ?5)";
+const TAG_STATS_QUERY: &'static str = "SELECT it.name, COUNT(*), SUM(it.plaintext),
+    AVG(CASE WHEN it.plaintext = 1 THEN LENGTH(it.value) END)
+    FROM items_tags it JOIN items i ON i.id = it.item_id
+    WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3
+    GROUP BY it.name";
+const TRUNCATE_BATCH_QUERY: &'static str = "DELETE FROM items WHERE id IN
+    (SELECT id FROM items WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 LIMIT ?4)";
+const CATEGORIES_QUERY: &'static str = "SELECT DISTINCT category FROM items
+    WHERE profile_id = ?1 AND kind = ?2";
+const CATEGORIES_PREFIX_QUERY: &'static str = "SELECT DISTINCT category FROM items
+    WHERE profile_id = ?1 AND kind = ?2
+    AND id IN (SELECT item_id FROM items_category_index WHERE prefix = ?3)";
+const CATEGORY_INDEX_INSERT_QUERY: &'static str = "INSERT OR IGNORE INTO items_category_index
+    (item_id, prefix) VALUES (?1, ?2)";
+const INDEX_CONFIG_QUERY: &'static str = "SELECT name FROM config
+    WHERE name IN ('category_index', 'name_index') AND value = '1'";
+const INDEX_PENDING_DELETE_QUERY: &'static str = "DELETE FROM config WHERE name IN (?1, ?2)";
+const INDEX_PENDING_INSERT_QUERY: &'static str =
+    "INSERT OR IGNORE INTO config (name, value) VALUES (?1, '1')";
+const INDEX_PENDING_QUERY: &'static str = "SELECT name FROM config WHERE name IN (?1, ?2)";
+const CATEGORY_INDEX_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_category_index (
+        item_id INTEGER NOT NULL,
+        prefix BLOB NOT NULL,
+        PRIMARY KEY (item_id, prefix),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS ix_items_category_index_prefix
+        ON items_category_index (prefix);
+    INSERT OR REPLACE INTO config (name, value) VALUES ('category_index', '1');
+";
+const ETAG_INSERT_QUERY: &'static str = "INSERT OR REPLACE INTO items_etag
+    (item_id, etag) VALUES (?1, ?2)";
+const ETAG_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS items_etag (
+        item_id INTEGER NOT NULL,
+        etag BLOB NOT NULL,
+        PRIMARY KEY (item_id),
+        FOREIGN KEY (item_id) REFERENCES items (id)
+            ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    INSERT OR REPLACE INTO config (name, value) VALUES ('value_etags', '1');
+";
+const UNENCRYPTED_VALUES_QUERY: &'static str =
+    "INSERT OR REPLACE INTO config (name, value) VALUES ('unencrypted_values', '1')";
+#[cfg(feature = "blob_store")]
+const BLOB_REF_QUERY: &'static str = "SELECT value FROM items
+    WHERE profile_id = ?1 AND kind = ?2 AND category = ?3 AND name = ?4
+    AND SUBSTR(value, 1, 11) = CAST('askar-blob:' AS BLOB)";
+#[cfg(feature = "blob_store")]
+const BLOB_REF_ALL_QUERY: &'static str = "SELECT i.value FROM items i
+    WHERE i.profile_id = ?1 AND i.kind = ?2 AND i.category = ?3
+    AND SUBSTR(i.value, 1, 11) = CAST('askar-blob:' AS BLOB)";
+#[cfg(feature = "blob_store")]
+const BLOB_REF_EXPIRED_QUERY: &'static str = "SELECT i.value FROM items i
+    WHERE i.expiry IS NOT NULL AND i.expiry <= DATETIME('now')
+    AND SUBSTR(i.value, 1, 11) = CAST('askar-blob:' AS BLOB)";
+#[cfg(feature = "blob_store")]
+const BLOB_REF_PROFILE_QUERY: &'static str = "SELECT i.value FROM items i
+    JOIN profiles p ON p.id = i.profile_id WHERE p.name = ?1
+    AND SUBSTR(i.value, 1, 11) = CAST('askar-blob:' AS BLOB)";
 
 /// A Sqlite database store
 pub struct SqliteStore {
     conn_pool: SqlitePool,
+    // an optional pool of read-only connections used by scans, so that long
+    // scans do not hold the connections needed for updates
+    read_pool: Option<SqlitePool>,
     default_profile: String,
     key_cache: Arc<KeyCache>,
     path: String,
+    settings: Arc<DbSettings>,
+    #[cfg(feature = "blob_store")]
+    blobs: Option<Arc<BlobStore>>,
+    // whether a profile with unencrypted entry values has been created, as
+    // recorded in the store configuration when the store was opened
+    #[cfg(feature = "blob_store")]
+    pub(crate) unencrypted_values: bool,
+    // whether a profile has a limit on the size of its entry values, as
+    // recorded when the store was opened
+    #[cfg(feature = "blob_store")]
+    pub(crate) byte_quota: bool,
 }
 
 impl SqliteStore {
@@ -74,10 +443,264 @@ impl SqliteStore {
     ) -> Self {
         Self {
             conn_pool,
+            read_pool: None,
             default_profile,
             key_cache: Arc::new(key_cache),
             path,
+            settings: Default::default(),
+            #[cfg(feature = "blob_store")]
+            blobs: None,
+            #[cfg(feature = "blob_store")]
+            unencrypted_values: false,
+            #[cfg(feature = "blob_store")]
+            byte_quota: false,
+        }
+    }
+
+    /// Check that the entry values of a new profile may be stored unencrypted.
+    ///
+    /// A sidecar file is referenced by an identifier stored in place of the
+    /// value, which an unencrypted value could imitate to read or remove the
+    /// sidecar file of another record. An external blob store is therefore
+    /// never combined with unencrypted values.
+    #[allow(unused)]
+    fn check_value_policy(&self, policy: &EncryptionPolicy) -> Result<(), Error> {
+        #[cfg(feature = "blob_store")]
+        if !policy.value && self.blobs.is_some() {
+            return Err(err_msg!(
+                Unsupported,
+                "Unencrypted entry values are not supported with an external blob store"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replace the store key, wrapping the profile keys again with the new key
+    async fn rekey_inner(
+        &mut self,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'static>,
+    ) -> Result<(), Error> {
+        let (store_key, store_key_ref) = unblock(move || method.resolve(pass_key)).await?;
+        let store_key = Arc::new(store_key);
+        let mut txn = self.conn_pool.begin().await?;
+        // profiles protected by a passphrase are not wrapped by the store key
+        let mut rows = sqlx::query("SELECT id, profile_key FROM profiles WHERE reference IS NULL")
+            .fetch(&mut txn);
+        let mut upd_keys = BTreeMap::<ProfileId, Vec<u8>>::new();
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            let pid = row.try_get(0)?;
+            let enc_key = row.try_get(1)?;
+            let profile_key = self.key_cache.load_key(pid, enc_key).await?;
+            let upd_key = unblock({
+                let store_key = store_key.clone();
+                move || encode_profile_key(&profile_key, &store_key)
+            })
+            .await?;
+            upd_keys.insert(pid, upd_key);
+        }
+        drop(rows);
+        for (pid, key) in upd_keys {
+            if sqlx::query("UPDATE profiles SET profile_key=?1 WHERE id=?2")
+                .bind(key)
+                .bind(pid)
+                .execute(&mut txn)
+                .await
+                .map(Sqlite::exec_result)?
+                .rows_affected()
+                != 1
+            {
+                return Err(err_msg!(Backend, "Error updating profile key"));
+            }
         }
+        if sqlx::query("UPDATE config SET value=?1 WHERE name='key'")
+            .bind(store_key_ref.into_uri())
+            .execute(&mut txn)
+            .await
+            .map(Sqlite::exec_result)?
+            .rows_affected()
+            != 1
+        {
+            return Err(err_msg!(Backend, "Error updating store key"));
+        }
+        txn.commit().await?;
+        // handles opened later with the prior pass key must not reuse its keys
+        self.key_cache.unshare();
+        self.key_cache = Arc::new(KeyCache::new(store_key));
+        self.settings.info.invalidate();
+        Ok(())
+    }
+
+    /// Add the existing records of each profile to a newly enabled index. The
+    /// records of a locked profile are added when it is next unlocked.
+    async fn backfill_index(&self, conn: &mut SqliteConnection, index: &str) -> Result<(), Error> {
+        let profiles = sqlx::query("SELECT id, name, profile_key, reference FROM profiles")
+            .fetch_all(&mut *conn)
+            .await?;
+        for row in profiles {
+            let profile_id: ProfileId = row.try_get(0)?;
+            let name: String = row.try_get(1)?;
+            let protected = row.try_get::<Option<String>, _>(3)?.is_some();
+            match index_profile_key(
+                &self.key_cache,
+                profile_id,
+                &name,
+                row.try_get(2)?,
+                protected,
+            )
+            .await?
+            {
+                Some(key) => {
+                    add_index_records(
+                        conn,
+                        profile_id,
+                        key,
+                        index == NAME_INDEX,
+                        index == CATEGORY_INDEX,
+                    )
+                    .await?
+                }
+                None => {
+                    debug!("Deferred indexing the records of locked profile '{}'", name);
+                    sqlx::query(INDEX_PENDING_INSERT_QUERY)
+                        .bind(index_pending_config(index, profile_id))
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the records of an unlocked profile to the indexes enabled while it
+    /// was locked
+    async fn backfill_unlocked(
+        &self,
+        profile_id: ProfileId,
+        key: Arc<ProfileKey>,
+    ) -> Result<(), Error> {
+        let name_pending = index_pending_config(NAME_INDEX, profile_id);
+        let category_pending = index_pending_config(CATEGORY_INDEX, profile_id);
+        let mut txn = self.conn_pool.begin().await?;
+        let pending = sqlx::query_scalar::<_, String>(INDEX_PENDING_QUERY)
+            .bind(&name_pending)
+            .bind(&category_pending)
+            .fetch_all(&mut txn)
+            .await?;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        add_index_records(
+            &mut txn,
+            profile_id,
+            key,
+            pending.contains(&name_pending),
+            pending.contains(&category_pending),
+        )
+        .await?;
+        sqlx::query(INDEX_PENDING_DELETE_QUERY)
+            .bind(&name_pending)
+            .bind(&category_pending)
+            .execute(&mut txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// Record an event in the access log, if enabled. The event may follow
+    /// changes which are already committed, so a failure to record it is
+    /// logged rather than returned in place of the outcome of the operation
+    async fn log_access(&self, event: AccessEvent, profile: Option<&str>, success: bool) {
+        if !self.settings.access_log() {
+            return;
+        }
+        let logged = async {
+            let mut conn = self.conn_pool.acquire().await?;
+            log_access(
+                &mut conn,
+                self.settings.client_label.as_deref(),
+                event,
+                profile,
+                success,
+            )
+            .await
+        }
+        .await;
+        if let Err(err) = logged {
+            warn!(
+                "Error recording {} in the access log: {}",
+                event.as_str(),
+                err
+            );
+        }
+    }
+
+    // a session for queries which do not modify the store, using the read pool if present
+    fn read_session(&self, profile: Option<String>) -> DbSession<Sqlite> {
+        let session = DbSession::new(
+            self.read_pool.as_ref().unwrap_or(&self.conn_pool).clone(),
+            self.key_cache.clone(),
+            profile.unwrap_or_else(|| self.default_profile.clone()),
+            self.settings.clone(),
+            false,
+        );
+        #[cfg(feature = "blob_store")]
+        let session = session.with_blob_store(self.blobs.clone());
+        session
+    }
+
+    fn scan_entries(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: Option<String>,
+        tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        Box::pin(async move {
+            let mut session = self.read_session(profile);
+            session.set_expiry_filter(expiry);
+            session.set_query_hint(hint);
+            let mut active = session.owned_ref();
+            let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
+            let scan = perform_scan(
+                active,
+                profile_id,
+                key.clone(),
+                kind,
+                category.clone(),
+                name_prefix,
+                tag_filter,
+                None,
+                offset,
+                limit,
+            );
+            let settings = self.settings.clone();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blobs.clone();
+            let stream = scan.then(move |enc_rows| {
+                let category = category.clone();
+                let key = key.clone();
+                let settings = settings.clone();
+                #[cfg(feature = "blob_store")]
+                let blobs = blobs.clone();
+                unblock(move || {
+                    #[cfg(feature = "blob_store")]
+                    let enc_rows = match blobs {
+                        Some(blobs) => blobs.resolve_rows(enc_rows?),
+                        None => enc_rows,
+                    };
+                    decrypt_scan_batch(category, enc_rows?, &key, &settings)
+                })
+            });
+            Scan::new(stream, PAGE_SIZE).tracked(&self.settings.scans)
+        })
     }
 }
 
@@ -92,576 +715,4661 @@ impl Debug for SqliteStore {
 
 impl QueryPrepare for SqliteStore {
     type DB = Sqlite;
+
+    fn hint_query(query: String, hint: QueryHint) -> String {
+        match hint {
+            // the records matched by the tag filter are still looked up by rowid,
+            // but the planner may not scan the category index instead
+            QueryHint::PreferTagIndex => query
+                .replacen("FROM items i WHERE", "FROM items i NOT INDEXED WHERE", 1)
+                .replacen(
+                    "FROM items AS i WHERE",
+                    "FROM items AS i NOT INDEXED WHERE",
+                    1,
+                ),
+        }
+    }
 }
 
 impl Backend for SqliteStore {
     type Session = DbSession<Sqlite>;
 
-    fn create_profile(&self, name: Option<String>) -> BoxFuture<'_, Result<String, Error>> {
+    fn create_profile(
+        &self,
+        name: Option<String>,
+        policy: EncryptionPolicy,
+    ) -> BoxFuture<'_, Result<String, Error>> {
         let name = name.unwrap_or_else(random_profile_name);
-        Box::pin(async move {
-            let key = ProfileKey::new()?;
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            self.check_value_policy(&policy)?;
+            let key = ProfileKey::new_with_policy(policy)?;
             let enc_key = key.to_bytes()?;
-            let mut conn = self.conn_pool.acquire().await?;
-            let done =
-                sqlx::query("INSERT OR IGNORE INTO profiles (name, profile_key) VALUES (?1, ?2)")
-                    .bind(&name)
-                    .bind(enc_key.as_ref())
-                    .execute(&mut conn)
-                    .await?;
+            let mut txn = self.conn_pool.begin().await?;
+            let done = sqlx::query(
+                "INSERT OR IGNORE INTO profiles (id, name, profile_key) VALUES (?3, ?1, ?2)",
+            )
+            .bind(&name)
+            .bind(enc_key.as_ref())
+            .bind(self.settings.next_profile_id())
+            .execute(&mut txn)
+            .await
+            .map(Sqlite::exec_result)?;
             if done.rows_affected() == 0 {
                 return Err(err_msg!(Duplicate, "Duplicate profile name"));
             }
+            if !policy.value {
+                sqlx::query(UNENCRYPTED_VALUES_QUERY)
+                    .execute(&mut txn)
+                    .await?;
+            }
+            txn.commit().await?;
             self.key_cache
-                .add_profile(name.clone(), done.last_insert_rowid(), Arc::new(key))
+                .add_profile(name.clone(), done.inserted_id()?, Arc::new(key))
                 .await;
+            self.settings.info.invalidate();
             Ok(name)
-        })
+        });
+        instrument(metrics, "create_profile", |_| None, fut)
     }
 
     fn get_profile_name(&self) -> &str {
         self.default_profile.as_str()
     }
 
-    fn remove_profile(&self, name: String) -> BoxFuture<'_, Result<bool, Error>> {
+    fn load_profile(&self, name: String) -> BoxFuture<'_, Result<(), Error>> {
         Box::pin(async move {
             let mut conn = self.conn_pool.acquire().await?;
-            Ok(sqlx::query("DELETE FROM profiles WHERE name=?")
+            resolve_profile_key(&mut conn, self.key_cache.clone(), name).await?;
+            Ok(())
+        })
+    }
+
+    fn remove_profile(&self, name: String) -> BoxFuture<'_, Result<bool, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            #[cfg(feature = "blob_store")]
+            let blob_ids = if self.blobs.is_some() {
+                fetch_blob_ids(sqlx::query(BLOB_REF_PROFILE_QUERY).bind(&name), &mut conn).await?
+            } else {
+                vec![]
+            };
+            let removed = sqlx::query("DELETE FROM profiles WHERE name=?")
                 .bind(&name)
                 .execute(&mut conn)
-                .await?
+                .await
+                .map(Sqlite::exec_result)?
                 .rows_affected()
-                != 0)
-        })
+                != 0;
+            self.settings.info.invalidate();
+            self.settings.clear_entry_cache();
+            #[cfg(feature = "blob_store")]
+            if let Some(blobs) = self.blobs.as_ref() {
+                blobs.remove_all(blob_ids);
+            }
+            Ok(removed)
+        });
+        instrument(metrics, "remove_profile", |_| None, fut)
     }
 
-    fn rekey_backend(
-        &mut self,
-        method: StoreKeyMethod,
-        pass_key: PassKey<'_>,
+    fn list_profiles(&self) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let names = sqlx::query_scalar("SELECT name FROM profiles ORDER BY name")
+                .fetch_all(&mut conn)
+                .await?;
+            Ok(names)
+        });
+        instrument(metrics, "list_profiles", |_| None, fut)
+    }
+
+    fn export_profile_key(
+        &self,
+        profile: Option<String>,
+    ) -> BoxFuture<'_, Result<SecretBytes, Error>> {
+        let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let (_, key) =
+                resolve_profile_key(&mut conn, self.key_cache.clone(), profile.clone()).await?;
+            info!("Exporting the key of profile '{}'", profile);
+            key.to_bytes()
+        });
+        instrument(metrics, "export_profile_key", |_| None, fut)
+    }
+
+    fn import_profile_key(
+        &self,
+        profile: String,
+        key: SecretBytes,
+        force: bool,
     ) -> BoxFuture<'_, Result<(), Error>> {
-        let pass_key = pass_key.into_owned();
-        Box::pin(async move {
-            let (store_key, store_key_ref) = unblock(move || method.resolve(pass_key)).await?;
-            let store_key = Arc::new(store_key);
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let store_key = self.key_cache.store_key.clone();
+            let (profile_key, enc_key) = unblock(move || {
+                let profile_key = ProfileKey::from_slice(key.as_ref())
+                    .map_err(err_map!(Input, "Invalid profile key"))?;
+                let enc_key = encode_profile_key(&profile_key, &store_key)?;
+                Result::<_, Error>::Ok((profile_key, enc_key))
+            })
+            .await?;
+            self.check_value_policy(&profile_key.policy)?;
             let mut txn = self.conn_pool.begin().await?;
-            let mut rows = sqlx::query("SELECT id, profile_key FROM profiles").fetch(&mut txn);
-            let mut upd_keys = BTreeMap::<ProfileId, Vec<u8>>::new();
-            while let Some(row) = rows.next().await {
-                let row = row?;
-                let pid = row.try_get(0)?;
-                let enc_key = row.try_get(1)?;
-                let profile_key = self.key_cache.load_key(enc_key).await?;
-                let upd_key = unblock({
-                    let store_key = store_key.clone();
-                    move || encode_profile_key(&profile_key, &store_key)
-                })
-                .await?;
-                upd_keys.insert(pid, upd_key);
-            }
-            drop(rows);
-            for (pid, key) in upd_keys {
-                if sqlx::query("UPDATE profiles SET profile_key=?1 WHERE id=?2")
-                    .bind(key)
-                    .bind(pid)
+            if !profile_key.policy.value {
+                sqlx::query(UNENCRYPTED_VALUES_QUERY)
                     .execute(&mut txn)
-                    .await?
-                    .rows_affected()
-                    != 1
+                    .await?;
+            }
+            let found =
+                sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=?1")
+                    .bind(&profile)
+                    .fetch_optional(&mut txn)
+                    .await?;
+            let pid = if let Some(row) = found {
+                let pid: ProfileId = row.try_get(0)?;
+                let protected = row.try_get::<Option<String>, _>(2)?.is_some();
+                if !force
+                    && (protected
+                        || self.key_cache.get_profile(&profile).await.is_some()
+                        || self.key_cache.load_key(pid, row.try_get(1)?).await.is_ok())
                 {
-                    return Err(err_msg!(Backend, "Error updating profile key"));
+                    return Err(err_msg!(
+                        Duplicate,
+                        "Profile '{}' has a working key, which is only replaced when forced",
+                        profile
+                    ));
                 }
+                sqlx::query("UPDATE profiles SET profile_key=?1, reference=NULL WHERE id=?2")
+                    .bind(&enc_key)
+                    .bind(pid)
+                    .execute(&mut txn)
+                    .await?;
+                pid
+            } else {
+                sqlx::query("INSERT INTO profiles (id, name, profile_key) VALUES (?3, ?1, ?2)")
+                    .bind(&profile)
+                    .bind(&enc_key)
+                    .bind(self.settings.next_profile_id())
+                    .execute(&mut txn)
+                    .await
+                    .map(Sqlite::exec_result)?
+                    .inserted_id()?
+            };
+            txn.commit().await?;
+            info!("Imported the key of profile '{}'", profile);
+            self.key_cache
+                .add_profile(profile, pid, Arc::new(profile_key))
+                .await;
+            self.settings.info.invalidate();
+            Ok(())
+        });
+        instrument(metrics, "import_profile_key", |_| None, fut)
+    }
+
+    fn protect_profile(
+        &self,
+        profile: String,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            if profile == self.default_profile {
+                return Err(err_msg!(
+                    Unsupported,
+                    "The default profile cannot be protected by a passphrase"
+                ));
             }
-            if sqlx::query("UPDATE config SET value=?1 WHERE name='key'")
-                .bind(store_key_ref.into_uri())
-                .execute(&mut txn)
-                .await?
+            let mut conn = self.conn_pool.acquire().await?;
+            let (pid, key) =
+                resolve_profile_key(&mut conn, self.key_cache.clone(), profile.clone()).await?;
+            let (enc_key, reference) =
+                unblock(move || protect_profile_key(&key, method, pass_key)).await?;
+            if sqlx::query("UPDATE profiles SET profile_key=?1, reference=?2 WHERE id=?3")
+                .bind(enc_key)
+                .bind(reference)
+                .bind(pid)
+                .execute(&mut conn)
+                .await
+                .map(Sqlite::exec_result)?
                 .rows_affected()
                 != 1
             {
-                return Err(err_msg!(Backend, "Error updating store key"));
+                return Err(err_msg!(Backend, "Error updating profile key"));
             }
-            txn.commit().await?;
-            self.key_cache = Arc::new(KeyCache::new(store_key));
+            info!("Protected profile '{}' with a passphrase", profile);
             Ok(())
-        })
+        });
+        instrument(metrics, "protect_profile", |_| None, fut)
     }
 
-    fn scan(
+    fn unlock_profile(
         &self,
-        profile: Option<String>,
-        kind: EntryKind,
-        category: String,
-        tag_filter: Option<TagFilter>,
-        offset: Option<i64>,
-        limit: Option<i64>,
-    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        profile: String,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let unlocked = async {
+                let mut conn = self.conn_pool.acquire().await?;
+                let row =
+                    sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=?1")
+                        .bind(&profile)
+                        .fetch_optional(&mut conn)
+                        .await?
+                        .ok_or_else(|| err_msg!(NotFound, "Profile not found: '{}'", profile))?;
+                let pid = row.try_get(0)?;
+                let reference: String = row.try_get::<Option<String>, _>(2)?.ok_or_else(|| {
+                    err_msg!(
+                        Input,
+                        "Profile '{}' is not protected by a passphrase",
+                        profile
+                    )
+                })?;
+                let wrap_key =
+                    unblock(move || resolve_profile_wrap_key(&reference, pass_key)).await?;
+                let key = Arc::new(
+                    self.key_cache
+                        .unlock_key(pid, row.try_get(1)?, wrap_key)
+                        .await?,
+                );
+                drop(conn);
+                self.backfill_unlocked(pid, key.clone()).await?;
+                self.key_cache.add_profile(profile.clone(), pid, key).await;
+                Result::<_, Error>::Ok(())
+            }
+            .await;
+            self.log_access(AccessEvent::UnlockProfile, Some(&profile), unlocked.is_ok())
+                .await;
+            unlocked
+        });
+        instrument(metrics, "unlock_profile", |_| None, fut)
+    }
+
+    fn lock_profile(&self, profile: String) -> BoxFuture<'_, Result<bool, Error>> {
         Box::pin(async move {
-            let session = self.session(profile, false)?;
-            let mut active = session.owned_ref();
-            let (profile_id, key) = acquire_key(&mut *active).await?;
-            let scan = perform_scan(
-                active,
-                profile_id,
-                key.clone(),
-                kind,
-                category.clone(),
-                tag_filter,
-                offset,
-                limit,
-            );
-            let stream = scan.then(move |enc_rows| {
-                let category = category.clone();
-                let key = key.clone();
-                unblock(move || decrypt_scan_batch(category, enc_rows?, &key))
-            });
-            Ok(Scan::new(stream, PAGE_SIZE))
+            let locked = self.key_cache.lock_profile(&profile).await;
+            if locked {
+                // drop any records decrypted with the profile key
+                self.settings.clear_entry_cache();
+            }
+            Ok(locked)
         })
     }
 
-    fn session(&self, profile: Option<String>, transaction: bool) -> Result<Self::Session, Error> {
-        Ok(DbSession::new(
-            self.conn_pool.clone(),
-            self.key_cache.clone(),
-            profile.unwrap_or_else(|| self.default_profile.clone()),
-            transaction,
-        ))
+    fn set_profile_quota(
+        &self,
+        profile: Option<String>,
+        quota: ProfileQuota,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            if quota.max_items.map(|max| max < 0).unwrap_or(false)
+                || quota.max_bytes.map(|max| max < 0).unwrap_or(false)
+            {
+                return Err(err_msg!(Input, "Profile quota limits must not be negative"));
+            }
+            // the size of a value held in a sidecar file is not counted in the
+            // usage of the profile, which only sees the reference to the file
+            #[cfg(feature = "blob_store")]
+            if quota.max_bytes.is_some() && self.blobs.is_some() {
+                return Err(err_msg!(
+                    Unsupported,
+                    "Byte quotas are not supported with an external blob store"
+                ));
+            }
+            let mut conn = self.conn_pool.acquire().await?;
+            if sqlx::query("UPDATE profiles SET quota_items=?1, quota_bytes=?2 WHERE name=?3")
+                .bind(quota.max_items)
+                .bind(quota.max_bytes)
+                .bind(&profile)
+                .execute(&mut conn)
+                .await
+                .map(Sqlite::exec_result)?
+                .rows_affected()
+                == 0
+            {
+                return Err(err_msg!(NotFound, "Profile not found: '{}'", profile));
+            }
+            Ok(())
+        });
+        instrument(metrics, "set_profile_quota", |_| None, fut)
     }
 
-    fn close(&self) -> BoxFuture<'_, Result<(), Error>> {
-        Box::pin(async move {
-            self.conn_pool.close().await;
+    fn profile_usage(&self, profile: Option<String>) -> BoxFuture<'_, Result<ProfileUsage, Error>> {
+        let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let row = sqlx::query(USAGE_FETCH_QUERY)
+                .bind(&profile)
+                .fetch_optional(&mut conn)
+                .await?
+                .ok_or_else(|| err_msg!(NotFound, "Profile not found: '{}'", profile))?;
+            Ok(ProfileUsage {
+                items: row.try_get(0)?,
+                bytes: row.try_get(1)?,
+                quota: ProfileQuota {
+                    max_items: row.try_get(2)?,
+                    max_bytes: row.try_get(3)?,
+                },
+            })
+        });
+        instrument(metrics, "profile_usage", |_| None, fut)
+    }
+
+    fn rebuild_usage(&self) -> BoxFuture<'_, Result<(), Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut txn = self.conn_pool.begin().await?;
+            sqlx::query(USAGE_REBUILD_QUERY).execute(&mut txn).await?;
+            txn.commit().await?;
             Ok(())
-        })
+        });
+        instrument(metrics, "rebuild_usage", |_| None, fut)
     }
-}
 
-impl QueryBackend for DbSession<Sqlite> {
-    fn count<'q>(
-        &'q mut self,
-        kind: EntryKind,
-        category: &'q str,
-        tag_filter: Option<TagFilter>,
-    ) -> BoxFuture<'q, Result<i64, Error>> {
-        let category = ProfileKey::prepare_input(category.as_bytes());
+    fn clear_cache(&self) {
+        self.settings.clear_entry_cache();
+    }
 
+    fn rekey_backend(
+        &mut self,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let pass_key = pass_key.into_owned();
         Box::pin(async move {
-            let (profile_id, key) = acquire_key(&mut *self).await?;
-            let mut params = QueryParams::new();
-            params.push(profile_id);
-            params.push(kind as i16);
-            let (enc_category, tag_filter) = unblock({
-                let params_len = params.len() + 1; // plus category
-                move || {
-                    Result::<_, Error>::Ok((
-                        key.encrypt_entry_category(category)?,
-                        encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?,
-                    ))
+            let rekeyed = self.rekey_inner(method, pass_key).await;
+            self.log_access(AccessEvent::Rekey, None, rekeyed.is_ok())
+                .await;
+            rekeyed
+        })
+    }
+
+    fn truncate_profile(
+        &self,
+        profile: Option<String>,
+        categories: Option<Vec<String>>,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, i64>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut session = self.session(profile, false)?;
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories = if let Some(categories) = categories {
+                unblock(move || {
+                    categories
+                        .into_iter()
+                        .map(|category| {
+                            let enc_category = key.encrypt_entry_category(
+                                ProfileKey::prepare_input(category.as_bytes()),
+                            )?;
+                            Ok((category, enc_category))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()
+                })
+                .await?
+            } else {
+                let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_QUERY)
+                    .bind(profile_id)
+                    .bind(EntryKind::Item as i16)
+                    .fetch_all(active.connection_mut())
+                    .await?;
+                unblock(move || {
+                    enc_categories
+                        .into_iter()
+                        .map(|enc_category| {
+                            Ok((
+                                key.decrypt_entry_category(enc_category.clone())?,
+                                enc_category,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()
+                })
+                .await?
+            };
+            let mut counts = BTreeMap::new();
+            for (category, enc_category) in enc_categories {
+                #[cfg(feature = "blob_store")]
+                let blob_ids = if self.blobs.is_some() {
+                    fetch_blob_ids(
+                        sqlx::query(BLOB_REF_ALL_QUERY)
+                            .bind(profile_id)
+                            .bind(EntryKind::Item as i16)
+                            .bind(enc_category.as_slice()),
+                        active.connection_mut(),
+                    )
+                    .await?
+                } else {
+                    vec![]
+                };
+                let removed = delete_category_batches(
+                    &mut active,
+                    TRUNCATE_BATCH_QUERY,
+                    profile_id,
+                    &enc_category,
+                )
+                .await?;
+                #[cfg(feature = "blob_store")]
+                if let Some(blobs) = self.blobs.as_ref() {
+                    blobs.remove_all(blob_ids);
                 }
-            })
-            .await?;
-            params.push(enc_category);
-            let query =
-                extend_query::<SqliteStore>(COUNT_QUERY, &mut params, tag_filter, None, None)?;
-            let mut active = acquire_session(&mut *self).await?;
-            let count = sqlx::query_scalar_with(query.as_str(), params)
-                .fetch_one(active.connection_mut())
+                counts.insert(category, removed);
+            }
+            self.settings.clear_entry_cache();
+            Ok(counts)
+        });
+        instrument(
+            metrics,
+            "truncate_profile",
+            |counts| Some(counts.values().sum()),
+            fut,
+        )
+    }
+
+    fn list_categories(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        Box::pin(async move {
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .fetch_all(active.connection_mut())
                 .await?;
-            Ok(count)
+            drop(active);
+            unblock(move || {
+                let mut categories = enc_categories
+                    .into_iter()
+                    .map(|enc_category| key.decrypt_entry_category(enc_category))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                categories.sort();
+                Ok(categories)
+            })
+            .await
         })
     }
 
-    fn fetch(
-        &mut self,
+    fn list_categories_prefix(
+        &self,
+        profile: Option<String>,
         kind: EntryKind,
-        category: &str,
-        name: &str,
-        _for_update: bool,
-    ) -> BoxFuture<'_, Result<Option<Entry>, Error>> {
-        let category = category.to_string();
-        let name = name.to_string();
-
+        category_prefix: String,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>> {
         Box::pin(async move {
-            let (profile_id, key) = acquire_key(&mut *self).await?;
-            let (enc_category, enc_name) = unblock({
+            check_category_prefix(&category_prefix)?;
+            if !self.settings.category_index() {
+                return Err(err_msg!(
+                    Unsupported,
+                    "Category index is not enabled for the store"
+                ));
+            }
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let enc_prefix = unblock({
                 let key = key.clone();
-                let category = ProfileKey::prepare_input(category.as_bytes());
-                let name = ProfileKey::prepare_input(name.as_bytes());
-                move || {
-                    Result::<_, Error>::Ok((
-                        key.encrypt_entry_category(category)?,
-                        key.encrypt_entry_name(name)?,
-                    ))
-                }
+                move || key.entry_category_prefix_hash(category_prefix.as_bytes())
             })
             .await?;
-            let mut active = acquire_session(&mut *self).await?;
-            if let Some(row) = sqlx::query(FETCH_QUERY)
+            let mut active = acquire_session(&mut session).await?;
+            let enc_categories: Vec<Vec<u8>> = sqlx::query_scalar(CATEGORIES_PREFIX_QUERY)
                 .bind(profile_id)
                 .bind(kind as i16)
-                .bind(enc_category)
-                .bind(enc_name)
-                .fetch_optional(active.connection_mut())
-                .await?
-            {
-                let value = row.try_get(1)?;
-                let tags = row.try_get(2)?;
-                let (category, name, value, tags) = unblock(move || {
-                    let value = key.decrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
-                    let enc_tags = decode_tags(tags)
-                        .map_err(|_| err_msg!(Unexpected, "Error decoding entry tags"))?;
-                    let tags = key.decrypt_entry_tags(enc_tags)?;
-                    Result::<_, Error>::Ok((category, name, value, tags))
-                })
+                .bind(enc_prefix)
+                .fetch_all(active.connection_mut())
                 .await?;
-                Ok(Some(Entry::new(category, name, value, tags)))
-            } else {
-                Ok(None)
-            }
+            drop(active);
+            unblock(move || {
+                let mut categories = enc_categories
+                    .into_iter()
+                    .map(|enc_category| key.decrypt_entry_category(enc_category))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                categories.sort();
+                Ok(categories)
+            })
+            .await
         })
     }
 
-    fn fetch_all<'q>(
-        &'q mut self,
+    fn scan(
+        &self,
+        profile: Option<String>,
         kind: EntryKind,
-        category: &'q str,
+        category: String,
         tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
+        offset: Option<i64>,
         limit: Option<i64>,
-        _for_update: bool,
-    ) -> BoxFuture<'q, Result<Vec<Entry>, Error>> {
-        let category = category.to_string();
-        Box::pin(async move {
-            let mut active = self.borrow_mut();
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        instrument(
+            self.settings.metrics.clone(),
+            "scan",
+            |_| None,
+            self.scan_entries(
+                profile, kind, category, None, tag_filter, expiry, hint, offset, limit,
+            ),
+        )
+    }
+
+    fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>> {
+        if let Err(err) = check_name_prefix(&name_prefix) {
+            return Box::pin(async move { Err(err) });
+        }
+        if !self.settings.name_index() {
+            return Box::pin(async move {
+                Err(err_msg!(
+                    Unsupported,
+                    "Name index is not enabled for the store"
+                ))
+            });
+        }
+        instrument(
+            self.settings.metrics.clone(),
+            "scan_prefix",
+            |_| None,
+            self.scan_entries(
+                profile,
+                kind,
+                category,
+                Some(name_prefix),
+                tag_filter,
+                ExpiryFilter::Exclude,
+                None,
+                offset,
+                limit,
+            ),
+        )
+    }
+
+    fn scan_resumable(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        cursor: Option<ScanCursor>,
+    ) -> BoxFuture<'_, Result<ResumableScan, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let profile = profile.unwrap_or_else(|| self.default_profile.clone());
+            let session = self.read_session(Some(profile.clone()));
+            let mut active = session.owned_ref();
             let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
+            let filter_str = tag_filter.as_ref().map(TagFilter::to_string).transpose()?;
+            let check = unblock({
+                let key = key.clone();
+                let category = category.clone();
+                move || {
+                    let enc_category =
+                        key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?;
+                    Result::<_, Error>::Ok(scan_cursor_check(&enc_category, filter_str.as_deref()))
+                }
+            })
+            .await?;
+            let last_id = match cursor {
+                Some(cursor) if cursor.check() != check.as_slice() => {
+                    return Err(err_msg!(
+                        Input,
+                        "Scan cursor does not match the store, profile, category or tag filter"
+                    ));
+                }
+                Some(cursor) => cursor.last_id(),
+                None => 0,
+            };
             let scan = perform_scan(
                 active,
                 profile_id,
                 key.clone(),
                 kind,
                 category.clone(),
+                None,
                 tag_filter,
+                Some(last_id),
+                None,
                 None,
-                limit,
             );
-            pin!(scan);
-            let mut enc_rows = vec![];
-            loop {
-                if let Some(rows) = scan.try_next().await? {
-                    enc_rows.extend(rows)
-                } else {
-                    break;
-                }
-            }
-            unblock(move || decrypt_scan_batch(category, enc_rows, &key)).await
-        })
+            let settings = self.settings.clone();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blobs.clone();
+            let stream = scan.then(move |enc_rows| {
+                let category = category.clone();
+                let key = key.clone();
+                let settings = settings.clone();
+                #[cfg(feature = "blob_store")]
+                let blobs = blobs.clone();
+                unblock(move || {
+                    #[cfg(feature = "blob_store")]
+                    let enc_rows = match blobs {
+                        Some(blobs) => blobs.resolve_rows(enc_rows?),
+                        None => enc_rows,
+                    };
+                    decrypt_scan_batch_with_ids(category, enc_rows?, &key, &settings)
+                })
+            });
+            let scan = Scan::new(stream, PAGE_SIZE).tracked(&self.settings.scans)?;
+            Ok(ResumableScan::new(scan, profile, check, last_id))
+        });
+        instrument(metrics, "scan_resumable", |_| None, fut)
     }
 
-    fn remove_all<'q>(
-        &'q mut self,
-        kind: EntryKind,
-        category: &'q str,
-        tag_filter: Option<TagFilter>,
-    ) -> BoxFuture<'q, Result<i64, Error>> {
-        let category = ProfileKey::prepare_input(category.as_bytes());
-
+    fn enable_name_index(&self) -> BoxFuture<'_, Result<(), Error>> {
         Box::pin(async move {
-            let (profile_id, key) = acquire_key(&mut *self).await?;
-            let mut params = QueryParams::new();
-            params.push(profile_id);
-            params.push(kind as i16);
-            let (enc_category, tag_filter) = unblock({
-                let params_len = params.len() + 1; // plus category
-                move || {
-                    Result::<_, Error>::Ok((
-                        key.encrypt_entry_category(category)?,
-                        encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?,
-                    ))
-                }
-            })
-            .await?;
-            params.push(enc_category);
-            let query =
-                extend_query::<SqliteStore>(DELETE_ALL_QUERY, &mut params, tag_filter, None, None)?;
+            let mut txn = self.conn_pool.begin().await?;
+            sqlx::query(NAME_INDEX_SCHEMA).execute(&mut txn).await?;
+            self.backfill_index(&mut txn, NAME_INDEX).await?;
+            txn.commit().await?;
+            self.settings.set_name_index(true);
+            Ok(())
+        })
+    }
 
-            let mut active = acquire_session(&mut *self).await?;
-            let removed = sqlx::query_with(query.as_str(), params)
-                .execute(active.connection_mut())
-                .await?
-                .rows_affected();
-            Ok(removed as i64)
+    fn enable_category_index(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let mut txn = self.conn_pool.begin().await?;
+            sqlx::query(CATEGORY_INDEX_SCHEMA).execute(&mut txn).await?;
+            self.backfill_index(&mut txn, CATEGORY_INDEX).await?;
+            txn.commit().await?;
+            self.settings.set_category_index(true);
+            Ok(())
         })
     }
 
-    fn update<'q>(
-        &'q mut self,
-        kind: EntryKind,
-        operation: EntryOperation,
-        category: &'q str,
-        name: &'q str,
-        value: Option<&'q [u8]>,
-        tags: Option<&'q [EntryTag]>,
-        expiry_ms: Option<i64>,
-    ) -> BoxFuture<'q, Result<(), Error>> {
-        let category = ProfileKey::prepare_input(category.as_bytes());
-        let name = ProfileKey::prepare_input(name.as_bytes());
+    fn enable_access_tracking(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            sqlx::query(ACCESS_SCHEMA).execute(&self.conn_pool).await?;
+            self.settings.access_tracker.set_enabled(true);
+            Ok(())
+        })
+    }
 
-        match operation {
-            op @ EntryOperation::Insert | op @ EntryOperation::Replace => {
-                let value = ProfileKey::prepare_input(value.unwrap());
-                let tags = tags.map(prepare_tags);
-                Box::pin(async move {
-                    let (_, key) = acquire_key(&mut *self).await?;
-                    let (enc_category, enc_name, enc_value, enc_tags) = unblock(move || {
-                        let enc_value =
-                            key.encrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
-                        Result::<_, Error>::Ok((
-                            key.encrypt_entry_category(category)?,
-                            key.encrypt_entry_name(name)?,
-                            enc_value,
-                            tags.transpose()?
-                                .map(|t| key.encrypt_entry_tags(t))
-                                .transpose()?,
-                        ))
-                    })
-                    .await?;
-                    let mut active = acquire_session(&mut *self).await?;
-                    let mut txn = active.as_transaction().await?;
-                    if op == EntryOperation::Replace {
-                        perform_remove(&mut txn, kind, &enc_category, &enc_name, false).await?;
-                    }
-                    perform_insert(
-                        &mut txn,
-                        kind,
-                        &enc_category,
-                        &enc_name,
-                        &enc_value,
-                        enc_tags,
-                        expiry_ms,
-                    )
-                    .await?;
-                    txn.commit().await?;
-                    Ok(())
-                })
+    fn enable_value_history(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            #[cfg(feature = "blob_store")]
+            if self.blobs.is_some() {
+                return Err(err_msg!(
+                    Unsupported,
+                    "Value history is not supported with an external blob store"
+                ));
             }
+            sqlx::query(HISTORY_SCHEMA).execute(&self.conn_pool).await?;
+            self.settings.set_value_history(true);
+            Ok(())
+        })
+    }
 
-            EntryOperation::Remove => Box::pin(async move {
-                let (_, key) = acquire_key(&mut *self).await?;
-                let (enc_category, enc_name) = unblock(move || {
-                    Result::<_, Error>::Ok((
-                        key.encrypt_entry_category(category)?,
-                        key.encrypt_entry_name(name)?,
-                    ))
-                })
-                .await?;
-                let mut active = acquire_session(&mut *self).await?;
-                Ok(perform_remove(&mut active, kind, &enc_category, &enc_name, false).await?)
-            }),
-        }
+    fn enable_value_etags(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            // existing records are not updated: their entity tags are derived
+            // from the decrypted value when they are fetched
+            sqlx::query(ETAG_SCHEMA).execute(&self.conn_pool).await?;
+            self.settings.set_value_etags(true);
+            Ok(())
+        })
     }
 
-    fn close(self, commit: bool) -> BoxFuture<'static, Result<(), Error>> {
-        Box::pin(DbSession::close(self, commit))
+    fn enable_value_envelopes(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            // existing records are not rewritten, and remain readable
+            sqlx::query(
+                r#"INSERT OR REPLACE INTO config (name, value) VALUES ("envelope_version", ?1)"#,
+            )
+            .bind(ENVELOPE_VERSION.to_string())
+            .execute(&self.conn_pool)
+            .await?;
+            self.settings.set_value_envelope(true);
+            Ok(())
+        })
     }
-}
 
-impl ExtDatabase for Sqlite {
-    fn start_transaction(
-        conn: &mut PoolConnection<Self>,
-        nested: bool,
-    ) -> BoxFuture<'_, std::result::Result<(), SqlxError>> {
-        // FIXME - this is a horrible workaround because there is currently
-        // no good way to start an immediate transaction with sqlx. Without this
-        // adjustment, updates will run into 'database is locked' errors.
+    fn enable_access_log(&self) -> BoxFuture<'_, Result<(), Error>> {
         Box::pin(async move {
-            <Sqlite as Database>::TransactionManager::begin(&mut *conn).await?;
-            if !nested {
-                sqlx::query("ROLLBACK").execute(&mut *conn).await?;
-                sqlx::query("BEGIN IMMEDIATE").execute(conn).await?;
-            }
+            sqlx::query(ACCESS_LOG_SCHEMA)
+                .execute(&self.conn_pool)
+                .await?;
+            self.settings.set_access_log(true);
             Ok(())
         })
     }
-}
 
-async fn acquire_key(
-    session: &mut DbSession<Sqlite>,
-) -> Result<(ProfileId, Arc<ProfileKey>), Error> {
+    fn read_access_log(
+        &self,
+        limit: Option<i64>,
+        since: Option<i64>,
+    ) -> BoxFuture<'_, Result<Vec<AccessLogEntry>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let since = since
+                .map(|since| {
+                    chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, since)
+                        .single()
+                        .ok_or_else(|| err_msg!(Input, "Invalid access log timestamp"))
+                })
+                .transpose()?;
+            let mut conn = self.conn_pool.acquire().await?;
+            if sqlx::query_scalar::<_, i64>(ACCESS_LOG_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 0
+            {
+                return Ok(vec![]);
+            }
+            let rows = sqlx::query(ACCESS_LOG_FETCH_QUERY)
+                .bind(since)
+                .bind(limit.unwrap_or(-1))
+                .fetch_all(&mut conn)
+                .await?;
+            let mut entries = Vec::with_capacity(rows.len());
+            for row in rows {
+                entries.push(AccessLogEntry {
+                    id: row.try_get(0)?,
+                    logged_at: row.try_get::<Expiry, _>(1)?.timestamp_millis(),
+                    event: AccessEvent::parse(row.try_get(2)?)?,
+                    client: row.try_get(3)?,
+                    profile: row.try_get(4)?,
+                    success: row.try_get(5)?,
+                });
+            }
+            Ok(entries)
+        });
+        instrument(
+            metrics,
+            "read_access_log",
+            |entries| Some(entries.len() as i64),
+            fut,
+        )
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        self.settings.name_normalization()
+    }
+
+    fn default_expiry_filter(&self) -> ExpiryFilter {
+        self.settings.expiry_filter
+    }
+
+    fn default_query_hint(&self) -> Option<QueryHint> {
+        self.settings.query_hint
+    }
+
+    fn scan_coldest(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<Vec<EntryAccess>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            if !self.settings.access_tracker.enabled() {
+                return Err(err_msg!(
+                    Unsupported,
+                    "Access tracking is not enabled for this store"
+                ));
+            }
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let enc_category = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(category.as_bytes());
+                move || key.encrypt_entry_category(category)
+            })
+            .await?;
+            let mut active = acquire_session(&mut session).await?;
+            let rows = sqlx::query(SCAN_COLDEST_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .bind(enc_category)
+                .bind(limit)
+                .fetch_all(active.connection_mut())
+                .await?;
+            drop(active);
+            let mut enc_rows = Vec::with_capacity(rows.len());
+            let mut counters = Vec::with_capacity(rows.len());
+            for row in rows {
+                enc_rows.push(EncScanEntry {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    value: row.try_get(2)?,
+                    value_type: row.try_get(4)?,
+                    tags: row.try_get(3)?,
+                    expiry: row_expiry(&row, 5)?,
+                });
+                counters.push((row.try_get(6)?, row_expiry(&row, 7)?));
+            }
+            let settings = self.settings.clone();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blobs.clone();
+            let entries = unblock(move || {
+                #[cfg(feature = "blob_store")]
+                let enc_rows = match blobs.as_ref() {
+                    Some(blobs) => blobs.resolve_rows(enc_rows)?,
+                    None => enc_rows,
+                };
+                decrypt_scan_batch(category, enc_rows, &key, &settings)
+            })
+            .await?;
+            Ok(entries
+                .into_iter()
+                .zip(counters)
+                .map(|(entry, (read_count, last_read_at))| EntryAccess {
+                    entry,
+                    read_count,
+                    last_read_at,
+                })
+                .collect())
+        });
+        instrument(
+            metrics,
+            "scan_coldest",
+            |entries| Some(entries.len() as i64),
+            fut,
+        )
+    }
+
+    fn tag_stats(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+    ) -> BoxFuture<'_, Result<Vec<TagStats>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let enc_category = unblock({
+                let key = key.clone();
+                move || key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))
+            })
+            .await?;
+            let mut active = acquire_session(&mut session).await?;
+            let rows = sqlx::query(TAG_STATS_QUERY)
+                .bind(profile_id)
+                .bind(kind as i16)
+                .bind(enc_category.as_slice())
+                .fetch_all(active.connection_mut())
+                .await?;
+            let mut enc_stats = Vec::with_capacity(rows.len());
+            for row in rows {
+                let enc_name: Vec<u8> = row.try_get(0)?;
+                let plaintext_count: i64 = row.try_get(2)?;
+                let distinct_values = if plaintext_count > 0 {
+                    Some(
+                        sqlx::query_scalar(TAG_DISTINCT_QUERY)
+                            .bind(profile_id)
+                            .bind(kind as i16)
+                            .bind(enc_category.as_slice())
+                            .bind(enc_name.as_slice())
+                            .bind(TAG_STATS_DISTINCT_LIMIT)
+                            .fetch_one(active.connection_mut())
+                            .await?,
+                    )
+                } else {
+                    None
+                };
+                enc_stats.push((
+                    enc_name,
+                    TagStats {
+                        name: String::new(),
+                        count: row.try_get(1)?,
+                        plaintext_count,
+                        distinct_values,
+                        avg_value_len: row.try_get(3)?,
+                    },
+                ));
+            }
+            drop(active);
+            unblock(move || {
+                enc_stats
+                    .into_iter()
+                    .map(|(enc_name, mut stats)| {
+                        stats.name = String::from_utf8(key.decrypt_tag_name(enc_name)?.into_vec())
+                            .map_err(err_map!(Unexpected, "Error decoding tag name"))?;
+                        Ok(stats)
+                    })
+                    .collect()
+            })
+            .await
+        });
+        instrument(metrics, "tag_stats", |_| None, fut)
+    }
+
+    fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        categories: Vec<String>,
+        tag_filter: Option<TagFilter>,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, Vec<Entry>>, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let categories = categories.into_iter().collect::<BTreeSet<_>>();
+            if categories.is_empty() {
+                return Ok(BTreeMap::new());
+            }
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let key = key.for_kind(kind);
+            let (categories, queries) = unblock({
+                let key = key.clone();
+                move || {
+                    let enc_categories = categories
+                        .iter()
+                        .map(|category| {
+                            key.encrypt_entry_category(ProfileKey::prepare_input(
+                                category.as_bytes(),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    let queries = grouped_scan_queries::<SqliteStore>(
+                        SCAN_GROUP_QUERY,
+                        profile_id,
+                        kind,
+                        enc_categories,
+                        tag_filter,
+                        &key,
+                        limit,
+                    )?;
+                    Result::<_, Error>::Ok((categories, queries))
+                }
+            })
+            .await?;
+            let mut groups = categories
+                .iter()
+                .map(|_| Vec::new())
+                .collect::<Vec<Vec<EncScanEntry>>>();
+            let mut active = acquire_session(&mut session).await?;
+            let query_timeout = active.query_timeout();
+            for (query, params) in queries {
+                let rows = run_query(
+                    query_timeout,
+                    sqlx::query_with(&*query, params).fetch_all(active.connection_mut()),
+                )
+                .await;
+                for row in active.check_timeout(rows)? {
+                    let grp: i64 = row.try_get(0)?;
+                    let group = groups
+                        .get_mut(grp as usize)
+                        .ok_or_else(|| err_msg!(Unexpected, "Invalid scan group"))?;
+                    group.push(EncScanEntry {
+                        id: row.try_get(1)?,
+                        name: row.try_get(2)?,
+                        value: row.try_get(3)?,
+                        value_type: row.try_get(5)?,
+                        tags: row.try_get(4)?,
+                        expiry: row_expiry(&row, 6)?,
+                    });
+                }
+            }
+            drop(active);
+            let settings = self.settings.clone();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blobs.clone();
+            unblock(move || {
+                categories
+                    .into_iter()
+                    .zip(groups)
+                    .map(|(category, enc_rows)| {
+                        #[cfg(feature = "blob_store")]
+                        let enc_rows = match blobs.as_ref() {
+                            Some(blobs) => blobs.resolve_rows(enc_rows)?,
+                            None => enc_rows,
+                        };
+                        let entries =
+                            decrypt_scan_batch(category.clone(), enc_rows, &key, &settings)?;
+                        Result::<_, Error>::Ok((category, entries))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, Error>>()
+            })
+            .await
+        });
+        instrument(
+            metrics,
+            "scan_grouped",
+            |groups| Some(groups.values().map(|g| g.len() as i64).sum()),
+            fut,
+        )
+    }
+
+    fn session(&self, profile: Option<String>, transaction: bool) -> Result<Self::Session, Error> {
+        let session = DbSession::new(
+            self.conn_pool.clone(),
+            self.key_cache.clone(),
+            profile.unwrap_or_else(|| self.default_profile.clone()),
+            self.settings.clone(),
+            transaction,
+        );
+        #[cfg(feature = "blob_store")]
+        let session = session.with_blob_store(self.blobs.clone());
+        Ok(session)
+    }
+
+    fn session_with_external_key(
+        &self,
+        profile: Option<String>,
+        store_key: StoreKey,
+        transaction: bool,
+    ) -> Result<Self::Session, Error> {
+        // the key cache is private to the session, so that the external key is
+        // not used by other sessions and the profile keys it unwraps are not shared
+        let session = DbSession::new(
+            self.conn_pool.clone(),
+            Arc::new(KeyCache::new(store_key)),
+            profile.unwrap_or_else(|| self.default_profile.clone()),
+            self.settings.clone(),
+            transaction,
+        );
+        #[cfg(feature = "blob_store")]
+        let session = session.with_blob_store(self.blobs.clone());
+        Ok(session)
+    }
+
+    fn purge_expired(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            #[cfg(feature = "blob_store")]
+            let blob_ids = if self.blobs.is_some() {
+                fetch_blob_ids(sqlx::query(BLOB_REF_EXPIRED_QUERY), &mut conn).await?
+            } else {
+                vec![]
+            };
+            let removed = sqlx::query(PURGE_EXPIRED_QUERY)
+                .execute(&mut conn)
+                .await
+                .map(Sqlite::exec_result)?
+                .rows_affected();
+            #[cfg(feature = "blob_store")]
+            if let Some(blobs) = self.blobs.as_ref() {
+                blobs.remove_all(blob_ids);
+            }
+            if sqlx::query_scalar::<_, i64>(BATCH_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 1
+            {
+                sqlx::query(BATCH_PURGE_QUERY)
+                    .bind(retention_modifier(self.settings.batch_retention()))
+                    .execute(&mut conn)
+                    .await?;
+            }
+            if sqlx::query_scalar::<_, i64>(HISTORY_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 1
+            {
+                sqlx::query(HISTORY_PURGE_QUERY)
+                    .bind(retention_modifier(self.settings.history_retention()))
+                    .bind(self.settings.history_versions() as i64)
+                    .execute(&mut conn)
+                    .await?;
+            }
+            if sqlx::query_scalar::<_, i64>(ACCESS_LOG_TABLE_QUERY)
+                .fetch_one(&mut conn)
+                .await?
+                == 1
+            {
+                let retention = chrono::Duration::from_std(self.settings.access_log_retention())
+                    .map_err(err_map!(Input, "Invalid access log retention"))?;
+                sqlx::query(ACCESS_LOG_PURGE_QUERY)
+                    .bind(chrono::Utc::now() - retention)
+                    .execute(&mut conn)
+                    .await?;
+            }
+            Ok(removed as i64)
+        });
+        instrument(metrics, "purge_expired", |removed| Some(*removed), fut)
+    }
+
+    fn verify_integrity(&self) -> BoxFuture<'_, Result<IntegrityReport, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let missing_keys = sqlx::query_as(MISSING_KEYS_QUERY)
+                .fetch_all(&mut conn)
+                .await?;
+            let orphaned_items = sqlx::query_as(ORPHANED_ITEMS_QUERY)
+                .fetch_all(&mut conn)
+                .await?;
+            let orphaned_tags = sqlx::query_scalar(ORPHANED_TAGS_QUERY)
+                .fetch_one(&mut conn)
+                .await?;
+            Ok(IntegrityReport {
+                missing_keys,
+                orphaned_items,
+                orphaned_tags,
+            })
+        });
+        instrument(metrics, "verify_integrity", |_| None, fut)
+    }
+
+    fn audit_decryption(
+        &self,
+        profile: Option<String>,
+        options: DecryptionAuditOptions,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<DecryptionAudit, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut session = self.read_session(profile);
+            let (profile_id, key) = acquire_key(&mut session).await?;
+            let enc_categories = if let Some(categories) = options.categories.clone() {
+                let key = key.clone();
+                Some(
+                    unblock(move || {
+                        categories
+                            .into_iter()
+                            .map(|category| {
+                                key.encrypt_entry_category(ProfileKey::prepare_input(
+                                    category.as_bytes(),
+                                ))
+                            })
+                            .collect::<Result<BTreeSet<_>, Error>>()
+                    })
+                    .await?,
+                )
+            } else {
+                None
+            };
+            let mut active = acquire_session(&mut session).await?;
+            let rows = sqlx::query(AUDIT_QUERY.as_str())
+                .bind(profile_id)
+                .bind(options.resume_after.unwrap_or(0))
+                .bind(limit)
+                .fetch_all(active.connection_mut())
+                .await?;
+            drop(active);
+            let scanned = rows.len() as i64;
+            let mut last_id = options.resume_after;
+            let mut enc_rows = vec![];
+            for row in rows {
+                let id: i64 = row.try_get(0)?;
+                last_id = Some(id);
+                let category: Vec<u8> = row.try_get(1)?;
+                if !options.sample(id)
+                    || !enc_categories
+                        .as_ref()
+                        .map_or(true, |cats| cats.contains(&category))
+                {
+                    continue;
+                }
+                enc_rows.push(EncAuditEntry {
+                    id,
+                    category,
+                    name: row.try_get(2)?,
+                    value: row.try_get(3)?,
+                    value_type: row.try_get(4)?,
+                    tags: row.try_get(5)?,
+                    kind: row.try_get(6)?,
+                });
+            }
+            let checked = enc_rows.len() as i64;
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blobs.clone();
+            let failures = unblock(move || {
+                enc_rows
+                    .into_iter()
+                    .filter_map(|mut enc_entry| {
+                        let id = enc_entry.id;
+                        #[cfg(feature = "blob_store")]
+                        if let Some(blobs) = blobs.as_ref() {
+                            match blobs.resolve(std::mem::take(&mut enc_entry.value)) {
+                                Ok(value) => enc_entry.value = value,
+                                Err(err) => return Some(DecryptionFailure::new(id, err)),
+                            }
+                        }
+                        audit_entry(&key, enc_entry)
+                            .err()
+                            .map(|err| DecryptionFailure::new(id, err))
+                    })
+                    .collect()
+            })
+            .await;
+            Ok(DecryptionAudit {
+                scanned,
+                checked,
+                failures,
+                last_id,
+                complete: scanned < limit,
+            })
+        });
+        instrument(
+            metrics,
+            "audit_decryption",
+            |report| Some(report.checked),
+            fut,
+        )
+    }
+
+    fn cleanup_orphans(&self) -> BoxFuture<'_, Result<i64, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            let removed = sqlx::query(ORPHANED_TAGS_DELETE_QUERY)
+                .execute(&mut conn)
+                .await
+                .map(Sqlite::exec_result)?
+                .rows_affected();
+            Ok(removed as i64)
+        });
+        instrument(metrics, "cleanup_orphans", |removed| Some(*removed), fut)
+    }
+
+    fn diagnostics(&self) -> BoxFuture<'_, Result<StoreDiagnostics, Error>> {
+        Box::pin(async move {
+            Ok(StoreDiagnostics {
+                active_scans: self.settings.scans.active(),
+                max_scans: self.settings.scans.max_active(),
+                pool_size: self.conn_pool.size(),
+                pool_idle: self.conn_pool.num_idle(),
+                read_pool_size: self.read_pool.as_ref().map(SqlitePool::size),
+                cached_profiles: self.key_cache.profile_count().await,
+            })
+        })
+    }
+
+    fn info(&self, refresh: bool) -> BoxFuture<'_, Result<StoreInfo, Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            if !refresh {
+                if let Some(info) = self.settings.info.get() {
+                    return Ok(info);
+                }
+            }
+            let mut conn = self.conn_pool.acquire().await?;
+            let mut schema_version: Option<String> = None;
+            let mut default_profile: Option<String> = None;
+            let mut store_key_ref: Option<String> = None;
+            let mut store_id: Option<String> = None;
+            let config = sqlx::query(INFO_CONFIG_QUERY).fetch_all(&mut conn).await?;
+            for row in config {
+                match row.try_get(0)? {
+                    "default_profile" => {
+                        default_profile.replace(row.try_get(1)?);
+                    }
+                    "key" => {
+                        store_key_ref.replace(row.try_get(1)?);
+                    }
+                    "store_id" => {
+                        store_id.replace(row.try_get(1)?);
+                    }
+                    "version" => {
+                        schema_version.replace(row.try_get(1)?);
+                    }
+                    _ => (),
+                }
+            }
+            let store_key_ref =
+                store_key_ref.ok_or_else(|| err_msg!(Unsupported, "Store key not found"))?;
+            let profile_count = sqlx::query_scalar("SELECT COUNT(*) FROM profiles")
+                .fetch_one(&mut conn)
+                .await?;
+            let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+                .fetch_one(&mut conn)
+                .await?;
+            let info = StoreInfo {
+                backend: "sqlite".to_string(),
+                schema_version: schema_version
+                    .ok_or_else(|| err_msg!(Unsupported, "Store version not found"))?,
+                // only the method is retained, not the derivation parameters
+                store_key_method: StoreKeyReference::parse_uri(&store_key_ref)?.method(),
+                encryption: PROFILE_KEY_ALG,
+                default_profile: default_profile
+                    .ok_or_else(|| err_msg!(Unsupported, "Default store profile not found"))?,
+                profile_count,
+                store_id: store_id.ok_or_else(|| err_msg!(Unsupported, "Store ID not found"))?,
+                journal_mode: Some(journal_mode.to_lowercase()),
+            };
+            self.settings.info.set(info.clone());
+            Ok(info)
+        });
+        instrument(metrics, "info", |_| None, fut)
+    }
+
+    fn ping(&self, deadline: Duration) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(run_probe(deadline, async move {
+            let mut conn = self.conn_pool.acquire().await?;
+            sqlx::query_scalar::<_, i64>("SELECT 1")
+                .fetch_one(&mut conn)
+                .await?;
+            Ok(())
+        }))
+    }
+
+    fn health(&self, deadline: Duration) -> BoxFuture<'_, HealthStatus> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = run_probe(deadline, async {
+                let mut conn = self.conn_pool.acquire().await?;
+                let version: Option<String> = sqlx::query_scalar(HEALTH_VERSION_QUERY)
+                    .fetch_optional(&mut conn)
+                    .await?;
+                if version.as_deref().and_then(|v| v.parse().ok()) != Some(SCHEMA_VERSION) {
+                    return Err(err_msg!(
+                        Unsupported,
+                        "Unexpected store version: {}",
+                        version.as_deref().unwrap_or("none")
+                    ));
+                }
+                let row = sqlx::query(HEALTH_PROFILE_QUERY)
+                    .bind(&self.default_profile)
+                    .fetch_optional(&mut conn)
+                    .await?
+                    .ok_or_else(|| err_msg!(NotFound, "Default store profile not found"))?;
+                drop(conn);
+                // a profile protected by a passphrase is not unwrapped by the store key
+                if row.try_get::<Option<String>, _>(2)?.is_none() {
+                    self.key_cache
+                        .load_key(row.try_get(0)?, row.try_get(1)?)
+                        .await?;
+                }
+                Ok(())
+            })
+            .await;
+            health_status(result, start.elapsed(), deadline, &self.settings.scans)
+        })
+    }
+
+    fn checkpoint(&self) -> BoxFuture<'_, Result<(), Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            // has no effect unless the database is in write-ahead logging mode
+            sqlx::query("PRAGMA wal_checkpoint(PASSIVE)")
+                .execute(&self.conn_pool)
+                .await?;
+            Ok(())
+        });
+        instrument(metrics, "checkpoint", |_| None, fut)
+    }
+
+    fn vacuum(&self) -> BoxFuture<'_, Result<(), Error>> {
+        let metrics = self.settings.metrics.clone();
+        let fut = Box::pin(async move {
+            sqlx::query("VACUUM").execute(&self.conn_pool).await?;
+            Ok(())
+        });
+        instrument(metrics, "vacuum", |_| None, fut)
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.log_access(AccessEvent::Close, None, true).await;
+            if let Some(read_pool) = self.read_pool.as_ref() {
+                read_pool.close().await;
+            }
+            self.conn_pool.close().await;
+            Ok(())
+        })
+    }
+}
+
+impl QueryBackend for DbSession<Sqlite> {
+    fn count<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<i64, Error>> {
+        let category = ProfileKey::prepare_input(category.as_bytes());
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let mut params = QueryParams::with_capacity(3);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, tag_filter) = unblock({
+                let params_len = params.len() + 1; // plus category
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            params.push(enc_category);
+            let query = expiry_query::<SqliteStore>(
+                extend_query::<SqliteStore>(
+                    COUNT_QUERY.as_str(),
+                    &mut params,
+                    tag_filter,
+                    self.query_hint(),
+                    None,
+                    None,
+                )?,
+                self.expiry_filter(),
+            );
+            let mut active = acquire_session(&mut *self).await?;
+            let query_timeout = active.query_timeout();
+            let count = run_query(
+                query_timeout,
+                sqlx::query_scalar_with(&*query, params).fetch_one(active.connection_mut()),
+            )
+            .await;
+            active.check_timeout(count)
+        });
+        instrument(metrics, "count", |count| Some(*count), fut)
+    }
+
+    fn fetch(
+        &mut self,
+        kind: EntryKind,
+        category: &[u8],
+        name: &[u8],
+        _for_update: bool,
+    ) -> BoxFuture<'_, Result<Option<Entry>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let (enc_category, enc_name) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                    ))
+                }
+            })
+            .await?;
+            // a fetch outside of a transaction may be answered from the record
+            // cache, or share the result of an identical fetch in flight
+            let cache = match settings.entry_cache.as_ref() {
+                Some(cache)
+                    if !self.is_transaction() && self.expiry_filter() == ExpiryFilter::Exclude =>
+                {
+                    let cache_key = (profile_id, kind, enc_category.clone(), enc_name.clone());
+                    if let Some(entry) = cache.get(&cache_key) {
+                        settings.record_cache(true);
+                        return Ok(Some(entry));
+                    }
+                    settings.record_cache(false);
+                    Some((cache.clone(), cache_key, cache.generation()))
+                }
+                _ => None,
+            };
+            let flight = match settings.fetch_coalescer.as_ref() {
+                Some(coalescer) if !self.is_transaction() => coalescer.join((
+                    profile_id,
+                    kind,
+                    enc_category.clone(),
+                    enc_name.clone(),
+                    self.expiry_filter(),
+                )),
+                _ => FetchFlight::Bypass,
+            };
+            if let Some(result) = flight.shared_result().await {
+                return Ok(result);
+            }
+            let query =
+                expiry_query::<SqliteStore>(FETCH_QUERY.as_str().into(), self.expiry_filter());
+            let access_pool = if settings.access_tracker.enabled() {
+                Some(self.pool().clone())
+            } else {
+                None
+            };
+            let mut active = acquire_session(&mut *self).await?;
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query(&*query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_optional(active.connection_mut()),
+            )
+            .await;
+            let result = if let Some(row) = active.check_timeout(row)? {
+                if let Some(pool) = access_pool {
+                    let item_id = row.try_get(0)?;
+                    if let Some(reads) = settings.access_tracker.record_read(item_id) {
+                        record_access(pool, item_id, reads);
+                    }
+                }
+                Some(
+                    decrypt_fetch_row(
+                        row,
+                        key,
+                        settings,
+                        #[cfg(feature = "blob_store")]
+                        blobs,
+                        category,
+                        name,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            flight.complete(&result);
+            if let (Some((cache, cache_key, generation)), Some(entry)) = (cache, result.as_ref()) {
+                cache.insert(cache_key, entry.clone(), generation);
+            }
+            Ok(result)
+        });
+        instrument(metrics, "fetch", |entry| Some(entry.is_some() as i64), fut)
+    }
+
+    fn fetch_conditional<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        etag: Option<&'q [u8]>,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<ConditionalFetch>, Error>> {
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            if let Some(etag) = etag.filter(|_| self.settings().value_etags()) {
+                // compare the stored entity tag, avoiding the decryption of
+                // an unchanged value
+                let (enc_category, enc_name) = unblock({
+                    let key = key.clone();
+                    let category = ProfileKey::prepare_input(category);
+                    let name = ProfileKey::prepare_input(name);
+                    move || {
+                        Result::<_, Error>::Ok((
+                            key.encrypt_entry_category(category)?,
+                            key.encrypt_entry_name(name)?,
+                        ))
+                    }
+                })
+                .await?;
+                let query = expiry_query::<SqliteStore>(
+                    ETAG_FETCH_QUERY.as_str().into(),
+                    self.expiry_filter(),
+                );
+                let mut active = acquire_session(&mut *self).await?;
+                let query_timeout = active.query_timeout();
+                let stored = run_query(
+                    query_timeout,
+                    sqlx::query_scalar::<_, Option<Vec<u8>>>(&*query)
+                        .bind(profile_id)
+                        .bind(kind as i16)
+                        .bind(enc_category)
+                        .bind(enc_name)
+                        .fetch_optional(active.connection_mut()),
+                )
+                .await;
+                let stored = active.check_timeout(stored)?.flatten();
+                if stored.as_deref() == Some(etag) {
+                    return Ok(Some(ConditionalFetch::NotModified));
+                }
+            }
+            let entry = match self.fetch(kind, category, name, for_update).await? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            let category = category.to_vec();
+            let name = name.to_vec();
+            let (entry, current) = unblock(move || {
+                let current = key.entry_value_hash(&category, &name, entry.value.as_ref())?;
+                Result::<_, Error>::Ok((entry, current))
+            })
+            .await?;
+            if etag == Some(current.as_slice()) {
+                Ok(Some(ConditionalFetch::NotModified))
+            } else {
+                Ok(Some(ConditionalFetch::Modified {
+                    entry,
+                    etag: current,
+                }))
+            }
+        });
+        instrument(
+            metrics,
+            "fetch_conditional",
+            |result| Some(matches!(result, Some(ConditionalFetch::Modified { .. })) as i64),
+            fut,
+        )
+    }
+
+    fn fetch_filtered<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_filter: TagFilter,
+        _for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<FilteredFetch>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let mut params = QueryParams::with_capacity(4);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, enc_name, tag_filter) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
+                let params_len = params.len() + 2; // plus category and name
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                        encode_tag_filter::<SqliteStore>(Some(tag_filter), &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            params.push(enc_category.clone());
+            params.push(enc_name.clone());
+            let query = expiry_query::<SqliteStore>(
+                extend_query::<SqliteStore>(
+                    FETCH_QUERY.as_str(),
+                    &mut params,
+                    tag_filter,
+                    self.query_hint(),
+                    None,
+                    None,
+                )?,
+                self.expiry_filter(),
+            );
+            let exists_query = expiry_query::<SqliteStore>(
+                FETCH_EXISTS_QUERY.as_str().into(),
+                self.expiry_filter(),
+            );
+            let mut active = acquire_session(&mut *self).await?;
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query_with(&*query, params).fetch_optional(active.connection_mut()),
+            )
+            .await;
+            if let Some(row) = active.check_timeout(row)? {
+                drop(active);
+                let entry = decrypt_fetch_row(
+                    row,
+                    key,
+                    settings,
+                    #[cfg(feature = "blob_store")]
+                    blobs,
+                    category,
+                    name,
+                )
+                .await?;
+                return Ok(Some(FilteredFetch::Matched(entry)));
+            }
+            // distinguish a record which does not match from a missing record
+            let count = run_query(
+                query_timeout,
+                sqlx::query_scalar::<_, i64>(&*exists_query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_one(active.connection_mut()),
+            )
+            .await;
+            let count = active.check_timeout(count)?;
+            Ok(if count > 0 {
+                Some(FilteredFetch::Filtered)
+            } else {
+                None
+            })
+        });
+        instrument(
+            metrics,
+            "fetch_filtered",
+            |result| Some(matches!(result, Some(FilteredFetch::Matched(_))) as i64),
+            fut,
+        )
+    }
+
+    fn fetch_with_tags<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_mode: TagRetrieval,
+        _for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<TaggedEntry>, Error>> {
+        let category = category.to_vec();
+        let name = name.to_vec();
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let (enc_category, enc_name) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(&category);
+                let name = ProfileKey::prepare_input(&name);
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                    ))
+                }
+            })
+            .await?;
+            let query = expiry_query::<SqliteStore>(
+                fetch_tags_query(tag_mode).into(),
+                self.expiry_filter(),
+            );
+            let mut active = acquire_session(&mut *self).await?;
+            let query_timeout = active.query_timeout();
+            let row = run_query(
+                query_timeout,
+                sqlx::query(&*query)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .fetch_optional(active.connection_mut()),
+            )
+            .await;
+            if let Some(row) = active.check_timeout(row)? {
+                drop(active);
+                Ok(Some(
+                    decrypt_tagged_row(
+                        row,
+                        key,
+                        settings,
+                        #[cfg(feature = "blob_store")]
+                        blobs,
+                        category,
+                        name,
+                        tag_mode,
+                    )
+                    .await?,
+                ))
+            } else {
+                Ok(None)
+            }
+        });
+        instrument(
+            metrics,
+            "fetch_with_tags",
+            |result| Some(result.is_some() as i64),
+            fut,
+        )
+    }
+
+    fn fetch_history(
+        &mut self,
+        kind: EntryKind,
+        category: &str,
+        name: &str,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Vec<EntryVersion>, Error>> {
+        let category = category.to_string();
+        let name = name.to_string();
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (_, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let (enc_category, enc_name) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(category.as_bytes());
+                let name = ProfileKey::prepare_input(name.as_bytes());
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                    ))
+                }
+            })
+            .await?;
+            let mut active = acquire_session(&mut *self).await?;
+            if sqlx::query_scalar::<_, i64>(HISTORY_TABLE_QUERY)
+                .fetch_one(active.connection_mut())
+                .await?
+                == 0
+            {
+                return Ok(vec![]);
+            }
+            let query_timeout = active.query_timeout();
+            let profile_id = active.profile_id;
+            let rows = run_query(
+                query_timeout,
+                sqlx::query(HISTORY_FETCH_QUERY)
+                    .bind(profile_id)
+                    .bind(kind as i16)
+                    .bind(enc_category)
+                    .bind(enc_name)
+                    .bind(limit.unwrap_or(-1))
+                    .fetch_all(active.connection_mut()),
+            )
+            .await;
+            let mut enc_versions = vec![];
+            for row in active.check_timeout(rows)? {
+                enc_versions.push((
+                    row.try_get::<Vec<u8>, _>(0)?,
+                    row.try_get::<Option<Vec<u8>>, _>(1)?,
+                    row.try_get::<Expiry, _>(2)?.timestamp_millis(),
+                ));
+            }
+            unblock(move || {
+                let mut versions = Vec::with_capacity(enc_versions.len());
+                for (value, value_type, replaced_at) in enc_versions {
+                    let value = key.decrypt_entry_value(category.as_ref(), name.as_ref(), value)?;
+                    let value_type =
+                        decrypt_value_type(&key, category.as_bytes(), name.as_bytes(), value_type)?;
+                    versions.push(EntryVersion {
+                        value,
+                        value_type,
+                        replaced_at,
+                    });
+                }
+                Result::<_, Error>::Ok(versions)
+            })
+            .await
+        });
+        instrument(
+            metrics,
+            "fetch_history",
+            |versions| Some(versions.len() as i64),
+            fut,
+        )
+    }
+
+    fn fetch_all<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        tag_filter: Option<TagFilter>,
+        limit: Option<i64>,
+        _for_update: bool,
+    ) -> BoxFuture<'q, Result<Vec<Entry>, Error>> {
+        let category = category.to_string();
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let settings = self.settings();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let mut active = self.borrow_mut();
+            let (profile_id, key) = acquire_key(&mut *active).await?;
+            let key = key.for_kind(kind);
+            let scan = perform_scan(
+                active,
+                profile_id,
+                key.clone(),
+                kind,
+                category.clone(),
+                None,
+                tag_filter,
+                None,
+                None,
+                limit,
+            );
+            pin!(scan);
+            let mut enc_rows = vec![];
+            loop {
+                if let Some(rows) = scan.try_next().await? {
+                    enc_rows.extend(rows)
+                } else {
+                    break;
+                }
+            }
+            unblock(move || {
+                #[cfg(feature = "blob_store")]
+                let enc_rows = match blobs {
+                    Some(blobs) => blobs.resolve_rows(enc_rows)?,
+                    None => enc_rows,
+                };
+                decrypt_scan_batch(category, enc_rows, &key, &settings)
+            })
+            .await
+        });
+        instrument(
+            metrics,
+            "fetch_all",
+            |entries| Some(entries.len() as i64),
+            fut,
+        )
+    }
+
+    fn remove_all<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<i64, Error>> {
+        let category = ProfileKey::prepare_input(category.as_bytes());
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let mut params = QueryParams::with_capacity(3);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, tag_filter) = unblock({
+                let params_len = params.len() + 1; // plus category
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            #[cfg(feature = "blob_store")]
+            let blob_query = if self.blob_store().is_some() {
+                let mut blob_params = QueryParams::with_capacity(3);
+                blob_params.push(profile_id);
+                blob_params.push(kind as i16);
+                blob_params.push(enc_category.clone());
+                let blob_query = extend_query::<SqliteStore>(
+                    BLOB_REF_ALL_QUERY,
+                    &mut blob_params,
+                    tag_filter.clone(),
+                    self.query_hint(),
+                    None,
+                    None,
+                )?;
+                Some((blob_query, blob_params))
+            } else {
+                None
+            };
+            params.push(enc_category);
+            let query = extend_query::<SqliteStore>(
+                DELETE_ALL_QUERY.as_str(),
+                &mut params,
+                tag_filter,
+                self.query_hint(),
+                None,
+                None,
+            )?;
+
+            let mut active = acquire_session(&mut *self).await?;
+            #[cfg(feature = "blob_store")]
+            let blob_ids = if let Some((blob_query, blob_params)) = blob_query {
+                fetch_blob_ids(
+                    sqlx::query_with(&*blob_query, blob_params),
+                    active.connection_mut(),
+                )
+                .await?
+            } else {
+                vec![]
+            };
+            let query_timeout = active.query_timeout();
+            let removed = run_query(
+                query_timeout,
+                sqlx::query_with(&*query, params).execute(active.connection_mut()),
+            )
+            .await;
+            let removed = active
+                .check_timeout(removed)
+                .map(Sqlite::exec_result)?
+                .rows_affected();
+            if removed > 0 {
+                active.invalidate_cached(None);
+            }
+            #[cfg(feature = "blob_store")]
+            {
+                drop(active);
+                self.track_blobs(None, blob_ids);
+            }
+            Ok(removed as i64)
+        });
+        instrument(metrics, "remove_all", |removed| Some(*removed), fut)
+    }
+
+    fn take<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: Option<&'q str>,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<Option<Entry>, Error>> {
+        let category = category.to_string();
+        let name = name.map(|name| ProfileKey::prepare_input(name.as_bytes()));
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let key = key.for_kind(kind);
+            let settings = self.settings();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let mut params = QueryParams::with_capacity(4);
+            params.push(profile_id);
+            params.push(kind as i16);
+            let (enc_category, enc_name, tag_filter) = unblock({
+                let key = key.clone();
+                let category = ProfileKey::prepare_input(category.as_bytes());
+                let params_len = params.len() + 1; // plus category
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        name.map(|name| key.encrypt_entry_name(name)).transpose()?,
+                        encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?,
+                    ))
+                }
+            })
+            .await?;
+            params.push(enc_category.clone());
+            let mut query = extend_query::<SqliteStore>(
+                SCAN_QUERY.as_str(),
+                &mut params,
+                tag_filter,
+                self.query_hint(),
+                None,
+                None,
+            )?;
+            if let Some(enc_name) = enc_name {
+                query
+                    .to_mut()
+                    .push_str(&replace_arg_placeholders::<SqliteStore>(
+                        " AND i.name = $$",
+                        (params.len() as i64) + 1,
+                    ));
+                params.push(enc_name);
+            }
+            query.to_mut().push_str(" ORDER BY i.id LIMIT 1");
+
+            let mut active = acquire_session(&mut *self).await?;
+            let mut txn = active.as_transaction().await?;
+            let row = if let Some(row) = sqlx::query_with(&*query, params)
+                .fetch_optional(txn.connection_mut())
+                .await?
+            {
+                row
+            } else {
+                txn.commit().await?;
+                return Ok(None);
+            };
+            let item_id: i64 = row.try_get(0)?;
+            let enc_name: Vec<u8> = row.try_get(1)?;
+            let enc_row = EncScanEntry {
+                id: item_id,
+                name: enc_name.clone(),
+                value: row.try_get(2)?,
+                value_type: row.try_get(4)?,
+                tags: row.try_get(3)?,
+                expiry: row_expiry(&row, 5)?,
+            };
+            #[cfg(feature = "blob_store")]
+            let blob_removed = blob_id(&enc_row.value)
+                .map(str::to_string)
+                .into_iter()
+                .collect::<Vec<_>>();
+            let entry = unblock(move || {
+                let enc_rows = vec![enc_row];
+                #[cfg(feature = "blob_store")]
+                let enc_rows = match blobs {
+                    Some(blobs) => blobs.resolve_rows(enc_rows)?,
+                    None => enc_rows,
+                };
+                decrypt_scan_batch(category, enc_rows, &key, &settings)
+            })
+            .await?
+            .pop();
+            sqlx::query(DELETE_ID_QUERY)
+                .bind(item_id)
+                .execute(txn.connection_mut())
+                .await?;
+            txn.commit().await?;
+            let profile_id = active.profile_id;
+            active.invalidate_cached(Some((profile_id, kind, enc_category, enc_name)));
+            #[cfg(feature = "blob_store")]
+            {
+                drop(active);
+                self.track_blobs(None, blob_removed);
+            }
+            Ok(entry)
+        });
+        instrument(metrics, "take", |entry| Some(entry.is_some() as i64), fut)
+    }
+
+    fn update<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        operation: EntryOperation,
+        category: &'q [u8],
+        name: &'q [u8],
+        value: Option<&'q [u8]>,
+        value_type: Option<&'q str>,
+        tags: Option<&'q [EntryTag]>,
+        expiry: ExpiryUpdate,
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        let category = ProfileKey::prepare_input(category);
+        let name = ProfileKey::prepare_input(name);
+
+        let op_name = match operation {
+            EntryOperation::Insert => "insert",
+            EntryOperation::Replace => "replace",
+            EntryOperation::Remove => "remove",
+        };
+        let metrics = self.metrics();
+        let fut: BoxFuture<'q, Result<(), Error>> = match operation {
+            op @ EntryOperation::Insert | op @ EntryOperation::Replace => {
+                let value = ProfileKey::prepare_input(value.unwrap());
+                let value_type = value_type.map(|vt| ProfileKey::prepare_input(vt.as_bytes()));
+                let tags = tags.map(prepare_tags);
+                Box::pin(async move {
+                    check_entry_key(category.as_ref(), name.as_ref())?;
+                    let (_, key) = acquire_key(&mut *self).await?;
+                    let settings = self.settings();
+                    let key = key.for_kind(kind);
+                    let envelope = settings.value_envelope();
+                    #[cfg(feature = "blob_store")]
+                    let blobs = self.blob_store();
+                    let history = settings.value_history();
+                    let value_etags = settings.value_etags();
+                    let index_input = (key.clone(), category.clone(), name.clone());
+                    let (enc_category, enc_name, enc_value, enc_value_type, enc_tags, etag) =
+                        unblock(move || {
+                            let etag = if value_etags {
+                                Some(key.entry_value_hash(
+                                    category.as_ref(),
+                                    name.as_ref(),
+                                    value.as_ref(),
+                                )?)
+                            } else {
+                                None
+                            };
+                            let enc_value = key.encrypt_entry_value(
+                                category.as_ref(),
+                                name.as_ref(),
+                                value,
+                                envelope,
+                            )?;
+                            let enc_value_type = encrypt_value_type(
+                                &key,
+                                category.as_ref(),
+                                name.as_ref(),
+                                value_type,
+                                envelope,
+                            )?;
+                            Result::<_, Error>::Ok((
+                                key.encrypt_entry_category(category)?,
+                                key.encrypt_entry_name(name)?,
+                                enc_value,
+                                enc_value_type,
+                                tags.transpose()?
+                                    .map(|t| key.encrypt_entry_tags(t))
+                                    .transpose()?,
+                                etag,
+                            ))
+                        })
+                        .await?;
+                    #[cfg(feature = "blob_store")]
+                    let (enc_value, pending_blob) = if let Some(blobs) = blobs.as_ref() {
+                        let (enc_value, blob_id) = unblock({
+                            let blobs = blobs.clone();
+                            move || blobs.store(enc_value)
+                        })
+                        .await?;
+                        (
+                            enc_value,
+                            blob_id.map(|id| PendingBlob::new(blobs.clone(), id)),
+                        )
+                    } else {
+                        (enc_value, None)
+                    };
+                    let mut active = acquire_session(&mut *self).await?;
+                    let mut txn = active.as_transaction().await?;
+                    let (name_index, category_index) =
+                        load_index_settings(&mut txn, &settings).await?;
+                    let (key, category, name) = index_input;
+                    let (enc_prefixes, enc_category_prefixes) = encode_index_prefixes(
+                        &key,
+                        category.as_ref(),
+                        name.as_ref(),
+                        name_index,
+                        category_index,
+                    )?;
+                    #[cfg(feature = "blob_store")]
+                    let blob_removed = if op == EntryOperation::Replace && blobs.is_some() {
+                        fetch_blob_ids(
+                            sqlx::query(BLOB_REF_QUERY)
+                                .bind(txn.profile_id)
+                                .bind(kind as i16)
+                                .bind(enc_category.as_slice())
+                                .bind(enc_name.as_slice()),
+                            txn.connection_mut(),
+                        )
+                        .await?
+                    } else {
+                        vec![]
+                    };
+                    let expiry = match expiry {
+                        ExpiryUpdate::Set(expiry_ms) => Some(expiry_timestamp(expiry_ms)?),
+                        ExpiryUpdate::Clear => None,
+                        ExpiryUpdate::Keep if op == EntryOperation::Replace => {
+                            sqlx::query_scalar::<_, Option<Expiry>>(EXPIRY_FETCH_QUERY)
+                                .bind(txn.profile_id)
+                                .bind(kind as i16)
+                                .bind(enc_category.as_slice())
+                                .bind(enc_name.as_slice())
+                                .fetch_optional(txn.connection_mut())
+                                .await?
+                                .flatten()
+                        }
+                        ExpiryUpdate::Keep => None,
+                    };
+                    if op == EntryOperation::Replace {
+                        perform_remove(&mut txn, kind, &enc_category, &enc_name, false, history)
+                            .await?;
+                    }
+                    perform_insert(
+                        &mut txn,
+                        kind,
+                        &enc_category,
+                        &enc_name,
+                        &enc_value,
+                        enc_value_type.as_deref(),
+                        enc_tags,
+                        enc_prefixes,
+                        enc_category_prefixes,
+                        etag,
+                        expiry,
+                    )
+                    .await?;
+                    if let Err(err) = check_profile_quota(&mut txn).await {
+                        txn.rollback().await?;
+                        return Err(err);
+                    }
+                    txn.commit_operation(op_name).await?;
+                    let profile_id = active.profile_id;
+                    active.invalidate_cached(Some((profile_id, kind, enc_category, enc_name)));
+                    #[cfg(feature = "blob_store")]
+                    {
+                        drop(active);
+                        self.track_blobs(pending_blob.map(PendingBlob::keep), blob_removed);
+                    }
+                    Ok(())
+                })
+            }
+
+            EntryOperation::Remove => Box::pin(async move {
+                let (_, key) = acquire_key(&mut *self).await?;
+                let key = key.for_kind(kind);
+                #[cfg(feature = "blob_store")]
+                let has_blobs = self.blob_store().is_some();
+                let history = self.settings().value_history();
+                let (enc_category, enc_name) = unblock(move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                    ))
+                })
+                .await?;
+                let mut active = acquire_session(&mut *self).await?;
+                #[cfg(feature = "blob_store")]
+                let blob_removed = if has_blobs {
+                    fetch_blob_ids(
+                        sqlx::query(BLOB_REF_QUERY)
+                            .bind(active.profile_id)
+                            .bind(kind as i16)
+                            .bind(enc_category.as_slice())
+                            .bind(enc_name.as_slice()),
+                        active.connection_mut(),
+                    )
+                    .await?
+                } else {
+                    vec![]
+                };
+                if history {
+                    // the prior value is recorded along with the removal
+                    let mut txn = active.as_transaction().await?;
+                    perform_remove(&mut txn, kind, &enc_category, &enc_name, false, true).await?;
+                    txn.commit_operation(op_name).await?;
+                } else {
+                    perform_remove(&mut active, kind, &enc_category, &enc_name, false, false)
+                        .await?;
+                }
+                let profile_id = active.profile_id;
+                active.invalidate_cached(Some((profile_id, kind, enc_category, enc_name)));
+                #[cfg(feature = "blob_store")]
+                {
+                    drop(active);
+                    self.track_blobs(None, blob_removed);
+                }
+                Ok(())
+            }),
+        };
+        instrument(metrics, op_name, |_| Some(1), fut)
+    }
+
+    fn rename<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        new_category: &'q [u8],
+        new_name: &'q [u8],
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        let category = ProfileKey::prepare_input(category);
+        let name = ProfileKey::prepare_input(name);
+        let new_category = ProfileKey::prepare_input(new_category);
+        let new_name = ProfileKey::prepare_input(new_name);
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            check_entry_key(new_category.as_ref(), new_name.as_ref())?;
+            let (_, key) = acquire_key(&mut *self).await?;
+            let settings = self.settings();
+            let key = key.for_kind(kind);
+            let envelope = settings.value_envelope();
+            #[cfg(feature = "blob_store")]
+            let blobs = self.blob_store();
+            let value_etags = settings.value_etags();
+            let (enc_category, enc_name, enc_new_category, enc_new_name) = unblock({
+                let key = key.clone();
+                let (category, name) = (category.clone(), name.clone());
+                let (new_category, new_name) = (new_category.clone(), new_name.clone());
+                move || {
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_category(category)?,
+                        key.encrypt_entry_name(name)?,
+                        key.encrypt_entry_category(new_category)?,
+                        key.encrypt_entry_name(new_name)?,
+                    ))
+                }
+            })
+            .await?;
+            let mut active = acquire_session(&mut *self).await?;
+            let mut txn = active.as_transaction().await?;
+            let (name_index, category_index) = load_index_settings(&mut txn, &settings).await?;
+            let row = sqlx::query(RENAME_FETCH_QUERY)
+                .bind(txn.profile_id)
+                .bind(kind as i16)
+                .bind(enc_category.as_slice())
+                .bind(enc_name.as_slice())
+                .fetch_optional(txn.connection_mut())
+                .await?
+                .ok_or_else(|| err_msg!(NotFound, "Entry not found"))?;
+            let item_id: i64 = row.try_get(0)?;
+            let enc_value: Vec<u8> = row.try_get(1)?;
+            let enc_value_type: Option<Vec<u8>> = row.try_get(2)?;
+            // expired records are counted, as they still occupy the unique key
+            let existing: i64 = sqlx::query_scalar(RENAME_EXISTS_QUERY)
+                .bind(txn.profile_id)
+                .bind(kind as i16)
+                .bind(enc_new_category.as_slice())
+                .bind(enc_new_name.as_slice())
+                .fetch_one(txn.connection_mut())
+                .await?;
+            if existing != 0 {
+                return Err(err_msg!(Duplicate, "Duplicate row"));
+            }
+            #[cfg(feature = "blob_store")]
+            let blob_removed: Vec<String> = blob_id(&enc_value)
+                .map(str::to_string)
+                .into_iter()
+                .collect();
+            // the value and value type are bound to the category and name
+            let (enc_value, enc_value_type, enc_prefixes, enc_category_prefixes, etag) = unblock({
+                #[cfg(feature = "blob_store")]
+                let blobs = blobs.clone();
+                move || {
+                    #[cfg(feature = "blob_store")]
+                    let enc_value = match blobs {
+                        Some(blobs) => blobs.resolve(enc_value)?,
+                        None => enc_value,
+                    };
+                    let value =
+                        key.decrypt_entry_value(category.as_ref(), name.as_ref(), enc_value)?;
+                    let value_type =
+                        decrypt_value_type(&key, category.as_ref(), name.as_ref(), enc_value_type)?
+                            .map(|vt| ProfileKey::prepare_input(vt.as_bytes()));
+                    let (enc_prefixes, enc_category_prefixes) = encode_index_prefixes(
+                        &key,
+                        new_category.as_ref(),
+                        new_name.as_ref(),
+                        name_index,
+                        category_index,
+                    )?;
+                    let etag = if value_etags {
+                        Some(key.entry_value_hash(
+                            new_category.as_ref(),
+                            new_name.as_ref(),
+                            value.as_ref(),
+                        )?)
+                    } else {
+                        None
+                    };
+                    Result::<_, Error>::Ok((
+                        key.encrypt_entry_value(
+                            new_category.as_ref(),
+                            new_name.as_ref(),
+                            value,
+                            envelope,
+                        )?,
+                        encrypt_value_type(
+                            &key,
+                            new_category.as_ref(),
+                            new_name.as_ref(),
+                            value_type,
+                            envelope,
+                        )?,
+                        enc_prefixes,
+                        enc_category_prefixes,
+                        etag,
+                    ))
+                }
+            })
+            .await?;
+            #[cfg(feature = "blob_store")]
+            let (enc_value, pending_blob) = if let Some(blobs) = blobs.as_ref() {
+                let (enc_value, blob_id) = unblock({
+                    let blobs = blobs.clone();
+                    move || blobs.store(enc_value)
+                })
+                .await?;
+                (
+                    enc_value,
+                    blob_id.map(|id| PendingBlob::new(blobs.clone(), id)),
+                )
+            } else {
+                (enc_value, None)
+            };
+            sqlx::query(RENAME_UPDATE_QUERY)
+                .bind(enc_new_category.as_slice())
+                .bind(enc_new_name.as_slice())
+                .bind(enc_value)
+                .bind(enc_value_type)
+                .bind(item_id)
+                .execute(txn.connection_mut())
+                .await?;
+            if name_index {
+                sqlx::query(NAME_INDEX_DELETE_QUERY)
+                    .bind(item_id)
+                    .execute(txn.connection_mut())
+                    .await?;
+                for prefix in enc_prefixes {
+                    sqlx::query(NAME_INDEX_INSERT_QUERY)
+                        .bind(item_id)
+                        .bind(prefix)
+                        .execute(txn.connection_mut())
+                        .await?;
+                }
+            }
+            if category_index {
+                sqlx::query(CATEGORY_INDEX_DELETE_QUERY)
+                    .bind(item_id)
+                    .execute(txn.connection_mut())
+                    .await?;
+                for prefix in enc_category_prefixes {
+                    sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+                        .bind(item_id)
+                        .bind(prefix)
+                        .execute(txn.connection_mut())
+                        .await?;
+                }
+            }
+            if let Some(etag) = etag {
+                sqlx::query(ETAG_INSERT_QUERY)
+                    .bind(item_id)
+                    .bind(etag)
+                    .execute(txn.connection_mut())
+                    .await?;
+            }
+            txn.commit().await?;
+            let profile_id = active.profile_id;
+            active.invalidate_cached(Some((profile_id, kind, enc_category, enc_name)));
+            active.invalidate_cached(Some((profile_id, kind, enc_new_category, enc_new_name)));
+            #[cfg(feature = "blob_store")]
+            {
+                drop(active);
+                self.track_blobs(pending_blob.map(PendingBlob::keep), blob_removed);
+            }
+            Ok(())
+        });
+        instrument(metrics, "rename", |_| Some(1), fut)
+    }
+
+    fn applied_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+    ) -> BoxFuture<'q, Result<Option<i64>, Error>> {
+        let idempotency_key = ProfileKey::prepare_input(idempotency_key.as_bytes());
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let enc_key = unblock(move || key.encrypt_entry_name(idempotency_key)).await?;
+            let retention = retention_modifier(self.settings().batch_retention());
+            let mut active = acquire_session(&mut *self).await?;
+            sqlx::query(BATCH_SCHEMA)
+                .execute(active.connection_mut())
+                .await?;
+            Ok(sqlx::query_scalar(BATCH_FETCH_QUERY)
+                .bind(profile_id)
+                .bind(enc_key)
+                .bind(retention)
+                .fetch_optional(active.connection_mut())
+                .await?)
+        });
+        instrument(metrics, "applied_batch", |_| None, fut)
+    }
+
+    fn record_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+        updates: i64,
+    ) -> BoxFuture<'q, Result<(), Error>> {
+        let idempotency_key = ProfileKey::prepare_input(idempotency_key.as_bytes());
+
+        let metrics = self.metrics();
+        let fut = Box::pin(async move {
+            let (profile_id, key) = acquire_key(&mut *self).await?;
+            let enc_key = unblock(move || key.encrypt_entry_name(idempotency_key)).await?;
+            let mut active = acquire_session(&mut *self).await?;
+            sqlx::query(BATCH_SCHEMA)
+                .execute(active.connection_mut())
+                .await?;
+            sqlx::query(BATCH_INSERT_QUERY)
+                .bind(profile_id)
+                .bind(enc_key)
+                .bind(updates)
+                .execute(active.connection_mut())
+                .await?;
+            active.record_writes(1, 0);
+            Ok(())
+        });
+        instrument(metrics, "record_batch", |_| None, fut)
+    }
+
+    fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        DbSession::set_query_timeout(self, timeout)
+    }
+
+    fn set_expiry_filter(&mut self, expiry: ExpiryFilter) {
+        DbSession::set_expiry_filter(self, expiry)
+    }
+
+    fn set_query_hint(&mut self, hint: Option<QueryHint>) {
+        DbSession::set_query_hint(self, hint)
+    }
+
+    fn set_profile(&mut self, profile: String) {
+        DbSession::set_profile(self, profile)
+    }
+
+    fn set_commit_operation(&mut self, operation: &'static str) {
+        DbSession::set_commit_operation(self, operation)
+    }
+
+    fn name_normalization(&self) -> NameNormalization {
+        self.settings().name_normalization()
+    }
+
+    fn close(self, commit: bool) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(DbSession::close(self, commit))
+    }
+}
+
+impl ExtDatabase for Sqlite {
+    fn exec_result(result: SqliteQueryResult) -> ExecResult {
+        ExecResult::new(result.rows_affected(), Some(result.last_insert_rowid()))
+    }
+
+    fn start_transaction(
+        conn: &mut PoolConnection<Self>,
+        nested: bool,
+    ) -> BoxFuture<'_, std::result::Result<(), SqlxError>> {
+        // FIXME - this is a horrible workaround because there is currently
+        // no good way to start an immediate transaction with sqlx. Without this
+        // adjustment, updates will run into 'database is locked' errors.
+        Box::pin(async move {
+            <Sqlite as Database>::TransactionManager::begin(&mut *conn).await?;
+            if !nested {
+                sqlx::query("ROLLBACK").execute(&mut *conn).await?;
+                sqlx::query("BEGIN IMMEDIATE").execute(conn).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn interrupt(conn: &mut PoolConnection<Self>) {
+        // the statement otherwise continues on the worker thread of the connection
+        unsafe { sqlite3_interrupt(conn.as_raw_handle()) }
+    }
+
+    fn is_fatal_error(err: &SqlxError) -> bool {
+        match err {
+            SqlxError::Database(db_err) => {
+                // the primary result code, without the extended code bits
+                let code = db_err.code().and_then(|code| code.parse::<i32>().ok());
+                matches!(
+                    code.map(|code| code & 0xff),
+                    Some(
+                        SQLITE_IOERR
+                            | SQLITE_CORRUPT
+                            | SQLITE_FULL
+                            | SQLITE_CANTOPEN
+                            | SQLITE_NOTADB
+                    )
+                )
+            }
+            SqlxError::Io(_) | SqlxError::Protocol(_) | SqlxError::WorkerCrashed => true,
+            _ => false,
+        }
+    }
+}
+
+/// Add the existing records of a profile to the name and/or category indexes
+async fn add_index_records(
+    conn: &mut SqliteConnection,
+    profile_id: ProfileId,
+    key: Arc<ProfileKey>,
+    name_index: bool,
+    category_index: bool,
+) -> Result<(), Error> {
+    let items = sqlx::query("SELECT id, category, name FROM items WHERE profile_id = ?1")
+        .bind(profile_id)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)))
+        .collect::<Result<Vec<(i64, Vec<u8>, Vec<u8>)>, Error>>()?;
+    let (name_prefixes, category_prefixes) =
+        unblock(move || encode_backfill_prefixes(&key, items, name_index, category_index)).await?;
+    for (item_id, prefix) in name_prefixes {
+        sqlx::query(NAME_INDEX_INSERT_QUERY)
+            .bind(item_id)
+            .bind(prefix)
+            .execute(&mut *conn)
+            .await?;
+    }
+    for (item_id, prefix) in category_prefixes {
+        sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+            .bind(item_id)
+            .bind(prefix)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Append an entry to the access log, which must have been created by
+/// `enable_access_log`
+async fn log_access(
+    conn: &mut SqliteConnection,
+    client: Option<&str>,
+    event: AccessEvent,
+    profile: Option<&str>,
+    success: bool,
+) -> Result<(), Error> {
+    sqlx::query(ACCESS_LOG_INSERT_QUERY)
+        .bind(chrono::Utc::now())
+        .bind(event.as_str())
+        .bind(client)
+        .bind(profile)
+        .bind(success)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// the date modifier selecting the rows within a retention period
+fn retention_modifier(retention: Duration) -> String {
+    format!("-{} seconds", retention.as_secs())
+}
+
+async fn acquire_key(
+    session: &mut DbSession<Sqlite>,
+) -> Result<(ProfileId, Arc<ProfileKey>), Error> {
     if let Some(ret) = session.profile_and_key() {
         Ok(ret)
     } else {
         session.make_active(&resolve_profile_key).await?;
         Ok(session.profile_and_key().unwrap())
     }
-}
+}
+
+/// Determine whether the name and category indexes are enabled, from within a
+/// write transaction. An index may have been enabled by another instance of the
+/// store since it was opened, in which case the settings are updated.
+async fn load_index_settings(
+    txn: &mut DbSessionActive<'_, Sqlite>,
+    settings: &DbSettings,
+) -> Result<(bool, bool), Error> {
+    if !settings.name_index() || !settings.category_index() {
+        let enabled = sqlx::query_scalar::<_, String>(INDEX_CONFIG_QUERY)
+            .fetch_all(txn.connection_mut())
+            .await?;
+        for name in enabled {
+            match name.as_str() {
+                "name_index" => settings.set_name_index(true),
+                "category_index" => settings.set_category_index(true),
+                _ => (),
+            }
+        }
+    }
+    Ok((settings.name_index(), settings.category_index()))
+}
+
+async fn acquire_session<'q>(
+    session: &'q mut DbSession<Sqlite>,
+) -> Result<DbSessionActive<'q, Sqlite>, Error> {
+    session.make_active(&resolve_profile_key).await
+}
+
+async fn resolve_profile_key(
+    conn: &mut PoolConnection<Sqlite>,
+    cache: Arc<KeyCache>,
+    profile: String,
+) -> Result<(ProfileId, Arc<ProfileKey>), Error> {
+    if let Some((pid, key)) = cache.get_profile(profile.as_str()).await {
+        Ok((pid, key))
+    } else {
+        if let Some(row) =
+            sqlx::query("SELECT id, profile_key, reference FROM profiles WHERE name=?1")
+                .bind(profile.as_str())
+                .fetch_optional(conn)
+                .await?
+        {
+            if row.try_get::<Option<String>, _>(2)?.is_some() {
+                return Err(locked_profile_error(&profile));
+            }
+            let pid = row.try_get(0)?;
+            let key = cache.load_shared_key(pid, row.try_get(1)?).await?;
+            cache.add_profile(profile, pid, key.clone()).await;
+            Ok((pid, key))
+        } else {
+            Err(err_msg!(NotFound, "Profile not found: '{}'", profile))
+        }
+    }
+}
+
+fn locked_profile_error(profile: &str) -> Error {
+    err_msg!(
+        Locked,
+        "Profile '{}' is protected by a passphrase and must be unlocked",
+        profile
+    )
+}
+
+fn fetch_tags_query(tag_mode: TagRetrieval) -> &'static str {
+    match tag_mode {
+        TagRetrieval::None => FETCH_NO_TAGS_QUERY.as_str(),
+        TagRetrieval::PlaintextOnly => FETCH_PLAINTEXT_TAGS_QUERY.as_str(),
+        TagRetrieval::CountOnly => FETCH_TAG_COUNT_QUERY.as_str(),
+        TagRetrieval::All => FETCH_QUERY.as_str(),
+    }
+}
+
+// decrypt a record selected by `FETCH_QUERY`
+async fn decrypt_fetch_row(
+    row: SqliteRow,
+    key: Arc<ProfileKey>,
+    settings: Arc<DbSettings>,
+    #[cfg(feature = "blob_store")] blobs: Option<Arc<BlobStore>>,
+    category: Vec<u8>,
+    name: Vec<u8>,
+) -> Result<Entry, Error> {
+    Ok(decrypt_tagged_row(
+        row,
+        key,
+        settings,
+        #[cfg(feature = "blob_store")]
+        blobs,
+        category,
+        name,
+        TagRetrieval::All,
+    )
+    .await?
+    .entry)
+}
+
+// decrypt a record selected by the query for the tag retrieval mode. Only
+// the tags present in the row are decoded and decrypted
+async fn decrypt_tagged_row(
+    row: SqliteRow,
+    key: Arc<ProfileKey>,
+    settings: Arc<DbSettings>,
+    #[cfg(feature = "blob_store")] blobs: Option<Arc<BlobStore>>,
+    category: Vec<u8>,
+    name: Vec<u8>,
+    tag_mode: TagRetrieval,
+) -> Result<TaggedEntry, Error> {
+    let value = row.try_get(1)?;
+    let tags = match tag_mode {
+        TagRetrieval::PlaintextOnly | TagRetrieval::All => Some(row.try_get::<Vec<u8>, _>(2)?),
+        TagRetrieval::None | TagRetrieval::CountOnly => None,
+    };
+    let value_type = row.try_get(3)?;
+    let expiry = row_expiry(&row, 4)?;
+    let mut tag_count = match tag_mode {
+        TagRetrieval::PlaintextOnly | TagRetrieval::CountOnly => {
+            Some(row.try_get::<i64, _>(5)? as usize)
+        }
+        TagRetrieval::None | TagRetrieval::All => None,
+    };
+    let (category, name, value, value_type, (tags, dropped_tags), enc_count) = unblock(move || {
+        #[cfg(feature = "blob_store")]
+        let value = match blobs {
+            Some(blobs) => blobs.resolve(value)?,
+            None => value,
+        };
+        let value = key.decrypt_entry_value(&category, &name, value)?;
+        let value_type = decrypt_value_type(&key, &category, &name, value_type)?;
+        let (tags, enc_count) = if let Some(tags) = tags {
+            let enc_tags =
+                decode_tags(tags).map_err(|_| err_msg!(Unexpected, "Error decoding entry tags"))?;
+            let enc_count = enc_tags.len();
+            (decrypt_tags(&key, enc_tags, &settings)?, enc_count)
+        } else {
+            ((Vec::new(), Vec::new()), 0)
+        };
+        Result::<_, Error>::Ok((category, name, value, value_type, tags, enc_count))
+    })
+    .await?;
+    if tag_mode == TagRetrieval::All {
+        tag_count = Some(enc_count);
+    }
+    Ok(TaggedEntry {
+        entry: Entry::from_bytes(category, name, value, tags)
+            .with_value_type(value_type)
+            .with_expiry(expiry)
+            .with_dropped_tags(dropped_tags),
+        tag_count,
+    })
+}
+
+async fn perform_insert<'q>(
+    active: &mut DbSessionActive<'q, Sqlite>,
+    kind: EntryKind,
+    enc_category: &[u8],
+    enc_name: &[u8],
+    enc_value: &[u8],
+    enc_value_type: Option<&[u8]>,
+    enc_tags: Option<Vec<EncEntryTag>>,
+    enc_prefixes: Vec<Vec<u8>>,
+    enc_category_prefixes: Vec<Vec<u8>>,
+    etag: Option<Vec<u8>>,
+    expiry: Option<Expiry>,
+) -> Result<(), Error> {
+    trace!("Insert entry");
+    let done = sqlx::query(INSERT_QUERY)
+        .bind(active.profile_id)
+        .bind(kind as i16)
+        .bind(enc_category)
+        .bind(enc_name)
+        .bind(enc_value)
+        .bind(enc_value_type)
+        .bind(expiry)
+        .execute(active.connection_mut())
+        .await
+        .map(Sqlite::exec_result)?;
+    if done.rows_affected() == 0 {
+        return Err(err_msg!(Duplicate, "Duplicate row"));
+    }
+    let row_id = done.inserted_id()?;
+    let mut statements = 1;
+    if let Some(tags) = enc_tags {
+        for tag in tags {
+            sqlx::query(TAG_INSERT_QUERY)
+                .bind(row_id)
+                .bind(&tag.name)
+                .bind(&tag.value)
+                .bind(tag.plaintext as i16)
+                .bind(tag.value_type as i16)
+                .execute(active.connection_mut())
+                .await?;
+            statements += 1;
+        }
+    }
+    for prefix in enc_prefixes {
+        sqlx::query(NAME_INDEX_INSERT_QUERY)
+            .bind(row_id)
+            .bind(prefix)
+            .execute(active.connection_mut())
+            .await?;
+        statements += 1;
+    }
+    for prefix in enc_category_prefixes {
+        sqlx::query(CATEGORY_INDEX_INSERT_QUERY)
+            .bind(row_id)
+            .bind(prefix)
+            .execute(active.connection_mut())
+            .await?;
+        statements += 1;
+    }
+    if let Some(etag) = etag {
+        sqlx::query(ETAG_INSERT_QUERY)
+            .bind(row_id)
+            .bind(etag)
+            .execute(active.connection_mut())
+            .await?;
+        statements += 1;
+    }
+    active.record_writes(statements, enc_value.len());
+    Ok(())
+}
+
+// read an expiry timestamp as milliseconds since the Unix epoch
+// update the access counters of a record without delaying the request
+/// Check the usage counters of the active profile against its quotas, after
+/// they have been updated by a write in the same transaction
+async fn check_profile_quota<'q>(active: &mut DbSessionActive<'q, Sqlite>) -> Result<(), Error> {
+    if let Some(row) = sqlx::query(QUOTA_CHECK_QUERY)
+        .bind(active.profile_id)
+        .fetch_optional(active.connection_mut())
+        .await?
+    {
+        let items: i64 = row.try_get(0)?;
+        let bytes: i64 = row.try_get(1)?;
+        if let Some(max) = row.try_get::<Option<i64>, _>(2)?.filter(|max| items > *max) {
+            return Err(err_msg!(
+                QuotaExceeded,
+                "Profile item quota exceeded: limit of {} records",
+                max
+            ));
+        }
+        if let Some(max) = row.try_get::<Option<i64>, _>(3)?.filter(|max| bytes > *max) {
+            return Err(err_msg!(
+                QuotaExceeded,
+                "Profile byte quota exceeded: limit of {} bytes",
+                max
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn record_access(pool: SqlitePool, item_id: i64, reads: i64) {
+    spawn_ok(async move {
+        if let Err(err) = sqlx::query(ACCESS_UPDATE_QUERY)
+            .bind(item_id)
+            .bind(reads)
+            .bind(chrono::Utc::now())
+            .execute(&pool)
+            .await
+        {
+            warn!(
+                "Error updating access counters (item id {}): {}",
+                item_id, err
+            );
+        }
+    });
+}
+
+fn row_expiry(row: &SqliteRow, index: usize) -> Result<Option<i64>, Error> {
+    Ok(row
+        .try_get::<Option<Expiry>, _>(index)?
+        .map(|expiry| expiry.timestamp_millis()))
+}
+
+async fn perform_remove<'q>(
+    active: &mut DbSessionActive<'q, Sqlite>,
+    kind: EntryKind,
+    enc_category: &[u8],
+    enc_name: &[u8],
+    ignore_error: bool,
+    history: bool,
+) -> Result<(), Error> {
+    trace!("Remove entry");
+    if history {
+        sqlx::query(HISTORY_INSERT_QUERY)
+            .bind(active.profile_id)
+            .bind(kind as i16)
+            .bind(enc_category)
+            .bind(enc_name)
+            .bind(chrono::Utc::now())
+            .execute(active.connection_mut())
+            .await?;
+    }
+    let done = sqlx::query(DELETE_QUERY.as_str())
+        .bind(active.profile_id)
+        .bind(kind as i16)
+        .bind(enc_category)
+        .bind(enc_name)
+        .execute(active.connection_mut())
+        .await
+        .map(Sqlite::exec_result)?;
+    active.record_writes(1 + history as u64, 0);
+    if done.rows_affected() == 0 && !ignore_error {
+        Err(err_msg!(NotFound, "Entry not found"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "blob_store")]
+async fn fetch_blob_ids<'q, A>(
+    query: sqlx::query::Query<'q, Sqlite, A>,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<Vec<String>, Error>
+where
+    A: 'q + Send + sqlx::IntoArguments<'q, Sqlite>,
+{
+    let rows = query.fetch_all(conn).await?;
+    let mut blob_ids = Vec::with_capacity(rows.len());
+    for row in rows {
+        if let Some(blob_id) = blob_id(row.try_get::<&[u8], _>(0)?) {
+            blob_ids.push(blob_id.to_string());
+        }
+    }
+    Ok(blob_ids)
+}
+
+fn perform_scan<'q>(
+    mut active: DbSessionRef<'q, Sqlite>,
+    profile_id: ProfileId,
+    key: Arc<ProfileKey>,
+    kind: EntryKind,
+    category: String,
+    name_prefix: Option<String>,
+    tag_filter: Option<TagFilter>,
+    after_id: Option<i64>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> impl Stream<Item = Result<Vec<EncScanEntry>, Error>> + 'q {
+    try_stream! {
+        let expiry = active.expiry_filter();
+        let hint = active.query_hint();
+        let page_bytes = active.settings().scan_page_bytes();
+        let mut params = QueryParams::with_capacity(3);
+        params.push(profile_id);
+        params.push(kind as i16);
+        let (enc_category, enc_prefix, tag_filter) = unblock({
+            let key = key.clone();
+            // plus category, and name prefix or last record ID
+            let params_len =
+                params.len() + 1 + (name_prefix.is_some() || after_id.is_some()) as usize;
+            move || {
+                let enc_prefix = name_prefix
+                    .map(|prefix| key.entry_name_prefix_hash(category.as_bytes(), prefix.as_bytes()))
+                    .transpose()?;
+                Result::<_, Error>::Ok((
+                    key.encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?,
+                    enc_prefix,
+                    encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?
+                ))
+            }
+        }).await?;
+        params.push(enc_category);
+        let base_query = if let Some(enc_prefix) = enc_prefix {
+            params.push(enc_prefix);
+            SCAN_PREFIX_QUERY.as_str()
+        } else if let Some(after_id) = after_id {
+            params.push(after_id);
+            SCAN_AFTER_QUERY.as_str()
+        } else {
+            SCAN_QUERY.as_str()
+        };
+        let mut query = expiry_query::<SqliteStore>(
+            extend_query::<SqliteStore>(base_query, &mut params, tag_filter, hint, offset, limit)?,
+            expiry,
+        );
+        if after_id.is_some() {
+            // resumable scans are not limited, so the ordering follows the conditions
+            query.to_mut().push_str(" ORDER BY i.id");
+        }
+
+        let mut batch = Vec::with_capacity(PAGE_SIZE);
+        let mut batch_bytes = 0;
+
+        let mut acquired = acquire_session(&mut *active).await?;
+        let query_timeout = acquired.query_timeout();
+        let mut rows = sqlx::query_with(&*query, params).fetch(acquired.connection_mut());
+        let mut timed_out = false;
+        let mut failed = None;
+        loop {
+            let row = match run_query(query_timeout, rows.try_next()).await {
+                Some(Ok(Some(row))) => row,
+                Some(Ok(None)) => break,
+                Some(Err(err)) => {
+                    failed = Some(err);
+                    break;
+                }
+                None => {
+                    timed_out = true;
+                    break;
+                }
+            };
+            let value: Vec<u8> = row.try_get(2)?;
+            batch_bytes += value.len();
+            batch.push(EncScanEntry {
+                id: row.try_get(0)?,
+                name: row.try_get(1)?,
+                value,
+                value_type: row.try_get(4)?,
+                tags: row.try_get(3)?,
+                expiry: row_expiry(&row, 5)?,
+            });
+            // large values end the page early to limit the memory held by each scan
+            if batch.len() == PAGE_SIZE || batch_bytes >= page_bytes {
+                batch_bytes = 0;
+                yield batch.split_off(0);
+            }
+        }
+        drop(rows);
+        if timed_out {
+            Err::<(), _>(acquired.timed_out())?;
+        }
+        if let Some(err) = failed {
+            Err::<(), _>(acquired.check_error(err))?;
+        }
+        drop(acquired);
+        drop(active);
+
+        if !batch.is_empty() {
+            yield batch;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::db_utils::replace_arg_placeholders;
+    use crate::error::ErrorKind;
+    use crate::future::block_on;
+    use crate::protect::{
+        generate_raw_store_key, PassKey, ProvisionKeys, StoreKeyMethod, ENVELOPE_VERSION,
+    };
+    use crate::storage::{AuditCancel, NameCollision, ScanTracker, Store};
+
+    // a store database in the temporary directory, which is removed when the
+    // fixture is dropped
+    struct TempDbFile(String);
+
+    impl TempDbFile {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("sqlite-test-{}.db", uuid::Uuid::new_v4()));
+            Self(path.to_str().unwrap().to_string())
+        }
+
+        fn options(&self) -> SqliteStoreOptions {
+            SqliteStoreOptions::from_path(&self.0)
+        }
+
+        async fn provision(&self, key: PassKey<'_>) -> Result<Store<SqliteStore>, Error> {
+            self.options()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+        }
+    }
+
+    impl Drop for TempDbFile {
+        fn drop(&mut self) {
+            // the stores using the file are expected to be closed by now
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn sqlite_check_expiry_timestamp() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let ts = expiry_timestamp(1000).unwrap();
+            let check = sqlx::query("SELECT datetime('now'), ?1, ?1 > datetime('now')")
+                .bind(ts)
+                .fetch_one(&db.inner().conn_pool)
+                .await?;
+            let now: String = check.try_get(0)?;
+            let cmp_ts: String = check.try_get(1)?;
+            let cmp: bool = check.try_get(2)?;
+            if !cmp {
+                panic!("now ({}) > expiry timestamp ({})", now, cmp_ts);
+            }
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_lenient_tags() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let tags = [
+                EntryTag::Encrypted("t1".to_string(), "a".to_string()),
+                EntryTag::Encrypted("t2".to_string(), "b".to_string()),
+            ];
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "name", b"value", Some(&tags[..]), None)
+                .await?;
+            drop(conn);
+
+            // corrupt the ciphertext of a single tag
+            sqlx::query(
+                "UPDATE items_tags SET value = X'00'
+                WHERE id = (SELECT MIN(id) FROM items_tags)",
+            )
+            .execute(&db.inner().conn_pool)
+            .await?;
+
+            // strict mode fails the whole request
+            let mut conn = db.session(None).await?;
+            assert!(conn.fetch("cat", "name", false).await.is_err());
+            assert!(conn.fetch_all("cat", None, None, false).await.is_err());
+            drop(conn);
+
+            let mut inner = db.into_inner();
+            inner.settings = Arc::new(DbSettings {
+                lenient_tags: true,
+                ..Default::default()
+            });
+            let db = Store::new(inner);
+
+            // lenient mode returns the value and the remaining tag, and
+            // reports the tag which was dropped
+            let mut conn = db.session(None).await?;
+            let entry = conn.fetch("cat", "name", false).await?.unwrap();
+            assert_eq!(&entry.value[..], b"value");
+            assert_eq!(entry.tags, vec![tags[1].clone()]);
+            assert_eq!(entry.dropped_tags, vec![Some("t1".to_string())]);
+            let entries = conn.fetch_all("cat", None, None, false).await?;
+            assert_eq!(entries, vec![entry]);
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_swapped_values() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "first", b"value1", None, None).await?;
+            conn.insert("cat", "second", b"value2", None, None).await?;
+            drop(conn);
+
+            // exchange the stored values of the two rows
+            let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as("SELECT id, value FROM items")
+                .fetch_all(&db.inner().conn_pool)
+                .await?;
+            assert_eq!(rows.len(), 2);
+            for (idx, (id, _)) in rows.iter().enumerate() {
+                sqlx::query("UPDATE items SET value = ?1 WHERE id = ?2")
+                    .bind(&rows[1 - idx].1)
+                    .bind(id)
+                    .execute(&db.inner().conn_pool)
+                    .await?;
+            }
+
+            let mut conn = db.session(None).await?;
+            for name in &["first", "second"] {
+                let err = conn
+                    .fetch("cat", name, false)
+                    .await
+                    .expect_err("Expected authentication failure");
+                assert_eq!(err.kind(), ErrorKind::Encryption);
+            }
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_value_etags() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "before", b"value", None, None).await?;
+            drop(conn);
+
+            db.enable_value_etags().await?;
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "after", b"value", None, None).await?;
+            let (_, before) = conn.fetch_with_etag("cat", "before", false).await?.unwrap();
+            let (_, after) = conn.fetch_with_etag("cat", "after", false).await?.unwrap();
+            drop(conn);
+
+            // the stored entity tag is the keyed hash rather than the ciphertext
+            let stored: Vec<u8> = sqlx::query_scalar("SELECT etag FROM items_etag")
+                .fetch_one(&db.inner().conn_pool)
+                .await?;
+            assert_eq!(stored, after);
+
+            // corrupt the stored values, which are not decrypted for an
+            // unchanged record with a stored entity tag
+            sqlx::query("UPDATE items SET value = X'00'")
+                .execute(&db.inner().conn_pool)
+                .await?;
+            let mut conn = db.session(None).await?;
+            assert_eq!(
+                conn.fetch_if_modified("cat", "after", &after).await?,
+                Some(ConditionalFetch::NotModified)
+            );
+            assert!(conn
+                .fetch_if_modified("cat", "before", &before)
+                .await
+                .is_err());
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_inserted_row_ids() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mut conn = db.session(None).await?;
+            let tags = |value: &str| {
+                vec![
+                    EntryTag::Encrypted("enc".to_string(), value.to_string()),
+                    EntryTag::Plaintext("plain".to_string(), value.to_string()),
+                ]
+            };
+            conn.insert("cat", "first", b"1", Some(tags("1").as_slice()), None)
+                .await?;
+            conn.insert("cat", "second", b"2", Some(tags("2").as_slice()), None)
+                .await?;
+            conn.remove("cat", "first").await?;
+            conn.insert("cat", "third", b"3", Some(tags("3").as_slice()), None)
+                .await?;
+
+            // every tag row belongs to the item inserted with it
+            let orphans: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM items_tags it
+                WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = it.item_id)",
+            )
+            .fetch_one(&db.inner().conn_pool)
+            .await?;
+            assert_eq!(orphans, 0);
+            let counts: Vec<(i64, i64)> = sqlx::query_as(
+                "SELECT i.id, COUNT(it.id) FROM items i
+                LEFT JOIN items_tags it ON it.item_id = i.id GROUP BY i.id ORDER BY i.id",
+            )
+            .fetch_all(&db.inner().conn_pool)
+            .await?;
+            assert_eq!(counts.len(), 2);
+            assert!(counts.iter().all(|(_, count)| *count == 2));
+
+            for (name, value) in &[("second", "2"), ("third", "3")] {
+                let entry = conn.fetch("cat", name, false).await?.unwrap();
+                assert_eq!(entry.value, value.as_bytes()[..]);
+                assert_eq!(entry.tags, tags(value));
+            }
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_load_profile_key_errors() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let profile = db.create_profile(None).await?;
+            let rows: Vec<(ProfileId, String, Vec<u8>)> =
+                sqlx::query_as("SELECT id, name, profile_key FROM profiles ORDER BY id")
+                    .fetch_all(&db.inner().conn_pool)
+                    .await?;
+            let (default_id, _, default_key) = rows[0].clone();
+            let (pid, name, mut enc_key) = rows[1].clone();
+            assert_eq!(name, profile);
+
+            // corrupt the ciphertext of the second profile key
+            let last = enc_key.len() - 1;
+            enc_key[last] ^= 1;
+            sqlx::query("UPDATE profiles SET profile_key = ?1 WHERE id = ?2")
+                .bind(&enc_key)
+                .bind(pid)
+                .execute(&db.inner().conn_pool)
+                .await?;
+
+            // the store key has not been verified by a previous load
+            let (wrong_key, _) = StoreKeyMethod::RawKey.resolve(generate_raw_store_key(None)?)?;
+            let err = KeyCache::new(wrong_key)
+                .load_key(default_id, Some(default_key.clone()))
+                .await
+                .expect_err("Expected decryption failure");
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+            assert!(err.to_string().contains(&format!(
+                "(profile id {}): the store key may be incorrect",
+                default_id
+            )));
+
+            // the store key is verified by successfully loading the default profile key
+            let cache = KeyCache::new(db.inner().key_cache.store_key.clone());
+            cache.load_key(default_id, Some(default_key)).await?;
+            let err = cache
+                .load_key(pid, Some(enc_key))
+                .await
+                .expect_err("Expected decryption failure");
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+            assert!(err.to_string().contains(&format!(
+                "(profile id {}): the stored key is corrupted",
+                pid
+            )));
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_verify_integrity() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            assert!(store.verify_integrity().await?.is_ok());
+
+            let tags = [EntryTag::Encrypted("t1".to_string(), "a".to_string())];
+            let mut profile_ids = vec![];
+            for _ in 0..2 {
+                let profile = store.create_profile(None).await?;
+                let mut conn = store.session(Some(profile.clone())).await?;
+                conn.insert("cat", "name", b"value", Some(&tags[..]), None)
+                    .await?;
+                drop(conn);
+                let (pid,): (ProfileId,) =
+                    sqlx::query_as("SELECT id FROM profiles WHERE name = ?1")
+                        .bind(profile)
+                        .fetch_one(&store.inner().conn_pool)
+                        .await?;
+                profile_ids.push(pid);
+            }
+
+            // remove the key of the first profile
+            sqlx::query("UPDATE profiles SET profile_key = NULL WHERE id = ?1")
+                .bind(profile_ids[0])
+                .execute(&store.inner().conn_pool)
+                .await?;
+            // remove the second profile without removing its records
+            let mut conn = store.inner().conn_pool.acquire().await?;
+            sqlx::query("PRAGMA foreign_keys = OFF")
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("DELETE FROM profiles WHERE id = ?1")
+                .bind(profile_ids[1])
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("PRAGMA foreign_keys = ON")
+                .execute(&mut conn)
+                .await?;
+            drop(conn);
+
+            let report = store.verify_integrity().await?;
+            assert_eq!(report.missing_keys, vec![(profile_ids[0], 1)]);
+            assert_eq!(report.orphaned_items, vec![(profile_ids[1], 1)]);
+
+            let err = KeyCache::new(store.inner().key_cache.store_key.clone())
+                .load_key(profile_ids[0], None)
+                .await
+                .expect_err("Expected missing key");
+            assert_eq!(err.kind(), ErrorKind::KeyNotFound);
+            assert!(err
+                .to_string()
+                .contains(&format!("(profile id {})", profile_ids[0])));
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_audit_decryption() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let tags = [EntryTag::Encrypted("t1".to_string(), "a".to_string())];
+            let mut conn = store.session(None).await?;
+            for (category, name) in [("cat1", "a"), ("cat1", "b"), ("cat2", "c")].iter() {
+                conn.insert(category, name, b"value", Some(&tags[..]), None)
+                    .await?;
+            }
+            drop(conn);
+            let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM items ORDER BY id")
+                .fetch_all(&store.inner().conn_pool)
+                .await?;
+            assert_eq!(ids.len(), 3);
+
+            let report = store
+                .audit_decryption(None, DecryptionAuditOptions::default(), None)
+                .await?;
+            assert!(report.is_ok());
+            assert!(report.complete);
+            assert_eq!((report.scanned, report.checked), (3, 3));
+            assert_eq!(report.last_id, Some(ids[2]));
+
+            // the value of a record is bound to its category and name, so a
+            // value moved from another record fails to decrypt
+            sqlx::query(
+                "UPDATE items SET value = (SELECT value FROM items WHERE id = ?1) WHERE id = ?2",
+            )
+            .bind(ids[0])
+            .bind(ids[1])
+            .execute(&store.inner().conn_pool)
+            .await?;
+            let report = store
+                .audit_decryption(None, DecryptionAuditOptions::default(), None)
+                .await?;
+            assert_eq!(report.failures.len(), 1);
+            assert_eq!(report.failures[0].row_id, ids[1]);
+            assert_eq!(report.failures[0].kind, ErrorKind::Encryption);
+
+            let report = store
+                .audit_decryption(
+                    None,
+                    DecryptionAuditOptions {
+                        categories: Some(vec!["cat2".to_string()]),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await?;
+            assert!(report.is_ok());
+            assert_eq!((report.scanned, report.checked), (3, 1));
+
+            // a cancelled audit can be resumed from the last record read
+            let cancel = AuditCancel::new();
+            cancel.cancel();
+            let mut report = store
+                .audit_decryption(
+                    None,
+                    DecryptionAuditOptions {
+                        resume_after: Some(ids[0]),
+                        ..Default::default()
+                    },
+                    Some(&cancel),
+                )
+                .await?;
+            assert!(!report.complete);
+            assert_eq!(report.checked, 0);
+            let resumed = store
+                .audit_decryption(
+                    None,
+                    DecryptionAuditOptions {
+                        resume_after: report.last_id,
+                        max_rate: Some(1000),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await?;
+            report.merge(resumed);
+            assert!(report.complete);
+            assert_eq!(report.checked, 2);
+            assert_eq!(report.failures.len(), 1);
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_cleanup_orphans() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let tags = [
+                EntryTag::Encrypted("t1".to_string(), "a".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "b".to_string()),
+            ];
+            let mut conn = store.session(None).await?;
+            for name in &["first", "second", "third"] {
+                conn.insert("cat", name, b"value", Some(&tags[..]), None)
+                    .await?;
+            }
+            drop(conn);
+            let item_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM items ORDER BY id")
+                .fetch_all(&store.inner().conn_pool)
+                .await?;
+
+            // foreign keys are enforced on pooled connections, so that removing
+            // a record directly also removes its tags
+            let mut conn = store.inner().conn_pool.acquire().await?;
+            let enabled: i64 = sqlx::query_scalar("PRAGMA foreign_keys")
+                .fetch_one(&mut conn)
+                .await?;
+            assert_eq!(enabled, 1);
+            sqlx::query("DELETE FROM items WHERE id = ?1")
+                .bind(item_ids[0])
+                .execute(&mut conn)
+                .await?;
+            let tag_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items_tags")
+                .fetch_one(&mut conn)
+                .await?;
+            assert_eq!(tag_count, 4);
+            assert!(store.verify_integrity().await?.is_ok());
+
+            // remove a record without removing its tags
+            sqlx::query("PRAGMA foreign_keys = OFF")
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("DELETE FROM items WHERE id = ?1")
+                .bind(item_ids[1])
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("PRAGMA foreign_keys = ON")
+                .execute(&mut conn)
+                .await?;
+            drop(conn);
+
+            let report = store.verify_integrity().await?;
+            assert_eq!(report.orphaned_tags, 2);
+            assert!(!report.is_ok());
+            assert_eq!(store.cleanup_orphans().await?, 2);
+            assert!(store.verify_integrity().await?.is_ok());
+            assert_eq!(store.cleanup_orphans().await?, 0);
+
+            // the tags of the remaining record are unaffected
+            let mut conn = store.session(None).await?;
+            let entry = conn.fetch("cat", "third", false).await?.unwrap();
+            assert_eq!(entry.tags, tags.to_vec());
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_provision_with_keys() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let keys = ProvisionKeys::create_batch(2, StoreKeyMethod::RawKey, key).await?;
+            for keys in keys {
+                let store = SqliteStoreOptions::in_memory()
+                    .provision_with_keys(keys, Some("default"), false)
+                    .await?;
+                let mut conn = store.session(None).await?;
+                conn.insert("cat", "name", b"value", None, None).await?;
+                let row = conn.fetch("cat", "name", false).await?.unwrap();
+                assert_eq!(&row.value[..], b"value");
+            }
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_provision_with_result() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let (store, result) = file
+                .options()
+                .provision_with_result(StoreKeyMethod::RawKey, PassKey::empty(), None, false)
+                .await?;
+            assert_eq!(result.store_key_method(), &StoreKeyMethod::RawKey);
+            assert_eq!(result.profile(), store.get_profile_name());
+            assert_eq!(result.schema_version(), SCHEMA_VERSION);
+            assert!(result.kdf_salt().is_none());
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "name", b"value", None, None).await?;
+            drop(conn);
+            store.close().await?;
+
+            // an existing store is not opened
+            let err = file
+                .options()
+                .provision_with_result(StoreKeyMethod::RawKey, PassKey::empty(), None, false)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+            // the generated key opens the store again
+            let generated = result.generated_key().expect("Missing generated key");
+            let store = file
+                .options()
+                .open(
+                    Some(StoreKeyMethod::RawKey),
+                    generated.as_opt_str().into(),
+                    Some(result.profile()),
+                )
+                .await?;
+            let mut conn = store.session(None).await?;
+            assert!(conn.fetch("cat", "name", false).await?.is_some());
+            drop(conn);
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_read_pool() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            // rejected in the same way as the URI parameter
+            let err = file
+                .options()
+                .read_pool_size(0)
+                .expect_err("Expected invalid read pool size");
+            assert_eq!(err.kind(), ErrorKind::Input);
+
+            let mut opts = file.options().read_pool_size(2)?;
+            opts.max_connections = 1;
+            let store = opts
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+
+            let mut conn = store.session(None).await?;
+            for idx in 0..(PAGE_SIZE + 1) {
+                conn.insert("cat", &format!("name-{}", idx), b"value", None, None)
+                    .await?;
+            }
+            drop(conn);
+
+            // the scan holds a read connection until it completes, leaving the
+            // single connection of the main pool available for updates
+            let mut scan = store.scan(None, "cat".into(), None, None, None).await?;
+            let first = scan.fetch_next().await?.expect("Expected scan results");
+            assert_eq!(first.len(), PAGE_SIZE);
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "other", b"value", None, None).await?;
+            drop(conn);
+            assert_eq!(store.diagnostics().await?.read_pool_size, Some(1));
+            assert!(scan.fetch_next().await?.is_some());
+            drop(scan);
+
+            // read-only connections cannot modify the database
+            let read_pool = store.inner().read_pool.clone().expect("Expected read pool");
+            let err = sqlx::query("DELETE FROM items")
+                .execute(&read_pool)
+                .await
+                .expect_err("Expected read-only error");
+            assert!(err.to_string().contains("readonly"));
+
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_share_keys() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            let store = file.provision(key.as_ref()).await?;
+            let open = |key: PassKey<'static>, share: bool| {
+                file.options()
+                    .share_keys(share)
+                    .open(Some(StoreKeyMethod::RawKey), key, None)
+            };
+
+            // a handle sharing the keys reuses the decrypted store and profile keys
+            let mut shared = open(key.clone(), true).await?;
+            let other = open(key.clone(), true).await?;
+            assert!(Arc::ptr_eq(
+                &shared.inner().key_cache.store_key,
+                &other.inner().key_cache.store_key
+            ));
+            let profile_key = |store: &Store<SqliteStore>| {
+                let cache = store.inner().key_cache.clone();
+                let profile = store.inner().default_profile.clone();
+                async move { cache.get_profile(&profile).await.unwrap().1 }
+            };
+            assert!(Arc::ptr_eq(
+                &profile_key(&shared).await,
+                &profile_key(&other).await
+            ));
+            let separate = open(key.clone(), false).await?;
+            assert!(!Arc::ptr_eq(
+                &shared.inner().key_cache.store_key,
+                &separate.inner().key_cache.store_key
+            ));
+            separate.close().await?;
+            other.close().await?;
+
+            // a different pass key is not matched to the shared keys
+            let wrong_key = generate_raw_store_key(None)?;
+            let err = open(wrong_key, true).await.expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+
+            // the prior pass key no longer opens the store once it is rekeyed
+            let new_key = generate_raw_store_key(None)?;
+            shared
+                .rekey(StoreKeyMethod::RawKey, new_key.as_ref())
+                .await?;
+            let err = open(key.clone(), true).await.expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+            let reopened = open(new_key.clone(), true).await?;
+            let mut conn = reopened.session(None).await?;
+            conn.insert("cat", "name", b"value", None, None).await?;
+            drop(conn);
+
+            reopened.close().await?;
+            shared.close().await?;
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_envelope_version() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            let store = file.provision(key.as_ref()).await?;
+            let envelope_version = |store: &Store<SqliteStore>| {
+                let pool = store.inner().conn_pool.clone();
+                async move {
+                    sqlx::query_scalar::<_, String>(
+                        r#"SELECT value FROM config WHERE name = "envelope_version""#,
+                    )
+                    .fetch_optional(&pool)
+                    .await
+                }
+            };
+            let raw_values = |store: &Store<SqliteStore>| {
+                let pool = store.inner().conn_pool.clone();
+                async move {
+                    sqlx::query_scalar::<_, Vec<u8>>("SELECT value FROM items ORDER BY id")
+                        .fetch_all(&pool)
+                        .await
+                }
+            };
+
+            // values are written in the headerless layout until envelopes are enabled
+            assert_eq!(envelope_version(&store).await?, None);
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "legacy", b"value", None, None).await?;
+            drop(conn);
+
+            store.enable_value_envelopes().await?;
+            assert_eq!(
+                envelope_version(&store).await?,
+                Some(ENVELOPE_VERSION.to_string())
+            );
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "framed", b"value", None, None).await?;
+            drop(conn);
+            let values = raw_values(&store).await?;
+            assert_eq!(values[1].len(), values[0].len() + 2);
+            assert_eq!(values[1][0], ENVELOPE_VERSION);
+            store.close().await?;
+
+            // the setting is retained when the store is reopened
+            let store = file
+                .options()
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "reopened", b"value", None, None).await?;
+            for name in ["legacy", "framed", "reopened"].iter() {
+                let entry = conn.fetch("cat", name, false).await?.unwrap();
+                assert_eq!(entry.value, &b"value"[..]);
+            }
+            drop(conn);
+            let values = raw_values(&store).await?;
+            assert_eq!(values[2].len(), values[1].len());
+
+            // a store recording an envelope version from a later release is rejected
+            sqlx::query(r#"UPDATE config SET value = "99" WHERE name = "envelope_version""#)
+                .execute(&store.inner().conn_pool)
+                .await?;
+            store.close().await?;
+            let err = file
+                .options()
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect_err("Expected unsupported envelope version");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_normalize_names() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            let store = file.provision(key.as_ref()).await?;
+            let mut conn = store.session(None).await?;
+            for (category, name) in [
+                ("cat", "Alice"),
+                ("cat", "alice"),
+                ("cat", "BOB"),
+                ("Cat", "carol"),
+            ]
+            .iter()
+            {
+                conn.insert(category, name, name.as_bytes(), None, None)
+                    .await?;
+            }
+            drop(conn);
+            assert!(store.normalize_names(None).await.is_err());
+
+            // records written before the normalization recorded in the store
+            // configuration are renamed, unless they would replace another record
+            sqlx::query(
+                r#"UPDATE config SET value = "lowercase" WHERE name = "name_normalization""#,
+            )
+            .execute(&store.inner().conn_pool)
+            .await?;
+            store.close().await?;
+            let store = file
+                .options()
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            assert_eq!(store.name_normalization(), NameNormalization::Lowercase);
+            let report = store.normalize_names(None).await?;
+            assert_eq!(report.updated, 2);
+            assert_eq!(
+                report.collisions,
+                vec![NameCollision {
+                    category: "cat".to_string(),
+                    name: "alice".to_string(),
+                    records: vec![
+                        ("cat".to_string(), "alice".to_string()),
+                        ("cat".to_string(), "Alice".to_string())
+                    ],
+                }]
+            );
+            let mut conn = store.session(None).await?;
+            let entry = conn.fetch("CAT", "Bob", false).await?.unwrap();
+            assert_eq!(entry.value, &b"BOB"[..]);
+            assert_eq!(conn.count("cat", None).await?, 4);
+            drop(conn);
+
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn sqlite_access_log() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            let store = file
+                .options()
+                .client_label("host-a")
+                .provision(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await?;
+            // nothing is logged until the access log is enabled
+            assert!(store.read_access_log(None, None).await?.is_empty());
+            store.enable_access_log().await?;
+            store.close().await?;
+
+            let wrong_key = generate_raw_store_key(None)?;
+            assert!(file
+                .options()
+                .client_label("host-b")
+                .open(Some(StoreKeyMethod::RawKey), wrong_key.as_ref(), None)
+                .await
+                .is_err());
+            let mut store = file
+                .options()
+                .client_label("host-b")
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            let new_key = generate_raw_store_key(None)?;
+            store
+                .rekey(StoreKeyMethod::RawKey, new_key.as_ref())
+                .await?;
+
+            let profile = Some(store.inner().default_profile.clone());
+            let log = store.read_access_log(None, None).await?;
+            assert_eq!(
+                log.iter()
+                    .map(|entry| (
+                        entry.event,
+                        entry.client.as_deref(),
+                        entry.profile.clone(),
+                        entry.success
+                    ))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (AccessEvent::Rekey, Some("host-b"), None, true),
+                    (AccessEvent::Open, Some("host-b"), profile.clone(), true),
+                    (AccessEvent::Open, Some("host-b"), profile, false),
+                    (AccessEvent::Close, Some("host-a"), None, true),
+                ]
+            );
+            assert!(log.windows(2).all(|pair| pair[0].id > pair[1].id));
+            let recent = store.read_access_log(Some(1), None).await?;
+            assert_eq!(recent, log[..1].to_vec());
+            let future = log[0].logged_at + 60_000;
+            assert!(store.read_access_log(None, Some(future)).await?.is_empty());
+            assert_eq!(
+                store
+                    .read_access_log(None, Some(log[3].logged_at))
+                    .await?
+                    .len(),
+                4
+            );
+
+            // entries beyond the retention period are purged
+            let mut inner = store.into_inner();
+            inner.settings = Arc::new(DbSettings {
+                access_log: inner.settings.access_log.clone(),
+                access_log_retention: Some(Duration::from_secs(0)),
+                ..Default::default()
+            });
+            let store = Store::new(inner);
+            store.purge_expired().await?;
+            assert!(store.read_access_log(None, None).await?.is_empty());
+
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
 
-async fn acquire_session<'q>(
-    session: &'q mut DbSession<Sqlite>,
-) -> Result<DbSessionActive<'q, Sqlite>, Error> {
-    session.make_active(&resolve_profile_key).await
-}
+    #[test]
+    fn sqlite_discard_failed_connection() {
+        block_on(async {
+            let file = TempDbFile::new();
+            let key = generate_raw_store_key(None)?;
+            let store = file
+                .options()
+                .test_before_acquire(true)
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
 
-async fn resolve_profile_key(
-    conn: &mut PoolConnection<Sqlite>,
-    cache: Arc<KeyCache>,
-    profile: String,
-) -> Result<(ProfileId, Arc<ProfileKey>), Error> {
-    if let Some((pid, key)) = cache.get_profile(profile.as_str()).await {
-        Ok((pid, key))
-    } else {
-        if let Some(row) = sqlx::query("SELECT id, profile_key FROM profiles WHERE name=?1")
-            .bind(profile.as_str())
-            .fetch_optional(conn)
-            .await?
-        {
-            let pid = row.try_get(0)?;
-            let key = Arc::new(cache.load_key(row.try_get(1)?).await?);
-            cache.add_profile(profile, pid, key.clone()).await;
-            Ok((pid, key))
-        } else {
-            Err(err_msg!(NotFound, "Profile not found"))
-        }
+            let mut session = store.inner().session(None, false)?;
+            let mut active = acquire_session(&mut session).await?;
+            let pool_size = store.inner().conn_pool.size();
+            let err = active.check_error(SqlxError::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "disk failure",
+            )));
+            assert_eq!(err.kind(), ErrorKind::Backend);
+            // the failed connection is not returned to the pool
+            assert_eq!(store.inner().conn_pool.size(), pool_size - 1);
+            drop(active);
+            drop(session);
+
+            // new operations use a working connection
+            let mut conn = store.session(None).await?;
+            conn.insert("cat", "name", b"value", None, None).await?;
+            assert!(conn.fetch("cat", "name", false).await?.is_some());
+            drop(conn);
+
+            store.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
     }
-}
 
-async fn perform_insert<'q>(
-    active: &mut DbSessionActive<'q, Sqlite>,
-    kind: EntryKind,
-    enc_category: &[u8],
-    enc_name: &[u8],
-    enc_value: &[u8],
-    enc_tags: Option<Vec<EncEntryTag>>,
-    expiry_ms: Option<i64>,
-) -> Result<(), Error> {
-    trace!("Insert entry");
-    let done = sqlx::query(INSERT_QUERY)
-        .bind(active.profile_id)
-        .bind(kind as i16)
-        .bind(enc_category)
-        .bind(enc_name)
-        .bind(enc_value)
-        .bind(expiry_ms.map(expiry_timestamp).transpose()?)
-        .execute(active.connection_mut())
-        .await?;
-    if done.rows_affected() == 0 {
-        return Err(err_msg!(Duplicate, "Duplicate row"));
+    #[test]
+    fn sqlite_upgrade_value_type() {
+        use crate::backend::types::ManageBackend;
+
+        let fname = std::env::temp_dir().join(format!(
+            "sqlite-test-{}.db",
+            uuid::Uuid::new_v4().to_string()
+        ));
+        let fname = fname.to_str().unwrap();
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::new(fname)?
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await?;
+            let tags = [EntryTag::Encrypted("t1".to_string(), "a".to_string())];
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "name", b"value", Some(&tags[..]), None)
+                .await?;
+            drop(conn);
+
+            // rebuild the items table in the format used by version 1
+            let mut sql_conn = db.inner().conn_pool.acquire().await?;
+            sqlx::query(
+                r#"PRAGMA foreign_keys = OFF;
+                CREATE TABLE items_v1 AS
+                    SELECT id, profile_id, kind, category, name, value, expiry FROM items;
+                DROP TABLE items;
+                ALTER TABLE items_v1 RENAME TO items;
+                CREATE TABLE items_tags_v1 AS
+                    SELECT id, item_id, name, value, plaintext FROM items_tags;
+                DROP TABLE items_tags;
+                ALTER TABLE items_tags_v1 RENAME TO items_tags;
+                UPDATE config SET value = "1" WHERE name = "version";
+                PRAGMA foreign_keys = ON;"#,
+            )
+            .persistent(false)
+            .execute(&mut sql_conn)
+            .await?;
+            // fail the third step after its schema change, when it records the
+            // new schema version
+            sqlx::query(
+                r#"CREATE TRIGGER fail_migration BEFORE UPDATE ON config
+                WHEN NEW.name = "version" AND NEW.value = "3"
+                BEGIN SELECT RAISE(ABORT, "Injected failure"); END"#,
+            )
+            .persistent(false)
+            .execute(&mut sql_conn)
+            .await?;
+            drop(sql_conn);
+            db.close().await?;
+
+            let db = SqliteStoreOptions::new(fname)?
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            let version: String =
+                sqlx::query_scalar(r#"SELECT value FROM config WHERE name = "version""#)
+                    .fetch_one(&db.inner().conn_pool)
+                    .await?;
+            assert_eq!(version, "4");
+            // the usage counters are computed from the existing records
+            let usage = db.profile_usage(None).await?;
+            assert_eq!((usage.items, usage.bytes > 0), (1, true));
+            let mut conn = db.session(None).await?;
+            let entry = conn.fetch("cat", "name", false).await?.unwrap();
+            assert_eq!(&entry.value[..], b"value");
+            assert_eq!(entry.value_type, None);
+            assert_eq!(entry.tags, tags.to_vec());
+            drop(conn);
+            db.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+        block_on(SqliteStoreOptions::new(fname).unwrap().remove_backend()).unwrap();
     }
-    let row_id = done.last_insert_rowid();
-    if let Some(tags) = enc_tags {
-        for tag in tags {
-            sqlx::query(TAG_INSERT_QUERY)
-                .bind(row_id)
-                .bind(&tag.name)
-                .bind(&tag.value)
-                .bind(tag.plaintext as i16)
-                .execute(active.connection_mut())
+
+    #[test]
+    fn sqlite_health_unhealthy() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
                 .await?;
-        }
+            let deadline = Duration::from_secs(30);
+            assert_eq!(db.health(deadline).await, HealthStatus::Healthy);
+
+            sqlx::query("UPDATE config SET value = '3' WHERE name = 'version'")
+                .execute(&db.inner().conn_pool)
+                .await?;
+            assert!(matches!(
+                db.health(deadline).await,
+                HealthStatus::Unhealthy(reason) if reason.contains("store version")
+            ));
+            // the connection is still usable
+            db.ping(deadline).await?;
+
+            sqlx::query("UPDATE config SET value = '4' WHERE name = 'version'")
+                .execute(&db.inner().conn_pool)
+                .await?;
+            sqlx::query("UPDATE profiles SET profile_key = X'00'")
+                .execute(&db.inner().conn_pool)
+                .await?;
+            assert!(!db.health(deadline).await.is_available());
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
     }
-    Ok(())
-}
 
-async fn perform_remove<'q>(
-    active: &mut DbSessionActive<'q, Sqlite>,
-    kind: EntryKind,
-    enc_category: &[u8],
-    enc_name: &[u8],
-    ignore_error: bool,
-) -> Result<(), Error> {
-    trace!("Remove entry");
-    let done = sqlx::query(DELETE_QUERY)
-        .bind(active.profile_id)
-        .bind(kind as i16)
-        .bind(enc_category)
-        .bind(enc_name)
-        .execute(active.connection_mut())
-        .await?;
-    if done.rows_affected() == 0 && !ignore_error {
-        Err(err_msg!(NotFound, "Entry not found"))
-    } else {
-        Ok(())
+    #[test]
+    fn sqlite_temp_scope_expired() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mut scope = db.temp_scope(None, Duration::from_secs(60))?;
+            scope.update("category", "name", b"value", None).await?;
+            scope.update("category", "other", b"value", None).await?;
+
+            // simulate the passing of the scope TTL, as after a crash
+            sqlx::query("UPDATE items SET expiry = DATETIME('now', '-1 minute') WHERE kind = ?1")
+                .bind(EntryKind::Temp as i16)
+                .execute(&db.inner().conn_pool)
+                .await?;
+            assert_eq!(db.purge_expired().await?, 2);
+            let temp_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items WHERE kind = ?1")
+                .bind(EntryKind::Temp as i16)
+                .fetch_one(&db.inner().conn_pool)
+                .await?;
+            assert_eq!(temp_count, 0);
+
+            let err = scope.commit().await.expect_err("Expected expired scope");
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            let mut conn = db.session(None).await?;
+            assert_eq!(conn.count("category", None).await?, 0);
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
     }
-}
 
-fn perform_scan<'q>(
-    mut active: DbSessionRef<'q, Sqlite>,
-    profile_id: ProfileId,
-    key: Arc<ProfileKey>,
-    kind: EntryKind,
-    category: String,
-    tag_filter: Option<TagFilter>,
-    offset: Option<i64>,
-    limit: Option<i64>,
-) -> impl Stream<Item = Result<Vec<EncScanEntry>, Error>> + 'q {
-    try_stream! {
-        let mut params = QueryParams::new();
-        params.push(profile_id);
-        params.push(kind as i16);
-        let (enc_category, tag_filter) = unblock({
-            let key = key.clone();
-            let category = ProfileKey::prepare_input(category.as_bytes());
-            let params_len = params.len() + 1; // plus category
-            move || {
-                Result::<_, Error>::Ok((
-                    key.encrypt_entry_category(category)?,
-                    encode_tag_filter::<SqliteStore>(tag_filter, &key, params_len)?
-                ))
-            }
-        }).await?;
-        params.push(enc_category);
-        let query = extend_query::<SqliteStore>(SCAN_QUERY, &mut params, tag_filter, offset, limit)?;
+    #[test]
+    fn sqlite_health_scan_panic() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let deadline = Duration::from_secs(30);
+            db.inner().settings.scans.panic_while_locked();
+            assert!(matches!(
+                db.health(deadline).await,
+                HealthStatus::Degraded(reason) if reason.contains("reopened")
+            ));
 
-        let mut batch = Vec::with_capacity(PAGE_SIZE);
+            // scans continue to be tracked without blocking
+            let mut scan = db
+                .scan(None, "category".to_string(), None, None, None)
+                .await?;
+            assert_eq!(db.inner().settings.scans.active().len(), 1);
+            assert!(scan.fetch_next().await?.is_none());
+            drop(scan);
+            assert!(db.inner().settings.scans.active().is_empty());
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+    }
 
-        let mut acquired = acquire_session(&mut *active).await?;
-        let mut rows = sqlx::query_with(query.as_str(), params).fetch(acquired.connection_mut());
-        while let Some(row) = rows.try_next().await? {
-            batch.push(EncScanEntry {
-                name: row.try_get(1)?, value: row.try_get(2)?, tags: row.try_get(3)?
+    #[test]
+    fn sqlite_scan_limit() {
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mut inner = db.into_inner();
+            inner.settings = Arc::new(DbSettings {
+                scans: Arc::new(ScanTracker::new(2)),
+                ..Default::default()
             });
-            if batch.len() == PAGE_SIZE {
-                yield batch.split_off(0);
-            }
-        }
-        drop(rows);
-        drop(acquired);
-        drop(active);
+            let db = Store::new(inner);
 
-        if !batch.is_empty() {
-            yield batch;
-        }
+            let scan_a = db
+                .scan(None, "category".to_string(), None, None, None)
+                .await?;
+            let _scan_b = db
+                .scan(None, "category".to_string(), None, None, None)
+                .await?;
+            let err = db
+                .scan(None, "category".to_string(), None, None, None)
+                .await
+                .expect_err("Expected scan limit error");
+            assert_eq!(err.kind(), ErrorKind::ResourceLimit);
+            assert!(err.message().unwrap().contains("askar_scan_free"));
+            let diag = db.diagnostics().await?;
+            assert_eq!(diag.active_scans.len(), 2);
+            assert_eq!(diag.max_scans, 2);
+
+            // a scan may be started again once another is released
+            drop(scan_a);
+            let _scan_c = db
+                .scan(None, "category".to_string(), None, None, None)
+                .await?;
+            assert_eq!(db.diagnostics().await?.active_scans.len(), 2);
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::backend::db_utils::replace_arg_placeholders;
-    use crate::future::block_on;
-    use crate::protect::{generate_raw_store_key, StoreKeyMethod};
+    #[test]
+    fn sqlite_migrate_resume() {
+        use crate::backend::types::ManageBackend;
+        use sqlx::Connection;
+
+        let fname = std::env::temp_dir().join(format!(
+            "sqlite-test-{}.db",
+            uuid::Uuid::new_v4().to_string()
+        ));
+        let fname = fname.to_str().unwrap();
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::new(fname)?
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await?;
+            let tags = [EntryTag::Encrypted("t1".to_string(), "a".to_string())];
+            let mut conn = db.session(None).await?;
+            conn.insert("cat", "first", b"value", Some(&tags[..]), None)
+                .await?;
+            conn.insert("cat", "second", b"value", None, None).await?;
+            drop(conn);
+
+            // rebuild the items tables in the format used by version 1
+            let mut sql_conn = db.inner().conn_pool.acquire().await?;
+            sqlx::query(
+                r#"PRAGMA foreign_keys = OFF;
+                CREATE TABLE items_v1 AS
+                    SELECT id, profile_id, kind, category, name, value, expiry FROM items;
+                DROP TABLE items;
+                ALTER TABLE items_v1 RENAME TO items;
+                CREATE TABLE items_tags_v1 AS
+                    SELECT id, item_id, name, value, plaintext FROM items_tags;
+                DROP TABLE items_tags;
+                ALTER TABLE items_tags_v1 RENAME TO items_tags;
+                UPDATE config SET value = "1" WHERE name = "version";
+                PRAGMA foreign_keys = ON;"#,
+            )
+            .persistent(false)
+            .execute(&mut sql_conn)
+            .await?;
+            // fail the third step after its schema change, when it records the
+            // new schema version
+            sqlx::query(
+                r#"CREATE TRIGGER fail_migration BEFORE UPDATE ON config
+                WHEN NEW.name = "version" AND NEW.value = "3"
+                BEGIN SELECT RAISE(ABORT, "Injected failure"); END"#,
+            )
+            .persistent(false)
+            .execute(&mut sql_conn)
+            .await?;
+            drop(sql_conn);
+            db.close().await?;
+
+            let opts = SqliteStoreOptions::new(fname)?;
+            let plan = opts.plan_migrations().await?;
+            assert_eq!(
+                plan.iter()
+                    .map(|step| (step.version, step.affected_rows))
+                    .collect::<Vec<_>>(),
+                vec![(2, 2), (3, 1), (4, 2)]
+            );
+            // planning does not apply any of the steps
+            assert_eq!(opts.plan_migrations().await?, plan);
+
+            // a step which fails is rolled back, after the previous steps have
+            // been committed
+            let mut completed = vec![];
+            let err = opts
+                .migrate(|step| {
+                    completed.push(step.version);
+                    Ok(())
+                })
+                .await
+                .expect_err("Expected migration failure");
+            assert_eq!(err.kind(), ErrorKind::Backend);
+            assert_eq!(completed, vec![2]);
+            assert_eq!(opts.plan_migrations().await?, plan[1..].to_vec());
+            let mut sql_conn = SqliteConnection::connect(&format!("sqlite://{}", fname)).await?;
+            sqlx::query("DROP TRIGGER fail_migration")
+                .execute(&mut sql_conn)
+                .await?;
+            sql_conn.close().await?;
+
+            // interrupt the migration once the failed step is complete
+            let mut completed = vec![];
+            let err = opts
+                .migrate(|step| {
+                    completed.push(step.version);
+                    if step.version == 3 {
+                        Err(err_msg!(Unexpected, "Injected failure"))
+                    } else {
+                        Ok(())
+                    }
+                })
+                .await
+                .expect_err("Expected migration failure");
+            assert_eq!(err.kind(), ErrorKind::Unexpected);
+            assert_eq!(completed, vec![3]);
+
+            // the migration resumes from the first incomplete step
+            let opts = SqliteStoreOptions::new(fname)?;
+            assert_eq!(opts.plan_migrations().await?, plan[2..].to_vec());
+            let applied = opts.migrate(|_| Ok(())).await?;
+            assert_eq!(applied, plan[2..].to_vec());
+            assert!(opts.plan_migrations().await?.is_empty());
+            assert!(opts.migrate(|_| Ok(())).await?.is_empty());
+
+            let db = opts
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            assert!(db.verify_integrity().await?.is_ok());
+            let usage = db.profile_usage(None).await?;
+            assert_eq!(usage.items, 2);
+            let mut conn = db.session(None).await?;
+            let entry = conn.fetch("cat", "first", false).await?.unwrap();
+            assert_eq!(&entry.value[..], b"value");
+            assert_eq!(entry.tags, tags.to_vec());
+            assert_eq!(conn.count("cat", None).await?, 2);
+            drop(conn);
+            db.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+        block_on(SqliteStoreOptions::new(fname).unwrap().remove_backend()).unwrap();
+    }
 
     #[test]
-    fn sqlite_check_expiry_timestamp() {
+    fn sqlite_external_key_session() {
+        use crate::backend::types::ManageBackend;
+        use crate::protect::parse_raw_store_key;
+
+        let fname = std::env::temp_dir().join(format!(
+            "sqlite-test-{}.db",
+            uuid::Uuid::new_v4().to_string()
+        ));
+        let fname = fname.to_str().unwrap();
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let db = SqliteStoreOptions::new(fname)?
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await?;
+            let profile = db.create_profile(Some("restored".into())).await?;
+            let mut conn = db.session(Some(profile.clone())).await?;
+            conn.insert("cat", "name", b"value", None, None).await?;
+            drop(conn);
+
+            // wrap the profile key with another store key, as for a profile
+            // restored from a backup of a store which has since been rekeyed
+            let backup_key = generate_raw_store_key(None)?;
+            let enc_key: Vec<u8> =
+                sqlx::query_scalar("SELECT profile_key FROM profiles WHERE name = ?1")
+                    .bind(&profile)
+                    .fetch_one(&db.inner().conn_pool)
+                    .await?;
+            let profile_key = db.inner().key_cache.store_key.unwrap_data(enc_key)?;
+            let enc_key = parse_raw_store_key(&*backup_key)?.wrap_data(profile_key)?;
+            sqlx::query("UPDATE profiles SET profile_key = ?1 WHERE name = ?2")
+                .bind(enc_key)
+                .bind(&profile)
+                .execute(&db.inner().conn_pool)
+                .await?;
+            db.close().await?;
+
+            let db = SqliteStoreOptions::new(fname)?
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await?;
+            let mut conn = db
+                .session_with_external_key(Some(profile.clone()), backup_key.as_ref(), false)
+                .await?;
+            let entry = conn.fetch("cat", "name", false).await?.unwrap();
+            assert_eq!(&entry.value[..], b"value");
+            drop(conn);
+            // the external key does not apply to other sessions
+            let mut conn = db.session(Some(profile.clone())).await?;
+            let err = conn.fetch("cat", "name", false).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+            drop(conn);
+            let err = db
+                .session_with_external_key(Some(profile), "not a key".into(), false)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Input);
+            db.close().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+        block_on(SqliteStoreOptions::new(fname).unwrap().remove_backend()).unwrap();
+    }
+
+    // a plaintext tag equality filter, as encoded for the fourth and following arguments
+    const PLAIN_TAG_FILTER: &'static str = "i.id IN (SELECT item_id FROM items_tags
+        WHERE name = ?4 AND value = ?5 AND value_type != 1 AND plaintext = 1)";
+
+    #[test]
+    fn sqlite_query_hint() {
+        let mut params = QueryParams::with_capacity(5);
+        let query = extend_query::<SqliteStore>(
+            SCAN_QUERY.as_str(),
+            &mut params,
+            Some((
+                PLAIN_TAG_FILTER.to_string(),
+                vec![b"name".to_vec(), b"value".to_vec()],
+            )),
+            Some(QueryHint::PreferTagIndex),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(query.contains(" FROM items i NOT INDEXED WHERE "));
+        // the hint is only applied along with a tag filter
+        let query = extend_query::<SqliteStore>(
+            SCAN_QUERY.as_str(),
+            &mut params,
+            None,
+            Some(QueryHint::PreferTagIndex),
+            Some(0),
+            Some(10),
+        )
+        .unwrap();
+        assert!(!query.contains("NOT INDEXED"));
+        let query =
+            SqliteStore::hint_query(DELETE_ALL_QUERY.to_string(), QueryHint::PreferTagIndex);
+        assert!(query.starts_with("DELETE FROM items AS i NOT INDEXED WHERE "));
+    }
+
+    // Compare the plans for a selective tag filter with and without the index hint,
+    // over a generated store of `ASKAR_QUERY_PLAN_ROWS` records. This is skipped
+    // unless the variable is set, as generating a large store is slow
+    #[test]
+    fn sqlite_query_hint_plan() {
+        let rows: i64 = match std::env::var("ASKAR_QUERY_PLAN_ROWS") {
+            Ok(rows) => rows
+                .parse()
+                .expect("Invalid value for ASKAR_QUERY_PLAN_ROWS"),
+            Err(_) => return,
+        };
         block_on(async {
             let key = generate_raw_store_key(None)?;
             let db = SqliteStoreOptions::in_memory()
                 .provision(StoreKeyMethod::RawKey, key, None, false)
                 .await?;
-            let ts = expiry_timestamp(1000).unwrap();
-            let check = sqlx::query("SELECT datetime('now'), ?1, ?1 > datetime('now')")
-                .bind(ts)
-                .fetch_one(&db.inner().conn_pool)
+            let pool = &db.inner().conn_pool;
+            let profile_id: ProfileId = sqlx::query_scalar("SELECT id FROM profiles")
+                .fetch_one(pool)
                 .await?;
-            let now: String = check.try_get(0)?;
-            let cmp_ts: String = check.try_get(1)?;
-            let cmp: bool = check.try_get(2)?;
-            if !cmp {
-                panic!("now ({}) > expiry timestamp ({})", now, cmp_ts);
+            sqlx::query(
+                "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < ?3)
+                INSERT INTO items (profile_id, kind, category, name, value)
+                SELECT ?1, ?2, X'00', CAST(n AS BLOB), X'00' FROM seq",
+            )
+            .bind(profile_id)
+            .bind(EntryKind::Item as i16)
+            .bind(rows)
+            .execute(pool)
+            .await?;
+            // each tag value is shared by ten records
+            sqlx::query(
+                "INSERT INTO items_tags (item_id, name, value, plaintext)
+                SELECT id, X'01', CAST(id / 10 AS BLOB), 1 FROM items",
+            )
+            .execute(pool)
+            .await?;
+            sqlx::query("ANALYZE").execute(pool).await?;
+
+            let mut plans = vec![];
+            for hint in [None, Some(QueryHint::PreferTagIndex)].iter() {
+                let mut params = QueryParams::with_capacity(5);
+                params.push(profile_id);
+                params.push(EntryKind::Item as i16);
+                params.push(vec![0u8]);
+                let query = extend_query::<SqliteStore>(
+                    SCAN_QUERY.as_str(),
+                    &mut params,
+                    Some((
+                        PLAIN_TAG_FILTER.to_string(),
+                        vec![vec![1u8], b"42".to_vec()],
+                    )),
+                    *hint,
+                    None,
+                    None,
+                )?;
+                let plan = sqlx::query_with(&format!("EXPLAIN QUERY PLAN {}", query), params)
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .map(|row| row.try_get::<String, _>(3))
+                    .collect::<Result<Vec<_>, _>>()?;
+                info!("Query plan with hint {:?}: {:?}", hint, plan);
+                plans.push(plan);
             }
+            let hinted = &plans[1];
+            assert!(hinted
+                .iter()
+                .any(|step| step.contains("ix_items_tags_name_plain")));
+            assert!(!hinted.iter().any(|step| step.contains("ix_items_uniq")));
             Result::<_, Error>::Ok(())
         })
         .unwrap();
     }
 
+    #[test]
+    fn sqlite_fetch_tags_query() {
+        let all = fetch_tags_query(TagRetrieval::All);
+        assert!(all.contains("HEX(it.value)"));
+        assert!(!all.contains("tag_count"));
+
+        let plaintext = fetch_tags_query(TagRetrieval::PlaintextOnly);
+        assert!(plaintext.contains("it.plaintext = 1"));
+        assert!(plaintext.contains("AS tag_count"));
+
+        let count = fetch_tags_query(TagRetrieval::CountOnly);
+        assert!(!count.contains("it.name"));
+        assert!(!count.contains("it.value"));
+        assert!(count.contains("AS tag_count"));
+
+        let none = fetch_tags_query(TagRetrieval::None);
+        assert!(!none.contains("items_tags"));
+
+        assert_eq!(TagRetrieval::from(true), TagRetrieval::All);
+        assert_eq!(TagRetrieval::from(false), TagRetrieval::None);
+    }
+
     #[test]
     fn sqlite_query_placeholders() {
         assert_eq!(
@@ -0,0 +1,333 @@
+use std::{
+    os::raw::{c_char, c_int, c_void},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use libsqlite3_sys::{
+    sqlite3_set_authorizer, SQLITE_DENY, SQLITE_FUNCTION, SQLITE_OK, SQLITE_READ, SQLITE_RECURSIVE,
+    SQLITE_SELECT,
+};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteRow},
+    ConnectOptions, Connection, Error as SqlxError, Row, TypeInfo, ValueRef,
+};
+
+use super::{acquire_key, acquire_session, SqliteStore};
+#[cfg(feature = "blob_store")]
+use crate::backend::blob::blob_id;
+use crate::{
+    backend::Backend,
+    error::Error,
+    storage::{Entry, EntryKind, Scan, Store, TagFilter},
+};
+
+const RAW_ITEM_QUERY: &'static str = "SELECT id, kind, category, name, value, value_type,
+    expiry FROM items WHERE profile_id = ?1 AND id = ?2";
+const REMOVE_ROW_QUERY: &'static str = "DELETE FROM items WHERE profile_id = ?1 AND id = ?2";
+
+/// The views available to raw queries, which replace the tables of the same name
+/// and are limited to the records of the selected profile. The `{}` placeholder
+/// is replaced by the profile ID.
+const RAW_QUERY_VIEWS: &[(&str, &str)] = &[
+    ("items", "SELECT * FROM main.items WHERE profile_id = {}"),
+    (
+        "items_tags",
+        "SELECT * FROM main.items_tags WHERE item_id IN
+            (SELECT id FROM main.items WHERE profile_id = {})",
+    ),
+];
+
+// the reasons for an access to be denied by the raw query authorizer
+const DENIED_READ: u8 = 1;
+const DENIED_WRITE: u8 = 2;
+
+/// A record of the SQLite backend as it is stored, with its category, name and
+/// value still encrypted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawItem {
+    /// The row ID of the record
+    pub id: i64,
+    /// The kind of entry, as stored
+    pub kind: i64,
+    /// The encrypted category
+    pub category: Vec<u8>,
+    /// The encrypted name
+    pub name: Vec<u8>,
+    /// The encrypted value, or a reference to an external blob
+    pub value: Vec<u8>,
+    /// The encrypted value type, if any
+    pub value_type: Option<Vec<u8>>,
+    /// The expiry time of the record, if any
+    pub expiry: Option<String>,
+}
+
+/// An untyped value passed to or returned from a raw query
+#[derive(Clone, Debug, PartialEq)]
+pub enum RawValue {
+    /// A NULL value
+    Null,
+    /// An integer value
+    Integer(i64),
+    /// A floating point value
+    Real(f64),
+    /// A text value
+    Text(String),
+    /// A binary value
+    Blob(Vec<u8>),
+}
+
+impl Store<SqliteStore> {
+    /// Run a read-only SQL query against the store, returning the untyped rows.
+    ///
+    /// Only a single `SELECT` statement is accepted. The query runs on a
+    /// dedicated read-only connection, where the `items` and `items_tags` tables
+    /// are replaced by views of the records of the selected profile, and any
+    /// other table is inaccessible. The ID of the selected profile is bound to the
+    /// first parameter (`?1`), and the provided parameters follow it. Encrypted
+    /// columns such as item categories, names and values are returned as
+    /// ciphertext. Attempts to modify the store result in an `Unsupported` error.
+    ///
+    /// Raw queries are not supported for in-memory stores.
+    pub async fn query_raw_readonly(
+        &self,
+        profile: Option<String>,
+        sql: &str,
+        params: Vec<RawValue>,
+    ) -> Result<Vec<Vec<RawValue>>, Error> {
+        check_raw_query(sql)?;
+        if self.inner().path == ":memory:" {
+            return Err(err_msg!(
+                Unsupported,
+                "Raw queries are not supported for in-memory stores"
+            ));
+        }
+        let mut session = self.inner().session(profile, false)?;
+        let (profile_id, _) = acquire_key(&mut session).await?;
+        drop(session);
+
+        let mut conn = SqliteConnectOptions::from_str(&self.inner().path)?
+            .read_only(true)
+            .connect()
+            .await?;
+        // temporary objects may be created by a read-only connection
+        for (name, view) in RAW_QUERY_VIEWS {
+            sqlx::query(&format!(
+                "CREATE TEMP VIEW {} AS {}",
+                name,
+                view.replace("{}", &profile_id.to_string())
+            ))
+            .execute(&mut conn)
+            .await?;
+        }
+        // the reference is released once the connection has been closed
+        let denied = Arc::new(AtomicU8::new(0));
+        let denied_ptr = Arc::into_raw(denied.clone());
+        let rc = unsafe {
+            sqlite3_set_authorizer(
+                conn.as_raw_handle(),
+                Some(authorize_raw_query),
+                denied_ptr as *mut c_void,
+            )
+        };
+        if rc != SQLITE_OK {
+            unsafe { Arc::from_raw(denied_ptr) };
+            return Err(err_msg!(
+                Backend,
+                "Error installing the raw query authorizer"
+            ));
+        }
+
+        let mut query = sqlx::query(sql).persistent(false).bind(profile_id);
+        for param in params {
+            query = match param {
+                RawValue::Null => query.bind(Option::<Vec<u8>>::None),
+                RawValue::Integer(value) => query.bind(value),
+                RawValue::Real(value) => query.bind(value),
+                RawValue::Text(value) => query.bind(value),
+                RawValue::Blob(value) => query.bind(value),
+            };
+        }
+        let result = query.fetch_all(&mut conn).await;
+        conn.close().await?;
+        unsafe { Arc::from_raw(denied_ptr) };
+
+        let rows = match result {
+            Ok(rows) => rows,
+            Err(SqlxError::Database(db_err))
+                if db_err.code().as_deref() == Some("23")
+                    && denied.load(Ordering::Acquire) == DENIED_READ =>
+            {
+                // SQLITE_AUTH error
+                return Err(err_msg!(
+                    Input,
+                    "Raw queries may only access the records of the selected profile"
+                ));
+            }
+            Err(SqlxError::Database(db_err))
+                if matches!(db_err.code().as_deref(), Some("8") | Some("23")) =>
+            {
+                // SQLITE_READONLY or SQLITE_AUTH error
+                return Err(err_msg!(
+                    Unsupported,
+                    "Raw queries are not permitted to modify the store"
+                ));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        rows.iter().map(decode_row).collect()
+    }
+
+    /// Create a scan of a category in order of row ID, returning the row ID of
+    /// each record along with the record.
+    ///
+    /// Row IDs are specific to the SQLite backend and to a single database file,
+    /// and are intended for diagnostics and repair.
+    pub async fn scan_with_row_ids(
+        &self,
+        profile: Option<String>,
+        category: String,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<Scan<'static, (i64, Entry)>, Error> {
+        let category = self
+            .inner()
+            .settings
+            .name_normalization()
+            .apply(&category)
+            .into_owned();
+        let scan = self
+            .inner()
+            .scan_resumable(profile, EntryKind::Item, category, tag_filter, None)
+            .await?;
+        Ok(scan.into_inner())
+    }
+
+    /// Fetch a record of the selected profile by its row ID without decrypting it.
+    ///
+    /// This allows a record which can no longer be decrypted to be inspected.
+    pub async fn fetch_raw_by_row_id(
+        &self,
+        profile: Option<String>,
+        id: i64,
+    ) -> Result<Option<RawItem>, Error> {
+        let mut session = self.inner().session(profile, false)?;
+        let (profile_id, _) = acquire_key(&mut session).await?;
+        let mut active = acquire_session(&mut session).await?;
+        let row = sqlx::query(RAW_ITEM_QUERY)
+            .bind(profile_id)
+            .bind(id)
+            .fetch_optional(active.connection_mut())
+            .await?;
+        row.map(|row| {
+            Ok(RawItem {
+                id: row.try_get(0)?,
+                kind: row.try_get(1)?,
+                category: row.try_get(2)?,
+                name: row.try_get(3)?,
+                value: row.try_get(4)?,
+                value_type: row.try_get(5)?,
+                expiry: row.try_get(6)?,
+            })
+        })
+        .transpose()
+    }
+
+    /// Remove a record of the selected profile by its row ID, along with its tags,
+    /// returning whether the record was found.
+    ///
+    /// This allows a record which can no longer be decrypted to be removed. The
+    /// value history is not updated.
+    pub async fn remove_by_row_id(&self, profile: Option<String>, id: i64) -> Result<bool, Error> {
+        let mut session = self.inner().session(profile, false)?;
+        let (profile_id, _) = acquire_key(&mut session).await?;
+        let mut active = acquire_session(&mut session).await?;
+        let mut txn = active.as_transaction().await?;
+        let row = sqlx::query(RAW_ITEM_QUERY)
+            .bind(profile_id)
+            .bind(id)
+            .fetch_optional(txn.connection_mut())
+            .await?;
+        if row.is_some() {
+            sqlx::query(REMOVE_ROW_QUERY)
+                .bind(profile_id)
+                .bind(id)
+                .execute(txn.connection_mut())
+                .await?;
+        }
+        txn.commit().await?;
+        if row.is_some() {
+            active.invalidate_cached(None);
+        }
+        drop(active);
+        #[cfg(feature = "blob_store")]
+        if let (Some(row), Some(blobs)) = (row.as_ref(), self.inner().blobs.as_ref()) {
+            // the record is already removed, so a sidecar file which cannot be
+            // removed is only logged
+            let blob_ids = row.try_get::<&[u8], _>(4).ok().and_then(blob_id);
+            blobs.remove_all(blob_ids.into_iter().map(str::to_string).collect());
+        }
+        Ok(row.is_some())
+    }
+}
+
+fn check_raw_query(sql: &str) -> Result<(), Error> {
+    let sql = sql.trim().trim_end_matches(';').to_ascii_lowercase();
+    if sql.contains(';') {
+        return Err(err_msg!(
+            Input,
+            "Raw queries must consist of a single statement"
+        ));
+    }
+    if !sql.starts_with("select") && !sql.starts_with("with") {
+        return Err(err_msg!(Input, "Raw queries must be SELECT statements"));
+    }
+    Ok(())
+}
+
+/// Permit only the reading of the profile views in a raw query. Any table read
+/// outside of a view, including the store configuration and profile keys, is
+/// denied along with all modifications. A denied modification takes precedence
+/// when reporting the error.
+extern "C" fn authorize_raw_query(
+    denied: *mut c_void,
+    action: c_int,
+    _arg1: *const c_char,
+    _arg2: *const c_char,
+    _db_name: *const c_char,
+    view: *const c_char,
+) -> c_int {
+    let denied = unsafe { &*(denied as *const AtomicU8) };
+    match action {
+        SQLITE_SELECT | SQLITE_FUNCTION | SQLITE_RECURSIVE => SQLITE_OK,
+        SQLITE_READ if !view.is_null() => SQLITE_OK,
+        SQLITE_READ => {
+            denied.fetch_max(DENIED_READ, Ordering::AcqRel);
+            SQLITE_DENY
+        }
+        _ => {
+            denied.fetch_max(DENIED_WRITE, Ordering::AcqRel);
+            SQLITE_DENY
+        }
+    }
+}
+
+fn decode_row(row: &SqliteRow) -> Result<Vec<RawValue>, Error> {
+    (0..row.len())
+        .map(|idx| {
+            let value = row.try_get_raw(idx)?;
+            if value.is_null() {
+                return Ok(RawValue::Null);
+            }
+            let type_name = value.type_info().name().to_string();
+            Ok(match type_name.as_str() {
+                "INTEGER" | "BOOLEAN" => RawValue::Integer(row.try_get_unchecked(idx)?),
+                "REAL" => RawValue::Real(row.try_get_unchecked(idx)?),
+                "BLOB" => RawValue::Blob(row.try_get_unchecked(idx)?),
+                _ => RawValue::Text(row.try_get_unchecked(idx)?),
+            })
+        })
+        .collect()
+}
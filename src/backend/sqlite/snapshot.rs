@@ -0,0 +1,63 @@
+use std::fs::metadata;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::Path;
+
+use super::SqliteStore;
+use crate::{error::Error, storage::Store};
+
+const SNAPSHOT_QUERY: &'static str = "VACUUM INTO ?1";
+
+impl Store<SqliteStore> {
+    /// Write a consistent copy of the store to a new database file, while other
+    /// operations on the store continue.
+    ///
+    /// The copy includes the store configuration and the wrapped profile keys,
+    /// and may be opened as a separate store with the same pass key. It keeps
+    /// the ID of the store, so that it may be identified as a copy. Records
+    /// written after the snapshot begins are not included. The destination
+    /// file must not already exist.
+    pub async fn snapshot_to(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let path_str = path.to_str().ok_or_else(|| {
+            err_msg!(
+                Input,
+                "The snapshot path is not valid UTF-8: '{}'",
+                path.display()
+            )
+        })?;
+        match metadata(path) {
+            Ok(_) => {
+                return Err(err_msg!(
+                    Duplicate,
+                    "The snapshot path already exists: '{}'",
+                    path_str
+                ))
+            }
+            Err(err) if err.kind() == IoErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(
+                    err_msg!(Backend, "Error accessing the snapshot path: '{}'", path_str)
+                        .with_cause(err),
+                )
+            }
+        }
+
+        let backend = self.inner();
+        #[cfg(feature = "blob_store")]
+        if backend.blobs.is_some() {
+            return Err(err_msg!(
+                Unsupported,
+                "Snapshots are not supported for a store with external blob storage"
+            ));
+        }
+
+        // the copy is read within a single transaction, so that it reflects
+        // the state of the database at one point in time
+        let mut conn = backend.conn_pool.acquire().await?;
+        sqlx::query(SNAPSHOT_QUERY)
+            .bind(path_str)
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+}
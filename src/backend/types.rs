@@ -1,8 +1,18 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
 use crate::{
+    crypto::buffer::SecretBytes,
     error::Error,
     future::BoxFuture,
-    protect::{PassKey, StoreKeyMethod},
-    storage::{Entry, EntryKind, EntryOperation, EntryTag, Scan, TagFilter},
+    protect::{EncryptionPolicy, PassKey, StoreKey, StoreKeyMethod},
+    storage::{
+        AccessLogEntry, ConditionalFetch, DecryptionAudit, DecryptionAuditOptions, Entry,
+        EntryAccess, EntryKind, EntryOperation, EntryTag, EntryVersion, ExpiryFilter, ExpiryUpdate,
+        FilteredFetch, HealthStatus, IntegrityReport, NameNormalization, ProfileQuota,
+        ProfileUsage, QueryHint, ResumableScan, Scan, ScanCursor, StoreDiagnostics, StoreInfo,
+        TagFilter, TagRetrieval, TagStats, TaggedEntry,
+    },
 };
 
 /// Represents a generic backend implementation
@@ -11,28 +21,269 @@ pub trait Backend: Send + Sync {
     type Session: QueryBackend;
 
     /// Create a new profile
-    fn create_profile(&self, name: Option<String>) -> BoxFuture<'_, Result<String, Error>>;
+    fn create_profile(
+        &self,
+        name: Option<String>,
+        policy: EncryptionPolicy,
+    ) -> BoxFuture<'_, Result<String, Error>>;
 
     /// Get the name of the active profile
     fn get_profile_name(&self) -> &str;
 
+    /// Load the key of a profile into the key cache, failing with a `NotFound`
+    /// error naming the profile if it does not exist
+    fn load_profile(&self, name: String) -> BoxFuture<'_, Result<(), Error>>;
+
     /// Remove an existing profile
     fn remove_profile(&self, name: String) -> BoxFuture<'_, Result<bool, Error>>;
 
-    /// Create a [`Scan`] against the store
+    /// List the names of the profiles in the store, in sorted order
+    fn list_profiles(&self) -> BoxFuture<'_, Result<Vec<String>, Error>>;
+
+    /// Remove all items from a profile, optionally limited to a set of categories,
+    /// returning the number of items removed per category. The profile itself and
+    /// any stored keys are retained.
+    fn truncate_profile(
+        &self,
+        profile: Option<String>,
+        categories: Option<Vec<String>>,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, i64>, Error>>;
+
+    /// List the distinct categories of the records in a profile, in sorted order
+    fn list_categories(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>>;
+
+    /// List the distinct categories of the records in a profile which begin
+    /// with a `::`-separated prefix, in sorted order
+    fn list_categories_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category_prefix: String,
+    ) -> BoxFuture<'_, Result<Vec<String>, Error>>;
+
+    /// Create a [`Scan`] against the store, applying any index hint to the tag filter
     fn scan(
         &self,
         profile: Option<String>,
         kind: EntryKind,
         category: String,
         tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        hint: Option<QueryHint>,
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>>;
 
+    /// Create a [`Scan`] over the records whose names begin with a prefix
+    /// ending in `/`. The name index must be enabled for the store
+    fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> BoxFuture<'_, Result<Scan<'static, Entry>, Error>>;
+
+    /// Create a [`ResumableScan`] in order of record ID, continuing after the
+    /// position of a cursor if provided. The cursor must have been created by a
+    /// scan of the same profile, category and tag filter
+    fn scan_resumable(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        cursor: Option<ScanCursor>,
+    ) -> BoxFuture<'_, Result<ResumableScan, Error>>;
+
+    /// Enable the index of entry name prefixes, indexing all existing records.
+    /// Other instances of the store which are already open maintain the index
+    /// from their next write
+    fn enable_name_index(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the index of entry category prefixes, indexing all existing
+    /// records. Other open instances maintain the index as for `enable_name_index`
+    fn enable_category_index(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the access counters of records which are updated when they are fetched
+    fn enable_access_tracking(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the retention of the prior values of replaced and removed records
+    fn enable_value_history(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the storage of the entity tags of record values, which allow a
+    /// conditional fetch to be answered without decrypting the value
+    fn enable_value_etags(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the envelope written before record values, which records the
+    /// format of the ciphertext and cannot be read by prior releases
+    fn enable_value_envelopes(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Enable the access log, recording the opening and closing of the store
+    /// along with changes of the store key and the unlocking of profiles
+    fn enable_access_log(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Fetch the most recent entries of the access log, newest first, limited
+    /// to those logged at or after `since` (in milliseconds since the Unix epoch)
+    fn read_access_log(
+        &self,
+        limit: Option<i64>,
+        since: Option<i64>,
+    ) -> BoxFuture<'_, Result<Vec<AccessLogEntry>, Error>>;
+
+    /// Get the normalization applied to record categories and names
+    fn name_normalization(&self) -> NameNormalization;
+
+    /// Get the expiry filter applied by sessions and scans unless another is selected
+    fn default_expiry_filter(&self) -> ExpiryFilter;
+
+    /// Get the index hint applied by sessions and scans unless another is selected
+    fn default_query_hint(&self) -> Option<QueryHint>;
+
+    /// Fetch the least recently and least frequently read records in a category
+    fn scan_coldest(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<Vec<EntryAccess>, Error>>;
+
+    /// Fetch the most recently added records from each of a set of categories,
+    /// returning at most `limit` records per category in a single query
+    fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        categories: Vec<String>,
+        tag_filter: Option<TagFilter>,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<BTreeMap<String, Vec<Entry>>, Error>>;
+
+    /// Collect usage statistics for the tag names used in a record category
+    fn tag_stats(
+        &self,
+        profile: Option<String>,
+        kind: EntryKind,
+        category: String,
+    ) -> BoxFuture<'_, Result<Vec<TagStats>, Error>>;
+
+    /// Remove the expired records of all profiles, returning the number removed.
+    ///
+    /// The idempotency keys of applied batches and the prior record values which
+    /// are older than their retention periods are also removed, but are not counted.
+    fn purge_expired(&self) -> BoxFuture<'_, Result<i64, Error>>;
+
+    /// Flush pending changes to the main database file, where supported
+    fn checkpoint(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Reclaim the space left unused by removed records
+    fn vacuum(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Check for profiles without a stored key, records without a profile and
+    /// tags without a record
+    fn verify_integrity(&self) -> BoxFuture<'_, Result<IntegrityReport, Error>>;
+
+    /// Attempt to decrypt up to `limit` records of a profile following the
+    /// record ID given by `options.resume_after`, in order of record ID. Only
+    /// the records selected by the sample and categories of the audit are decrypted
+    fn audit_decryption(
+        &self,
+        profile: Option<String>,
+        options: DecryptionAuditOptions,
+        limit: i64,
+    ) -> BoxFuture<'_, Result<DecryptionAudit, Error>>;
+
+    /// Remove the tags of records which no longer exist, returning the number removed
+    fn cleanup_orphans(&self) -> BoxFuture<'_, Result<i64, Error>>;
+
+    /// Report the active scans, connection pool status and key cache occupancy
+    fn diagnostics(&self) -> BoxFuture<'_, Result<StoreDiagnostics, Error>>;
+
+    /// Describe the store and the parameters it was provisioned with. The
+    /// information is loaded from the database on first use, or when `refresh`
+    /// is set, and cached otherwise
+    fn info(&self, refresh: bool) -> BoxFuture<'_, Result<StoreInfo, Error>>;
+
+    /// Check that a database connection can be acquired and used within the deadline
+    fn ping(&self, deadline: Duration) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Check that the database is available within the deadline, that the schema
+    /// version is current, and that the store key decrypts the default profile key
+    fn health(&self, deadline: Duration) -> BoxFuture<'_, HealthStatus>;
+
     /// Create a new session against the store
     fn session(&self, profile: Option<String>, transaction: bool) -> Result<Self::Session, Error>;
 
+    /// Create a new session against the store which resolves profile keys using
+    /// an externally supplied store key. The key is not retained by the store,
+    /// and is never used to resolve the profile keys of other sessions
+    fn session_with_external_key(
+        &self,
+        profile: Option<String>,
+        store_key: StoreKey,
+        transaction: bool,
+    ) -> Result<Self::Session, Error>;
+
+    /// Fetch the decrypted key of a profile in its serialized form
+    fn export_profile_key(
+        &self,
+        profile: Option<String>,
+    ) -> BoxFuture<'_, Result<SecretBytes, Error>>;
+
+    /// Store the serialized key of a profile, wrapped by the current store key.
+    /// The profile is created if it does not exist, and an existing key which
+    /// can be decrypted is only replaced when `force` is set
+    fn import_profile_key(
+        &self,
+        profile: String,
+        key: SecretBytes,
+        force: bool,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Wrap the key of a profile with a key derived from its own passphrase,
+    /// in place of the store key. The profile must be accessible, and remains
+    /// unlocked until `lock_profile` is called or the store is reopened
+    fn protect_profile(
+        &self,
+        profile: String,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Load the key of a profile protected by a passphrase
+    fn unlock_profile(
+        &self,
+        profile: String,
+        pass_key: PassKey<'_>,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Drop the key of a profile from memory, returning whether it was loaded
+    fn lock_profile(&self, profile: String) -> BoxFuture<'_, Result<bool, Error>>;
+
+    /// Set the limits on the records held by a profile, replacing any prior limits
+    fn set_profile_quota(
+        &self,
+        profile: Option<String>,
+        quota: ProfileQuota,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Fetch the number and size of the records held by a profile
+    fn profile_usage(&self, profile: Option<String>) -> BoxFuture<'_, Result<ProfileUsage, Error>>;
+
+    /// Rebuild the usage counters of all profiles from the stored records
+    fn rebuild_usage(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Discard all records held in the record cache, if enabled
+    fn clear_cache(&self);
+
     /// Replace the wrapping key of the store
     fn rekey_backend(
         &mut self,
@@ -81,14 +332,61 @@ pub trait QueryBackend: Send {
     ) -> BoxFuture<'q, Result<i64, Error>>;
 
     /// Fetch a single record from the store by category and name
+    ///
+    /// The category and name are arbitrary byte strings. Those which are not
+    /// valid UTF-8 are retained as bytes in the returned entry.
     fn fetch<'q>(
         &'q mut self,
         kind: EntryKind,
-        category: &'q str,
-        name: &'q str,
+        category: &'q [u8],
+        name: &'q [u8],
         for_update: bool,
     ) -> BoxFuture<'q, Result<Option<Entry>, Error>>;
 
+    /// Fetch a single record from the store along with the entity tag of its
+    /// value, unless the entity tag matches the one provided
+    ///
+    /// The entity tag is a keyed hash of the record value, and is unchanged
+    /// when the same value is written again.
+    fn fetch_conditional<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        etag: Option<&'q [u8]>,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<ConditionalFetch>, Error>>;
+
+    /// Fetch a single record from the store if it matches a tag filter,
+    /// distinguishing a record which does not match from a missing record
+    fn fetch_filtered<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_filter: TagFilter,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<FilteredFetch>, Error>>;
+
+    /// Fetch a single record from the store, retrieving only the selected tags
+    fn fetch_with_tags<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        tag_mode: TagRetrieval,
+        for_update: bool,
+    ) -> BoxFuture<'q, Result<Option<TaggedEntry>, Error>>;
+
+    /// Fetch the prior values of a record, most recently replaced first
+    fn fetch_history<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: &'q str,
+        limit: Option<i64>,
+    ) -> BoxFuture<'q, Result<Vec<EntryVersion>, Error>>;
+
     /// Fetch all matching records from the store
     fn fetch_all<'q>(
         &'q mut self,
@@ -107,18 +405,86 @@ pub trait QueryBackend: Send {
         tag_filter: Option<TagFilter>,
     ) -> BoxFuture<'q, Result<i64, Error>>;
 
+    /// Fetch and remove the oldest matching record from the store.
+    ///
+    /// The record is selected and removed within a single transaction, so that
+    /// concurrent callers never receive the same record
+    fn take<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q str,
+        name: Option<&'q str>,
+        tag_filter: Option<TagFilter>,
+    ) -> BoxFuture<'q, Result<Option<Entry>, Error>>;
+
     /// Insert or replace a record in the store
+    ///
+    /// The category and name are arbitrary byte strings, and records are unique
+    /// according to their exact bytes.
     fn update<'q>(
         &'q mut self,
         kind: EntryKind,
         operation: EntryOperation,
-        category: &'q str,
-        name: &'q str,
+        category: &'q [u8],
+        name: &'q [u8],
         value: Option<&'q [u8]>,
+        value_type: Option<&'q str>,
         tags: Option<&'q [EntryTag]>,
-        expiry_ms: Option<i64>,
+        expiry: ExpiryUpdate,
     ) -> BoxFuture<'q, Result<(), Error>>;
 
+    /// Move a record to a new category and name within a single transaction
+    ///
+    /// The record is updated in place, keeping its row identifier, tags and
+    /// expiry time, while its value is encrypted again for the new category and
+    /// name. A `Duplicate` error is returned if a record already exists with
+    /// the new category and name.
+    fn rename<'q>(
+        &'q mut self,
+        kind: EntryKind,
+        category: &'q [u8],
+        name: &'q [u8],
+        new_category: &'q [u8],
+        new_name: &'q [u8],
+    ) -> BoxFuture<'q, Result<(), Error>>;
+
+    /// Look up a batch of updates previously applied with the same idempotency key
+    /// within the retention period, returning the number of updates it applied
+    fn applied_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+    ) -> BoxFuture<'q, Result<Option<i64>, Error>>;
+
+    /// Record the idempotency key of a batch of updates applied in the current
+    /// transaction
+    fn record_batch<'q>(
+        &'q mut self,
+        idempotency_key: &'q str,
+        updates: i64,
+    ) -> BoxFuture<'q, Result<(), Error>>;
+
+    /// Set the maximum time to wait on each query, overriding the store default
+    fn set_query_timeout(&mut self, timeout: Option<Duration>);
+
+    /// Select the records returned by `count`, `fetch` and `fetch_all` according
+    /// to their expiry time
+    fn set_expiry_filter(&mut self, expiry: ExpiryFilter);
+
+    /// Select the index hint applied to the queries of `count`, `fetch_all`,
+    /// `remove_all` and `take` with a tag filter
+    fn set_query_hint(&mut self, hint: Option<QueryHint>);
+
+    /// Select the profile used by subsequent operations, within any transaction
+    /// already in progress
+    fn set_profile(&mut self, profile: String);
+
+    /// Set the operation name under which the commit of the current transaction
+    /// is reported to the store metrics
+    fn set_commit_operation(&mut self, operation: &'static str);
+
+    /// Get the normalization applied to record categories and names
+    fn name_normalization(&self) -> NameNormalization;
+
     /// Close the current store session
     fn close(self, commit: bool) -> BoxFuture<'static, Result<(), Error>>;
 }
@@ -0,0 +1,218 @@
+//! Inspect the contents of an Askar store from the command line
+
+use std::env;
+use std::error::Error as StdError;
+use std::process::exit;
+use std::str::FromStr;
+
+use aries_askar::{
+    any::AnyStore, future::block_on, Entry, Error, ManageBackend, PassKey, StoreKeyMethod,
+    TagFilter,
+};
+
+const USAGE: &'static str = "\
+Usage: askar-inspect [OPTIONS] <STORE-URI> <COMMAND> [ARGS]
+
+Options:
+    --key-method <METHOD>   The store key method, such as kdf:argon2i or raw
+    --pass-key <KEY>        The store passphrase or raw key. The ASKAR_PASS_KEY
+                            environment variable is used when not provided
+    --profile <NAME>        The profile to inspect, in place of the default
+    --show-secrets          Print record values and tag values
+    -h, --help              Print this message
+
+Commands:
+    info                        Describe the store
+    profiles                    List the profiles of the store
+    categories                  List the record categories of the profile
+    count <CATEGORY> [FILTER]   Count the records matching a WQL tag filter
+    fetch <CATEGORY> <NAME>     Fetch a record by name
+    verify                      Check the integrity of the store
+    purge                       Remove expired records
+    vacuum                      Reclaim the space left by removed records
+";
+
+#[derive(Debug, Default)]
+struct Args {
+    key_method: Option<String>,
+    pass_key: Option<String>,
+    profile: Option<String>,
+    show_secrets: bool,
+    store_uri: String,
+    command: String,
+    params: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut result = Self::default();
+        let mut positional = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut option_value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for option: {}", name))
+            };
+            match arg.as_str() {
+                "--key-method" => result.key_method = Some(option_value(&arg)?),
+                "--pass-key" => result.pass_key = Some(option_value(&arg)?),
+                "--profile" => result.profile = Some(option_value(&arg)?),
+                "--show-secrets" => result.show_secrets = true,
+                "-h" | "--help" => return Err(String::new()),
+                opt if opt.starts_with("--") => return Err(format!("Unknown option: {}", opt)),
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        result.store_uri = positional.next().ok_or("Missing store URI")?;
+        result.command = positional.next().ok_or("Missing command")?;
+        result.params = positional.collect();
+        Ok(result)
+    }
+
+    fn param(&self, index: usize, name: &str) -> Result<&str, String> {
+        self.params
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Missing {}", name))
+    }
+}
+
+async fn open_store(args: &Args) -> Result<AnyStore, Error> {
+    let method = args
+        .key_method
+        .as_deref()
+        .map(StoreKeyMethod::parse_uri)
+        .transpose()?;
+    let pass_key = args
+        .pass_key
+        .clone()
+        .or_else(|| env::var("ASKAR_PASS_KEY").ok());
+    args.store_uri
+        .as_str()
+        .open_backend(
+            method,
+            PassKey::from(pass_key.as_deref()),
+            args.profile.as_deref(),
+        )
+        .await
+}
+
+async fn run(args: &Args) -> Result<(), Box<dyn StdError>> {
+    let store = open_store(args).await?;
+    match args.command.as_str() {
+        "info" => {
+            let info = store.info(true).await?;
+            println!("backend: {}", info.backend);
+            println!("schema version: {}", info.schema_version);
+            println!("store key method: {:?}", info.store_key_method);
+            println!("encryption: {}", info.encryption.as_str());
+            println!("default profile: {}", info.default_profile);
+            println!("profiles: {}", info.profile_count);
+            println!("store id: {}", info.store_id);
+            if let Some(journal_mode) = info.journal_mode {
+                println!("journal mode: {}", journal_mode);
+            }
+        }
+        "profiles" => {
+            for name in store.list_profiles().await? {
+                println!("{}", name);
+            }
+        }
+        "categories" => {
+            for category in store.list_categories(args.profile.clone()).await? {
+                println!("{}", category);
+            }
+        }
+        "count" => {
+            let category = args.param(0, "category")?;
+            let tag_filter = args
+                .params
+                .get(1)
+                .map(|filter| TagFilter::from_str(filter))
+                .transpose()?;
+            let mut session = store.session(args.profile.clone()).await?;
+            println!("{}", session.count(category, tag_filter).await?);
+        }
+        "fetch" => {
+            let (category, name) = (args.param(0, "category")?, args.param(1, "name")?);
+            let mut session = store.session(args.profile.clone()).await?;
+            match session.fetch(category, name, false).await? {
+                Some(entry) => print_entry(&entry, args.show_secrets),
+                None => return Err("Record not found".into()),
+            }
+        }
+        "verify" => {
+            let report = store.verify_integrity().await?;
+            for (profile_id, count) in report.missing_keys.iter() {
+                println!(
+                    "profile {} has no stored key ({} unreadable records)",
+                    profile_id, count
+                );
+            }
+            for (profile_id, count) in report.orphaned_items.iter() {
+                println!(
+                    "removed profile {} is referenced by {} records",
+                    profile_id, count
+                );
+            }
+            if report.orphaned_tags > 0 {
+                println!("{} tags without a record", report.orphaned_tags);
+            }
+            println!("{}", if report.is_ok() { "ok" } else { "failed" });
+        }
+        "purge" => {
+            println!("{} expired records removed", store.purge_expired().await?);
+        }
+        "vacuum" => {
+            store.vacuum().await?;
+        }
+        other => return Err(format!("Unknown command: {}", other).into()),
+    }
+    store.close().await?;
+    Ok(())
+}
+
+fn print_entry(entry: &Entry, show_secrets: bool) {
+    println!("category: {}", entry.category);
+    println!("name: {}", entry.name);
+    if let Some(value_type) = entry.value_type.as_ref() {
+        println!("value type: {}", value_type);
+    }
+    if let Some(expiry) = entry.expiry {
+        println!("expiry: {}", expiry);
+    }
+    if show_secrets {
+        match std::str::from_utf8(entry.value.as_ref()) {
+            Ok(value) => println!("value: {}", value),
+            Err(_) => println!("value (hex): {}", entry.value.as_hex()),
+        }
+    } else {
+        println!("value: <{} bytes>", entry.value.len());
+    }
+    for tag in entry.tags.iter() {
+        let value = if show_secrets {
+            tag.value()
+        } else {
+            "<hidden>"
+        };
+        println!("tag {}: {}", tag.name(), value);
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprint!("{}", USAGE);
+            exit(2);
+        }
+    };
+    if let Err(err) = block_on(run(&args)) {
+        eprintln!("Error: {}", err);
+        exit(1);
+    }
+}
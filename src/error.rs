@@ -21,9 +21,27 @@ pub enum ErrorKind {
     /// The input parameters to the method were incorrect
     Input,
 
+    /// A stored key required by the operation could not be found
+    KeyNotFound,
+
+    /// The profile is protected by a passphrase and has not been unlocked
+    Locked,
+
     /// The requested record was not found
     NotFound,
 
+    /// A lookup expected to match a single record matched several
+    NotUnique,
+
+    /// A write would take a profile over one of its configured quotas
+    QuotaExceeded,
+
+    /// The store has reached its limit on a resource held open by the caller
+    ResourceLimit,
+
+    /// An operation did not complete within the configured timeout
+    Timeout,
+
     /// An unexpected error occurred
     Unexpected,
 
@@ -40,7 +58,13 @@ impl ErrorKind {
             Self::Duplicate => "Duplicate",
             Self::Encryption => "Encryption error",
             Self::Input => "Input error",
+            Self::KeyNotFound => "Key not found",
+            Self::Locked => "Locked",
             Self::NotFound => "Not found",
+            Self::NotUnique => "Not unique",
+            Self::QuotaExceeded => "Quota exceeded",
+            Self::ResourceLimit => "Resource limit reached",
+            Self::Timeout => "Timeout",
             Self::Unexpected => "Unexpected error",
             Self::Unsupported => "Unsupported",
         }
@@ -21,6 +21,12 @@ pub enum ErrorCode {
     NotFound = 6,
     Unexpected = 7,
     Unsupported = 8,
+    Timeout = 9,
+    KeyNotFound = 10,
+    Locked = 11,
+    QuotaExceeded = 12,
+    NotUnique = 13,
+    ResourceLimit = 14,
 }
 
 impl From<ErrorKind> for ErrorCode {
@@ -31,7 +37,13 @@ impl From<ErrorKind> for ErrorCode {
             ErrorKind::Duplicate => ErrorCode::Duplicate,
             ErrorKind::Encryption => ErrorCode::Encryption,
             ErrorKind::Input => ErrorCode::Input,
+            ErrorKind::KeyNotFound => ErrorCode::KeyNotFound,
+            ErrorKind::Locked => ErrorCode::Locked,
             ErrorKind::NotFound => ErrorCode::NotFound,
+            ErrorKind::NotUnique => ErrorCode::NotUnique,
+            ErrorKind::QuotaExceeded => ErrorCode::QuotaExceeded,
+            ErrorKind::ResourceLimit => ErrorCode::ResourceLimit,
+            ErrorKind::Timeout => ErrorCode::Timeout,
             ErrorKind::Unexpected => ErrorCode::Unexpected,
             ErrorKind::Unsupported => ErrorCode::Unsupported,
         }
@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, os::raw::c_char, ptr, str::FromStr, sync::Arc};
+use std::{collections::BTreeMap, ops::Deref, os::raw::c_char, ptr, str::FromStr, sync::Arc};
 
 use async_lock::RwLock;
 use ffi_support::{rust_string_to_c, ByteBuffer, FfiStr};
@@ -63,30 +63,46 @@ impl StoreHandle {
     }
 }
 
+/// The number of independently locked partitions of a `StoreResourceMap`
+const RESOURCE_MAP_SHARDS: usize = 16;
+
+type ResourceShard<K, V> = RwLock<BTreeMap<K, (StoreHandle, Arc<TryMutex<V>>)>>;
+
+/// A map of open resources, partitioned so that concurrent operations on
+/// distinct handles rarely contend for the same lock. Each lock is only held
+/// for the duration of the map operation, never while the resource is in use.
 struct StoreResourceMap<K, V> {
-    map: RwLock<BTreeMap<K, (StoreHandle, Arc<TryMutex<V>>)>>,
+    shards: Vec<ResourceShard<K, V>>,
 }
 
 impl<K, V> StoreResourceMap<K, V>
 where
-    K: ResourceHandle,
+    K: ResourceHandle + Deref<Target = usize>,
 {
     pub fn new() -> Self {
         Self {
-            map: RwLock::new(BTreeMap::new()),
+            shards: (0..RESOURCE_MAP_SHARDS)
+                .map(|_| RwLock::new(BTreeMap::new()))
+                .collect(),
         }
     }
 
+    #[inline]
+    fn shard(&self, handle: K) -> &ResourceShard<K, V> {
+        // handles are assigned in sequence, so they are spread evenly across the shards
+        &self.shards[*handle % RESOURCE_MAP_SHARDS]
+    }
+
     pub async fn insert(&self, store: StoreHandle, value: V) -> K {
         let handle = K::next();
-        let mut map = self.map.write().await;
+        let mut map = self.shard(handle).write().await;
         map.insert(handle, (store, Arc::new(TryMutex::new(value))));
         handle
     }
 
     pub async fn remove(&self, handle: K) -> Result<V, Error> {
         Arc::try_unwrap(
-            self.map
+            self.shard(handle)
                 .write()
                 .await
                 .remove(&handle)
@@ -98,7 +114,8 @@ where
     }
 
     pub async fn borrow(&self, handle: K) -> Result<TryMutexGuard<V>, Error> {
-        self.map
+        // the shard lock is released before the resource is used
+        self.shard(handle)
             .read()
             .await
             .get(&handle)
@@ -109,23 +126,25 @@ where
     }
 
     pub async fn remove_all(&self, store: StoreHandle) -> Result<(), Error> {
-        let mut guard = self.map.write().await;
-        let mut pos = K::from(0usize);
-        let mut found;
-        loop {
-            found = false;
-            for (h, (sh, _)) in guard.range(pos..) {
-                if store == *sh {
-                    pos = *h;
-                    found = true;
+        for shard in self.shards.iter() {
+            let mut guard = shard.write().await;
+            let mut pos = K::from(0usize);
+            let mut found;
+            loop {
+                found = false;
+                for (h, (sh, _)) in guard.range(pos..) {
+                    if store == *sh {
+                        pos = *h;
+                        found = true;
+                        break;
+                    }
+                }
+                if found {
+                    guard.remove(&pos);
+                } else {
                     break;
                 }
             }
-            if found {
-                guard.remove(&pos);
-            } else {
-                break;
-            }
         }
         Ok(())
     }
@@ -1017,3 +1036,55 @@ pub extern "C" fn askar_session_close(
         Ok(ErrorCode::Success)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::block_on;
+    use futures_lite::future::yield_now;
+    use std::thread;
+
+    new_sequence_handle!(TestHandle, TEST_HANDLE_CTR);
+
+    #[test]
+    fn resource_map_concurrent_borrow() {
+        const TASKS: usize = 64;
+        const ROUNDS: usize = 500;
+
+        let resources: Arc<StoreResourceMap<TestHandle, usize>> = Arc::new(StoreResourceMap::new());
+        let store = StoreHandle::next();
+
+        let workers = (0..TASKS)
+            .map(|_| {
+                let resources = resources.clone();
+                thread::spawn(move || {
+                    block_on(async move {
+                        let handle = resources.insert(store, 0).await;
+                        for _ in 0..ROUNDS {
+                            let mut count = resources.borrow(handle).await.unwrap();
+                            *count += 1;
+                            // other handles remain available while this one is in use
+                            yield_now().await;
+                            drop(count);
+                            let temp = resources.insert(store, 0).await;
+                            resources.remove(temp).await.unwrap();
+                        }
+                        resources.remove(handle).await.unwrap()
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), ROUNDS);
+        }
+
+        block_on(async {
+            let handle = resources.insert(store, 0).await;
+            let guard = resources.borrow(handle).await.unwrap();
+            assert!(resources.borrow(handle).await.is_err());
+            drop(guard);
+            resources.remove_all(store).await.unwrap();
+            assert!(resources.borrow(handle).await.is_err());
+        });
+    }
+}
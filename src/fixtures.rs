@@ -0,0 +1,410 @@
+//! Generation of large stores with realistic contents for performance testing
+
+use std::sync::Arc;
+
+use crate::{
+    backend::sqlite::{SqliteStore, SqliteStoreOptions},
+    error::Error,
+    protect::{generate_raw_store_key, StoreKeyMethod},
+    storage::{BatchUpdate, EntryTag, IngestConfig, Store, DEFAULT_INGEST_BATCH_SIZE},
+};
+
+/// The distribution of the values of a tag attached to each generated record
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagSpec {
+    /// The tag name
+    pub name: String,
+    /// Whether the tag is stored as plaintext
+    pub plaintext: bool,
+    /// The number of distinct values of the tag
+    pub cardinality: usize,
+    /// The exponent of the zipfian distribution of the tag values. Zero
+    /// produces a uniform distribution, while larger values concentrate the
+    /// records on fewer values
+    pub skew: f64,
+}
+
+impl TagSpec {
+    /// An encrypted tag with a zipfian distribution of values
+    pub fn encrypted(name: impl Into<String>, cardinality: usize, skew: f64) -> Self {
+        Self {
+            name: name.into(),
+            plaintext: false,
+            cardinality,
+            skew,
+        }
+    }
+
+    /// A plaintext tag with a zipfian distribution of values
+    pub fn plaintext(name: impl Into<String>, cardinality: usize, skew: f64) -> Self {
+        Self {
+            name: name.into(),
+            plaintext: true,
+            cardinality,
+            skew,
+        }
+    }
+}
+
+/// The distribution of the sizes of the generated record values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueSize {
+    /// All values have the same size
+    Fixed(usize),
+    /// Value sizes are uniformly distributed within an inclusive range
+    Uniform {
+        /// The minimum value size
+        min: usize,
+        /// The maximum value size
+        max: usize,
+    },
+}
+
+/// The contents of a store generated by `generate_store`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureSpec {
+    /// The seed determining the generated names, tags and values
+    pub seed: u64,
+    /// The number of profiles, including the default profile
+    pub profiles: usize,
+    /// The number of record categories in each profile
+    pub categories: usize,
+    /// The number of records in each category
+    pub entries_per_category: usize,
+    /// The tags attached to each record
+    pub tags: Vec<TagSpec>,
+    /// The distribution of record value sizes
+    pub value_size: ValueSize,
+    /// The fraction of records which are given an expiry time
+    pub expiry_ratio: f64,
+    /// The expiry time of the records with an expiry, in milliseconds from the
+    /// time they are written
+    pub expiry_ms: i64,
+    /// The number of records written in each transaction
+    pub batch_size: usize,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            profiles: 1,
+            categories: 4,
+            entries_per_category: 1000,
+            tags: vec![
+                TagSpec::encrypted("enc", 100, 1.0),
+                TagSpec::plaintext("plain", 1000, 0.5),
+            ],
+            value_size: ValueSize::Uniform { min: 32, max: 512 },
+            expiry_ratio: 0.0,
+            expiry_ms: 24 * 60 * 60 * 1000,
+            batch_size: DEFAULT_INGEST_BATCH_SIZE,
+        }
+    }
+}
+
+impl FixtureSpec {
+    /// The name of a generated profile
+    pub fn profile_name(idx: usize) -> String {
+        format!("profile-{}", idx)
+    }
+
+    /// The name of a generated record category
+    pub fn category_name(idx: usize) -> String {
+        format!("category-{}", idx)
+    }
+
+    /// The name of a generated record
+    pub fn entry_name(idx: usize) -> String {
+        format!("name-{:08}", idx)
+    }
+
+    /// The value of a generated tag, with `idx` ranging up to the tag cardinality
+    pub fn tag_value(tag_name: &str, idx: usize) -> String {
+        format!("{}-{}", tag_name, idx)
+    }
+
+    /// The total number of records generated
+    pub fn total_entries(&self) -> u64 {
+        (self.profiles * self.categories * self.entries_per_category) as u64
+    }
+}
+
+/// A generated store, backed by a temporary Sqlite database
+#[derive(Debug)]
+pub struct Fixture {
+    /// The generated store
+    pub store: Arc<Store<SqliteStore>>,
+    /// The path of the Sqlite database
+    pub path: String,
+    /// The names of the generated profiles, starting with the default profile
+    pub profiles: Vec<String>,
+    /// The number of records written
+    pub entries: u64,
+}
+
+impl Fixture {
+    /// Close the store and remove the Sqlite database
+    pub async fn remove(self) -> Result<bool, Error> {
+        self.store.arc_close().await?;
+        SqliteStoreOptions::from_path(&self.path).remove().await
+    }
+}
+
+/// Provision a temporary Sqlite store and fill it with records according to
+/// a specification.
+///
+/// The contents of the store are determined by the seed of the specification,
+/// apart from the store and profile keys. Records are written using the
+/// batched ingest of `Store::ingest`.
+pub async fn generate_store(spec: FixtureSpec) -> Result<Fixture, Error> {
+    if spec.profiles == 0 || spec.batch_size == 0 {
+        return Err(err_msg!(
+            Input,
+            "Fixture profile count and batch size must be greater than zero"
+        ));
+    }
+    if !(0.0..=1.0).contains(&spec.expiry_ratio) {
+        return Err(err_msg!(
+            Input,
+            "Fixture expiry ratio must be between 0 and 1"
+        ));
+    }
+    if let ValueSize::Uniform { min, max } = spec.value_size {
+        if min > max {
+            return Err(err_msg!(Input, "Invalid fixture value size range"));
+        }
+    }
+    let tag_dists = spec
+        .tags
+        .iter()
+        .map(|tag| Zipf::new(tag.cardinality, tag.skew))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let path = std::env::temp_dir()
+        .join(format!("askar-fixture-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let key = generate_raw_store_key(None)?;
+    let default_profile = FixtureSpec::profile_name(0);
+    let store = Arc::new(
+        SqliteStoreOptions::from_path(&path)
+            .provision(StoreKeyMethod::RawKey, key, Some(&default_profile), true)
+            .await?,
+    );
+    let mut profiles = vec![default_profile];
+    for idx in 1..spec.profiles {
+        profiles.push(
+            store
+                .create_profile(Some(FixtureSpec::profile_name(idx)))
+                .await?,
+        );
+    }
+
+    let mut rng = SplitMix64(spec.seed);
+    let mut entries = 0;
+    for profile in profiles.iter() {
+        let mut sink = store.clone().ingest(IngestConfig {
+            profile: Some(profile.clone()),
+            batch_size: spec.batch_size,
+            ..Default::default()
+        })?;
+        for category_idx in 0..spec.categories {
+            let category = FixtureSpec::category_name(category_idx);
+            for entry_idx in 0..spec.entries_per_category {
+                let tags = spec
+                    .tags
+                    .iter()
+                    .zip(tag_dists.iter())
+                    .map(|(tag, dist)| {
+                        let value = FixtureSpec::tag_value(&tag.name, dist.sample(&mut rng));
+                        if tag.plaintext {
+                            EntryTag::Plaintext(tag.name.clone(), value)
+                        } else {
+                            EntryTag::Encrypted(tag.name.clone(), value)
+                        }
+                    })
+                    .collect();
+                let value = rng.bytes(match spec.value_size {
+                    ValueSize::Fixed(size) => size,
+                    ValueSize::Uniform { min, max } => min + rng.below(max - min + 1),
+                });
+                let mut update = BatchUpdate::insert(
+                    &category,
+                    &FixtureSpec::entry_name(entry_idx),
+                    &value,
+                    Some(tags),
+                );
+                if rng.next_f64() < spec.expiry_ratio {
+                    update.expiry_ms = Some(spec.expiry_ms);
+                }
+                sink.send(update).await;
+                for result in sink.take_results() {
+                    result.result?;
+                    entries += 1;
+                }
+            }
+        }
+        for result in sink.close().await {
+            result.result?;
+            entries += 1;
+        }
+    }
+
+    Ok(Fixture {
+        store,
+        path,
+        profiles,
+        entries,
+    })
+}
+
+// a small deterministic generator, so that fixtures are reproducible
+// across platforms and releases
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(len + 8);
+        while buf.len() < len {
+            buf.extend_from_slice(&self.next_u64().to_le_bytes());
+        }
+        buf.truncate(len);
+        buf
+    }
+}
+
+// samples indexes in 0..cardinality, with the probability of index k
+// proportional to 1 / (k + 1)^skew
+#[derive(Debug)]
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(cardinality: usize, skew: f64) -> Result<Self, Error> {
+        if cardinality == 0 || skew.is_nan() || skew < 0.0 {
+            return Err(err_msg!(
+                Input,
+                "Fixture tag cardinality must be greater than zero, with a non-negative skew"
+            ));
+        }
+        let mut total = 0.0;
+        let mut cumulative = (1..=cardinality)
+            .map(|k| {
+                total += (k as f64).powf(-skew);
+                total
+            })
+            .collect::<Vec<_>>();
+        for weight in cumulative.iter_mut() {
+            *weight /= total;
+        }
+        Ok(Self { cumulative })
+    }
+
+    fn sample(&self, rng: &mut SplitMix64) -> usize {
+        let target = rng.next_f64();
+        self.cumulative
+            .partition_point(|weight| *weight <= target)
+            .min(self.cumulative.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{future::block_on, TagFilter};
+
+    #[test]
+    fn zipf_distribution() {
+        let mut rng = SplitMix64(1);
+        let dist = Zipf::new(10, 1.0).unwrap();
+        let mut counts = [0usize; 10];
+        for _ in 0..10000 {
+            counts[dist.sample(&mut rng)] += 1;
+        }
+        assert!(counts[0] > counts[1] && counts[1] > counts[9]);
+        assert!(counts.iter().all(|count| *count > 0));
+
+        let dist = Zipf::new(1, 2.0).unwrap();
+        assert_eq!(dist.sample(&mut rng), 0);
+        assert!(Zipf::new(0, 1.0).is_err());
+    }
+
+    #[test]
+    fn generate_fixture() {
+        block_on(async {
+            let spec = FixtureSpec {
+                seed: 7,
+                profiles: 2,
+                categories: 2,
+                entries_per_category: 50,
+                expiry_ratio: 0.5,
+                batch_size: 20,
+                ..Default::default()
+            };
+            let fixture = generate_store(spec.clone()).await?;
+            assert_eq!(fixture.entries, spec.total_entries());
+            assert_eq!(fixture.profiles.len(), 2);
+
+            let mut counts = vec![];
+            for profile in fixture.profiles.iter() {
+                let mut conn = fixture.store.session(Some(profile.clone())).await?;
+                assert_eq!(conn.count(&FixtureSpec::category_name(1), None).await?, 50);
+                counts.push(
+                    conn.count(
+                        &FixtureSpec::category_name(0),
+                        Some(TagFilter::is_eq("enc", FixtureSpec::tag_value("enc", 0))),
+                    )
+                    .await?,
+                );
+                let entry = conn
+                    .fetch(
+                        &FixtureSpec::category_name(0),
+                        &FixtureSpec::entry_name(0),
+                        false,
+                    )
+                    .await?
+                    .expect("Expected fixture record");
+                assert!((32..=512).contains(&entry.value.len()));
+                assert_eq!(entry.tags.len(), 2);
+            }
+            assert!(counts.iter().all(|count| *count > 0));
+            assert!(fixture.remove().await?);
+
+            // the same seed produces the same records
+            let first = generate_store(spec.clone()).await?;
+            let second = generate_store(spec).await?;
+            let mut rows = vec![];
+            for fixture in [&first, &second].iter() {
+                let mut conn = fixture.store.session(None).await?;
+                rows.push(
+                    conn.fetch_all(&FixtureSpec::category_name(1), None, None, false)
+                        .await?,
+                );
+            }
+            assert_eq!(rows[0], rows[1]);
+            first.remove().await?;
+            second.remove().await?;
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap()
+    }
+}
@@ -1,16 +1,28 @@
-use std::{future::Future, pin::Pin, time::Duration};
+use std::{future::Future, pin::Pin};
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
 use once_cell::sync::Lazy;
+#[cfg(not(target_arch = "wasm32"))]
 use tokio::runtime::Runtime;
 
 pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
+/// A boxed future which is not required to be `Send`, as produced by stores
+/// bound to a single browser context
+pub type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+#[cfg(not(target_arch = "wasm32"))]
 static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().expect("Error creating tokio runtime"));
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn block_on<R>(f: impl Future<Output = R>) -> R {
     RUNTIME.block_on(f)
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 #[inline]
 pub async fn unblock<F, T>(f: F) -> T
 where
@@ -23,16 +35,70 @@ where
         .expect("Error running blocking task")
 }
 
+// there is no blocking thread pool in the browser, so blocking functions are
+// run in place on the calling context
+#[cfg(target_arch = "wasm32")]
+#[inline]
+pub async fn unblock<F, T>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    f()
+}
+
+/// Run a batch of blocking functions in parallel on the blocking thread pool,
+/// returning their results in order
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn unblock_batch<F, T>(batch: impl IntoIterator<Item = F>) -> Vec<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let tasks: Vec<_> = batch
+        .into_iter()
+        .map(|f| RUNTIME.spawn_blocking(f))
+        .collect();
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.expect("Error running blocking task"));
+    }
+    results
+}
+
+/// Run a batch of blocking functions in order on the calling context
+#[cfg(target_arch = "wasm32")]
+pub async fn unblock_batch<F, T>(batch: impl IntoIterator<Item = F>) -> Vec<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    batch.into_iter().map(|f| f()).collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 #[inline]
 pub fn spawn_ok(fut: impl Future<Output = ()> + Send + 'static) {
     RUNTIME.spawn(fut);
 }
 
+/// Run a future in the background, returning a future which resolves to its output
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn<T>(fut: impl Future<Output = T> + Send + 'static) -> BoxFuture<'static, T>
+where
+    T: Send + 'static,
+{
+    let task = RUNTIME.spawn(fut);
+    Box::pin(async move { task.await.expect("Error running background task") })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 pub async fn sleep(dur: Duration) {
     let _rt = RUNTIME.enter();
     tokio::time::sleep(dur).await
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub async fn timeout<R>(dur: Duration, f: impl Future<Output = R>) -> Option<R> {
     let _rt = RUNTIME.enter();
     tokio::time::timeout(dur, f).await.ok()
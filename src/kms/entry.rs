@@ -118,6 +118,68 @@ impl KeyEntry {
     }
 }
 
+/// Non-secret information about a stored key, as returned by key listings
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub(crate) name: String,
+    pub(crate) alg: Option<String>,
+    pub(crate) metadata: Option<String>,
+    pub(crate) reference: Option<String>,
+    pub(crate) thumbprints: Vec<String>,
+    pub(crate) tags: Vec<EntryTag>,
+}
+
+impl KeyInfo {
+    /// Accessor for the key algorithm
+    pub fn algorithm(&self) -> Option<&str> {
+        self.alg.as_ref().map(String::as_ref)
+    }
+
+    /// Accessor for the stored key metadata
+    pub fn metadata(&self) -> Option<&str> {
+        self.metadata.as_ref().map(String::as_ref)
+    }
+
+    /// Accessor for the key identity
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Accessor for the external reference of the key, if any
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_ref().map(String::as_ref)
+    }
+
+    /// Accessor for the JWK thumbprints of the public key
+    pub fn thumbprints(&self) -> &[String] {
+        self.thumbprints.as_slice()
+    }
+
+    /// Accessor for the user-defined tags on the key
+    pub fn tags(&self) -> &[EntryTag] {
+        self.tags.as_slice()
+    }
+}
+
+impl From<KeyEntry> for KeyInfo {
+    fn from(entry: KeyEntry) -> Self {
+        // the key data is dropped here and never exposed
+        let KeyParams {
+            metadata,
+            reference,
+            ..
+        } = entry.params;
+        Self {
+            name: entry.name,
+            alg: entry.alg,
+            metadata,
+            reference,
+            thumbprints: entry.thumbprints,
+            tags: entry.tags,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -21,20 +21,26 @@ extern crate log;
 #[macro_use]
 extern crate serde;
 
+#[cfg(not(target_arch = "wasm32"))]
 pub mod backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub use self::backend::{Backend, ManageBackend};
 
-#[cfg(feature = "any")]
+#[cfg(all(feature = "any", not(target_arch = "wasm32")))]
 pub use self::backend::any;
 
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "postgres", not(target_arch = "wasm32")))]
 pub use self::backend::postgres;
 
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
 pub use self::backend::sqlite;
 
 pub use askar_crypto as crypto;
 
+#[cfg(all(feature = "test-utils", not(target_arch = "wasm32")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-utils")))]
+pub mod fixtures;
+
 #[doc(hidden)]
 pub mod future;
 
@@ -42,16 +48,39 @@ pub mod future;
 #[macro_use]
 extern crate serde_json;
 
-#[cfg(feature = "ffi")]
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
 mod ffi;
 
 pub mod kms;
 
 mod protect;
-pub use protect::{generate_raw_store_key, PassKey, StoreKeyMethod};
+pub use protect::{
+    generate_raw_store_key, EncryptionPolicy, PassKey, ProvisionKeys, ProvisionResult,
+    StoreKeyMethod,
+};
 
 mod storage;
-pub use storage::{Entry, EntryTag, Scan, Store, TagFilter};
+pub use storage::{
+    AccessEvent, AccessLogEntry, BatchResult, BatchUpdate, CommitRecord, CommitStats,
+    ConditionalFetch, Entry, EntryAccess, EntryOperation, EntryTag, EntryVersion, ExpiryFilter,
+    ExpiryUpdate, FilteredFetch, HealthStatus, IdSource, InMemoryMetrics, IntTagValue,
+    IntegrityReport, KvStore, Metrics, MetricsSnapshot, NameCollision, NameNormalization,
+    NormalizeReport, OperationRecord, OperationStats, ProfileQuota, ProfileUsage, QueryHint,
+    ResumableScan, Scan, ScanCursor, ScanInfo, SequentialIds, StoreDiagnostics, StoreInfo,
+    TagFilter, TagRetrieval, TagStats, TaggedEntry, WaitKind, WqlDialect,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use storage::{
+    AuditCancel, CategorySpec, DecryptionAudit, DecryptionAuditOptions, DecryptionFailure,
+    ExportedCategory, ImportConflict, IndyImportReport, IndyRecord, IngestConfig, IngestResult,
+    IngestSink, JsonlImportReport, KeySplitter, KeyValue, KvAdapter, MaintenanceConfig,
+    MaintenanceHandle, MaintenanceTask, NamespacedStore, SeparatorSplitter, SkippedLine,
+    SkippedRecord, Store, TempScope,
+};
+
+#[cfg(all(feature = "wasm-storage", target_arch = "wasm32"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "wasm-storage")))]
+pub use storage::IndexedDbStore;
 
 // pub struct FfiStr<'a> {
 //     cstr: *const c_char,
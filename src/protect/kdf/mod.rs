@@ -1,6 +1,9 @@
 use super::store_key::{StoreKey, PREFIX_KDF};
 use crate::{
-    crypto::{buffer::ArrayKey, generic_array::ArrayLength},
+    crypto::{
+        buffer::{ArrayKey, SecretBytes},
+        generic_array::ArrayLength,
+    },
     error::Error,
     storage::Options,
 };
@@ -77,6 +80,16 @@ impl KdfMethod {
             }
         }
     }
+
+    /// Extract the salt from the detail of a derived key reference
+    pub(crate) fn salt(&self, detail: &str) -> Result<SecretBytes, Error> {
+        match self {
+            Self::Argon2i(_) => {
+                let salt = parse_salt::<Argon2Salt>(detail)?;
+                Ok(SecretBytes::from_slice(salt.as_ref()))
+            }
+        }
+    }
 }
 
 fn parse_salt<L: ArrayLength<u8>>(detail: &str) -> Result<ArrayKey<L>, Error> {
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex, MutexGuard, Weak},
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+
+use super::{PassKey, ProfileId, ProfileKey, StoreKey};
+use crate::{crypto::random::fill_random, error::Error};
+
+// the key of the pass key fingerprints, which never leaves the process
+static FINGERPRINT_KEY: Lazy<[u8; 32]> = Lazy::new(|| {
+    let mut key = [0u8; 32];
+    fill_random(&mut key);
+    key
+});
+
+static SHARED_KEYS: Lazy<Mutex<HashMap<KeyShareId, Weak<SharedKeys>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Identifies the keys of a store opened with a particular pass key
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct KeyShareId {
+    store_id: String,
+    fingerprint: [u8; 32],
+}
+
+impl KeyShareId {
+    /// Identify the keys of a store by its ID and a fingerprint of its pass key.
+    ///
+    /// The fingerprint is a hash of the store key reference and the pass key,
+    /// keyed by a random secret of the process, so that it cannot be used to
+    /// test guesses of the pass key.
+    pub fn new(store_id: &str, store_key_ref: &str, pass_key: &PassKey<'_>) -> Result<Self, Error> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&*FINGERPRINT_KEY)
+            .map_err(|_| err_msg!(Unexpected, "Error creating pass key fingerprint"))?;
+        mac.update(store_key_ref.as_bytes());
+        // distinguish a missing pass key from an empty one
+        mac.update(&[0, pass_key.is_none() as u8]);
+        mac.update(pass_key.as_bytes());
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(&mac.finalize().into_bytes());
+        Ok(Self {
+            store_id: store_id.to_string(),
+            fingerprint,
+        })
+    }
+}
+
+/// Compute a fingerprint of a resolved store key, keyed by the random secret
+/// of the process, so that store keys may be compared without revealing them
+pub fn store_key_fingerprint(store_key: &StoreKey) -> Result<[u8; 32], Error> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&*FINGERPRINT_KEY)
+        .map_err(|_| err_msg!(Unexpected, "Error creating store key fingerprint"))?;
+    let pass_key = store_key.to_passkey();
+    // distinguish an unprotected store from a protected one
+    mac.update(&[1, pass_key.is_none() as u8]);
+    mac.update(pass_key.as_bytes());
+    let mut fingerprint = [0u8; 32];
+    fingerprint.copy_from_slice(&mac.finalize().into_bytes());
+    Ok(fingerprint)
+}
+
+impl Debug for KeyShareId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyShareId")
+            .field("store_id", &self.store_id)
+            .finish()
+    }
+}
+
+/// The decrypted keys of a store, shared by the handles of the process which
+/// open the store with the same pass key.
+///
+/// The registry only holds weak references, so that the keys are dropped and
+/// zeroized along with the last handle using them.
+pub struct SharedKeys {
+    id: KeyShareId,
+    store_key: Arc<StoreKey>,
+    // the profile keys are reused only when decrypted from the same ciphertext
+    profile_keys: Mutex<HashMap<ProfileId, (Vec<u8>, Weak<ProfileKey>)>>,
+}
+
+impl SharedKeys {
+    /// Find the keys shared under an ID, if they are still held by a handle
+    pub fn get(id: &KeyShareId) -> Option<Arc<Self>> {
+        shared_keys().get(id).and_then(Weak::upgrade)
+    }
+
+    /// Share a store key under an ID. The keys already shared under the same ID
+    /// are returned instead, when registered by a concurrent open
+    pub fn register(id: KeyShareId, store_key: StoreKey) -> Arc<Self> {
+        let mut registry = shared_keys();
+        registry.retain(|_, keys| keys.strong_count() > 0);
+        if let Some(existing) = registry.get(&id).and_then(Weak::upgrade) {
+            return existing;
+        }
+        let keys = Arc::new(Self {
+            id: id.clone(),
+            store_key: Arc::new(store_key),
+            profile_keys: Mutex::new(HashMap::new()),
+        });
+        registry.insert(id, Arc::downgrade(&keys));
+        keys
+    }
+
+    pub fn store_key(&self) -> Arc<StoreKey> {
+        self.store_key.clone()
+    }
+
+    /// Find a profile key decrypted by another handle from the same ciphertext
+    pub fn profile_key(&self, pid: ProfileId, ciphertext: &[u8]) -> Option<Arc<ProfileKey>> {
+        match self.profile_keys().get(&pid) {
+            Some((shared, key)) if shared.as_slice() == ciphertext => key.upgrade(),
+            _ => None,
+        }
+    }
+
+    pub fn add_profile_key(&self, pid: ProfileId, ciphertext: Vec<u8>, key: &Arc<ProfileKey>) {
+        self.profile_keys()
+            .insert(pid, (ciphertext, Arc::downgrade(key)));
+    }
+
+    /// Stop sharing the keys with the handles opened later, as when the store
+    /// key is replaced
+    pub fn invalidate(&self) {
+        let mut registry = shared_keys();
+        if registry
+            .get(&self.id)
+            .map_or(false, |keys| std::ptr::eq(keys.as_ptr(), self))
+        {
+            registry.remove(&self.id);
+        }
+    }
+
+    fn profile_keys(&self) -> MutexGuard<'_, HashMap<ProfileId, (Vec<u8>, Weak<ProfileKey>)>> {
+        self.profile_keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Debug for SharedKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedKeys").field("id", &self.id).finish()
+    }
+}
+
+fn shared_keys() -> MutexGuard<'static, HashMap<KeyShareId, Weak<SharedKeys>>> {
+    // the map is left consistent if another thread panicked while holding the lock
+    SHARED_KEYS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protect::generate_raw_store_key;
+
+    #[test]
+    fn share_id_fingerprint() {
+        let id = KeyShareId::new("store", "raw", &PassKey::from("key")).unwrap();
+        assert_eq!(
+            id,
+            KeyShareId::new("store", "raw", &PassKey::from("key")).unwrap()
+        );
+        assert_ne!(
+            id,
+            KeyShareId::new("store", "raw", &PassKey::from("other")).unwrap()
+        );
+        assert_ne!(
+            id,
+            KeyShareId::new("other", "raw", &PassKey::from("key")).unwrap()
+        );
+        assert_ne!(
+            KeyShareId::new("store", "raw", &PassKey::from("")).unwrap(),
+            KeyShareId::new("store", "raw", &PassKey::empty()).unwrap()
+        );
+    }
+
+    #[test]
+    fn share_dropped_with_last_handle() {
+        let pass_key = generate_raw_store_key(None).unwrap();
+        let id = KeyShareId::new("store", "raw", &pass_key).unwrap();
+        let keys = SharedKeys::register(id.clone(), StoreKey::random().unwrap());
+        let again = SharedKeys::register(id.clone(), StoreKey::random().unwrap());
+        assert!(Arc::ptr_eq(&keys.store_key(), &again.store_key()));
+        drop(again);
+        assert!(SharedKeys::get(&id).is_some());
+
+        keys.invalidate();
+        assert!(SharedKeys::get(&id).is_none());
+        let keys = SharedKeys::register(id.clone(), StoreKey::random().unwrap());
+        drop(keys);
+        assert!(SharedKeys::get(&id).is_none());
+    }
+}
@@ -1,4 +1,10 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use async_lock::RwLock;
 
@@ -6,20 +12,30 @@ pub mod kdf;
 
 mod hmac_key;
 
+mod key_share;
+pub(crate) use self::key_share::{store_key_fingerprint, KeyShareId, SharedKeys};
+
 mod pass_key;
 pub use self::pass_key::PassKey;
 
 mod profile_key;
-pub use self::profile_key::ProfileKey;
+pub(crate) use self::profile_key::ENVELOPE_VERSION;
+use self::profile_key::{plaintext_tag, plaintext_tag_value};
+pub use self::profile_key::{EncryptionPolicy, ProfileKey, PROFILE_KEY_ALG};
+
+mod provision;
+pub use self::provision::{ProvisionKeys, ProvisionResult};
 
 mod store_key;
-pub use self::store_key::{generate_raw_store_key, StoreKey, StoreKeyMethod, StoreKeyReference};
+pub use self::store_key::{
+    generate_raw_store_key, parse_raw_store_key, StoreKey, StoreKeyMethod, StoreKeyReference,
+};
 
 use crate::{
     crypto::buffer::SecretBytes,
     error::Error,
     future::unblock,
-    storage::{EncEntryTag, EntryTag},
+    storage::{EncEntryTag, EntryTag, TagValueType},
 };
 
 pub type ProfileId = i64;
@@ -28,6 +44,10 @@ pub type ProfileId = i64;
 pub struct KeyCache {
     profile_info: RwLock<HashMap<String, (ProfileId, Arc<ProfileKey>)>>,
     pub(crate) store_key: Arc<StoreKey>,
+    // set once the store key is known to be correct
+    verified: AtomicBool,
+    // the keys shared with other handles opening the same store, if any
+    shared: Option<Arc<SharedKeys>>,
 }
 
 impl KeyCache {
@@ -35,24 +55,100 @@ impl KeyCache {
         Self {
             profile_info: RwLock::new(HashMap::new()),
             store_key: store_key.into(),
+            verified: AtomicBool::new(false),
+            shared: None,
         }
     }
 
-    pub async fn load_key(&self, ciphertext: Vec<u8>) -> Result<ProfileKey, Error> {
-        let store_key = self.store_key.clone();
-        unblock(move || {
-            let data = store_key
-                .unwrap_data(ciphertext)
-                .map_err(err_map!(Encryption, "Error decrypting profile key"))?;
-            Ok(ProfileKey::from_slice(data.as_ref())?)
-        })
+    /// Create a cache using the keys shared between the handles of a store
+    pub(crate) fn with_shared(shared: Arc<SharedKeys>) -> Self {
+        Self {
+            profile_info: RwLock::new(HashMap::new()),
+            store_key: shared.store_key(),
+            verified: AtomicBool::new(false),
+            shared: Some(shared),
+        }
+    }
+
+    /// Decrypt and decode the stored key for a profile.
+    ///
+    /// A key which cannot be decrypted is reported as corrupted when the store
+    /// key has already been used successfully, otherwise the store key itself
+    /// is likely to be incorrect. A missing key produces a `KeyNotFound` error.
+    pub async fn load_key(
+        &self,
+        pid: ProfileId,
+        ciphertext: Option<Vec<u8>>,
+    ) -> Result<ProfileKey, Error> {
+        let verified = self.verified.load(Ordering::Acquire);
+        let reason = if verified {
+            "the stored key is corrupted"
+        } else {
+            "the store key may be incorrect"
+        };
+        let key = unwrap_profile_key(pid, ciphertext, self.store_key.clone(), reason).await?;
+        self.verified.store(true, Ordering::Release);
+        Ok(key)
+    }
+
+    /// Decrypt and decode the stored key for a profile which is protected by
+    /// its own wrapping key, derived from the profile passphrase.
+    pub async fn unlock_key(
+        &self,
+        pid: ProfileId,
+        ciphertext: Option<Vec<u8>>,
+        wrap_key: StoreKey,
+    ) -> Result<ProfileKey, Error> {
+        unwrap_profile_key(
+            pid,
+            ciphertext,
+            Arc::new(wrap_key),
+            "the passphrase may be incorrect",
+        )
         .await
     }
 
-    pub fn add_profile_mut(&mut self, ident: String, pid: ProfileId, key: ProfileKey) {
-        self.profile_info
-            .get_mut()
-            .insert(ident, (pid, Arc::new(key)));
+    /// Decrypt and decode the stored key for a profile, as for `load_key`.
+    ///
+    /// When the keys of the store are shared, a key already decrypted from the
+    /// same ciphertext by another handle is reused.
+    pub async fn load_shared_key(
+        &self,
+        pid: ProfileId,
+        ciphertext: Option<Vec<u8>>,
+    ) -> Result<Arc<ProfileKey>, Error> {
+        let shared = match (self.shared.as_ref(), ciphertext.as_ref()) {
+            (Some(shared), Some(ciphertext)) => {
+                if let Some(key) = shared.profile_key(pid, ciphertext) {
+                    self.verified.store(true, Ordering::Release);
+                    return Ok(key);
+                }
+                Some((shared, ciphertext.clone()))
+            }
+            _ => None,
+        };
+        let key = Arc::new(self.load_key(pid, ciphertext).await?);
+        if let Some((shared, ciphertext)) = shared {
+            shared.add_profile_key(pid, ciphertext, &key);
+        }
+        Ok(key)
+    }
+
+    /// Stop sharing the keys of the store with the handles opened later
+    pub fn unshare(&self) {
+        if let Some(shared) = self.shared.as_ref() {
+            shared.invalidate();
+        }
+    }
+
+    pub fn add_profile_mut(
+        &mut self,
+        ident: String,
+        pid: ProfileId,
+        key: impl Into<Arc<ProfileKey>>,
+    ) {
+        *self.verified.get_mut() = true;
+        self.profile_info.get_mut().insert(ident, (pid, key.into()));
     }
 
     pub async fn add_profile(&self, ident: String, pid: ProfileId, key: Arc<ProfileKey>) {
@@ -62,6 +158,52 @@ impl KeyCache {
     pub async fn get_profile(&self, name: &str) -> Option<(ProfileId, Arc<ProfileKey>)> {
         self.profile_info.read().await.get(name).cloned()
     }
+
+    /// Drop the key for a profile from the cache, returning whether it was held.
+    /// A profile protected by a passphrase must be unlocked again before use.
+    pub async fn lock_profile(&self, name: &str) -> bool {
+        self.profile_info.write().await.remove(name).is_some()
+    }
+
+    /// The number of profile keys held in the cache
+    pub async fn profile_count(&self) -> usize {
+        self.profile_info.read().await.len()
+    }
+}
+
+async fn unwrap_profile_key(
+    pid: ProfileId,
+    ciphertext: Option<Vec<u8>>,
+    wrap_key: Arc<StoreKey>,
+    reason: &'static str,
+) -> Result<ProfileKey, Error> {
+    let ciphertext = ciphertext.ok_or_else(|| {
+        err_msg!(
+            KeyNotFound,
+            "Profile key not found (profile id {}): the stored key has been removed",
+            pid
+        )
+    })?;
+    unblock(move || {
+        let data = wrap_key.unwrap_data(ciphertext).map_err(|err| {
+            err_msg!(
+                Encryption,
+                "Error decrypting profile key (profile id {}): {}",
+                pid,
+                reason
+            )
+            .with_cause(err)
+        })?;
+        ProfileKey::from_slice(data.as_ref()).map_err(|err| {
+            err_msg!(
+                Encryption,
+                "Error decoding profile key (profile id {}): the stored key is corrupted",
+                pid
+            )
+            .with_cause(err)
+        })
+    })
+    .await
 }
 
 pub(crate) trait EntryEncryptor {
@@ -71,11 +213,14 @@ pub(crate) trait EntryEncryptor {
 
     fn encrypt_entry_category(&self, category: SecretBytes) -> Result<Vec<u8>, Error>;
     fn encrypt_entry_name(&self, name: SecretBytes) -> Result<Vec<u8>, Error>;
+    // the value is written within an envelope when `envelope` is set, once the
+    // store has opted in, and otherwise in the layout read by prior releases
     fn encrypt_entry_value(
         &self,
         category: &[u8],
         name: &[u8],
         value: SecretBytes,
+        envelope: bool,
     ) -> Result<Vec<u8>, Error>;
     fn encrypt_entry_tags(&self, tags: Vec<EntryTag>) -> Result<Vec<EncEntryTag>, Error>;
 
@@ -104,6 +249,7 @@ impl EntryEncryptor for NullEncryptor {
         _category: &[u8],
         _name: &[u8],
         value: SecretBytes,
+        _envelope: bool,
     ) -> Result<Vec<u8>, Error> {
         Ok(value.into_vec())
     }
@@ -115,12 +261,17 @@ impl EntryEncryptor for NullEncryptor {
                     name: name.into_bytes().into(),
                     value: value.into_bytes().into(),
                     plaintext: false,
+                    value_type: TagValueType::Text,
                 },
-                EntryTag::Plaintext(name, value) => EncEntryTag {
-                    name: name.into_bytes().into(),
-                    value: value.into_bytes().into(),
-                    plaintext: true,
-                },
+                tag => {
+                    let (value, value_type) = plaintext_tag_value(&tag);
+                    EncEntryTag {
+                        name: tag.into_name().into_bytes(),
+                        value,
+                        plaintext: true,
+                        value_type,
+                    }
+                }
             })
             .collect())
     }
@@ -144,7 +295,7 @@ impl EntryEncryptor for NullEncryptor {
             let name = String::from_utf8(tag.name).map_err(err_map!(Encryption))?;
             let value = String::from_utf8(tag.value).map_err(err_map!(Encryption))?;
             acc.push(if tag.plaintext {
-                EntryTag::Plaintext(name, value)
+                plaintext_tag(name, value, tag.value_type)?
             } else {
                 EntryTag::Encrypted(name, value)
             });
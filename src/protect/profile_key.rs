@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 
@@ -5,7 +7,10 @@ use super::hmac_key::{HmacDerive, HmacKey};
 use super::EntryEncryptor;
 use crate::{
     crypto::{
-        alg::chacha20::{Chacha20Key, C20P},
+        alg::{
+            chacha20::{Chacha20Key, C20P},
+            Chacha20Types, KeyAlg,
+        },
         buffer::{ArrayKey, ResizeBuffer, SecretBytes, WriteBuffer},
         encrypt::{KeyAeadInPlace, KeyAeadMeta},
         generic_array::typenum::{Unsigned, U32},
@@ -13,12 +18,79 @@ use crate::{
         repr::KeyGen,
     },
     error::Error,
-    storage::{EncEntryTag, EntryTag},
+    storage::{
+        decode_int_tag_value, encode_int_tag_value, EncEntryTag, EntryKind, EntryTag, TagValueType,
+    },
 };
 
 pub type ProfileKey = ProfileKeyImpl<Chacha20Key<C20P>, HmacKey<Sha256, U32>>;
 
+/// The key algorithm used to encrypt the records of a profile
+pub const PROFILE_KEY_ALG: KeyAlg = KeyAlg::Chacha20(Chacha20Types::C20P);
+
+/// The default limit on the size of the entry and tag values encrypted or
+/// decrypted with a profile key
+pub const DEFAULT_MAX_PLAINTEXT_SIZE: usize = 64 * 1024 * 1024;
+
+/// The version of the envelope header written before entry value ciphertexts
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// The length of the envelope header: a version byte followed by a flags byte
+pub const ENVELOPE_HEADER_LEN: usize = 2;
+
+// no flags are defined for the current envelope version
+const ENVELOPE_FLAGS: u8 = 0;
+
+fn default_max_plaintext_size() -> usize {
+    DEFAULT_MAX_PLAINTEXT_SIZE
+}
+
+/// Selects the parts of a profile's entries which are stored encrypted.
+///
+/// The policy is fixed when the profile is created. Fields which are not
+/// encrypted are stored as plaintext, and remain searchable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct EncryptionPolicy {
+    /// Encrypt entry categories
+    #[serde(rename = "c")]
+    pub category: bool,
+    /// Encrypt entry names
+    #[serde(rename = "n")]
+    pub name: bool,
+    /// Encrypt entry values and value types. The secret material of keys stored
+    /// in the profile is encrypted regardless of this setting
+    #[serde(rename = "v")]
+    pub value: bool,
+    /// Encrypt tag names and the values of encrypted tags
+    #[serde(rename = "t")]
+    pub tags: bool,
+}
+
+impl EncryptionPolicy {
+    /// Check whether all entry fields are encrypted
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for EncryptionPolicy {
+    fn default() -> Self {
+        Self {
+            category: true,
+            name: true,
+            value: true,
+            tags: true,
+        }
+    }
+}
+
 /// A record combining the keys required to encrypt and decrypt storage entries
+///
+/// The searchable ciphertexts and index hashes derived from these keys are
+/// persisted and compared across processes, so they are computed only with
+/// HMAC-SHA256 and ChaCha20-Poly1305 over explicitly encoded inputs. Their
+/// output for a given key never changes between releases: a new construction
+/// requires a new key version.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(bound(
     deserialize = "Key: for<'a> Deserialize<'a>, HmacKey: for<'a> Deserialize<'a>",
@@ -38,6 +110,15 @@ pub struct ProfileKeyImpl<Key, HmacKey> {
     pub tag_value_key: Key,
     #[serde(rename = "thk")]
     pub tags_hmac_key: HmacKey,
+    #[serde(
+        rename = "pol",
+        default,
+        skip_serializing_if = "EncryptionPolicy::is_default"
+    )]
+    pub policy: EncryptionPolicy,
+    /// The limit on the size of entry and tag values, which is not stored with the key
+    #[serde(skip, default = "default_max_plaintext_size")]
+    pub max_plaintext_size: usize,
 }
 
 impl<Key, HmacKey> ProfileKeyImpl<Key, HmacKey>
@@ -46,6 +127,10 @@ where
     HmacKey: KeyGen,
 {
     pub fn new() -> Result<Self, Error> {
+        Self::new_with_policy(EncryptionPolicy::default())
+    }
+
+    pub fn new_with_policy(policy: EncryptionPolicy) -> Result<Self, Error> {
         Ok(Self {
             category_key: KeyGen::random()?,
             name_key: KeyGen::random()?,
@@ -53,10 +138,36 @@ where
             tag_name_key: KeyGen::random()?,
             tag_value_key: KeyGen::random()?,
             tags_hmac_key: KeyGen::random()?,
+            policy,
+            max_plaintext_size: DEFAULT_MAX_PLAINTEXT_SIZE,
         })
     }
 }
 
+impl<Key, HmacKey> ProfileKeyImpl<Key, HmacKey> {
+    /// Set the limit on the size of the entry and tag values encrypted or
+    /// decrypted with this key
+    pub fn with_max_plaintext_size(mut self, max_size: usize) -> Self {
+        self.max_plaintext_size = max_size;
+        self
+    }
+}
+
+impl<Key: Clone, HmacKey: Clone> ProfileKeyImpl<Key, HmacKey> {
+    /// Get the key to use for entries of the given kind. The values of KMS
+    /// entries hold secret key material, and are encrypted even when the
+    /// encryption policy excludes entry values
+    pub fn for_kind(self: Arc<Self>, kind: EntryKind) -> Arc<Self> {
+        if kind == EntryKind::Kms && !self.policy.value {
+            let mut key = (*self).clone();
+            key.policy.value = true;
+            Arc::new(key)
+        } else {
+            self
+        }
+    }
+}
+
 impl<Key, HmacKey> ProfileKeyImpl<Key, HmacKey>
 where
     Key: Serialize + for<'de> Deserialize<'de>,
@@ -96,14 +207,73 @@ where
         Ok(buffer.into_vec())
     }
 
-    fn encrypt(mut buffer: SecretBytes, enc_key: &Key) -> Result<Vec<u8>, Error> {
+    fn encrypt(mut buffer: SecretBytes, enc_key: &Key, aad: &[u8]) -> Result<Vec<u8>, Error> {
         let nonce = ArrayKey::<Key::NonceSize>::random();
-        enc_key.encrypt_in_place(&mut buffer, nonce.as_ref(), &[])?;
+        enc_key.encrypt_in_place(&mut buffer, nonce.as_ref(), aad)?;
         buffer.buffer_insert(0, nonce.as_ref())?;
         Ok(buffer.into_vec())
     }
 
+    /// Encrypt an entry value within an envelope, prefixing the ciphertext with
+    /// the version and flags used to produce it. The header is authenticated
+    /// along with the value
+    fn encrypt_envelope(buffer: SecretBytes, enc_key: &Key) -> Result<Vec<u8>, Error> {
+        let header = [ENVELOPE_VERSION, ENVELOPE_FLAGS];
+        let mut buffer = SecretBytes::from(Self::encrypt(buffer, enc_key, &header)?);
+        buffer.buffer_insert(0, &header)?;
+        Ok(buffer.into_vec())
+    }
+
+    /// Decrypt an entry value written either within an envelope or in the
+    /// headerless layout used before envelopes were introduced.
+    ///
+    /// A legacy ciphertext begins with a random nonce, which may happen to
+    /// match the envelope header, so a value which fails authentication as an
+    /// envelope is checked again as a legacy ciphertext.
+    fn decrypt_envelope(ciphertext: Vec<u8>, enc_key: &Key) -> Result<SecretBytes, Error> {
+        if is_envelope(&ciphertext) {
+            let (header, framed) = ciphertext.split_at(ENVELOPE_HEADER_LEN);
+            if let Ok(value) = Self::decrypt_with_aad(framed.to_vec(), enc_key, header) {
+                return Ok(value);
+            }
+        }
+        Self::decrypt(ciphertext, enc_key)
+    }
+
+    /// Check the size of a value before it is encrypted
+    fn check_plaintext_size(&self, len: usize) -> Result<(), Error> {
+        if len > self.max_plaintext_size {
+            return Err(err_msg!(
+                Input,
+                "Value exceeds the maximum size of {} bytes",
+                self.max_plaintext_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check the size of a ciphertext before it is decrypted. The ciphertext is
+    /// read from storage, so an oversized value is treated as corrupted
+    fn check_ciphertext_size(&self, len: usize) -> Result<(), Error> {
+        if len > Self::encrypted_size(0).saturating_add(self.max_plaintext_size) {
+            return Err(err_msg!(
+                Encryption,
+                "Encrypted value exceeds the maximum size of {} bytes, it may be corrupted",
+                self.max_plaintext_size
+            ));
+        }
+        Ok(())
+    }
+
     fn decrypt(ciphertext: Vec<u8>, enc_key: &Key) -> Result<SecretBytes, Error> {
+        Self::decrypt_with_aad(ciphertext, enc_key, &[])
+    }
+
+    fn decrypt_with_aad(
+        ciphertext: Vec<u8>,
+        enc_key: &Key,
+        aad: &[u8],
+    ) -> Result<SecretBytes, Error> {
         let nonce_len = Key::NonceSize::USIZE;
         if ciphertext.len() < nonce_len {
             return Err(err_msg!(Encryption, "invalid encrypted value"));
@@ -111,10 +281,14 @@ where
         let mut buffer = SecretBytes::from(ciphertext);
         let nonce = ArrayKey::<Key::NonceSize>::from_slice(&buffer.as_ref()[..nonce_len]);
         buffer.buffer_remove(0..nonce_len)?;
-        enc_key.decrypt_in_place(&mut buffer, nonce.as_ref(), &[])?;
+        enc_key.decrypt_in_place(&mut buffer, nonce.as_ref(), aad)?;
         Ok(buffer)
     }
 
+    /// Derive the key for an entry value from the entry category and name.
+    ///
+    /// The value ciphertext is bound to its entry: a value moved to another
+    /// entry fails authentication when it is decrypted.
     #[inline]
     fn derive_value_key(&self, category: &[u8], name: &[u8]) -> Result<Key, Error> {
         Ok(Key::from_key_derivation(self.item_hmac_key.hmac_deriver(
@@ -128,20 +302,73 @@ where
     }
 
     pub fn encrypt_tag_name(&self, name: SecretBytes) -> Result<Vec<u8>, Error> {
+        if !self.policy.tags {
+            return Ok(name.into_vec());
+        }
         Self::encrypt_searchable(name, &self.tag_name_key, &self.tags_hmac_key)
     }
 
     pub fn encrypt_tag_value(&self, value: SecretBytes) -> Result<Vec<u8>, Error> {
+        if !self.policy.tags {
+            return Ok(value.into_vec());
+        }
+        self.check_plaintext_size(value.len())?;
         Self::encrypt_searchable(value, &self.tag_value_key, &self.tags_hmac_key)
     }
 
     pub fn decrypt_tag_name(&self, enc_tag_name: Vec<u8>) -> Result<SecretBytes, Error> {
+        if !self.policy.tags {
+            return Ok(enc_tag_name.into());
+        }
         Self::decrypt(enc_tag_name, &self.tag_name_key)
     }
 
     pub fn decrypt_tag_value(&self, enc_tag_value: Vec<u8>) -> Result<SecretBytes, Error> {
+        if !self.policy.tags {
+            return Ok(enc_tag_value.into());
+        }
+        self.check_ciphertext_size(enc_tag_value.len())?;
         Self::decrypt(enc_tag_value, &self.tag_value_key)
     }
+
+    /// Derive the searchable index value for a prefix of an entry name
+    pub fn entry_name_prefix_hash(&self, category: &[u8], prefix: &[u8]) -> Result<Vec<u8>, Error> {
+        let hash = ArrayKey::<U32>::from_key_derivation(self.item_hmac_key.hmac_deriver(&[
+            b"name-prefix",
+            &(category.len() as u32).to_be_bytes(),
+            category,
+            prefix,
+        ]))?;
+        Ok(hash.as_ref().to_vec())
+    }
+
+    /// Derive the entity tag of an entry value, which is unchanged when the
+    /// same value is encrypted again
+    pub fn entry_value_hash(
+        &self,
+        category: &[u8],
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let hash = ArrayKey::<U32>::from_key_derivation(self.item_hmac_key.hmac_deriver(&[
+            b"value-hash",
+            &(category.len() as u32).to_be_bytes(),
+            category,
+            &(name.len() as u32).to_be_bytes(),
+            name,
+            value,
+        ]))?;
+        Ok(hash.as_ref().to_vec())
+    }
+
+    /// Derive the searchable index value for a prefix of an entry category
+    pub fn entry_category_prefix_hash(&self, prefix: &[u8]) -> Result<Vec<u8>, Error> {
+        let hash = ArrayKey::<U32>::from_key_derivation(
+            self.item_hmac_key
+                .hmac_deriver(&[b"category-prefix", prefix]),
+        )?;
+        Ok(hash.as_ref().to_vec())
+    }
 }
 
 impl<Key: PartialEq, HmacKey: PartialEq> PartialEq for ProfileKeyImpl<Key, HmacKey> {
@@ -152,6 +379,7 @@ impl<Key: PartialEq, HmacKey: PartialEq> PartialEq for ProfileKeyImpl<Key, HmacK
             && self.tag_name_key == other.tag_name_key
             && self.tag_value_key == other.tag_value_key
             && self.tags_hmac_key == other.tags_hmac_key
+            && self.policy == other.policy
     }
 }
 impl<Key: PartialEq, HmacKey: PartialEq> Eq for ProfileKeyImpl<Key, HmacKey> {}
@@ -162,16 +390,23 @@ where
     HmacKey: HmacDerive,
 {
     fn prepare_input(input: &[u8]) -> SecretBytes {
-        let mut buf = SecretBytes::with_capacity(Self::encrypted_size(input.len()));
+        let mut buf =
+            SecretBytes::with_capacity(ENVELOPE_HEADER_LEN + Self::encrypted_size(input.len()));
         buf.buffer_write(input).unwrap();
         buf
     }
 
     fn encrypt_entry_category(&self, category: SecretBytes) -> Result<Vec<u8>, Error> {
+        if !self.policy.category {
+            return Ok(category.into_vec());
+        }
         Self::encrypt_searchable(category, &self.category_key, &self.item_hmac_key)
     }
 
     fn encrypt_entry_name(&self, name: SecretBytes) -> Result<Vec<u8>, Error> {
+        if !self.policy.name {
+            return Ok(name.into_vec());
+        }
         Self::encrypt_searchable(name, &self.name_key, &self.item_hmac_key)
     }
 
@@ -180,16 +415,31 @@ where
         category: &[u8],
         name: &[u8],
         value: SecretBytes,
+        envelope: bool,
     ) -> Result<Vec<u8>, Error> {
+        if !self.policy.value {
+            return Ok(value.into_vec());
+        }
+        self.check_plaintext_size(value.len())?;
         let value_key = self.derive_value_key(category, name)?;
-        Self::encrypt(value, &value_key)
+        if envelope {
+            Self::encrypt_envelope(value, &value_key)
+        } else {
+            Self::encrypt(value, &value_key, &[])
+        }
     }
 
     fn decrypt_entry_category(&self, enc_category: Vec<u8>) -> Result<String, Error> {
+        if !self.policy.category {
+            return decode_utf8(enc_category);
+        }
         decode_utf8(Self::decrypt(enc_category, &self.category_key)?.into_vec())
     }
 
     fn decrypt_entry_name(&self, enc_name: Vec<u8>) -> Result<String, Error> {
+        if !self.policy.name {
+            return decode_utf8(enc_name);
+        }
         decode_utf8(Self::decrypt(enc_name, &self.name_key)?.into_vec())
     }
 
@@ -199,28 +449,46 @@ where
         name: &[u8],
         enc_value: Vec<u8>,
     ) -> Result<SecretBytes, Error> {
+        if !self.policy.value {
+            return Ok(enc_value.into());
+        }
+        // the envelope header is not counted against the size limit
+        let header_len = if is_envelope(&enc_value) {
+            ENVELOPE_HEADER_LEN
+        } else {
+            0
+        };
+        self.check_ciphertext_size(enc_value.len() - header_len)?;
         let value_key = self.derive_value_key(category, name)?;
-        Self::decrypt(enc_value, &value_key)
+        Self::decrypt_envelope(enc_value, &value_key).map_err(|_| {
+            err_msg!(
+                Encryption,
+                "Entry value failed authentication, it may have been modified or moved"
+            )
+        })
     }
 
     fn encrypt_entry_tags(&self, tags: Vec<EntryTag>) -> Result<Vec<EncEntryTag>, Error> {
         tags.into_iter()
             .map(|tag| match tag {
-                EntryTag::Plaintext(name, value) => {
+                EntryTag::Encrypted(name, value) => {
                     let name = self.encrypt_tag_name(name.into())?;
+                    let value = self.encrypt_tag_value(value.into())?;
                     Ok(EncEntryTag {
                         name,
-                        value: value.into_bytes().into(),
-                        plaintext: true,
+                        value,
+                        plaintext: false,
+                        value_type: TagValueType::Text,
                     })
                 }
-                EntryTag::Encrypted(name, value) => {
-                    let name = self.encrypt_tag_name(name.into())?;
-                    let value = self.encrypt_tag_value(value.into())?;
+                tag => {
+                    let (value, value_type) = plaintext_tag_value(&tag);
+                    let name = self.encrypt_tag_name(tag.into_name().into())?;
                     Ok(EncEntryTag {
                         name,
                         value,
-                        plaintext: false,
+                        plaintext: true,
+                        value_type,
                     })
                 }
             })
@@ -231,8 +499,7 @@ where
         enc_tags.into_iter().try_fold(vec![], |mut acc, tag| {
             let name = decode_utf8(self.decrypt_tag_name(tag.name)?.into_vec())?;
             acc.push(if tag.plaintext {
-                let value = decode_utf8(tag.value)?;
-                EntryTag::Plaintext(name, value)
+                plaintext_tag(name, decode_utf8(tag.value)?, tag.value_type)?
             } else {
                 let value = decode_utf8(self.decrypt_tag_value(tag.value)?.into_vec())?;
                 EntryTag::Encrypted(name, value)
@@ -242,14 +509,60 @@ where
     }
 }
 
+/// Check whether a value ciphertext begins with the current envelope header
+#[inline]
+fn is_envelope(ciphertext: &[u8]) -> bool {
+    ciphertext.len() >= ENVELOPE_HEADER_LEN
+        && ciphertext[0] == ENVELOPE_VERSION
+        && ciphertext[1] == ENVELOPE_FLAGS
+}
+
 #[inline(always)]
 fn decode_utf8(value: Vec<u8>) -> Result<String, Error> {
     String::from_utf8(value).map_err(err_map!(Encryption))
 }
 
+/// Format the stored value of a plaintext tag, along with its type
+pub(crate) fn plaintext_tag_value(tag: &EntryTag) -> (Vec<u8>, TagValueType) {
+    match tag {
+        EntryTag::Int(_, value) => (
+            encode_int_tag_value(value.get()).into_bytes(),
+            TagValueType::Int,
+        ),
+        EntryTag::Bool(_, value) => (value.to_string().into_bytes(), TagValueType::Bool),
+        EntryTag::Encrypted(_, value) | EntryTag::Plaintext(_, value) => {
+            (value.as_bytes().to_vec(), TagValueType::Text)
+        }
+    }
+}
+
+/// Restore a plaintext tag from its stored value and type
+pub(crate) fn plaintext_tag(
+    name: String,
+    value: String,
+    value_type: TagValueType,
+) -> Result<EntryTag, Error> {
+    Ok(match value_type {
+        TagValueType::Text => EntryTag::Plaintext(name, value),
+        TagValueType::Int => EntryTag::Int(
+            name,
+            decode_int_tag_value(&value)
+                .ok_or_else(|| err_msg!(Encryption, "Invalid integer tag value"))?
+                .into(),
+        ),
+        TagValueType::Bool => EntryTag::Bool(
+            name,
+            value
+                .parse()
+                .map_err(err_map!(Encryption, "Invalid boolean tag value"))?,
+        ),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::repr::KeySecretBytes;
     use crate::storage::Entry;
 
     #[test]
@@ -275,6 +588,7 @@ mod tests {
                 test_record.category.as_bytes(),
                 test_record.name.as_bytes(),
                 test_record.value.clone().into(),
+                false,
             )
             .unwrap();
         let enc_tags = key.encrypt_entry_tags(test_record.tags.clone()).unwrap();
@@ -296,6 +610,240 @@ mod tests {
         assert_eq!(test_record, cmp_record);
     }
 
+    #[test]
+    fn decrypt_entry_value_bound() {
+        let key = ProfileKey::new().unwrap();
+        let enc_value = key
+            .encrypt_entry_value(b"category", b"name", "value".into(), false)
+            .unwrap();
+        let cases: [(&[u8], &[u8]); 2] = [(b"category", b"other"), (b"other", b"name")];
+        for (category, name) in cases.iter().copied() {
+            let err = key
+                .decrypt_entry_value(category, name, enc_value.clone())
+                .expect_err("Expected authentication failure");
+            assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        }
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value)
+                .unwrap(),
+            &b"value"[..]
+        );
+    }
+
+    #[test]
+    fn decrypt_oversized_value() {
+        let key = ProfileKey::new().unwrap().with_max_plaintext_size(16);
+        let enc_value = key
+            .encrypt_entry_value(
+                b"category",
+                b"name",
+                SecretBytes::from(&[1u8; 16][..]),
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value)
+                .unwrap(),
+            &[1u8; 16][..]
+        );
+
+        // a crafted ciphertext which is one byte over the limit
+        let oversized = vec![0u8; ProfileKey::encrypted_size(17)];
+        let err = key
+            .decrypt_entry_value(b"category", b"name", oversized.clone())
+            .expect_err("Expected size limit failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        assert!(err.to_string().contains("maximum size"));
+        let err = key
+            .decrypt_tag_value(oversized)
+            .expect_err("Expected size limit failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        assert!(err.to_string().contains("maximum size"));
+
+        let err = key
+            .encrypt_entry_value(
+                b"category",
+                b"name",
+                SecretBytes::from(&[1u8; 17][..]),
+                false,
+            )
+            .expect_err("Expected size limit failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Input);
+        let err = key
+            .encrypt_tag_value(SecretBytes::from(&[1u8; 17][..]))
+            .expect_err("Expected size limit failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Input);
+    }
+
+    #[test]
+    fn max_plaintext_size_not_serialized() {
+        let key = ProfileKey::new().unwrap().with_max_plaintext_size(16);
+        let key_cbor = serde_cbor::to_vec(&key).unwrap();
+        let key_cmp: ProfileKey = serde_cbor::from_slice(&key_cbor).unwrap();
+        assert_eq!(key_cmp.max_plaintext_size, DEFAULT_MAX_PLAINTEXT_SIZE);
+    }
+
+    #[test]
+    fn persisted_hashes_expected() {
+        let key = ProfileKey {
+            category_key: Chacha20Key::from_secret_bytes(&[1u8; 32]).unwrap(),
+            name_key: Chacha20Key::from_secret_bytes(&[2u8; 32]).unwrap(),
+            item_hmac_key: HmacKey::from_slice(&[3u8; 32]).unwrap(),
+            tag_name_key: Chacha20Key::from_secret_bytes(&[4u8; 32]).unwrap(),
+            tag_value_key: Chacha20Key::from_secret_bytes(&[5u8; 32]).unwrap(),
+            tags_hmac_key: HmacKey::from_slice(&[6u8; 32]).unwrap(),
+            policy: EncryptionPolicy::default(),
+            max_plaintext_size: DEFAULT_MAX_PLAINTEXT_SIZE,
+        };
+        assert_eq!(
+            key.encrypt_entry_category("category".into()).unwrap(),
+            &hex!("7bf7c1dcdac0f2b56ed6eedcb9f834c900a3a48012dc6cc0bcfbd5cdce82de8f8f66cb8d")[..]
+        );
+        assert_eq!(
+            key.encrypt_tag_name("tag".into()).unwrap(),
+            &hex!("8aeb69a9e6d6c8a5ceea4c3cc85147cf34d24cbc4299198a8096b298afb718")[..]
+        );
+        assert_eq!(
+            key.entry_name_prefix_hash(b"category", b"na").unwrap(),
+            &hex!("aa1b9d6e7d15b2c36f30bc026a709e24f3c1c7709e2a2b099a9648e5e4b12ad4")[..]
+        );
+        assert_eq!(
+            key.entry_category_prefix_hash(b"cat").unwrap(),
+            &hex!("a21d32af37bbef4c04098f093b728bea6415105d3320d3acb0b57e8256a4c816")[..]
+        );
+        assert_eq!(
+            key.entry_value_hash(b"category", b"name", b"value")
+                .unwrap(),
+            &hex!("e308699279544e269c3150a03277ee0b8d84db8bf84b7b0f5bda8e0370e88bbf")[..]
+        );
+    }
+
+    fn fixture_key() -> ProfileKey {
+        ProfileKey {
+            category_key: Chacha20Key::from_secret_bytes(&[1u8; 32]).unwrap(),
+            name_key: Chacha20Key::from_secret_bytes(&[2u8; 32]).unwrap(),
+            item_hmac_key: HmacKey::from_slice(&[3u8; 32]).unwrap(),
+            tag_name_key: Chacha20Key::from_secret_bytes(&[4u8; 32]).unwrap(),
+            tag_value_key: Chacha20Key::from_secret_bytes(&[5u8; 32]).unwrap(),
+            tags_hmac_key: HmacKey::from_slice(&[6u8; 32]).unwrap(),
+            policy: EncryptionPolicy::default(),
+            max_plaintext_size: DEFAULT_MAX_PLAINTEXT_SIZE,
+        }
+    }
+
+    #[test]
+    fn encrypt_value_legacy() {
+        let key = ProfileKey::new().unwrap();
+        let enc_value = key
+            .encrypt_entry_value(b"category", b"name", "value".into(), false)
+            .unwrap();
+        assert_eq!(enc_value.len(), ProfileKey::encrypted_size(5));
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value)
+                .unwrap(),
+            &b"value"[..]
+        );
+    }
+
+    #[test]
+    fn encrypt_value_envelope() {
+        let key = ProfileKey::new().unwrap();
+        let enc_value = key
+            .encrypt_entry_value(b"category", b"name", "value".into(), true)
+            .unwrap();
+        assert_eq!(&enc_value[..ENVELOPE_HEADER_LEN], &[ENVELOPE_VERSION, 0]);
+        assert_eq!(
+            enc_value.len(),
+            ENVELOPE_HEADER_LEN + ProfileKey::encrypted_size(5)
+        );
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value.clone())
+                .unwrap(),
+            &b"value"[..]
+        );
+
+        // the header is authenticated with the value
+        for (index, byte) in [(0, ENVELOPE_VERSION + 1), (1, 1)].iter().copied() {
+            let mut modified = enc_value.clone();
+            modified[index] = byte;
+            let err = key
+                .decrypt_entry_value(b"category", b"name", modified)
+                .expect_err("Expected authentication failure");
+            assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        }
+        let err = key
+            .decrypt_entry_value(
+                b"category",
+                b"name",
+                enc_value[ENVELOPE_HEADER_LEN..].to_vec(),
+            )
+            .expect_err("Expected authentication failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+
+        // searchable tag ciphertexts are not framed, so that they match existing rows
+        let enc_tag = key.encrypt_tag_value("value".into(), true).unwrap();
+        assert_eq!(enc_tag.len(), ProfileKey::encrypted_size(5));
+        assert_eq!(
+            enc_tag,
+            key.encrypt_tag_value("value".into(), true).unwrap()
+        );
+    }
+
+    #[test]
+    fn decrypt_value_fixtures() {
+        let key = fixture_key();
+        let fixtures: [&[u8]; 3] = [
+            // written before envelopes were introduced
+            &hex!("0909090909090909090909095ffb3432c6673c7e289a44a0450871940c9b20ccb9"),
+            // written within a version 1 envelope
+            &hex!("01000909090909090909090909095ffb3432c6ab86805922ae9559688528bc0aee06e4"),
+            // written before envelopes, with a nonce which matches the envelope header
+            &hex!("010007070707070707070707516f193b3f437e7d6e506a07978543b520f3c5a65e"),
+        ];
+        for fixture in fixtures.iter() {
+            assert_eq!(
+                key.decrypt_entry_value(b"category", b"name", fixture.to_vec())
+                    .unwrap(),
+                &b"value"[..]
+            );
+            let err = key
+                .decrypt_entry_value(b"category", b"other", fixture.to_vec())
+                .expect_err("Expected authentication failure");
+            assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        }
+    }
+
+    #[test]
+    fn decrypt_value_envelope_bound() {
+        let key = ProfileKey::new().unwrap().with_max_plaintext_size(16);
+        let enc_value = key
+            .encrypt_entry_value(
+                b"category",
+                b"name",
+                SecretBytes::from(&[1u8; 16][..]),
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            enc_value.len(),
+            ENVELOPE_HEADER_LEN + ProfileKey::encrypted_size(16)
+        );
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value)
+                .unwrap(),
+            &[1u8; 16][..]
+        );
+
+        // a crafted envelope which is one byte over the limit
+        let mut oversized = vec![0u8; ENVELOPE_HEADER_LEN + ProfileKey::encrypted_size(17)];
+        oversized[0] = ENVELOPE_VERSION;
+        let err = key
+            .decrypt_entry_value(b"category", b"name", oversized)
+            .expect_err("Expected size limit failure");
+        assert_eq!(err.kind(), crate::ErrorKind::Encryption);
+        assert!(err.to_string().contains("maximum size"));
+    }
+
     #[test]
     fn check_encrypt_searchable() {
         let input = SecretBytes::from(&b"hello"[..]);
@@ -303,7 +851,7 @@ mod tests {
         let hmac_key = HmacKey::random().unwrap();
         let enc1 = ProfileKey::encrypt_searchable(input.clone(), &key, &hmac_key).unwrap();
         let enc2 = ProfileKey::encrypt_searchable(input.clone(), &key, &hmac_key).unwrap();
-        let enc3 = ProfileKey::encrypt(input.clone(), &key).unwrap();
+        let enc3 = ProfileKey::encrypt(input.clone(), &key, &[]).unwrap();
         assert_eq!(&enc1, &enc2);
         assert_ne!(&enc1, &enc3);
         let dec = ProfileKey::decrypt(enc1, &key).unwrap();
@@ -316,5 +864,87 @@ mod tests {
         let key_cbor = serde_cbor::to_vec(&key).unwrap();
         let key_cmp = serde_cbor::from_slice(&key_cbor).unwrap();
         assert_eq!(key, key_cmp);
+
+        let key = ProfileKey::new_with_policy(EncryptionPolicy {
+            value: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let key_cbor = serde_cbor::to_vec(&key).unwrap();
+        let key_cmp: ProfileKey = serde_cbor::from_slice(&key_cbor).unwrap();
+        assert_eq!(key, key_cmp);
+        assert!(!key_cmp.policy.value);
+    }
+
+    #[test]
+    fn encrypt_kms_value_policy() {
+        let key = Arc::new(
+            ProfileKey::new_with_policy(EncryptionPolicy {
+                value: false,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let item_value = key
+            .clone()
+            .for_kind(EntryKind::Item)
+            .encrypt_entry_value(b"category", b"name", "secret".into(), false)
+            .unwrap();
+        assert_eq!(&item_value[..], b"secret");
+
+        let kms_key = key.clone().for_kind(EntryKind::Kms);
+        let kms_value = kms_key
+            .encrypt_entry_value(b"category", b"name", "secret".into(), false)
+            .unwrap();
+        assert_ne!(&kms_value[..], b"secret");
+        assert_eq!(
+            kms_key
+                .decrypt_entry_value(b"category", b"name", kms_value)
+                .unwrap(),
+            &b"secret"[..]
+        );
+        assert!(!key.policy.value);
+    }
+
+    #[test]
+    fn encrypt_entry_policy() {
+        let key = ProfileKey::new_with_policy(EncryptionPolicy {
+            value: false,
+            tags: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let enc_category = key.encrypt_entry_category("category".into()).unwrap();
+        let enc_value = key
+            .encrypt_entry_value(b"category", b"name", "value".into(), false)
+            .unwrap();
+        let enc_tags = key
+            .encrypt_entry_tags(vec![EntryTag::Encrypted(
+                "enctag".to_string(),
+                "enctagval".to_string(),
+            )])
+            .unwrap();
+        assert_ne!(&enc_category[..], b"category");
+        assert_eq!(&enc_value[..], b"value");
+        assert_eq!(&enc_tags[0].name[..], b"enctag");
+        assert_eq!(&enc_tags[0].value[..], b"enctagval");
+        assert!(!enc_tags[0].plaintext);
+
+        assert_eq!(
+            key.decrypt_entry_category(enc_category).unwrap(),
+            "category"
+        );
+        assert_eq!(
+            key.decrypt_entry_value(b"category", b"name", enc_value)
+                .unwrap(),
+            &b"value"[..]
+        );
+        assert_eq!(
+            key.decrypt_entry_tags(enc_tags).unwrap(),
+            vec![EntryTag::Encrypted(
+                "enctag".to_string(),
+                "enctagval".to_string()
+            )]
+        );
     }
 }
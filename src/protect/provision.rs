@@ -0,0 +1,241 @@
+use std::fmt::{self, Debug, Formatter};
+
+use super::{PassKey, ProfileId, ProfileKey, StoreKey, StoreKeyMethod, StoreKeyReference};
+use crate::{crypto::buffer::SecretBytes, error::Error, future::unblock_batch};
+
+/// Key material for provisioning a new store.
+///
+/// Resolving the store key may involve an expensive key derivation. The keys
+/// may be generated in advance, away from the latency-sensitive path, and
+/// later passed to `provision_with_keys` on the backend options.
+pub struct ProvisionKeys {
+    pub(crate) profile_key: ProfileKey,
+    pub(crate) enc_profile_key: Vec<u8>,
+    pub(crate) store_key: StoreKey,
+    pub(crate) store_key_ref: String,
+    pub(crate) generated_key: Option<SecretBytes>,
+    pub(crate) kdf_salt: Option<SecretBytes>,
+}
+
+impl ProvisionKeys {
+    /// Generate the keys for a new store, blocking the current thread
+    pub fn generate(method: StoreKeyMethod, pass_key: PassKey<'_>) -> Result<Self, Error> {
+        let generate_key = method == StoreKeyMethod::RawKey && pass_key.is_empty();
+        let (store_key, store_key_ref) = method.resolve(pass_key)?;
+        // a generated raw key is the only means of opening the store again
+        let generated_key = if generate_key {
+            Some(SecretBytes::from_slice(store_key.to_passkey().as_bytes()))
+        } else {
+            None
+        };
+        let kdf_salt = match &store_key_ref {
+            StoreKeyReference::DeriveKey(method, detail) => Some(method.salt(detail)?),
+            _ => None,
+        };
+        let profile_key = ProfileKey::new()?;
+        let enc_profile_key = store_key.wrap_data(profile_key.to_bytes()?)?;
+        Ok(Self {
+            profile_key,
+            enc_profile_key,
+            store_key,
+            store_key_ref: store_key_ref.into_uri(),
+            generated_key,
+            kdf_salt,
+        })
+    }
+
+    /// Generate the keys for a new store on the blocking thread pool
+    pub async fn create(method: StoreKeyMethod, pass_key: PassKey<'_>) -> Result<Self, Error> {
+        Self::create_batch(1, method, pass_key)
+            .await
+            .map(|mut keys| keys.remove(0))
+    }
+
+    /// Generate the keys for `count` independent stores.
+    ///
+    /// Each set of keys is generated in parallel on the blocking thread pool.
+    /// A derived store key uses a new salt for each store, and every store is
+    /// assigned a distinct profile key.
+    pub async fn create_batch(
+        count: usize,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> Result<Vec<Self>, Error> {
+        let pass_key = pass_key.into_owned();
+        unblock_batch((0..count).map(|_| {
+            let method = method.clone();
+            let pass_key = pass_key.clone();
+            move || Self::generate(method, pass_key)
+        }))
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Accessor for the reference to the store key
+    pub fn store_key_ref(&self) -> &str {
+        self.store_key_ref.as_str()
+    }
+}
+
+impl Debug for ProvisionKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProvisionKeys")
+            .field("store_key_ref", &self.store_key_ref)
+            .finish()
+    }
+}
+
+/// The details of a newly provisioned store.
+///
+/// The result holds everything needed to open the store again, including
+/// any key material generated during provisioning, which is not recorded
+/// anywhere else and should be backed up by the caller. The contents of the
+/// generated key and salt are not included in the `Debug` output.
+pub struct ProvisionResult {
+    pub(crate) store_key_method: StoreKeyMethod,
+    pub(crate) store_key_ref: String,
+    pub(crate) generated_key: Option<SecretBytes>,
+    pub(crate) kdf_salt: Option<SecretBytes>,
+    pub(crate) profile: String,
+    pub(crate) profile_id: ProfileId,
+    pub(crate) schema_version: u32,
+}
+
+impl ProvisionResult {
+    pub(crate) fn new(
+        keys: &ProvisionKeys,
+        profile: String,
+        profile_id: ProfileId,
+        schema_version: u32,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            store_key_method: StoreKeyReference::parse_uri(&keys.store_key_ref)?.method(),
+            store_key_ref: keys.store_key_ref.clone(),
+            generated_key: keys.generated_key.clone(),
+            kdf_salt: keys.kdf_salt.clone(),
+            profile,
+            profile_id,
+            schema_version,
+        })
+    }
+
+    /// Accessor for the method used to protect the store key
+    pub fn store_key_method(&self) -> &StoreKeyMethod {
+        &self.store_key_method
+    }
+
+    /// Accessor for the reference to the store key, as recorded in the store
+    pub fn store_key_ref(&self) -> &str {
+        self.store_key_ref.as_str()
+    }
+
+    /// Accessor for the encoded raw store key, when it was generated during
+    /// provisioning because no pass key was provided
+    pub fn generated_key(&self) -> Option<&SecretBytes> {
+        self.generated_key.as_ref()
+    }
+
+    /// Accessor for the salt used to derive the store key from the pass key
+    pub fn kdf_salt(&self) -> Option<&SecretBytes> {
+        self.kdf_salt.as_ref()
+    }
+
+    /// Accessor for the name of the default profile
+    pub fn profile(&self) -> &str {
+        self.profile.as_str()
+    }
+
+    /// Accessor for the row identifier of the default profile
+    pub fn profile_id(&self) -> ProfileId {
+        self.profile_id
+    }
+
+    /// Accessor for the schema version of the store
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+}
+
+impl Debug for ProvisionResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProvisionResult")
+            .field("store_key_method", &self.store_key_method)
+            .field("store_key_ref", &self.store_key_ref)
+            .field("generated_key", &self.generated_key.as_ref().map(|_| ".."))
+            .field("kdf_salt", &self.kdf_salt.as_ref().map(|_| ".."))
+            .field("profile", &self.profile)
+            .field("profile_id", &self.profile_id)
+            .field("schema_version", &self.schema_version)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::block_on;
+    use crate::protect::{generate_raw_store_key, StoreKeyReference};
+
+    #[test]
+    fn create_batch_derived() {
+        let keys = block_on(ProvisionKeys::create_batch(
+            3,
+            StoreKeyMethod::parse_uri("kdf:argon2i:int").unwrap(),
+            "password".into(),
+        ))
+        .expect("Error creating keys");
+        assert_eq!(keys.len(), 3);
+        // each store key is derived using a distinct salt
+        assert_ne!(keys[0].store_key_ref(), keys[1].store_key_ref());
+        for key in keys.iter() {
+            let key_ref = StoreKeyReference::parse_uri(key.store_key_ref()).unwrap();
+            let store_key = key_ref.resolve("password".into()).unwrap();
+            let profile_key = store_key.unwrap_data(key.enc_profile_key.clone()).unwrap();
+            assert_eq!(profile_key, key.profile_key.to_bytes().unwrap());
+        }
+    }
+
+    #[test]
+    fn create_raw() {
+        let pass_key = generate_raw_store_key(None).unwrap();
+        let keys = block_on(ProvisionKeys::create(StoreKeyMethod::RawKey, pass_key))
+            .expect("Error creating keys");
+        assert_eq!(keys.store_key_ref(), "raw");
+        assert!(keys.generated_key.is_none());
+    }
+
+    #[test]
+    fn create_generated_raw() {
+        let keys = block_on(ProvisionKeys::create(
+            StoreKeyMethod::RawKey,
+            PassKey::empty(),
+        ))
+        .expect("Error creating keys");
+        let generated = keys.generated_key.clone().expect("Missing generated key");
+        let store_key = StoreKeyReference::RawKey
+            .resolve(generated.as_opt_str().into())
+            .unwrap();
+        let profile_key = store_key.unwrap_data(keys.enc_profile_key.clone()).unwrap();
+        assert_eq!(profile_key, keys.profile_key.to_bytes().unwrap());
+
+        let result = ProvisionResult::new(&keys, "default".to_string(), 1, 1).unwrap();
+        assert_eq!(result.store_key_method(), &StoreKeyMethod::RawKey);
+        let debug = format!("{:?}", result);
+        assert!(!debug.contains(generated.as_opt_str().unwrap()));
+    }
+
+    #[test]
+    fn create_derived_salt() {
+        let keys = block_on(ProvisionKeys::create(
+            StoreKeyMethod::parse_uri("kdf:argon2i:int").unwrap(),
+            "password".into(),
+        ))
+        .expect("Error creating keys");
+        let salt = keys.kdf_salt.as_ref().expect("Missing salt");
+        assert!(keys
+            .store_key_ref()
+            .ends_with(&format!("?salt={}", hex::encode(salt.as_ref()))));
+        assert!(keys.generated_key.is_none());
+    }
+}
@@ -74,6 +74,9 @@ impl StoreKey {
     pub fn unwrap_data(&self, ciphertext: Vec<u8>) -> Result<SecretBytes, Error> {
         match &self.0 {
             Some(key) => {
+                if ciphertext.len() < StoreKeyNonce::SIZE {
+                    return Err(err_msg!(Encryption, "Invalid wrapped data"));
+                }
                 let nonce = StoreKeyNonce::from_slice(&ciphertext[..StoreKeyNonce::SIZE]);
                 let mut buffer = SecretBytes::from(ciphertext);
                 buffer.buffer_remove(0..StoreKeyNonce::SIZE)?;
@@ -113,7 +116,9 @@ pub enum StoreKeyMethod {
 }
 
 impl StoreKeyMethod {
-    pub(crate) fn parse_uri(uri: &str) -> Result<Self, Error> {
+    /// Parse a store key method from its URI form, such as `kdf:argon2i:int`,
+    /// `raw` or `none`
+    pub fn parse_uri(uri: &str) -> Result<Self, Error> {
         let mut prefix_and_detail = uri.splitn(2, ':');
         let prefix = prefix_and_detail.next().unwrap_or_default();
         // let detail = prefix_and_detail.next().unwrap_or_default();
@@ -204,6 +209,15 @@ impl StoreKeyReference {
         }
     }
 
+    /// The method used to create the referenced store key
+    pub fn method(&self) -> StoreKeyMethod {
+        match self {
+            Self::DeriveKey(method, _detail) => StoreKeyMethod::DeriveKey(*method),
+            Self::RawKey => StoreKeyMethod::RawKey,
+            Self::Unprotected => StoreKeyMethod::Unprotected,
+        }
+    }
+
     pub fn into_uri(self) -> String {
         match self {
             // Self::ManagedKey(keyref) => keyref,
@@ -0,0 +1,235 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use crate::{
+    backend::Backend,
+    error::{Error, ErrorKind},
+    future::sleep,
+};
+
+/// The maximum number of records read by each query of a decryption audit
+pub const AUDIT_BATCH_SIZE: i64 = 100;
+
+/// Options for a decryption audit, as performed by `Store::audit_decryption`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecryptionAuditOptions {
+    /// The proportion of the records to check, greater than zero and at most one
+    pub sample_ratio: f64,
+    /// The seed for the selection of the sampled records. The same records are
+    /// selected for the same seed and ratio
+    pub seed: u64,
+    /// The maximum number of records to check per second, or `None` for no limit
+    pub max_rate: Option<u32>,
+    /// The categories to check, or `None` to check all categories
+    pub categories: Option<Vec<String>>,
+    /// Continue an audit after the given record ID, as reported by `DecryptionAudit::last_id`
+    pub resume_after: Option<i64>,
+}
+
+impl DecryptionAuditOptions {
+    /// Check whether a record is selected by the sample
+    pub(crate) fn sample(&self, row_id: i64) -> bool {
+        if self.sample_ratio >= 1.0 {
+            return true;
+        }
+        // splitmix64, so that consecutive record IDs are selected independently
+        let mut hash = self
+            .seed
+            .wrapping_add((row_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+        ((hash >> 11) as f64 / (1u64 << 53) as f64) < self.sample_ratio
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(self.sample_ratio > 0.0 && self.sample_ratio <= 1.0) {
+            return Err(err_msg!(
+                Input,
+                "Invalid sample ratio for a decryption audit: {}",
+                self.sample_ratio
+            ));
+        }
+        if self.max_rate == Some(0) {
+            return Err(err_msg!(Input, "Invalid rate for a decryption audit"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for DecryptionAuditOptions {
+    fn default() -> Self {
+        Self {
+            sample_ratio: 1.0,
+            seed: 0,
+            max_rate: None,
+            categories: None,
+            resume_after: None,
+        }
+    }
+}
+
+/// A record which could not be decrypted during a decryption audit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecryptionFailure {
+    /// The ID of the record
+    pub row_id: i64,
+    /// The kind of error produced
+    pub kind: ErrorKind,
+    /// The error message
+    pub message: String,
+}
+
+impl DecryptionFailure {
+    pub(crate) fn new(row_id: i64, err: Error) -> Self {
+        Self {
+            row_id,
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// The outcome of a decryption audit
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecryptionAudit {
+    /// The number of records read
+    pub scanned: i64,
+    /// The number of records selected by the sample and checked
+    pub checked: i64,
+    /// The records which could not be decrypted, in order of record ID
+    pub failures: Vec<DecryptionFailure>,
+    /// The ID of the last record read, from which the audit may be resumed
+    pub last_id: Option<i64>,
+    /// Whether every record of the profile has been read
+    pub complete: bool,
+}
+
+impl DecryptionAudit {
+    /// Check whether no failures were found
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Combine the outcome of an audit with that of a later audit resumed from it
+    pub fn merge(&mut self, other: DecryptionAudit) {
+        self.scanned += other.scanned;
+        self.checked += other.checked;
+        self.failures.extend(other.failures);
+        if other.last_id.is_some() {
+            self.last_id = other.last_id;
+        }
+        self.complete = other.complete;
+    }
+}
+
+/// A handle used to stop a decryption audit in progress.
+///
+/// The audit stops before its next query once cancelled, returning the records
+/// checked so far.
+#[derive(Clone, Debug, Default)]
+pub struct AuditCancel(Arc<AtomicBool>);
+
+impl AuditCancel {
+    /// Create a new handle
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the audit
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Check whether the audit has been stopped
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+pub(crate) async fn audit_decryption<B: Backend>(
+    backend: &B,
+    profile: Option<String>,
+    options: DecryptionAuditOptions,
+    cancel: Option<&AuditCancel>,
+) -> Result<DecryptionAudit, Error> {
+    options.validate()?;
+    // a slow rate reads fewer records at a time, so that the checks are spread evenly
+    let limit = options
+        .max_rate
+        .map_or(AUDIT_BATCH_SIZE, |rate| AUDIT_BATCH_SIZE.min(rate as i64));
+    let mut report = DecryptionAudit {
+        last_id: options.resume_after,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    loop {
+        if cancel.map_or(false, AuditCancel::is_cancelled) {
+            return Ok(report);
+        }
+        let batch = backend
+            .audit_decryption(
+                profile.clone(),
+                DecryptionAuditOptions {
+                    resume_after: report.last_id,
+                    ..options.clone()
+                },
+                limit,
+            )
+            .await?;
+        report.merge(batch);
+        if report.complete {
+            return Ok(report);
+        }
+        if let Some(rate) = options.max_rate {
+            let due = start + Duration::from_secs_f64(report.checked as f64 / rate as f64);
+            let delay = due.saturating_duration_since(Instant::now());
+            if delay > Duration::from_secs(0) {
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_deterministic() {
+        let options = DecryptionAuditOptions {
+            sample_ratio: 0.25,
+            seed: 7,
+            ..Default::default()
+        };
+        let selected = (1..=10_000).filter(|id| options.sample(*id)).count();
+        assert!(selected > 2_000 && selected < 3_000);
+        let again = (1..=10_000).filter(|id| options.sample(*id)).count();
+        assert_eq!(selected, again);
+
+        let other = DecryptionAuditOptions {
+            seed: 8,
+            ..options.clone()
+        };
+        assert!((1..=10_000).any(|id| options.sample(id) != other.sample(id)));
+    }
+
+    #[test]
+    fn invalid_options() {
+        for ratio in [0.0, -1.0, 1.5, f64::NAN].iter().copied() {
+            let options = DecryptionAuditOptions {
+                sample_ratio: ratio,
+                ..Default::default()
+            };
+            assert_eq!(options.validate().unwrap_err().kind(), ErrorKind::Input);
+        }
+        let options = DecryptionAuditOptions {
+            max_rate: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(options.validate().unwrap_err().kind(), ErrorKind::Input);
+    }
+}
@@ -0,0 +1,76 @@
+use super::entry::{EntryOperation, EntryTag};
+
+/// A record update applied as part of a batch by `Store::apply_batch`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchUpdate {
+    /// The operation to perform
+    pub operation: EntryOperation,
+    /// The record category
+    pub category: String,
+    /// The record name
+    pub name: String,
+    /// The record value, for an insert or replace
+    pub value: Option<Vec<u8>>,
+    /// The type of the record value, if any
+    pub value_type: Option<String>,
+    /// The record tags, for an insert or replace
+    pub tags: Option<Vec<EntryTag>>,
+    /// The expiry time of the record in milliseconds from now, if any
+    pub expiry_ms: Option<i64>,
+    /// The profile of the record, if not the profile of the batch
+    pub profile: Option<String>,
+}
+
+impl BatchUpdate {
+    /// Insert a new record
+    pub fn insert(category: &str, name: &str, value: &[u8], tags: Option<Vec<EntryTag>>) -> Self {
+        Self {
+            operation: EntryOperation::Insert,
+            category: category.to_string(),
+            name: name.to_string(),
+            value: Some(value.to_vec()),
+            value_type: None,
+            tags,
+            expiry_ms: None,
+            profile: None,
+        }
+    }
+
+    /// Replace the value and tags of an existing record
+    pub fn replace(category: &str, name: &str, value: &[u8], tags: Option<Vec<EntryTag>>) -> Self {
+        Self {
+            operation: EntryOperation::Replace,
+            ..Self::insert(category, name, value, tags)
+        }
+    }
+
+    /// Remove an existing record
+    pub fn remove(category: &str, name: &str) -> Self {
+        Self {
+            operation: EntryOperation::Remove,
+            category: category.to_string(),
+            name: name.to_string(),
+            value: None,
+            value_type: None,
+            tags: None,
+            expiry_ms: None,
+            profile: None,
+        }
+    }
+
+    /// Apply the update to the records of another profile
+    pub fn with_profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+}
+
+/// The outcome of `Store::apply_batch`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchResult {
+    /// The number of updates applied by the batch
+    pub updates: i64,
+    /// Whether the batch was previously applied with the same idempotency key,
+    /// in which case the updates were not performed again
+    pub replayed: bool,
+}
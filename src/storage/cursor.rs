@@ -0,0 +1,155 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::entry::{Entry, Scan};
+use crate::error::Error;
+
+const CURSOR_VERSION: u8 = 1;
+
+/// The position of a resumable record scan, as returned by `ResumableScan::cursor`.
+///
+/// A cursor may be persisted in its string form and passed to `Store::scan_resume`
+/// in another process, in order to continue the scan after the last record that
+/// was returned. It is only accepted for the same store, profile, category and
+/// tag filter as the original scan.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanCursor {
+    #[serde(rename = "v")]
+    version: u8,
+    #[serde(rename = "p")]
+    profile: String,
+    #[serde(rename = "i")]
+    last_id: i64,
+    #[serde(rename = "c", with = "serde_bytes")]
+    check: Vec<u8>,
+}
+
+impl ScanCursor {
+    pub(crate) fn new(profile: String, last_id: i64, check: Vec<u8>) -> Self {
+        Self {
+            version: CURSOR_VERSION,
+            profile,
+            last_id,
+            check,
+        }
+    }
+
+    /// Get the name of the profile being scanned
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub(crate) fn last_id(&self) -> i64 {
+        self.last_id
+    }
+
+    pub(crate) fn check(&self) -> &[u8] {
+        &self.check
+    }
+}
+
+impl Debug for ScanCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScanCursor")
+            .field("profile", &self.profile)
+            .field("last_id", &self.last_id)
+            .finish()
+    }
+}
+
+impl Display for ScanCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let enc = serde_cbor::to_vec(self).map_err(|_| fmt::Error)?;
+        f.write_str(&base64::encode_config(enc, base64::URL_SAFE_NO_PAD))
+    }
+}
+
+impl FromStr for ScanCursor {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Self, Error> {
+        let enc = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .map_err(err_map!(Input, "Invalid scan cursor"))?;
+        let cursor: Self =
+            serde_cbor::from_slice(&enc).map_err(err_map!(Input, "Invalid scan cursor"))?;
+        if cursor.version != CURSOR_VERSION {
+            return Err(err_msg!(
+                Unsupported,
+                "Unsupported scan cursor version: {}",
+                cursor.version
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+/// A record scan in order of insertion, which may be resumed from a cursor
+pub struct ResumableScan {
+    scan: Scan<'static, (i64, Entry)>,
+    profile: String,
+    check: Vec<u8>,
+    last_id: i64,
+}
+
+impl ResumableScan {
+    pub(crate) fn new(
+        scan: Scan<'static, (i64, Entry)>,
+        profile: String,
+        check: Vec<u8>,
+        last_id: i64,
+    ) -> Self {
+        Self {
+            scan,
+            profile,
+            check,
+            last_id,
+        }
+    }
+
+    /// Fetch the next set of result rows
+    pub async fn fetch_next(&mut self) -> Result<Option<Vec<Entry>>, Error> {
+        if let Some(rows) = self.scan.fetch_next().await? {
+            if let Some((id, _)) = rows.last() {
+                self.last_id = *id;
+            }
+            Ok(Some(rows.into_iter().map(|(_, entry)| entry).collect()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get a cursor positioned after the last record fetched
+    pub fn cursor(&self) -> ScanCursor {
+        ScanCursor::new(self.profile.clone(), self.last_id, self.check.clone())
+    }
+
+    #[cfg(feature = "unsafe_queries")]
+    pub(crate) fn into_inner(self) -> Scan<'static, (i64, Entry)> {
+        self.scan
+    }
+}
+
+impl Debug for ResumableScan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResumableScan")
+            .field("profile", &self.profile)
+            .field("last_id", &self.last_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = ScanCursor::new("profile".to_string(), 42, vec![1, 2, 3]);
+        let enc = cursor.to_string();
+        assert_eq!(enc.parse::<ScanCursor>().unwrap(), cursor);
+        assert!("not a cursor".parse::<ScanCursor>().is_err());
+        assert!(enc[..enc.len() / 2].parse::<ScanCursor>().is_err());
+    }
+}
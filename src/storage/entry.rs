@@ -3,6 +3,7 @@ use std::{
     fmt::{self, Debug, Formatter},
     pin::Pin,
     str::FromStr,
+    sync::Arc,
 };
 
 use futures_lite::stream::{Stream, StreamExt};
@@ -13,9 +14,24 @@ use serde::{
 };
 use zeroize::Zeroize;
 
-use super::wql;
+use super::{
+    stats::{ScanHandle, ScanTracker},
+    wql::{self, dialect::WqlDialect},
+};
 use crate::{crypto::buffer::SecretBytes, error::Error};
 
+// decode a category or name, retaining the original bytes when they are not
+// valid UTF-8
+fn decode_lossy(value: Vec<u8>) -> (String, Option<Vec<u8>>) {
+    match String::from_utf8(value) {
+        Ok(value) => (value, None),
+        Err(err) => {
+            let value = err.into_bytes();
+            (String::from_utf8_lossy(&value).into_owned(), Some(value))
+        }
+    }
+}
+
 pub(crate) fn sorted_tags(tags: &Vec<EntryTag>) -> Vec<&EntryTag> {
     if tags.len() > 0 {
         let mut tags = tags.iter().collect::<Vec<&EntryTag>>();
@@ -27,19 +43,52 @@ pub(crate) fn sorted_tags(tags: &Vec<EntryTag>) -> Vec<&EntryTag> {
 }
 
 /// A record in the store
+///
+/// Entries are compared by their category, name, value and tags only.
 #[derive(Clone, Debug, Eq)]
 pub struct Entry {
     /// The category of the entry record
     pub category: String,
 
     /// The name of the entry record, unique within its category
+    ///
+    /// Records may be stored with a category or name which is not valid UTF-8.
+    /// Such records are only returned by `Session::fetch_bytes`, where invalid
+    /// sequences are replaced in `category` and `name`, and the exact bytes are
+    /// available from `category_bytes` and `name_bytes`
     pub name: String,
 
     /// The value of the entry record
     pub value: SecretBytes,
 
     /// Tags associated with the entry record
+    ///
+    /// For entries fetched from the store, the tags are sorted according to the
+    /// ordering of `EntryTag`, so that identical stored records always produce
+    /// identical entries
     pub tags: Vec<EntryTag>,
+
+    /// An optional hint describing the encoding of the value, such as a content type
+    pub value_type: Option<String>,
+
+    /// The expiry time of the entry record in milliseconds since the Unix epoch, if any
+    pub expiry: Option<i64>,
+
+    /// The tags of the entry record which could not be decrypted, and which
+    /// are not included in `tags`. Tags are only dropped when the store is
+    /// opened with lenient tag decryption, otherwise the fetch fails.
+    ///
+    /// Each tag is reported by its name, or `None` when the name could not
+    /// be decrypted either
+    pub dropped_tags: Vec<Option<String>>,
+
+    /// The exact bytes of the category of a fetched record, when they are not
+    /// valid UTF-8 and `category` holds a lossy conversion
+    pub raw_category: Option<Vec<u8>>,
+
+    /// The exact bytes of the name of a fetched record, when they are not
+    /// valid UTF-8 and `name` holds a lossy conversion
+    pub raw_name: Option<Vec<u8>>,
 }
 
 impl Entry {
@@ -56,9 +105,66 @@ impl Entry {
             name: name.into(),
             value: value.into(),
             tags,
+            value_type: None,
+            expiry: None,
+            dropped_tags: Vec::new(),
+            raw_category: None,
+            raw_name: None,
+        }
+    }
+
+    /// Create a new `Entry` for a record identified by the bytes of its
+    /// category and name, which may not be valid UTF-8
+    pub(crate) fn from_bytes<V: Into<SecretBytes>>(
+        category: Vec<u8>,
+        name: Vec<u8>,
+        value: V,
+        tags: Vec<EntryTag>,
+    ) -> Self {
+        let (category, raw_category) = decode_lossy(category);
+        let (name, raw_name) = decode_lossy(name);
+        Self {
+            raw_category,
+            raw_name,
+            ..Self::new(category, name, value, tags)
         }
     }
 
+    /// Accessor for the exact bytes of the category of the record
+    pub fn category_bytes(&self) -> &[u8] {
+        self.raw_category
+            .as_deref()
+            .unwrap_or_else(|| self.category.as_bytes())
+    }
+
+    /// Accessor for the exact bytes of the name of the record
+    pub fn name_bytes(&self) -> &[u8] {
+        self.raw_name
+            .as_deref()
+            .unwrap_or_else(|| self.name.as_bytes())
+    }
+
+    /// Set the value type hint for the entry
+    #[inline]
+    pub fn with_value_type(mut self, value_type: Option<String>) -> Self {
+        self.value_type = value_type;
+        self
+    }
+
+    /// Set the expiry time of the entry, in milliseconds since the Unix epoch
+    #[inline]
+    pub fn with_expiry(mut self, expiry: Option<i64>) -> Self {
+        self.expiry = expiry;
+        self
+    }
+
+    /// Record the tags of the entry which could not be decrypted
+    #[inline]
+    pub(crate) fn with_dropped_tags(mut self, dropped_tags: Vec<Option<String>>) -> Self {
+        self.dropped_tags = dropped_tags;
+        self
+    }
+
     pub(crate) fn sorted_tags(&self) -> Vec<&EntryTag> {
         sorted_tags(&self.tags)
     }
@@ -66,8 +172,8 @@ impl Entry {
 
 impl PartialEq for Entry {
     fn eq(&self, rhs: &Self) -> bool {
-        self.category == rhs.category
-            && self.name == rhs.name
+        self.category_bytes() == rhs.category_bytes()
+            && self.name_bytes() == rhs.name_bytes()
             && self.value == rhs.value
             && self.sorted_tags() == rhs.sorted_tags()
     }
@@ -77,6 +183,8 @@ impl PartialEq for Entry {
 pub enum EntryKind {
     Kms = 1,
     Item = 2,
+    Alias = 3,
+    Temp = 4,
 }
 
 /// Supported operations for entries in the store
@@ -90,47 +198,244 @@ pub enum EntryOperation {
     Remove,
 }
 
+/// Selection of records according to their expiry time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExpiryFilter {
+    /// Exclude expired records
+    Exclude,
+    /// Include expired records along with unexpired records
+    Include,
+    /// Select only expired records
+    Only,
+}
+
+impl ExpiryFilter {
+    /// Parse an expiry filter from its configuration value
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "exclude" => Ok(Self::Exclude),
+            "include" => Ok(Self::Include),
+            "only" => Ok(Self::Only),
+            _ => Err(err_msg!(
+                Unsupported,
+                "Unsupported expiry filter: '{}'",
+                value
+            )),
+        }
+    }
+}
+
+impl Default for ExpiryFilter {
+    fn default() -> Self {
+        Self::Exclude
+    }
+}
+
+/// The result of a conditional fetch of a single record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConditionalFetch {
+    /// The record value matches the entity tag provided by the caller
+    NotModified,
+    /// The record value does not match the entity tag provided by the caller
+    Modified {
+        /// The current record
+        entry: Entry,
+        /// The entity tag of the current record value
+        etag: Vec<u8>,
+    },
+}
+
+/// The result of a fetch of a single record with a tag filter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilteredFetch {
+    /// The record matches the tag filter
+    Matched(Entry),
+    /// The record exists but does not match the tag filter
+    Filtered,
+}
+
+impl FilteredFetch {
+    /// Get the record if it matches the tag filter
+    pub fn into_entry(self) -> Option<Entry> {
+        match self {
+            Self::Matched(entry) => Some(entry),
+            Self::Filtered => None,
+        }
+    }
+}
+
+/// The selection of tags returned when fetching a record
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TagRetrieval {
+    /// Do not retrieve any tags
+    None,
+    /// Retrieve the plaintext tags and the number of tags, without reading
+    /// the values of the encrypted tags
+    PlaintextOnly,
+    /// Retrieve only the number of tags
+    CountOnly,
+    /// Retrieve and decrypt all tags
+    All,
+}
+
+impl Default for TagRetrieval {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+impl From<bool> for TagRetrieval {
+    fn from(retrieve_tags: bool) -> Self {
+        if retrieve_tags {
+            Self::All
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// A record fetched with a selection of its tags
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaggedEntry {
+    /// The record, holding the tags selected by the `TagRetrieval` mode
+    pub entry: Entry,
+    /// The total number of tags of the record, or `None` when no tags were retrieved
+    pub tag_count: Option<usize>,
+}
+
+/// The change made to the expiry time of a record when it is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExpiryUpdate {
+    /// Expire the record after the given number of milliseconds
+    Set(i64),
+    /// Remove any expiry time, so that the record does not expire
+    Clear,
+    /// Preserve the expiry time of the record being replaced. A new record
+    /// does not expire
+    Keep,
+}
+
+impl From<Option<i64>> for ExpiryUpdate {
+    /// A number of milliseconds sets the expiry time, while `None` clears it
+    fn from(expiry_ms: Option<i64>) -> Self {
+        match expiry_ms {
+            Some(ms) => Self::Set(ms),
+            None => Self::Clear,
+        }
+    }
+}
+
+/// A hint for the selection of indexes by the database when filtering records
+/// by their tags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QueryHint {
+    /// Select records using the indexes on tag names and values rather than the
+    /// index on record categories. This may be much faster for a selective tag
+    /// filter over a large category, and slower otherwise
+    PreferTagIndex,
+}
+
+impl QueryHint {
+    /// Parse an index hint from its configuration value
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "prefer_tag_index" => Ok(Self::PreferTagIndex),
+            _ => Err(err_msg!(Unsupported, "Unsupported query hint: '{}'", value)),
+        }
+    }
+}
+
 /// A tag on an entry record in the store
+///
+/// Tag names may not begin with `$` or `~`, which are reserved by the tag
+/// query syntax. Records with such tags are rejected when they are written.
 #[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Zeroize)]
+#[non_exhaustive]
 pub enum EntryTag {
     /// An entry tag to be stored encrypted
     Encrypted(String, String),
     /// An entry tag to be stored in plaintext (for ordered comparison)
     Plaintext(String, String),
+    /// An entry tag with an integer value, stored in plaintext
+    ///
+    /// Ordered comparisons in tag filters compare the values numerically
+    Int(String, IntTagValue),
+    /// An entry tag with a boolean value, stored in plaintext
+    Bool(String, bool),
 }
 
 impl EntryTag {
     /// Accessor for the tag name
     pub fn name(&self) -> &str {
         match self {
-            Self::Encrypted(name, _) | Self::Plaintext(name, _) => name,
+            Self::Encrypted(name, _)
+            | Self::Plaintext(name, _)
+            | Self::Int(name, _)
+            | Self::Bool(name, _) => name,
         }
     }
 
-    pub(crate) fn map_ref(&self, f: impl FnOnce(&str, &str) -> (String, String)) -> Self {
+    /// Create a copy of the tag with a new name
+    pub(crate) fn map_name(&self, f: impl FnOnce(&str) -> String) -> Self {
         match self {
-            Self::Encrypted(name, val) => {
-                let (name, val) = f(name.as_str(), val.as_str());
-                Self::Encrypted(name, val)
-            }
-            Self::Plaintext(name, val) => {
-                let (name, val) = f(name.as_str(), val.as_str());
-                Self::Plaintext(name, val)
-            }
+            Self::Encrypted(name, val) => Self::Encrypted(f(name), val.clone()),
+            Self::Plaintext(name, val) => Self::Plaintext(f(name), val.clone()),
+            Self::Int(name, val) => Self::Int(f(name), val.clone()),
+            Self::Bool(name, val) => Self::Bool(f(name), *val),
         }
     }
 
     /// Setter for the tag name
     pub(crate) fn update_name(&mut self, f: impl FnOnce(&mut String)) {
         match self {
-            Self::Encrypted(name, _) | Self::Plaintext(name, _) => f(name),
+            Self::Encrypted(name, _)
+            | Self::Plaintext(name, _)
+            | Self::Int(name, _)
+            | Self::Bool(name, _) => f(name),
         }
     }
 
-    /// Accessor for the tag value
+    /// Accessor for the tag value, with typed values in their text form
     pub fn value(&self) -> &str {
         match self {
             Self::Encrypted(_, val) | Self::Plaintext(_, val) => val,
+            Self::Int(_, val) => val.as_str(),
+            Self::Bool(_, true) => "true",
+            Self::Bool(_, false) => "false",
+        }
+    }
+
+    /// Accessor for the value of an integer tag
+    pub fn int_value(&self) -> Option<i64> {
+        match self {
+            Self::Int(_, val) => Some(val.get()),
+            _ => None,
+        }
+    }
+
+    /// Accessor for the value of a boolean tag
+    pub fn bool_value(&self) -> Option<bool> {
+        match self {
+            Self::Bool(_, val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Determine whether the tag is stored in plaintext.
+    ///
+    /// Plaintext tags support ordered comparison and `$like` in tag filters,
+    /// with the tag name prefixed by `~`
+    pub fn is_plaintext(&self) -> bool {
+        !matches!(self, Self::Encrypted(..))
+    }
+
+    /// Unwrap the tag name
+    pub(crate) fn into_name(self) -> String {
+        match self {
+            Self::Encrypted(name, _)
+            | Self::Plaintext(name, _)
+            | Self::Int(name, _)
+            | Self::Bool(name, _) => name,
         }
     }
 
@@ -138,10 +443,51 @@ impl EntryTag {
     pub(crate) fn into_value(self) -> String {
         match self {
             Self::Encrypted(_, value) | Self::Plaintext(_, value) => value,
+            Self::Int(_, value) => value.text,
+            Self::Bool(_, value) => value.to_string(),
         }
     }
 }
 
+/// The value of an integer entry tag, held along with its text form
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Zeroize)]
+pub struct IntTagValue {
+    value: i64,
+    text: String,
+}
+
+impl IntTagValue {
+    /// Create a new integer tag value
+    pub fn new(value: i64) -> Self {
+        Self {
+            value,
+            text: value.to_string(),
+        }
+    }
+
+    /// Accessor for the integer value
+    pub fn get(&self) -> i64 {
+        self.value
+    }
+
+    /// Accessor for the value in its text form
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<i64> for IntTagValue {
+    fn from(value: i64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Debug for IntTagValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.value, f)
+    }
+}
+
 impl Debug for EntryTag {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -155,10 +501,49 @@ impl Debug for EntryTag {
                 .field(&name)
                 .field(&value)
                 .finish(),
+            Self::Int(name, value) => f.debug_tuple("Int").field(&name).field(&value).finish(),
+            Self::Bool(name, value) => f.debug_tuple("Bool").field(&name).field(&value).finish(),
         }
     }
 }
 
+/// The type of a plaintext tag value, recorded alongside the stored value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TagValueType {
+    Text = 0,
+    Int = 1,
+    Bool = 2,
+}
+
+impl TagValueType {
+    pub fn from_i16(value: i16) -> Option<Self> {
+        match value {
+            0 => Some(Self::Text),
+            1 => Some(Self::Int),
+            2 => Some(Self::Bool),
+            _ => None,
+        }
+    }
+}
+
+// integer tag values are stored as fixed-width decimal strings of the value
+// with the sign bit flipped, so that the stored values sort numerically. The
+// value is reinterpreted as unsigned and its top bit inverted, mapping i64::MIN
+// to 0, -1 to 2^63 - 1, 0 to 2^63 and i64::MAX to u64::MAX. This mapping is
+// monotonic, and padding every value to the 20 digits of u64::MAX means that
+// comparing the stored strings byte-wise (as the ordered tag filters do)
+// matches the numeric order of the values, negative values included.
+pub(crate) fn encode_int_tag_value(value: i64) -> String {
+    format!("{:020}", (value as u64) ^ (1 << 63))
+}
+
+pub(crate) fn decode_int_tag_value(value: &str) -> Option<i64> {
+    if value.len() != 20 {
+        return None;
+    }
+    value.parse::<u64>().ok().map(|v| (v ^ (1 << 63)) as i64)
+}
+
 /// A wrapper type used for managing (de)serialization of tags
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct EntryTagSet<'e>(Cow<'e, [EntryTag]>);
@@ -208,23 +593,31 @@ impl<'de> Deserialize<'de> for EntryTagSet<'static> {
                         None => return Err(M::Error::custom("invalid tag name: empty string")),
                         _ => (key.to_owned(), true),
                     };
-                    match (values, enc) {
-                        (EntryTagValues::Single(value), true) => {
-                            v.push(EntryTag::Encrypted(tag, value))
-                        }
-                        (EntryTagValues::Single(value), false) => {
-                            v.push(EntryTag::Plaintext(tag, value))
-                        }
-                        (EntryTagValues::Multiple(values), true) => {
-                            for value in values {
-                                v.push(EntryTag::Encrypted(tag.clone(), value))
+                    let values = match values {
+                        EntryTagValues::Single(value) => vec![value],
+                        EntryTagValues::Multiple(values) => values,
+                    };
+                    for value in values {
+                        v.push(match (value, enc) {
+                            (EntryTagValue::Text(value), true) => {
+                                EntryTag::Encrypted(tag.clone(), value)
+                            }
+                            (EntryTagValue::Text(value), false) => {
+                                EntryTag::Plaintext(tag.clone(), value)
                             }
-                        }
-                        (EntryTagValues::Multiple(values), false) => {
-                            for value in values {
-                                v.push(EntryTag::Plaintext(tag.clone(), value))
+                            (EntryTagValue::Int(value), false) => {
+                                EntryTag::Int(tag.clone(), value.into())
                             }
-                        }
+                            (EntryTagValue::Bool(value), false) => {
+                                EntryTag::Bool(tag.clone(), value)
+                            }
+                            (_, true) => {
+                                return Err(M::Error::custom(format!(
+                                    "invalid value for tag '{}': typed values must be plaintext",
+                                    tag
+                                )))
+                            }
+                        });
                     }
                 }
 
@@ -236,9 +629,72 @@ impl<'de> Deserialize<'de> for EntryTagSet<'static> {
     }
 }
 
+enum EntryTagValue {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl<'de> Deserialize<'de> for EntryTagValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TagValueVisitor;
+
+        impl<'d> Visitor<'d> for TagValueVisitor {
+            type Value = EntryTagValue;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a string, integer or boolean")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValue::Text(value.to_owned()))
+            }
+
+            fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValue::Text(value))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValue::Int(value))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                if value > i64::MAX as u64 {
+                    return Err(E::custom("integer tag value out of range"));
+                }
+                Ok(EntryTagValue::Int(value as i64))
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValue::Bool(value))
+            }
+        }
+
+        deserializer.deserialize_any(TagValueVisitor)
+    }
+}
+
 enum EntryTagValues {
-    Single(String),
-    Multiple(Vec<String>),
+    Single(EntryTagValue),
+    Multiple(Vec<EntryTagValue>),
 }
 
 impl<'de> Deserialize<'de> for EntryTagValues {
@@ -252,21 +708,47 @@ impl<'de> Deserialize<'de> for EntryTagValues {
             type Value = EntryTagValues;
 
             fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-                formatter.write_str("a string or list of strings")
+                formatter.write_str("a tag value or list of tag values")
             }
 
             fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
             where
                 E: SerdeError,
             {
-                Ok(EntryTagValues::Single(value.to_owned()))
+                Ok(EntryTagValues::Single(EntryTagValue::Text(
+                    value.to_owned(),
+                )))
             }
 
             fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
             where
                 E: SerdeError,
             {
-                Ok(EntryTagValues::Single(value))
+                Ok(EntryTagValues::Single(EntryTagValue::Text(value)))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValues::Single(EntryTagValue::Int(value)))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                if value > i64::MAX as u64 {
+                    return Err(E::custom("integer tag value out of range"));
+                }
+                Ok(EntryTagValues::Single(EntryTagValue::Int(value as i64)))
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+            where
+                E: SerdeError,
+            {
+                Ok(EntryTagValues::Single(EntryTagValue::Bool(value)))
             }
 
             fn visit_seq<S>(self, mut access: S) -> Result<Self::Value, S::Error>
@@ -316,11 +798,38 @@ impl Serialize for EntryTagSet<'_> {
             }
         }
 
+        enum TagValue<'a> {
+            Text(&'a str),
+            Int(i64),
+            Bool(bool),
+        }
+
+        impl Serialize for TagValue<'_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                match self {
+                    Self::Text(value) => serializer.serialize_str(value),
+                    Self::Int(value) => serializer.serialize_i64(*value),
+                    Self::Bool(value) => serializer.serialize_bool(*value),
+                }
+            }
+        }
+
         let mut tags = BTreeMap::new();
         for tag in self.0.iter() {
             let (name, value) = match tag {
-                EntryTag::Encrypted(name, val) => (TagName(name.as_str(), true), val.as_str()),
-                EntryTag::Plaintext(name, val) => (TagName(name.as_str(), false), val.as_str()),
+                EntryTag::Encrypted(name, val) => {
+                    (TagName(name.as_str(), true), TagValue::Text(val.as_str()))
+                }
+                EntryTag::Plaintext(name, val) => {
+                    (TagName(name.as_str(), false), TagValue::Text(val.as_str()))
+                }
+                EntryTag::Int(name, val) => {
+                    (TagName(name.as_str(), false), TagValue::Int(val.get()))
+                }
+                EntryTag::Bool(name, val) => (TagName(name.as_str(), false), TagValue::Bool(*val)),
             };
             tags.entry(name).or_insert_with(|| vec![]).push(value);
         }
@@ -342,6 +851,7 @@ pub(crate) struct EncEntryTag {
     pub name: Vec<u8>,
     pub value: Vec<u8>,
     pub plaintext: bool,
+    pub value_type: TagValueType,
 }
 
 /// A WQL filter used to restrict record queries
@@ -353,6 +863,8 @@ pub struct TagFilter {
 
 impl TagFilter {
     /// Combine multiple tag filters using the `AND` operator
+    ///
+    /// An empty list of filters matches all records.
     #[inline]
     pub fn all_of(each: Vec<TagFilter>) -> Self {
         Self {
@@ -361,6 +873,8 @@ impl TagFilter {
     }
 
     /// Combine multiple tag filters using the `OR` operator
+    ///
+    /// An empty list of filters matches no records.
     #[inline]
     pub fn any_of(each: Vec<TagFilter>) -> Self {
         Self {
@@ -369,6 +883,9 @@ impl TagFilter {
     }
 
     /// Get the inverse of a tag filter
+    ///
+    /// This matches exactly the records not matched by the inner filter, so that
+    /// a negated comparison also matches records which do not have the tag.
     #[inline]
     pub fn not(filter: TagFilter) -> Self {
         Self {
@@ -448,6 +965,13 @@ impl TagFilter {
         }
     }
 
+    /// Parse a tag filter from JSON in the given dialect
+    pub fn from_json(query: &str, dialect: WqlDialect) -> Result<Self, Error> {
+        Ok(Self {
+            query: wql::dialect::parse_with_dialect(query, dialect)?,
+        })
+    }
+
     /// Convert the tag filter to JSON format
     pub fn to_string(&self) -> Result<String, Error> {
         serde_json::to_string(&self.query).map_err(err_map!("Error encoding tag filter"))
@@ -463,9 +987,26 @@ impl From<wql::Query> for TagFilter {
 impl FromStr for TagFilter {
     type Err = Error;
 
+    /// Parse a tag filter from JSON, detecting its dialect
     fn from_str(query: &str) -> Result<Self, Error> {
-        let query = serde_json::from_str(query).map_err(err_map!("Error parsing tag query"))?;
-        Ok(Self { query })
+        Self::from_json(query, WqlDialect::Auto)
+    }
+}
+
+/// The size of a scanned row, as counted towards the page byte limit of a scan
+pub(crate) trait ScanRowSize {
+    fn row_size(&self) -> usize;
+}
+
+impl ScanRowSize for Entry {
+    fn row_size(&self) -> usize {
+        self.value.len()
+    }
+}
+
+impl ScanRowSize for (i64, Entry) {
+    fn row_size(&self) -> usize {
+        self.1.value.len()
     }
 }
 
@@ -473,6 +1014,7 @@ impl FromStr for TagFilter {
 pub struct Scan<'s, T> {
     stream: Option<Pin<Box<dyn Stream<Item = Result<Vec<T>, Error>> + Send + 's>>>,
     page_size: usize,
+    handle: Option<Arc<ScanHandle>>,
 }
 
 impl<'s, T> Scan<'s, T> {
@@ -483,20 +1025,66 @@ impl<'s, T> Scan<'s, T> {
         Self {
             stream: Some(stream.boxed()),
             page_size,
+            handle: None,
+        }
+    }
+
+    /// Report the progress of the scan to a tracker until it is completed
+    /// or dropped, failing if the tracker is at its limit of active scans
+    pub(crate) fn tracked(mut self, tracker: &Arc<ScanTracker>) -> Result<Self, Error>
+    where
+        T: ScanRowSize + 's,
+    {
+        let handle = Arc::new(tracker.start()?);
+        // pages are recorded as they are produced, before any mapping of the rows
+        let record = handle.clone();
+        self.stream = self.stream.map(|stream| {
+            stream
+                .map(move |rows| {
+                    if let Ok(rows) = rows.as_ref() {
+                        record.record_page(rows.len(), rows.iter().map(T::row_size).sum());
+                    }
+                    rows
+                })
+                .boxed()
+        });
+        self.handle = Some(handle);
+        Ok(self)
+    }
+
+    /// Transform each of the rows produced by the scan
+    pub(crate) fn map_rows<U, F>(self, f: F) -> Scan<'s, U>
+    where
+        T: 's,
+        U: 's,
+        F: Fn(T) -> U + Send + 's,
+    {
+        Scan {
+            stream: self.stream.map(|stream| {
+                stream
+                    .map(move |rows| rows.map(|rows| rows.into_iter().map(&f).collect()))
+                    .boxed()
+            }),
+            page_size: self.page_size,
+            handle: self.handle,
         }
     }
 
     /// Fetch the next set of result rows
     pub async fn fetch_next(&mut self) -> Result<Option<Vec<T>>, Error> {
         if let Some(mut s) = self.stream.take() {
-            match s.try_next().await? {
-                Some(val) => {
-                    if val.len() == self.page_size {
-                        self.stream.replace(s);
-                    }
+            match s.try_next().await {
+                Ok(Some(val)) => {
+                    // a page may be cut short by the page byte limit, so the scan
+                    // only completes once the stream is exhausted
+                    self.stream.replace(s);
                     Ok(Some(val))
                 }
-                None => Ok(None),
+                result => {
+                    // the scan is complete or has failed
+                    self.handle.take();
+                    result
+                }
             }
         } else {
             Ok(None)
@@ -528,4 +1116,69 @@ mod tests {
         let tags2 = serde_json::from_str(&ser).unwrap();
         assert_eq!(tags, tags2);
     }
+
+    #[test]
+    fn serialize_typed_tags() {
+        let tags = EntryTagSet::from(vec![
+            EntryTag::Int("a".to_owned(), IntTagValue::new(-5)),
+            EntryTag::Int("a".to_owned(), IntTagValue::new(10)),
+            EntryTag::Bool("b".to_owned(), true),
+            EntryTag::Plaintext("c".to_owned(), "10".to_owned()),
+        ]);
+        let ser = serde_json::to_string(&tags).unwrap();
+        assert_eq!(ser, r#"{"~a":[-5,10],"~b":true,"~c":"10"}"#);
+        let tags2 = serde_json::from_str(&ser).unwrap();
+        assert_eq!(tags, tags2);
+
+        assert!(serde_json::from_str::<EntryTagSet<'static>>(r#"{"a":10}"#).is_err());
+    }
+
+    #[test]
+    fn typed_tag_values() {
+        let tag = EntryTag::Int("a".to_owned(), IntTagValue::new(-5));
+        assert_eq!(tag.value(), "-5");
+        assert_eq!(tag.int_value(), Some(-5));
+        assert_eq!(tag.bool_value(), None);
+        let tag = EntryTag::Bool("b".to_owned(), true);
+        assert_eq!(tag.value(), "true");
+        assert_eq!(tag.bool_value(), Some(true));
+        let tag = EntryTag::Plaintext("c".to_owned(), "10".to_owned());
+        assert_eq!(tag.value(), "10");
+        assert_eq!(tag.int_value(), None);
+    }
+
+    #[test]
+    fn int_tag_value_order() {
+        let values = [i64::MIN, -100, -1, 0, 1, 9, 10, 100, i64::MAX];
+        let encoded = values
+            .iter()
+            .map(|v| encode_int_tag_value(*v))
+            .collect::<Vec<_>>();
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+        for (value, enc) in values.iter().zip(encoded) {
+            assert_eq!(decode_int_tag_value(&enc), Some(*value));
+        }
+    }
+
+    #[test]
+    fn deserialize_tag_flags() {
+        let tags: EntryTagSet<'static> =
+            serde_json::from_str(r#"{"a":"aval","~b":["bval","bval-2"],"~~c":"cval"}"#).unwrap();
+        let flags = tags
+            .into_vec()
+            .into_iter()
+            .map(|tag| (tag.name().to_string(), tag.is_plaintext()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flags,
+            vec![
+                ("a".to_string(), false),
+                ("b".to_string(), true),
+                ("b".to_string(), true),
+                ("~c".to_string(), true),
+            ]
+        );
+    }
 }
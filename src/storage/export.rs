@@ -0,0 +1,384 @@
+use std::io::BufRead;
+
+use chrono::Utc;
+use futures_lite::io::{AsyncWrite, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::entry::{Entry, EntryOperation, EntryTagSet, TagFilter};
+use super::store::Store;
+use crate::{
+    backend::Backend,
+    error::{Error, ErrorKind},
+};
+
+/// The number of records written in each transaction of a JSON Lines import
+pub const JSONL_IMPORT_BATCH_SIZE: usize = 100;
+
+const EXPORT_HEADER_VERSION: u32 = 1;
+
+/// A category included in a partial export by `Store::export_jsonl_partial`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CategorySpec {
+    /// The record category
+    pub category: String,
+    /// Restrict the exported records to those matching a tag filter
+    pub tag_filter: Option<TagFilter>,
+    /// Omit the record values, exporting only the names, tags and expiry times
+    pub skip_values: bool,
+}
+
+impl CategorySpec {
+    /// Export all of the records in a category
+    pub fn new(category: impl Into<String>) -> Self {
+        Self {
+            category: category.into(),
+            tag_filter: None,
+            skip_values: false,
+        }
+    }
+
+    /// Export only the records matching a tag filter
+    pub fn with_tag_filter(mut self, tag_filter: TagFilter) -> Self {
+        self.tag_filter = Some(tag_filter);
+        self
+    }
+
+    /// Omit the record values from the export
+    pub fn without_values(mut self) -> Self {
+        self.skip_values = true;
+        self
+    }
+}
+
+/// The handling of imported records which already exist in the profile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportConflict {
+    /// Keep the existing record and report the imported record as skipped
+    Skip,
+    /// Replace the existing record with the imported record
+    Replace,
+    /// Stop the import with a `Duplicate` error. Records written in earlier
+    /// transactions are retained
+    Fail,
+}
+
+/// A line of a JSON Lines export which was not imported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedLine {
+    /// The line number within the export, starting from 1
+    pub line: u64,
+    /// The record category
+    pub category: String,
+    /// The record name
+    pub name: String,
+    /// The reason the record was skipped
+    pub reason: String,
+}
+
+/// The outcome of a JSON Lines import
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JsonlImportReport {
+    /// The number of records inserted into the profile
+    pub imported: u64,
+    /// The number of existing records replaced
+    pub replaced: u64,
+    /// The records which were not imported
+    pub skipped: Vec<SkippedLine>,
+    /// The filters applied by a partial export, as recorded in its header
+    pub categories: Option<Vec<ExportedCategory>>,
+}
+
+/// A category included in a partial export, as recorded in its header
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedCategory {
+    /// The record category
+    pub category: String,
+    /// The tag filter applied to the records, in WQL format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_filter: Option<String>,
+    /// Whether the record values were included
+    pub values: bool,
+}
+
+// the first line of a partial export, recording the filters which were applied
+#[derive(Serialize, Deserialize)]
+struct ExportHeader {
+    version: u32,
+    categories: Vec<ExportedCategory>,
+}
+
+// a single line of a JSON Lines export
+#[derive(Serialize)]
+struct ExportRecord<'a> {
+    category: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_type: Option<&'a str>,
+    tags: EntryTagSet<'a>,
+    expiry: Option<i64>,
+}
+
+// a single line of a JSON Lines export, as read by an import
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImportLine {
+    Header {
+        header: ExportHeader,
+    },
+    Record {
+        category: String,
+        name: String,
+        #[serde(default)]
+        value: Option<String>,
+        #[serde(default)]
+        value_type: Option<String>,
+        tags: EntryTagSet<'static>,
+        #[serde(default)]
+        expiry: Option<i64>,
+    },
+}
+
+pub(crate) async fn export_jsonl<B: Backend, W: AsyncWrite + Unpin>(
+    store: &Store<B>,
+    profile: Option<String>,
+    writer: W,
+    include_categories: Option<Vec<&str>>,
+    allow_plaintext: bool,
+    on_progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    check_plaintext(allow_plaintext)?;
+    let categories = match include_categories {
+        Some(categories) => categories.into_iter().map(str::to_string).collect(),
+        None => store.list_categories(profile.clone()).await?,
+    };
+    let specs = categories.into_iter().map(CategorySpec::new).collect();
+    write_categories(store, profile, writer, specs, on_progress).await
+}
+
+pub(crate) async fn export_jsonl_partial<B: Backend, W: AsyncWrite + Unpin>(
+    store: &Store<B>,
+    profile: Option<String>,
+    mut writer: W,
+    categories: Vec<CategorySpec>,
+    allow_plaintext: bool,
+    on_progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    check_plaintext(allow_plaintext)?;
+    let header = ExportHeader {
+        version: EXPORT_HEADER_VERSION,
+        categories: categories
+            .iter()
+            .map(|spec| {
+                Ok(ExportedCategory {
+                    category: spec.category.clone(),
+                    tag_filter: spec
+                        .tag_filter
+                        .as_ref()
+                        .map(TagFilter::to_string)
+                        .transpose()?,
+                    values: !spec.skip_values,
+                })
+            })
+            .collect::<Result<_, Error>>()?,
+    };
+    let line = serde_json::to_vec(&serde_json::json!({ "header": header }))
+        .map_err(err_map!(Unexpected, "Error encoding export"))?;
+    write_line(&mut writer, &line).await?;
+    write_categories(store, profile, writer, categories, on_progress).await
+}
+
+pub(crate) async fn import_jsonl<B: Backend, R: BufRead>(
+    store: &Store<B>,
+    profile: Option<String>,
+    reader: R,
+    conflict: ImportConflict,
+) -> Result<JsonlImportReport, Error> {
+    let mut report = JsonlImportReport::default();
+    let mut lines = reader.lines().zip(1u64..).peekable();
+    while lines.peek().is_some() {
+        let mut txn = store.transaction(profile.clone()).await?;
+        let mut written = 0;
+        while written < JSONL_IMPORT_BATCH_SIZE {
+            let (line, line_no) = match lines.next() {
+                Some((line, line_no)) => (
+                    Zeroizing::new(line.map_err(err_map!(Input, "Error reading export"))?),
+                    line_no,
+                ),
+                None => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (category, name, value, value_type, tags, expiry) =
+                match serde_json::from_str(&line) {
+                    Ok(ImportLine::Header { header }) if line_no == 1 => {
+                        if header.version != EXPORT_HEADER_VERSION {
+                            return Err(err_msg!(
+                                Unsupported,
+                                "Unsupported export header version: {}",
+                                header.version
+                            ));
+                        }
+                        report.categories = Some(header.categories);
+                        continue;
+                    }
+                    Ok(ImportLine::Record {
+                        category,
+                        name,
+                        value,
+                        value_type,
+                        tags,
+                        expiry,
+                    }) => (category, name, value, value_type, tags, expiry),
+                    Ok(ImportLine::Header { .. }) | Err(_) => {
+                        return Err(err_msg!(Input, "Invalid export record at line {}", line_no))
+                    }
+                };
+            let skip = |reason: &str| SkippedLine {
+                line: line_no,
+                category: category.clone(),
+                name: name.clone(),
+                reason: reason.to_string(),
+            };
+            let value = match value.map(Zeroizing::new) {
+                Some(value) => {
+                    Zeroizing::new(base64::decode(value.as_bytes()).map_err(err_map!(
+                        Input,
+                        "Invalid record value at line {}",
+                        line_no
+                    ))?)
+                }
+                None => {
+                    report
+                        .skipped
+                        .push(skip("The record value was not exported"));
+                    continue;
+                }
+            };
+            // check for an existing record first, as a failed insert aborts
+            // the transaction for some backends
+            let operation = match txn.fetch(&category, &name, false).await? {
+                None => EntryOperation::Insert,
+                Some(_) => match conflict {
+                    ImportConflict::Skip => {
+                        report
+                            .skipped
+                            .push(skip("A record with the same name already exists"));
+                        continue;
+                    }
+                    ImportConflict::Replace => EntryOperation::Replace,
+                    ImportConflict::Fail => {
+                        txn.rollback().await?;
+                        return Err(err_msg!(
+                            Duplicate,
+                            "A record with the same name already exists at line {}",
+                            line_no
+                        ));
+                    }
+                },
+            };
+            let tags = tags.into_vec();
+            let expiry_ms = expiry.map(|expiry| expiry - Utc::now().timestamp_millis());
+            match txn
+                .update_typed(
+                    operation,
+                    &category,
+                    &name,
+                    Some(value.as_slice()),
+                    value_type.as_deref(),
+                    Some(tags.as_slice()),
+                    expiry_ms,
+                )
+                .await
+            {
+                Ok(()) if operation == EntryOperation::Replace => report.replaced += 1,
+                Ok(()) => report.imported += 1,
+                Err(err) if err.kind() == ErrorKind::Input => {
+                    report.skipped.push(skip(&err.to_string()));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+            written += 1;
+        }
+        txn.commit().await?;
+    }
+    Ok(report)
+}
+
+fn check_plaintext(allow_plaintext: bool) -> Result<(), Error> {
+    if allow_plaintext {
+        Ok(())
+    } else {
+        Err(err_msg!(
+            Input,
+            "A plaintext export must be explicitly allowed"
+        ))
+    }
+}
+
+async fn write_categories<B: Backend, W: AsyncWrite + Unpin>(
+    store: &Store<B>,
+    profile: Option<String>,
+    mut writer: W,
+    categories: Vec<CategorySpec>,
+    mut on_progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    let mut count = 0;
+    for spec in categories {
+        let mut scan = store
+            .scan(profile.clone(), spec.category, spec.tag_filter, None, None)
+            .await?;
+        while let Some(rows) = scan.fetch_next().await? {
+            for entry in rows {
+                write_entry(&mut writer, &entry, spec.skip_values).await?;
+                count += 1;
+            }
+            on_progress(count);
+        }
+    }
+    writer
+        .flush()
+        .await
+        .map_err(err_map!(Backend, "Error writing export"))?;
+    Ok(count)
+}
+
+async fn write_entry<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    entry: &Entry,
+    skip_value: bool,
+) -> Result<(), Error> {
+    let value = if skip_value {
+        None
+    } else {
+        Some(Zeroizing::new(base64::encode(&entry.value)))
+    };
+    let record = ExportRecord {
+        category: &entry.category,
+        name: &entry.name,
+        value: value.as_deref().map(String::as_str),
+        value_type: entry.value_type.as_deref(),
+        tags: EntryTagSet::from(entry.tags.as_slice()),
+        expiry: entry.expiry,
+    };
+    // the serialized record contains the plaintext value, and is cleared once written
+    let line = Zeroizing::new(
+        serde_json::to_vec(&record).map_err(err_map!(Unexpected, "Error encoding export"))?,
+    );
+    write_line(writer, &line).await
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &[u8]) -> Result<(), Error> {
+    writer
+        .write_all(line)
+        .await
+        .map_err(err_map!(Backend, "Error writing export"))?;
+    writer
+        .write_all(b"\n")
+        .await
+        .map_err(err_map!(Backend, "Error writing export"))
+}
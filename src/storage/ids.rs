@@ -0,0 +1,35 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::protect::ProfileId;
+
+/// A source of the IDs assigned to new profiles
+///
+/// By default the database assigns each profile the next available ID. A
+/// deterministic source allows the databases provisioned by tests to be
+/// compared, or the IDs of an existing store to be reproduced.
+pub trait IdSource: Debug + Send + Sync {
+    /// Select the ID of a new profile, or `None` to let the database assign it
+    fn next_profile_id(&self) -> Option<ProfileId>;
+}
+
+/// An `IdSource` assigning profile IDs from an increasing sequence
+#[derive(Debug)]
+pub struct SequentialIds {
+    next: AtomicI64,
+}
+
+impl SequentialIds {
+    /// Create a sequence starting from `start`
+    pub fn new(start: ProfileId) -> Self {
+        Self {
+            next: AtomicI64::new(start),
+        }
+    }
+}
+
+impl IdSource for SequentialIds {
+    fn next_profile_id(&self) -> Option<ProfileId> {
+        Some(self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
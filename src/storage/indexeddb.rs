@@ -0,0 +1,438 @@
+use std::fmt::{self, Debug, Formatter};
+
+use indexed_db_futures::prelude::*;
+use js_sys::Uint8Array;
+use wasm_bindgen::JsValue;
+use web_sys::{DomException, IdbKeyRange, IdbTransactionMode};
+
+use super::{
+    entry::{EncEntryTag, Entry, EntryOperation, EntryTag, TagFilter, TagValueType},
+    kv_store::KvStore,
+    wql::{matcher::match_tag_query, tags::tag_query},
+};
+use crate::{
+    error::Error,
+    future::LocalBoxFuture,
+    protect::{EntryEncryptor, KeyCache, PassKey, ProfileKey, StoreKeyMethod, StoreKeyReference},
+};
+
+// the version of the IndexedDB database schema
+const DB_VERSION: u32 = 1;
+const CONFIG_STORE: &'static str = "config";
+const ITEMS_STORE: &'static str = "items";
+const CONFIG_KEY: &'static str = "key";
+const CONFIG_PROFILE_KEY: &'static str = "profile_key";
+// the ID reported for the single profile of the store when its key is loaded
+const PROFILE_ID: i64 = 1;
+
+/// A store of records in a browser IndexedDB database, for use by wallets
+/// compiled to WebAssembly.
+///
+/// The store holds the records of a single profile, which are encrypted with
+/// the profile key as in the other stores. Each record is kept under the
+/// encrypted form of its category and name. Tag filters are evaluated against
+/// the decrypted tags of the records in a category, so that scans and counts
+/// read the whole category.
+///
+/// The store is bound to the browser context which opened it, so fetching a
+/// record for update does not take a lock. Each update is applied atomically
+/// in its own IndexedDB transaction, which fails if the record has been
+/// inserted or removed in the meantime.
+pub struct IndexedDbStore {
+    db: IdbDatabase,
+    name: String,
+    key: ProfileKey,
+}
+
+impl IndexedDbStore {
+    /// Open the IndexedDB database with the given name, creating it if it does
+    /// not exist. The method for wrapping the profile key only applies when the
+    /// store is created, after which the pass key is checked against the stored
+    /// key reference.
+    pub async fn open(
+        name: &str,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> Result<Self, Error> {
+        let mut request = IdbDatabase::open_u32(name, DB_VERSION).map_err(idb_error)?;
+        request.set_on_upgrade_needed(Some(|evt: &IdbVersionChangeEvent| -> Result<(), JsValue> {
+            for store in &[CONFIG_STORE, ITEMS_STORE] {
+                if !evt.db().object_store_names().any(|name| name == *store) {
+                    evt.db().create_object_store(*store)?;
+                }
+            }
+            Ok(())
+        }));
+        let db = request.await.map_err(idb_error)?;
+        let key = match read_keys(&db).await? {
+            Some((key_ref, enc_key)) => unwrap_key(&key_ref, enc_key, pass_key).await?,
+            None => {
+                let (store_key, key_ref) = method.resolve(pass_key.as_ref())?;
+                let profile_key = ProfileKey::new()?;
+                let enc_key = store_key.wrap_data(profile_key.to_bytes()?)?;
+                let key_ref = key_ref.into_uri();
+                if init_keys(&db, &key_ref, &enc_key).await? {
+                    profile_key
+                } else {
+                    // the store was created concurrently by another context
+                    let (key_ref, enc_key) = read_keys(&db)
+                        .await?
+                        .ok_or_else(|| err_msg!(Unexpected, "Store key not found"))?;
+                    unwrap_key(&key_ref, enc_key, pass_key).await?
+                }
+            }
+        };
+        Ok(Self {
+            db,
+            name: name.to_string(),
+            key,
+        })
+    }
+
+    /// Accessor for the name of the IndexedDB database
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn item_key(&self, category: &str, name: &str) -> Result<JsValue, Error> {
+        let enc_category = self
+            .key
+            .encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?;
+        let enc_name = self
+            .key
+            .encrypt_entry_name(ProfileKey::prepare_input(name.as_bytes()))?;
+        Ok(JsValue::from_str(&format!(
+            "{}:{}",
+            hex::encode(enc_category),
+            hex::encode(enc_name)
+        )))
+    }
+
+    // the range of keys of the records in a category
+    fn category_range(&self, category: &str) -> Result<IdbKeyRange, Error> {
+        let enc_category = hex::encode(
+            self.key
+                .encrypt_entry_category(ProfileKey::prepare_input(category.as_bytes()))?,
+        );
+        IdbKeyRange::bound(
+            &JsValue::from_str(&format!("{}:", enc_category)),
+            &JsValue::from_str(&format!("{};", enc_category)),
+        )
+        .map_err(js_error)
+    }
+
+    fn encrypt_record(
+        &self,
+        category: &str,
+        name: &str,
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+    ) -> Result<JsValue, Error> {
+        let record = StoredRecord {
+            name: self
+                .key
+                .encrypt_entry_name(ProfileKey::prepare_input(name.as_bytes()))?,
+            value: self.key.encrypt_entry_value(
+                category.as_bytes(),
+                name.as_bytes(),
+                ProfileKey::prepare_input(value),
+                false,
+            )?,
+            tags: self
+                .key
+                .encrypt_entry_tags(tags.map(<[EntryTag]>::to_vec).unwrap_or_default())?
+                .into_iter()
+                .map(StoredTag::from)
+                .collect(),
+        };
+        let record = serde_cbor::to_vec(&record)
+            .map_err(err_map!(Unexpected, "Error encoding stored record"))?;
+        Ok(Uint8Array::from(record.as_slice()).into())
+    }
+
+    fn decrypt_tags(&self, record: &mut StoredRecord) -> Result<Vec<EntryTag>, Error> {
+        let enc_tags = record
+            .tags
+            .drain(..)
+            .map(StoredTag::into_enc_tag)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut tags = self.key.decrypt_entry_tags(enc_tags)?;
+        tags.sort();
+        Ok(tags)
+    }
+
+    // fetch the records of a category along with their decrypted tags,
+    // filtered by an optional tag filter
+    async fn fetch_category(
+        &self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<Vec<(StoredRecord, Vec<EntryTag>)>, Error> {
+        let tag_query = tag_filter.map(|f| tag_query(f.query)).transpose()?;
+        let range = self.category_range(category)?;
+        let tx = self.db.transaction_on_one(ITEMS_STORE).map_err(idb_error)?;
+        let store = tx.object_store(ITEMS_STORE).map_err(idb_error)?;
+        let values = store
+            .get_all_with_key(&range)
+            .map_err(idb_error)?
+            .await
+            .map_err(idb_error)?;
+        let mut records = vec![];
+        for value in values.iter() {
+            let mut record = StoredRecord::decode(value)?;
+            let tags = self.decrypt_tags(&mut record)?;
+            if tag_query
+                .as_ref()
+                .map(|query| match_tag_query(query, &tags))
+                .unwrap_or(true)
+            {
+                records.push((record, tags));
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl Debug for IndexedDbStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexedDbStore")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl KvStore for IndexedDbStore {
+    fn fetch<'a>(
+        &'a self,
+        category: &'a str,
+        name: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<Entry>, Error>> {
+        Box::pin(async move {
+            let item_key = self.item_key(category, name)?;
+            let tx = self.db.transaction_on_one(ITEMS_STORE).map_err(idb_error)?;
+            let store = tx.object_store(ITEMS_STORE).map_err(idb_error)?;
+            let value = store
+                .get(&item_key)
+                .map_err(idb_error)?
+                .await
+                .map_err(idb_error)?;
+            if let Some(value) = value {
+                let mut record = StoredRecord::decode(value)?;
+                let tags = self.decrypt_tags(&mut record)?;
+                let value = self.key.decrypt_entry_value(
+                    category.as_bytes(),
+                    name.as_bytes(),
+                    record.value,
+                )?;
+                Ok(Some(Entry::new(category, name, value, tags)))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    fn update<'a>(
+        &'a self,
+        operation: EntryOperation,
+        category: &'a str,
+        name: &'a str,
+        value: Option<&'a [u8]>,
+        tags: Option<&'a [EntryTag]>,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let item_key = self.item_key(category, name)?;
+            // records are encrypted before the transaction is started, as it is
+            // committed once no requests are pending
+            let record = match operation {
+                EntryOperation::Insert | EntryOperation::Replace => {
+                    let value = value.ok_or_else(|| err_msg!(Input, "Missing entry value"))?;
+                    Some(self.encrypt_record(category, name, value, tags)?)
+                }
+                EntryOperation::Remove => None,
+            };
+            let tx = self
+                .db
+                .transaction_on_one_with_mode(ITEMS_STORE, IdbTransactionMode::Readwrite)
+                .map_err(idb_error)?;
+            let store = tx.object_store(ITEMS_STORE).map_err(idb_error)?;
+            let exists = store
+                .get(&item_key)
+                .map_err(idb_error)?
+                .await
+                .map_err(idb_error)?
+                .is_some();
+            match (operation, record) {
+                (EntryOperation::Insert, Some(record)) => {
+                    if exists {
+                        return Err(err_msg!(Duplicate, "Duplicate entry"));
+                    }
+                    store.add_key_val(&item_key, &record).map_err(idb_error)?;
+                }
+                (EntryOperation::Replace, Some(record)) => {
+                    if !exists {
+                        return Err(err_msg!(NotFound, "Entry not found"));
+                    }
+                    store.put_key_val(&item_key, &record).map_err(idb_error)?;
+                }
+                _ => {
+                    if !exists {
+                        return Err(err_msg!(NotFound, "Entry not found"));
+                    }
+                    store.delete(&item_key).map_err(idb_error)?;
+                }
+            }
+            tx.await.into_result().map_err(idb_error)?;
+            Ok(())
+        })
+    }
+
+    fn scan<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> LocalBoxFuture<'a, Result<Vec<Entry>, Error>> {
+        Box::pin(async move {
+            let records = self.fetch_category(category, tag_filter).await?;
+            let offset = offset.unwrap_or(0).max(0) as usize;
+            let limit = limit.map(|l| l.max(0) as usize).unwrap_or(usize::MAX);
+            let mut entries = vec![];
+            for (record, tags) in records.into_iter().skip(offset).take(limit) {
+                let name = self.key.decrypt_entry_name(record.name)?;
+                let value = self.key.decrypt_entry_value(
+                    category.as_bytes(),
+                    name.as_bytes(),
+                    record.value,
+                )?;
+                entries.push(Entry::new(category, name, value, tags));
+            }
+            Ok(entries)
+        })
+    }
+
+    fn count<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+    ) -> LocalBoxFuture<'a, Result<i64, Error>> {
+        Box::pin(async move { Ok(self.fetch_category(category, tag_filter).await?.len() as i64) })
+    }
+}
+
+/// The stored form of a record: the encrypted name, value and tags
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    #[serde(with = "serde_bytes")]
+    name: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    value: Vec<u8>,
+    tags: Vec<StoredTag>,
+}
+
+impl StoredRecord {
+    fn decode(value: JsValue) -> Result<Self, Error> {
+        serde_cbor::from_slice(&Uint8Array::new(&value).to_vec())
+            .map_err(err_map!(Unexpected, "Error decoding stored record"))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTag {
+    #[serde(with = "serde_bytes")]
+    name: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    value: Vec<u8>,
+    plaintext: bool,
+    value_type: i16,
+}
+
+impl From<EncEntryTag> for StoredTag {
+    fn from(tag: EncEntryTag) -> Self {
+        Self {
+            name: tag.name,
+            value: tag.value,
+            plaintext: tag.plaintext,
+            value_type: tag.value_type as i16,
+        }
+    }
+}
+
+impl StoredTag {
+    fn into_enc_tag(self) -> Result<EncEntryTag, Error> {
+        Ok(EncEntryTag {
+            name: self.name,
+            value: self.value,
+            plaintext: self.plaintext,
+            value_type: TagValueType::from_i16(self.value_type)
+                .ok_or_else(|| err_msg!(Unexpected, "Invalid stored tag value type"))?,
+        })
+    }
+}
+
+// read the store key reference and the wrapped profile key, if the store has
+// been created
+async fn read_keys(db: &IdbDatabase) -> Result<Option<(String, Vec<u8>)>, Error> {
+    let tx = db.transaction_on_one(CONFIG_STORE).map_err(idb_error)?;
+    let store = tx.object_store(CONFIG_STORE).map_err(idb_error)?;
+    let key_ref = store
+        .get_owned(CONFIG_KEY)
+        .map_err(idb_error)?
+        .await
+        .map_err(idb_error)?;
+    let enc_key = store
+        .get_owned(CONFIG_PROFILE_KEY)
+        .map_err(idb_error)?
+        .await
+        .map_err(idb_error)?;
+    match (key_ref.and_then(|r| r.as_string()), enc_key) {
+        (Some(key_ref), Some(enc_key)) => Ok(Some((key_ref, Uint8Array::new(&enc_key).to_vec()))),
+        (None, None) => Ok(None),
+        _ => Err(err_msg!(Unexpected, "Incomplete store configuration")),
+    }
+}
+
+// record the store key reference and the wrapped profile key of a new store,
+// returning false if the store has been created in the meantime
+async fn init_keys(db: &IdbDatabase, key_ref: &str, enc_key: &[u8]) -> Result<bool, Error> {
+    let tx = db
+        .transaction_on_one_with_mode(CONFIG_STORE, IdbTransactionMode::Readwrite)
+        .map_err(idb_error)?;
+    let store = tx.object_store(CONFIG_STORE).map_err(idb_error)?;
+    if store
+        .get_owned(CONFIG_KEY)
+        .map_err(idb_error)?
+        .await
+        .map_err(idb_error)?
+        .is_some()
+    {
+        return Ok(false);
+    }
+    store
+        .add_key_val_owned(CONFIG_KEY, JsValue::from_str(key_ref))
+        .map_err(idb_error)?;
+    store
+        .add_key_val_owned(CONFIG_PROFILE_KEY, Uint8Array::from(enc_key))
+        .map_err(idb_error)?;
+    tx.await.into_result().map_err(idb_error)?;
+    Ok(true)
+}
+
+async fn unwrap_key(
+    key_ref: &str,
+    enc_key: Vec<u8>,
+    pass_key: PassKey<'_>,
+) -> Result<ProfileKey, Error> {
+    let store_key = StoreKeyReference::parse_uri(key_ref)?.resolve(pass_key)?;
+    KeyCache::new(store_key)
+        .load_key(PROFILE_ID, Some(enc_key))
+        .await
+}
+
+fn idb_error(err: DomException) -> Error {
+    err_msg!(Backend, "IndexedDB error: {}", err.message())
+}
+
+fn js_error(err: JsValue) -> Error {
+    err_msg!(Backend, "IndexedDB error: {:?}", err)
+}
@@ -0,0 +1,946 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use super::entry::{Entry, EntryOperation, EntryTag};
+use super::store::Store;
+use crate::{
+    backend::Backend,
+    crypto::{
+        alg::chacha20::{Chacha20Key, C20P},
+        buffer::{ArrayKey, SecretBytes},
+        encrypt::{KeyAeadInPlace, KeyAeadMeta},
+        generic_array::typenum::Unsigned,
+        kdf::{
+            argon2::{Argon2, Params, PARAMS_INTERACTIVE, PARAMS_MODERATE},
+            KeyDerivation,
+        },
+        repr::{KeyMeta, KeySecretBytes},
+    },
+    error::{Error, ErrorKind},
+    future::unblock,
+};
+
+/// The number of records written in each transaction during an indy wallet import
+pub const INDY_IMPORT_BATCH_SIZE: usize = 100;
+
+type ExportKey = Chacha20Key<C20P>;
+
+const HASH_LEN: usize = 32;
+
+// nesting limit for decoded msgpack values, which are at most three levels deep
+// in a valid export
+const MAX_DEPTH: usize = 8;
+
+const METHOD_NAMES: [&str; 3] = [
+    "ChaCha20Poly1305IETF",
+    "ChaCha20Poly1305IETFInteractive",
+    "ChaCha20Poly1305IETFRaw",
+];
+
+/// A record read from an indy-sdk wallet export
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndyRecord {
+    /// The record type
+    pub type_: String,
+    /// The record identifier, unique within its type
+    pub id: String,
+    /// The record value
+    pub value: String,
+    /// The record tags. Tag names prefixed by `~` are unencrypted tags
+    pub tags: BTreeMap<String, String>,
+}
+
+impl IndyRecord {
+    /// Convert the record to an entry, using the record type as the category
+    /// and the identifier as the name. Unencrypted tags are stored as plaintext tags.
+    pub fn into_entry(self) -> Entry {
+        let tags = self
+            .tags
+            .into_iter()
+            .map(|(name, value)| match name.strip_prefix('~') {
+                Some(name) => EntryTag::Plaintext(name.to_string(), value),
+                None => EntryTag::Encrypted(name, value),
+            })
+            .collect();
+        Entry::new(self.type_, self.id, self.value, tags)
+    }
+}
+
+/// A record of an indy wallet export which was not imported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedRecord {
+    /// The position of the record in the export
+    pub offset: u64,
+    /// The record type
+    pub type_: String,
+    /// The record identifier
+    pub id: String,
+    /// The reason the record was skipped
+    pub reason: String,
+}
+
+/// The outcome of an indy wallet import
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndyImportReport {
+    /// The number of records written to the store
+    pub imported: u64,
+    /// The records which were rejected by the mapping or the store
+    pub skipped: Vec<SkippedRecord>,
+    /// The offset of the first record not yet processed, at which an
+    /// interrupted import may be resumed
+    pub next_offset: u64,
+}
+
+pub(crate) async fn import_indy_wallet<B: Backend>(
+    store: &Store<B>,
+    path: PathBuf,
+    export_key: String,
+    profile: Option<String>,
+    mut mapping: impl FnMut(IndyRecord) -> Result<Option<Entry>, Error>,
+    start_offset: u64,
+) -> Result<IndyImportReport, Error> {
+    let export_key = Zeroizing::new(export_key);
+    let mut reader = unblock(move || {
+        let file =
+            File::open(&path).map_err(err_map!(Input, "Error reading indy wallet export"))?;
+        IndyExportReader::new(BufReader::new(file), &export_key)
+    })
+    .await?;
+
+    let mut report = IndyImportReport {
+        next_offset: start_offset,
+        ..Default::default()
+    };
+    let mut read_offset = 0u64;
+    loop {
+        let resume_err = |err: Error| {
+            Error::from_msg(
+                err.kind(),
+                format!(
+                    "Error importing indy wallet records, the import may be resumed at offset {}",
+                    report.next_offset
+                ),
+            )
+            .with_cause(err)
+        };
+        // the records are decrypted and decoded a batch at a time
+        let (batch, returned) = unblock(move || {
+            let mut batch = Vec::with_capacity(INDY_IMPORT_BATCH_SIZE);
+            while batch.len() < INDY_IMPORT_BATCH_SIZE {
+                match reader.next_record() {
+                    Ok(Some(record)) => {
+                        if read_offset >= start_offset {
+                            batch.push((record, read_offset));
+                        }
+                        read_offset += 1;
+                    }
+                    Ok(None) => break,
+                    Err(err) => return (Err(err), reader),
+                }
+            }
+            (Ok((batch, read_offset)), reader)
+        })
+        .await;
+        reader = returned;
+        let batch = match batch {
+            Ok((batch, offset)) => {
+                read_offset = offset;
+                batch
+            }
+            Err(err) => return Err(resume_err(err)),
+        };
+        if batch.is_empty() {
+            break;
+        }
+
+        let mut txn = store.transaction(profile.clone()).await?;
+        let mut imported = 0;
+        let mut skipped = vec![];
+        let mut next_offset = report.next_offset;
+        for (record, offset) in batch {
+            next_offset = offset + 1;
+            let (type_, id) = (record.type_.clone(), record.id.clone());
+            let skip = |reason: String| SkippedRecord {
+                offset,
+                type_,
+                id,
+                reason,
+            };
+            let entry = match mapping(record) {
+                Ok(Some(entry)) => entry,
+                Ok(None) => {
+                    skipped.push(skip("Excluded by the record mapping".to_string()));
+                    continue;
+                }
+                Err(err) => {
+                    skipped.push(skip(err.to_string()));
+                    continue;
+                }
+            };
+            // check for an existing record first, as a failed insert aborts
+            // the transaction for some backends
+            match txn.fetch(&entry.category, &entry.name, false).await {
+                Ok(None) => (),
+                Ok(Some(_)) => {
+                    skipped.push(skip(
+                        "A record with the same name already exists".to_string(),
+                    ));
+                    continue;
+                }
+                Err(err) => return Err(resume_err(err)),
+            }
+            match txn
+                .update_typed(
+                    EntryOperation::Insert,
+                    &entry.category,
+                    &entry.name,
+                    Some(entry.value.as_ref()),
+                    entry.value_type.as_deref(),
+                    Some(entry.tags.as_slice()),
+                    None,
+                )
+                .await
+            {
+                Ok(()) => imported += 1,
+                Err(err) if matches!(err.kind(), ErrorKind::Duplicate | ErrorKind::Input) => {
+                    skipped.push(skip(err.to_string()))
+                }
+                Err(err) => return Err(resume_err(err)),
+            }
+        }
+        txn.commit().await.map_err(resume_err)?;
+        report.imported += imported;
+        report.skipped.extend(skipped);
+        report.next_offset = next_offset;
+    }
+    Ok(report)
+}
+
+/// Reads the records of an indy-sdk wallet export, decrypting the export one
+/// chunk at a time so that only the records being decoded are held in memory
+pub(crate) struct IndyExportReader<R> {
+    reader: R,
+    key: ExportKey,
+    nonce: Vec<u8>,
+    chunk_size: usize,
+    // the decrypted data, of which the bytes before `pos` have been decoded
+    plain: Zeroizing<Vec<u8>>,
+    pos: usize,
+    // the hash of the header, which begins the decrypted data
+    header_hash: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: Read> IndyExportReader<R> {
+    /// Read the header of an export and derive the key used to decrypt it
+    pub fn new(mut reader: R, export_key: &str) -> Result<Self, Error> {
+        let mut header_len = [0u8; 4];
+        reader.read_exact(&mut header_len).map_err(err_map!(
+            Input,
+            "Invalid indy wallet export: unexpected end of data"
+        ))?;
+        let mut header = vec![];
+        reader
+            .by_ref()
+            .take(u32::from_le_bytes(header_len) as u64)
+            .read_to_end(&mut header)
+            .map_err(err_map!(Input, "Error reading indy wallet export"))?;
+        if header.len() != u32::from_le_bytes(header_len) as usize {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: truncated header"
+            ));
+        }
+        let header_hash = Sha256::new()
+            .chain(&header_len)
+            .chain(&header)
+            .finalize()
+            .to_vec();
+
+        let header = MsgReader::new(&header).read_all()?;
+        let (method, fields) = header
+            .fields(&["encryption_method", "time", "version"])?
+            .swap_remove(0)
+            .variant(&METHOD_NAMES)?;
+        let (key, nonce, chunk_size) = match method {
+            0 | 1 => {
+                let mut fields = fields.fields(&["salt", "nonce", "chunk_size"])?.into_iter();
+                let salt = fields.next().unwrap().into_bytes()?;
+                let params = if method == 0 {
+                    &PARAMS_MODERATE
+                } else {
+                    &PARAMS_INTERACTIVE
+                };
+                let key = derive_export_key(export_key, &salt, params)?;
+                let nonce = fields.next().unwrap().into_bytes()?;
+                (key, nonce, fields.next().unwrap().into_uint()?)
+            }
+            _ => {
+                let mut fields = fields.fields(&["nonce", "chunk_size"])?.into_iter();
+                let key = parse_export_key(export_key)?;
+                let nonce = fields.next().unwrap().into_bytes()?;
+                (key, nonce, fields.next().unwrap().into_uint()?)
+            }
+        };
+        if nonce.len() != <ExportKey as KeyAeadMeta>::NonceSize::USIZE {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: incorrect nonce length"
+            ));
+        }
+        if chunk_size == 0 || chunk_size > u32::MAX as u64 {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: invalid chunk size"
+            ));
+        }
+        Ok(Self {
+            reader,
+            key,
+            nonce,
+            chunk_size: chunk_size as usize,
+            plain: Zeroizing::new(vec![]),
+            pos: 0,
+            header_hash: Some(header_hash),
+            done: false,
+        })
+    }
+
+    /// Read the next record of the export, or `None` at the end of the export
+    pub fn next_record(&mut self) -> Result<Option<IndyRecord>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let result = self.read_record();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_record(&mut self) -> Result<Option<IndyRecord>, Error> {
+        if let Some(header_hash) = self.header_hash.take() {
+            if !self.fill(HASH_LEN)? || self.plain[self.pos..self.pos + HASH_LEN] != header_hash[..]
+            {
+                return Err(err_msg!(
+                    Input,
+                    "Invalid indy wallet export: header hash mismatch"
+                ));
+            }
+            self.pos += HASH_LEN;
+        }
+        if !self.fill(4)? {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: unexpected end of data"
+            ));
+        }
+        let len = read_u32(&self.plain, self.pos)? as usize;
+        self.pos += 4;
+        if len == 0 {
+            return Ok(None);
+        }
+        if !self.fill(len)? {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: truncated record"
+            ));
+        }
+        let record = MsgReader::new(&self.plain[self.pos..self.pos + len]).read_all()?;
+        self.pos += len;
+        let mut fields = record.fields(&["type", "id", "value", "tags"])?.into_iter();
+        Ok(Some(IndyRecord {
+            type_: fields.next().unwrap().into_string()?,
+            id: fields.next().unwrap().into_string()?,
+            value: fields.next().unwrap().into_string()?,
+            tags: fields.next().unwrap().into_tags()?,
+        }))
+    }
+
+    // decrypt chunks until `len` bytes are available, returning false at the
+    // end of the export
+    fn fill(&mut self, len: usize) -> Result<bool, Error> {
+        while self.plain.len() - self.pos < len {
+            if !self.decrypt_chunk()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // the export body is encrypted in chunks, each with its own tag, using a nonce
+    // which is incremented (as a little-endian integer) after every chunk
+    fn decrypt_chunk(&mut self) -> Result<bool, Error> {
+        let mut chunk = vec![];
+        self.reader
+            .by_ref()
+            .take((self.chunk_size + <ExportKey as KeyAeadMeta>::TagSize::USIZE) as u64)
+            .read_to_end(&mut chunk)
+            .map_err(err_map!(Input, "Error reading indy wallet export"))?;
+        if chunk.is_empty() {
+            return Ok(false);
+        }
+        let mut buf = SecretBytes::from(chunk);
+        self.key
+            .decrypt_in_place(&mut buf, &self.nonce, &[])
+            .map_err(|_| {
+                err_msg!(
+                    Encryption,
+                    "Error decrypting indy wallet export: the export key may be incorrect"
+                )
+            })?;
+        // the decoded data is dropped, which clears it
+        let mut plain = Zeroizing::new(Vec::with_capacity(self.plain.len() - self.pos + buf.len()));
+        plain.extend_from_slice(&self.plain[self.pos..]);
+        plain.extend_from_slice(&buf);
+        self.plain = plain;
+        self.pos = 0;
+        for byte in self.nonce.iter_mut() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                break;
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn derive_export_key(password: &str, salt: &[u8], params: &Params) -> Result<ExportKey, Error> {
+    ArrayKey::<<ExportKey as KeyMeta>::KeySize>::temp(|key| {
+        Argon2::new(password.as_bytes(), salt, *params)?.derive_key_bytes(key)?;
+        Ok(ExportKey::from_secret_bytes(&*key)?)
+    })
+}
+
+fn parse_export_key(raw_key: &str) -> Result<ExportKey, Error> {
+    ArrayKey::<<ExportKey as KeyMeta>::KeySize>::temp(|key| {
+        let key_len = bs58::decode(raw_key)
+            .into(&mut *key)
+            .map_err(|_| err_msg!(Input, "Error parsing raw export key as base58 value"))?;
+        if key_len != key.len() {
+            Err(err_msg!(
+                Input,
+                "Incorrect length for encoded raw export key"
+            ))
+        } else {
+            Ok(ExportKey::from_secret_bytes(&*key)?)
+        }
+    })
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| err_msg!(Input, "Invalid indy wallet export: unexpected end of data"))
+}
+
+// a decoded msgpack value. Only the types produced by the indy-sdk export are supported
+#[derive(Debug, PartialEq)]
+enum MsgValue {
+    Nil,
+    Bool(bool),
+    Int(i128),
+    Bytes(Vec<u8>),
+    Str(String),
+    Array(Vec<MsgValue>),
+    Map(Vec<(MsgValue, MsgValue)>),
+}
+
+impl MsgValue {
+    // structures may be encoded either as arrays or as maps of named fields
+    fn fields(self, names: &[&str]) -> Result<Vec<MsgValue>, Error> {
+        match self {
+            Self::Array(mut values) if values.len() >= names.len() => {
+                values.truncate(names.len());
+                Ok(values)
+            }
+            Self::Map(mut entries) => names
+                .iter()
+                .map(|name| {
+                    entries
+                        .iter()
+                        .position(|(k, _)| matches!(k, Self::Str(k) if k == name))
+                        .map(|idx| entries.swap_remove(idx).1)
+                        .ok_or_else(|| {
+                            err_msg!(
+                                Input,
+                                "Invalid indy wallet export: missing field '{}'",
+                                name
+                            )
+                        })
+                })
+                .collect(),
+            _ => Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: expected a structure"
+            )),
+        }
+    }
+
+    // enum variants may be encoded as `[index, fields]` or `{name: fields}`
+    fn variant(self, names: &[&str]) -> Result<(usize, MsgValue), Error> {
+        let (id, fields) = match self {
+            Self::Array(values) if values.len() == 2 => {
+                let mut values = values.into_iter();
+                (values.next().unwrap(), values.next().unwrap())
+            }
+            Self::Map(entries) if entries.len() == 1 => entries.into_iter().next().unwrap(),
+            _ => {
+                return Err(err_msg!(
+                    Input,
+                    "Invalid indy wallet export: expected a variant"
+                ))
+            }
+        };
+        let idx = match id {
+            Self::Int(idx) if idx >= 0 && (idx as usize) < names.len() => Some(idx as usize),
+            Self::Str(name) => names.iter().position(|n| *n == name),
+            _ => None,
+        };
+        idx.map(|idx| (idx, fields))
+            .ok_or_else(|| err_msg!(Input, "Invalid indy wallet export: unsupported variant"))
+    }
+
+    fn into_uint(self) -> Result<u64, Error> {
+        match self {
+            Self::Int(val) if val >= 0 && val <= u64::MAX as i128 => Ok(val as u64),
+            _ => Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: expected an integer"
+            )),
+        }
+    }
+
+    fn into_string(self) -> Result<String, Error> {
+        match self {
+            Self::Str(val) => Ok(val),
+            Self::Bytes(val) => String::from_utf8(val).map_err(err_map!(
+                Input,
+                "Invalid indy wallet export: invalid string"
+            )),
+            _ => Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: expected a string"
+            )),
+        }
+    }
+
+    // byte vectors are encoded as arrays of integers by older serializers
+    fn into_bytes(self) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Bytes(val) => Ok(val),
+            Self::Array(values) => values
+                .into_iter()
+                .map(|val| match val {
+                    Self::Int(b) if b >= 0 && b <= 255 => Ok(b as u8),
+                    _ => Err(err_msg!(
+                        Input,
+                        "Invalid indy wallet export: expected bytes"
+                    )),
+                })
+                .collect(),
+            _ => Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: expected bytes"
+            )),
+        }
+    }
+
+    fn into_tags(self) -> Result<BTreeMap<String, String>, Error> {
+        match self {
+            Self::Nil => Ok(BTreeMap::new()),
+            Self::Map(entries) => entries
+                .into_iter()
+                .map(|(k, v)| Ok((k.into_string()?, v.into_string()?)))
+                .collect(),
+            _ => Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: expected a map"
+            )),
+        }
+    }
+}
+
+struct MsgReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MsgReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read_all(mut self) -> Result<MsgValue, Error> {
+        let value = self.read(0)?;
+        if self.pos != self.data.len() {
+            return Err(err_msg!(Input, "Invalid indy wallet export: trailing data"));
+        }
+        Ok(value)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.pos < len {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: truncated value"
+            ));
+        }
+        let result = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(result)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, Error> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    fn int(&mut self, len: usize) -> Result<i64, Error> {
+        let shift = 64 - len * 8;
+        Ok(((self.uint(len)? << shift) as i64) >> shift)
+    }
+
+    fn read(&mut self, depth: usize) -> Result<MsgValue, Error> {
+        if depth > MAX_DEPTH {
+            return Err(err_msg!(
+                Input,
+                "Invalid indy wallet export: value nested too deeply"
+            ));
+        }
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            0x00..=0x7f => MsgValue::Int(marker as i128),
+            0x80..=0x8f => self.read_map((marker & 0x0f) as usize, depth)?,
+            0x90..=0x9f => self.read_array((marker & 0x0f) as usize, depth)?,
+            0xa0..=0xbf => self.read_str((marker & 0x1f) as usize)?,
+            0xc0 => MsgValue::Nil,
+            0xc2 => MsgValue::Bool(false),
+            0xc3 => MsgValue::Bool(true),
+            0xc4..=0xc6 => {
+                let len = self.uint(1 << (marker - 0xc4))? as usize;
+                MsgValue::Bytes(self.take(len)?.to_vec())
+            }
+            0xcc..=0xcf => MsgValue::Int(self.uint(1 << (marker - 0xcc))? as i128),
+            0xd0..=0xd3 => MsgValue::Int(self.int(1 << (marker - 0xd0))? as i128),
+            0xd9..=0xdb => {
+                let len = self.uint(1 << (marker - 0xd9))? as usize;
+                self.read_str(len)?
+            }
+            0xdc | 0xdd => {
+                let len = self.uint(2 << (marker - 0xdc))? as usize;
+                self.read_array(len, depth)?
+            }
+            0xde | 0xdf => {
+                let len = self.uint(2 << (marker - 0xde))? as usize;
+                self.read_map(len, depth)?
+            }
+            0xe0..=0xff => MsgValue::Int((marker as i8) as i128),
+            _ => {
+                return Err(err_msg!(
+                    Input,
+                    "Invalid indy wallet export: unsupported value type 0x{:02x}",
+                    marker
+                ))
+            }
+        })
+    }
+
+    fn read_str(&mut self, len: usize) -> Result<MsgValue, Error> {
+        let val = std::str::from_utf8(self.take(len)?).map_err(err_map!(
+            Input,
+            "Invalid indy wallet export: invalid string"
+        ))?;
+        Ok(MsgValue::Str(val.to_string()))
+    }
+
+    fn read_array(&mut self, len: usize, depth: usize) -> Result<MsgValue, Error> {
+        // each element occupies at least one byte
+        let mut values = Vec::with_capacity(len.min(self.data.len() - self.pos));
+        for _ in 0..len {
+            values.push(self.read(depth + 1)?);
+        }
+        Ok(MsgValue::Array(values))
+    }
+
+    fn read_map(&mut self, len: usize, depth: usize) -> Result<MsgValue, Error> {
+        let mut entries = Vec::with_capacity(len.min(self.data.len() - self.pos));
+        for _ in 0..len {
+            let key = self.read(depth + 1)?;
+            entries.push((key, self.read(depth + 1)?));
+        }
+        Ok(MsgValue::Map(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::random::fill_random;
+    use std::fs;
+
+    fn read_indy_export(data: &[u8], export_key: &str) -> Result<Vec<IndyRecord>, Error> {
+        let mut reader = IndyExportReader::new(data, export_key)?;
+        let mut records = vec![];
+        while let Some(record) = reader.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn write_str(buf: &mut Vec<u8>, val: &str) {
+        assert!(val.len() < 256);
+        buf.extend_from_slice(&[0xd9, val.len() as u8]);
+        buf.extend_from_slice(val.as_bytes());
+    }
+
+    fn write_record(buf: &mut Vec<u8>, type_: &str, id: &str, value: &str, tags: &[(&str, &str)]) {
+        let mut rec = vec![0x94];
+        write_str(&mut rec, type_);
+        write_str(&mut rec, id);
+        write_str(&mut rec, value);
+        rec.push(0x80 | tags.len() as u8);
+        for (k, v) in tags {
+            write_str(&mut rec, k);
+            write_str(&mut rec, v);
+        }
+        buf.extend_from_slice(&(rec.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&rec);
+    }
+
+    // build an export using a raw key, with the header encoded in the
+    // array format of older indy-sdk releases
+    fn build_export(raw_key: &str, records: &[(&str, &str, &str, &[(&str, &str)])]) -> Vec<u8> {
+        let key = parse_export_key(raw_key).unwrap();
+        let mut nonce = [0u8; 12];
+        fill_random(&mut nonce);
+        let chunk_size = 64;
+        let mut header = vec![0x93, 0x92, 0x02, 0x92, 0xc4, 0x0c];
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(&[0x40, 0xce, 0x60, 0x00, 0x00, 0x00, 0x00]);
+        let mut out = (header.len() as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(&header);
+
+        let mut plain = Sha256::digest(&out).to_vec();
+        for (type_, id, value, tags) in records {
+            write_record(&mut plain, type_, id, value, tags);
+        }
+        plain.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut nonce = nonce.to_vec();
+        for chunk in plain.chunks(chunk_size) {
+            let mut buf = SecretBytes::from_slice(chunk);
+            key.encrypt_in_place(&mut buf, &nonce, &[]).unwrap();
+            out.extend_from_slice(&buf);
+            for byte in nonce.iter_mut() {
+                *byte = byte.wrapping_add(1);
+                if *byte != 0 {
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn read_export_raw_key() {
+        let raw_key = crate::protect::generate_raw_store_key(None).unwrap();
+        let export = build_export(
+            &raw_key,
+            &[
+                (
+                    "Indy::Did",
+                    "did-1",
+                    "{\"did\":\"did-1\"}",
+                    &[("~verkey", "vk")],
+                ),
+                ("Indy::Key", "key-1", "{\"signkey\":\"secret\"}", &[]),
+            ],
+        );
+        let records = read_indy_export(&export, &raw_key).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].type_, "Indy::Did");
+        assert_eq!(records[1].value, "{\"signkey\":\"secret\"}");
+        assert_eq!(
+            records[0].clone().into_entry().tags,
+            vec![EntryTag::Plaintext("verkey".to_string(), "vk".to_string())]
+        );
+
+        let other_key = crate::protect::generate_raw_store_key(None).unwrap();
+        let err = read_indy_export(&export, &other_key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encryption);
+
+        let mut truncated = export.clone();
+        truncated.truncate(export.len() - 8);
+        assert!(read_indy_export(&truncated, &raw_key).is_err());
+    }
+
+    #[test]
+    fn read_export_truncated() {
+        let raw_key = crate::protect::generate_raw_store_key(None).unwrap();
+        let export = build_export(
+            &raw_key,
+            &[
+                ("Indy::Did", "did-1", "a", &[]),
+                ("Indy::Did", "did-2", "b", &[]),
+            ],
+        );
+        // the header is incomplete
+        for len in &[0, 3, 10] {
+            let err = read_indy_export(&export[..*len], &raw_key).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Input);
+        }
+        // the export ends on a chunk boundary before the final record
+        let header_len = 4 + read_u32(&export, 0).unwrap() as usize;
+        let chunk_len = 64 + <ExportKey as KeyAeadMeta>::TagSize::USIZE;
+        let mut reader = IndyExportReader::new(&export[..header_len + chunk_len], &raw_key)
+            .expect("Error reading export header");
+        assert_eq!(reader.next_record().unwrap().unwrap().id, "did-1");
+        let err = reader.next_record().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Input);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_malformed_values() {
+        for data in &[
+            // empty input
+            &[][..],
+            // truncated string
+            &[0xd9, 0x05, b'a'][..],
+            // truncated integer
+            &[0xcd, 0x01][..],
+            // array length exceeding the data
+            &[0xdd, 0xff, 0xff, 0xff, 0xff, 0x01][..],
+            // map missing a value
+            &[0x81, 0xa1, b'a'][..],
+            // unsupported type
+            &[0xc1][..],
+            // invalid UTF-8 string
+            &[0xa2, 0xff, 0xfe][..],
+            // trailing data
+            &[0x01, 0x02][..],
+        ] {
+            let err = MsgReader::new(data).read_all().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Input);
+        }
+
+        let mut nested = vec![0x91; MAX_DEPTH + 2];
+        nested.push(0x00);
+        let err = MsgReader::new(&nested).read_all().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Input);
+        nested.drain(..2);
+        assert!(MsgReader::new(&nested).read_all().is_ok());
+
+        // values of the wrong type are rejected when the fields are read
+        let value = MsgReader::new(&[0x92, 0x01, 0xc0]).read_all().unwrap();
+        let err = value.fields(&["type", "id", "value"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Input);
+        let value = MsgReader::new(&[0xa1, b'a']).read_all().unwrap();
+        assert_eq!(value.into_uint().unwrap_err().kind(), ErrorKind::Input);
+    }
+
+    #[test]
+    fn decode_header_map_format() {
+        // {"encryption_method": {"ChaCha20Poly1305IETFRaw": {"nonce": bin, "chunk_size": 1024}}}
+        let mut buf = vec![0x83];
+        write_str(&mut buf, "encryption_method");
+        buf.push(0x81);
+        write_str(&mut buf, "ChaCha20Poly1305IETFRaw");
+        buf.push(0x82);
+        write_str(&mut buf, "chunk_size");
+        buf.extend_from_slice(&[0xcd, 0x04, 0x00]);
+        write_str(&mut buf, "nonce");
+        buf.extend_from_slice(&[0xc4, 0x02, 0xaa, 0xbb]);
+        write_str(&mut buf, "time");
+        buf.push(0x01);
+        write_str(&mut buf, "version");
+        buf.push(0x00);
+
+        let header = MsgReader::new(&buf).read_all().unwrap();
+        let (method, fields) = header
+            .fields(&["encryption_method", "time", "version"])
+            .unwrap()
+            .swap_remove(0)
+            .variant(&METHOD_NAMES)
+            .unwrap();
+        assert_eq!(method, 2);
+        let mut fields = fields.fields(&["nonce", "chunk_size"]).unwrap().into_iter();
+        assert_eq!(
+            fields.next().unwrap().into_bytes().unwrap(),
+            vec![0xaa, 0xbb]
+        );
+        assert_eq!(fields.next().unwrap().into_uint().unwrap(), 1024);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn import_skip_and_resume() {
+        use crate::backend::sqlite::SqliteStoreOptions;
+        use crate::future::block_on;
+        use crate::protect::{generate_raw_store_key, StoreKeyMethod};
+
+        let raw_key = generate_raw_store_key(None).unwrap();
+        let export = build_export(
+            &raw_key,
+            &[
+                ("Indy::Did", "did-1", "a", &[]),
+                ("Indy::Did", "did-2", "b", &[]),
+                ("Indy::Temp", "tmp-1", "c", &[]),
+                ("Indy::Did", "did-1", "d", &[]),
+            ],
+        );
+        let path = std::env::temp_dir().join(format!("indy-export-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, &export).unwrap();
+
+        block_on(async {
+            let key = generate_raw_store_key(None)?;
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await?;
+            let mapping = |record: IndyRecord| -> Result<Option<Entry>, Error> {
+                Ok(if record.type_ == "Indy::Temp" {
+                    None
+                } else {
+                    Some(record.into_entry())
+                })
+            };
+
+            // resume after the first record
+            let report = store
+                .import_indy_wallet(&path, &raw_key, None, mapping, 1)
+                .await?;
+            assert_eq!(report.imported, 2);
+            assert_eq!(report.next_offset, 4);
+            assert_eq!(
+                report.skipped.iter().map(|s| s.offset).collect::<Vec<_>>(),
+                vec![2]
+            );
+
+            // the remaining records are imported from the start, and duplicates are reported
+            let report = store
+                .import_indy_wallet(&path, &raw_key, None, mapping, 0)
+                .await?;
+            assert_eq!(report.imported, 0);
+            assert_eq!(
+                report.skipped.iter().map(|s| s.offset).collect::<Vec<_>>(),
+                vec![0, 1, 2, 3]
+            );
+
+            let mut session = store.session(None).await?;
+            let entry = session.fetch("Indy::Did", "did-1", false).await?.unwrap();
+            assert_eq!(entry.value, b"d".to_vec());
+            assert_eq!(session.count("Indy::Did", None).await?, 2);
+            Result::<_, Error>::Ok(())
+        })
+        .unwrap();
+        fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::batch::BatchUpdate;
+use super::store::Store;
+use crate::{
+    backend::Backend,
+    error::Error,
+    future::{spawn, BoxFuture},
+};
+
+/// The default number of updates applied in each transaction by an `IngestSink`
+pub const DEFAULT_INGEST_BATCH_SIZE: usize = 500;
+
+/// Configuration for the bulk ingestion of record updates by `Store::ingest`
+#[derive(Clone, Debug)]
+pub struct IngestConfig {
+    /// The profile receiving the updates, if not the default profile
+    pub profile: Option<String>,
+    /// The maximum number of updates applied in a single transaction
+    pub batch_size: usize,
+    /// The maximum time a partial batch is held before it is applied. The
+    /// interval is checked as each update is sent
+    pub flush_interval: Duration,
+    /// The maximum number of batches being applied at once
+    pub max_workers: usize,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            batch_size: DEFAULT_INGEST_BATCH_SIZE,
+            flush_interval: Duration::from_millis(100),
+            max_workers: 4,
+        }
+    }
+}
+
+/// The outcome of a single update sent to an `IngestSink`
+#[derive(Debug)]
+pub struct IngestResult {
+    /// The sequence number returned when the update was sent
+    pub sequence: u64,
+    /// The result of applying the update
+    pub result: Result<(), Error>,
+}
+
+/// A buffer for the bulk ingestion of record updates, as returned by `Store::ingest`.
+///
+/// Updates are collected into batches which are applied in the background,
+/// each within a single transaction. When a batch fails, its updates are
+/// applied individually so that a result is reported for each. Sending an
+/// update waits while the maximum number of batches are being applied, so
+/// that the sender is held back when the database falls behind.
+///
+/// Updates which have not been applied are discarded when the sink is dropped,
+/// so it should be completed using `close`.
+pub struct IngestSink<B: Backend> {
+    store: Arc<Store<B>>,
+    config: IngestConfig,
+    pending: Vec<(u64, BatchUpdate)>,
+    pending_since: Option<Instant>,
+    workers: VecDeque<BoxFuture<'static, Vec<IngestResult>>>,
+    results: VecDeque<IngestResult>,
+    sequence: u64,
+}
+
+impl<B: Backend + 'static> IngestSink<B> {
+    pub(crate) fn new(store: Arc<Store<B>>, config: IngestConfig) -> Result<Self, Error> {
+        if config.batch_size == 0 || config.max_workers == 0 {
+            return Err(err_msg!(
+                Input,
+                "Ingest batch size and worker count must be greater than zero"
+            ));
+        }
+        Ok(Self {
+            store,
+            config,
+            pending: vec![],
+            pending_since: None,
+            workers: VecDeque::new(),
+            results: VecDeque::new(),
+            sequence: 0,
+        })
+    }
+
+    /// Add an update to the current batch, returning its sequence number.
+    ///
+    /// The batch is started when it is full or the flush interval has passed.
+    pub async fn send(&mut self, update: BatchUpdate) -> u64 {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        self.pending.push((sequence, update));
+        let since = *self.pending_since.get_or_insert_with(Instant::now);
+        if self.pending.len() >= self.config.batch_size
+            || since.elapsed() >= self.config.flush_interval
+        {
+            self.start_batch().await;
+        }
+        sequence
+    }
+
+    /// Take the results of the updates applied so far, without waiting
+    pub fn take_results(&mut self) -> Vec<IngestResult> {
+        self.results.drain(..).collect()
+    }
+
+    /// Wait for the next result, in the order the updates were sent. Returns
+    /// `None` when all the results have been taken
+    pub async fn next_result(&mut self) -> Option<IngestResult> {
+        if self.results.is_empty() {
+            if self.workers.is_empty() && !self.pending.is_empty() {
+                self.start_batch().await;
+            }
+            if let Some(worker) = self.workers.pop_front() {
+                self.results.extend(worker.await);
+            }
+        }
+        self.results.pop_front()
+    }
+
+    /// Apply all the pending updates, waiting for them to complete
+    pub async fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.start_batch().await;
+        }
+        while let Some(worker) = self.workers.pop_front() {
+            self.results.extend(worker.await);
+        }
+    }
+
+    /// Apply all the pending updates and return the results not yet taken
+    pub async fn close(mut self) -> Vec<IngestResult> {
+        self.flush().await;
+        self.take_results()
+    }
+
+    async fn start_batch(&mut self) {
+        // wait for the oldest batch to complete when all workers are busy
+        while self.workers.len() >= self.config.max_workers {
+            let worker = self.workers.pop_front().unwrap();
+            self.results.extend(worker.await);
+        }
+        let batch = mem::take(&mut self.pending);
+        self.pending_since = None;
+        let store = self.store.clone();
+        let profile = self.config.profile.clone();
+        self.workers
+            .push_back(spawn(apply_ingest_batch(store, profile, batch)));
+    }
+}
+
+impl<B: Backend> Debug for IngestSink<B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IngestSink")
+            .field("config", &self.config)
+            .field("pending", &self.pending.len())
+            .field("workers", &self.workers.len())
+            .field("results", &self.results.len())
+            .finish()
+    }
+}
+
+async fn apply_ingest_batch<B: Backend>(
+    store: Arc<Store<B>>,
+    profile: Option<String>,
+    batch: Vec<(u64, BatchUpdate)>,
+) -> Vec<IngestResult> {
+    let updates = batch
+        .iter()
+        .map(|(_, update)| update.clone())
+        .collect::<Vec<_>>();
+    match store.apply_batch(profile.clone(), &updates, None).await {
+        Ok(_) => batch
+            .into_iter()
+            .map(|(sequence, _)| IngestResult {
+                sequence,
+                result: Ok(()),
+            })
+            .collect(),
+        Err(err) => {
+            debug!(
+                "Ingest batch failed, applying updates individually: {}",
+                err
+            );
+            let mut results = Vec::with_capacity(batch.len());
+            for (sequence, update) in batch {
+                let result = store
+                    .apply_batch(profile.clone(), &[update], None)
+                    .await
+                    .map(|_| ());
+                results.push(IngestResult { sequence, result });
+            }
+            results
+        }
+    }
+}
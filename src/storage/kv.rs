@@ -0,0 +1,214 @@
+use std::fmt::Debug;
+
+use super::store::Store;
+use crate::{
+    backend::Backend,
+    error::{Error, ErrorKind},
+    future::BoxFuture,
+};
+
+/// The separator placed between the category and name of a record by `SeparatorSplitter`
+pub const KEY_SEPARATOR: &str = "/";
+
+/// A minimal asynchronous key-value interface, for frameworks which store
+/// opaque values and do not depend on the record model of the store
+pub trait KeyValue: Send + Sync {
+    /// Fetch the value stored under a key
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>>;
+
+    /// Store a value under a key, replacing any existing value
+    fn put<'a>(&'a self, key: &'a str, value: &'a [u8]) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Remove the value stored under a key, returning whether it was present
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, Error>>;
+
+    /// List the keys beginning with a prefix, in sorted order
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>>;
+}
+
+/// Maps the keys of a `KeyValue` interface to record categories and names
+pub trait KeySplitter: Debug + Send + Sync {
+    /// Split a key into a category and a name, or return `None` if the key
+    /// does not include a category
+    fn split<'k>(&self, key: &'k str) -> Option<(&'k str, &'k str)>;
+
+    /// Combine a category and a name into a key
+    fn join(&self, category: &str, name: &str) -> String;
+}
+
+/// Splits keys into a category and name at the first occurrence of a separator
+#[derive(Clone, Debug)]
+pub struct SeparatorSplitter {
+    separator: String,
+}
+
+impl SeparatorSplitter {
+    /// Create a new splitter for a non-empty separator
+    pub fn new(separator: &str) -> Result<Self, Error> {
+        if separator.is_empty() {
+            return Err(err_msg!(Input, "Key separator must not be empty"));
+        }
+        Ok(Self {
+            separator: separator.to_string(),
+        })
+    }
+}
+
+impl Default for SeparatorSplitter {
+    fn default() -> Self {
+        Self {
+            separator: KEY_SEPARATOR.to_string(),
+        }
+    }
+}
+
+impl KeySplitter for SeparatorSplitter {
+    fn split<'k>(&self, key: &'k str) -> Option<(&'k str, &'k str)> {
+        let idx = key.find(self.separator.as_str())?;
+        Some((&key[..idx], &key[(idx + self.separator.len())..]))
+    }
+
+    fn join(&self, category: &str, name: &str) -> String {
+        format!("{}{}{}", category, self.separator, name)
+    }
+}
+
+/// An implementation of `KeyValue` over the records of a store profile
+///
+/// Values are stored as record values without tags. Listing keys performs a
+/// scan of the matching categories, and is not suited to large profiles.
+#[derive(Debug)]
+pub struct KvAdapter<'s, B: Backend, S: KeySplitter = SeparatorSplitter> {
+    store: &'s Store<B>,
+    profile: Option<String>,
+    splitter: S,
+}
+
+impl<'s, B: Backend> KvAdapter<'s, B> {
+    pub(crate) fn new(store: &'s Store<B>) -> Self {
+        Self {
+            store,
+            profile: None,
+            splitter: SeparatorSplitter::default(),
+        }
+    }
+}
+
+impl<'s, B: Backend, S: KeySplitter> KvAdapter<'s, B, S> {
+    /// Access the records of a profile other than the default profile
+    pub fn with_profile(mut self, profile: String) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Replace the mapping of keys to record categories and names
+    pub fn with_splitter<T: KeySplitter>(self, splitter: T) -> KvAdapter<'s, B, T> {
+        KvAdapter {
+            store: self.store,
+            profile: self.profile,
+            splitter,
+        }
+    }
+
+    fn split_key<'k>(&self, key: &'k str) -> Result<(&'k str, &'k str), Error> {
+        self.splitter
+            .split(key)
+            .ok_or_else(|| err_msg!(Input, "Key does not include a category: '{}'", key))
+    }
+
+    async fn get_value(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let (category, name) = self.split_key(key)?;
+        let mut conn = self.store.session(self.profile.clone()).await?;
+        Ok(conn
+            .fetch(category, name, false)
+            .await?
+            .map(|entry| entry.value.into_vec()))
+    }
+
+    async fn put_value(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let (category, name) = self.split_key(key)?;
+        let mut conn = self.store.session(self.profile.clone()).await?;
+        match conn.replace(category, name, value, None, None).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                conn.insert(category, name, value, None, None).await
+            }
+            result => result,
+        }
+    }
+
+    async fn delete_value(&self, key: &str) -> Result<bool, Error> {
+        let (category, name) = self.split_key(key)?;
+        let mut conn = self.store.session(self.profile.clone()).await?;
+        match conn.remove(category, name).await {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let scans = if let Some((category, name_prefix)) = self.splitter.split(prefix) {
+            vec![(category.to_string(), name_prefix)]
+        } else {
+            self.store
+                .list_categories(self.profile.clone())
+                .await?
+                .into_iter()
+                .filter(|category| category.starts_with(prefix))
+                .map(|category| (category, ""))
+                .collect()
+        };
+        let mut keys = vec![];
+        for (category, name_prefix) in scans {
+            let mut scan = self
+                .store
+                .scan(self.profile.clone(), category.clone(), None, None, None)
+                .await?;
+            while let Some(rows) = scan.fetch_next().await? {
+                keys.extend(
+                    rows.into_iter()
+                        .filter(|entry| entry.name.starts_with(name_prefix))
+                        .map(|entry| self.splitter.join(&category, &entry.name)),
+                );
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+impl<'s, B: Backend, S: KeySplitter> KeyValue for KvAdapter<'s, B, S> {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        Box::pin(self.get_value(key))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: &'a [u8]) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.put_value(key, value))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(self.delete_value(key))
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>> {
+        Box::pin(self.list_keys(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separator_split_join() {
+        let splitter = SeparatorSplitter::default();
+        assert_eq!(splitter.split("cat/name/sub"), Some(("cat", "name/sub")));
+        assert_eq!(splitter.split("cat/"), Some(("cat", "")));
+        assert_eq!(splitter.split("cat"), None);
+        assert_eq!(splitter.join("cat", "name/sub"), "cat/name/sub");
+
+        let splitter = SeparatorSplitter::new("::").unwrap();
+        assert_eq!(splitter.split("cat::name"), Some(("cat", "name")));
+        assert!(SeparatorSplitter::new("").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use super::entry::{Entry, EntryOperation, EntryTag, TagFilter};
+use crate::{error::Error, future::LocalBoxFuture};
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::store::Store;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::backend::Backend;
+
+/// A minimal interface to the records of a single store profile, which does
+/// not depend on a database connection.
+///
+/// This is implemented by `Store` for its default profile, and by the IndexedDB
+/// store available to browser wallets with the `wasm-storage` feature. The
+/// futures produced are not required to be `Send`, as a browser store is bound
+/// to a single context. Each update is applied atomically on its own.
+pub trait KvStore {
+    /// Fetch the record with a category and name.
+    ///
+    /// No lock is held on the record between calls, so a record which is read
+    /// and then updated may have been changed in the meantime. `Store`
+    /// sessions and transactions should be used where this matters.
+    fn fetch<'a>(
+        &'a self,
+        category: &'a str,
+        name: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<Entry>, Error>>;
+
+    /// Insert, replace or remove a record. Inserting a record which already
+    /// exists fails with a `Duplicate` error, while replacing or removing one
+    /// which does not exist fails with a `NotFound` error.
+    fn update<'a>(
+        &'a self,
+        operation: EntryOperation,
+        category: &'a str,
+        name: &'a str,
+        value: Option<&'a [u8]>,
+        tags: Option<&'a [EntryTag]>,
+    ) -> LocalBoxFuture<'a, Result<(), Error>>;
+
+    /// Fetch the records of a category which match an optional tag filter.
+    ///
+    /// The order of the records is determined by the store, and the offset and
+    /// limit are applied to the matching records in that order.
+    fn scan<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> LocalBoxFuture<'a, Result<Vec<Entry>, Error>>;
+
+    /// Count the records of a category which match an optional tag filter
+    fn count<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+    ) -> LocalBoxFuture<'a, Result<i64, Error>>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<B: Backend> KvStore for Store<B> {
+    fn fetch<'a>(
+        &'a self,
+        category: &'a str,
+        name: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<Entry>, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(None).await?;
+            session.fetch(category, name, false).await
+        })
+    }
+
+    fn update<'a>(
+        &'a self,
+        operation: EntryOperation,
+        category: &'a str,
+        name: &'a str,
+        value: Option<&'a [u8]>,
+        tags: Option<&'a [EntryTag]>,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut session = self.session(None).await?;
+            session
+                .update(operation, category, name, value, tags, None)
+                .await
+        })
+    }
+
+    fn scan<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> LocalBoxFuture<'a, Result<Vec<Entry>, Error>> {
+        Box::pin(async move {
+            let mut scan =
+                Store::scan(self, None, category.to_string(), tag_filter, offset, limit).await?;
+            let mut entries = vec![];
+            while let Some(rows) = scan.fetch_next().await? {
+                entries.extend(rows);
+            }
+            Ok(entries)
+        })
+    }
+
+    fn count<'a>(
+        &'a self,
+        category: &'a str,
+        tag_filter: Option<TagFilter>,
+    ) -> LocalBoxFuture<'a, Result<i64, Error>> {
+        Box::pin(async move {
+            let mut session = self.session(None).await?;
+            session.count(category, tag_filter).await
+        })
+    }
+}
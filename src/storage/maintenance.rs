@@ -0,0 +1,186 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_lock::{Mutex, MutexGuardArc};
+use futures_lite::future;
+
+use super::store::Store;
+use crate::{
+    backend::Backend,
+    crypto::random::fill_random,
+    error::Error,
+    future::{sleep, spawn_ok},
+};
+
+/// A callback invoked with the outcome of each maintenance run
+pub type MaintenanceCallback = Arc<dyn Fn(MaintenanceTask, &Result<i64, Error>) + Send + Sync>;
+
+/// A maintenance operation performed in the background
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaintenanceTask {
+    /// Remove expired records, along with applied batches, prior record values
+    /// and access log entries beyond their retention. The outcome is the number
+    /// of records removed
+    PurgeExpired,
+    /// Checkpoint the database. The outcome is always zero
+    Checkpoint,
+}
+
+/// Configuration for the periodic maintenance of a store
+#[derive(Clone)]
+pub struct MaintenanceConfig {
+    /// The interval between purges of expired records, or `None` to disable
+    pub purge_interval: Option<Duration>,
+    /// The interval between database checkpoints, or `None` to disable
+    pub checkpoint_interval: Option<Duration>,
+    /// The maximum random delay added to each interval, to avoid multiple
+    /// instances performing maintenance at the same time
+    pub jitter: Duration,
+    /// A callback invoked after each maintenance run
+    pub on_complete: Option<MaintenanceCallback>,
+}
+
+impl MaintenanceConfig {
+    /// Set the callback invoked after each maintenance run
+    pub fn on_complete(
+        mut self,
+        f: impl Fn(MaintenanceTask, &Result<i64, Error>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_complete = Some(Arc::new(f));
+        self
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            purge_interval: Some(Duration::from_secs(600)),
+            checkpoint_interval: Some(Duration::from_secs(300)),
+            jitter: Duration::from_secs(10),
+            on_complete: None,
+        }
+    }
+}
+
+impl Debug for MaintenanceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MaintenanceConfig")
+            .field("purge_interval", &self.purge_interval)
+            .field("checkpoint_interval", &self.checkpoint_interval)
+            .field("jitter", &self.jitter)
+            .field("on_complete", &self.on_complete.is_some())
+            .finish()
+    }
+}
+
+/// A handle to the background maintenance of a store.
+///
+/// Maintenance is stopped when the handle is dropped.
+#[derive(Debug)]
+pub struct MaintenanceHandle {
+    // the background task exits once this guard is released
+    running: Option<MutexGuardArc<()>>,
+    done: Arc<Mutex<()>>,
+}
+
+impl MaintenanceHandle {
+    pub(crate) fn start<B: Backend + 'static>(
+        store: Arc<Store<B>>,
+        config: MaintenanceConfig,
+    ) -> Self {
+        let stop = Arc::new(Mutex::new(()));
+        let running = stop.try_lock_arc().expect("Error locking new mutex");
+        let done = Arc::new(Mutex::new(()));
+        let finished = done.try_lock_arc().expect("Error locking new mutex");
+        spawn_ok(async move {
+            run_maintenance(store, config, stop).await;
+            drop(finished);
+        });
+        Self {
+            running: Some(running),
+            done,
+        }
+    }
+
+    /// Stop the background maintenance, waiting for any run in progress to complete
+    pub async fn stop(mut self) {
+        self.running.take();
+        self.done.lock().await;
+    }
+}
+
+async fn run_maintenance<B: Backend>(
+    store: Arc<Store<B>>,
+    config: MaintenanceConfig,
+    stop: Arc<Mutex<()>>,
+) {
+    let start = Instant::now();
+    let mut next_purge = config
+        .purge_interval
+        .map(|interval| start + interval + jitter(config.jitter));
+    let mut next_checkpoint = config
+        .checkpoint_interval
+        .map(|interval| start + interval + jitter(config.jitter));
+
+    loop {
+        let next = match (next_purge, next_checkpoint) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => break,
+        };
+        let delay = next.saturating_duration_since(Instant::now());
+        let stopped = future::or(
+            async {
+                sleep(delay).await;
+                false
+            },
+            async {
+                stop.lock().await;
+                true
+            },
+        )
+        .await;
+        if stopped {
+            break;
+        }
+
+        // tasks are performed in sequence, so runs can never overlap. A run which
+        // exceeds the interval delays the next one instead
+        let now = Instant::now();
+        if let (Some(due), Some(interval)) = (next_purge, config.purge_interval) {
+            if due <= now {
+                let result = store.purge_expired().await;
+                report(&config, MaintenanceTask::PurgeExpired, result);
+                next_purge = Some(Instant::now() + interval + jitter(config.jitter));
+            }
+        }
+        if let (Some(due), Some(interval)) = (next_checkpoint, config.checkpoint_interval) {
+            if due <= now {
+                let result = store.checkpoint().await.map(|_| 0);
+                report(&config, MaintenanceTask::Checkpoint, result);
+                next_checkpoint = Some(Instant::now() + interval + jitter(config.jitter));
+            }
+        }
+    }
+}
+
+fn report(config: &MaintenanceConfig, task: MaintenanceTask, result: Result<i64, Error>) {
+    match &result {
+        Ok(count) => debug!("Store maintenance {:?} completed: {}", task, count),
+        Err(err) => warn!("Store maintenance {:?} failed: {}", task, err),
+    }
+    if let Some(cb) = config.on_complete.as_ref() {
+        cb(task, &result);
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::default();
+    }
+    let mut buf = [0u8; 8];
+    fill_random(&mut buf);
+    Duration::from_millis(u64::from_le_bytes(buf) % (max_ms + 1))
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::error::ErrorKind;
+
+/// A receiver for measurements of store operations, such as an exporter
+/// for a monitoring system
+///
+/// Methods are called inline with the store operations and should not block.
+/// The default implementations discard the measurements.
+pub trait Metrics: Debug + Send + Sync {
+    /// Record the completion of a store operation
+    fn record_operation(&self, _record: &OperationRecord) {}
+
+    /// Record the time spent waiting to acquire a database connection or lock
+    fn record_wait(&self, _kind: WaitKind, _duration: Duration) {}
+
+    /// Record a lookup in the record cache, when enabled
+    fn record_cache(&self, _hit: bool) {}
+
+    /// Record the commit of a transaction which wrote to the store
+    fn record_commit(&self, _record: &CommitRecord) {}
+}
+
+/// The measurements of a single store operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OperationRecord {
+    /// The name of the operation, such as `fetch` or `insert`
+    pub operation: &'static str,
+    /// The time taken to complete the operation
+    pub duration: Duration,
+    /// The kind of error produced by the operation, if it failed
+    pub error: Option<ErrorKind>,
+    /// The number of records returned or affected, where known
+    pub rows: Option<i64>,
+}
+
+/// The measurements of a single committed transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitRecord {
+    /// The operation performing the transaction, such as `insert`, `apply_batch`
+    /// or `migrate`, or `transaction` for a session transaction
+    pub operation: &'static str,
+    /// The time taken by the commit itself
+    pub duration: Duration,
+    /// The number of write statements executed within the transaction
+    pub statements: u64,
+    /// The number of bytes of (encrypted) record values written
+    pub value_bytes: u64,
+}
+
+/// A resource acquired by a store operation before it can proceed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WaitKind {
+    /// A connection from the connection pool
+    Connection,
+    /// The database lock taken when starting a transaction
+    Lock,
+}
+
+/// Aggregate timings for a set of events
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DurationStats {
+    /// The number of events
+    pub count: u64,
+    /// The total duration of all events
+    pub total: Duration,
+    /// The longest duration of a single event
+    pub max: Duration,
+}
+
+impl DurationStats {
+    fn add(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// Get the mean duration of the events
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::default()
+        } else {
+            Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+        }
+    }
+}
+
+/// Aggregate measurements for a single kind of store operation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationStats {
+    /// The timings of all completed operations
+    pub duration: DurationStats,
+    /// The number of failed operations for each kind of error
+    pub errors: Vec<(ErrorKind, u64)>,
+    /// The total number of records returned or affected
+    pub rows: i64,
+}
+
+impl OperationStats {
+    /// Get the total number of failed operations
+    pub fn error_count(&self) -> u64 {
+        self.errors.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// The number of buckets in the latency histogram of `CommitStats`
+pub const COMMIT_LATENCY_BUCKETS: usize = 32;
+
+/// Aggregate measurements for the transactions committed by a single kind of
+/// store operation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitStats {
+    /// The timings of all commits
+    pub duration: DurationStats,
+    /// The number of commits by latency, where bucket `n` counts the commits
+    /// taking less than `2^n` microseconds (and at least `2^(n-1)`). The last
+    /// bucket also counts any longer commits
+    pub latency_buckets: [u64; COMMIT_LATENCY_BUCKETS],
+    /// The total number of write statements executed
+    pub statements: u64,
+    /// The total number of bytes of record values written
+    pub value_bytes: u64,
+}
+
+impl CommitStats {
+    fn add(&mut self, record: &CommitRecord) {
+        self.duration.add(record.duration);
+        let micros = record.duration.as_micros();
+        let bucket = (128 - micros.leading_zeros()) as usize;
+        self.latency_buckets[bucket.min(COMMIT_LATENCY_BUCKETS - 1)] += 1;
+        self.statements += record.statements;
+        self.value_bytes += record.value_bytes;
+    }
+
+    /// Get the mean number of write statements per transaction
+    pub fn mean_statements(&self) -> f64 {
+        if self.duration.count == 0 {
+            0.0
+        } else {
+            self.statements as f64 / self.duration.count as f64
+        }
+    }
+
+    /// Estimate a percentile of the commit latency, such as `0.95`, as the upper
+    /// bound of the histogram bucket containing it. The estimate is at most
+    /// twice the actual latency, and is limited by the longest commit recorded
+    pub fn latency_percentile(&self, percentile: f64) -> Duration {
+        let rank = (percentile.max(0.0).min(1.0) * self.duration.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.latency_buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return Duration::from_micros(1 << bucket).min(self.duration.max);
+            }
+        }
+        self.duration.max
+    }
+}
+
+/// A snapshot of the measurements collected by `InMemoryMetrics`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The measurements for each operation name
+    pub operations: BTreeMap<&'static str, OperationStats>,
+    /// The transactions committed by each operation name
+    pub commits: BTreeMap<&'static str, CommitStats>,
+    /// The time spent waiting for each kind of resource
+    pub waits: BTreeMap<WaitKind, DurationStats>,
+    /// The number of fetches answered from the record cache
+    pub cache_hits: u64,
+    /// The number of fetches which were not found in the record cache
+    pub cache_misses: u64,
+}
+
+/// A `Metrics` implementation which aggregates measurements in memory,
+/// for use in tests and debugging
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl InMemoryMetrics {
+    /// Create a new, empty instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of the measurements collected so far
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.lock().clone()
+    }
+
+    /// Discard the measurements collected so far
+    pub fn reset(&self) {
+        *self.lock() = MetricsSnapshot::default();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MetricsSnapshot> {
+        // the snapshot is left consistent if another thread panicked while holding the lock
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn record_operation(&self, record: &OperationRecord) {
+        let mut inner = self.lock();
+        let stats = inner.operations.entry(record.operation).or_default();
+        stats.duration.add(record.duration);
+        stats.rows += record.rows.unwrap_or(0);
+        if let Some(kind) = record.error {
+            match stats.errors.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, count)) => *count += 1,
+                None => stats.errors.push((kind, 1)),
+            }
+        }
+    }
+
+    fn record_wait(&self, kind: WaitKind, duration: Duration) {
+        self.lock().waits.entry(kind).or_default().add(duration);
+    }
+
+    fn record_cache(&self, hit: bool) {
+        let mut inner = self.lock();
+        if hit {
+            inner.cache_hits += 1;
+        } else {
+            inner.cache_misses += 1;
+        }
+    }
+
+    fn record_commit(&self, record: &CommitRecord) {
+        self.lock()
+            .commits
+            .entry(record.operation)
+            .or_default()
+            .add(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_latency_percentile() {
+        let metrics = InMemoryMetrics::new();
+        for micros in (1..=100).map(|n| n * 10) {
+            metrics.record_commit(&CommitRecord {
+                operation: "insert",
+                duration: Duration::from_micros(micros),
+                statements: 2,
+                value_bytes: 5,
+            });
+        }
+        let snapshot = metrics.snapshot();
+        let stats = &snapshot.commits["insert"];
+        assert_eq!(stats.duration.count, 100);
+        assert_eq!(stats.statements, 200);
+        assert_eq!(stats.value_bytes, 500);
+        assert_eq!(stats.mean_statements(), 2.0);
+        // the 95th commit takes 950us, falling in the bucket below 1024us
+        assert_eq!(stats.latency_percentile(0.95), Duration::from_micros(1000));
+        assert_eq!(stats.latency_percentile(0.5), Duration::from_micros(512));
+        assert_eq!(
+            CommitStats::default().latency_percentile(0.95),
+            Duration::default()
+        );
+    }
+}
@@ -1,11 +1,100 @@
+#[cfg(not(target_arch = "wasm32"))]
+mod audit;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::audit::{
+    AuditCancel, DecryptionAudit, DecryptionAuditOptions, DecryptionFailure, AUDIT_BATCH_SIZE,
+};
+
+mod batch;
+pub use self::batch::{BatchResult, BatchUpdate};
+
+mod cursor;
+pub use self::cursor::{ResumableScan, ScanCursor};
+
 mod entry;
-pub(crate) use self::entry::{EncEntryTag, EntryTagSet};
-pub use self::entry::{Entry, EntryKind, EntryOperation, EntryTag, Scan, TagFilter};
+pub(crate) use self::entry::{
+    decode_int_tag_value, encode_int_tag_value, EncEntryTag, EntryTagSet, TagValueType,
+};
+pub use self::entry::{
+    ConditionalFetch, Entry, EntryKind, EntryOperation, EntryTag, ExpiryFilter, ExpiryUpdate,
+    FilteredFetch, IntTagValue, QueryHint, Scan, TagFilter, TagRetrieval, TaggedEntry,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod export;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::export::{
+    CategorySpec, ExportedCategory, ImportConflict, JsonlImportReport, SkippedLine,
+    JSONL_IMPORT_BATCH_SIZE,
+};
+
+mod ids;
+pub use self::ids::{IdSource, SequentialIds};
+
+#[cfg(all(feature = "wasm-storage", target_arch = "wasm32"))]
+mod indexeddb;
+#[cfg(all(feature = "wasm-storage", target_arch = "wasm32"))]
+pub use self::indexeddb::IndexedDbStore;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod indy;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::indy::{IndyImportReport, IndyRecord, SkippedRecord, INDY_IMPORT_BATCH_SIZE};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod ingest;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::ingest::{IngestConfig, IngestResult, IngestSink, DEFAULT_INGEST_BATCH_SIZE};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod kv;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::kv::{KeySplitter, KeyValue, KvAdapter, SeparatorSplitter, KEY_SEPARATOR};
+
+mod kv_store;
+pub use self::kv_store::KvStore;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod maintenance;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::maintenance::{
+    MaintenanceCallback, MaintenanceConfig, MaintenanceHandle, MaintenanceTask,
+};
+
+mod metrics;
+pub use self::metrics::{
+    CommitRecord, CommitStats, DurationStats, InMemoryMetrics, Metrics, MetricsSnapshot,
+    OperationRecord, OperationStats, WaitKind, COMMIT_LATENCY_BUCKETS,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod namespace;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::namespace::{NamespacedSession, NamespacedStore, NAMESPACE_SEPARATOR};
+
+mod normalize;
+pub use self::normalize::{NameCollision, NameNormalization, NormalizeReport};
 
 mod options;
 pub(crate) use self::options::{IntoOptions, Options};
 
+mod stats;
+pub(crate) use self::stats::ScanTracker;
+pub use self::stats::{
+    AccessEvent, AccessLogEntry, EntryAccess, EntryVersion, HealthStatus, IntegrityReport,
+    ProfileQuota, ProfileUsage, ScanInfo, StoreDiagnostics, StoreInfo, TagStats,
+    TAG_STATS_DISTINCT_LIMIT,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
 mod store;
+#[cfg(not(target_arch = "wasm32"))]
 pub use self::store::{Session, Store};
 
+#[cfg(not(target_arch = "wasm32"))]
+mod temp;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::temp::TempScope;
+
 pub(crate) mod wql;
+pub use self::wql::dialect::WqlDialect;
@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+
+use super::entry::{Entry, EntryOperation, EntryTag, Scan, TagFilter};
+use super::store::{Session, Store};
+use crate::{
+    backend::{Backend, QueryBackend},
+    error::Error,
+};
+
+/// The separator placed between a namespace and the categories within it
+pub const NAMESPACE_SEPARATOR: &str = "::";
+
+/// A view of a store in which record categories are scoped to a namespace
+///
+/// Categories are stored with the namespace prefix (as in `didexchange::<category>`),
+/// which is removed from the entries returned. Keys are not affected by the namespace.
+#[derive(Debug)]
+pub struct NamespacedStore<'s, B: Backend> {
+    store: &'s Store<B>,
+    prefix: String,
+}
+
+impl<'s, B: Backend> NamespacedStore<'s, B> {
+    pub(crate) fn new(store: &'s Store<B>, namespace: &str) -> Result<Self, Error> {
+        check_namespace(namespace)?;
+        Ok(Self {
+            store,
+            prefix: format!("{}{}", namespace, NAMESPACE_SEPARATOR),
+        })
+    }
+
+    /// Get the prefix applied to the categories of this namespace
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Create a nested namespace within this namespace
+    pub fn namespace(&self, namespace: &str) -> Result<NamespacedStore<'s, B>, Error> {
+        check_namespace(namespace)?;
+        Ok(Self {
+            store: self.store,
+            prefix: format!("{}{}{}", self.prefix, namespace, NAMESPACE_SEPARATOR),
+        })
+    }
+
+    /// List the distinct categories of the records in the namespace, in sorted
+    /// order and without the namespace prefix
+    ///
+    /// The categories of nested namespaces are included, prefixed with the name
+    /// of the nested namespace.
+    pub async fn list_categories(&self, profile: Option<String>) -> Result<Vec<String>, Error> {
+        Ok(self
+            .store
+            .list_categories(profile)
+            .await?
+            .into_iter()
+            .filter_map(|category| {
+                category
+                    .strip_prefix(self.prefix.as_str())
+                    .map(str::to_string)
+            })
+            .collect())
+    }
+
+    /// Create a new scan instance against the namespace
+    pub async fn scan(
+        &self,
+        profile: Option<String>,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Scan<'static, Entry>, Error> {
+        let scan = self
+            .store
+            .scan(
+                profile,
+                scoped_category(&self.prefix, &category),
+                tag_filter,
+                offset,
+                limit,
+            )
+            .await?;
+        Ok(scan.map_rows(move |entry| unscoped_entry(&category, entry)))
+    }
+
+    /// Create a new scan over the records in a category of the namespace whose
+    /// names begin with `name_prefix`
+    pub async fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Scan<'static, Entry>, Error> {
+        let scan = self
+            .store
+            .scan_prefix(
+                profile,
+                scoped_category(&self.prefix, &category),
+                name_prefix,
+                tag_filter,
+                offset,
+                limit,
+            )
+            .await?;
+        Ok(scan.map_rows(move |entry| unscoped_entry(&category, entry)))
+    }
+
+    /// Fetch the most recently added records from each of a set of categories
+    /// in the namespace
+    pub async fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        categories: Vec<&str>,
+        tag_filter: Option<TagFilter>,
+        per_category_limit: i64,
+    ) -> Result<BTreeMap<String, Vec<Entry>>, Error> {
+        let scoped = categories
+            .iter()
+            .map(|category| scoped_category(&self.prefix, category))
+            .collect::<Vec<_>>();
+        let groups = self
+            .store
+            .scan_grouped(
+                profile,
+                scoped.iter().map(String::as_str).collect(),
+                tag_filter,
+                per_category_limit,
+            )
+            .await?;
+        Ok(groups
+            .into_iter()
+            .map(|(category, entries)| {
+                let category = category[self.prefix.len()..].to_string();
+                let entries = entries
+                    .into_iter()
+                    .map(|entry| unscoped_entry(&category, entry))
+                    .collect();
+                (category, entries)
+            })
+            .collect())
+    }
+
+    /// Create a new session against the namespace
+    pub async fn session(
+        &self,
+        profile: Option<String>,
+    ) -> Result<NamespacedSession<B::Session>, Error> {
+        Ok(NamespacedSession::new(
+            self.store.session(profile).await?,
+            self.prefix.clone(),
+        ))
+    }
+
+    /// Create a new transaction session against the namespace
+    pub async fn transaction(
+        &self,
+        profile: Option<String>,
+    ) -> Result<NamespacedSession<B::Session>, Error> {
+        Ok(NamespacedSession::new(
+            self.store.transaction(profile).await?,
+            self.prefix.clone(),
+        ))
+    }
+}
+
+/// An active connection to the store backend, scoped to a namespace
+#[derive(Debug)]
+pub struct NamespacedSession<Q: QueryBackend> {
+    session: Session<Q>,
+    prefix: String,
+}
+
+impl<Q: QueryBackend> NamespacedSession<Q> {
+    pub(crate) fn new(session: Session<Q>, prefix: String) -> Self {
+        Self { session, prefix }
+    }
+
+    /// Access the underlying session, which is not scoped to the namespace
+    pub fn inner_mut(&mut self) -> &mut Session<Q> {
+        &mut self.session
+    }
+
+    /// Count the number of entries for a given record category
+    pub async fn count(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<i64, Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session.count(&category, tag_filter).await
+    }
+
+    /// Retrieve the current record at `(category, name)`
+    pub async fn fetch(
+        &mut self,
+        category: &str,
+        name: &str,
+        for_update: bool,
+    ) -> Result<Option<Entry>, Error> {
+        let scoped = scoped_category(&self.prefix, category);
+        Ok(self
+            .session
+            .fetch(&scoped, name, for_update)
+            .await?
+            .map(|entry| unscoped_entry(category, entry)))
+    }
+
+    /// Retrieve all records matching the given `category` and `tag_filter`
+    pub async fn fetch_all(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+        limit: Option<i64>,
+        for_update: bool,
+    ) -> Result<Vec<Entry>, Error> {
+        let scoped = scoped_category(&self.prefix, category);
+        Ok(self
+            .session
+            .fetch_all(&scoped, tag_filter, limit, for_update)
+            .await?
+            .into_iter()
+            .map(|entry| unscoped_entry(category, entry))
+            .collect())
+    }
+
+    /// Fetch the current record at `(category, name)` and remove it from the store
+    pub async fn take(&mut self, category: &str, name: &str) -> Result<Option<Entry>, Error> {
+        let scoped = scoped_category(&self.prefix, category);
+        Ok(self
+            .session
+            .take(&scoped, name)
+            .await?
+            .map(|entry| unscoped_entry(category, entry)))
+    }
+
+    /// Fetch the oldest record matching the given `category` and `tag_filter`
+    /// and remove it from the store
+    pub async fn take_first(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<Option<Entry>, Error> {
+        let scoped = scoped_category(&self.prefix, category);
+        Ok(self
+            .session
+            .take_first(&scoped, tag_filter)
+            .await?
+            .map(|entry| unscoped_entry(category, entry)))
+    }
+
+    /// Insert a new record into the store
+    pub async fn insert(
+        &mut self,
+        category: &str,
+        name: &str,
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session
+            .insert(&category, name, value, tags, expiry_ms)
+            .await
+    }
+
+    /// Remove a record from the store
+    pub async fn remove(&mut self, category: &str, name: &str) -> Result<(), Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session.remove(&category, name).await
+    }
+
+    /// Replace the value and tags of a record in the store
+    pub async fn replace(
+        &mut self,
+        category: &str,
+        name: &str,
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session
+            .replace(&category, name, value, tags, expiry_ms)
+            .await
+    }
+
+    /// Remove all records in the store matching a given `category` and `tag_filter`
+    pub async fn remove_all(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<i64, Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session.remove_all(&category, tag_filter).await
+    }
+
+    /// Perform a record update
+    pub async fn update(
+        &mut self,
+        operation: EntryOperation,
+        category: &str,
+        name: &str,
+        value: Option<&[u8]>,
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        self.update_typed(operation, category, name, value, None, tags, expiry_ms)
+            .await
+    }
+
+    /// Perform a record update along with a value type
+    pub async fn update_typed(
+        &mut self,
+        operation: EntryOperation,
+        category: &str,
+        name: &str,
+        value: Option<&[u8]>,
+        value_type: Option<&str>,
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        let category = scoped_category(&self.prefix, category);
+        self.session
+            .update_typed(
+                operation, &category, name, value, value_type, tags, expiry_ms,
+            )
+            .await
+    }
+
+    /// Commit the pending transaction
+    pub async fn commit(self) -> Result<(), Error> {
+        self.session.commit().await
+    }
+
+    /// Roll back the pending transaction
+    pub async fn rollback(self) -> Result<(), Error> {
+        self.session.rollback().await
+    }
+}
+
+fn check_namespace(namespace: &str) -> Result<(), Error> {
+    if namespace.is_empty() {
+        return Err(err_msg!(Input, "Namespace must not be empty"));
+    }
+    if namespace.contains(NAMESPACE_SEPARATOR) {
+        return Err(err_msg!(
+            Input,
+            "Namespace must not contain the separator '{}'",
+            NAMESPACE_SEPARATOR
+        ));
+    }
+    Ok(())
+}
+
+#[inline]
+fn scoped_category(prefix: &str, category: &str) -> String {
+    let mut scoped = String::with_capacity(prefix.len() + category.len());
+    scoped.push_str(prefix);
+    scoped.push_str(category);
+    scoped
+}
+
+#[inline]
+fn unscoped_entry(category: &str, mut entry: Entry) -> Entry {
+    entry.category = category.to_string();
+    entry
+}
@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::Error;
+
+/// The normalization applied to the categories and names of records before
+/// they are encrypted, so that lookups may be insensitive to case.
+///
+/// The policy is selected when the store is provisioned and recorded in the
+/// store configuration, so that all readers and writers of the store agree on
+/// it. The `name_normalization` option is ignored when a store is opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NameNormalization {
+    /// Categories and names are stored as provided
+    None,
+    /// Categories and names are converted to lowercase and to Unicode
+    /// normalization form C
+    Lowercase,
+}
+
+impl NameNormalization {
+    /// Parse a normalization policy from its configuration value
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "none" => Ok(Self::None),
+            "lowercase" => Ok(Self::Lowercase),
+            _ => Err(err_msg!(
+                Unsupported,
+                "Unsupported name normalization: '{}'",
+                value
+            )),
+        }
+    }
+
+    /// The configuration value of the normalization policy
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Lowercase => "lowercase",
+        }
+    }
+
+    /// Apply the normalization policy to a record category or name
+    pub fn apply<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::None => Cow::Borrowed(value),
+            Self::Lowercase => {
+                let normalized = value.to_lowercase().nfc().collect::<String>();
+                if normalized == value {
+                    Cow::Borrowed(value)
+                } else {
+                    Cow::Owned(normalized)
+                }
+            }
+        }
+    }
+
+    /// Apply the normalization policy to a record category or name given as
+    /// bytes. Values which are not valid UTF-8 are left unchanged
+    pub fn apply_bytes<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        match std::str::from_utf8(value) {
+            Ok(value) => match self.apply(value) {
+                Cow::Borrowed(value) => Cow::Borrowed(value.as_bytes()),
+                Cow::Owned(value) => Cow::Owned(value.into_bytes()),
+            },
+            Err(_) => Cow::Borrowed(value),
+        }
+    }
+}
+
+impl Default for NameNormalization {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// A set of records whose categories and names are identical once normalized
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameCollision {
+    /// The normalized category
+    pub category: String,
+    /// The normalized name
+    pub name: String,
+    /// The original category and name of each conflicting record
+    pub records: Vec<(String, String)>,
+}
+
+/// The outcome of `Store::normalize_names`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NormalizeReport {
+    /// The number of records which were renamed
+    pub updated: i64,
+    /// The records which could not be renamed without replacing another record.
+    /// These are left unchanged
+    pub collisions: Vec<NameCollision>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_lowercase() {
+        let norm = NameNormalization::Lowercase;
+        assert!(matches!(norm.apply("did:sov:abc"), Cow::Borrowed(_)));
+        assert_eq!(norm.apply("did:sov:ABC"), "did:sov:abc");
+        // decomposed and precomposed forms are equivalent
+        assert_eq!(norm.apply("E\u{301}"), "\u{e9}");
+        assert_eq!(NameNormalization::None.apply("ABC"), "ABC");
+    }
+
+    #[test]
+    fn normalize_bytes() {
+        let norm = NameNormalization::Lowercase;
+        assert_eq!(norm.apply_bytes(b"ABC").as_ref(), b"abc");
+        assert_eq!(norm.apply_bytes(b"AB\xffC").as_ref(), b"AB\xffC");
+        assert_eq!(norm.apply_bytes(b"A\0B").as_ref(), b"a\0b");
+    }
+
+    #[test]
+    fn parse_normalization() {
+        for norm in [NameNormalization::None, NameNormalization::Lowercase].iter() {
+            assert_eq!(NameNormalization::parse(norm.as_str()).unwrap(), *norm);
+        }
+        assert!(NameNormalization::parse("upper").is_err());
+    }
+}
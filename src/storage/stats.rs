@@ -0,0 +1,363 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::SystemTime,
+};
+
+use super::entry::Entry;
+use crate::{
+    crypto::{alg::KeyAlg, buffer::SecretBytes},
+    error::Error,
+    protect::StoreKeyMethod,
+};
+
+/// The default limit on the number of active record scans of a store
+pub(crate) const DEFAULT_MAX_SCANS: usize = 256;
+
+/// A record along with its access counters, as returned by `Store::scan_coldest`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryAccess {
+    /// The record
+    pub entry: Entry,
+    /// The approximate number of times the record has been fetched
+    pub read_count: i64,
+    /// The time the access counters were last updated, in milliseconds since
+    /// the Unix epoch
+    pub last_read_at: Option<i64>,
+}
+
+/// A prior value of a record, as returned by `Session::fetch_history`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryVersion {
+    /// The value of the record before it was replaced or removed
+    pub value: SecretBytes,
+    /// The value type hint of the record, if any
+    pub value_type: Option<String>,
+    /// The time the value was replaced or removed, in milliseconds since the
+    /// Unix epoch
+    pub replaced_at: i64,
+}
+
+/// An event recorded in the access log of a store
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessEvent {
+    /// The store was opened, or the store key or profile key could not be
+    /// verified when opening the store
+    Open,
+    /// The store was closed
+    Close,
+    /// The store key was changed
+    Rekey,
+    /// A profile protected by a passphrase was unlocked
+    UnlockProfile,
+}
+
+impl AccessEvent {
+    /// Get the name of the event, as recorded in the access log
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Close => "close",
+            Self::Rekey => "rekey",
+            Self::UnlockProfile => "unlock_profile",
+        }
+    }
+
+    /// Parse an event from its name in the access log
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "open" => Ok(Self::Open),
+            "close" => Ok(Self::Close),
+            "rekey" => Ok(Self::Rekey),
+            "unlock_profile" => Ok(Self::UnlockProfile),
+            _ => Err(err_msg!(
+                Unexpected,
+                "Unknown access log event: '{}'",
+                value
+            )),
+        }
+    }
+}
+
+/// An entry of the access log of a store, as returned by `Store::read_access_log`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessLogEntry {
+    /// The sequence number of the entry
+    pub id: i64,
+    /// The time of the event, in milliseconds since the Unix epoch
+    pub logged_at: i64,
+    /// The event recorded
+    pub event: AccessEvent,
+    /// The client label configured by the caller when opening the store, if any
+    pub client: Option<String>,
+    /// The profile involved in the event, if any
+    pub profile: Option<String>,
+    /// Whether the operation succeeded. A failed `Open` event indicates that the
+    /// store key or profile key could not be verified
+    pub success: bool,
+}
+
+/// The outcome of a store integrity check
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The profiles without a stored profile key, with the number of records
+    /// in each which can no longer be decrypted
+    pub missing_keys: Vec<(i64, i64)>,
+    /// The identifiers of removed profiles which are still referenced by records,
+    /// with the number of orphaned records for each
+    pub orphaned_items: Vec<(i64, i64)>,
+    /// The number of tags belonging to records which no longer exist, as
+    /// removed by `Store::cleanup_orphans`
+    pub orphaned_tags: i64,
+}
+
+impl IntegrityReport {
+    /// Check whether no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.missing_keys.is_empty() && self.orphaned_items.is_empty() && self.orphaned_tags == 0
+    }
+}
+
+/// Limits on the records held by a profile, as set by `Store::set_profile_quota`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProfileQuota {
+    /// The maximum number of records
+    pub max_items: Option<i64>,
+    /// The maximum total size of the stored record values, in bytes
+    pub max_bytes: Option<i64>,
+}
+
+/// The records held by a profile, as returned by `Store::profile_usage`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProfileUsage {
+    /// The number of records, including expired records not yet purged
+    pub items: i64,
+    /// The total size of the stored (encrypted) record values, in bytes
+    pub bytes: i64,
+    /// The limits configured for the profile
+    pub quota: ProfileQuota,
+}
+
+/// The maximum number of distinct values counted for a tag by `Store::tag_stats`
+pub const TAG_STATS_DISTINCT_LIMIT: i64 = 10_000;
+
+/// Usage statistics for a tag name within a record category
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagStats {
+    /// The tag name
+    pub name: String,
+    /// The number of tag values stored under the tag name
+    pub count: i64,
+    /// The number of tag values stored as plaintext
+    pub plaintext_count: i64,
+    /// The number of distinct plaintext values, up to `TAG_STATS_DISTINCT_LIMIT`.
+    /// This is not reported for encrypted tag values
+    pub distinct_values: Option<i64>,
+    /// The average length in bytes of the plaintext values
+    pub avg_value_len: Option<f64>,
+}
+
+impl TagStats {
+    /// The proportion of the tag values which are stored as plaintext
+    pub fn plaintext_ratio(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.plaintext_count as f64 / self.count as f64
+        }
+    }
+}
+
+/// The state of a record scan which has not yet completed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanInfo {
+    /// An identifier for the scan, unique within the store
+    pub id: u64,
+    /// The time the scan was started
+    pub started_at: SystemTime,
+    /// The number of pages of results fetched so far
+    pub pages: usize,
+    /// The number of records fetched so far
+    pub rows: usize,
+    /// The largest total size in bytes of the record values in a single page
+    pub max_page_bytes: usize,
+}
+
+/// A snapshot of the runtime state of a store, as returned by `Store::diagnostics`.
+///
+/// No key material, record names or other encrypted values are included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreDiagnostics {
+    /// The record scans which have not yet completed or been dropped
+    pub active_scans: Vec<ScanInfo>,
+    /// The limit on the number of active record scans
+    pub max_scans: usize,
+    /// The number of open database connections
+    pub pool_size: u32,
+    /// The number of open connections not currently in use
+    pub pool_idle: usize,
+    /// The number of open connections of the separate pool used for scans, if any
+    pub read_pool_size: Option<u32>,
+    /// The number of profile keys held in the key cache
+    pub cached_profiles: usize,
+}
+
+/// The outcome of a health check of a store, as returned by `Store::health`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The store is available and its keys and schema were verified
+    Healthy,
+    /// The store is available but did not respond promptly
+    Degraded(String),
+    /// The store is unavailable or could not be verified
+    Unhealthy(String),
+}
+
+impl HealthStatus {
+    /// Check whether the store is available, although possibly degraded
+    pub fn is_available(&self) -> bool {
+        !matches!(self, Self::Unhealthy(_))
+    }
+}
+
+/// A description of a store and the parameters it was provisioned with, as
+/// returned by `Store::info`.
+///
+/// Only the method used to protect the store key is reported, never the key
+/// itself or the parameters used to derive it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreInfo {
+    /// The type of backend, such as `sqlite` or `postgres`
+    pub backend: String,
+    /// The version of the database schema
+    pub schema_version: String,
+    /// The method used to protect the store key
+    pub store_key_method: StoreKeyMethod,
+    /// The algorithm used to encrypt the records of each profile
+    pub encryption: KeyAlg,
+    /// The profile used when none is specified on opening the store
+    pub default_profile: String,
+    /// The number of profiles in the store
+    pub profile_count: i64,
+    /// The unique identifier of the store, generated when it is provisioned.
+    /// A snapshot of the store keeps the same ID
+    pub store_id: String,
+    /// The journal mode of the database, where applicable. Write-ahead logging
+    /// is in effect when this is `wal`
+    pub journal_mode: Option<String>,
+}
+
+/// Keeps track of the active record scans of a store
+#[derive(Debug)]
+pub struct ScanTracker {
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, ScanInfo>>,
+    max_active: usize,
+}
+
+impl Default for ScanTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SCANS)
+    }
+}
+
+impl ScanTracker {
+    /// Create a new tracker, limited to a number of active scans
+    pub fn new(max_active: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            active: Mutex::new(BTreeMap::new()),
+            max_active,
+        }
+    }
+
+    /// Accessor for the limit on the number of active scans
+    pub fn max_active(&self) -> usize {
+        self.max_active
+    }
+
+    /// Register a new scan, which remains active until the handle is dropped.
+    ///
+    /// A `ResourceLimit` error is returned when the limit on the number of
+    /// active scans has been reached.
+    pub fn start(self: &Arc<Self>) -> Result<ScanHandle, Error> {
+        let mut active = self.lock();
+        if active.len() >= self.max_active {
+            return Err(err_msg!(
+                ResourceLimit,
+                "The limit of {} active scans has been reached: scans which are no longer \
+                needed must be dropped, or freed using askar_scan_free",
+                self.max_active
+            ));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        active.insert(
+            id,
+            ScanInfo {
+                id,
+                started_at: SystemTime::now(),
+                pages: 0,
+                rows: 0,
+                max_page_bytes: 0,
+            },
+        );
+        Ok(ScanHandle {
+            tracker: self.clone(),
+            id,
+        })
+    }
+
+    /// List the active scans in the order they were started
+    pub fn active(&self) -> Vec<ScanInfo> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Check whether a thread panicked while updating the active scans. The
+    /// map remains usable, but the store is reported as degraded
+    pub fn is_poisoned(&self) -> bool {
+        self.active.is_poisoned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, ScanInfo>> {
+        // the map is left consistent if another thread panicked while holding the lock
+        self.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn panic_while_locked(&self) {
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = self.lock();
+            panic!("Scan tracker test panic");
+        }));
+    }
+}
+
+/// The registration of an active scan with a `ScanTracker`
+#[derive(Debug)]
+pub struct ScanHandle {
+    tracker: Arc<ScanTracker>,
+    id: u64,
+}
+
+impl ScanHandle {
+    /// Record a page of results fetched by the scan, along with the total size
+    /// of the record values
+    pub fn record_page(&self, rows: usize, bytes: usize) {
+        if let Some(info) = self.tracker.lock().get_mut(&self.id) {
+            info.pages += 1;
+            info.rows += rows;
+            info.max_page_bytes = info.max_page_bytes.max(bytes);
+        }
+    }
+}
+
+impl Drop for ScanHandle {
+    fn drop(&mut self) {
+        self.tracker.lock().remove(&self.id);
+    }
+}
@@ -1,13 +1,49 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufRead;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use super::entry::{Entry, EntryKind, EntryOperation, EntryTag, Scan, TagFilter};
+use async_stream::try_stream;
+use chrono::Utc;
+use futures_lite::io::AsyncWrite;
+
+use super::audit::{audit_decryption, AuditCancel, DecryptionAudit, DecryptionAuditOptions};
+use super::batch::{BatchResult, BatchUpdate};
+use super::cursor::{ResumableScan, ScanCursor};
+use super::entry::{
+    ConditionalFetch, Entry, EntryKind, EntryOperation, EntryTag, ExpiryFilter, ExpiryUpdate,
+    FilteredFetch, QueryHint, Scan, TagFilter, TagRetrieval, TaggedEntry,
+};
+use super::export::{
+    export_jsonl, export_jsonl_partial, import_jsonl, CategorySpec, ImportConflict,
+    JsonlImportReport,
+};
+use super::indy::{import_indy_wallet, IndyImportReport, IndyRecord};
+use super::ingest::{IngestConfig, IngestSink};
+use super::kv::KvAdapter;
+use super::maintenance::{MaintenanceConfig, MaintenanceHandle};
+use super::namespace::NamespacedStore;
+use super::normalize::{NameCollision, NameNormalization, NormalizeReport};
+use super::stats::{
+    AccessLogEntry, EntryAccess, EntryVersion, HealthStatus, IntegrityReport, ProfileQuota,
+    ProfileUsage, StoreDiagnostics, StoreInfo, TagStats,
+};
 use crate::{
     backend::{Backend, QueryBackend},
-    error::Error,
-    kms::{KeyEntry, KeyParams, KmsCategory, LocalKey},
-    protect::{PassKey, StoreKeyMethod},
+    error::{Error, ErrorKind},
+    kms::{
+        crypto_box_seal, crypto_box_seal_open, KeyEntry, KeyInfo, KeyParams, KmsCategory, LocalKey,
+    },
+    protect::{parse_raw_store_key, EncryptionPolicy, PassKey, StoreKeyMethod},
 };
 
+// the encrypted tag of an alias record holding the name of its target record
+const ALIAS_TARGET_TAG: &str = "target";
+
+// the nominal page size of a scan across categories, as used by the backends
+const CATEGORY_SCAN_PAGE_SIZE: usize = 32;
+
 #[derive(Debug)]
 /// An instance of an opened store
 pub struct Store<B: Backend>(B);
@@ -17,7 +53,7 @@ impl<B: Backend> Store<B> {
         Self(inner)
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "sqlite"))]
     #[allow(unused)]
     pub(crate) fn inner(&self) -> &B {
         &self.0
@@ -43,9 +79,120 @@ impl<B: Backend> Store<B> {
         Ok(self.0.rekey_backend(method, pass_key).await?)
     }
 
+    /// Export the key of a profile, sealed to an X25519 recovery public key
+    ///
+    /// The envelope may be opened with the corresponding secret key by
+    /// `import_profile_key`, restoring access to the profile's records if the
+    /// store key is lost.
+    pub async fn export_profile_key(
+        &self,
+        profile: Option<String>,
+        recovery_key: &LocalKey,
+    ) -> Result<Vec<u8>, Error> {
+        let key = self.0.export_profile_key(profile).await?;
+        crypto_box_seal(recovery_key, key.as_ref())
+    }
+
+    /// Restore the key of a profile from an envelope created by `export_profile_key`
+    ///
+    /// The key is stored under the current store key, creating the profile if
+    /// necessary. A key which can already be decrypted is only replaced when
+    /// `force` is set.
+    pub async fn import_profile_key(
+        &self,
+        profile: String,
+        recovery_key: &LocalKey,
+        envelope: &[u8],
+        force: bool,
+    ) -> Result<(), Error> {
+        let key = crypto_box_seal_open(recovery_key, envelope)?;
+        Ok(self.0.import_profile_key(profile, key, force).await?)
+    }
+
+    /// Protect a profile with its own passphrase, so that its key is no longer
+    /// wrapped by the store key
+    ///
+    /// Once the store is reopened, the profile must be unlocked with
+    /// `unlock_profile` before it can be accessed, otherwise operations fail
+    /// with a `Locked` error. The default profile cannot be protected.
+    pub async fn protect_profile(
+        &self,
+        profile: String,
+        method: StoreKeyMethod,
+        pass_key: PassKey<'_>,
+    ) -> Result<(), Error> {
+        Ok(self.0.protect_profile(profile, method, pass_key).await?)
+    }
+
+    /// Unlock a profile protected by a passphrase, loading its key into memory.
+    /// The records of the profile are first added to any index which was
+    /// enabled while it was locked.
+    pub async fn unlock_profile(
+        &self,
+        profile: String,
+        pass_key: PassKey<'_>,
+    ) -> Result<(), Error> {
+        Ok(self.0.unlock_profile(profile, pass_key).await?)
+    }
+
+    /// Drop the key of a profile from memory, returning whether it was loaded
+    ///
+    /// A profile protected by a passphrase must be unlocked again before use.
+    pub async fn lock_profile(&self, profile: String) -> Result<bool, Error> {
+        Ok(self.0.lock_profile(profile).await?)
+    }
+
+    /// Limit the number of records held by a profile, or the total size of
+    /// their stored values, replacing any prior limits
+    ///
+    /// Inserts and replacements which would take the profile over a limit fail
+    /// with a `QuotaExceeded` error. Within a transaction, the transaction
+    /// should be rolled back after such an error. Existing records are not
+    /// affected by lowering a limit. A limit on the size of stored values is
+    /// not supported by a SQLite store with an external blob store.
+    pub async fn set_profile_quota(
+        &self,
+        profile: Option<String>,
+        quota: ProfileQuota,
+    ) -> Result<(), Error> {
+        Ok(self.0.set_profile_quota(profile, quota).await?)
+    }
+
+    /// Fetch the number and size of the records held by a profile, along with
+    /// its configured quota
+    pub async fn profile_usage(&self, profile: Option<String>) -> Result<ProfileUsage, Error> {
+        Ok(self.0.profile_usage(profile).await?)
+    }
+
+    /// Recompute the usage counters of all profiles from the stored records
+    pub async fn rebuild_usage(&self) -> Result<(), Error> {
+        Ok(self.0.rebuild_usage().await?)
+    }
+
+    /// Discard all records held in the record cache, if it has been enabled
+    ///
+    /// The cache cannot observe writes made by other processes sharing the
+    /// database, which may call for the cache to be cleared.
+    pub fn clear_cache(&self) {
+        self.0.clear_cache()
+    }
+
     /// Create a new profile with the given profile name
     pub async fn create_profile(&self, name: Option<String>) -> Result<String, Error> {
-        Ok(self.0.create_profile(name).await?)
+        self.create_profile_with_policy(name, EncryptionPolicy::default())
+            .await
+    }
+
+    /// Create a new profile with the given profile name, selecting which parts
+    /// of its entries are encrypted
+    ///
+    /// The policy cannot be changed once the profile has been created.
+    pub async fn create_profile_with_policy(
+        &self,
+        name: Option<String>,
+        policy: EncryptionPolicy,
+    ) -> Result<String, Error> {
+        Ok(self.0.create_profile(name, policy).await?)
     }
 
     /// Remove an existing profile with the given profile name
@@ -53,9 +200,171 @@ impl<B: Backend> Store<B> {
         Ok(self.0.remove_profile(name).await?)
     }
 
+    /// List the names of the profiles in the store, in sorted order
+    pub async fn list_profiles(&self) -> Result<Vec<String>, Error> {
+        Ok(self.0.list_profiles().await?)
+    }
+
+    /// Remove all items from a profile, retaining the profile itself and its keys
+    ///
+    /// When `categories` is provided, only items in those categories are removed.
+    /// Returns the number of items removed for each category.
+    pub async fn truncate_profile(
+        &self,
+        profile: Option<String>,
+        categories: Option<Vec<String>>,
+    ) -> Result<BTreeMap<String, i64>, Error> {
+        let norm = self.0.name_normalization();
+        let categories = categories.map(|categories| {
+            categories
+                .iter()
+                .map(|category| norm.apply(category).into_owned())
+                .collect()
+        });
+        Ok(self.0.truncate_profile(profile, categories).await?)
+    }
+
+    /// List the distinct categories of the records in a profile, in sorted order
+    pub async fn list_categories(&self, profile: Option<String>) -> Result<Vec<String>, Error> {
+        Ok(self.0.list_categories(profile, EntryKind::Item).await?)
+    }
+
+    /// List the distinct categories of the records in a profile which begin
+    /// with `category_prefix`, in sorted order.
+    ///
+    /// Categories are treated as hierarchical paths separated by `::`, and the
+    /// prefix must end with a separator, as in `cred::`. The category index must
+    /// first be enabled using `enable_category_index`.
+    pub async fn list_categories_prefix(
+        &self,
+        profile: Option<String>,
+        category_prefix: &str,
+    ) -> Result<Vec<String>, Error> {
+        let category_prefix = self.0.name_normalization().apply(category_prefix);
+        Ok(self
+            .0
+            .list_categories_prefix(profile, EntryKind::Item, category_prefix.into_owned())
+            .await?)
+    }
+
+    /// Export the records of a profile to `writer` in JSON Lines format.
+    ///
+    /// Each line holds a JSON object with the `category`, `name`, base64-encoded
+    /// `value`, `value_type` (when set), `tags` (with plaintext tag names prefixed
+    /// by `~`) and `expiry` (in milliseconds since the Unix epoch) of a record.
+    /// Only the listed categories are exported when `include_categories` is
+    /// provided, and expired records are skipped. `on_progress` is invoked with
+    /// the number of records written so far after each batch.
+    ///
+    /// The output is not encrypted, so the export is refused unless
+    /// `allow_plaintext` is set. Returns the number of records written. The
+    /// writer is asynchronous so that a slow destination does not stall the
+    /// executor, and a blocking writer should be wrapped in an adapter which
+    /// performs the writes on another thread.
+    pub async fn export_jsonl<W: AsyncWrite + Unpin>(
+        &self,
+        profile: Option<String>,
+        writer: W,
+        include_categories: Option<Vec<&str>>,
+        allow_plaintext: bool,
+        on_progress: impl FnMut(u64),
+    ) -> Result<u64, Error> {
+        export_jsonl(
+            self,
+            profile,
+            writer,
+            include_categories,
+            allow_plaintext,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Export selected records of a profile to `writer` in JSON Lines format.
+    ///
+    /// Each of the `categories` may restrict the exported records with a tag
+    /// filter, and may omit the record values. The records are written as by
+    /// `export_jsonl`, following a first line holding a `header` object which
+    /// records the categories and filters that were applied.
+    ///
+    /// The output is not encrypted, so the export is refused unless
+    /// `allow_plaintext` is set. Returns the number of records written.
+    pub async fn export_jsonl_partial<W: AsyncWrite + Unpin>(
+        &self,
+        profile: Option<String>,
+        writer: W,
+        categories: Vec<CategorySpec>,
+        allow_plaintext: bool,
+        on_progress: impl FnMut(u64),
+    ) -> Result<u64, Error> {
+        export_jsonl_partial(
+            self,
+            profile,
+            writer,
+            categories,
+            allow_plaintext,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Import the records of a full or partial JSON Lines export into a profile
+    ///
+    /// Records which already exist in the profile are handled according to
+    /// `conflict`. Records exported without their values cannot be imported,
+    /// and are listed as skipped in the report along with those rejected by the
+    /// store. Records are written in transactions of `JSONL_IMPORT_BATCH_SIZE`
+    /// records. The report includes the filters recorded in the header of a
+    /// partial export.
+    pub async fn import_jsonl<R: BufRead>(
+        &self,
+        profile: Option<String>,
+        reader: R,
+        conflict: ImportConflict,
+    ) -> Result<JsonlImportReport, Error> {
+        import_jsonl(self, profile, reader, conflict).await
+    }
+
+    /// Import the records of an indy-sdk wallet export file into a profile
+    ///
+    /// The export is decrypted using `export_key`, which is the passphrase or
+    /// the raw key (for a raw export) provided when the export was created.
+    /// Each record is passed to `mapping`, which may return an entry to be
+    /// inserted or `None` to exclude the record; `IndyRecord::into_entry` may be
+    /// used to import the records unchanged.
+    ///
+    /// Records are written in transactions of `INDY_IMPORT_BATCH_SIZE` records.
+    /// Records rejected by the mapping, or which already exist in the store, are
+    /// skipped and listed in the report. Records before `start_offset` are not
+    /// processed, so that an interrupted import may be resumed from the offset
+    /// included in the error message. The export is decrypted as it is read,
+    /// so the batches preceding a damaged part of the export are written
+    /// before the error is reported.
+    pub async fn import_indy_wallet(
+        &self,
+        path: impl AsRef<Path>,
+        export_key: &str,
+        profile: Option<String>,
+        mapping: impl FnMut(IndyRecord) -> Result<Option<Entry>, Error>,
+        start_offset: u64,
+    ) -> Result<IndyImportReport, Error> {
+        import_indy_wallet(
+            self,
+            path.as_ref().to_path_buf(),
+            export_key.to_string(),
+            profile,
+            mapping,
+            start_offset,
+        )
+        .await
+    }
+
     /// Create a new scan instance against the store
     ///
-    /// The result will keep an open connection to the backend until it is consumed
+    /// The result will keep an open connection to the backend until it is consumed.
+    /// The default expiry filter and index hint of the store are applied. A
+    /// `ResourceLimit` error is returned when the store already has the maximum
+    /// number of active scans, as set by the `max_scans` store parameter.
     pub async fn scan(
         &self,
         profile: Option<String>,
@@ -64,73 +373,1194 @@ impl<B: Backend> Store<B> {
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> Result<Scan<'static, Entry>, Error> {
+        self.scan_with_expiry(
+            profile,
+            category,
+            tag_filter,
+            self.0.default_expiry_filter(),
+            offset,
+            limit,
+        )
+        .await
+    }
+
+    /// Create a new scan instance against the store, selecting records
+    /// according to their expiry time
+    ///
+    /// Use `ExpiryFilter::Only` to inspect expired records before they are purged
+    pub async fn scan_with_expiry(
+        &self,
+        profile: Option<String>,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        expiry: ExpiryFilter,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Scan<'static, Entry>, Error> {
+        let category = self.0.name_normalization().apply(&category).into_owned();
+        Ok(self
+            .0
+            .scan(
+                profile,
+                EntryKind::Item,
+                category,
+                tag_filter,
+                expiry,
+                self.0.default_query_hint(),
+                offset,
+                limit,
+            )
+            .await?)
+    }
+
+    /// Create a new scan instance against the store, applying an index hint to
+    /// the tag filter.
+    ///
+    /// Use `QueryHint::PreferTagIndex` when the tag filter is expected to match
+    /// a small fraction of a large category.
+    pub async fn scan_with_hint(
+        &self,
+        profile: Option<String>,
+        category: String,
+        tag_filter: Option<TagFilter>,
+        hint: QueryHint,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Scan<'static, Entry>, Error> {
+        let category = self.0.name_normalization().apply(&category).into_owned();
+        Ok(self
+            .0
+            .scan(
+                profile,
+                EntryKind::Item,
+                category,
+                tag_filter,
+                self.0.default_expiry_filter(),
+                Some(hint),
+                offset,
+                limit,
+            )
+            .await?)
+    }
+
+    /// Create a new record scan iterator over the records in a category whose
+    /// names begin with `name_prefix`.
+    ///
+    /// Entry names are treated as hierarchical paths separated by `/`, and the
+    /// prefix must end with a separator, as in `conn/123/`. The name index must
+    /// first be enabled using `enable_name_index`.
+    pub async fn scan_prefix(
+        &self,
+        profile: Option<String>,
+        category: String,
+        name_prefix: String,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Scan<'static, Entry>, Error> {
+        let norm = self.0.name_normalization();
+        let category = norm.apply(&category).into_owned();
+        let name_prefix = norm.apply(&name_prefix).into_owned();
+        Ok(self
+            .0
+            .scan_prefix(
+                profile,
+                EntryKind::Item,
+                category,
+                name_prefix,
+                tag_filter,
+                offset,
+                limit,
+            )
+            .await?)
+    }
+
+    /// Create a new record scan iterator over the records in all categories
+    /// beginning with `category_prefix`, as listed by `list_categories_prefix`.
+    ///
+    /// The records of each category are returned in turn, in order of category.
+    pub async fn scan_category_prefix(
+        &self,
+        profile: Option<String>,
+        category_prefix: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<Scan<'_, Entry>, Error> {
+        let categories = self
+            .list_categories_prefix(profile.clone(), category_prefix)
+            .await?;
+        let stream = try_stream! {
+            for category in categories {
+                let mut scan = self
+                    .0
+                    .scan(
+                        profile.clone(),
+                        EntryKind::Item,
+                        category,
+                        tag_filter.clone(),
+                        self.0.default_expiry_filter(),
+                        self.0.default_query_hint(),
+                        None,
+                        None,
+                    )
+                    .await?;
+                while let Some(rows) = scan.fetch_next().await? {
+                    yield rows;
+                }
+            }
+        };
+        Ok(Scan::new(stream, CATEGORY_SCAN_PAGE_SIZE))
+    }
+
+    /// Create a new record scan in order of insertion, which may be resumed
+    /// by another process.
+    ///
+    /// The position of the scan is available from `ResumableScan::cursor`, and may
+    /// be persisted and passed to `scan_resume` along with the same category and
+    /// tag filter. Records inserted after the scan was started may be included
+    /// when it is resumed.
+    pub async fn scan_resumable(
+        &self,
+        profile: Option<String>,
+        category: String,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<ResumableScan, Error> {
+        let category = self.0.name_normalization().apply(&category).into_owned();
+        Ok(self
+            .0
+            .scan_resumable(profile, EntryKind::Item, category, tag_filter, None)
+            .await?)
+    }
+
+    /// Continue a record scan after the position of a cursor.
+    ///
+    /// The category and tag filter must be the same as for the original scan,
+    /// otherwise the cursor is rejected with an `Input` error. A cursor created
+    /// for another store or profile is also rejected.
+    pub async fn scan_resume(
+        &self,
+        cursor: ScanCursor,
+        category: String,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<ResumableScan, Error> {
+        let category = self.0.name_normalization().apply(&category).into_owned();
+        let profile = cursor.profile().to_string();
+        Ok(self
+            .0
+            .scan_resumable(
+                Some(profile),
+                EntryKind::Item,
+                category,
+                tag_filter,
+                Some(cursor),
+            )
+            .await?)
+    }
+
+    /// Enable the index of entry name prefixes required by `scan_prefix`.
+    ///
+    /// Existing records are added to the index, which is then maintained as
+    /// records are added and removed. The setting is persisted in the store.
+    /// The records of a profile protected by a passphrase which is locked are
+    /// added when it is next unlocked. Note that the index reveals which
+    /// records share a name prefix.
+    pub async fn enable_name_index(&self) -> Result<(), Error> {
+        Ok(self.0.enable_name_index().await?)
+    }
+
+    /// Enable the index of category prefixes required by `list_categories_prefix`
+    /// and `scan_category_prefix`.
+    ///
+    /// Existing records are added to the index, which is then maintained as
+    /// records are added and removed. The setting is persisted in the store.
+    /// The records of a profile protected by a passphrase which is locked are
+    /// added when it is next unlocked. The index is disabled by default,
+    /// because it reveals which records share a category prefix even where
+    /// their categories differ.
+    pub async fn enable_category_index(&self) -> Result<(), Error> {
+        Ok(self.0.enable_category_index().await?)
+    }
+
+    /// Enable the access counters of records required by `scan_coldest`.
+    ///
+    /// When enabled, fetching a record updates its read count and last read time
+    /// in the background without delaying the result. The counters of each record
+    /// are written at most once per minute by each store instance, so they are
+    /// approximate. The setting is persisted in the store.
+    pub async fn enable_access_tracking(&self) -> Result<(), Error> {
+        Ok(self.0.enable_access_tracking().await?)
+    }
+
+    /// Enable the retention of the prior values of records required by
+    /// `Session::fetch_history`.
+    ///
+    /// When enabled, replacing or removing a record retains its encrypted value
+    /// and value type along with the time it was replaced. At most
+    /// `history_versions` values are retained for each record, for at most
+    /// `history_retention` seconds, as set in the store URI. Values beyond these
+    /// limits are removed by `purge_expired`. The setting is persisted in the store.
+    pub async fn enable_value_history(&self) -> Result<(), Error> {
+        Ok(self.0.enable_value_history().await?)
+    }
+
+    /// Store the entity tags of record values as they are written, allowing
+    /// `Session::fetch_if_modified` to answer for an unchanged record without
+    /// decrypting its value.
+    ///
+    /// Records written before the setting is enabled have their entity tags
+    /// derived from the decrypted value. The setting is persisted in the store.
+    pub async fn enable_value_etags(&self) -> Result<(), Error> {
+        Ok(self.0.enable_value_etags().await?)
+    }
+
+    /// Write record values within an envelope, which records the version of
+    /// the format used to encrypt them.
+    ///
+    /// Until this is enabled, values are written in the headerless layout read
+    /// by prior releases, which cannot read values written within an envelope.
+    /// Existing records are not rewritten, and values in either layout remain
+    /// readable. Other instances of the store which are already open write
+    /// envelopes once they are reopened. The setting is persisted in the store.
+    pub async fn enable_value_envelopes(&self) -> Result<(), Error> {
+        Ok(self.0.enable_value_envelopes().await?)
+    }
+
+    /// Record the opening and closing of the store, changes of the store key
+    /// and the unlocking of profiles in the access log of the store, along with
+    /// the outcome of each and the client label set when the store was opened.
+    ///
+    /// A store key or profile key which cannot be verified when the store is
+    /// opened is logged as a failed `Open` event. An attempt to open the store
+    /// which fails before its configuration can be read, such as when the
+    /// database file cannot be opened, is not logged. Entries older than the
+    /// `access_log_retention` period (in seconds, as set in the store URI) are
+    /// removed by `purge_expired`. The setting is persisted in the store.
+    ///
+    /// An event which cannot be recorded after a key change, unlock or close
+    /// is reported as a warning, and the result of the operation is returned.
+    pub async fn enable_access_log(&self) -> Result<(), Error> {
+        Ok(self.0.enable_access_log().await?)
+    }
+
+    /// Fetch the most recent entries of the access log, newest first, limited
+    /// to those logged at or after `since` (in milliseconds since the Unix epoch).
+    ///
+    /// No entries are returned when the access log has not been enabled.
+    pub async fn read_access_log(
+        &self,
+        limit: Option<i64>,
+        since: Option<i64>,
+    ) -> Result<Vec<AccessLogEntry>, Error> {
+        Ok(self.0.read_access_log(limit, since).await?)
+    }
+
+    /// Get the normalization applied to record categories and names, as
+    /// selected when the store was provisioned
+    pub fn name_normalization(&self) -> NameNormalization {
+        self.0.name_normalization()
+    }
+
+    /// Rename the records of a profile according to the name normalization of
+    /// the store, within a single transaction. Records may need to be renamed
+    /// when they were written without the normalization recorded in the store
+    /// configuration, such as by a release which did not apply it.
+    ///
+    /// Records which would be given the same category and name as another
+    /// record are not merged. They are left unchanged and listed in the report
+    /// as collisions, to be resolved by the caller.
+    pub async fn normalize_names(&self, profile: Option<String>) -> Result<NormalizeReport, Error> {
+        let norm = self.0.name_normalization();
+        if norm == NameNormalization::None {
+            return Err(err_msg!(
+                Input,
+                "No name normalization is configured for the store"
+            ));
+        }
+        // the records which are already normalized, and those to be renamed
+        // indexed by their normalized category and name
+        let mut existing = BTreeSet::new();
+        let mut renamed = BTreeMap::<(String, String), Vec<Entry>>::new();
+        for category in self
+            .0
+            .list_categories(profile.clone(), EntryKind::Item)
+            .await?
+        {
+            let mut scan = self
+                .0
+                .scan(
+                    profile.clone(),
+                    EntryKind::Item,
+                    category,
+                    None,
+                    ExpiryFilter::Include,
+                    None,
+                    None,
+                )
+                .await?;
+            while let Some(rows) = scan.fetch_next().await? {
+                for entry in rows {
+                    let norm_category = norm.apply(&entry.category).into_owned();
+                    let norm_name = norm.apply(&entry.name).into_owned();
+                    if norm_category == entry.category && norm_name == entry.name {
+                        existing.insert((norm_category, norm_name));
+                    } else {
+                        renamed
+                            .entry((norm_category, norm_name))
+                            .or_default()
+                            .push(entry);
+                    }
+                }
+            }
+        }
+
+        let mut report = NormalizeReport::default();
+        let mut txn = self.0.session(profile, true)?;
+        let now_ms = Utc::now().timestamp_millis();
+        for ((category, name), mut entries) in renamed {
+            let key = (category, name);
+            let taken = existing.contains(&key);
+            let (category, name) = key;
+            if taken || entries.len() > 1 {
+                let mut records = Vec::with_capacity(entries.len() + 1);
+                if taken {
+                    records.push((category.clone(), name.clone()));
+                }
+                records.extend(
+                    entries
+                        .into_iter()
+                        .map(|entry| (entry.category, entry.name)),
+                );
+                report.collisions.push(NameCollision {
+                    category,
+                    name,
+                    records,
+                });
+                continue;
+            }
+            let entry = entries.remove(0);
+            txn.update(
+                EntryKind::Item,
+                EntryOperation::Remove,
+                entry.category.as_bytes(),
+                entry.name.as_bytes(),
+                None,
+                None,
+                None,
+                ExpiryUpdate::Clear,
+            )
+            .await?;
+            txn.update(
+                EntryKind::Item,
+                EntryOperation::Insert,
+                category.as_bytes(),
+                name.as_bytes(),
+                Some(entry.value.as_ref()),
+                entry.value_type.as_deref(),
+                Some(entry.tags.as_slice()),
+                entry.expiry.map(|expiry| expiry - now_ms).into(),
+            )
+            .await?;
+            report.updated += 1;
+        }
+        txn.close(true).await?;
+        Ok(report)
+    }
+
+    /// Fetch the records in a category with the lowest read counts, least
+    /// recently read first, for use in cache eviction
+    pub async fn scan_coldest(
+        &self,
+        profile: Option<String>,
+        category: &str,
+        limit: i64,
+    ) -> Result<Vec<EntryAccess>, Error> {
+        Ok(self
+            .0
+            .scan_coldest(
+                profile,
+                EntryKind::Item,
+                self.0.name_normalization().apply(category).into_owned(),
+                limit,
+            )
+            .await?)
+    }
+
+    /// Fetch the most recently added records from each of a set of categories
+    ///
+    /// At most `per_category_limit` records are returned for each category, most
+    /// recent first. The result contains an entry for every requested category.
+    pub async fn scan_grouped(
+        &self,
+        profile: Option<String>,
+        categories: Vec<&str>,
+        tag_filter: Option<TagFilter>,
+        per_category_limit: i64,
+    ) -> Result<BTreeMap<String, Vec<Entry>>, Error> {
+        let norm = self.0.name_normalization();
+        Ok(self
+            .0
+            .scan_grouped(
+                profile,
+                EntryKind::Item,
+                categories
+                    .into_iter()
+                    .map(|category| norm.apply(category).into_owned())
+                    .collect(),
+                tag_filter,
+                per_category_limit,
+            )
+            .await?)
+    }
+
+    /// Collect usage statistics for each tag name used in a record category.
+    ///
+    /// Distinct values and value lengths are only reported for plaintext tags.
+    /// This may be used to identify tags which inflate the size of the tag indexes
+    pub async fn tag_stats(
+        &self,
+        profile: Option<String>,
+        category: &str,
+    ) -> Result<Vec<TagStats>, Error> {
+        Ok(self
+            .0
+            .tag_stats(
+                profile,
+                EntryKind::Item,
+                self.0.name_normalization().apply(category).into_owned(),
+            )
+            .await?)
+    }
+
+    /// Apply a batch of record updates within a single transaction.
+    ///
+    /// When an `idempotency_key` is provided, it is recorded along with the
+    /// updates. A batch repeated with the same key within the retention period
+    /// of the store returns the original result without applying the updates
+    /// again, so that a retried request cannot apply a batch twice. Keys older
+    /// than the retention period are removed by `purge_expired`.
+    ///
+    /// Updates may be directed to other profiles using `BatchUpdate::with_profile`.
+    /// The keys of all the profiles are resolved before the transaction is
+    /// started, so that the batch fails without applying any updates when a
+    /// profile does not exist. The updates for each profile are applied together,
+    /// in the order they are given.
+    pub async fn apply_batch(
+        &self,
+        profile: Option<String>,
+        updates: &[BatchUpdate],
+        idempotency_key: Option<&str>,
+    ) -> Result<BatchResult, Error> {
+        let batch_profile = profile.unwrap_or_else(|| self.0.get_profile_name().to_string());
+        let mut groups = BTreeMap::<&str, Vec<&BatchUpdate>>::new();
+        for update in updates {
+            groups
+                .entry(update.profile.as_deref().unwrap_or(&batch_profile))
+                .or_default()
+                .push(update);
+        }
+        self.0.load_profile(batch_profile.clone()).await?;
+        for group_profile in groups.keys().filter(|p| **p != batch_profile) {
+            self.0.load_profile(group_profile.to_string()).await?;
+        }
+
+        let mut txn = self.transaction(Some(batch_profile.clone())).await?;
+        txn.0.set_commit_operation("apply_batch");
+        if let Some(idempotency_key) = idempotency_key {
+            if let Some(updates) = txn.0.applied_batch(idempotency_key).await? {
+                txn.rollback().await?;
+                return Ok(BatchResult {
+                    updates,
+                    replayed: true,
+                });
+            }
+        }
+        for (group_profile, group) in groups {
+            txn.0.set_profile(group_profile.to_string());
+            for update in group {
+                txn.update_typed(
+                    update.operation,
+                    &update.category,
+                    &update.name,
+                    update.value.as_deref(),
+                    update.value_type.as_deref(),
+                    update.tags.as_deref(),
+                    update.expiry_ms,
+                )
+                .await?;
+            }
+        }
+        let count = updates.len() as i64;
+        if let Some(idempotency_key) = idempotency_key {
+            // the key is recorded against the profile of the batch
+            txn.0.set_profile(batch_profile);
+            txn.0.record_batch(idempotency_key, count).await?;
+        }
+        txn.commit().await?;
+        Ok(BatchResult {
+            updates: count,
+            replayed: false,
+        })
+    }
+
+    /// Copy a record to another profile, decrypting it with the key of the source
+    /// profile and encrypting it again with the key of the destination profile.
+    ///
+    /// The value, value type, tags and expiry time of the record are copied. A
+    /// record with the same category and name in the destination profile is
+    /// replaced when `overwrite` is set, and otherwise a `Duplicate` error is
+    /// returned. A `NotFound` error is returned when the source record does not
+    /// exist or has expired.
+    pub async fn copy_entry(
+        &self,
+        src_profile: Option<String>,
+        dst_profile: Option<String>,
+        category: &str,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        self.transfer_entry(src_profile, dst_profile, category, name, overwrite, false)
+            .await
+    }
+
+    /// Move a record to another profile, as for `copy_entry`, removing the
+    /// record from the source profile along with any aliases of the record.
+    ///
+    /// The copy and the removal are performed in a single transaction. If either
+    /// one fails, such as when the destination record exists and `overwrite` is
+    /// not set, the transaction is rolled back and the source record is left
+    /// unchanged. A failure to commit the transaction likewise leaves both
+    /// profiles unchanged, so that a move may be retried.
+    pub async fn move_entry(
+        &self,
+        src_profile: Option<String>,
+        dst_profile: Option<String>,
+        category: &str,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        self.transfer_entry(src_profile, dst_profile, category, name, overwrite, true)
+            .await
+    }
+
+    async fn transfer_entry(
+        &self,
+        src_profile: Option<String>,
+        dst_profile: Option<String>,
+        category: &str,
+        name: &str,
+        overwrite: bool,
+        remove: bool,
+    ) -> Result<(), Error> {
+        let default_profile = || self.0.get_profile_name().to_string();
+        let src_profile = src_profile.unwrap_or_else(default_profile);
+        let dst_profile = dst_profile.unwrap_or_else(default_profile);
+        if src_profile == dst_profile {
+            return Err(err_msg!(
+                Input,
+                "The source and destination profiles must be different"
+            ));
+        }
+        // resolve both keys before starting the transaction
+        self.0.load_profile(src_profile.clone()).await?;
+        self.0.load_profile(dst_profile.clone()).await?;
+
+        let mut txn = self.transaction(Some(src_profile.clone())).await?;
+        let entry = txn
+            .fetch(category, name, true)
+            .await?
+            .ok_or_else(|| err_msg!(NotFound, "Entry not found"))?;
+        let now_ms = Utc::now().timestamp_millis();
+        let expiry_ms = entry.expiry.map(|expiry| expiry - now_ms);
+        txn.0.set_profile(dst_profile);
+        let inserted = txn
+            .update_typed(
+                EntryOperation::Insert,
+                &entry.category,
+                &entry.name,
+                Some(entry.value.as_ref()),
+                entry.value_type.as_deref(),
+                Some(entry.tags.as_slice()),
+                expiry_ms,
+            )
+            .await;
+        match inserted {
+            // a duplicate insert leaves the transaction usable
+            Err(err) if overwrite && err.kind() == ErrorKind::Duplicate => {
+                txn.update_typed(
+                    EntryOperation::Replace,
+                    &entry.category,
+                    &entry.name,
+                    Some(entry.value.as_ref()),
+                    entry.value_type.as_deref(),
+                    Some(entry.tags.as_slice()),
+                    expiry_ms,
+                )
+                .await?
+            }
+            result => result?,
+        }
+        if remove {
+            txn.0.set_profile(src_profile);
+            txn.remove(&entry.category, &entry.name).await?;
+        }
+        txn.commit().await
+    }
+
+    /// Remove the expired records of all profiles, returning the number removed.
+    ///
+    /// The idempotency keys recorded by `apply_batch` are also removed once
+    /// they are older than the retention period, as are the prior record values
+    /// retained by the value history beyond its limits and the entries of the
+    /// access log beyond its retention period.
+    pub async fn purge_expired(&self) -> Result<i64, Error> {
+        Ok(self.0.purge_expired().await?)
+    }
+
+    /// Check the store for profiles whose stored key has been removed, for
+    /// records belonging to profiles which no longer exist, and for tags
+    /// belonging to records which no longer exist
+    pub async fn verify_integrity(&self) -> Result<IntegrityReport, Error> {
+        Ok(self.0.verify_integrity().await?)
+    }
+
+    /// Check that the records of a profile can be decrypted, returning the
+    /// records which failed along with the error produced for each.
+    ///
+    /// The category, name, value, value type and tags of each record are
+    /// decrypted in order of record ID. A proportion of the records may be
+    /// selected for checking, and the rate of checking may be limited in order
+    /// to reduce the load on the store. When the audit is cancelled using the
+    /// `cancel` handle, the records checked so far are returned, and the audit
+    /// may be continued later by setting `resume_after` to the reported `last_id`.
+    pub async fn audit_decryption(
+        &self,
+        profile: Option<String>,
+        options: DecryptionAuditOptions,
+        cancel: Option<&AuditCancel>,
+    ) -> Result<DecryptionAudit, Error> {
+        audit_decryption(&self.0, profile, options, cancel).await
+    }
+
+    /// Remove the tags left behind by records which no longer exist, returning
+    /// the number of tags removed.
+    ///
+    /// Tags are normally removed along with their record. They may remain when
+    /// records were deleted while foreign key enforcement was disabled, such as
+    /// by direct modification of the database.
+    pub async fn cleanup_orphans(&self) -> Result<i64, Error> {
+        Ok(self.0.cleanup_orphans().await?)
+    }
+
+    /// Report the runtime state of the store: the record scans which have not
+    /// completed, the status of the connection pool and the number of cached
+    /// profile keys.
+    ///
+    /// No key material or decrypted record data is included, and the database
+    /// is not queried, so this may be called frequently from a health check.
+    pub async fn diagnostics(&self) -> Result<StoreDiagnostics, Error> {
+        Ok(self.0.diagnostics().await?)
+    }
+
+    /// Describe the store: the schema version, the method used to protect the
+    /// store key, the record encryption algorithm, the default profile and the
+    /// number of profiles.
+    ///
+    /// The information is cached after it is first loaded. Set `refresh` to read
+    /// it from the database again, for example when another instance may have
+    /// added profiles.
+    pub async fn info(&self, refresh: bool) -> Result<StoreInfo, Error> {
+        Ok(self.0.info(refresh).await?)
+    }
+
+    /// Check that a database connection can be acquired and a trivial query
+    /// run within the deadline, failing with a `Timeout` error otherwise.
+    ///
+    /// The connection is returned to the pool when the check completes, so
+    /// this may be called frequently from a liveness probe.
+    pub async fn ping(&self, deadline: Duration) -> Result<(), Error> {
+        Ok(self.0.ping(deadline).await?)
+    }
+
+    /// Check the health of the store for a readiness probe.
+    ///
+    /// In addition to the checks made by `ping`, the schema version of the
+    /// database is compared to the current version and the stored key of the
+    /// default profile is decrypted using the store key. A store which passes
+    /// these checks but takes more than half of the deadline to do so is
+    /// reported as degraded.
+    pub async fn health(&self, deadline: Duration) -> HealthStatus {
+        self.0.health(deadline).await
+    }
+
+    /// Flush pending changes to the main database file, where supported by
+    /// the backend
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        Ok(self.0.checkpoint().await?)
+    }
+
+    /// Rebuild the database to reclaim the space left unused by removed
+    /// records. The store may be unavailable to other connections until
+    /// the operation is complete
+    pub async fn vacuum(&self) -> Result<(), Error> {
+        Ok(self.0.vacuum().await?)
+    }
+
+    /// Start performing periodic maintenance of the store in the background.
+    ///
+    /// Maintenance continues until the returned handle is stopped or dropped.
+    pub fn start_maintenance(self: Arc<Self>, config: MaintenanceConfig) -> MaintenanceHandle
+    where
+        B: 'static,
+    {
+        MaintenanceHandle::start(self, config)
+    }
+
+    /// Start a bulk ingestion of record updates.
+    ///
+    /// Updates sent to the returned sink are applied in batches by a bounded
+    /// number of background workers, and a result is reported for each.
+    pub fn ingest(self: Arc<Self>, config: IngestConfig) -> Result<IngestSink<B>, Error>
+    where
+        B: 'static,
+    {
+        IngestSink::new(self, config)
+    }
+
+    /// List the keys stored in a profile, matching the given filters.
+    ///
+    /// Only non-secret key information is returned. Use `offset` and `limit`
+    /// to page through the results.
+    pub async fn list_keys(
+        &self,
+        profile: Option<String>,
+        algorithm: Option<&str>,
+        thumbprint: Option<&str>,
+        tag_filter: Option<TagFilter>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<KeyInfo>, Error> {
+        let mut scan = self
+            .0
+            .scan(
+                profile,
+                EntryKind::Kms,
+                KmsCategory::CryptoKey.as_str().to_string(),
+                key_tag_filter(algorithm, thumbprint, tag_filter),
+                ExpiryFilter::Exclude,
+                None,
+                offset,
+                limit,
+            )
+            .await?;
+        let mut keys = vec![];
+        while let Some(rows) = scan.fetch_next().await? {
+            for row in rows {
+                keys.push(KeyInfo::from(KeyEntry::from_entry(row)?));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Access the store through a namespace.
+    ///
+    /// The categories of all records accessed through the namespace are
+    /// prefixed with `<namespace>::`, so that independent components sharing a
+    /// store cannot collide. Namespaces may be nested. The namespace must not
+    /// be empty or contain the separator `::`.
+    pub fn namespace(&self, namespace: &str) -> Result<NamespacedStore<'_, B>, Error> {
+        NamespacedStore::new(self, namespace)
+    }
+
+    /// Start a scope for temporary records, which are made permanent only when
+    /// the scope is committed.
+    ///
+    /// The records are written with an expiry time of `ttl`, after which they
+    /// are removed by `purge_expired` if the scope has not been committed.
+    pub fn temp_scope(
+        &self,
+        profile: Option<String>,
+        ttl: Duration,
+    ) -> Result<TempScope<'_, B>, Error> {
+        TempScope::new(self, profile, ttl)
+    }
+
+    /// Access the records of the store through the minimal `KeyValue` interface
+    ///
+    /// Keys are split into a category and a name at the first `/` by default,
+    /// and the default profile is used unless another is selected.
+    pub fn key_value(&self) -> KvAdapter<'_, B> {
+        KvAdapter::new(self)
+    }
+
+    /// Create a new session against the store
+    pub async fn session(&self, profile: Option<String>) -> Result<Session<B::Session>, Error> {
+        // FIXME - add 'immediate' flag
+        Ok(Session::new(self.0.session(profile, false)?))
+    }
+
+    /// Create a new transaction session against the store
+    pub async fn transaction(&self, profile: Option<String>) -> Result<Session<B::Session>, Error> {
+        Ok(Session::new(self.0.session(profile, true)?))
+    }
+
+    /// Create a new session against the store which unwraps the profile key
+    /// using an externally supplied store key, such as a previous store key
+    /// recovered from a backup, in place of the key of the store.
+    ///
+    /// The `store_key` is a raw key in the format produced by
+    /// `generate_raw_store_key`. It only applies to the returned session: it is
+    /// not persisted, and the profile keys it unwraps are not made available to
+    /// other sessions.
+    pub async fn session_with_external_key(
+        &self,
+        profile: Option<String>,
+        store_key: PassKey<'_>,
+        transaction: bool,
+    ) -> Result<Session<B::Session>, Error> {
+        let store_key = parse_raw_store_key(&*store_key)?;
+        Ok(Session::new(self.0.session_with_external_key(
+            profile,
+            store_key,
+            transaction,
+        )?))
+    }
+
+    /// Close the store instance, waiting for any shutdown procedures to complete.
+    pub async fn close(self) -> Result<(), Error> {
+        Ok(self.0.close().await?)
+    }
+
+    pub(crate) async fn arc_close(self: Arc<Self>) -> Result<(), Error> {
+        Ok(self.0.close().await?)
+    }
+}
+
+/// An active connection to the store backend
+#[derive(Debug)]
+pub struct Session<Q: QueryBackend>(Q);
+
+impl<Q: QueryBackend> Session<Q> {
+    pub(crate) fn new(inner: Q) -> Self {
+        Self(inner)
+    }
+
+    pub(crate) fn backend(&mut self) -> &mut Q {
+        &mut self.0
+    }
+}
+
+impl<Q: QueryBackend> Session<Q> {
+    /// Set the maximum time to wait on the database for each query result.
+    ///
+    /// This overrides the `query_timeout` configured for the store. When a query
+    /// times out, an error of kind `Timeout` is returned and an active transaction
+    /// is rolled back
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.0.set_query_timeout(timeout)
+    }
+
+    /// Select the records returned by `count`, `fetch` and `fetch_all` according
+    /// to their expiry time.
+    ///
+    /// By default expired records are excluded, unless another default has been
+    /// configured for the store. The filter does not apply to updates and removals
+    pub fn set_expiry_filter(&mut self, expiry: ExpiryFilter) {
+        self.0.set_expiry_filter(expiry)
+    }
+
+    /// Select the index hint applied to the tag filters of subsequent queries
+    /// by `count`, `fetch_all`, `remove_all` and `take`, replacing the default
+    /// hint configured for the store
+    pub fn set_query_hint(&mut self, hint: Option<QueryHint>) {
+        self.0.set_query_hint(hint)
+    }
+
+    /// Count the number of entries for a given record category
+    pub async fn count(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<i64, Error> {
+        let category = self.0.name_normalization().apply(category);
+        Ok(self.0.count(EntryKind::Item, &category, tag_filter).await?)
+    }
+
+    /// Retrieve the current record at `(category, name)`.
+    ///
+    /// Specify `for_update` when in a transaction to create an update lock on the
+    /// associated record, if supported by the store backend
+    pub async fn fetch(
+        &mut self,
+        category: &str,
+        name: &str,
+        for_update: bool,
+    ) -> Result<Option<Entry>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        Ok(self
+            .0
+            .fetch(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                for_update,
+            )
+            .await?)
+    }
+
+    /// Retrieve the current record at `(category, name)` if it matches a tag filter.
+    ///
+    /// A record which exists but does not match the filter is reported as
+    /// `FilteredFetch::Filtered`, without decrypting its value. Use
+    /// `FilteredFetch::into_entry` to treat such a record as missing.
+    pub async fn fetch_filtered(
+        &mut self,
+        category: &str,
+        name: &str,
+        tag_filter: TagFilter,
+        for_update: bool,
+    ) -> Result<Option<FilteredFetch>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        Ok(self
+            .0
+            .fetch_filtered(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                tag_filter,
+                for_update,
+            )
+            .await?)
+    }
+
+    /// Retrieve the current record at `(category, name)` with a selection of its tags.
+    ///
+    /// Encrypted tag values are only read and decrypted for `TagRetrieval::All`,
+    /// and a boolean is accepted in place of the mode to retrieve all tags or none.
+    pub async fn fetch_with_tags(
+        &mut self,
+        category: &str,
+        name: &str,
+        tag_mode: impl Into<TagRetrieval>,
+        for_update: bool,
+    ) -> Result<Option<TaggedEntry>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        Ok(self
+            .0
+            .fetch_with_tags(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                tag_mode.into(),
+                for_update,
+            )
+            .await?)
+    }
+
+    /// Fetch a record from the store by category and name, along with the
+    /// entity tag of its value
+    ///
+    /// The entity tag is a keyed hash of the record value, which is unchanged
+    /// when the same value is written again, and may be passed to
+    /// `fetch_if_modified`.
+    pub async fn fetch_with_etag(
+        &mut self,
+        category: &str,
+        name: &str,
+        for_update: bool,
+    ) -> Result<Option<(Entry, Vec<u8>)>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        match self
+            .0
+            .fetch_conditional(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                None,
+                for_update,
+            )
+            .await?
+        {
+            Some(ConditionalFetch::Modified { entry, etag }) => Ok(Some((entry, etag))),
+            Some(ConditionalFetch::NotModified) => Err(err_msg!(
+                Unexpected,
+                "Unexpected result for unconditional fetch"
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch a record from the store by category and name, unless the entity
+    /// tag of its value matches `etag`
+    ///
+    /// When the store has value entity tags enabled, an unchanged record is
+    /// reported as `ConditionalFetch::NotModified` without decrypting its value.
+    pub async fn fetch_if_modified(
+        &mut self,
+        category: &str,
+        name: &str,
+        etag: &[u8],
+    ) -> Result<Option<ConditionalFetch>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
         Ok(self
             .0
-            .scan(
-                profile,
+            .fetch_conditional(
                 EntryKind::Item,
-                category,
-                tag_filter,
-                offset,
-                limit,
+                category.as_bytes(),
+                name.as_bytes(),
+                Some(etag),
+                false,
             )
             .await?)
     }
 
-    /// Create a new session against the store
-    pub async fn session(&self, profile: Option<String>) -> Result<Session<B::Session>, Error> {
-        // FIXME - add 'immediate' flag
-        Ok(Session::new(self.0.session(profile, false)?))
-    }
-
-    /// Create a new transaction session against the store
-    pub async fn transaction(&self, profile: Option<String>) -> Result<Session<B::Session>, Error> {
-        Ok(Session::new(self.0.session(profile, true)?))
-    }
-
-    /// Close the store instance, waiting for any shutdown procedures to complete.
-    pub async fn close(self) -> Result<(), Error> {
-        Ok(self.0.close().await?)
+    /// Retrieve the record at `(category, name)`, or the record referred to by
+    /// an alias of that name, along with whether an alias was followed.
+    ///
+    /// A record takes precedence over an alias with the same name. An alias whose
+    /// target no longer exists is treated as missing.
+    pub async fn fetch_follow_alias(
+        &mut self,
+        category: &str,
+        name: &str,
+        for_update: bool,
+    ) -> Result<Option<(Entry, bool)>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        if let Some(entry) = self
+            .0
+            .fetch(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                for_update,
+            )
+            .await?
+        {
+            return Ok(Some((entry, false)));
+        }
+        if let Some(alias) = self
+            .0
+            .fetch(
+                EntryKind::Alias,
+                category.as_bytes(),
+                name.as_bytes(),
+                for_update,
+            )
+            .await?
+        {
+            let target = String::from_utf8(alias.value.into_vec())
+                .map_err(err_map!(Unexpected, "Invalid alias target"))?;
+            Ok(self
+                .0
+                .fetch(
+                    EntryKind::Item,
+                    category.as_bytes(),
+                    target.as_bytes(),
+                    for_update,
+                )
+                .await?
+                .map(|entry| (entry, true)))
+        } else {
+            Ok(None)
+        }
     }
 
-    pub(crate) async fn arc_close(self: Arc<Self>) -> Result<(), Error> {
-        Ok(self.0.close().await?)
+    /// Add an alias for an existing record in the same category.
+    ///
+    /// The alias may be used to look up the record with `fetch_follow_alias`.
+    /// Aliases are removed along with their target by `remove`, or when the
+    /// record is removed by `update`
+    pub async fn add_alias(
+        &mut self,
+        category: &str,
+        alias: &str,
+        target: &str,
+    ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, alias, target) =
+            (norm.apply(category), norm.apply(alias), norm.apply(target));
+        if self
+            .0
+            .fetch(
+                EntryKind::Item,
+                category.as_bytes(),
+                target.as_bytes(),
+                false,
+            )
+            .await?
+            .is_none()
+        {
+            return Err(err_msg!(NotFound, "Alias target not found"));
+        }
+        let tags = [EntryTag::Encrypted(
+            ALIAS_TARGET_TAG.to_string(),
+            target.to_string(),
+        )];
+        Ok(self
+            .0
+            .update(
+                EntryKind::Alias,
+                EntryOperation::Insert,
+                category.as_bytes(),
+                alias.as_bytes(),
+                Some(target.as_bytes()),
+                None,
+                Some(&tags),
+                ExpiryUpdate::Clear,
+            )
+            .await?)
     }
-}
-
-/// An active connection to the store backend
-#[derive(Debug)]
-pub struct Session<Q: QueryBackend>(Q);
 
-impl<Q: QueryBackend> Session<Q> {
-    pub(crate) fn new(inner: Q) -> Self {
-        Self(inner)
+    /// Remove an alias, leaving its target record unchanged
+    pub async fn remove_alias(&mut self, category: &str, alias: &str) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, alias) = (norm.apply(category), norm.apply(alias));
+        Ok(self
+            .0
+            .update(
+                EntryKind::Alias,
+                EntryOperation::Remove,
+                category.as_bytes(),
+                alias.as_bytes(),
+                None,
+                None,
+                None,
+                ExpiryUpdate::Clear,
+            )
+            .await?)
     }
-}
 
-impl<Q: QueryBackend> Session<Q> {
-    /// Count the number of entries for a given record category
-    pub async fn count(
-        &mut self,
-        category: &str,
-        tag_filter: Option<TagFilter>,
-    ) -> Result<i64, Error> {
-        Ok(self.0.count(EntryKind::Item, category, tag_filter).await?)
+    async fn remove_aliases(&mut self, category: &str, target: &str) -> Result<(), Error> {
+        self.0
+            .remove_all(
+                EntryKind::Alias,
+                category,
+                Some(TagFilter::is_eq(ALIAS_TARGET_TAG, target)),
+            )
+            .await?;
+        Ok(())
     }
 
-    /// Retrieve the current record at `(category, name)`.
+    /// Fetch the prior values of a record retained by the value history, most
+    /// recently replaced first.
     ///
-    /// Specify `for_update` when in a transaction to create an update lock on the
-    /// associated record, if supported by the store backend
-    pub async fn fetch(
+    /// The record itself need not exist, as the values of removed records are
+    /// also retained. An empty list is returned when value history is not enabled.
+    pub async fn fetch_history(
         &mut self,
         category: &str,
         name: &str,
-        for_update: bool,
-    ) -> Result<Option<Entry>, Error> {
+        limit: Option<i64>,
+    ) -> Result<Vec<EntryVersion>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
         Ok(self
             .0
-            .fetch(EntryKind::Item, category, name, for_update)
+            .fetch_history(EntryKind::Item, &category, &name, limit)
             .await?)
     }
 
@@ -146,9 +1576,81 @@ impl<Q: QueryBackend> Session<Q> {
         limit: Option<i64>,
         for_update: bool,
     ) -> Result<Vec<Entry>, Error> {
+        let category = self.0.name_normalization().apply(category);
+        Ok(self
+            .0
+            .fetch_all(EntryKind::Item, &category, tag_filter, limit, for_update)
+            .await?)
+    }
+
+    /// Fetch the single record in a category with a tag matching `tag_value`.
+    ///
+    /// The tag name is given as in a tag filter, with a `~` prefix for a
+    /// plaintext tag. A `NotUnique` error is returned when more than one record
+    /// matches.
+    pub async fn fetch_by_tag(
+        &mut self,
+        category: &str,
+        tag_name: &str,
+        tag_value: &str,
+        for_update: bool,
+    ) -> Result<Option<Entry>, Error> {
+        let category = self.0.name_normalization().apply(category);
+        let mut rows = self
+            .0
+            .fetch_all(
+                EntryKind::Item,
+                &category,
+                Some(TagFilter::is_eq(tag_name, tag_value)),
+                Some(2),
+                for_update,
+            )
+            .await?;
+        if rows.len() > 1 {
+            let count = self
+                .0
+                .count(
+                    EntryKind::Item,
+                    &category,
+                    Some(TagFilter::is_eq(tag_name, tag_value)),
+                )
+                .await?;
+            return Err(err_msg!(
+                NotUnique,
+                "Found {} records with a matching tag",
+                count
+            ));
+        }
+        Ok(rows.pop())
+    }
+
+    /// Fetch the current record at `(category, name)` and remove it from the store.
+    ///
+    /// The record is fetched and removed atomically, so that it is only ever
+    /// returned to a single caller
+    pub async fn take(&mut self, category: &str, name: &str) -> Result<Option<Entry>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        Ok(self
+            .0
+            .take(EntryKind::Item, &category, Some(&name), None)
+            .await?)
+    }
+
+    /// Fetch the oldest record matching the given `category` and `tag_filter`
+    /// and remove it from the store.
+    ///
+    /// Concurrent callers never receive the same record, making this suitable
+    /// for simple work queues
+    pub async fn take_first(
+        &mut self,
+        category: &str,
+        tag_filter: Option<TagFilter>,
+    ) -> Result<Option<Entry>, Error> {
+        let category = self.0.name_normalization().apply(category);
         Ok(self
             .0
-            .fetch_all(EntryKind::Item, category, tag_filter, limit, for_update)
+            .take(EntryKind::Item, &category, None, tag_filter)
             .await?)
     }
 
@@ -161,37 +1663,46 @@ impl<Q: QueryBackend> Session<Q> {
         tags: Option<&[EntryTag]>,
         expiry_ms: Option<i64>,
     ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
         Ok(self
             .0
             .update(
                 EntryKind::Item,
                 EntryOperation::Insert,
-                category,
-                name,
+                category.as_bytes(),
+                name.as_bytes(),
                 Some(value),
+                None,
                 tags,
-                expiry_ms,
+                expiry_ms.into(),
             )
             .await?)
     }
 
-    /// Remove a record from the store
+    /// Remove a record from the store, along with any aliases of the record
     pub async fn remove(&mut self, category: &str, name: &str) -> Result<(), Error> {
-        Ok(self
-            .0
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        self.0
             .update(
                 EntryKind::Item,
                 EntryOperation::Remove,
-                category,
-                name,
+                category.as_bytes(),
+                name.as_bytes(),
                 None,
                 None,
                 None,
+                ExpiryUpdate::Clear,
             )
-            .await?)
+            .await?;
+        self.remove_aliases(&category, &name).await
     }
 
     /// Replace the value and tags of a record in the store
+    ///
+    /// The expiry time of the record is set from `expiry_ms` when provided, and
+    /// otherwise removed. Use `replace_with_expiry` to keep the existing expiry time.
     pub async fn replace(
         &mut self,
         category: &str,
@@ -200,36 +1711,88 @@ impl<Q: QueryBackend> Session<Q> {
         tags: Option<&[EntryTag]>,
         expiry_ms: Option<i64>,
     ) -> Result<(), Error> {
+        self.replace_with_expiry(category, name, value, tags, expiry_ms.into())
+            .await
+    }
+
+    /// Replace the value and tags of a record in the store, selecting how its
+    /// expiry time is updated
+    pub async fn replace_with_expiry(
+        &mut self,
+        category: &str,
+        name: &str,
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+        expiry: ExpiryUpdate,
+    ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
         Ok(self
             .0
             .update(
                 EntryKind::Item,
                 EntryOperation::Replace,
-                category,
-                name,
+                category.as_bytes(),
+                name.as_bytes(),
                 Some(value),
+                None,
                 tags,
-                expiry_ms,
+                expiry,
             )
             .await?)
     }
 
+    /// Move a record to a new category and name, keeping either one when `None`
+    /// is given.
+    ///
+    /// The record is updated in place within a single transaction, so that its
+    /// tags and expiry time are unchanged. A `Duplicate` error is returned if a
+    /// record already exists with the new category and name. Aliases of the
+    /// record are removed, as for `remove`, and any prior values retained by
+    /// the value history remain under the original category and name.
+    pub async fn rename(
+        &mut self,
+        category: &str,
+        name: &str,
+        new_category: Option<&str>,
+        new_name: Option<&str>,
+    ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        let new_category = new_category.map_or_else(|| category.clone(), |c| norm.apply(c));
+        let new_name = new_name.map_or_else(|| name.clone(), |n| norm.apply(n));
+        if new_category == category && new_name == name {
+            return Ok(());
+        }
+        self.0
+            .rename(
+                EntryKind::Item,
+                category.as_bytes(),
+                name.as_bytes(),
+                new_category.as_bytes(),
+                new_name.as_bytes(),
+            )
+            .await?;
+        self.remove_aliases(&category, &name).await
+    }
+
     /// Remove all records in the store matching a given `category` and `tag_filter`
     pub async fn remove_all(
         &mut self,
         category: &str,
         tag_filter: Option<TagFilter>,
     ) -> Result<i64, Error> {
+        let category = self.0.name_normalization().apply(category);
         Ok(self
             .0
-            .remove_all(EntryKind::Item, category, tag_filter)
+            .remove_all(EntryKind::Item, &category, tag_filter)
             .await?)
     }
 
     /// Perform a record update
     ///
     /// This may correspond to an record insert, replace, or remove depending on
-    /// the provided `operation`
+    /// the provided `operation`.
     pub async fn update(
         &mut self,
         operation: EntryOperation,
@@ -239,18 +1802,155 @@ impl<Q: QueryBackend> Session<Q> {
         tags: Option<&[EntryTag]>,
         expiry_ms: Option<i64>,
     ) -> Result<(), Error> {
+        self.update_typed(operation, category, name, value, None, tags, expiry_ms)
+            .await
+    }
+
+    /// Perform a record update, as for `update`, along with a value type.
+    ///
+    /// An optional `value_type` (such as a content type) is stored encrypted
+    /// alongside the value and returned with the entry.
+    pub async fn update_typed(
+        &mut self,
+        operation: EntryOperation,
+        category: &str,
+        name: &str,
+        value: Option<&[u8]>,
+        value_type: Option<&str>,
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply(category), norm.apply(name));
+        self.0
+            .update(
+                EntryKind::Item,
+                operation,
+                category.as_bytes(),
+                name.as_bytes(),
+                value,
+                value_type,
+                tags,
+                expiry_ms.into(),
+            )
+            .await?;
+        if operation == EntryOperation::Remove {
+            self.remove_aliases(&category, &name).await?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the current record at `(category, name)`, where the category
+    /// and name are arbitrary byte strings.
+    ///
+    /// Records are identified by the exact bytes of their category and name,
+    /// which are normalized only when they are valid UTF-8. A category or name
+    /// which is not valid UTF-8 is decoded lossily in the `category` and `name`
+    /// of the returned entry, while `Entry::category_bytes` and
+    /// `Entry::name_bytes` return the exact bytes. Such records cannot be
+    /// returned by a scan, which fails instead.
+    pub async fn fetch_bytes(
+        &mut self,
+        category: &[u8],
+        name: &[u8],
+        for_update: bool,
+    ) -> Result<Option<Entry>, Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply_bytes(category), norm.apply_bytes(name));
         Ok(self
             .0
+            .fetch(EntryKind::Item, &category, &name, for_update)
+            .await?)
+    }
+
+    /// Insert a new record into the store, where the category and name are
+    /// arbitrary byte strings
+    pub async fn insert_bytes(
+        &mut self,
+        category: &[u8],
+        name: &[u8],
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+        expiry_ms: Option<i64>,
+    ) -> Result<(), Error> {
+        self.update_bytes(
+            EntryOperation::Insert,
+            category,
+            name,
+            Some(value),
+            tags,
+            expiry_ms.into(),
+        )
+        .await
+    }
+
+    /// Replace the value and tags of a record in the store, where the category
+    /// and name are arbitrary byte strings
+    pub async fn replace_bytes(
+        &mut self,
+        category: &[u8],
+        name: &[u8],
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+        expiry: ExpiryUpdate,
+    ) -> Result<(), Error> {
+        self.update_bytes(
+            EntryOperation::Replace,
+            category,
+            name,
+            Some(value),
+            tags,
+            expiry,
+        )
+        .await
+    }
+
+    /// Remove a record from the store, where the category and name are
+    /// arbitrary byte strings
+    pub async fn remove_bytes(&mut self, category: &[u8], name: &[u8]) -> Result<(), Error> {
+        self.update_bytes(
+            EntryOperation::Remove,
+            category,
+            name,
+            None,
+            None,
+            ExpiryUpdate::Clear,
+        )
+        .await
+    }
+
+    async fn update_bytes(
+        &mut self,
+        operation: EntryOperation,
+        category: &[u8],
+        name: &[u8],
+        value: Option<&[u8]>,
+        tags: Option<&[EntryTag]>,
+        expiry: ExpiryUpdate,
+    ) -> Result<(), Error> {
+        let norm = self.0.name_normalization();
+        let (category, name) = (norm.apply_bytes(category), norm.apply_bytes(name));
+        self.0
             .update(
                 EntryKind::Item,
                 operation,
-                category,
-                name,
+                &category,
+                &name,
                 value,
+                None,
                 tags,
-                expiry_ms,
+                expiry,
             )
-            .await?)
+            .await?;
+        if operation == EntryOperation::Remove {
+            // aliases are only created for categories and names given as strings
+            if let (Ok(category), Ok(name)) =
+                (std::str::from_utf8(&category), std::str::from_utf8(&name))
+            {
+                self.remove_aliases(category, name).await?;
+            }
+        }
+        Ok(())
     }
 
     /// Insert a local key instance into the store
@@ -280,18 +1980,19 @@ impl<Q: QueryBackend> Session<Q> {
         }
         if let Some(tags) = tags {
             for t in tags {
-                ins_tags.push(t.map_ref(|k, v| (format!("user:{}", k), v.to_string())));
+                ins_tags.push(t.map_name(|k| format!("user:{}", k)));
             }
         }
         self.0
             .update(
                 EntryKind::Kms,
                 EntryOperation::Insert,
-                KmsCategory::CryptoKey.as_str(),
-                name,
+                KmsCategory::CryptoKey.as_str().as_bytes(),
+                name.as_bytes(),
                 Some(value.as_ref()),
+                None,
                 Some(ins_tags.as_slice()),
-                expiry_ms,
+                expiry_ms.into(),
             )
             .await?;
         Ok(())
@@ -311,8 +2012,8 @@ impl<Q: QueryBackend> Session<Q> {
                 .0
                 .fetch(
                     EntryKind::Kms,
-                    KmsCategory::CryptoKey.as_str(),
-                    name,
+                    KmsCategory::CryptoKey.as_str().as_bytes(),
+                    name.as_bytes(),
                     for_update,
                 )
                 .await?
@@ -333,28 +2034,7 @@ impl<Q: QueryBackend> Session<Q> {
         limit: Option<i64>,
         for_update: bool,
     ) -> Result<Vec<KeyEntry>, Error> {
-        let mut query_parts = Vec::with_capacity(3);
-        if let Some(query) = tag_filter.map(|f| f.query) {
-            query_parts.push(TagFilter::from(
-                query
-                    .map_names(|mut k| {
-                        k.replace_range(0..0, "user:");
-                        Result::<_, ()>::Ok(k)
-                    })
-                    .unwrap(),
-            ));
-        }
-        if let Some(algorithm) = algorithm {
-            query_parts.push(TagFilter::is_eq("alg", algorithm));
-        }
-        if let Some(thumbprint) = thumbprint {
-            query_parts.push(TagFilter::is_eq("thumb", thumbprint));
-        }
-        let tag_filter = if query_parts.is_empty() {
-            None
-        } else {
-            Some(TagFilter::all_of(query_parts))
-        };
+        let tag_filter = key_tag_filter(algorithm, thumbprint, tag_filter);
         let rows = self
             .0
             .fetch_all(
@@ -378,11 +2058,12 @@ impl<Q: QueryBackend> Session<Q> {
             .update(
                 EntryKind::Kms,
                 EntryOperation::Remove,
-                KmsCategory::CryptoKey.as_str(),
-                name,
+                KmsCategory::CryptoKey.as_str().as_bytes(),
+                name.as_bytes(),
                 None,
                 None,
                 None,
+                ExpiryUpdate::Clear,
             )
             .await
     }
@@ -397,7 +2078,12 @@ impl<Q: QueryBackend> Session<Q> {
     ) -> Result<(), Error> {
         let row = self
             .0
-            .fetch(EntryKind::Kms, KmsCategory::CryptoKey.as_str(), name, true)
+            .fetch(
+                EntryKind::Kms,
+                KmsCategory::CryptoKey.as_str().as_bytes(),
+                name.as_bytes(),
+                true,
+            )
             .await?
             .ok_or_else(|| err_msg!(NotFound, "Key entry not found"))?;
 
@@ -408,7 +2094,7 @@ impl<Q: QueryBackend> Session<Q> {
         let mut upd_tags = Vec::with_capacity(10);
         if let Some(tags) = tags {
             for t in tags {
-                upd_tags.push(t.map_ref(|k, v| (format!("user:{}", k), v.to_string())));
+                upd_tags.push(t.map_name(|k| format!("user:{}", k)));
             }
         }
         for t in row.tags {
@@ -421,11 +2107,12 @@ impl<Q: QueryBackend> Session<Q> {
             .update(
                 EntryKind::Kms,
                 EntryOperation::Replace,
-                KmsCategory::CryptoKey.as_str(),
-                name,
+                KmsCategory::CryptoKey.as_str().as_bytes(),
+                name.as_bytes(),
                 Some(value.as_ref()),
+                None,
                 Some(upd_tags.as_slice()),
-                expiry_ms,
+                expiry_ms.into(),
             )
             .await?;
 
@@ -442,3 +2129,33 @@ impl<Q: QueryBackend> Session<Q> {
         Ok(self.0.close(false).await?)
     }
 }
+
+// build the tag filter for a key query, mapping user tag names into their namespace
+fn key_tag_filter(
+    algorithm: Option<&str>,
+    thumbprint: Option<&str>,
+    tag_filter: Option<TagFilter>,
+) -> Option<TagFilter> {
+    let mut query_parts = Vec::with_capacity(3);
+    if let Some(query) = tag_filter.map(|f| f.query) {
+        query_parts.push(TagFilter::from(
+            query
+                .map_names(|mut k| {
+                    k.replace_range(0..0, "user:");
+                    Result::<_, ()>::Ok(k)
+                })
+                .unwrap(),
+        ));
+    }
+    if let Some(algorithm) = algorithm {
+        query_parts.push(TagFilter::is_eq("alg", algorithm));
+    }
+    if let Some(thumbprint) = thumbprint {
+        query_parts.push(TagFilter::is_eq("thumb", thumbprint));
+    }
+    if query_parts.is_empty() {
+        None
+    } else {
+        Some(TagFilter::all_of(query_parts))
+    }
+}
@@ -0,0 +1,191 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use super::entry::{Entry, EntryKind, EntryOperation, EntryTag, ExpiryUpdate};
+use super::store::Store;
+use crate::{
+    backend::{Backend, QueryBackend},
+    error::{Error, ErrorKind},
+};
+
+/// A set of temporary records, which are made permanent when the scope is
+/// committed, as returned by `Store::temp_scope`.
+///
+/// Temporary records are stored apart from the records of the profile, so
+/// they are not visible to other sessions until the scope is committed. Each
+/// is written with an expiry time of the scope TTL: when the scope is dropped
+/// without being committed, or the process exits, the records are removed by
+/// `Store::purge_expired`.
+#[derive(Debug)]
+pub struct TempScope<'s, B: Backend> {
+    store: &'s Store<B>,
+    profile: Option<String>,
+    id: String,
+    ttl_ms: i64,
+    entries: BTreeSet<(String, String)>,
+}
+
+impl<'s, B: Backend> TempScope<'s, B> {
+    pub(crate) fn new(
+        store: &'s Store<B>,
+        profile: Option<String>,
+        ttl: Duration,
+    ) -> Result<Self, Error> {
+        let ttl_ms = ttl.as_millis();
+        if ttl_ms == 0 || ttl_ms > i64::MAX as u128 {
+            return Err(err_msg!(Input, "Invalid TTL for a temporary scope"));
+        }
+        Ok(Self {
+            store,
+            profile,
+            id: uuid::Uuid::new_v4().to_string(),
+            ttl_ms: ttl_ms as i64,
+            entries: BTreeSet::new(),
+        })
+    }
+
+    /// Get the unique identifier of the scope
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Write a temporary record, replacing any record with the same category
+    /// and name previously written to the scope
+    pub async fn update(
+        &mut self,
+        category: &str,
+        name: &str,
+        value: &[u8],
+        tags: Option<&[EntryTag]>,
+    ) -> Result<(), Error> {
+        let mut session = self.store.session(self.profile.clone()).await?;
+        let backend = session.backend();
+        let norm = backend.name_normalization();
+        let key = (
+            norm.apply(category).into_owned(),
+            norm.apply(name).into_owned(),
+        );
+        let operation = if self.entries.contains(&key) {
+            EntryOperation::Replace
+        } else {
+            EntryOperation::Insert
+        };
+        backend
+            .update(
+                EntryKind::Temp,
+                operation,
+                self.temp_category(&key.0).as_bytes(),
+                key.1.as_bytes(),
+                Some(value),
+                None,
+                tags,
+                ExpiryUpdate::Set(self.ttl_ms),
+            )
+            .await?;
+        self.entries.insert(key);
+        Ok(())
+    }
+
+    /// Fetch a temporary record written to the scope
+    pub async fn fetch(&self, category: &str, name: &str) -> Result<Option<Entry>, Error> {
+        let mut session = self.store.session(self.profile.clone()).await?;
+        let backend = session.backend();
+        let norm = backend.name_normalization();
+        let (category, name) = (norm.apply(category).into_owned(), norm.apply(name));
+        let entry = backend
+            .fetch(
+                EntryKind::Temp,
+                self.temp_category(&category).as_bytes(),
+                name.as_bytes(),
+                false,
+            )
+            .await?;
+        Ok(entry.map(|entry| Entry { category, ..entry }))
+    }
+
+    /// Make the temporary records permanent, returning the number of records.
+    ///
+    /// The records are inserted into the profile without an expiry time,
+    /// within a single transaction. The commit fails without any changes when
+    /// a record of the profile has the same category and name, or when a
+    /// temporary record has already expired.
+    pub async fn commit(self) -> Result<usize, Error> {
+        let mut txn = self.store.transaction(self.profile.clone()).await?;
+        let backend = txn.backend();
+        for (category, name) in self.entries.iter() {
+            let temp_category = self.temp_category(category);
+            let entry = backend
+                .fetch(
+                    EntryKind::Temp,
+                    temp_category.as_bytes(),
+                    name.as_bytes(),
+                    true,
+                )
+                .await?
+                .ok_or_else(|| {
+                    err_msg!(
+                        NotFound,
+                        "Temporary record '{}' in category '{}' has expired",
+                        name,
+                        category
+                    )
+                })?;
+            backend
+                .update(
+                    EntryKind::Item,
+                    EntryOperation::Insert,
+                    category.as_bytes(),
+                    name.as_bytes(),
+                    Some(entry.value.as_ref()),
+                    entry.value_type.as_deref(),
+                    Some(entry.tags.as_slice()),
+                    ExpiryUpdate::Clear,
+                )
+                .await?;
+            backend
+                .update(
+                    EntryKind::Temp,
+                    EntryOperation::Remove,
+                    temp_category.as_bytes(),
+                    name.as_bytes(),
+                    None,
+                    None,
+                    None,
+                    ExpiryUpdate::Clear,
+                )
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(self.entries.len())
+    }
+
+    /// Remove the temporary records immediately, rather than waiting for them
+    /// to expire
+    pub async fn discard(self) -> Result<(), Error> {
+        let mut session = self.store.session(self.profile.clone()).await?;
+        let backend = session.backend();
+        for (category, name) in self.entries.iter() {
+            match backend
+                .update(
+                    EntryKind::Temp,
+                    EntryOperation::Remove,
+                    self.temp_category(category).as_bytes(),
+                    name.as_bytes(),
+                    None,
+                    None,
+                    None,
+                    ExpiryUpdate::Clear,
+                )
+                .await
+            {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // temporary records are kept apart from those of other scopes by their category
+    fn temp_category(&self, category: &str) -> String {
+        format!("{}:{}", self.id, category)
+    }
+}
@@ -0,0 +1,89 @@
+use serde_json::Value;
+
+use super::Query;
+use crate::error::Error;
+
+/// The JSON dialect of a tag filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WqlDialect {
+    /// The dialect of indy-sdk wallet queries, in which every tag value is
+    /// given as a JSON string
+    Indy,
+    /// The dialect following the JSON format of tag sets, in which the values
+    /// of integer and boolean tags may also be given as JSON numbers and booleans
+    Askar,
+    /// Detect the dialect from the values present in the filter
+    Auto,
+}
+
+/// Parse a tag filter in the given dialect. Either dialect produces the same
+/// query for equivalent filters
+pub fn parse_with_dialect(query: &str, dialect: WqlDialect) -> Result<Query, Error> {
+    let mut value: Value =
+        serde_json::from_str(query).map_err(err_map!("Error parsing tag query"))?;
+    let dialect = match dialect {
+        WqlDialect::Auto if has_typed_values(&value) => WqlDialect::Askar,
+        WqlDialect::Auto => WqlDialect::Indy,
+        dialect => dialect,
+    };
+    if dialect == WqlDialect::Askar {
+        normalize_typed_values(&mut value);
+    }
+    serde_json::from_value(value).map_err(err_map!("Error parsing tag query"))
+}
+
+fn has_typed_values(value: &Value) -> bool {
+    match value {
+        Value::Bool(_) | Value::Number(_) => true,
+        Value::Array(values) => values.iter().any(has_typed_values),
+        Value::Object(entries) => entries.values().any(has_typed_values),
+        Value::Null | Value::String(_) => false,
+    }
+}
+
+// tag names only appear as object keys, so every number or boolean is a tag value
+fn normalize_typed_values(value: &mut Value) {
+    match value {
+        Value::Bool(flag) => *value = Value::String(flag.to_string()),
+        Value::Number(num) => *value = Value::String(num.to_string()),
+        Value::Array(values) => values.iter_mut().for_each(normalize_typed_values),
+        Value::Object(entries) => entries.values_mut().for_each(normalize_typed_values),
+        Value::Null | Value::String(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dialects_equivalent() {
+        let cases = [
+            (r#"{"~age": "30"}"#, r#"{"~age": 30}"#),
+            (
+                r#"{"~age": {"$gte": "-5"}, "~active": "true"}"#,
+                r#"{"~age": {"$gte": -5}, "~active": true}"#,
+            ),
+            (
+                r#"{"$or": [{"~age": {"$in": ["1", "2"]}}, {"$not": {"~active": "false"}}]}"#,
+                r#"{"$or": [{"~age": {"$in": [1, 2]}}, {"$not": {"~active": false}}]}"#,
+            ),
+        ];
+        for (indy, askar) in cases.iter().copied() {
+            let expected = parse_with_dialect(indy, WqlDialect::Indy).unwrap();
+            assert_eq!(
+                parse_with_dialect(askar, WqlDialect::Askar).unwrap(),
+                expected
+            );
+            assert_eq!(
+                parse_with_dialect(askar, WqlDialect::Auto).unwrap(),
+                expected
+            );
+            assert_eq!(
+                parse_with_dialect(indy, WqlDialect::Auto).unwrap(),
+                expected
+            );
+            assert!(parse_with_dialect(askar, WqlDialect::Indy).is_err());
+        }
+    }
+}
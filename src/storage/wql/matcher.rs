@@ -0,0 +1,209 @@
+use super::tags::{CompareOp, TagName, TagQuery};
+use crate::storage::EntryTag;
+
+/// Determine whether the tags of a record are matched by a tag query.
+///
+/// This evaluates a query in memory with the same results as its SQL encoding,
+/// for stores which cannot run queries against their records. A comparison is
+/// satisfied by any tag of the record with the same name and form (encrypted or
+/// plaintext), and a negated comparison by the absence of such a tag. Plaintext
+/// integer tags are compared numerically against integer query values, and all
+/// other tags are compared as text. Unlike the SQL encoding, ordered comparisons
+/// of encrypted tags compare their plaintext values. `$like` patterns are
+/// matched case-sensitively.
+pub fn match_tag_query(query: &TagQuery, tags: &[EntryTag]) -> bool {
+    match query {
+        TagQuery::Eq(name, value) => match_tag_op(CompareOp::Eq, name, value, tags),
+        TagQuery::Neq(name, value) => match_tag_op(CompareOp::Neq, name, value, tags),
+        TagQuery::Gt(name, value) => match_tag_op(CompareOp::Gt, name, value, tags),
+        TagQuery::Gte(name, value) => match_tag_op(CompareOp::Gte, name, value, tags),
+        TagQuery::Lt(name, value) => match_tag_op(CompareOp::Lt, name, value, tags),
+        TagQuery::Lte(name, value) => match_tag_op(CompareOp::Lte, name, value, tags),
+        TagQuery::Like(name, value) => match_tag_op(CompareOp::Like, name, value, tags),
+        TagQuery::In(name, values) => values
+            .iter()
+            .any(|value| match_tag_op(CompareOp::Eq, name, value, tags)),
+        TagQuery::Exist(names) => names
+            .iter()
+            .all(|name| tags.iter().any(|tag| tag_has_name(tag, name))),
+        TagQuery::And(subqueries) => subqueries.iter().all(|q| match_tag_query(q, tags)),
+        TagQuery::Or(subqueries) => subqueries.iter().any(|q| match_tag_query(q, tags)),
+        TagQuery::Not(subquery) => !match_tag_query(subquery, tags),
+    }
+}
+
+fn match_tag_op(op: CompareOp, name: &TagName, value: &str, tags: &[EntryTag]) -> bool {
+    tags.iter()
+        .any(|tag| tag_has_name(tag, name) && compare_tag_value(op, tag, value))
+}
+
+fn tag_has_name(tag: &EntryTag, name: &TagName) -> bool {
+    match name {
+        TagName::Encrypted(name) => !tag.is_plaintext() && tag.name() == name,
+        TagName::Plaintext(name) => tag.is_plaintext() && tag.name() == name,
+    }
+}
+
+fn compare_tag_value(op: CompareOp, tag: &EntryTag, value: &str) -> bool {
+    if let Some(tag_value) = tag.int_value() {
+        // integer tags are only compared against integer values, and never
+        // matched by a `$like` pattern
+        match (op, value.parse::<i64>()) {
+            (CompareOp::Like, _) | (CompareOp::NotLike, _) | (_, Err(_)) => false,
+            (op, Ok(value)) => compare_ordered(op, &tag_value, &value),
+        }
+    } else {
+        match op {
+            CompareOp::Like => match_like(tag.value(), value),
+            CompareOp::NotLike => !match_like(tag.value(), value),
+            op => compare_ordered(op, tag.value(), value),
+        }
+    }
+}
+
+fn compare_ordered<T: Ord + ?Sized>(op: CompareOp, left: &T, right: &T) -> bool {
+    match op {
+        CompareOp::Eq => left == right,
+        CompareOp::Neq => left != right,
+        CompareOp::Gt => left > right,
+        CompareOp::Gte => left >= right,
+        CompareOp::Lt => left < right,
+        CompareOp::Lte => left <= right,
+        CompareOp::Like | CompareOp::NotLike => false,
+    }
+}
+
+// match an SQL `LIKE` pattern, where `%` matches any sequence of characters
+// and `_` matches a single character
+fn match_like(value: &str, pattern: &str) -> bool {
+    let value = value.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let (mut v, mut p) = (0, 0);
+    // the position of the last `%` in the pattern, and of the value it has
+    // consumed up to, used to backtrack after a mismatch
+    let mut wildcard: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == '%' {
+            wildcard = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '_' || pattern[p] == value[v]) {
+            v += 1;
+            p += 1;
+        } else if let Some((wp, wv)) = wildcard {
+            wildcard = Some((wp, wv + 1));
+            p = wp + 1;
+            v = wv + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::IntTagValue;
+
+    fn plain(name: &str) -> TagName {
+        TagName::Plaintext(name.to_string())
+    }
+
+    fn enc(name: &str) -> TagName {
+        TagName::Encrypted(name.to_string())
+    }
+
+    fn test_tags() -> Vec<EntryTag> {
+        vec![
+            EntryTag::Encrypted("color".to_string(), "blue".to_string()),
+            EntryTag::Plaintext("label".to_string(), "invoice-2021".to_string()),
+            EntryTag::Int("count".to_string(), IntTagValue::new(-5)),
+            EntryTag::Bool("active".to_string(), true),
+        ]
+    }
+
+    #[test]
+    fn match_compare() {
+        let tags = test_tags();
+        let matches = |query: TagQuery| match_tag_query(&query, &tags);
+        assert!(matches(TagQuery::Eq(enc("color"), "blue".to_string())));
+        assert!(!matches(TagQuery::Eq(enc("color"), "red".to_string())));
+        // the form of the tag must also match
+        assert!(!matches(TagQuery::Eq(plain("color"), "blue".to_string())));
+        assert!(!matches(TagQuery::Eq(
+            enc("label"),
+            "invoice-2021".to_string()
+        )));
+        assert!(matches(TagQuery::Neq(enc("color"), "red".to_string())));
+        // a comparison is not satisfied by a missing tag
+        assert!(!matches(TagQuery::Neq(enc("size"), "large".to_string())));
+        assert!(matches(TagQuery::Gt(
+            plain("label"),
+            "invoice-2020".to_string()
+        )));
+        assert!(matches(TagQuery::Eq(plain("active"), "true".to_string())));
+        assert!(matches(TagQuery::In(
+            enc("color"),
+            vec!["red".to_string(), "blue".to_string()]
+        )));
+        assert!(!matches(TagQuery::In(
+            enc("color"),
+            vec!["red".to_string()]
+        )));
+    }
+
+    #[test]
+    fn match_int() {
+        let tags = test_tags();
+        let matches = |query: TagQuery| match_tag_query(&query, &tags);
+        // compared numerically rather than as text
+        assert!(matches(TagQuery::Gt(plain("count"), "-10".to_string())));
+        assert!(matches(TagQuery::Lt(plain("count"), "2".to_string())));
+        assert!(matches(TagQuery::Eq(plain("count"), "-5".to_string())));
+        assert!(!matches(TagQuery::Gte(plain("count"), "0".to_string())));
+        // not compared against values which are not integers
+        assert!(!matches(TagQuery::Neq(plain("count"), "five".to_string())));
+        assert!(!matches(TagQuery::Like(plain("count"), "%".to_string())));
+    }
+
+    #[test]
+    fn match_like_pattern() {
+        assert!(match_like("invoice-2021", "invoice-%"));
+        assert!(match_like("invoice-2021", "%-2021"));
+        assert!(match_like("invoice-2021", "%voice%"));
+        assert!(match_like("invoice-2021", "invoice-20_1"));
+        assert!(match_like("", "%"));
+        assert!(match_like("a%b", "a%b"));
+        assert!(!match_like("invoice-2021", "Invoice-%"));
+        assert!(!match_like("invoice-2021", "invoice-20_"));
+        assert!(!match_like("invoice-2021", "%-2020"));
+    }
+
+    #[test]
+    fn match_conj() {
+        let tags = test_tags();
+        let matches = |query: TagQuery| match_tag_query(&query, &tags);
+        assert!(matches(TagQuery::And(vec![])));
+        assert!(!matches(TagQuery::Or(vec![])));
+        assert!(matches(TagQuery::And(vec![
+            TagQuery::Eq(enc("color"), "blue".to_string()),
+            TagQuery::Like(plain("label"), "invoice-%".to_string()),
+        ])));
+        assert!(!matches(TagQuery::And(vec![
+            TagQuery::Eq(enc("color"), "blue".to_string()),
+            TagQuery::Eq(enc("size"), "large".to_string()),
+        ])));
+        assert!(matches(TagQuery::Or(vec![
+            TagQuery::Eq(enc("color"), "red".to_string()),
+            TagQuery::Exist(vec![plain("count"), plain("active")]),
+        ])));
+        // a negated comparison matches records without the tag
+        assert!(matches(TagQuery::Not(Box::new(TagQuery::Eq(
+            enc("size"),
+            "large".to_string()
+        )))));
+        assert!(!matches(TagQuery::Not(Box::new(TagQuery::Exist(vec![
+            enc("color")
+        ])))));
+    }
+}
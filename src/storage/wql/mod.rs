@@ -1,4 +1,6 @@
 pub use indy_wql::{AbstractQuery, Query};
 
+pub mod dialect;
+pub mod matcher;
 pub mod sql;
 pub mod tags;
@@ -3,8 +3,14 @@ use std::marker::PhantomData;
 use itertools::Itertools;
 
 use super::tags::{CompareOp, ConjunctionOp, TagName, TagQueryEncoder};
-use crate::error::Error;
+use crate::{error::Error, storage::encode_int_tag_value};
 
+/// Encodes a tag query as an SQL predicate over the records of `items i`.
+///
+/// Each tag clause is a semi-join on `i.id` (an `IN` or `NOT IN` subquery)
+/// rather than a join against `items_tags`, so that a record having several
+/// tags which match, or matching several branches of an `$or`, is selected
+/// and counted only once.
 pub struct TagSqlEncoder<'e, EN, EV> {
     pub enc_name: EN,
     pub enc_value: EV,
@@ -55,6 +61,7 @@ where
         enc_name: Self::Arg,
         enc_value: Self::Arg,
         is_plaintext: bool,
+        negate: bool,
     ) -> Result<Option<Self::Clause>, Error> {
         let idx = self.arguments.len();
         let (op_prefix, match_prefix) = match (is_plaintext, op.as_sql_str_for_prefix()) {
@@ -70,18 +77,41 @@ where
             }
             _ => (String::new(), None),
         };
+        let int_value = match op {
+            CompareOp::Like | CompareOp::NotLike => None,
+            _ if is_plaintext => int_tag_arg(&enc_value),
+            _ => None,
+        };
         self.arguments.push(enc_name);
         self.arguments.push(enc_value);
         if let Some(v) = match_prefix {
             self.arguments.push(v);
         }
 
+        // plaintext values which are integers are also compared against the
+        // stored form of integer tags, while other tags are compared as text
+        let value_clause = if !is_plaintext {
+            format!("value {} ${}{}", op.as_sql_str(), idx + 2, op_prefix)
+        } else if let Some(int_value) = int_value {
+            self.arguments.push(int_value);
+            format!(
+                "((value {op} ${} AND value_type != 1) OR (value {op} ${} AND value_type = 1))",
+                idx + 2,
+                idx + 3,
+                op = op.as_sql_str()
+            )
+        } else {
+            format!("value {} ${} AND value_type != 1", op.as_sql_str(), idx + 2)
+        };
+
+        // a negated comparison must also match records without the tag, so it
+        // is expressed as the absence of a matching tag rather than the presence
+        // of a non-matching one
         let query = format!(
-            "i.id IN (SELECT item_id FROM items_tags WHERE name = ${} AND value {} ${}{} AND plaintext = {})",
+            "{} (SELECT item_id FROM items_tags WHERE name = ${} AND {} AND plaintext = {})",
+            if negate { "i.id NOT IN" } else { "i.id IN" },
             idx + 1,
-            op.as_sql_str(),
-            idx + 2,
-            op_prefix.as_str(),
+            value_clause,
             if is_plaintext { 1 } else { 0 }
         );
         Ok(Some(query))
@@ -94,16 +124,37 @@ where
         is_plaintext: bool,
         negate: bool,
     ) -> Result<Option<Self::Clause>, Error> {
-        let args_in = Itertools::intersperse(std::iter::repeat("$$").take(enc_values.len()), ", ")
-            .collect::<String>();
+        let int_values = if is_plaintext {
+            enc_values
+                .iter()
+                .filter_map(|value| int_tag_arg(value))
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        let value_clause = if !is_plaintext {
+            format!("value IN ({})", in_args(enc_values.len()))
+        } else if int_values.is_empty() {
+            format!(
+                "value IN ({}) AND value_type != 1",
+                in_args(enc_values.len())
+            )
+        } else {
+            format!(
+                "((value IN ({}) AND value_type != 1) OR (value IN ({}) AND value_type = 1))",
+                in_args(enc_values.len()),
+                in_args(int_values.len())
+            )
+        };
         let query = format!(
-            "i.id IN (SELECT item_id FROM items_tags WHERE name = $$ AND value {} ({}) AND plaintext = {})",
+            "i.id {} (SELECT item_id FROM items_tags WHERE name = $$ AND {} AND plaintext = {})",
             if negate { "NOT IN" } else { "IN" },
-            args_in,
+            value_clause,
             if is_plaintext { 1 } else { 0 }
         );
         self.arguments.push(enc_name);
         self.arguments.extend(enc_values);
+        self.arguments.extend(int_values);
         Ok(Some(query))
     }
 
@@ -129,11 +180,17 @@ where
     ) -> Result<Option<Self::Clause>, Error> {
         let qc = clauses.len();
         if qc == 0 {
-            if op == ConjunctionOp::Or {
-                return Ok(Some("0".to_string()));
-            } else {
-                return Ok(None);
-            }
+            // an empty conjunction matches all records and an empty disjunction
+            // matches none, following indy-wql. these must remain literal
+            // predicates so that they keep their meaning within a larger query
+            return Ok(Some(
+                if op == ConjunctionOp::Or {
+                    MATCH_NONE
+                } else {
+                    MATCH_ALL
+                }
+                .to_string(),
+            ));
         }
         let mut s = String::new();
         if qc > 1 {
@@ -152,11 +209,88 @@ where
     }
 }
 
+/// The predicate used for a filter which matches every record
+pub const MATCH_ALL: &str = "1=1";
+
+/// The predicate used for a filter which matches no records
+pub const MATCH_NONE: &str = "0=1";
+
+fn in_args(count: usize) -> String {
+    Itertools::intersperse(std::iter::repeat("$$").take(count), ", ").collect()
+}
+
+// convert a plaintext filter value to the stored form of an integer tag value,
+// if it represents an integer
+fn int_tag_arg(value: &[u8]) -> Option<Vec<u8>> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(|value| encode_int_tag_value(value).into_bytes())
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::tags::TagQuery;
     use super::*;
 
+    #[test]
+    fn tag_query_encode_int() {
+        let query = TagQuery::And(vec![
+            TagQuery::Gt(TagName::Plaintext("num".to_string()), "10".to_string()),
+            TagQuery::In(
+                TagName::Plaintext("num".to_string()),
+                vec!["a".to_string(), "-1".to_string()],
+            ),
+        ]);
+        let mut enc = TagSqlEncoder::new(
+            |name: &str| Ok(name.as_bytes().to_vec()),
+            |value: &str| Ok(value.as_bytes().to_vec()),
+        );
+        let query_str = enc.encode_query(&query).unwrap().unwrap();
+        assert_eq!(query_str, "(i.id IN (SELECT item_id FROM items_tags WHERE name = $1 AND ((value > $2 AND value_type != 1) OR (value > $3 AND value_type = 1)) AND plaintext = 1) AND i.id IN (SELECT item_id FROM items_tags WHERE name = $$ AND ((value IN ($$, $$) AND value_type != 1) OR (value IN ($$) AND value_type = 1)) AND plaintext = 1))");
+        assert_eq!(
+            enc.arguments,
+            vec![
+                b"num".to_vec(),
+                b"10".to_vec(),
+                encode_int_tag_value(10).into_bytes(),
+                b"num".to_vec(),
+                b"a".to_vec(),
+                b"-1".to_vec(),
+                encode_int_tag_value(-1).into_bytes(),
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_query_encode_same_name() {
+        // each predicate on the tag is matched by an independent subquery
+        let mut each = (0..9)
+            .map(|idx| {
+                TagQuery::Not(Box::new(TagQuery::Eq(
+                    TagName::Encrypted("color".to_string()),
+                    format!("noncenonce12other{}", idx),
+                )))
+            })
+            .collect::<Vec<_>>();
+        each.push(TagQuery::Eq(
+            TagName::Encrypted("color".to_string()),
+            "noncenonce12green".to_string(),
+        ));
+        let query = TagQuery::And(each);
+        let mut enc = TagSqlEncoder::new(
+            |name: &str| Ok(name.as_bytes().to_vec()),
+            |value: &str| Ok(value.as_bytes().to_vec()),
+        );
+        let query_str = enc.encode_query(&query).unwrap().unwrap();
+        assert_eq!(query_str.matches("i.id NOT IN (SELECT item_id").count(), 9);
+        assert_eq!(query_str.matches("i.id IN (SELECT item_id").count(), 1);
+        assert!(query_str.ends_with("i.id IN (SELECT item_id FROM items_tags WHERE name = $28 AND value = $29 AND SUBSTR(value, 1, 12) = $30 AND plaintext = 0))"));
+        // name, value and prefix for each predicate, well within the
+        // minimum limit of 999 query parameters
+        assert_eq!(enc.arguments.len(), 30);
+    }
+
     #[test]
     fn tag_query_encode() {
         let condition_1 = TagQuery::And(vec![
@@ -185,7 +319,7 @@ mod tests {
             |value: &str| Ok(value.to_uppercase().into_bytes()),
         );
         let query_str = enc.encode_query(&query).unwrap().unwrap();
-        assert_eq!(query_str, "((i.id IN (SELECT item_id FROM items_tags WHERE name = $1 AND value = $2 AND SUBSTR(value, 1, 12) = $3 AND plaintext = 0) AND i.id IN (SELECT item_id FROM items_tags WHERE name = $4 AND value = $5 AND plaintext = 1)) OR (i.id IN (SELECT item_id FROM items_tags WHERE name = $6 AND value = $7 AND SUBSTR(value, 1, 12) = $8 AND plaintext = 0) AND i.id IN (SELECT item_id FROM items_tags WHERE name = $9 AND value != $10 AND plaintext = 1)))");
+        assert_eq!(query_str, "((i.id IN (SELECT item_id FROM items_tags WHERE name = $1 AND value = $2 AND SUBSTR(value, 1, 12) = $3 AND plaintext = 0) AND i.id IN (SELECT item_id FROM items_tags WHERE name = $4 AND value = $5 AND value_type != 1 AND plaintext = 1)) OR (i.id IN (SELECT item_id FROM items_tags WHERE name = $6 AND value = $7 AND SUBSTR(value, 1, 12) = $8 AND plaintext = 0) AND i.id NOT IN (SELECT item_id FROM items_tags WHERE name = $9 AND value = $10 AND value_type != 1 AND plaintext = 1)))");
         let args = enc.arguments;
         assert_eq!(
             args,
@@ -203,4 +337,31 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn tag_query_encode_empty_conj() {
+        let tag = || TagQuery::Eq(TagName::Plaintext("tag".to_string()), "val".to_string());
+        let cases = vec![
+            (TagQuery::And(vec![]), "1=1"),
+            (TagQuery::Or(vec![]), "0=1"),
+            (TagQuery::Not(Box::new(TagQuery::And(vec![]))), "0=1"),
+            (TagQuery::Not(Box::new(TagQuery::Or(vec![]))), "1=1"),
+            (
+                TagQuery::Or(vec![TagQuery::And(vec![]), tag()]),
+                "(1=1 OR i.id IN (SELECT item_id FROM items_tags WHERE name = $1 AND value = $2 AND value_type != 1 AND plaintext = 1))",
+            ),
+            (
+                TagQuery::And(vec![TagQuery::Or(vec![]), tag()]),
+                "(0=1 AND i.id IN (SELECT item_id FROM items_tags WHERE name = $1 AND value = $2 AND value_type != 1 AND plaintext = 1))",
+            ),
+        ];
+        for (query, expected) in cases {
+            let mut enc = TagSqlEncoder::new(
+                |name: &str| Ok(name.as_bytes().to_vec()),
+                |value: &str| Ok(value.as_bytes().to_vec()),
+            );
+            let query_str = enc.encode_query(&query).unwrap().unwrap();
+            assert_eq!(query_str, expected);
+        }
+    }
 }
@@ -58,12 +58,15 @@ pub trait TagQueryEncoder {
 
     fn encode_value(&mut self, value: &String, is_plaintext: bool) -> Result<Self::Arg, Error>;
 
+    /// Encode a tag comparison. When `negate` is set, the clause must match
+    /// all records not matched by the comparison, including those without the tag.
     fn encode_op_clause(
         &mut self,
         op: CompareOp,
         enc_name: Self::Arg,
         enc_value: Self::Arg,
         is_plaintext: bool,
+        negate: bool,
     ) -> Result<Option<Self::Clause>, Error>;
 
     fn encode_in_clause(
@@ -212,9 +215,8 @@ where
     };
     let enc_name = enc.encode_name(name)?;
     let enc_value = enc.encode_value(value, is_plaintext)?;
-    let op = if negate { op.negate() } else { op };
 
-    enc.encode_op_clause(op, enc_name, enc_value, is_plaintext)
+    enc.encode_op_clause(op, enc_name, enc_value, is_plaintext, negate)
 }
 
 fn encode_tag_in<V, E>(
@@ -309,7 +311,9 @@ mod tests {
             name: Self::Arg,
             value: Self::Arg,
             _is_plaintext: bool,
+            negate: bool,
         ) -> Result<Option<Self::Clause>, Error> {
+            let op = if negate { op.negate() } else { op };
             Ok(Some(format!("{} {} {}", name, op.as_sql_str(), value)))
         }
 
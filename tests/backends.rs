@@ -1,3 +1,6 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+mod contract;
 mod utils;
 
 macro_rules! backend_tests {
@@ -17,6 +20,30 @@ macro_rules! backend_tests {
             })
         }
 
+        #[test]
+        fn list_profiles() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_list_profiles(&db).await;
+            })
+        }
+
+        #[test]
+        fn vacuum() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_vacuum(&db).await;
+            })
+        }
+
+        #[test]
+        fn encryption_policy() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_encryption_policy(&db).await;
+            })
+        }
+
         #[test]
         fn fetch_fail() {
             block_on(async {
@@ -33,6 +60,14 @@ macro_rules! backend_tests {
             })
         }
 
+        #[test]
+        fn insert_fetch_tag_flags() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_insert_fetch_tag_flags(&db).await;
+            })
+        }
+
         #[test]
         fn insert_duplicate() {
             block_on(async {
@@ -50,240 +85,2594 @@ macro_rules! backend_tests {
         }
 
         #[test]
-        fn remove_missing() {
+        fn take() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_remove_missing(&db).await;
+                super::utils::db_take(&db).await;
             })
         }
 
         #[test]
-        fn replace_fetch() {
+        fn take_concurrent() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_replace_fetch(&db).await;
+                super::utils::db_take_concurrent(&db).await;
             })
         }
 
         #[test]
-        fn replace_missing() {
+        fn scan_prefix() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_replace_missing(&db).await;
+                super::utils::db_scan_prefix(&db).await;
             })
         }
 
         #[test]
-        fn count() {
+        fn binary_names() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_count(&db).await;
+                super::utils::db_binary_names(&db).await;
             })
         }
 
         #[test]
-        fn count_exist() {
+        fn tag_filter_distinct() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_count_exist(&db).await;
+                super::utils::db_tag_filter_distinct(&db).await;
             })
         }
 
         #[test]
-        fn scan() {
+        fn category_prefix() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_scan(&db).await;
+                super::utils::db_category_prefix(&db).await;
             })
         }
 
         #[test]
-        fn remove_all() {
+        fn insert_long_name() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_remove_all(&db).await;
+                super::utils::db_insert_long_name(&db).await;
             })
         }
 
-        // #[test]
-        // fn keypair_create_fetch() {
-        //     block_on(async {
-        //         let db = $init.await;
-        //         super::utils::db_keypair_create_fetch(&db).await;
-        //     })
-        // }
+        #[test]
+        fn namespace() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_namespace(&db).await;
+            })
+        }
 
-        // #[test]
-        // fn keypair_sign_verify() {
-        //     block_on(async {
-        //         let db = $init.await;
-        //         super::utils::db_keypair_sign_verify(&db).await;
-        //     })
-        // }
+        #[test]
+        fn expiry_filter() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_expiry_filter(&db).await;
+            })
+        }
 
-        // #[test]
-        // fn keypair_pack_unpack_anon() {
-        //     block_on(async {
-        //         let db = $init.await;
-        //         super::utils::db_keypair_pack_unpack_anon(&db).await;
-        //     })
-        // }
+        #[test]
+        fn typed_tags() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_typed_tags(&db).await;
+            })
+        }
 
-        // #[test]
-        // fn keypair_pack_unpack_auth() {
-        //     block_on(async {
-        //         let db = $init.await;
-        //         super::utils::db_keypair_pack_unpack_auth(&db).await;
-        //     })
-        // }
+        #[test]
+        fn insert_duplicate_tags() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_insert_duplicate_tags(&db).await;
+            })
+        }
 
         #[test]
-        fn txn_rollback() {
+        fn purge_expired() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_txn_rollback(&db).await;
+                super::utils::db_purge_expired(&db).await;
             })
         }
 
         #[test]
-        fn txn_drop() {
+        fn export_jsonl() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_txn_drop(&db).await;
+                super::utils::db_export_jsonl(&db).await;
             })
         }
 
         #[test]
-        fn session_drop() {
+        fn export_jsonl_partial() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_session_drop(&db).await;
+                super::utils::db_export_jsonl_partial(&db).await;
             })
         }
 
         #[test]
-        fn txn_commit() {
+        fn fetch_if_modified() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_txn_commit(&db).await;
+                super::utils::db_fetch_if_modified(&db).await;
             })
         }
 
         #[test]
-        fn txn_fetch_for_update() {
+        fn reserved_tag_names() {
             block_on(async {
                 let db = $init.await;
-                super::utils::db_txn_fetch_for_update(&db).await;
+                super::utils::db_reserved_tag_names(&db).await;
             })
         }
-    };
-}
 
-#[cfg(feature = "sqlite")]
-mod sqlite {
-    use aries_askar::backend::sqlite::{SqliteStore, SqliteStoreOptions};
-    use aries_askar::{generate_raw_store_key, ManageBackend, Store, StoreKeyMethod};
-    use std::path::Path;
+        #[test]
+        fn health() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_health(&db).await;
+            })
+        }
 
-    #[test]
-    fn create_remove_db() {
-        env_logger::builder().is_test(true).try_init().unwrap_or(());
-        let fname = format!("sqlite-test-{}.db", uuid::Uuid::new_v4().to_string());
-        assert_eq!(
-            Path::new(&fname).exists(),
-            false,
-            "Oops, should be a unique filename"
-        );
+        #[test]
+        fn fetch_by_tag() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_fetch_by_tag(&db).await;
+            })
+        }
 
-        let key = generate_raw_store_key(None).expect("Error creating raw key");
-        block_on(async move {
-            assert_eq!(
-                SqliteStoreOptions::new(fname.as_str())
-                    .expect("Error initializing sqlite store options")
-                    .remove_backend()
-                    .await
-                    .expect("Error removing sqlite store"),
-                false
-            );
+        #[test]
+        fn temp_scope() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_temp_scope(&db).await;
+            })
+        }
 
-            let store = SqliteStoreOptions::new(fname.as_str())
-                .expect("Error initializing sqlite store options")
-                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
-                .await
-                .expect("Error provisioning sqlite store");
-            assert_eq!(Path::new(&fname).exists(), true);
+        #[test]
+        fn wql_dialects() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_wql_dialects(&db).await;
+            })
+        }
 
-            let store2 = SqliteStoreOptions::new(fname.as_str())
-                .expect("Error initializing sqlite store options")
-                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
-                .await
-                .expect("Error opening sqlite store");
-            store2.close().await.expect("Error closing sqlite store");
+        #[test]
+        fn rename() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_rename(&db).await;
+            })
+        }
 
-            store.close().await.expect("Error closing sqlite store");
-            assert_eq!(Path::new(&fname).exists(), true);
+        #[test]
+        fn audit_decryption() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_audit_decryption(&db).await;
+            })
+        }
 
-            assert_eq!(
-                SqliteStoreOptions::new(fname.as_str())
-                    .expect("Error initializing sqlite store options")
-                    .remove_backend()
-                    .await
-                    .expect("Error removing sqlite store"),
-                true
-            );
-            assert_eq!(Path::new(&fname).exists(), false);
-        })
-    }
+        #[test]
+        fn fetch_filtered() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_fetch_filtered(&db).await;
+            })
+        }
 
-    #[test]
-    fn rekey_db() {
-        env_logger::builder().is_test(true).try_init().unwrap_or(());
-        let fname = format!("sqlite-test-{}.db", uuid::Uuid::new_v4().to_string());
-        let key1 = generate_raw_store_key(None).expect("Error creating raw key");
-        let key2 = generate_raw_store_key(None).expect("Error creating raw key");
-        assert_ne!(key1, key2);
+        #[test]
+        fn copy_entry() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_copy_entry(&db).await;
+            })
+        }
 
-        block_on(async move {
-            let mut store = SqliteStoreOptions::new(fname.as_str())
-                .expect("Error initializing sqlite store options")
-                .provision_backend(StoreKeyMethod::RawKey, key1.as_ref(), None, false)
-                .await
-                .expect("Error provisioning sqlite store");
+        #[test]
+        fn fetch_with_tags() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_fetch_with_tags(&db).await;
+            })
+        }
 
-            store
-                .rekey(StoreKeyMethod::RawKey, key2.as_ref())
-                .await
-                .expect("Error rekeying database");
+        #[test]
+        fn tag_stats() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_tag_stats(&db).await;
+            })
+        }
 
-            SqliteStoreOptions::new(fname.as_str())
-                .expect("Error initializing sqlite store options")
-                .open_backend(Some(StoreKeyMethod::RawKey), key2.as_ref(), None)
-                .await
-                .expect("Error opening rekeyed store")
-                .close()
-                .await
-                .expect("Error closing store");
+        #[test]
+        fn remove_missing() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_remove_missing(&db).await;
+            })
+        }
 
-            store.close().await.expect("Error closing store");
+        #[test]
+        fn replace_fetch() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_replace_fetch(&db).await;
+            })
+        }
 
-            SqliteStoreOptions::new(fname.as_str())
-                .expect("Error initializing sqlite store options")
-                .remove_backend()
-                .await
-                .expect("Error removing sqlite store");
-        })
-    }
+        #[test]
+        fn replace_missing() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_replace_missing(&db).await;
+            })
+        }
 
-    async fn init_db() -> Store<SqliteStore> {
-        env_logger::builder().is_test(true).try_init().unwrap_or(());
-        let key = generate_raw_store_key(None).expect("Error creating raw key");
-        SqliteStoreOptions::in_memory()
+        #[test]
+        fn replace_expiry() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_replace_expiry(&db).await;
+            })
+        }
+
+        #[test]
+        fn count() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_count(&db).await;
+            })
+        }
+
+        #[test]
+        fn count_exist() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_count_exist(&db).await;
+            })
+        }
+
+        #[test]
+        fn scan_resumable() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_scan_resumable(&db).await;
+            })
+        }
+
+        #[test]
+        fn alias() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_alias(&db).await;
+            })
+        }
+
+        #[test]
+        fn tag_filter_empty() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_tag_filter_empty(&db).await;
+            })
+        }
+
+        #[test]
+        fn tag_order() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_tag_order(&db).await;
+            })
+        }
+
+        #[test]
+        fn profile_key_recovery() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_profile_key_recovery(&db).await;
+            })
+        }
+
+        #[test]
+        fn profile_passphrase() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_profile_passphrase(&db).await;
+            })
+        }
+
+        #[test]
+        fn access_log() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_access_log(&db).await;
+            })
+        }
+
+        #[test]
+        fn index_locked_profile() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_index_locked_profile(&db).await;
+            })
+        }
+
+        #[test]
+        fn key_value() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_key_value(&db).await;
+            })
+        }
+
+        #[test]
+        fn scan() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_scan(&db).await;
+            })
+        }
+
+        #[test]
+        fn remove_all() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_remove_all(&db).await;
+            })
+        }
+
+        #[test]
+        fn insert_fetch_value_type() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_insert_fetch_value_type(&db).await;
+            })
+        }
+
+        #[test]
+        fn tag_filter_not() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_tag_filter_not(&db).await;
+            })
+        }
+
+        #[test]
+        fn tag_filter_same_name() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_tag_filter_same_name(&db).await;
+            })
+        }
+
+        #[test]
+        fn diagnostics() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_diagnostics(&db).await;
+            })
+        }
+
+        #[test]
+        fn info() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_info(&db).await;
+            })
+        }
+
+        #[test]
+        fn scan_grouped() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_scan_grouped(&db).await;
+            })
+        }
+
+        #[test]
+        fn truncate_profile() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_truncate_profile(&db).await;
+            })
+        }
+
+        #[test]
+        fn kind_isolation() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_kind_isolation(&db).await;
+            })
+        }
+
+        #[test]
+        fn keypair_list() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_keypair_list(&db).await;
+            })
+        }
+
+        // #[test]
+        // fn keypair_create_fetch() {
+        //     block_on(async {
+        //         let db = $init.await;
+        //         super::utils::db_keypair_create_fetch(&db).await;
+        //     })
+        // }
+
+        // #[test]
+        // fn keypair_sign_verify() {
+        //     block_on(async {
+        //         let db = $init.await;
+        //         super::utils::db_keypair_sign_verify(&db).await;
+        //     })
+        // }
+
+        // #[test]
+        // fn keypair_pack_unpack_anon() {
+        //     block_on(async {
+        //         let db = $init.await;
+        //         super::utils::db_keypair_pack_unpack_anon(&db).await;
+        //     })
+        // }
+
+        // #[test]
+        // fn keypair_pack_unpack_auth() {
+        //     block_on(async {
+        //         let db = $init.await;
+        //         super::utils::db_keypair_pack_unpack_auth(&db).await;
+        //     })
+        // }
+
+        #[test]
+        fn txn_rollback() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_txn_rollback(&db).await;
+            })
+        }
+
+        #[test]
+        fn txn_drop() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_txn_drop(&db).await;
+            })
+        }
+
+        #[test]
+        fn session_drop() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_session_drop(&db).await;
+            })
+        }
+
+        #[test]
+        fn txn_commit() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_txn_commit(&db).await;
+            })
+        }
+
+        #[test]
+        fn txn_fetch_for_update() {
+            block_on(async {
+                let db = $init.await;
+                super::utils::db_txn_fetch_for_update(&db).await;
+            })
+        }
+    };
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use aries_askar::backend::sqlite::{SqliteStore, SqliteStoreOptions};
+    use aries_askar::{generate_raw_store_key, ManageBackend, Store, StoreKeyMethod};
+    use std::path::Path;
+
+    #[test]
+    fn create_remove_db() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = format!("sqlite-test-{}.db", uuid::Uuid::new_v4().to_string());
+        assert_eq!(
+            Path::new(&fname).exists(),
+            false,
+            "Oops, should be a unique filename"
+        );
+
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            assert_eq!(
+                SqliteStoreOptions::new(fname.as_str())
+                    .expect("Error initializing sqlite store options")
+                    .remove_backend()
+                    .await
+                    .expect("Error removing sqlite store"),
+                false
+            );
+
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            assert_eq!(Path::new(&fname).exists(), true);
+
+            let store2 = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening sqlite store");
+            store2.close().await.expect("Error closing sqlite store");
+
+            store.close().await.expect("Error closing sqlite store");
+            assert_eq!(Path::new(&fname).exists(), true);
+
+            assert_eq!(
+                SqliteStoreOptions::new(fname.as_str())
+                    .expect("Error initializing sqlite store options")
+                    .remove_backend()
+                    .await
+                    .expect("Error removing sqlite store"),
+                true
+            );
+            assert_eq!(Path::new(&fname).exists(), false);
+        })
+    }
+
+    #[test]
+    fn rekey_db() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = format!("sqlite-test-{}.db", uuid::Uuid::new_v4().to_string());
+        let key1 = generate_raw_store_key(None).expect("Error creating raw key");
+        let key2 = generate_raw_store_key(None).expect("Error creating raw key");
+        assert_ne!(key1, key2);
+
+        block_on(async move {
+            let mut store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key1.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            store
+                .rekey(StoreKeyMethod::RawKey, key2.as_ref())
+                .await
+                .expect("Error rekeying database");
+
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_backend(Some(StoreKeyMethod::RawKey), key2.as_ref(), None)
+                .await
+                .expect("Error opening rekeyed store")
+                .close()
+                .await
+                .expect("Error closing store");
+
+            store.close().await.expect("Error closing store");
+
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn provision_round_trip() {
+        use aries_askar::ErrorKind;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), Some("main"), true)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            // entries written immediately after provisioning can be read back
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            let entry = conn
+                .fetch("category", "name", false)
+                .await
+                .expect("Error fetching test row")
+                .expect("Test row not found");
+            assert_eq!(&entry.value[..], b"value");
+            drop(conn);
+            store.close().await.expect("Error closing sqlite store");
+
+            // and by a new instance, which loads the profile key from the database
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening sqlite store");
+            assert_eq!(store.get_profile_name(), "main");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            let entry = conn
+                .fetch("category", "name", false)
+                .await
+                .expect("Error fetching test row")
+                .expect("Test row not found");
+            assert_eq!(&entry.value[..], b"value");
+            drop(conn);
+            store.close().await.expect("Error closing sqlite store");
+
+            let err = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_backend(Some(StoreKeyMethod::RawKey), key.as_ref(), Some("other"))
+                .await
+                .expect_err("Expected error opening an unknown profile");
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn provision_concurrent() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        let handles = (0..2)
+            .map(|_| {
+                let fname = fname.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    block_on(async move {
+                        let store = SqliteStoreOptions::new(
+                            format!("sqlite://{}?busy_timeout=30&open_existing=true", fname)
+                                .as_str(),
+                        )
+                        .expect("Error initializing sqlite store options")
+                        .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                        .await
+                        .expect("Error provisioning sqlite store");
+                        let mut conn = store.session(None).await.expect("Error starting session");
+                        conn.count("category", None)
+                            .await
+                            .expect("Error performing count");
+                        drop(conn);
+                        store.close().await.expect("Error closing sqlite store");
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().expect("Error joining provisioning thread");
+        }
+
+        block_on(async move {
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn provision_existing() {
+        use aries_askar::ErrorKind;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            store.close().await.expect("Error closing sqlite store");
+
+            let err = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect_err("Expected duplicate store error");
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_existing(true)
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error opening existing sqlite store");
+            store.close().await.expect("Error closing sqlite store");
+
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn query_timeout() {
+        use aries_askar::{EntryTag, ErrorKind, TagFilter};
+        use std::time::Duration;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store =
+                SqliteStoreOptions::new(format!("sqlite://{}?query_timeout=30", fname).as_str())
+                    .expect("Error initializing sqlite store options")
+                    .provision(StoreKeyMethod::RawKey, key, None, false)
+                    .await
+                    .expect("Error provisioning sqlite store");
+            let filter = || {
+                TagFilter::any_of(
+                    (0..50)
+                        .map(|idx| TagFilter::is_gte("~t", idx.to_string()))
+                        .collect(),
+                )
+            };
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for idx in 0..10 {
+                conn.insert(
+                    "category",
+                    &format!("name-{}", idx),
+                    b"value",
+                    Some(&[EntryTag::Plaintext("t".to_string(), idx.to_string())]),
+                    None,
+                )
+                .await
+                .expect("Error inserting test row");
+            }
+
+            // a query cannot complete before an immediate timeout
+            conn.set_query_timeout(Some(Duration::from_secs(0)));
+            let err = conn
+                .count("category", Some(filter()))
+                .await
+                .expect_err("Expected timeout");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+            let err = conn
+                .fetch_all("category", Some(filter()), None, false)
+                .await
+                .expect_err("Expected timeout");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+            let err = conn
+                .fetch("category", "name-1", false)
+                .await
+                .expect_err("Expected timeout");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+
+            // the session acquires a new connection for the next query
+            conn.set_query_timeout(None);
+            let count = conn
+                .count("category", Some(filter()))
+                .await
+                .expect("Error performing count");
+            assert_eq!(count, 10);
+            drop(conn);
+
+            // a transaction cannot be used after a timeout
+            let mut txn = store
+                .transaction(None)
+                .await
+                .expect("Error starting transaction");
+            txn.remove("category", "name-0")
+                .await
+                .expect("Error removing test row");
+            txn.set_query_timeout(Some(Duration::from_secs(0)));
+            let err = txn
+                .count("category", Some(filter()))
+                .await
+                .expect_err("Expected timeout");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+            let err = txn
+                .count("category", None)
+                .await
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+            let err = txn.commit().await.expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Timeout);
+
+            // the removal was rolled back
+            let mut conn = store.session(None).await.expect("Error starting session");
+            let count = conn
+                .count("category", None)
+                .await
+                .expect("Error performing count");
+            assert_eq!(count, 10);
+            drop(conn);
+
+            store.close().await.expect("Error closing sqlite store");
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn open_shared() {
+        use aries_askar::{ErrorKind, PassKey};
+        use std::sync::Arc;
+
+        async fn open(fname: &str, key: PassKey<'_>) -> Arc<Store<SqliteStore>> {
+            SqliteStoreOptions::new(fname)
+                .expect("Error initializing sqlite store options")
+                .open_shared(Some(StoreKeyMethod::RawKey), key, None)
+                .await
+                .expect("Error opening shared sqlite store")
+        }
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            store.close().await.expect("Error closing sqlite store");
+
+            let first = open(&fname, key.as_ref()).await;
+            let second = open(&fname, key.as_ref()).await;
+            assert!(Arc::ptr_eq(&first, &second));
+
+            // the pass key, key method and profile must match the open store
+            let other_key = generate_raw_store_key(None).expect("Error creating raw key");
+            let err = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_shared(Some(StoreKeyMethod::RawKey), other_key.as_ref(), None)
+                .await
+                .expect_err("Expected store key mismatch");
+            assert_eq!(err.kind(), ErrorKind::Encryption);
+            let err = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_shared(Some(StoreKeyMethod::Unprotected), key.as_ref(), None)
+                .await
+                .expect_err("Expected store key method mismatch");
+            assert_eq!(err.kind(), ErrorKind::Input);
+            let other_profile = first
+                .create_profile(None)
+                .await
+                .expect("Error creating profile");
+            let err = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_shared(
+                    Some(StoreKeyMethod::RawKey),
+                    key.as_ref(),
+                    Some(&other_profile),
+                )
+                .await
+                .expect_err("Expected store profile mismatch");
+            assert_eq!(err.kind(), ErrorKind::Input);
+            let named = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open_shared(
+                    Some(StoreKeyMethod::RawKey),
+                    key.as_ref(),
+                    Some(first.get_profile_name()),
+                )
+                .await
+                .expect("Error opening shared sqlite store");
+            assert!(Arc::ptr_eq(&first, &named));
+            drop(named);
+
+            // an independent instance is still available
+            let separate = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening sqlite store");
+            separate.close().await.expect("Error closing sqlite store");
+
+            drop(first);
+            drop(second);
+            let third = open(&fname, key.as_ref()).await;
+            assert_eq!(Arc::strong_count(&third), 1);
+            drop(third);
+
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn snapshot_to() {
+        use aries_askar::{EntryTag, ErrorKind, TagFilter};
+        use futures_lite::future::zip;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let temp_name = || {
+            std::env::temp_dir()
+                .join(format!(
+                    "sqlite-test-{}.db",
+                    uuid::Uuid::new_v4().to_string()
+                ))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let (fname, snapshot_name) = (temp_name(), temp_name());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            // take the snapshot while records are being written
+            let writes = async {
+                let mut conn = store.session(None).await.expect("Error starting session");
+                for idx in 0..100 {
+                    conn.insert(
+                        "category",
+                        &format!("name-{}", idx),
+                        b"value",
+                        Some(&[
+                            EntryTag::Encrypted("a".to_string(), idx.to_string()),
+                            EntryTag::Plaintext("b".to_string(), "b".to_string()),
+                        ]),
+                        None,
+                    )
+                    .await
+                    .expect("Error inserting test row");
+                }
+            };
+            let ((), result) = zip(writes, store.snapshot_to(&snapshot_name)).await;
+            result.expect("Error creating snapshot");
+
+            let err = store
+                .snapshot_to(&snapshot_name)
+                .await
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+            let store_id = store
+                .info(false)
+                .await
+                .expect("Error fetching store info")
+                .store_id;
+            store.close().await.expect("Error closing sqlite store");
+
+            // the snapshot may be identified as a copy of the store
+            let snapshot = SqliteStoreOptions::new(snapshot_name.as_str())
+                .expect("Error initializing sqlite store options")
+                .expected_store_id(store_id)
+                .expect("Error initializing sqlite store options")
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening snapshot");
+            let mut conn = snapshot
+                .session(None)
+                .await
+                .expect("Error starting session");
+            let rows = conn
+                .fetch_all("category", None, None, false)
+                .await
+                .expect("Error fetching snapshot rows");
+            assert!(rows.len() <= 100);
+            // every record copied into the snapshot has all of its tags
+            for row in rows.iter() {
+                assert_eq!(row.tags.len(), 2);
+            }
+            let count = conn
+                .count("category", Some(TagFilter::is_eq("~b", "b")))
+                .await
+                .expect("Error performing count");
+            assert_eq!(count, rows.len() as i64);
+            drop(conn);
+            snapshot.close().await.expect("Error closing snapshot");
+
+            for name in &[fname, snapshot_name] {
+                SqliteStoreOptions::new(name.as_str())
+                    .expect("Error initializing sqlite store options")
+                    .remove_backend()
+                    .await
+                    .expect("Error removing sqlite store");
+            }
+        })
+    }
+
+    #[test]
+    fn store_id() {
+        use aries_askar::ErrorKind;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let temp_name = || {
+            std::env::temp_dir()
+                .join(format!(
+                    "sqlite-test-{}.db",
+                    uuid::Uuid::new_v4().to_string()
+                ))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let (fname, other_name) = (temp_name(), temp_name());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let mut store_ids = vec![];
+            for name in &[&fname, &other_name] {
+                let store = SqliteStoreOptions::new(name.as_str())
+                    .expect("Error initializing sqlite store options")
+                    .provision(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                    .await
+                    .expect("Error provisioning sqlite store");
+                let info = store.info(false).await.expect("Error fetching store info");
+                assert!(uuid::Uuid::parse_str(&info.store_id).is_ok());
+                store_ids.push(info.store_id);
+                store.close().await.expect("Error closing sqlite store");
+            }
+            assert_ne!(store_ids[0], store_ids[1]);
+
+            // the ID is kept when the store is reopened
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .expected_store_id(store_ids[0].clone())
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening sqlite store");
+            assert_eq!(
+                store
+                    .info(true)
+                    .await
+                    .expect("Error fetching store info")
+                    .store_id,
+                store_ids[0]
+            );
+            store.close().await.expect("Error closing sqlite store");
+
+            // opening the other store in its place fails
+            let err = SqliteStoreOptions::new(other_name.as_str())
+                .expect("Error initializing sqlite store options")
+                .expected_store_id(store_ids[0].clone())
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect_err("Expected store ID mismatch");
+            assert_eq!(err.kind(), ErrorKind::Input);
+            assert!(err.to_string().contains("Store ID mismatch"));
+
+            for name in &[fname, other_name] {
+                SqliteStoreOptions::new(name.as_str())
+                    .expect("Error initializing sqlite store options")
+                    .remove_backend()
+                    .await
+                    .expect("Error removing sqlite store");
+            }
+        })
+    }
+    #[test]
+    fn index_shared_handles() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async move {
+            let store = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let other = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .open(Some(StoreKeyMethod::RawKey), key.as_ref(), None)
+                .await
+                .expect("Error opening sqlite store");
+
+            // the index is enabled through one handle and written through the other
+            store
+                .enable_name_index()
+                .await
+                .expect("Error enabling name index");
+            let mut conn = other.session(None).await.expect("Error starting session");
+            conn.insert("msg", "conn/1/msg/1", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            let mut scan = store
+                .scan_prefix(
+                    None,
+                    "msg".to_string(),
+                    "conn/1/".to_string(),
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .expect("Error starting scan");
+            let rows = scan
+                .fetch_next()
+                .await
+                .expect("Error fetching scan rows")
+                .unwrap_or_default();
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].name, "conn/1/msg/1");
+
+            other
+                .enable_category_index()
+                .await
+                .expect("Error enabling category index");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("cred::w3c", "cred/1", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+            assert_eq!(
+                other
+                    .list_categories_prefix(None, "cred::")
+                    .await
+                    .expect("Error listing categories"),
+                vec!["cred::w3c"]
+            );
+
+            other.close().await.expect("Error closing sqlite store");
+            store.close().await.expect("Error closing sqlite store");
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        })
+    }
+
+    #[test]
+    fn maintenance() {
+        use aries_askar::{future::sleep, MaintenanceConfig, MaintenanceTask};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        block_on(async {
+            let store = Arc::new(init_db().await);
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "expired", b"value", None, Some(-60_000))
+                .await
+                .expect("Error inserting test row");
+            conn.insert("category", "live", b"value", None, Some(60_000))
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            let runs = Arc::new(Mutex::new(vec![]));
+            let config = MaintenanceConfig {
+                purge_interval: Some(Duration::from_millis(10)),
+                checkpoint_interval: Some(Duration::from_millis(15)),
+                jitter: Duration::default(),
+                on_complete: None,
+            }
+            .on_complete({
+                let runs = runs.clone();
+                move |task, result| {
+                    runs.lock()
+                        .unwrap()
+                        .push((task, *result.as_ref().expect("Maintenance error")))
+                }
+            });
+            let handle = store.clone().start_maintenance(config);
+            sleep(Duration::from_millis(200)).await;
+            handle.stop().await;
+
+            let completed = runs.lock().unwrap().clone();
+            assert!(completed.contains(&(MaintenanceTask::PurgeExpired, 1)));
+            assert!(completed.contains(&(MaintenanceTask::Checkpoint, 0)));
+            let purged = completed
+                .iter()
+                .filter(|(task, _)| *task == MaintenanceTask::PurgeExpired)
+                .map(|(_, count)| count)
+                .sum::<i64>();
+            assert_eq!(purged, 1);
+
+            // no further runs are performed once stopped
+            sleep(Duration::from_millis(50)).await;
+            assert_eq!(completed.len(), runs.lock().unwrap().len());
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.set_expiry_filter(aries_askar::ExpiryFilter::Include);
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                1
+            );
+        })
+    }
+
+    #[test]
+    fn default_expiry_filter() {
+        use aries_askar::ExpiryFilter;
+        block_on(async {
+            env_logger::builder().is_test(true).try_init().unwrap_or(());
+            let key = generate_raw_store_key(None).expect("Error creating raw key");
+            let store = SqliteStoreOptions::in_memory()
+                .default_expiry_filter(ExpiryFilter::Include)
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "expired", b"value", None, Some(-60_000))
+                .await
+                .expect("Error inserting test row");
+            conn.insert("category", "live", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                2
+            );
+            assert!(conn
+                .fetch("category", "expired", false)
+                .await
+                .expect("Error fetching test row")
+                .is_some());
+
+            // a filter selected for the session takes precedence
+            conn.set_expiry_filter(ExpiryFilter::Exclude);
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                1
+            );
+            drop(conn);
+
+            let mut scan = store
+                .scan(None, "category".to_string(), None, None, None)
+                .await
+                .expect("Error starting scan");
+            let rows = scan
+                .fetch_next()
+                .await
+                .expect("Error fetching scan rows")
+                .expect("Expected rows");
+            assert_eq!(rows.len(), 2);
+            drop(scan);
+
+            // as does a filter selected for the scan
+            let mut scan = store
+                .scan_with_expiry(
+                    None,
+                    "category".to_string(),
+                    None,
+                    ExpiryFilter::Only,
+                    None,
+                    None,
+                )
+                .await
+                .expect("Error starting scan");
+            let rows = scan
+                .fetch_next()
+                .await
+                .expect("Error fetching scan rows")
+                .expect("Expected rows");
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].name, "expired");
+        })
+    }
+
+    #[test]
+    fn metrics() {
+        use aries_askar::{ErrorKind, InMemoryMetrics, WaitKind};
+        use std::sync::Arc;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let metrics = Arc::new(InMemoryMetrics::new());
+            let store = SqliteStoreOptions::in_memory()
+                .with_metrics(metrics.clone())
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            assert!(conn
+                .insert("category", "name", b"value", None, None)
+                .await
+                .is_err());
+            conn.fetch("category", "name", false)
+                .await
+                .expect("Error fetching test row");
+            conn.fetch("category", "missing", false)
+                .await
+                .expect("Error fetching test row");
+            assert_eq!(
+                conn.remove_all("category", None)
+                    .await
+                    .expect("Error removing test rows"),
+                1
+            );
+            drop(conn);
+
+            let snapshot = metrics.snapshot();
+            let insert = &snapshot.operations["insert"];
+            assert_eq!(insert.duration.count, 2);
+            assert_eq!(insert.rows, 1);
+            assert_eq!(insert.errors, vec![(ErrorKind::Duplicate, 1)]);
+            let fetch = &snapshot.operations["fetch"];
+            assert_eq!(fetch.duration.count, 2);
+            assert_eq!(fetch.rows, 1);
+            assert_eq!(fetch.error_count(), 0);
+            assert_eq!(snapshot.operations["remove_all"].rows, 1);
+            // the session acquires a single connection for all operations
+            assert_eq!(snapshot.waits[&WaitKind::Connection].count, 1);
+            // each insert starts a transaction
+            assert_eq!(snapshot.waits[&WaitKind::Lock].count, 2);
+
+            metrics.reset();
+            let mut txn = store
+                .transaction(None)
+                .await
+                .expect("Error starting transaction");
+            txn.count("category", None)
+                .await
+                .expect("Error performing count");
+            txn.commit().await.expect("Error committing transaction");
+            let snapshot = metrics.snapshot();
+            assert_eq!(snapshot.operations["count"].rows, 0);
+            assert_eq!(snapshot.waits[&WaitKind::Lock].count, 1);
+        })
+    }
+
+    async fn init_db() -> Store<SqliteStore> {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        SqliteStoreOptions::in_memory()
             .provision(StoreKeyMethod::RawKey, key, None, false)
             .await
             .expect("Error provisioning sqlite store")
     }
 
-    backend_tests!(init_db());
+    backend_tests!(init_db());
+
+    #[test]
+    fn info_store_key_method() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        block_on(async {
+            let store = init_db().await;
+            let info = store.info(false).await.expect("Error fetching store info");
+            assert_eq!(info.backend, "sqlite");
+            assert_eq!(info.store_key_method, StoreKeyMethod::RawKey);
+            assert_eq!(info.default_profile, store.get_profile_name());
+            assert_eq!(info.journal_mode.as_deref(), Some("memory"));
+        })
+    }
+
+    #[test]
+    fn apply_batch_idempotent() {
+        use aries_askar::{BatchUpdate, ErrorKind};
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        block_on(async {
+            let store = init_db().await;
+            let updates = vec![
+                BatchUpdate::insert("category", "one", b"value", None),
+                BatchUpdate::insert("category", "two", b"value", None),
+            ];
+            let result = store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+            assert_eq!(result.updates, 2);
+            assert!(!result.replayed);
+
+            // a retry returns the original result without inserting again
+            let result = store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+            assert_eq!(result.updates, 2);
+            assert!(result.replayed);
+
+            // the key is scoped to the profile
+            let profile = store
+                .create_profile(None)
+                .await
+                .expect("Error creating profile");
+            let result = store
+                .apply_batch(Some(profile), &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+            assert!(!result.replayed);
+
+            // a new key applies the updates, and a failed batch is not recorded
+            let err = store
+                .apply_batch(None, &updates, Some("request-2"))
+                .await
+                .expect_err("Expected duplicate error");
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+            let result = store
+                .apply_batch(
+                    None,
+                    &[BatchUpdate::remove("category", "one")],
+                    Some("request-2"),
+                )
+                .await
+                .expect("Error applying batch");
+            assert!(!result.replayed);
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                1
+            );
+        });
+
+        // keys are not retained beyond the configured period
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?batch_retention=0")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let updates = vec![BatchUpdate::insert("category", "one", b"value", None)];
+            store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+            store.purge_expired().await.expect("Error purging records");
+            let err = store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect_err("Expected duplicate error");
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+        });
+    }
+
+    #[test]
+    fn apply_batch_profiles() {
+        use aries_askar::{BatchUpdate, ErrorKind};
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        block_on(async {
+            let store = init_db().await;
+            let profile = store
+                .create_profile(None)
+                .await
+                .expect("Error creating profile");
+            let updates = vec![
+                BatchUpdate::insert("category", "one", b"value", None),
+                BatchUpdate::insert("category", "two", b"value", None).with_profile(&profile),
+                BatchUpdate::insert("category", "three", b"value", None),
+            ];
+            let result = store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+            assert_eq!(result.updates, 3);
+            let mut conn = store.session(None).await.expect("Error starting session");
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                2
+            );
+            drop(conn);
+            let mut conn = store
+                .session(Some(profile.clone()))
+                .await
+                .expect("Error starting session");
+            assert!(conn
+                .fetch("category", "two", false)
+                .await
+                .expect("Error fetching test row")
+                .is_some());
+            drop(conn);
+
+            // an unknown profile fails the batch before any update is applied
+            let updates = vec![
+                BatchUpdate::insert("category", "four", b"value", None),
+                BatchUpdate::insert("category", "five", b"value", None).with_profile("unknown"),
+            ];
+            let err = store
+                .apply_batch(None, &updates, None)
+                .await
+                .expect_err("Expected profile not found error");
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            assert!(err.message().unwrap_or_default().contains("'unknown'"));
+            let mut conn = store.session(None).await.expect("Error starting session");
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error performing count"),
+                2
+            );
+        });
+    }
+
+    #[test]
+    fn ingest_results() {
+        use aries_askar::{BatchUpdate, ErrorKind, IngestConfig};
+        use std::{sync::Arc, time::Duration};
+
+        block_on(async {
+            let store = Arc::new(init_db().await);
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name-3", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            let mut sink = store
+                .clone()
+                .ingest(IngestConfig {
+                    batch_size: 4,
+                    flush_interval: Duration::from_secs(60),
+                    max_workers: 2,
+                    ..Default::default()
+                })
+                .expect("Error starting ingestion");
+            for idx in 0..10 {
+                let sequence = sink
+                    .send(BatchUpdate::insert(
+                        "category",
+                        &format!("name-{}", idx),
+                        b"value",
+                        None,
+                    ))
+                    .await;
+                assert_eq!(sequence, idx);
+            }
+            let first = sink.next_result().await.expect("Expected result");
+            assert_eq!(first.sequence, 0);
+            let mut results = vec![first];
+            results.extend(sink.close().await);
+
+            // the batch holding the duplicate record is applied individually
+            assert_eq!(
+                results.iter().map(|r| r.sequence).collect::<Vec<_>>(),
+                (0..10).collect::<Vec<_>>()
+            );
+            for result in results {
+                if result.sequence == 3 {
+                    let err = result.result.expect_err("Expected duplicate error");
+                    assert_eq!(err.kind(), ErrorKind::Duplicate);
+                } else {
+                    result.result.expect("Error applying update");
+                }
+            }
+            let mut conn = store.session(None).await.expect("Error starting session");
+            let count = conn
+                .count("category", None)
+                .await
+                .expect("Error performing count");
+            assert_eq!(count, 10);
+
+            let err = store
+                .clone()
+                .ingest(IngestConfig {
+                    batch_size: 0,
+                    ..Default::default()
+                })
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Input);
+        });
+    }
+
+    #[test]
+    fn ingest_throughput() {
+        use aries_askar::{BatchUpdate, IngestConfig};
+        use std::sync::Arc;
+
+        const COUNT: u64 = 100_000;
+
+        block_on(async {
+            let store = Arc::new(init_db().await);
+            let mut sink = store
+                .clone()
+                .ingest(IngestConfig::default())
+                .expect("Error starting ingestion");
+            let mut failed = 0;
+            for idx in 0..COUNT {
+                sink.send(BatchUpdate::insert(
+                    "category",
+                    &format!("name-{}", idx),
+                    b"value",
+                    None,
+                ))
+                .await;
+                failed += sink
+                    .take_results()
+                    .into_iter()
+                    .filter(|r| r.result.is_err())
+                    .count();
+            }
+            failed += sink
+                .close()
+                .await
+                .into_iter()
+                .filter(|r| r.result.is_err())
+                .count();
+            assert_eq!(failed, 0);
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            let count = conn
+                .count("category", None)
+                .await
+                .expect("Error performing count");
+            assert_eq!(count, COUNT as i64);
+        });
+    }
+
+    #[test]
+    fn name_normalization() {
+        use aries_askar::{ErrorKind, NameNormalization};
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?name_normalization=lowercase")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            assert_eq!(store.name_normalization(), NameNormalization::Lowercase);
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("Category", "Did:Sov:ABC", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            let entry = conn
+                .fetch("category", "did:sov:abc", false)
+                .await
+                .expect("Error fetching test row")
+                .expect("Expected row");
+            assert_eq!(entry.name, "did:sov:abc");
+            assert_eq!(
+                conn.fetch_all("CATEGORY", None, None, false)
+                    .await
+                    .expect("Error fetching test rows")
+                    .len(),
+                1
+            );
+            let err = conn
+                .insert("category", "DID:SOV:abc", b"value", None, None)
+                .await
+                .expect_err("Expected duplicate error");
+            assert_eq!(err.kind(), ErrorKind::Duplicate);
+        });
+    }
+
+    #[test]
+    fn access_tracking() {
+        use aries_askar::future::sleep;
+        use std::time::Duration;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            assert!(store.scan_coldest(None, "category", 10).await.is_err());
+            store
+                .enable_access_tracking()
+                .await
+                .expect("Error enabling access tracking");
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for name in &["cold", "warm", "hot"] {
+                conn.insert("category", name, b"value", None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            for _ in 0..3 {
+                conn.fetch("category", "hot", false)
+                    .await
+                    .expect("Error fetching test row");
+            }
+            conn.fetch("category", "warm", false)
+                .await
+                .expect("Error fetching test row");
+            drop(conn);
+
+            // the counters are updated in the background
+            let mut coldest = vec![];
+            for _ in 0..50 {
+                coldest = store
+                    .scan_coldest(None, "category", 10)
+                    .await
+                    .expect("Error scanning coldest rows");
+                if coldest.iter().filter(|row| row.read_count > 0).count() == 2 {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(coldest.len(), 3);
+            assert_eq!(coldest[0].entry.name, "cold");
+            assert_eq!(coldest[0].read_count, 0);
+            assert_eq!(coldest[0].last_read_at, None);
+            // repeated reads within the update interval are deferred
+            assert!(coldest[1..]
+                .iter()
+                .all(|row| row.read_count == 1 && row.last_read_at.is_some()));
+
+            let coldest = store
+                .scan_coldest(None, "category", 1)
+                .await
+                .expect("Error scanning coldest rows");
+            assert_eq!(coldest.len(), 1);
+        });
+    }
+
+    #[test]
+    fn scan_page_bytes() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?scan_page_bytes=10000")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let value = vec![0u8; 3000];
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for idx in 0..20 {
+                conn.insert("category", &format!("name-{:02}", idx), &value, None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            drop(conn);
+
+            // a resumable scan returns the records in order of insertion
+            let mut scan = store
+                .scan_resumable(None, "category".to_string(), None)
+                .await
+                .expect("Error starting scan");
+            let mut names = vec![];
+            while let Some(rows) = scan.fetch_next().await.expect("Error fetching scan rows") {
+                // each page stops at the first record reaching the byte limit
+                assert!(!rows.is_empty() && rows.len() <= 4);
+                if names.is_empty() {
+                    let diag = store
+                        .diagnostics()
+                        .await
+                        .expect("Error fetching diagnostics");
+                    assert_eq!(diag.active_scans[0].max_page_bytes, rows.len() * 3000);
+                }
+                names.extend(rows.into_iter().map(|row| row.name));
+            }
+            let expected = (0..20)
+                .map(|idx| format!("name-{:02}", idx))
+                .collect::<Vec<_>>();
+            assert_eq!(names, expected);
+        });
+    }
+
+    #[test]
+    fn coalesce_fetches() {
+        use futures_lite::future::zip;
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?coalesce_fetches=true")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            let store = &store;
+            let fetch = || async move {
+                let mut conn = store.session(None).await.expect("Error starting session");
+                conn.fetch("category", "name", false)
+                    .await
+                    .expect("Error fetching test row")
+                    .expect("Expected row")
+            };
+            let ((a, b), (c, d)) = zip(zip(fetch(), fetch()), zip(fetch(), fetch())).await;
+            for row in [&b, &c, &d].iter() {
+                assert_eq!(**row, a);
+            }
+
+            // results are not retained once the fetch has completed
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.replace("category", "name", b"updated", None, None)
+                .await
+                .expect("Error replacing test row");
+            drop(conn);
+            let (a, b) = zip(fetch(), fetch()).await;
+            assert_eq!(a.value, b"updated"[..]);
+            assert_eq!(b.value, b"updated"[..]);
+
+            // a fetch within a transaction is not coalesced
+            let mut txn = store
+                .transaction(None)
+                .await
+                .expect("Error starting transaction");
+            txn.replace("category", "name", b"pending", None, None)
+                .await
+                .expect("Error replacing test row");
+            let row = txn
+                .fetch("category", "name", false)
+                .await
+                .expect("Error fetching test row")
+                .expect("Expected row");
+            assert_eq!(row.value, b"pending"[..]);
+            txn.rollback()
+                .await
+                .expect("Error rolling back transaction");
+        });
+    }
+
+    #[test]
+    fn entry_cache() {
+        use aries_askar::InMemoryMetrics;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        async fn fetch_value(store: &Store<SqliteStore>, name: &str) -> Option<Vec<u8>> {
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.fetch("category", name, false)
+                .await
+                .expect("Error fetching test row")
+                .map(|row| row.value.as_ref().to_vec())
+        }
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let metrics = Arc::new(InMemoryMetrics::new());
+            let store = SqliteStoreOptions::in_memory()
+                .entry_cache(2, Duration::from_secs(60))
+                .with_metrics(metrics.clone())
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for name in ["a", "b", "c"].iter() {
+                conn.insert("category", name, name.as_bytes(), None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            drop(conn);
+            let counts = || {
+                let snapshot = metrics.snapshot();
+                (snapshot.cache_hits, snapshot.cache_misses)
+            };
+
+            assert_eq!(fetch_value(&store, "a").await, Some(b"a".to_vec()));
+            assert_eq!(fetch_value(&store, "a").await, Some(b"a".to_vec()));
+            assert_eq!(counts(), (1, 1));
+
+            // writes invalidate the cached record
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.replace("category", "a", b"updated", None, None)
+                .await
+                .expect("Error replacing test row");
+            drop(conn);
+            assert_eq!(fetch_value(&store, "a").await, Some(b"updated".to_vec()));
+            assert_eq!(counts(), (1, 2));
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.remove("category", "a")
+                .await
+                .expect("Error removing test row");
+            drop(conn);
+            assert_eq!(fetch_value(&store, "a").await, None);
+
+            // the least recently used record is evicted
+            fetch_value(&store, "b").await;
+            fetch_value(&store, "c").await;
+            fetch_value(&store, "b").await;
+            metrics.reset();
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "a", b"a", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+            fetch_value(&store, "a").await;
+            fetch_value(&store, "b").await;
+            fetch_value(&store, "c").await;
+            assert_eq!(counts(), (1, 2));
+
+            // records written in a transaction are invalidated on commit
+            let mut txn = store
+                .transaction(None)
+                .await
+                .expect("Error starting transaction");
+            txn.replace("category", "a", b"committed", None, None)
+                .await
+                .expect("Error replacing test row");
+            txn.commit().await.expect("Error committing transaction");
+            assert_eq!(fetch_value(&store, "a").await, Some(b"committed".to_vec()));
+
+            metrics.reset();
+            store.clear_cache();
+            fetch_value(&store, "a").await;
+            assert_eq!(counts(), (0, 1));
+        });
+    }
+
+    #[test]
+    fn commit_metrics() {
+        use aries_askar::{BatchUpdate, InMemoryMetrics};
+        use std::sync::Arc;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let metrics = Arc::new(InMemoryMetrics::new());
+            let store = SqliteStoreOptions::in_memory()
+                .with_metrics(metrics.clone())
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            conn.replace("category", "name", b"updated", None, None)
+                .await
+                .expect("Error replacing test row");
+            drop(conn);
+            let updates = vec![
+                BatchUpdate::insert("category", "one", b"value", None),
+                BatchUpdate::insert("category", "two", b"value", None),
+            ];
+            store
+                .apply_batch(None, &updates, Some("request-1"))
+                .await
+                .expect("Error applying batch");
+
+            let snapshot = metrics.snapshot();
+            let insert = &snapshot.commits["insert"];
+            assert_eq!(insert.duration.count, 1);
+            assert_eq!(insert.statements, 1);
+            // the encrypted value is larger than the plaintext
+            assert!(insert.value_bytes > 5);
+            let replace = &snapshot.commits["replace"];
+            assert_eq!(replace.duration.count, 1);
+            assert_eq!(replace.statements, 2);
+            // the updates of a batch are committed together, with the idempotency key
+            let batch = &snapshot.commits["apply_batch"];
+            assert_eq!(batch.duration.count, 1);
+            assert_eq!(batch.statements, 3);
+            assert_eq!(batch.mean_statements(), 3.0);
+            assert_eq!(batch.value_bytes, 2 * insert.value_bytes);
+            assert!(batch.latency_percentile(0.95) <= batch.duration.max);
+            // the individual updates within the batch are not reported separately
+            assert_eq!(snapshot.commits.len(), 3);
+        });
+    }
+
+    #[test]
+    fn profile_quota() {
+        use aries_askar::{ErrorKind, ProfileQuota};
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::in_memory()
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let profile = Some(
+                store
+                    .create_profile(None)
+                    .await
+                    .expect("Error creating profile"),
+            );
+            store
+                .set_profile_quota(
+                    profile.clone(),
+                    ProfileQuota {
+                        max_items: Some(2),
+                        max_bytes: None,
+                    },
+                )
+                .await
+                .expect("Error setting profile quota");
+
+            let mut conn = store
+                .session(profile.clone())
+                .await
+                .expect("Error starting session");
+            for name in ["a", "b"].iter() {
+                conn.insert("category", name, b"value", None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            let err = conn
+                .insert("category", "c", b"value", None, None)
+                .await
+                .expect_err("Expected quota error");
+            assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+            let usage = store
+                .profile_usage(profile.clone())
+                .await
+                .expect("Error fetching profile usage");
+            assert_eq!(usage.items, 2);
+
+            // a replacement counts the difference in value sizes
+            conn.replace("category", "a", b"longer value", None, None)
+                .await
+                .expect("Error replacing test row");
+            let replaced = store
+                .profile_usage(profile.clone())
+                .await
+                .expect("Error fetching profile usage");
+            assert_eq!(replaced.items, 2);
+            assert_eq!(replaced.bytes, usage.bytes + 7);
+
+            // removing a record makes room for another
+            conn.remove("category", "b")
+                .await
+                .expect("Error removing test row");
+            conn.insert("category", "c", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+
+            let usage = store
+                .profile_usage(profile.clone())
+                .await
+                .expect("Error fetching profile usage");
+            store
+                .set_profile_quota(
+                    profile.clone(),
+                    ProfileQuota {
+                        max_items: None,
+                        max_bytes: Some(usage.bytes),
+                    },
+                )
+                .await
+                .expect("Error setting profile quota");
+            let err = conn
+                .insert("category", "d", b"value", None, None)
+                .await
+                .expect_err("Expected quota error");
+            assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+            drop(conn);
+
+            // other profiles are not limited
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for name in ["a", "b", "c"].iter() {
+                conn.insert("category", name, b"value", None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            drop(conn);
+
+            store
+                .rebuild_usage()
+                .await
+                .expect("Error rebuilding profile usage");
+            let rebuilt = store
+                .profile_usage(profile)
+                .await
+                .expect("Error fetching profile usage");
+            assert_eq!((rebuilt.items, rebuilt.bytes), (usage.items, usage.bytes));
+            assert_eq!(rebuilt.quota.max_bytes, Some(usage.bytes));
+        });
+    }
+
+    #[test]
+    fn max_entry_tags() {
+        use aries_askar::{EntryTag, ErrorKind};
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?max_entry_tags=2")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let tags = (0..3)
+                .map(|idx| EntryTag::Encrypted(format!("t{}", idx), "v".to_string()))
+                .collect::<Vec<_>>();
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "few", b"value", Some(&tags[..2]), None)
+                .await
+                .expect("Error inserting test row");
+            conn.insert("category", "many", b"value", Some(&tags), None)
+                .await
+                .expect("Error inserting test row");
+
+            let row = conn
+                .fetch("category", "few", false)
+                .await
+                .expect("Error fetching test row")
+                .expect("Expected row");
+            assert_eq!(row.tags.len(), 2);
+            let err = conn
+                .fetch("category", "many", false)
+                .await
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Unexpected);
+        });
+    }
+
+    #[test]
+    fn value_history() {
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        block_on(async move {
+            let store = SqliteStoreOptions::new("sqlite://:memory:?history_versions=2")
+                .expect("Error initializing sqlite store options")
+                .provision(StoreKeyMethod::RawKey, key, None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"v1", None, None)
+                .await
+                .expect("Error inserting test row");
+            conn.replace("category", "name", b"v2", None, None)
+                .await
+                .expect("Error replacing test row");
+            // no history is retained until it is enabled
+            assert!(conn
+                .fetch_history("category", "name", None)
+                .await
+                .expect("Error fetching history")
+                .is_empty());
+            drop(conn);
+            store
+                .enable_value_history()
+                .await
+                .expect("Error enabling value history");
+
+            let mut conn = store.session(None).await.expect("Error starting session");
+            for value in [&b"v3"[..], b"v4"].iter() {
+                conn.replace("category", "name", value, None, None)
+                    .await
+                    .expect("Error replacing test row");
+            }
+            conn.remove("category", "name")
+                .await
+                .expect("Error removing test row");
+            let history = conn
+                .fetch_history("category", "name", None)
+                .await
+                .expect("Error fetching history");
+            assert_eq!(
+                history
+                    .iter()
+                    .map(|version| version.value.as_ref())
+                    .collect::<Vec<_>>(),
+                vec![&b"v4"[..], b"v3", b"v2"]
+            );
+            assert!(history[0].replaced_at >= history[1].replaced_at);
+            let history = conn
+                .fetch_history("category", "name", Some(1))
+                .await
+                .expect("Error fetching history");
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].value, b"v4"[..]);
+            // the record may be inserted again once removed
+            conn.insert("category", "name", b"v5", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            // versions beyond the limit are removed by maintenance
+            store.purge_expired().await.expect("Error purging");
+            let mut conn = store.session(None).await.expect("Error starting session");
+            let history = conn
+                .fetch_history("category", "name", None)
+                .await
+                .expect("Error fetching history");
+            assert_eq!(history.len(), 2);
+            assert_eq!(history[1].value, b"v3"[..]);
+        });
+    }
+
+    #[cfg(feature = "blob_store")]
+    #[test]
+    fn blob_store_values() {
+        use aries_askar::{EncryptionPolicy, Entry, ErrorKind, ProfileQuota};
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let blob_dir = std::env::temp_dir().join(format!(
+            "sqlite-test-blobs-{}",
+            uuid::Uuid::new_v4().to_string()
+        ));
+        let blob_count = |dir: &Path| {
+            std::fs::read_dir(dir)
+                .map(|entries| entries.count())
+                .unwrap_or(0)
+        };
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async {
+            let db_url = format!(
+                "sqlite://:memory:?blob_dir={}&blob_threshold=64",
+                blob_dir.to_str().unwrap()
+            );
+            let db = db_url
+                .as_str()
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning store");
+
+            let large = Entry::new("category", "large", vec![7u8; 1024], Vec::new());
+            let small = Entry::new("category", "small", "value", Vec::new());
+
+            let mut conn = db.session(None).await.expect("Error starting session");
+            for entry in [&large, &small].iter() {
+                conn.insert(
+                    &entry.category,
+                    &entry.name,
+                    &entry.value,
+                    Some(entry.tags.as_slice()),
+                    None,
+                )
+                .await
+                .expect("Error inserting test row");
+            }
+            assert_eq!(blob_count(&blob_dir), 1);
+
+            let row = conn
+                .fetch(&large.category, &large.name, false)
+                .await
+                .expect("Error loading test row");
+            assert_eq!(row, Some(large.clone()));
+            let rows = conn
+                .fetch_all(&large.category, None, None, false)
+                .await
+                .expect("Error loading test rows");
+            assert_eq!(rows.len(), 2);
+
+            conn.replace(&large.category, &large.name, &[8u8; 2048], None, None)
+                .await
+                .expect("Error replacing test row");
+            assert_eq!(blob_count(&blob_dir), 1);
+
+            let mut txn = db
+                .transaction(None)
+                .await
+                .expect("Error starting transaction");
+            txn.remove(&large.category, &large.name)
+                .await
+                .expect("Error removing test row");
+            txn.rollback()
+                .await
+                .expect("Error rolling back transaction");
+            assert_eq!(blob_count(&blob_dir), 1);
+
+            conn.remove(&large.category, &large.name)
+                .await
+                .expect("Error removing test row");
+            assert_eq!(blob_count(&blob_dir), 0);
+            drop(conn);
+
+            // an unencrypted value could imitate the reference to a sidecar file
+            let err = db
+                .create_profile_with_policy(
+                    None,
+                    EncryptionPolicy {
+                        value: false,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect_err("Expected unsupported policy");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+            // the size of a value in a sidecar file is not counted in the usage
+            let err = db
+                .set_profile_quota(
+                    None,
+                    ProfileQuota {
+                        max_bytes: Some(1024),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect_err("Expected unsupported quota");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        });
+
+        std::fs::remove_dir_all(&blob_dir).unwrap_or(());
+    }
+
+    #[cfg(feature = "unsafe_queries")]
+    #[test]
+    fn raw_readonly_query() {
+        use aries_askar::{sqlite::RawValue, ErrorKind};
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "sqlite-test-{}.db",
+                uuid::Uuid::new_v4().to_string()
+            ))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+
+        block_on(async {
+            let err = init_db()
+                .await
+                .query_raw_readonly(None, "SELECT 1", vec![])
+                .await
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+            let db = SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .provision_backend(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            let mut conn = db.session(None).await.expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+            let other = db
+                .create_profile(None)
+                .await
+                .expect("Error creating profile");
+            let mut conn = db
+                .session(Some(other.clone()))
+                .await
+                .expect("Error starting session");
+            conn.insert("category", "other", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+
+            // only the records of the selected profile are visible
+            let rows = db
+                .query_raw_readonly(None, "SELECT COUNT(*) FROM items", vec![])
+                .await
+                .expect("Error running raw query");
+            assert_eq!(rows, vec![vec![RawValue::Integer(1)]]);
+            let rows = db
+                .query_raw_readonly(Some(other), "SELECT COUNT(*) FROM items", vec![])
+                .await
+                .expect("Error running raw query");
+            assert_eq!(rows, vec![vec![RawValue::Integer(1)]]);
+
+            let rows = db
+                .query_raw_readonly(
+                    None,
+                    "SELECT COUNT(*), ?2, value FROM items WHERE profile_id = ?1",
+                    vec![RawValue::Text("param".to_string())],
+                )
+                .await
+                .expect("Error running raw query");
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0][0], RawValue::Integer(1));
+            assert_eq!(rows[0][1], RawValue::Text("param".to_string()));
+            // values are returned encrypted
+            match &rows[0][2] {
+                RawValue::Blob(value) => assert_ne!(&value[..], b"value"),
+                other => panic!("Unexpected value: {:?}", other),
+            }
+
+            let err = db
+                .query_raw_readonly(
+                    None,
+                    "WITH ids AS (SELECT id FROM items) DELETE FROM items WHERE id IN ids",
+                    vec![],
+                )
+                .await
+                .expect_err("Expected error");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+            for sql in &[
+                "SELECT * FROM config",
+                "SELECT profile_key FROM \"profiles\"",
+                "SELECT COUNT(*) FROM main.items",
+                "SELECT 1; DELETE FROM items",
+                "DELETE FROM items",
+            ] {
+                let err = db
+                    .query_raw_readonly(None, sql, vec![])
+                    .await
+                    .expect_err("Expected error");
+                assert_eq!(err.kind(), ErrorKind::Input);
+            }
+
+            let mut conn = db.session(None).await.expect("Error starting session");
+            conn.remove("category", "name")
+                .await
+                .expect("Error removing test row");
+            drop(conn);
+            db.close().await.expect("Error closing sqlite store");
+            SqliteStoreOptions::new(fname.as_str())
+                .expect("Error initializing sqlite store options")
+                .remove_backend()
+                .await
+                .expect("Error removing sqlite store");
+        });
+    }
+
+    #[cfg(feature = "unsafe_queries")]
+    #[test]
+    fn row_id_repair() {
+        block_on(async {
+            let db = init_db().await;
+            let mut conn = db.session(None).await.expect("Error starting session");
+            for name in &["first", "second"] {
+                conn.insert("category", name, b"value", None, None)
+                    .await
+                    .expect("Error inserting test row");
+            }
+            drop(conn);
+
+            let mut scan = db
+                .scan_with_row_ids(None, "category".to_string(), None)
+                .await
+                .expect("Error starting scan");
+            let rows = scan
+                .fetch_next()
+                .await
+                .expect("Error fetching scan rows")
+                .expect("Expected rows");
+            assert_eq!(rows.len(), 2);
+            assert!(rows[0].0 < rows[1].0);
+            let (row_id, entry) = &rows[1];
+            assert_eq!(entry.name, "second");
+
+            let raw = db
+                .fetch_raw_by_row_id(None, *row_id)
+                .await
+                .expect("Error fetching raw row")
+                .expect("Expected raw row");
+            assert_eq!(raw.id, *row_id);
+            // the record is returned encrypted
+            assert_ne!(&raw.name[..], b"second");
+            assert_ne!(&raw.value[..], b"value");
+
+            assert!(db
+                .remove_by_row_id(None, *row_id)
+                .await
+                .expect("Error removing row"));
+            assert!(!db
+                .remove_by_row_id(None, *row_id)
+                .await
+                .expect("Error removing row"));
+            assert!(db
+                .fetch_raw_by_row_id(None, *row_id)
+                .await
+                .expect("Error fetching raw row")
+                .is_none());
+            let mut conn = db.session(None).await.expect("Error starting session");
+            assert_eq!(
+                conn.count("category", None)
+                    .await
+                    .expect("Error counting rows"),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn id_source() {
+        use aries_askar::{IdSource, SequentialIds};
+        use std::sync::Arc;
+
+        env_logger::builder().is_test(true).try_init().unwrap_or(());
+        let key = generate_raw_store_key(None).expect("Error creating raw key");
+        let source: Arc<dyn IdSource> = Arc::new(SequentialIds::new(10));
+
+        block_on(async {
+            let (db, result) = SqliteStoreOptions::in_memory()
+                .id_source(source.clone())
+                .provision_with_result(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            assert_eq!(result.profile_id(), 10);
+
+            // created profiles draw the next ID from the same source
+            let other = db
+                .create_profile(None)
+                .await
+                .expect("Error creating profile");
+            let mut conn = db
+                .session(Some(other))
+                .await
+                .expect("Error starting session");
+            conn.insert("category", "name", b"value", None, None)
+                .await
+                .expect("Error inserting test row");
+            drop(conn);
+            assert_eq!(source.next_profile_id(), Some(12));
+
+            // stores provisioned from identical sources are assigned identical IDs
+            let (_db, result) = SqliteStoreOptions::in_memory()
+                .id_source(Arc::new(SequentialIds::new(10)))
+                .provision_with_result(StoreKeyMethod::RawKey, key.as_ref(), None, false)
+                .await
+                .expect("Error provisioning sqlite store");
+            assert_eq!(result.profile_id(), 10);
+        });
+    }
+
+    #[test]
+    fn kv_store_contract() {
+        block_on(async {
+            let db = init_db().await;
+            super::contract::kv_store_contract(&db).await;
+        });
+    }
 
     #[test]
     fn provision_from_str() {
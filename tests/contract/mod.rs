@@ -0,0 +1,212 @@
+use aries_askar::{Entry, EntryOperation, EntryTag, ErrorKind, IntTagValue, KvStore, TagFilter};
+
+const ERR_FETCH: &'static str = "Error fetching test row";
+const ERR_REQ_ROW: &'static str = "Expected row";
+const ERR_REQ_ERR: &'static str = "Expected error";
+const ERR_INSERT: &'static str = "Error inserting test row";
+const ERR_REPLACE: &'static str = "Error replacing test row";
+const ERR_REMOVE: &'static str = "Error removing test row";
+const ERR_SCAN: &'static str = "Error scanning test rows";
+const ERR_COUNT: &'static str = "Error performing count";
+
+fn test_tags(index: i64) -> Vec<EntryTag> {
+    vec![
+        EntryTag::Encrypted(
+            "color".to_string(),
+            if index % 2 == 0 { "blue" } else { "red" }.to_string(),
+        ),
+        EntryTag::Plaintext("label".to_string(), format!("item-{}", index)),
+        EntryTag::Int("index".to_string(), IntTagValue::new(index)),
+    ]
+}
+
+/// Run the checks shared by all implementations of `KvStore` against an
+/// empty store
+pub async fn kv_store_contract<S: KvStore>(store: &S) {
+    kv_fetch_update(store).await;
+    kv_scan_count(store).await;
+}
+
+async fn kv_fetch_update<S: KvStore>(store: &S) {
+    assert!(store.fetch("cat", "name").await.expect(ERR_FETCH).is_none());
+
+    let test_row = Entry::new("cat", "name", "value", test_tags(1));
+    store
+        .update(
+            EntryOperation::Insert,
+            &test_row.category,
+            &test_row.name,
+            Some(test_row.value.as_ref()),
+            Some(test_row.tags.as_slice()),
+        )
+        .await
+        .expect(ERR_INSERT);
+    let row = store
+        .fetch(&test_row.category, &test_row.name)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row, test_row);
+
+    // records are not visible in other categories
+    assert!(store
+        .fetch("other", &test_row.name)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+
+    let err = store
+        .update(
+            EntryOperation::Insert,
+            &test_row.category,
+            &test_row.name,
+            Some(&b"other"[..]),
+            None,
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+    let upd_row = Entry::new("cat", "name", "updated", test_tags(2));
+    store
+        .update(
+            EntryOperation::Replace,
+            &upd_row.category,
+            &upd_row.name,
+            Some(upd_row.value.as_ref()),
+            Some(upd_row.tags.as_slice()),
+        )
+        .await
+        .expect(ERR_REPLACE);
+    let row = store
+        .fetch(&upd_row.category, &upd_row.name)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row, upd_row);
+
+    let err = store
+        .update(
+            EntryOperation::Replace,
+            "cat",
+            "missing",
+            Some(&b"value"[..]),
+            None,
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    store
+        .update(EntryOperation::Remove, "cat", "name", None, None)
+        .await
+        .expect(ERR_REMOVE);
+    assert!(store.fetch("cat", "name").await.expect(ERR_FETCH).is_none());
+
+    let err = store
+        .update(EntryOperation::Remove, "cat", "name", None, None)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+async fn kv_scan_count<S: KvStore>(store: &S) {
+    for index in 0..10 {
+        store
+            .update(
+                EntryOperation::Insert,
+                "scan",
+                &format!("name-{}", index),
+                Some(format!("value-{}", index).as_bytes()),
+                Some(test_tags(index).as_slice()),
+            )
+            .await
+            .expect(ERR_INSERT);
+    }
+    store
+        .update(
+            EntryOperation::Insert,
+            "other",
+            "name-0",
+            Some(&b"value"[..]),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+
+    assert_eq!(store.count("scan", None).await.expect(ERR_COUNT), 10);
+    assert_eq!(store.count("missing", None).await.expect(ERR_COUNT), 0);
+    assert_eq!(
+        store
+            .count("scan", Some(TagFilter::is_eq("color", "blue")))
+            .await
+            .expect(ERR_COUNT),
+        5
+    );
+    assert_eq!(
+        store
+            .count(
+                "scan",
+                Some(TagFilter::all_of(vec![
+                    TagFilter::is_eq("color", "red"),
+                    TagFilter::is_gte("~index", "5"),
+                ]))
+            )
+            .await
+            .expect(ERR_COUNT),
+        3
+    );
+    assert_eq!(
+        store
+            .count("scan", Some(TagFilter::is_like("~label", "item-%")))
+            .await
+            .expect(ERR_COUNT),
+        10
+    );
+    assert_eq!(
+        store
+            .count(
+                "scan",
+                Some(TagFilter::not(TagFilter::is_in(
+                    "~label",
+                    vec!["item-1".to_string(), "item-2".to_string()]
+                )))
+            )
+            .await
+            .expect(ERR_COUNT),
+        8
+    );
+
+    let rows = store
+        .scan("scan", Some(TagFilter::is_lt("~index", "3")), None, None)
+        .await
+        .expect(ERR_SCAN);
+    let mut names = rows.iter().map(|row| row.name.clone()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["name-0", "name-1", "name-2"]);
+    for row in rows {
+        let index = row.name["name-".len()..].parse::<i64>().unwrap();
+        assert_eq!(
+            row,
+            Entry::new(
+                "scan",
+                row.name.clone(),
+                format!("value-{}", index),
+                test_tags(index)
+            )
+        );
+    }
+
+    let mut names = vec![];
+    for offset in (0..10).step_by(4) {
+        let rows = store
+            .scan("scan", None, Some(offset), Some(4))
+            .await
+            .expect(ERR_SCAN);
+        assert_eq!(rows.len(), if offset < 8 { 4 } else { 2 });
+        names.extend(rows.into_iter().map(|row| row.name));
+    }
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), 10);
+}
@@ -1,4 +1,15 @@
-use aries_askar::{Backend, Entry, EntryTag, ErrorKind, Store, TagFilter};
+use std::time::Duration;
+
+use aries_askar::{
+    crypto::alg::Chacha20Types,
+    generate_raw_store_key,
+    kms::{KeyAlg, LocalKey},
+    AccessEvent, Backend, CategorySpec, ConditionalFetch, DecryptionAuditOptions, EncryptionPolicy,
+    Entry, EntryOperation, EntryTag, ErrorKind, ExpiryFilter, ExpiryUpdate, FilteredFetch,
+    HealthStatus, ImportConflict, IntTagValue, KeyValue, ScanCursor, SeparatorSplitter, Store,
+    StoreKeyMethod, TagFilter, TagRetrieval, WqlDialect,
+};
+use futures_lite::future::zip;
 
 const ERR_PROFILE: &'static str = "Error creating profile";
 const ERR_SESSION: &'static str = "Error starting session";
@@ -10,9 +21,14 @@ const ERR_REQ_ROW: &'static str = "Expected row";
 const ERR_REQ_ERR: &'static str = "Expected error";
 const ERR_INSERT: &'static str = "Error inserting test row";
 const ERR_REPLACE: &'static str = "Error replacing test row";
+const ERR_TAKE: &'static str = "Error taking test row";
 const ERR_REMOVE_ALL: &'static str = "Error removing test rows";
 const ERR_SCAN: &'static str = "Error starting scan";
 const ERR_SCAN_NEXT: &'static str = "Error fetching scan rows";
+const ERR_LIST_KEYS: &'static str = "Error listing keys";
+const ERR_TRUNCATE: &'static str = "Error truncating profile";
+const ERR_NAMESPACE: &'static str = "Error creating namespace";
+const ERR_LIST_CATEGORIES: &'static str = "Error listing categories";
 // const ERR_CREATE_KEYPAIR: &'static str = "Error creating keypair";
 // const ERR_FETCH_KEY: &'static str = "Error fetching key";
 // const ERR_SIGN: &'static str = "Error signing message";
@@ -34,6 +50,190 @@ pub async fn db_create_remove_profile<DB: Backend>(db: &Store<DB>) {
     );
 }
 
+pub async fn db_list_profiles<DB: Backend>(db: &Store<DB>) {
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let profiles = db.list_profiles().await.expect("Error listing profiles");
+    assert!(profiles.iter().any(|name| name == db.get_profile_name()));
+    assert!(profiles.contains(&profile));
+    let mut sorted = profiles.clone();
+    sorted.sort();
+    assert_eq!(profiles, sorted);
+
+    db.remove_profile(profile.clone())
+        .await
+        .expect("Error removing profile");
+    let profiles = db.list_profiles().await.expect("Error listing profiles");
+    assert!(!profiles.contains(&profile));
+}
+
+pub async fn db_vacuum<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..10 {
+        conn.insert("vacuum", &format!("name-{}", idx), &[0u8; 1024], None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    conn.remove_all("vacuum", None).await.expect(ERR_REMOVE_ALL);
+    conn.insert("vacuum", "kept", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
+
+    db.vacuum().await.expect("Error vacuuming store");
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(conn.count("vacuum", None).await.expect(ERR_COUNT), 1);
+    let row = conn
+        .fetch("vacuum", "kept", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"value");
+}
+
+pub async fn db_encryption_policy<DB: Backend>(db: &Store<DB>) {
+    let policies = [
+        EncryptionPolicy {
+            value: false,
+            ..Default::default()
+        },
+        EncryptionPolicy {
+            category: false,
+            name: false,
+            ..Default::default()
+        },
+        EncryptionPolicy {
+            tags: false,
+            ..Default::default()
+        },
+        EncryptionPolicy {
+            category: false,
+            name: false,
+            value: false,
+            tags: false,
+        },
+    ];
+
+    for policy in policies.iter() {
+        let profile = db
+            .create_profile_with_policy(None, *policy)
+            .await
+            .expect(ERR_PROFILE);
+        let test_row = Entry::new(
+            "category",
+            "name",
+            "value",
+            vec![
+                EntryTag::Encrypted("t1".to_string(), "v1".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "v2".to_string()),
+            ],
+        );
+
+        let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+
+        conn.insert(
+            &test_row.category,
+            &test_row.name,
+            &test_row.value,
+            Some(test_row.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+
+        let row = conn
+            .fetch(&test_row.category, &test_row.name, false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW);
+        assert_eq!(row, test_row, "Policy: {:?}", policy);
+
+        let rows = conn
+            .fetch_all(
+                &test_row.category,
+                Some(TagFilter::all_of(vec![
+                    TagFilter::is_eq("t1", "v1"),
+                    TagFilter::is_eq("~t2", "v2"),
+                ])),
+                None,
+                false,
+            )
+            .await
+            .expect(ERR_FETCH_ALL);
+        assert_eq!(rows, vec![test_row.clone()], "Policy: {:?}", policy);
+
+        let count = conn
+            .count(&test_row.category, Some(TagFilter::is_eq("t1", "other")))
+            .await
+            .expect(ERR_COUNT);
+        assert_eq!(count, 0, "Policy: {:?}", policy);
+
+        let replace_row = Entry::new(
+            "category",
+            "name",
+            "new value",
+            vec![EntryTag::Encrypted("t1".to_string(), "v3".to_string())],
+        );
+        conn.replace(
+            &replace_row.category,
+            &replace_row.name,
+            &replace_row.value,
+            Some(replace_row.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect(ERR_REPLACE);
+        drop(conn);
+
+        let mut scan = db
+            .scan(
+                Some(profile.clone()),
+                replace_row.category.clone(),
+                Some(TagFilter::is_eq("t1", "v3")),
+                None,
+                None,
+            )
+            .await
+            .expect(ERR_SCAN);
+        let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
+        assert_eq!(
+            rows,
+            Some(vec![replace_row.clone()]),
+            "Policy: {:?}",
+            policy
+        );
+
+        let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+        conn.remove(&replace_row.category, &replace_row.name)
+            .await
+            .expect(ERR_REQ_ROW);
+        let row = conn
+            .fetch(&replace_row.category, &replace_row.name, false)
+            .await
+            .expect(ERR_FETCH);
+        assert_eq!(row, None, "Policy: {:?}", policy);
+
+        // stored keys are always encrypted
+        let key = LocalKey::generate(KeyAlg::Ed25519, false).expect("Error creating key");
+        conn.insert_key("key", &key, None, None, None)
+            .await
+            .expect("Error inserting key");
+        let found = conn
+            .fetch_key("key", false)
+            .await
+            .expect("Error fetching key")
+            .expect(ERR_REQ_ROW)
+            .load_local_key()
+            .expect("Error loading key");
+        assert_eq!(
+            found.to_jwk_secret().expect("Error encoding key"),
+            key.to_jwk_secret().expect("Error encoding key"),
+            "Policy: {:?}",
+            policy
+        );
+    }
+}
+
 pub async fn db_fetch_fail<DB: Backend>(db: &Store<DB>) {
     let mut conn = db.session(None).await.expect(ERR_SESSION);
     let result = conn.fetch("cat", "name", false).await.expect(ERR_FETCH);
@@ -78,6 +278,124 @@ pub async fn db_insert_fetch<DB: Backend>(db: &Store<DB>) {
     assert_eq!(rows[0], test_row);
 }
 
+pub async fn db_insert_fetch_tag_flags<DB: Backend>(db: &Store<DB>) {
+    // the same tag name may be used for both plaintext and encrypted tags
+    let tags = vec![
+        EntryTag::Encrypted("t1".to_string(), "v1".to_string()),
+        EntryTag::Plaintext("t1".to_string(), "v2".to_string()),
+        EntryTag::Plaintext("t2".to_string(), "v3".to_string()),
+        EntryTag::Encrypted("t3".to_string(), "v4".to_string()),
+    ];
+    let expected = tags
+        .iter()
+        .map(|tag| (tag.value().to_string(), tag.is_plaintext()))
+        .collect::<Vec<_>>();
+    let check_flags = |tags: &[EntryTag]| {
+        let mut found = tags
+            .iter()
+            .map(|tag| (tag.value().to_string(), tag.is_plaintext()))
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, expected);
+    };
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.insert("category", "name", b"value", Some(tags.as_slice()), None)
+        .await
+        .expect(ERR_INSERT);
+
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    check_flags(&row.tags);
+
+    let rows = conn
+        .fetch_all("category", Some(TagFilter::is_eq("~t1", "v2")), None, false)
+        .await
+        .expect(ERR_FETCH_ALL);
+    assert_eq!(rows.len(), 1);
+    check_flags(&rows[0].tags);
+    drop(conn);
+
+    let mut scan = db
+        .scan(None, "category".to_string(), None, None, None)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(rows.len(), 1);
+    check_flags(&rows[0].tags);
+}
+
+pub async fn db_insert_fetch_value_type<DB: Backend>(db: &Store<DB>) {
+    let typed_row = Entry::new("category", "typed", "{}", Vec::new())
+        .with_value_type(Some("application/json".to_string()));
+    let plain_row = Entry::new("category", "plain", "value", Vec::new());
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.update_typed(
+        EntryOperation::Insert,
+        &typed_row.category,
+        &typed_row.name,
+        Some(&typed_row.value),
+        typed_row.value_type.as_deref(),
+        None,
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+    conn.insert(
+        &plain_row.category,
+        &plain_row.name,
+        &plain_row.value,
+        None,
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    let row = conn
+        .fetch(&typed_row.category, &typed_row.name, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row, typed_row);
+    assert_eq!(row.value_type, typed_row.value_type);
+
+    let row = conn
+        .fetch(&plain_row.category, &plain_row.name, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.value_type, None);
+
+    let rows = conn
+        .fetch_all(&typed_row.category, None, None, false)
+        .await
+        .expect(ERR_FETCH_ALL);
+    assert_eq!(rows.len(), 2);
+    assert!(rows.contains(&typed_row));
+    assert!(rows.contains(&plain_row));
+
+    // replacing the value without a type clears the previous type
+    conn.replace(&typed_row.category, &typed_row.name, b"[]", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    let row = conn
+        .fetch(&typed_row.category, &typed_row.name, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.value_type, None);
+}
+
 pub async fn db_insert_duplicate<DB: Backend>(db: &Store<DB>) {
     let test_row = Entry::new("category", "name", "value", Vec::new());
 
@@ -126,15 +444,13 @@ pub async fn db_insert_remove<DB: Backend>(db: &Store<DB>) {
         .expect(ERR_REQ_ROW);
 }
 
-pub async fn db_remove_missing<DB: Backend>(db: &Store<DB>) {
-    let mut conn = db.session(None).await.expect(ERR_SESSION);
-
-    let err = conn.remove("cat", "name").await.expect_err(ERR_REQ_ERR);
-    assert_eq!(err.kind(), ErrorKind::NotFound);
-}
-
-pub async fn db_replace_fetch<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+pub async fn db_take<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new(
+        "category",
+        "name",
+        "value",
+        vec![EntryTag::Encrypted("t1".to_string(), "v1".to_string())],
+    );
 
     let mut conn = db.session(None).await.expect(ERR_SESSION);
 
@@ -148,568 +464,3518 @@ pub async fn db_replace_fetch<DB: Backend>(db: &Store<DB>) {
     .await
     .expect(ERR_INSERT);
 
-    let mut replace_row = test_row.clone();
-    replace_row.value = "new value".into();
-    conn.replace(
-        &replace_row.category,
-        &replace_row.name,
-        &replace_row.value,
-        Some(replace_row.tags.as_slice()),
-        None,
-    )
-    .await
-    .expect(ERR_REPLACE);
-
     let row = conn
-        .fetch(&replace_row.category, &replace_row.name, false)
+        .take(&test_row.category, &test_row.name)
         .await
-        .expect(ERR_FETCH)
+        .expect(ERR_TAKE)
         .expect(ERR_REQ_ROW);
-    assert_eq!(row, replace_row);
-}
-
-pub async fn db_replace_missing<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
-
-    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(row, test_row);
 
-    let err = conn
-        .replace(
-            &test_row.category,
-            &test_row.name,
-            &test_row.value,
-            Some(test_row.tags.as_slice()),
-            None,
-        )
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, false)
         .await
-        .expect_err(ERR_REQ_ERR);
-    assert_eq!(err.kind(), ErrorKind::NotFound);
-}
-
-pub async fn db_count<DB: Backend>(db: &Store<DB>) {
-    let category = "category".to_string();
-    let test_rows = vec![Entry::new(&category, "name", "value", Vec::new())];
+        .expect(ERR_FETCH);
+    assert_eq!(row, None);
 
-    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let row = conn
+        .take(&test_row.category, &test_row.name)
+        .await
+        .expect(ERR_TAKE);
+    assert_eq!(row, None);
 
-    for upd in test_rows.iter() {
+    for idx in 0..3 {
         conn.insert(
-            &upd.category,
-            &upd.name,
-            &upd.value,
-            Some(upd.tags.as_slice()),
+            "queue",
+            &format!("item-{}", idx),
+            b"value",
+            Some(&[EntryTag::Plaintext("idx".to_string(), idx.to_string())]),
             None,
         )
         .await
         .expect(ERR_INSERT);
     }
 
-    let tag_filter = None;
-    let count = conn.count(&category, tag_filter).await.expect(ERR_COUNT);
+    // the oldest matching record is taken first
+    let filter = || Some(TagFilter::is_gte("~idx", "1"));
+    let row = conn
+        .take_first("queue", filter())
+        .await
+        .expect(ERR_TAKE)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.name, "item-1");
+    let row = conn
+        .take_first("queue", filter())
+        .await
+        .expect(ERR_TAKE)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.name, "item-2");
+    let row = conn.take_first("queue", filter()).await.expect(ERR_TAKE);
+    assert_eq!(row, None);
+
+    let count = conn.count("queue", None).await.expect(ERR_COUNT);
     assert_eq!(count, 1);
+}
 
-    let tag_filter = Some(TagFilter::is_eq("sometag", "someval"));
-    let count = conn.count(&category, tag_filter).await.expect(ERR_COUNT);
-    assert_eq!(count, 0);
+pub async fn db_take_concurrent<DB: Backend>(db: &Store<DB>) {
+    const ITEM_COUNT: usize = 40;
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..ITEM_COUNT {
+        conn.insert("queue", &format!("item-{}", idx), b"value", None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    drop(conn);
+
+    let worker = move || async move {
+        let mut taken = vec![];
+        loop {
+            let mut conn = db.session(None).await.expect(ERR_SESSION);
+            if let Some(row) = conn.take_first("queue", None).await.expect(ERR_TAKE) {
+                taken.push(row.name);
+            } else {
+                break;
+            }
+        }
+        taken
+    };
+    let ((a, b), (c, d)) = zip(zip(worker(), worker()), zip(worker(), worker())).await;
+
+    let mut taken = [a, b, c, d].concat();
+    taken.sort();
+    let mut expected = (0..ITEM_COUNT)
+        .map(|idx| format!("item-{}", idx))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(taken, expected);
 }
 
-pub async fn db_count_exist<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new(
-        "category",
-        "name",
-        "value",
-        vec![
-            EntryTag::Encrypted("enc".to_string(), "v1".to_string()),
-            EntryTag::Plaintext("plain".to_string(), "v2".to_string()),
-        ],
-    );
+pub async fn db_scan_prefix<DB: Backend>(db: &Store<DB>) {
+    let scan_names = |prefix: &'static str| async move {
+        let mut scan = db
+            .scan_prefix(
+                None,
+                "msg".to_string(),
+                prefix.to_string(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .expect(ERR_SCAN);
+        let mut names = vec![];
+        while let Some(rows) = scan.fetch_next().await.expect(ERR_SCAN_NEXT) {
+            names.extend(rows.into_iter().map(|row| row.name));
+        }
+        names.sort();
+        names
+    };
 
     let mut conn = db.session(None).await.expect(ERR_SESSION);
 
-    conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
-    )
-    .await
-    .expect(ERR_INSERT);
+    // added to the index when it is enabled
+    conn.insert("msg", "conn/1/msg/1", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec!["enc".to_string()]))
+    let err = db
+        .scan_prefix(
+            None,
+            "msg".to_string(),
+            "conn/1/".to_string(),
+            None,
+            None,
+            None,
         )
         .await
-        .expect(ERR_COUNT),
-        1
-    );
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec!["~plain".to_string()]))
-        )
+    db.enable_name_index()
         .await
-        .expect(ERR_COUNT),
-        1
-    );
+        .expect("Error enabling name index");
+
+    for name in &[
+        "conn/1/msg/2",
+        "conn/1/msg/3",
+        "conn/1/state",
+        "conn/2/msg/1",
+    ] {
+        conn.insert("msg", name, b"value", None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    conn.insert("other", "conn/1/msg/4", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    conn.replace("msg", "conn/1/msg/2", b"updated", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    conn.remove("msg", "conn/1/msg/3")
+        .await
+        .expect("Error removing test row");
+    drop(conn);
 
     assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec!["~enc".to_string()]))
-        )
-        .await
-        .expect(ERR_COUNT),
-        0
+        scan_names("conn/1/").await,
+        vec!["conn/1/msg/1", "conn/1/msg/2", "conn/1/state"]
     );
-
     assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec!["plain".to_string()]))
-        )
-        .await
-        .expect(ERR_COUNT),
-        0
+        scan_names("conn/1/msg/").await,
+        vec!["conn/1/msg/1", "conn/1/msg/2"]
     );
+    assert_eq!(scan_names("conn/").await.len(), 4);
+    assert!(scan_names("conn/3/").await.is_empty());
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec!["other".to_string()]))
+    let err = db
+        .scan_prefix(
+            None,
+            "msg".to_string(),
+            "conn/1".to_string(),
+            None,
+            None,
+            None,
         )
         .await
-        .expect(ERR_COUNT),
-        0
-    );
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::exist(vec![
-                "enc".to_string(),
-                "other".to_string()
-            ]))
-        )
+pub async fn db_category_prefix<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    // added to the index when it is enabled
+    for category in &["cred::schema-1", "cred::schema-2::v1", "other"] {
+        conn.insert(category, "name", b"value", None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+
+    let err = db
+        .list_categories_prefix(None, "cred::")
         .await
-        .expect(ERR_COUNT),
-        0
-    );
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::all_of(vec![
-                TagFilter::exist(vec!["enc".to_string()]),
-                TagFilter::exist(vec!["~plain".to_string()])
-            ]))
-        )
+    db.enable_category_index()
         .await
-        .expect(ERR_COUNT),
-        1
-    );
+        .expect("Error enabling category index");
 
-    assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::any_of(vec![
-                TagFilter::exist(vec!["~enc".to_string()]),
-                TagFilter::exist(vec!["~plain".to_string()])
-            ]))
-        )
+    for category in &["cred::schema-2::v2", "cred::schema-3", "credential"] {
+        conn.insert(category, "name", b"value", None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    conn.insert("cred::schema-1", "other", b"value", None, None)
         .await
-        .expect(ERR_COUNT),
-        1
-    );
+        .expect(ERR_INSERT);
+    conn.replace("cred::schema-2::v1", "name", b"updated", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    conn.remove("cred::schema-3", "name")
+        .await
+        .expect("Error removing test row");
+    drop(conn);
 
     assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::all_of(vec![
-                TagFilter::exist(vec!["~enc".to_string()]),
-                TagFilter::exist(vec!["~plain".to_string()])
-            ]))
-        )
-        .await
-        .expect(ERR_COUNT),
-        0
+        db.list_categories_prefix(None, "cred::")
+            .await
+            .expect("Error listing categories"),
+        vec!["cred::schema-1", "cred::schema-2::v1", "cred::schema-2::v2"]
     );
-
     assert_eq!(
-        conn.count(
-            &test_row.category,
-            Some(TagFilter::not(TagFilter::exist(vec![
-                "enc".to_string(),
-                "other".to_string()
-            ]),))
-        )
-        .await
-        .expect(ERR_COUNT),
-        0
+        db.list_categories_prefix(None, "cred::schema-2::")
+            .await
+            .expect("Error listing categories"),
+        vec!["cred::schema-2::v1", "cred::schema-2::v2"]
     );
-}
+    assert!(db
+        .list_categories_prefix(None, "cred::schema-1::")
+        .await
+        .expect("Error listing categories")
+        .is_empty());
 
-pub async fn db_scan<DB: Backend>(db: &Store<DB>) {
-    let category = "category".to_string();
-    let test_rows = vec![Entry::new(
-        &category,
-        "name",
-        "value",
+    let mut scan = db
+        .scan_category_prefix(None, "cred::", None)
+        .await
+        .expect(ERR_SCAN);
+    let mut rows = vec![];
+    while let Some(batch) = scan.fetch_next().await.expect(ERR_SCAN_NEXT) {
+        rows.extend(batch.into_iter().map(|row| (row.category, row.name)));
+    }
+    assert_eq!(
+        rows,
         vec![
-            EntryTag::Encrypted("t1".to_string(), "v1".to_string()),
-            EntryTag::Plaintext("t2".to_string(), "v2".to_string()),
-        ],
-    )];
+            ("cred::schema-1".to_string(), "name".to_string()),
+            ("cred::schema-1".to_string(), "other".to_string()),
+            ("cred::schema-2::v1".to_string(), "name".to_string()),
+            ("cred::schema-2::v2".to_string(), "name".to_string()),
+        ]
+    );
+
+    let err = db
+        .list_categories_prefix(None, "cred")
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
 
+pub async fn db_insert_long_name<DB: Backend>(db: &Store<DB>) {
     let mut conn = db.session(None).await.expect(ERR_SESSION);
 
-    for upd in test_rows.iter() {
-        conn.insert(
-            &upd.category,
-            &upd.name,
-            &upd.value,
-            Some(upd.tags.as_slice()),
-            None,
-        )
+    let max_name = "n".repeat(1024);
+    conn.insert("category", &max_name, b"value", None, None)
         .await
         .expect(ERR_INSERT);
+    let row = conn
+        .fetch("category", &max_name, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.name, max_name);
+
+    let long_name = "n".repeat(1025);
+    let err = conn
+        .insert("category", &long_name, b"value", None, None)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+
+    let long_category = "c".repeat(10 * 1024);
+    let err = conn
+        .insert(&long_category, "name", b"value", None, None)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
+
+pub async fn db_namespace<DB: Backend>(db: &Store<DB>) {
+    let ns_a = db.namespace("a").expect(ERR_NAMESPACE);
+    let ns_b = db.namespace("b").expect(ERR_NAMESPACE);
+    let ns_nested = ns_a.namespace("nested").expect(ERR_NAMESPACE);
+    assert_eq!(ns_nested.prefix(), "a::nested::");
+
+    for namespace in &["", "a::b"] {
+        let err = db.namespace(namespace).err().expect(ERR_REQ_ERR);
+        assert_eq!(err.kind(), ErrorKind::Input);
+        let err = ns_a.namespace(namespace).err().expect(ERR_REQ_ERR);
+        assert_eq!(err.kind(), ErrorKind::Input);
     }
+
+    let mut conn_a = ns_a.session(None).await.expect(ERR_SESSION);
+    conn_a
+        .insert("category", "name", b"value-a", None, None)
+        .await
+        .expect(ERR_INSERT);
+    let mut conn_b = ns_b.session(None).await.expect(ERR_SESSION);
+    conn_b
+        .insert("category", "name", b"value-b", None, None)
+        .await
+        .expect(ERR_INSERT);
+    let mut conn_nested = ns_nested.session(None).await.expect(ERR_SESSION);
+    conn_nested
+        .insert("category", "name", b"value-nested", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    let row = conn_a
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.category, "category");
+    assert_eq!(&row.value[..], b"value-a");
+    let row = conn_nested
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"value-nested");
+
+    // the categories are stored with the namespace prefix
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 0);
+    assert_eq!(conn.count("a::category", None).await.expect(ERR_COUNT), 1);
+    assert_eq!(
+        conn.count("a::nested::category", None)
+            .await
+            .expect(ERR_COUNT),
+        1
+    );
     drop(conn);
 
-    let tag_filter = None;
-    let offset = None;
-    let limit = None;
-    let mut scan = db
-        .scan(None, category.clone(), tag_filter, offset, limit)
+    let mut scan = ns_b
+        .scan(None, "category".to_string(), None, None, None)
         .await
         .expect(ERR_SCAN);
-    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
-    assert_eq!(rows, Some(test_rows));
-    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
-    assert_eq!(rows, None);
+    let rows = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].category, "category");
+    assert_eq!(&rows[0].value[..], b"value-b");
 
-    let tag_filter = Some(TagFilter::is_eq("sometag", "someval"));
-    let mut scan = db
-        .scan(None, category.clone(), tag_filter, offset, limit)
+    let groups = ns_a
+        .scan_grouped(None, vec!["category"], None, 10)
         .await
         .expect(ERR_SCAN);
-    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
-    assert_eq!(rows, None);
-}
+    assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["category"]);
+    assert_eq!(groups["category"][0].category, "category");
 
-pub async fn db_remove_all<DB: Backend>(db: &Store<DB>) {
-    let test_rows = vec![
-        Entry::new(
-            "category",
-            "item1",
-            "value",
-            vec![
-                EntryTag::Encrypted("t1".to_string(), "del".to_string()),
-                EntryTag::Plaintext("t2".to_string(), "del".to_string()),
-            ],
-        ),
-        Entry::new(
-            "category",
-            "item2",
-            "value",
-            vec![
-                EntryTag::Encrypted("t1".to_string(), "del".to_string()),
-                EntryTag::Plaintext("t2".to_string(), "del".to_string()),
-            ],
-        ),
-        Entry::new(
-            "category",
-            "item3",
-            "value",
-            vec![
-                EntryTag::Encrypted("t1".to_string(), "keep".to_string()),
-                EntryTag::Plaintext("t2".to_string(), "keep".to_string()),
-            ],
-        ),
-    ];
+    assert_eq!(
+        ns_a.list_categories(None).await.expect(ERR_LIST_CATEGORIES),
+        vec!["category", "nested::category"]
+    );
+    assert_eq!(
+        ns_nested
+            .list_categories(None)
+            .await
+            .expect(ERR_LIST_CATEGORIES),
+        vec!["category"]
+    );
 
-    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(
+        conn_a
+            .remove_all("category", None)
+            .await
+            .expect(ERR_REMOVE_ALL),
+        1
+    );
+    assert_eq!(conn_b.count("category", None).await.expect(ERR_COUNT), 1);
+    assert_eq!(
+        conn_nested.count("category", None).await.expect(ERR_COUNT),
+        1
+    );
+}
 
-    for test_row in test_rows.iter() {
-        conn.insert(
-            &test_row.category,
-            &test_row.name,
-            &test_row.value,
-            Some(test_row.tags.as_slice()),
-            None,
-        )
+pub async fn db_purge_expired<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    conn.insert("category", "live", b"value", None, Some(60_000))
         .await
         .expect(ERR_INSERT);
-    }
+    conn.insert("category", "expired", b"value", None, Some(-60_000))
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("category", "permanent", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
 
-    // could detect that a second transaction would block here?
-    // depends on the backend. just checking that no SQL errors occur for now.
-    let removed = conn
-        .remove_all(
-            "category",
-            Some(TagFilter::all_of(vec![
-                TagFilter::is_eq("t1", "del"),
-                TagFilter::is_eq("~t2", "del"),
-            ])),
-        )
+    assert_eq!(
+        db.purge_expired()
+            .await
+            .expect("Error purging expired rows"),
+        1
+    );
+    assert_eq!(
+        db.purge_expired()
+            .await
+            .expect("Error purging expired rows"),
+        0
+    );
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    conn.set_expiry_filter(ExpiryFilter::Include);
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 2);
+    assert!(conn
+        .fetch("category", "expired", false)
         .await
-        .expect(ERR_REMOVE_ALL);
-    assert_eq!(removed, 2);
+        .expect(ERR_FETCH)
+        .is_none());
 }
 
-// pub async fn db_keypair_create_fetch<DB: Backend>(db: &Store<DB>) {
+pub async fn db_expiry_filter<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    conn.insert("category", "live", b"value", None, Some(60_000))
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("category", "expired", b"value", None, Some(-60_000))
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("category", "permanent", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    let cases = [
+        (ExpiryFilter::Exclude, vec!["live", "permanent"]),
+        (ExpiryFilter::Include, vec!["expired", "live", "permanent"]),
+        (ExpiryFilter::Only, vec!["expired"]),
+    ];
+    for (expiry, expected) in cases.iter() {
+        conn.set_expiry_filter(*expiry);
+        assert_eq!(
+            conn.count("category", None).await.expect(ERR_COUNT),
+            expected.len() as i64,
+            "{:?}",
+            expiry
+        );
+        for name in &["expired", "live", "permanent"] {
+            let found = conn
+                .fetch("category", name, false)
+                .await
+                .expect(ERR_FETCH)
+                .is_some();
+            assert_eq!(found, expected.contains(name), "{:?} {}", expiry, name);
+        }
+        let mut names = conn
+            .fetch_all("category", None, None, false)
+            .await
+            .expect(ERR_FETCH_ALL)
+            .into_iter()
+            .map(|row| row.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(&names, expected, "{:?}", expiry);
+
+        let mut scan = db
+            .scan_with_expiry(None, "category".to_string(), None, *expiry, None, None)
+            .await
+            .expect(ERR_SCAN);
+        let mut names = scan
+            .fetch_next()
+            .await
+            .expect(ERR_SCAN_NEXT)
+            .expect(ERR_REQ_ROW)
+            .into_iter()
+            .map(|row| row.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(&names, expected, "{:?}", expiry);
+    }
+}
+
+pub async fn db_typed_tags<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for (name, num) in &[("a", 5), ("b", 10), ("c", 100), ("d", -20)] {
+        let tags = vec![
+            EntryTag::Int("num".to_string(), IntTagValue::new(*num)),
+            EntryTag::Bool("even".to_string(), num % 2 == 0),
+            EntryTag::Plaintext("text".to_string(), num.to_string()),
+        ];
+        conn.insert("category", name, b"value", Some(&tags), None)
+            .await
+            .expect(ERR_INSERT);
+    }
+
+    let row = conn
+        .fetch("category", "b", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    let mut tags = row.tags.clone();
+    tags.sort();
+    assert_eq!(
+        tags,
+        vec![
+            EntryTag::Plaintext("text".to_string(), "10".to_string()),
+            EntryTag::Int("num".to_string(), IntTagValue::new(10)),
+            EntryTag::Bool("even".to_string(), true),
+        ]
+    );
+
+    // integer tags are compared numerically, text tags lexicographically
+    let cases = vec![
+        (TagFilter::is_gt("~num", "9"), vec!["b", "c"]),
+        (TagFilter::is_lt("~num", "0"), vec!["d"]),
+        (TagFilter::is_gt("~text", "9"), vec![]),
+        (TagFilter::is_eq("~num", "100"), vec!["c"]),
+        (
+            TagFilter::is_in("~num", vec!["5".to_string(), "-20".to_string()]),
+            vec!["a", "d"],
+        ),
+        (TagFilter::is_eq("~even", "true"), vec!["b", "c", "d"]),
+        (
+            TagFilter::not(TagFilter::is_eq("~num", "5")),
+            vec!["b", "c", "d"],
+        ),
+    ];
+    for (filter, expected) in cases {
+        let mut names = conn
+            .fetch_all("category", Some(filter.clone()), None, false)
+            .await
+            .expect(ERR_FETCH_ALL)
+            .into_iter()
+            .map(|row| row.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, expected, "{:?}", filter);
+    }
+}
+
+pub async fn db_insert_duplicate_tags<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let err = conn
+        .insert(
+            "category",
+            "name",
+            b"value",
+            Some(&[
+                EntryTag::Encrypted("t1".to_string(), "a".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "b".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "b".to_string()),
+            ]),
+            None,
+        )
+        .await
+        .expect_err("Expected duplicate tag error");
+    assert_eq!(err.kind(), ErrorKind::Input);
+    assert!(err.to_string().contains("index 2"));
+    assert_eq!(
+        conn.count("category", None).await.expect(ERR_COUNT),
+        0,
+        "Entry with duplicate tags should not be inserted"
+    );
+
+    // multiple values, differing flags and differing case are all distinct tags
+    let tags = vec![
+        EntryTag::Plaintext("t1".to_string(), "a".to_string()),
+        EntryTag::Plaintext("t1".to_string(), "b".to_string()),
+        EntryTag::Encrypted("t1".to_string(), "a".to_string()),
+        EntryTag::Plaintext("T1".to_string(), "a".to_string()),
+    ];
+    conn.insert("category", "name", b"value", Some(&tags), None)
+        .await
+        .expect(ERR_INSERT);
+
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    let mut found_tags = row.tags.clone();
+    found_tags.sort();
+    let mut expected_tags = tags.clone();
+    expected_tags.sort();
+    assert_eq!(found_tags, expected_tags);
+
+    let found = conn
+        .count("category", Some(TagFilter::is_eq("~T1", "a")))
+        .await
+        .expect(ERR_COUNT);
+    assert_eq!(found, 1);
+    let found = conn
+        .count("category", Some(TagFilter::is_eq("~t1", "c")))
+        .await
+        .expect(ERR_COUNT);
+    assert_eq!(found, 0);
+}
+
+pub async fn db_tag_stats<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..6 {
+        conn.insert(
+            "category",
+            &format!("name-{}", idx),
+            b"value",
+            Some(&[
+                EntryTag::Plaintext("plain".to_string(), format!("v{}", idx % 3)),
+                EntryTag::Encrypted("enc".to_string(), "secret".to_string()),
+                if idx % 2 == 0 {
+                    EntryTag::Plaintext("mixed".to_string(), "value".to_string())
+                } else {
+                    EntryTag::Encrypted("mixed".to_string(), "value".to_string())
+                },
+            ]),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+    conn.insert(
+        "other",
+        "name",
+        b"value",
+        Some(&[EntryTag::Plaintext("other".to_string(), "v".to_string())]),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+    drop(conn);
+
+    let mut stats = db
+        .tag_stats(None, "category")
+        .await
+        .expect("Error collecting tag statistics");
+    stats.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(
+        stats.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        vec!["enc", "mixed", "plain"]
+    );
+
+    let (enc, mixed, plain) = (&stats[0], &stats[1], &stats[2]);
+    assert_eq!(enc.count, 6);
+    assert_eq!(enc.plaintext_count, 0);
+    assert_eq!(enc.distinct_values, None);
+    assert_eq!(enc.avg_value_len, None);
+
+    assert_eq!(mixed.count, 6);
+    assert_eq!(mixed.plaintext_ratio(), 0.5);
+    assert_eq!(mixed.distinct_values, Some(1));
+
+    assert_eq!(plain.count, 6);
+    assert_eq!(plain.plaintext_ratio(), 1.0);
+    assert_eq!(plain.distinct_values, Some(3));
+    assert_eq!(plain.avg_value_len, Some(2.0));
+}
+
+pub async fn db_remove_missing<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let err = conn.remove("cat", "name").await.expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+pub async fn db_replace_fetch<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    let mut replace_row = test_row.clone();
+    replace_row.value = "new value".into();
+    conn.replace(
+        &replace_row.category,
+        &replace_row.name,
+        &replace_row.value,
+        Some(replace_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_REPLACE);
+
+    let row = conn
+        .fetch(&replace_row.category, &replace_row.name, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row, replace_row);
+}
+
+pub async fn db_replace_missing<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let err = conn
+        .replace(
+            &test_row.category,
+            &test_row.name,
+            &test_row.value,
+            Some(test_row.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+pub async fn db_replace_expiry<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.insert("category", "name", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    assert_eq!(
+        conn.fetch("category", "name", false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW)
+            .expiry,
+        None
+    );
+
+    conn.replace("category", "name", b"value", None, Some(60_000))
+        .await
+        .expect(ERR_REPLACE);
+    let expiry = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW)
+        .expiry
+        .expect("Expected expiry time");
+
+    // the expiry time is preserved when requested
+    conn.replace_with_expiry("category", "name", b"new value", None, ExpiryUpdate::Keep)
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(
+        conn.fetch("category", "name", false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW)
+            .expiry,
+        Some(expiry)
+    );
+
+    // replacing without an expiry time clears it
+    conn.replace("category", "name", b"value", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(
+        conn.fetch("category", "name", false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW)
+            .expiry,
+        None
+    );
+
+    conn.replace_with_expiry("category", "name", b"value", None, ExpiryUpdate::Keep)
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(
+        conn.fetch("category", "name", false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW)
+            .expiry,
+        None
+    );
+}
+
+pub async fn db_binary_names<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let invalid: &[u8] = b"name\xff";
+    let with_nul: &[u8] = b"na\0me";
+    let tags = [EntryTag::Encrypted("t1".to_string(), "v1".to_string())];
+
+    conn.insert_bytes(b"binary", invalid, b"first", Some(&tags), None)
+        .await
+        .expect(ERR_INSERT);
+    conn.insert_bytes(b"binary", with_nul, b"second", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    // records are unique according to the exact bytes of the name
+    let err = conn
+        .insert_bytes(b"binary", invalid, b"first", None, None)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+    let entry = conn
+        .fetch_bytes(b"binary", invalid, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(entry.name, "name\u{fffd}");
+    assert_eq!(entry.name_bytes(), invalid);
+    assert_eq!(entry.category_bytes(), b"binary");
+    assert_eq!(&*entry.value, b"first");
+    let entry = conn
+        .fetch_bytes(b"binary", with_nul, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(entry.name, "na\0me");
+    assert_eq!(entry.name_bytes(), with_nul);
+    assert_eq!(&*entry.value, b"second");
+
+    // the lossy form of the name does not refer to the same record
+    assert!(conn
+        .fetch("binary", "name\u{fffd}", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+    // names given as strings are interchangeable with their bytes
+    assert!(conn
+        .fetch("binary", "na\0me", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_some());
+
+    // tag filters apply as for any other record
+    assert_eq!(
+        conn.count("binary", Some(TagFilter::is_eq("t1", "v1")))
+            .await
+            .expect(ERR_COUNT),
+        1
+    );
+    // a scan reports an error rather than returning a record under a lossy name
+    let err = conn
+        .fetch_all("binary", None, None, false)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Encryption);
+
+    conn.replace_bytes(b"binary", invalid, b"updated", None, ExpiryUpdate::Keep)
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(
+        &*conn
+            .fetch_bytes(b"binary", invalid, false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW)
+            .value,
+        b"updated"
+    );
+    conn.remove_bytes(b"binary", invalid)
+        .await
+        .expect("Error removing test row");
+    assert!(conn
+        .fetch_bytes(b"binary", invalid, false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+    let err = conn
+        .remove_bytes(b"binary", invalid)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+pub async fn db_tag_filter_distinct<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let tags = [
+        EntryTag::Encrypted("color".to_string(), "red".to_string()),
+        EntryTag::Encrypted("color".to_string(), "blue".to_string()),
+        EntryTag::Plaintext("size".to_string(), "large".to_string()),
+        EntryTag::Plaintext("size".to_string(), "small".to_string()),
+    ];
+    conn.insert("distinct", "name", b"value", Some(&tags), None)
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("distinct", "other", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    // each filter is matched by several tags, and by every branch of the `$or`
+    let filters = || {
+        vec![
+            TagFilter::any_of(vec![
+                TagFilter::is_eq("color", "red"),
+                TagFilter::is_eq("color", "blue"),
+                TagFilter::is_eq("~size", "large"),
+            ]),
+            TagFilter::is_in("color", vec!["red".to_string(), "blue".to_string()]),
+            TagFilter::any_of(vec![
+                TagFilter::is_in("~size", vec!["large".to_string(), "small".to_string()]),
+                TagFilter::exist(vec!["color".to_string()]),
+            ]),
+        ]
+    };
+    for filter in filters() {
+        assert_eq!(
+            conn.count("distinct", Some(filter.clone()))
+                .await
+                .expect(ERR_COUNT),
+            1
+        );
+        let rows = conn
+            .fetch_all("distinct", Some(filter), None, false)
+            .await
+            .expect(ERR_FETCH_ALL);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name, "name");
+    }
+    drop(conn);
+
+    for filter in filters() {
+        let mut scan = db
+            .scan(None, "distinct".to_string(), Some(filter), None, None)
+            .await
+            .expect(ERR_SCAN);
+        let rows = scan
+            .fetch_next()
+            .await
+            .expect(ERR_SCAN_NEXT)
+            .expect(ERR_REQ_ROW);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(scan.fetch_next().await.expect(ERR_SCAN_NEXT), None);
+    }
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(
+        conn.remove_all("distinct", filters().pop())
+            .await
+            .expect(ERR_REMOVE_ALL),
+        1
+    );
+}
+
+pub async fn db_count<DB: Backend>(db: &Store<DB>) {
+    let category = "category".to_string();
+    let test_rows = vec![Entry::new(&category, "name", "value", Vec::new())];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for upd in test_rows.iter() {
+        conn.insert(
+            &upd.category,
+            &upd.name,
+            &upd.value,
+            Some(upd.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+
+    let tag_filter = None;
+    let count = conn.count(&category, tag_filter).await.expect(ERR_COUNT);
+    assert_eq!(count, 1);
+
+    let tag_filter = Some(TagFilter::is_eq("sometag", "someval"));
+    let count = conn.count(&category, tag_filter).await.expect(ERR_COUNT);
+    assert_eq!(count, 0);
+}
+
+pub async fn db_count_exist<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new(
+        "category",
+        "name",
+        "value",
+        vec![
+            EntryTag::Encrypted("enc".to_string(), "v1".to_string()),
+            EntryTag::Plaintext("plain".to_string(), "v2".to_string()),
+        ],
+    );
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec!["enc".to_string()]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        1
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec!["~plain".to_string()]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        1
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec!["~enc".to_string()]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec!["plain".to_string()]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec!["other".to_string()]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::exist(vec![
+                "enc".to_string(),
+                "other".to_string()
+            ]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::all_of(vec![
+                TagFilter::exist(vec!["enc".to_string()]),
+                TagFilter::exist(vec!["~plain".to_string()])
+            ]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        1
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::any_of(vec![
+                TagFilter::exist(vec!["~enc".to_string()]),
+                TagFilter::exist(vec!["~plain".to_string()])
+            ]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        1
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::all_of(vec![
+                TagFilter::exist(vec!["~enc".to_string()]),
+                TagFilter::exist(vec!["~plain".to_string()])
+            ]))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+
+    assert_eq!(
+        conn.count(
+            &test_row.category,
+            Some(TagFilter::not(TagFilter::exist(vec![
+                "enc".to_string(),
+                "other".to_string()
+            ]),))
+        )
+        .await
+        .expect(ERR_COUNT),
+        0
+    );
+}
+
+pub async fn db_tag_filter_not<DB: Backend>(db: &Store<DB>) {
+    // each tag state is stored in a separate record: absent, equal, different
+    let states = [None, Some("a"), Some("b")];
+    // pairs of a tag filter and the expected result for a record's tag value
+    let queries: [(fn(&str) -> TagFilter, fn(Option<&str>) -> bool); 9] = [
+        (|t| TagFilter::is_eq(t, "a"), |v| v == Some("a")),
+        (
+            |t| TagFilter::not(TagFilter::is_eq(t, "a")),
+            |v| v != Some("a"),
+        ),
+        (
+            |t| TagFilter::is_not_eq(t, "a"),
+            |v| v.is_some() && v != Some("a"),
+        ),
+        (
+            |t| TagFilter::not(TagFilter::is_not_eq(t, "a")),
+            |v| v.is_none() || v == Some("a"),
+        ),
+        (
+            |t| TagFilter::not(TagFilter::not(TagFilter::is_eq(t, "a"))),
+            |v| v == Some("a"),
+        ),
+        (
+            |t| TagFilter::not(TagFilter::is_in(t, vec!["a".to_string()])),
+            |v| v != Some("a"),
+        ),
+        (
+            |t| TagFilter::not(TagFilter::exist(vec![t.to_string()])),
+            |v| v.is_none(),
+        ),
+        (
+            |t| {
+                TagFilter::not(TagFilter::any_of(vec![
+                    TagFilter::is_eq(t, "a"),
+                    TagFilter::is_eq(t, "b"),
+                ]))
+            },
+            |v| v.is_none(),
+        ),
+        (
+            |t| {
+                TagFilter::not(TagFilter::all_of(vec![
+                    TagFilter::exist(vec![t.to_string()]),
+                    TagFilter::is_not_eq(t, "a"),
+                ]))
+            },
+            |v| v.is_none() || v == Some("a"),
+        ),
+    ];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for (tag_name, plaintext) in [("enc", false), ("~plain", true)].iter() {
+        let category = format!("category-{}", tag_name);
+        for (idx, state) in states.iter().enumerate() {
+            let tags = state
+                .map(|value| {
+                    if *plaintext {
+                        vec![EntryTag::Plaintext(
+                            tag_name[1..].to_string(),
+                            value.to_string(),
+                        )]
+                    } else {
+                        vec![EntryTag::Encrypted(tag_name.to_string(), value.to_string())]
+                    }
+                })
+                .unwrap_or_default();
+            conn.insert(
+                &category,
+                &format!("name-{}", idx),
+                b"value",
+                Some(tags.as_slice()),
+                None,
+            )
+            .await
+            .expect(ERR_INSERT);
+        }
+
+        for (query_idx, (filter, eval)) in queries.iter().enumerate() {
+            let rows = conn
+                .fetch_all(&category, Some(filter(tag_name)), None, false)
+                .await
+                .expect(ERR_FETCH_ALL);
+            for (idx, state) in states.iter().enumerate() {
+                let name = format!("name-{}", idx);
+                assert_eq!(
+                    rows.iter().any(|row| row.name == name),
+                    eval(*state),
+                    "Unexpected result for query {} on tag {} with value {:?}",
+                    query_idx,
+                    tag_name,
+                    state
+                );
+            }
+        }
+    }
+}
+
+pub async fn db_scan_resumable<DB: Backend>(db: &Store<DB>) {
+    let category = "category".to_string();
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..70 {
+        conn.insert(
+            &category,
+            &format!("name-{}", idx),
+            b"value",
+            Some(&[EntryTag::Plaintext(
+                "even".to_string(),
+                (idx % 2 == 0).to_string(),
+            )]),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+    drop(conn);
+
+    let filter = || Some(TagFilter::is_eq("~even", "true"));
+    let mut scan = db
+        .scan_resumable(None, category.clone(), filter())
+        .await
+        .expect(ERR_SCAN);
+    let mut names = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW)
+        .into_iter()
+        .map(|row| row.name)
+        .collect::<Vec<_>>();
+    let cursor = scan.cursor().to_string();
+    drop(scan);
+
+    // the cursor is bound to the category and tag filter
+    let parsed = cursor.parse::<ScanCursor>().expect("Error parsing cursor");
+    assert_eq!(parsed.profile(), db.get_profile_name());
+    let err = db
+        .scan_resume(parsed.clone(), category.clone(), None)
+        .await
+        .expect_err("Expected error for changed filter");
+    assert_eq!(err.kind(), ErrorKind::Input);
+    let err = db
+        .scan_resume(parsed.clone(), "other".to_string(), filter())
+        .await
+        .expect_err("Expected error for changed category");
+    assert_eq!(err.kind(), ErrorKind::Input);
+
+    let mut scan = db
+        .scan_resume(parsed, category.clone(), filter())
+        .await
+        .expect(ERR_SCAN);
+    while let Some(rows) = scan.fetch_next().await.expect(ERR_SCAN_NEXT) {
+        names.extend(rows.into_iter().map(|row| row.name));
+    }
+    let expected = (0..70)
+        .step_by(2)
+        .map(|idx| format!("name-{}", idx))
+        .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+
+    // a cursor at the end of the scan produces no further rows
+    let mut scan = db
+        .scan_resume(scan.cursor(), category.clone(), filter())
+        .await
+        .expect(ERR_SCAN);
+    assert_eq!(scan.fetch_next().await.expect(ERR_SCAN_NEXT), None);
+}
+
+pub async fn db_alias<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    conn.insert("category", "conn-1", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    let err = conn
+        .add_alias("category", "did:1", "missing")
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    conn.add_alias("category", "did:1", "conn-1")
+        .await
+        .expect("Error adding alias");
+    conn.add_alias("category", "thread:1", "conn-1")
+        .await
+        .expect("Error adding alias");
+    let err = conn
+        .add_alias("category", "did:1", "conn-1")
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+    // aliases are not records of the category
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 1);
+    assert!(conn
+        .fetch("category", "did:1", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+
+    let (entry, aliased) = conn
+        .fetch_follow_alias("category", "did:1", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(entry.name, "conn-1");
+    assert!(aliased);
+    let (entry, aliased) = conn
+        .fetch_follow_alias("category", "conn-1", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(entry.name, "conn-1");
+    assert!(!aliased);
+
+    conn.remove_alias("category", "did:1")
+        .await
+        .expect("Error removing alias");
+    assert!(conn
+        .fetch_follow_alias("category", "did:1", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+
+    // removing the target cascades to its remaining aliases
+    conn.remove("category", "conn-1")
+        .await
+        .expect("Error removing test row");
+    conn.insert("category", "conn-1", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    assert!(conn
+        .fetch_follow_alias("category", "thread:1", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+}
+
+pub async fn db_tag_filter_empty<DB: Backend>(db: &Store<DB>) {
+    let category = "category".to_string();
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for (name, value) in [("a", "1"), ("b", "2")].iter() {
+        conn.insert(
+            &category,
+            name,
+            b"value",
+            Some(&[EntryTag::Plaintext("t".to_string(), value.to_string())]),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+    drop(conn);
+
+    // an empty conjunction matches all records, an empty disjunction none
+    let queries: [(fn() -> TagFilter, i64); 8] = [
+        (|| TagFilter::all_of(vec![]), 2),
+        (|| TagFilter::any_of(vec![]), 0),
+        (|| TagFilter::not(TagFilter::all_of(vec![])), 0),
+        (|| TagFilter::not(TagFilter::any_of(vec![])), 2),
+        (
+            || TagFilter::any_of(vec![TagFilter::all_of(vec![]), TagFilter::is_eq("~t", "1")]),
+            2,
+        ),
+        (
+            || TagFilter::all_of(vec![TagFilter::all_of(vec![]), TagFilter::is_eq("~t", "1")]),
+            1,
+        ),
+        (
+            || TagFilter::any_of(vec![TagFilter::any_of(vec![]), TagFilter::is_eq("~t", "1")]),
+            1,
+        ),
+        (
+            || TagFilter::all_of(vec![TagFilter::any_of(vec![]), TagFilter::is_eq("~t", "1")]),
+            0,
+        ),
+    ];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for (query_idx, (filter, expected)) in queries.iter().enumerate() {
+        let count = conn
+            .count(&category, Some(filter()))
+            .await
+            .expect(ERR_COUNT);
+        assert_eq!(count, *expected, "Unexpected count for query {}", query_idx);
+    }
+    drop(conn);
+
+    for (query_idx, (filter, expected)) in queries.iter().enumerate() {
+        let mut scan = db
+            .scan(None, category.clone(), Some(filter()), None, None)
+            .await
+            .expect(ERR_SCAN);
+        let mut rows = 0;
+        while let Some(page) = scan.fetch_next().await.expect(ERR_SCAN_NEXT) {
+            rows += page.len() as i64;
+        }
+        assert_eq!(rows, *expected, "Unexpected scan for query {}", query_idx);
+    }
+}
+
+pub async fn db_tag_order<DB: Backend>(db: &Store<DB>) {
+    let tags = vec![
+        EntryTag::Plaintext("zeta".to_string(), "1".to_string()),
+        EntryTag::Encrypted("b".to_string(), "2".to_string()),
+        EntryTag::Plaintext("alpha".to_string(), "3".to_string()),
+        EntryTag::Encrypted("a".to_string(), "4".to_string()),
+        EntryTag::Encrypted("c".to_string(), "5".to_string()),
+    ];
+    let mut sorted = tags.clone();
+    sorted.sort();
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    conn.insert("category", "name", b"value", Some(&tags), None)
+        .await
+        .expect(ERR_INSERT);
+
+    // the tags are returned in a stable order, regardless of insertion order
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.tags, sorted);
+
+    let rows = conn
+        .fetch_all("category", None, None, false)
+        .await
+        .expect(ERR_FETCH_ALL);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].tags, sorted);
+    drop(conn);
+
+    let mut scan = db
+        .scan(None, "category".to_string(), None, None, None)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].tags, sorted);
+}
+
+pub async fn db_tag_filter_same_name<DB: Backend>(db: &Store<DB>) {
+    // each combination of values for the tag is stored in a separate record
+    let states: [&[&str]; 8] = [
+        &[],
+        &["red"],
+        &["blue"],
+        &["green"],
+        &["red", "blue"],
+        &["red", "green"],
+        &["blue", "green"],
+        &["red", "blue", "green"],
+    ];
+    // pairs of a tag filter and the expected result for a record's tag values
+    let queries: [(fn(&str) -> TagFilter, fn(&[&str]) -> bool); 8] = [
+        (
+            |t| {
+                TagFilter::all_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::is_eq(t, "blue"),
+                ])
+            },
+            |v| v.contains(&"red") && v.contains(&"blue"),
+        ),
+        (
+            |t| {
+                TagFilter::all_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::is_not_eq(t, "blue"),
+                ])
+            },
+            |v| v.contains(&"red") && v.iter().any(|v| *v != "blue"),
+        ),
+        (
+            |t| {
+                TagFilter::all_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::not(TagFilter::is_eq(t, "blue")),
+                ])
+            },
+            |v| v.contains(&"red") && !v.contains(&"blue"),
+        ),
+        (
+            |t| {
+                TagFilter::any_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::is_eq(t, "blue"),
+                ])
+            },
+            |v| v.contains(&"red") || v.contains(&"blue"),
+        ),
+        (
+            |t| {
+                TagFilter::all_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::is_in(t, vec!["blue".to_string(), "green".to_string()]),
+                ])
+            },
+            |v| v.contains(&"red") && (v.contains(&"blue") || v.contains(&"green")),
+        ),
+        (
+            |t| {
+                TagFilter::not(TagFilter::all_of(vec![
+                    TagFilter::is_eq(t, "red"),
+                    TagFilter::is_eq(t, "blue"),
+                ]))
+            },
+            |v| !(v.contains(&"red") && v.contains(&"blue")),
+        ),
+        (
+            |t| {
+                TagFilter::all_of(vec![
+                    TagFilter::exist(vec![t.to_string()]),
+                    TagFilter::not(TagFilter::is_eq(t, "red")),
+                    TagFilter::not(TagFilter::is_eq(t, "green")),
+                ])
+            },
+            |v| v == ["blue"],
+        ),
+        (
+            // ten predicates on the same tag
+            |t| {
+                let mut each = (0..9)
+                    .map(|idx| TagFilter::not(TagFilter::is_eq(t, format!("other{}", idx))))
+                    .collect::<Vec<_>>();
+                each.push(TagFilter::is_eq(t, "green"));
+                TagFilter::all_of(each)
+            },
+            |v| v.contains(&"green"),
+        ),
+    ];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for (tag_name, plaintext) in [("color", false), ("~color", true)].iter() {
+        let category = format!("category-{}", tag_name);
+        for (idx, values) in states.iter().enumerate() {
+            let tags = values
+                .iter()
+                .map(|value| {
+                    if *plaintext {
+                        EntryTag::Plaintext(tag_name[1..].to_string(), value.to_string())
+                    } else {
+                        EntryTag::Encrypted(tag_name.to_string(), value.to_string())
+                    }
+                })
+                .collect::<Vec<_>>();
+            conn.insert(
+                &category,
+                &format!("name-{}", idx),
+                b"value",
+                Some(tags.as_slice()),
+                None,
+            )
+            .await
+            .expect(ERR_INSERT);
+        }
+
+        for (query_idx, (filter, eval)) in queries.iter().enumerate() {
+            let rows = conn
+                .fetch_all(&category, Some(filter(tag_name)), None, false)
+                .await
+                .expect(ERR_FETCH_ALL);
+            for (idx, values) in states.iter().enumerate() {
+                let name = format!("name-{}", idx);
+                assert_eq!(
+                    rows.iter().any(|row| row.name == name),
+                    eval(values),
+                    "Unexpected result for query {} on tag {} with values {:?}",
+                    query_idx,
+                    tag_name,
+                    values
+                );
+            }
+        }
+    }
+}
+
+pub async fn db_scan<DB: Backend>(db: &Store<DB>) {
+    let category = "category".to_string();
+    let test_rows = vec![Entry::new(
+        &category,
+        "name",
+        "value",
+        vec![
+            EntryTag::Encrypted("t1".to_string(), "v1".to_string()),
+            EntryTag::Plaintext("t2".to_string(), "v2".to_string()),
+        ],
+    )];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for upd in test_rows.iter() {
+        conn.insert(
+            &upd.category,
+            &upd.name,
+            &upd.value,
+            Some(upd.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+    drop(conn);
+
+    let tag_filter = None;
+    let offset = None;
+    let limit = None;
+    let mut scan = db
+        .scan(None, category.clone(), tag_filter, offset, limit)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
+    assert_eq!(rows, Some(test_rows));
+    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
+    assert_eq!(rows, None);
+
+    let tag_filter = Some(TagFilter::is_eq("sometag", "someval"));
+    let mut scan = db
+        .scan(None, category.clone(), tag_filter, offset, limit)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
+    assert_eq!(rows, None);
+}
+
+pub async fn db_diagnostics<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..40 {
+        conn.insert("category", &format!("item{}", idx), b"value", None, None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    drop(conn);
+
+    let diag = db.diagnostics().await.expect("Error fetching diagnostics");
+    assert!(diag.active_scans.is_empty());
+    assert!(diag.pool_size > 0);
+    assert!(diag.cached_profiles > 0);
+
+    let mut scan = db
+        .scan(None, "category".to_string(), None, None, None)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW);
+    let diag = db.diagnostics().await.expect("Error fetching diagnostics");
+    assert_eq!(diag.active_scans.len(), 1);
+    assert_eq!(diag.active_scans[0].pages, 1);
+    assert_eq!(diag.active_scans[0].rows, rows.len());
+
+    // a completed scan is no longer reported
+    while scan.fetch_next().await.expect(ERR_SCAN_NEXT).is_some() {}
+    let diag = db.diagnostics().await.expect("Error fetching diagnostics");
+    assert!(diag.active_scans.is_empty());
+
+    let scan = db
+        .scan(None, "category".to_string(), None, None, None)
+        .await
+        .expect(ERR_SCAN);
+    assert_eq!(
+        db.diagnostics()
+            .await
+            .expect("Error fetching diagnostics")
+            .active_scans
+            .len(),
+        1
+    );
+    drop(scan);
+    let diag = db.diagnostics().await.expect("Error fetching diagnostics");
+    assert!(diag.active_scans.is_empty());
+}
+
+pub async fn db_info<DB: Backend>(db: &Store<DB>) {
+    let info = db.info(false).await.expect("Error fetching store info");
+    // the sqlite schema gained a fourth version for profile quotas
+    let schema_version = if info.backend == "sqlite" { "4" } else { "3" };
+    assert_eq!(info.schema_version, schema_version);
+    assert_eq!(info.encryption, KeyAlg::Chacha20(Chacha20Types::C20P));
+    assert_eq!(info.profile_count, 1);
+    let default_profile = info.default_profile.clone();
+
+    // the cached information is updated when a profile is added
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let info = db.info(false).await.expect("Error fetching store info");
+    assert_eq!(info.profile_count, 2);
+    assert_eq!(info.default_profile, default_profile);
+
+    assert!(db.remove_profile(profile).await.expect(ERR_PROFILE));
+    let refreshed = db.info(true).await.expect("Error fetching store info");
+    assert_eq!(refreshed.profile_count, 1);
+    assert_eq!(
+        db.info(false).await.expect("Error fetching store info"),
+        refreshed
+    );
+}
+
+pub async fn db_scan_grouped<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for (category, count) in [("cat-a", 4), ("cat-b", 2), ("cat-c", 3)].iter() {
+        for idx in 0..*count {
+            let tags = vec![EntryTag::Plaintext(
+                "even".to_string(),
+                (idx % 2 == 0).to_string(),
+            )];
+            conn.insert(
+                category,
+                &format!("name-{}", idx),
+                b"value",
+                Some(tags.as_slice()),
+                None,
+            )
+            .await
+            .expect(ERR_INSERT);
+        }
+    }
+    drop(conn);
+
+    let groups = db
+        .scan_grouped(None, vec!["cat-a", "cat-b", "cat-missing"], None, 3)
+        .await
+        .expect(ERR_SCAN);
+    assert_eq!(
+        groups.keys().map(String::as_str).collect::<Vec<_>>(),
+        vec!["cat-a", "cat-b", "cat-missing"]
+    );
+    let names = |category: &str| {
+        groups[category]
+            .iter()
+            .map(|row| row.name.as_str())
+            .collect::<Vec<_>>()
+    };
+    // most recently added first
+    assert_eq!(names("cat-a"), vec!["name-3", "name-2", "name-1"]);
+    assert_eq!(names("cat-b"), vec!["name-1", "name-0"]);
+    assert!(names("cat-missing").is_empty());
+    assert_eq!(groups["cat-a"][0].category, "cat-a");
+    assert_eq!(
+        groups["cat-a"][0].tags,
+        vec![EntryTag::Plaintext("even".to_string(), "false".to_string())]
+    );
+
+    let groups = db
+        .scan_grouped(
+            None,
+            vec!["cat-a", "cat-c"],
+            Some(TagFilter::is_eq("~even", "true")),
+            1,
+        )
+        .await
+        .expect(ERR_SCAN);
+    assert_eq!(groups["cat-a"].len(), 1);
+    assert_eq!(groups["cat-a"][0].name, "name-2");
+    assert_eq!(groups["cat-c"].len(), 1);
+    assert_eq!(groups["cat-c"][0].name, "name-2");
+
+    // more categories than fit in a single query
+    let extra = (0..1200)
+        .map(|idx| format!("cat-extra-{}", idx))
+        .collect::<Vec<_>>();
+    let mut categories = extra.iter().map(String::as_str).collect::<Vec<_>>();
+    categories.extend(["cat-a", "cat-c"].iter());
+    let groups = db
+        .scan_grouped(None, categories, Some(TagFilter::is_eq("~even", "true")), 1)
+        .await
+        .expect(ERR_SCAN);
+    assert_eq!(groups.len(), 1202);
+    assert_eq!(groups["cat-a"][0].name, "name-2");
+    assert_eq!(groups["cat-c"][0].name, "name-2");
+    assert!(groups["cat-extra-0"].is_empty());
+}
+
+pub async fn db_remove_all<DB: Backend>(db: &Store<DB>) {
+    let test_rows = vec![
+        Entry::new(
+            "category",
+            "item1",
+            "value",
+            vec![
+                EntryTag::Encrypted("t1".to_string(), "del".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "del".to_string()),
+            ],
+        ),
+        Entry::new(
+            "category",
+            "item2",
+            "value",
+            vec![
+                EntryTag::Encrypted("t1".to_string(), "del".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "del".to_string()),
+            ],
+        ),
+        Entry::new(
+            "category",
+            "item3",
+            "value",
+            vec![
+                EntryTag::Encrypted("t1".to_string(), "keep".to_string()),
+                EntryTag::Plaintext("t2".to_string(), "keep".to_string()),
+            ],
+        ),
+    ];
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for test_row in test_rows.iter() {
+        conn.insert(
+            &test_row.category,
+            &test_row.name,
+            &test_row.value,
+            Some(test_row.tags.as_slice()),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+
+    // could detect that a second transaction would block here?
+    // depends on the backend. just checking that no SQL errors occur for now.
+    let removed = conn
+        .remove_all(
+            "category",
+            Some(TagFilter::all_of(vec![
+                TagFilter::is_eq("t1", "del"),
+                TagFilter::is_eq("~t2", "del"),
+            ])),
+        )
+        .await
+        .expect(ERR_REMOVE_ALL);
+    assert_eq!(removed, 2);
+}
+
+pub async fn db_truncate_profile<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for (category, count) in [("cat1", 3), ("cat2", 2)].iter() {
+        for idx in 0..*count {
+            conn.insert(
+                category,
+                &format!("item{}", idx),
+                b"value",
+                Some(&[EntryTag::Encrypted("t1".to_string(), "a".to_string())]),
+                None,
+            )
+            .await
+            .expect(ERR_INSERT);
+        }
+    }
+    let key = LocalKey::generate(KeyAlg::Ed25519, false).expect("Error creating key");
+    conn.insert_key("key", &key, None, None, None)
+        .await
+        .expect("Error inserting key");
+    drop(conn);
+
+    let counts = db
+        .truncate_profile(None, Some(vec!["cat1".to_string()]))
+        .await
+        .expect(ERR_TRUNCATE);
+    assert_eq!(counts.get("cat1"), Some(&3));
+    assert_eq!(counts.len(), 1);
+
+    let counts = db.truncate_profile(None, None).await.expect(ERR_TRUNCATE);
+    assert_eq!(counts.get("cat2"), Some(&2));
+    assert_eq!(counts.len(), 1);
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(conn.count("cat1", None).await.expect(ERR_COUNT), 0);
+    assert_eq!(conn.count("cat2", None).await.expect(ERR_COUNT), 0);
+    assert!(conn
+        .fetch_key("key", false)
+        .await
+        .expect("Error fetching key")
+        .is_some());
+}
+
+pub async fn db_kind_isolation<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    // an item sharing the category and name used for stored keys
+    let key = LocalKey::generate(KeyAlg::Ed25519, false).expect("Error creating key");
+    conn.insert_key("key", &key, None, None, None)
+        .await
+        .expect("Error inserting key");
+    conn.insert("cryptokey", "key", b"item", None, None)
+        .await
+        .expect(ERR_INSERT);
+
+    let row = conn
+        .fetch("cryptokey", "key", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.value, b"item"[..]);
+    assert_eq!(conn.count("cryptokey", None).await.expect(ERR_COUNT), 1);
+    let rows = conn
+        .fetch_all("cryptokey", None, None, false)
+        .await
+        .expect(ERR_FETCH_ALL);
+    assert_eq!(rows.len(), 1);
+    let keys = conn
+        .fetch_all_keys(None, None, None, None, false)
+        .await
+        .expect("Error fetching keys");
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name(), "key");
+    drop(conn);
+
+    let mut scan = db
+        .scan(None, "cryptokey".to_string(), None, None, None)
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan.fetch_next().await.expect(ERR_SCAN_NEXT);
+    assert_eq!(rows.map(|r| r.len()), Some(1));
+    drop(scan);
+
+    // removing the items must leave the key in place
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let removed = conn
+        .remove_all("cryptokey", None)
+        .await
+        .expect(ERR_REMOVE_ALL);
+    assert_eq!(removed, 1);
+    assert!(conn
+        .fetch_key("key", false)
+        .await
+        .expect("Error fetching key")
+        .is_some());
+    let err = conn
+        .remove("cryptokey", "key")
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    // and removing the key must not touch a re-inserted item
+    conn.insert("cryptokey", "key", b"item", None, None)
+        .await
+        .expect(ERR_INSERT);
+    conn.remove_key("key").await.expect("Error removing key");
+    assert!(conn
+        .fetch_key("key", false)
+        .await
+        .expect("Error fetching key")
+        .is_none());
+    assert!(conn
+        .fetch("cryptokey", "key", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_some());
+}
+
+pub async fn db_export_jsonl<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let tags = vec![
+        EntryTag::Encrypted("enc".to_string(), "a".to_string()),
+        EntryTag::Plaintext("plain".to_string(), "b".to_string()),
+    ];
+    conn.insert("cat1", "one", b"value1", Some(&tags), Some(60_000))
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("cat2", "two", b"value2", None, None)
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("cat2", "expired", b"value3", None, Some(-60_000))
+        .await
+        .expect(ERR_INSERT);
+
+    // the expiry time is returned with the entry
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let expiry = conn
+        .fetch("cat1", "one", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW)
+        .expiry
+        .expect("Expected expiry time");
+    assert!(expiry > now && expiry <= now + 61_000);
+    drop(conn);
+
+    assert_eq!(
+        db.list_categories(None)
+            .await
+            .expect("Error listing categories"),
+        vec!["cat1".to_string(), "cat2".to_string()]
+    );
+
+    let mut output = vec![];
+    let err = db
+        .export_jsonl(None, &mut output, None, false, |_| ())
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+    assert!(output.is_empty());
+
+    let mut progress = vec![];
+    let count = db
+        .export_jsonl(None, &mut output, None, true, |count| progress.push(count))
+        .await
+        .expect("Error exporting profile");
+    assert_eq!(count, 2);
+    assert_eq!(progress.last(), Some(&2));
+    let lines = std::str::from_utf8(&output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("Invalid JSON"))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["category"], "cat1");
+    assert_eq!(lines[0]["name"], "one");
+    assert_eq!(
+        base64::decode(lines[0]["value"].as_str().unwrap()).unwrap(),
+        b"value1"
+    );
+    assert_eq!(lines[0]["tags"]["enc"], "a");
+    assert_eq!(lines[0]["tags"]["~plain"], "b");
+    assert_eq!(lines[0]["expiry"], expiry);
+    assert_eq!(lines[1]["name"], "two");
+    assert!(lines[1]["expiry"].is_null());
+
+    let mut output = vec![];
+    let count = db
+        .export_jsonl(None, &mut output, Some(vec!["cat2"]), true, |_| ())
+        .await
+        .expect("Error exporting profile");
+    assert_eq!(count, 1);
+    assert!(std::str::from_utf8(&output)
+        .unwrap()
+        .contains(r#""name":"two""#));
+}
+
+pub async fn db_export_jsonl_partial<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for (name, state) in &[("a", "completed"), ("b", "pending"), ("c", "completed")] {
+        conn.insert(
+            "connection",
+            name,
+            name.as_bytes(),
+            Some(&[EntryTag::Plaintext("state".to_string(), state.to_string())]),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
+    conn.insert("other", "d", b"d", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
+
+    let completed = TagFilter::is_eq("~state", "completed");
+    let mut output = vec![];
+    let count = db
+        .export_jsonl_partial(
+            None,
+            &mut output,
+            vec![
+                CategorySpec::new("connection").with_tag_filter(completed.clone()),
+                CategorySpec::new("other").without_values(),
+            ],
+            true,
+            |_| (),
+        )
+        .await
+        .expect("Error exporting profile");
+    assert_eq!(count, 3);
+    let lines = std::str::from_utf8(&output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("Invalid JSON"))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0]["header"]["categories"][0]["category"],
+        "connection"
+    );
+    assert_eq!(lines[0]["header"]["categories"][1]["values"], false);
+    assert_eq!(lines[1]["name"], "a");
+    assert_eq!(lines[2]["name"], "c");
+    assert_eq!(lines[3]["name"], "d");
+    assert!(lines[3].get("value").is_none());
+
+    // import into another profile, where one record already exists
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    conn.insert("connection", "a", b"existing", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
+
+    let report = db
+        .import_jsonl(
+            Some(profile.clone()),
+            output.as_slice(),
+            ImportConflict::Skip,
+        )
+        .await
+        .expect("Error importing profile");
+    assert_eq!(report.imported, 1);
+    assert_eq!(report.replaced, 0);
+    assert_eq!(
+        report
+            .skipped
+            .iter()
+            .map(|skip| (skip.line, skip.name.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(2, "a"), (4, "d")]
+    );
+    let categories = report.categories.expect("Expected export header");
+    assert_eq!(categories.len(), 2);
+    assert_eq!(
+        categories[0].tag_filter.as_deref(),
+        Some(completed.to_string().unwrap().as_str())
+    );
+    assert!(!categories[1].values);
+
+    let err = db
+        .import_jsonl(
+            Some(profile.clone()),
+            output.as_slice(),
+            ImportConflict::Fail,
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+
+    let report = db
+        .import_jsonl(
+            Some(profile.clone()),
+            output.as_slice(),
+            ImportConflict::Replace,
+        )
+        .await
+        .expect("Error importing profile");
+    assert_eq!(report.imported, 0);
+    assert_eq!(report.replaced, 2);
+
+    let mut conn = db.session(Some(profile)).await.expect(ERR_SESSION);
+    let entry = conn
+        .fetch("connection", "a", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&*entry.value, b"a");
+    assert_eq!(
+        entry.tags,
+        vec![EntryTag::Plaintext(
+            "state".to_string(),
+            "completed".to_string()
+        )]
+    );
+    assert!(conn
+        .fetch("connection", "b", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+
+    let err = db
+        .import_jsonl(None, &b"{\"category\":\"c\"}\n"[..], ImportConflict::Skip)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
+
+pub async fn db_fetch_if_modified<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    assert!(conn
+        .fetch_with_etag("etag", "missing", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+    assert!(conn
+        .fetch_if_modified("etag", "missing", b"etag")
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+
+    conn.insert("etag", "name", b"first", None, None)
+        .await
+        .expect(ERR_INSERT);
+    let (entry, etag) = conn
+        .fetch_with_etag("etag", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&*entry.value, b"first");
+    assert_eq!(
+        conn.fetch_if_modified("etag", "name", &etag)
+            .await
+            .expect(ERR_FETCH),
+        Some(ConditionalFetch::NotModified)
+    );
+
+    // the entity tag is unchanged when the same value is written again
+    conn.replace("etag", "name", b"first", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(
+        conn.fetch_if_modified("etag", "name", &etag)
+            .await
+            .expect(ERR_FETCH),
+        Some(ConditionalFetch::NotModified)
+    );
+
+    conn.replace("etag", "name", b"second", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    match conn
+        .fetch_if_modified("etag", "name", &etag)
+        .await
+        .expect(ERR_FETCH)
+    {
+        Some(ConditionalFetch::Modified {
+            entry,
+            etag: new_etag,
+        }) => {
+            assert_eq!(&*entry.value, b"second");
+            assert_ne!(new_etag, etag);
+            let (_, fetched_etag) = conn
+                .fetch_with_etag("etag", "name", false)
+                .await
+                .expect(ERR_FETCH)
+                .expect(ERR_REQ_ROW);
+            assert_eq!(fetched_etag, new_etag);
+        }
+        other => panic!("Expected modified record, found: {:?}", other),
+    }
+
+    // the entity tag is bound to the record
+    conn.insert("etag", "other", b"first", None, None)
+        .await
+        .expect(ERR_INSERT);
+    let (_, other_etag) = conn
+        .fetch_with_etag("etag", "other", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_ne!(other_etag, etag);
+}
+
+pub async fn db_keypair_list<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for idx in 0..3 {
+        let key = LocalKey::generate(KeyAlg::Ed25519, false).expect("Error creating key");
+        let usage = if idx == 0 { "signing" } else { "agreement" };
+        conn.insert_key(
+            &format!("key{}", idx),
+            &key,
+            Some("meta"),
+            Some(&[EntryTag::Encrypted("usage".to_string(), usage.to_string())]),
+            None,
+        )
+        .await
+        .expect("Error inserting key");
+    }
+    drop(conn);
+
+    let keys = db
+        .list_keys(None, None, None, None, None, None)
+        .await
+        .expect(ERR_LIST_KEYS);
+    assert_eq!(keys.len(), 3);
+    for key in keys.iter() {
+        assert_eq!(key.algorithm(), Some("ed25519"));
+        assert_eq!(key.metadata(), Some("meta"));
+        assert_eq!(key.thumbprints().len(), 1);
+    }
+
+    let page = db
+        .list_keys(None, Some("ed25519"), None, None, Some(1), Some(1))
+        .await
+        .expect(ERR_LIST_KEYS);
+    assert_eq!(page.len(), 1);
+
+    let keys = db
+        .list_keys(
+            None,
+            None,
+            None,
+            Some(TagFilter::is_eq("usage", "agreement")),
+            None,
+            None,
+        )
+        .await
+        .expect(ERR_LIST_KEYS);
+    assert_eq!(keys.len(), 2);
+    assert_eq!(
+        keys[0].tags(),
+        &[EntryTag::Encrypted(
+            "usage".to_string(),
+            "agreement".to_string()
+        )]
+    );
+}
+
+// pub async fn db_keypair_create_fetch<DB: Backend>(db: &Store<DB>) {
+//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+//     let metadata = "meta".to_owned();
+//     let key_info = conn
+//         .create_keypair(KeyAlg::Ed25519, Some(&metadata), None, None)
+//         .await
+//         .expect(ERR_CREATE_KEYPAIR);
+//     assert_eq!(key_info.params.metadata, Some(metadata));
+
+//     let found = conn
+//         .fetch_key(key_info.category.clone(), &key_info.ident, false)
+//         .await
+//         .expect(ERR_FETCH_KEY);
+//     assert_eq!(Some(key_info), found);
+// }
+
+// pub async fn db_keypair_sign_verify<DB: Backend>(db: &Store<DB>) {
+//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+//     let key_info = conn
+//         .create_keypair(KeyAlg::Ed25519, None, None, None)
+//         .await
+//         .expect(ERR_CREATE_KEYPAIR);
+
+//     let message = b"message".to_vec();
+//     let sig = conn
+//         .sign_message(&key_info.ident, &message)
+//         .await
+//         .expect(ERR_SIGN);
+
+//     assert_eq!(
+//         verify_signature(&key_info.ident, &message, &sig).expect(ERR_VERIFY),
+//         true
+//     );
+
+//     assert_eq!(
+//         verify_signature(&key_info.ident, b"bad input", &sig).expect(ERR_VERIFY),
+//         false
+//     );
+
+//     assert_eq!(
+//         verify_signature(
+//             &key_info.ident,
+//             // [0u8; 64]
+//             b"xt19s1sp2UZCGhy9rNyb1FtxdKiDGZZPNFnc1KiM9jYYEuHxuwNeFf1oQKsn8zv6yvYBGhXa83288eF4MqN1oDq",
+//             &sig
+//         ).expect(ERR_VERIFY),
+//         false
+//     );
+
+//     assert_eq!(
+//         verify_signature(&key_info.ident, &message, b"bad sig").is_err(),
+//         true
+//     );
+
+//     let err = verify_signature("not a key", &message, &sig).expect_err(ERR_REQ_ERR);
+//     assert_eq!(err.kind(), ErrorKind::Input);
+// }
+
+// pub async fn db_keypair_pack_unpack_anon<DB: Backend>(db: &Store<DB>) {
+//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+//     let recip_key = conn
+//         .create_keypair(KeyAlg::Ed25519, None, None, None)
+//         .await
+//         .expect(ERR_CREATE_KEYPAIR);
+
+//     let msg = b"message".to_vec();
+
+//     let packed = conn
+//         .pack_message(vec![recip_key.ident.as_str()], None, &msg)
+//         .await
+//         .expect(ERR_PACK);
+
+//     let (unpacked, p_recip, p_send) = conn.unpack_message(&packed).await.expect(ERR_UNPACK);
+//     assert_eq!(unpacked, msg);
+//     assert_eq!(p_recip.to_string(), recip_key.ident);
+//     assert_eq!(p_send, None);
+// }
+
+// pub async fn db_keypair_pack_unpack_auth<DB: Backend>(db: &Store<DB>) {
 //     let mut conn = db.session(None).await.expect(ERR_SESSION);
 
-//     let metadata = "meta".to_owned();
-//     let key_info = conn
-//         .create_keypair(KeyAlg::Ed25519, Some(&metadata), None, None)
-//         .await
-//         .expect(ERR_CREATE_KEYPAIR);
-//     assert_eq!(key_info.params.metadata, Some(metadata));
+//     let sender_key = conn
+//         .create_keypair(KeyAlg::Ed25519, None, None, None)
+//         .await
+//         .expect(ERR_CREATE_KEYPAIR);
+//     let recip_key = conn
+//         .create_keypair(KeyAlg::Ed25519, None, None, None)
+//         .await
+//         .expect(ERR_CREATE_KEYPAIR);
+
+//     let msg = b"message".to_vec();
+
+//     let packed = conn
+//         .pack_message(
+//             vec![recip_key.ident.as_str()],
+//             Some(&sender_key.ident),
+//             &msg,
+//         )
+//         .await
+//         .expect(ERR_PACK);
+
+//     let (unpacked, p_recip, p_send) = conn.unpack_message(&packed).await.expect(ERR_UNPACK);
+//     assert_eq!(unpacked, msg);
+//     assert_eq!(p_recip.to_string(), recip_key.ident);
+//     assert_eq!(p_send.map(|k| k.to_string()), Some(sender_key.ident));
+// }
+
+pub async fn db_txn_rollback<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    conn.rollback()
+        .await
+        .expect("Error rolling back transaction");
+
+    let mut conn = db.session(None).await.expect("Error starting new session");
+
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, false)
+        .await
+        .expect("Error fetching test row");
+    assert_eq!(row, None);
+}
+
+pub async fn db_txn_drop<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db
+        .transaction(None)
+        .await
+        .expect("Error starting new transaction");
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    drop(conn);
+
+    let mut conn = db.session(None).await.expect("Error starting new session");
+
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, false)
+        .await
+        .expect("Error fetching test row");
+    assert_eq!(row, None);
+}
+
+// test that session does NOT have transaction rollback behaviour
+pub async fn db_session_drop<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    drop(conn);
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, false)
+        .await
+        .expect(ERR_FETCH);
+    assert_eq!(row, Some(test_row));
+}
+
+pub async fn db_txn_commit<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    conn.commit().await.expect("Error committing transaction");
+
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, false)
+        .await
+        .expect(ERR_FETCH);
+    assert_eq!(row, Some(test_row));
+}
+
+pub async fn db_txn_fetch_for_update<DB: Backend>(db: &Store<DB>) {
+    let test_row = Entry::new("category", "name", "value", Vec::new());
+
+    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+
+    conn.insert(
+        &test_row.category,
+        &test_row.name,
+        &test_row.value,
+        Some(test_row.tags.as_slice()),
+        None,
+    )
+    .await
+    .expect(ERR_INSERT);
+
+    // could detect that a second transaction would block here?
+    // depends on the backend. just checking that no SQL errors occur for now.
+    let row = conn
+        .fetch(&test_row.category, &test_row.name, true)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row, test_row);
+
+    let rows = conn
+        .fetch_all(&test_row.category, None, Some(2), true)
+        .await
+        .expect(ERR_FETCH_ALL);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0], test_row);
+
+    conn.commit().await.expect("Error committing transaction");
+}
 
-//     let found = conn
-//         .fetch_key(key_info.category.clone(), &key_info.ident, false)
-//         .await
-//         .expect(ERR_FETCH_KEY);
-//     assert_eq!(Some(key_info), found);
-// }
+pub async fn db_profile_key_recovery<DB: Backend>(db: &Store<DB>) {
+    let recovery = LocalKey::generate(KeyAlg::X25519, false).expect("Error creating key");
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    conn.insert("category", "name", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
 
-// pub async fn db_keypair_sign_verify<DB: Backend>(db: &Store<DB>) {
-//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let envelope = db
+        .export_profile_key(Some(profile.clone()), &recovery)
+        .await
+        .expect("Error exporting profile key");
 
-//     let key_info = conn
-//         .create_keypair(KeyAlg::Ed25519, None, None, None)
-//         .await
-//         .expect(ERR_CREATE_KEYPAIR);
+    // a working key is only replaced when forced
+    let err = db
+        .import_profile_key(profile.clone(), &recovery, &envelope, false)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+    db.import_profile_key(profile.clone(), &recovery, &envelope, true)
+        .await
+        .expect("Error importing profile key");
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.value, b"value"[..]);
+    drop(conn);
 
-//     let message = b"message".to_vec();
-//     let sig = conn
-//         .sign_message(&key_info.ident, &message)
-//         .await
-//         .expect(ERR_SIGN);
+    // the envelope cannot be opened with another key
+    let other = LocalKey::generate(KeyAlg::X25519, false).expect("Error creating key");
+    db.import_profile_key("restored".to_string(), &other, &envelope, false)
+        .await
+        .expect_err(ERR_REQ_ERR);
 
-//     assert_eq!(
-//         verify_signature(&key_info.ident, &message, &sig).expect(ERR_VERIFY),
-//         true
-//     );
+    // importing under a new name creates the profile
+    db.import_profile_key("restored".to_string(), &recovery, &envelope, false)
+        .await
+        .expect("Error importing profile key");
+    let mut conn = db
+        .session(Some("restored".to_string()))
+        .await
+        .expect(ERR_SESSION);
+    conn.insert("category", "name", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+}
 
-//     assert_eq!(
-//         verify_signature(&key_info.ident, b"bad input", &sig).expect(ERR_VERIFY),
-//         false
-//     );
+pub async fn db_profile_passphrase<DB: Backend>(db: &Store<DB>) {
+    let passphrase = generate_raw_store_key(None).expect("Error creating raw key");
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    conn.insert("category", "name", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
 
-//     assert_eq!(
-//         verify_signature(
-//             &key_info.ident,
-//             // [0u8; 64]
-//             b"xt19s1sp2UZCGhy9rNyb1FtxdKiDGZZPNFnc1KiM9jYYEuHxuwNeFf1oQKsn8zv6yvYBGhXa83288eF4MqN1oDq",
-//             &sig
-//         ).expect(ERR_VERIFY),
-//         false
-//     );
+    // the default profile cannot be protected
+    let err = db
+        .protect_profile(
+            db.get_profile_name().to_string(),
+            StoreKeyMethod::RawKey,
+            passphrase.as_ref(),
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
 
-//     assert_eq!(
-//         verify_signature(&key_info.ident, &message, b"bad sig").is_err(),
-//         true
-//     );
+    db.protect_profile(profile.clone(), StoreKeyMethod::RawKey, passphrase.as_ref())
+        .await
+        .expect("Error protecting profile");
 
-//     let err = verify_signature("not a key", &message, &sig).expect_err(ERR_REQ_ERR);
-//     assert_eq!(err.kind(), ErrorKind::Input);
-// }
+    // the profile stays unlocked until it is locked explicitly
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    conn.fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    drop(conn);
+    assert!(db
+        .lock_profile(profile.clone())
+        .await
+        .expect("Error locking profile"));
 
-// pub async fn db_keypair_pack_unpack_anon<DB: Backend>(db: &Store<DB>) {
-//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    let err = conn
+        .fetch("category", "name", false)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Locked);
+    drop(conn);
 
-//     let recip_key = conn
-//         .create_keypair(KeyAlg::Ed25519, None, None, None)
-//         .await
-//         .expect(ERR_CREATE_KEYPAIR);
+    let other = generate_raw_store_key(None).expect("Error creating raw key");
+    let err = db
+        .unlock_profile(profile.clone(), other.as_ref())
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Encryption);
 
-//     let msg = b"message".to_vec();
+    db.unlock_profile(profile.clone(), passphrase.as_ref())
+        .await
+        .expect("Error unlocking profile");
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.value, b"value"[..]);
+    drop(conn);
 
-//     let packed = conn
-//         .pack_message(vec![recip_key.ident.as_str()], None, &msg)
-//         .await
-//         .expect(ERR_PACK);
+    // an unprotected profile cannot be unlocked
+    let plain = db.create_profile(None).await.expect(ERR_PROFILE);
+    let err = db
+        .unlock_profile(plain, passphrase.as_ref())
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
 
-//     let (unpacked, p_recip, p_send) = conn.unpack_message(&packed).await.expect(ERR_UNPACK);
-//     assert_eq!(unpacked, msg);
-//     assert_eq!(p_recip.to_string(), recip_key.ident);
-//     assert_eq!(p_send, None);
-// }
+pub async fn db_access_log<DB: Backend>(db: &Store<DB>) {
+    let passphrase = generate_raw_store_key(None).expect("Error creating raw key");
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    db.protect_profile(profile.clone(), StoreKeyMethod::RawKey, passphrase.as_ref())
+        .await
+        .expect("Error protecting profile");
+    assert!(db
+        .read_access_log(None, None)
+        .await
+        .expect("Error reading access log")
+        .is_empty());
+    db.enable_access_log()
+        .await
+        .expect("Error enabling access log");
 
-// pub async fn db_keypair_pack_unpack_auth<DB: Backend>(db: &Store<DB>) {
-//     let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let other = generate_raw_store_key(None).expect("Error creating raw key");
+    db.unlock_profile(profile.clone(), other.as_ref())
+        .await
+        .expect_err(ERR_REQ_ERR);
+    db.unlock_profile(profile.clone(), passphrase.as_ref())
+        .await
+        .expect("Error unlocking profile");
 
-//     let sender_key = conn
-//         .create_keypair(KeyAlg::Ed25519, None, None, None)
-//         .await
-//         .expect(ERR_CREATE_KEYPAIR);
-//     let recip_key = conn
-//         .create_keypair(KeyAlg::Ed25519, None, None, None)
-//         .await
-//         .expect(ERR_CREATE_KEYPAIR);
+    let log = db
+        .read_access_log(None, None)
+        .await
+        .expect("Error reading access log");
+    assert_eq!(
+        log.iter()
+            .map(|entry| (entry.event, entry.profile.clone(), entry.success))
+            .collect::<Vec<_>>(),
+        vec![
+            (AccessEvent::UnlockProfile, Some(profile.clone()), true),
+            (AccessEvent::UnlockProfile, Some(profile), false),
+        ]
+    );
+}
+
+pub async fn db_index_locked_profile<DB: Backend>(db: &Store<DB>) {
+    let passphrase = generate_raw_store_key(None).expect("Error creating raw key");
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    conn.insert("cred::schema-1", "conn/1/msg", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
+    drop(conn);
+    db.protect_profile(profile.clone(), StoreKeyMethod::RawKey, passphrase.as_ref())
+        .await
+        .expect("Error protecting profile");
+    assert!(db
+        .lock_profile(profile.clone())
+        .await
+        .expect("Error locking profile"));
+
+    // the records of the locked profile are indexed when it is unlocked
+    db.enable_name_index()
+        .await
+        .expect("Error enabling name index");
+    db.enable_category_index()
+        .await
+        .expect("Error enabling category index");
+    db.unlock_profile(profile.clone(), passphrase.as_ref())
+        .await
+        .expect("Error unlocking profile");
+
+    assert_eq!(
+        db.list_categories_prefix(Some(profile.clone()), "cred::")
+            .await
+            .expect("Error listing categories"),
+        vec!["cred::schema-1"]
+    );
+    let mut scan = db
+        .scan_prefix(
+            Some(profile.clone()),
+            "cred::schema-1".to_string(),
+            "conn/1/".to_string(),
+            None,
+            None,
+            None,
+        )
+        .await
+        .expect(ERR_SCAN);
+    let rows = scan
+        .fetch_next()
+        .await
+        .expect(ERR_SCAN_NEXT)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].name, "conn/1/msg");
+
+    // the pending indexing is cleared once the records are indexed
+    assert!(db
+        .lock_profile(profile.clone())
+        .await
+        .expect("Error locking profile"));
+    db.unlock_profile(profile.clone(), passphrase.as_ref())
+        .await
+        .expect("Error unlocking profile");
+    assert_eq!(
+        db.list_categories_prefix(Some(profile), "cred::")
+            .await
+            .expect("Error listing categories"),
+        vec!["cred::schema-1"]
+    );
+}
+
+// a consumer which depends only on the `KeyValue` interface
+async fn exercise_key_value<K: KeyValue>(kv: &K) {
+    assert_eq!(kv.get("config/a").await.expect(ERR_FETCH), None);
+    kv.put("config/a", b"1").await.expect(ERR_INSERT);
+    kv.put("config/b/c", b"2").await.expect(ERR_INSERT);
+    kv.put("state/a", b"3").await.expect(ERR_INSERT);
+    kv.put("config/a", b"4").await.expect(ERR_REPLACE);
+    assert_eq!(
+        kv.get("config/a").await.expect(ERR_FETCH),
+        Some(b"4".to_vec())
+    );
+
+    assert_eq!(
+        kv.list("config/").await.expect(ERR_SCAN),
+        vec!["config/a".to_string(), "config/b/c".to_string()]
+    );
+    assert_eq!(
+        kv.list("config/b").await.expect(ERR_SCAN),
+        vec!["config/b/c".to_string()]
+    );
+    assert_eq!(
+        kv.list("").await.expect(ERR_SCAN),
+        vec![
+            "config/a".to_string(),
+            "config/b/c".to_string(),
+            "state/a".to_string()
+        ]
+    );
+
+    assert!(kv.delete("config/a").await.expect("Error deleting key"));
+    assert!(!kv.delete("config/a").await.expect("Error deleting key"));
+    assert_eq!(kv.get("config/a").await.expect(ERR_FETCH), None);
+
+    // a key must include a category
+    let err = kv.get("config").await.expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
+
+pub async fn db_key_value<DB: Backend>(db: &Store<DB>) {
+    exercise_key_value(&db.key_value()).await;
+
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let kv = db
+        .key_value()
+        .with_profile(profile)
+        .with_splitter(SeparatorSplitter::new("::").expect("Error creating splitter"));
+    kv.put("config::a", b"1").await.expect(ERR_INSERT);
+    assert_eq!(
+        kv.list("config::").await.expect(ERR_SCAN),
+        vec!["config::a".to_string()]
+    );
+    // the default profile is not affected
+    assert_eq!(
+        db.key_value().list("").await.expect(ERR_SCAN),
+        vec!["config/b/c".to_string(), "state/a".to_string()]
+    );
+}
+
+pub async fn db_reserved_tag_names<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    for tag in &[
+        EntryTag::Encrypted("$and".to_string(), "v".to_string()),
+        EntryTag::Encrypted("~t1".to_string(), "v".to_string()),
+        EntryTag::Plaintext("$t1".to_string(), "v".to_string()),
+        EntryTag::Int("~t1".to_string(), IntTagValue::new(1)),
+    ] {
+        let err = conn
+            .insert("reserved", "name", b"value", Some(&[tag.clone()]), None)
+            .await
+            .expect_err(ERR_REQ_ERR);
+        assert_eq!(err.kind(), ErrorKind::Input);
+    }
+    assert_eq!(conn.count("reserved", None).await.expect(ERR_COUNT), 0);
+
+    // the reserved characters may appear after the first character
+    let tags = [
+        EntryTag::Encrypted("t$and".to_string(), "v1".to_string()),
+        EntryTag::Plaintext("t~1".to_string(), "v2".to_string()),
+    ];
+    conn.insert("reserved", "name", b"value", Some(&tags), None)
+        .await
+        .expect(ERR_INSERT);
+    for query in &[r#"{"t$and": "v1"}"#, r#"{"~t~1": "v2"}"#] {
+        let filter = query.parse::<TagFilter>().expect("Error parsing tag query");
+        let rows = conn
+            .fetch_all("reserved", Some(filter), None, false)
+            .await
+            .expect(ERR_FETCH_ALL);
+        assert_eq!(rows.len(), 1);
+    }
+
+    let err = conn
+        .replace(
+            "reserved",
+            "name",
+            b"value",
+            Some(&[EntryTag::Encrypted("$or".to_string(), "v".to_string())]),
+            None,
+        )
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+    let entry = conn
+        .fetch("reserved", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(entry.tags.len(), 2);
+}
+
+pub async fn db_health<DB: Backend>(db: &Store<DB>) {
+    let deadline = Duration::from_secs(30);
+
+    // repeated probes return their connections to the pool
+    for _ in 0..50 {
+        db.ping(deadline).await.expect("Error pinging store");
+    }
+    for _ in 0..10 {
+        assert_eq!(db.health(deadline).await, HealthStatus::Healthy);
+    }
+}
+
+pub async fn db_fetch_by_tag<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+
+    let rows = [
+        ("name1", "t1", "p1"),
+        ("name2", "t2", "p2"),
+        ("name3", "t2", "p2"),
+    ];
+    for (name, enc, plain) in rows.iter().copied() {
+        let tags = vec![
+            EntryTag::Encrypted("thread".to_string(), enc.to_string()),
+            EntryTag::Plaintext("ref".to_string(), plain.to_string()),
+        ];
+        conn.insert("category", name, b"value", Some(tags.as_slice()), None)
+            .await
+            .expect(ERR_INSERT);
+    }
+
+    let found = conn
+        .fetch_by_tag("category", "thread", "t1", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(found.name, "name1");
+    let found = conn
+        .fetch_by_tag("category", "~ref", "p1", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(found.name, "name1");
+
+    let missing = conn
+        .fetch_by_tag("category", "thread", "t3", false)
+        .await
+        .expect(ERR_FETCH);
+    assert!(missing.is_none());
+
+    for (tag_name, tag_value) in [("thread", "t2"), ("~ref", "p2")].iter().copied() {
+        let err = conn
+            .fetch_by_tag("category", tag_name, tag_value, false)
+            .await
+            .expect_err("Expected non-unique result");
+        assert_eq!(err.kind(), ErrorKind::NotUnique);
+        assert!(err.to_string().contains("Found 2 records"));
+    }
+}
+
+pub async fn db_temp_scope<DB: Backend>(db: &Store<DB>) {
+    let ttl = Duration::from_secs(60);
+    let tags = vec![EntryTag::Encrypted("enc".to_string(), "value".to_string())];
+    let mut scope = db
+        .temp_scope(None, ttl)
+        .expect("Error starting temporary scope");
+    scope
+        .update("category", "name", b"value", Some(tags.as_slice()))
+        .await
+        .expect(ERR_INSERT);
+    let row = scope
+        .fetch("category", "name")
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(row.category, "category");
+    assert_eq!(&row.value[..], b"value");
+    assert!(row.expiry.is_some());
+
+    // temporary records are not visible to sessions
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert!(conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 0);
+    drop(conn);
+
+    scope
+        .update("category", "name", b"value-2", Some(tags.as_slice()))
+        .await
+        .expect(ERR_REPLACE);
+    assert_eq!(scope.commit().await.expect("Error committing scope"), 1);
 
-//     let msg = b"message".to_vec();
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let row = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"value-2");
+    assert_eq!(row.tags, tags);
+    assert_eq!(row.expiry, None);
+    drop(conn);
 
-//     let packed = conn
-//         .pack_message(
-//             vec![recip_key.ident.as_str()],
-//             Some(&sender_key.ident),
-//             &msg,
-//         )
-//         .await
-//         .expect(ERR_PACK);
+    // the records of a dropped or discarded scope are not committed
+    let mut scope = db
+        .temp_scope(None, ttl)
+        .expect("Error starting temporary scope");
+    scope
+        .update("category", "dropped", b"value", None)
+        .await
+        .expect(ERR_INSERT);
+    drop(scope);
+    let mut scope = db
+        .temp_scope(None, ttl)
+        .expect("Error starting temporary scope");
+    scope
+        .update("category", "discarded", b"value", None)
+        .await
+        .expect(ERR_INSERT);
+    scope.discard().await.expect("Error discarding scope");
+
+    // a record which already exists prevents the whole scope from being committed
+    let mut scope = db
+        .temp_scope(None, ttl)
+        .expect("Error starting temporary scope");
+    for name in ["name", "other"].iter().copied() {
+        scope
+            .update("category", name, b"value-3", None)
+            .await
+            .expect(ERR_INSERT);
+    }
+    let err = scope.commit().await.expect_err("Expected duplicate error");
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
 
-//     let (unpacked, p_recip, p_send) = conn.unpack_message(&packed).await.expect(ERR_UNPACK);
-//     assert_eq!(unpacked, msg);
-//     assert_eq!(p_recip.to_string(), recip_key.ident);
-//     assert_eq!(p_send.map(|k| k.to_string()), Some(sender_key.ident));
-// }
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 1);
+    conn.set_expiry_filter(ExpiryFilter::Include);
+    assert_eq!(conn.count("category", None).await.expect(ERR_COUNT), 1);
+}
 
-pub async fn db_txn_rollback<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+pub async fn db_wql_dialects<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let rows = [
+        ("conn-1", "active", 30, true),
+        ("conn-2", "active", 41, false),
+        ("conn-3", "invited", 25, true),
+        ("conn-4", "invited", 30, false),
+    ];
+    for (name, state, age, trusted) in rows.iter().copied() {
+        let tags = vec![
+            EntryTag::Encrypted("state".to_string(), state.to_string()),
+            EntryTag::Plaintext("their_role".to_string(), "inviter".to_string()),
+            EntryTag::Int("age".to_string(), IntTagValue::new(age)),
+            EntryTag::Bool("trusted".to_string(), trusted),
+        ];
+        conn.insert("connection", name, b"value", Some(tags.as_slice()), None)
+            .await
+            .expect(ERR_INSERT);
+    }
 
-    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+    // pairs of equivalent filters in the indy and askar dialects
+    let corpus: &[(&str, &str, &[&str])] = &[
+        (
+            r#"{"state": "active"}"#,
+            r#"{"state": "active"}"#,
+            &["conn-1", "conn-2"],
+        ),
+        (
+            r#"{"~age": "30"}"#,
+            r#"{"~age": 30}"#,
+            &["conn-1", "conn-4"],
+        ),
+        (
+            r#"{"~age": {"$gt": "29"}, "~trusted": "true"}"#,
+            r#"{"~age": {"$gt": 29}, "~trusted": true}"#,
+            &["conn-1"],
+        ),
+        (
+            r#"{"~age": {"$in": ["25", "41"]}}"#,
+            r#"{"~age": {"$in": [25, 41]}}"#,
+            &["conn-2", "conn-3"],
+        ),
+        (
+            r#"{"$or": [{"state": "invited", "~trusted": "false"}, {"~age": {"$lte": "25"}}]}"#,
+            r#"{"$or": [{"state": "invited", "~trusted": false}, {"~age": {"$lte": 25}}]}"#,
+            &["conn-3", "conn-4"],
+        ),
+        (
+            r#"{"$not": {"~trusted": "true"}, "~their_role": "inviter"}"#,
+            r#"{"$not": {"~trusted": true}, "~their_role": "inviter"}"#,
+            &["conn-2", "conn-4"],
+        ),
+        (
+            r#"{"$exist": ["state"], "~age": {"$neq": "30"}}"#,
+            r#"{"$exist": ["state"], "~age": {"$neq": 30}}"#,
+            &["conn-2", "conn-3"],
+        ),
+    ];
+    for (indy, askar, expected) in corpus.iter().copied() {
+        let filters = [
+            TagFilter::from_json(indy, WqlDialect::Indy).expect("Error parsing tag query"),
+            TagFilter::from_json(askar, WqlDialect::Askar).expect("Error parsing tag query"),
+            askar.parse().expect("Error parsing tag query"),
+        ];
+        for filter in filters.iter() {
+            let mut names = conn
+                .fetch_all("connection", Some(filter.clone()), None, false)
+                .await
+                .expect(ERR_FETCH_ALL)
+                .into_iter()
+                .map(|row| row.name)
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, expected, "{}", askar);
+            assert_eq!(
+                conn.count("connection", Some(filter.clone()))
+                    .await
+                    .expect(ERR_COUNT),
+                expected.len() as i64
+            );
+        }
+    }
+}
+
+pub async fn db_rename<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
 
+    let tags = vec![
+        EntryTag::Encrypted("enc".to_string(), "a".to_string()),
+        EntryTag::Plaintext("plain".to_string(), "b".to_string()),
+    ];
     conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
+        "rename-a",
+        "name",
+        b"value",
+        Some(tags.as_slice()),
+        Some(60_000),
     )
     .await
     .expect(ERR_INSERT);
+    conn.insert("rename-b", "taken", b"other", None, None)
+        .await
+        .expect(ERR_INSERT);
+    let expiry = conn
+        .fetch("rename-a", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW)
+        .expiry;
 
-    conn.rollback()
+    conn.rename("rename-a", "name", Some("rename-b"), Some("moved"))
         .await
-        .expect("Error rolling back transaction");
+        .expect("Error renaming test row");
+    let missing = conn
+        .fetch("rename-a", "name", false)
+        .await
+        .expect(ERR_FETCH);
+    assert!(missing.is_none());
+    let row = conn
+        .fetch("rename-b", "moved", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"value");
+    assert_eq!(row.tags, tags);
+    assert_eq!(row.expiry, expiry);
 
-    let mut conn = db.session(None).await.expect("Error starting new session");
+    for (tag_name, tag_value) in [("enc", "a"), ("~plain", "b")].iter().copied() {
+        let count = conn
+            .count("rename-b", Some(TagFilter::is_eq(tag_name, tag_value)))
+            .await
+            .expect(ERR_COUNT);
+        assert_eq!(count, 1);
+        let count = conn
+            .count("rename-a", Some(TagFilter::is_eq(tag_name, tag_value)))
+            .await
+            .expect(ERR_COUNT);
+        assert_eq!(count, 0);
+    }
 
+    conn.rename("rename-b", "moved", None, Some("renamed"))
+        .await
+        .expect("Error renaming test row");
     let row = conn
-        .fetch(&test_row.category, &test_row.name, false)
+        .fetch("rename-b", "renamed", false)
         .await
-        .expect("Error fetching test row");
-    assert_eq!(row, None);
-}
-
-pub async fn db_txn_drop<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"value");
 
-    let mut conn = db
-        .transaction(None)
+    let err = conn
+        .rename("rename-b", "renamed", None, Some("taken"))
         .await
-        .expect("Error starting new transaction");
+        .expect_err("Expected duplicate error");
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+    let row = conn
+        .fetch("rename-b", "taken", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&row.value[..], b"other");
+    let row = conn
+        .fetch("rename-b", "renamed", false)
+        .await
+        .expect(ERR_FETCH);
+    assert!(row.is_some());
 
-    conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
-    )
-    .await
-    .expect(ERR_INSERT);
+    let err = conn
+        .rename("rename-b", "missing", None, Some("other"))
+        .await
+        .expect_err("Expected not found error");
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
 
+pub async fn db_audit_decryption<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for idx in 0..5 {
+        let tags = vec![EntryTag::Encrypted("idx".to_string(), idx.to_string())];
+        conn.insert(
+            "audit",
+            &format!("name-{}", idx),
+            b"value",
+            Some(&tags),
+            None,
+        )
+        .await
+        .expect(ERR_INSERT);
+    }
     drop(conn);
 
-    let mut conn = db.session(None).await.expect("Error starting new session");
+    let report = db
+        .audit_decryption(None, DecryptionAuditOptions::default(), None)
+        .await
+        .expect("Error performing audit");
+    assert!(report.is_ok());
+    assert!(report.complete);
+    assert_eq!(report.checked, 5);
 
-    let row = conn
-        .fetch(&test_row.category, &test_row.name, false)
+    let report = db
+        .audit_decryption(
+            None,
+            DecryptionAuditOptions {
+                categories: Some(vec!["other".to_string()]),
+                ..Default::default()
+            },
+            None,
+        )
         .await
-        .expect("Error fetching test row");
-    assert_eq!(row, None);
-}
+        .expect("Error performing audit");
+    assert_eq!((report.scanned, report.checked), (5, 0));
 
-// test that session does NOT have transaction rollback behaviour
-pub async fn db_session_drop<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+    let err = db
+        .audit_decryption(
+            None,
+            DecryptionAuditOptions {
+                sample_ratio: 0.0,
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .expect_err("Expected invalid sample ratio");
+    assert_eq!(err.kind(), ErrorKind::Input);
+}
 
+pub async fn db_fetch_filtered<DB: Backend>(db: &Store<DB>) {
     let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let tags = vec![
+        EntryTag::Encrypted("state".to_string(), "active".to_string()),
+        EntryTag::Plaintext("kind".to_string(), "a".to_string()),
+    ];
+    conn.insert("category", "name", b"value", Some(tags.as_slice()), None)
+        .await
+        .expect(ERR_INSERT);
 
-    conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
-    )
-    .await
-    .expect(ERR_INSERT);
-
-    drop(conn);
+    for filter in [
+        TagFilter::is_eq("state", "active"),
+        TagFilter::is_eq("~kind", "a"),
+        TagFilter::all_of(vec![
+            TagFilter::is_eq("state", "active"),
+            TagFilter::is_not_eq("~kind", "b"),
+        ]),
+    ]
+    .iter()
+    {
+        let found = conn
+            .fetch_filtered("category", "name", filter.clone(), false)
+            .await
+            .expect(ERR_FETCH);
+        match found {
+            Some(FilteredFetch::Matched(entry)) => {
+                assert_eq!(&entry.value[..], b"value");
+                assert_eq!(entry.tags, tags);
+            }
+            other => panic!("Expected matching record, found {:?}", other),
+        }
+    }
 
-    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    for filter in [
+        TagFilter::is_eq("state", "inactive"),
+        TagFilter::is_eq("~kind", "b"),
+        TagFilter::exist(vec!["other".to_string()]),
+    ]
+    .iter()
+    {
+        let found = conn
+            .fetch_filtered("category", "name", filter.clone(), false)
+            .await
+            .expect(ERR_FETCH);
+        assert_eq!(found, Some(FilteredFetch::Filtered));
+        assert!(found.and_then(FilteredFetch::into_entry).is_none());
+    }
 
-    let row = conn
-        .fetch(&test_row.category, &test_row.name, false)
+    let missing = conn
+        .fetch_filtered(
+            "category",
+            "missing",
+            TagFilter::is_eq("state", "active"),
+            false,
+        )
         .await
         .expect(ERR_FETCH);
-    assert_eq!(row, Some(test_row));
-}
-
-pub async fn db_txn_commit<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+    assert!(missing.is_none());
+    drop(conn);
 
-    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+    let mut txn = db.transaction(None).await.expect(ERR_TRANSACTION);
+    let found = txn
+        .fetch_filtered(
+            "category",
+            "name",
+            TagFilter::is_eq("state", "active"),
+            true,
+        )
+        .await
+        .expect(ERR_FETCH)
+        .and_then(FilteredFetch::into_entry);
+    assert!(found.is_some());
+    txn.commit().await.expect("Error committing transaction");
+}
 
+pub async fn db_copy_entry<DB: Backend>(db: &Store<DB>) {
+    let profile = db.create_profile(None).await.expect(ERR_PROFILE);
+    let tags = vec![
+        EntryTag::Encrypted("state".to_string(), "active".to_string()),
+        EntryTag::Plaintext("kind".to_string(), "a".to_string()),
+    ];
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
     conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
+        "category",
+        "name",
+        b"value",
+        Some(tags.as_slice()),
+        Some(60_000),
     )
     .await
     .expect(ERR_INSERT);
+    drop(conn);
 
-    conn.commit().await.expect("Error committing transaction");
+    db.copy_entry(None, Some(profile.clone()), "category", "name", false)
+        .await
+        .expect("Error copying record");
+    let mut conn = db.session(Some(profile.clone())).await.expect(ERR_SESSION);
+    let copied = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&copied.value[..], b"value");
+    assert_eq!(copied.tags, tags);
+    assert!(copied.expiry.is_some());
+    drop(conn);
 
+    // the record is copied, leaving the source in place
     let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert!(conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_some());
+    conn.replace("category", "name", b"updated", None, None)
+        .await
+        .expect(ERR_REPLACE);
+    drop(conn);
 
-    let row = conn
-        .fetch(&test_row.category, &test_row.name, false)
+    let err = db
+        .copy_entry(None, Some(profile.clone()), "category", "name", false)
         .await
-        .expect(ERR_FETCH);
-    assert_eq!(row, Some(test_row));
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+    let err = db
+        .copy_entry(None, Some(profile.clone()), "category", "missing", false)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let err = db
+        .copy_entry(None, None, "category", "name", true)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Input);
+
+    // a failed move leaves the source record in place
+    let err = db
+        .move_entry(None, Some(profile.clone()), "category", "name", false)
+        .await
+        .expect_err(ERR_REQ_ERR);
+    assert_eq!(err.kind(), ErrorKind::Duplicate);
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert!(conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_some());
+    drop(conn);
+
+    db.move_entry(None, Some(profile.clone()), "category", "name", true)
+        .await
+        .expect("Error moving record");
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    assert!(conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .is_none());
+    drop(conn);
+    let mut conn = db.session(Some(profile)).await.expect(ERR_SESSION);
+    let moved = conn
+        .fetch("category", "name", false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&moved.value[..], b"updated");
+    assert!(moved.tags.is_empty());
+    assert!(moved.expiry.is_none());
 }
 
-pub async fn db_txn_fetch_for_update<DB: Backend>(db: &Store<DB>) {
-    let test_row = Entry::new("category", "name", "value", Vec::new());
+pub async fn db_fetch_with_tags<DB: Backend>(db: &Store<DB>) {
+    let mut conn = db.session(None).await.expect(ERR_SESSION);
+    let tags = vec![
+        EntryTag::Encrypted("state".to_string(), "active".to_string()),
+        EntryTag::Plaintext("kind".to_string(), "a".to_string()),
+        EntryTag::Plaintext("size".to_string(), "large".to_string()),
+    ];
+    conn.insert("category", "name", b"value", Some(tags.as_slice()), None)
+        .await
+        .expect(ERR_INSERT);
+    conn.insert("category", "untagged", b"value", None, None)
+        .await
+        .expect(ERR_INSERT);
 
-    let mut conn = db.transaction(None).await.expect(ERR_TRANSACTION);
+    let found = conn
+        .fetch_with_tags("category", "name", TagRetrieval::All, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&found.entry.value[..], b"value");
+    assert_eq!(found.entry.tags, tags);
+    assert_eq!(found.tag_count, Some(3));
 
-    conn.insert(
-        &test_row.category,
-        &test_row.name,
-        &test_row.value,
-        Some(test_row.tags.as_slice()),
-        None,
-    )
-    .await
-    .expect(ERR_INSERT);
+    let found = conn
+        .fetch_with_tags("category", "name", TagRetrieval::PlaintextOnly, false)
+        .await
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(found.entry.tags, tags[1..].to_vec());
+    assert_eq!(found.tag_count, Some(3));
 
-    // could detect that a second transaction would block here?
-    // depends on the backend. just checking that no SQL errors occur for now.
-    let row = conn
-        .fetch(&test_row.category, &test_row.name, true)
+    let found = conn
+        .fetch_with_tags("category", "name", TagRetrieval::CountOnly, false)
         .await
         .expect(ERR_FETCH)
         .expect(ERR_REQ_ROW);
-    assert_eq!(row, test_row);
+    assert!(found.entry.tags.is_empty());
+    assert_eq!(found.tag_count, Some(3));
 
-    let rows = conn
-        .fetch_all(&test_row.category, None, Some(2), true)
+    let found = conn
+        .fetch_with_tags("category", "name", false, false)
         .await
-        .expect(ERR_FETCH_ALL);
-    assert_eq!(rows.len(), 1);
-    assert_eq!(rows[0], test_row);
+        .expect(ERR_FETCH)
+        .expect(ERR_REQ_ROW);
+    assert_eq!(&found.entry.value[..], b"value");
+    assert!(found.entry.tags.is_empty());
+    assert_eq!(found.tag_count, None);
+
+    for mode in [
+        TagRetrieval::All,
+        TagRetrieval::PlaintextOnly,
+        TagRetrieval::CountOnly,
+    ]
+    .iter()
+    {
+        let found = conn
+            .fetch_with_tags("category", "untagged", *mode, false)
+            .await
+            .expect(ERR_FETCH)
+            .expect(ERR_REQ_ROW);
+        assert!(found.entry.tags.is_empty());
+        assert_eq!(found.tag_count, Some(0));
+    }
 
-    conn.commit().await.expect("Error committing transaction");
+    let missing = conn
+        .fetch_with_tags("category", "missing", true, false)
+        .await
+        .expect(ERR_FETCH);
+    assert!(missing.is_none());
 }
@@ -0,0 +1,60 @@
+#![cfg(target_arch = "wasm32")]
+
+mod contract;
+
+use aries_askar::{
+    generate_raw_store_key, EntryOperation, IndexedDbStore, KvStore, StoreKeyMethod,
+};
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+fn db_name() -> String {
+    format!("askar-test-{}", uuid::Uuid::new_v4().to_string())
+}
+
+#[wasm_bindgen_test]
+async fn indexeddb_contract() {
+    let key = generate_raw_store_key(None).expect("Error creating raw key");
+    let store = IndexedDbStore::open(&db_name(), StoreKeyMethod::RawKey, key)
+        .await
+        .expect("Error opening IndexedDB store");
+    contract::kv_store_contract(&store).await;
+}
+
+#[wasm_bindgen_test]
+async fn indexeddb_reopen() {
+    let name = db_name();
+    let key = generate_raw_store_key(None).expect("Error creating raw key");
+    let store = IndexedDbStore::open(&name, StoreKeyMethod::RawKey, key.as_ref())
+        .await
+        .expect("Error opening IndexedDB store");
+    store
+        .update(
+            EntryOperation::Insert,
+            "cat",
+            "name",
+            Some(&b"value"[..]),
+            None,
+        )
+        .await
+        .expect("Error inserting test row");
+    drop(store);
+
+    // the records are decrypted with the stored profile key
+    let store = IndexedDbStore::open(&name, StoreKeyMethod::RawKey, key.as_ref())
+        .await
+        .expect("Error reopening IndexedDB store");
+    let row = store
+        .fetch("cat", "name")
+        .await
+        .expect("Error fetching test row")
+        .expect("Expected row");
+    assert_eq!(&*row.value, b"value");
+    drop(store);
+
+    let other_key = generate_raw_store_key(None).expect("Error creating raw key");
+    IndexedDbStore::open(&name, StoreKeyMethod::RawKey, other_key)
+        .await
+        .expect_err("Expected failure with a different store key");
+}
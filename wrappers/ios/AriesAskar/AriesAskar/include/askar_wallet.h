@@ -15,6 +15,12 @@
 //  NotFound = 6,
 //  Unexpected = 7,
 //  Unsupported = 8,
+//  Timeout = 9,
+//  KeyNotFound = 10,
+//  Locked = 11,
+//  QuotaExceeded = 12,
+//  NotUnique = 13,
+//  ResourceLimit = 14,
 //};
 
 typedef uintptr_t ErrorCode;
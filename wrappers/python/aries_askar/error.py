@@ -13,6 +13,12 @@ class AskarErrorCode(IntEnum):
     NOT_FOUND = 6
     UNEXPECTED = 7
     UNSUPPORTED = 8
+    TIMEOUT = 9
+    KEY_NOT_FOUND = 10
+    LOCKED = 11
+    QUOTA_EXCEEDED = 12
+    NOT_UNIQUE = 13
+    RESOURCE_LIMIT = 14
     WRAPPER = 99
 
 